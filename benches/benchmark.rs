@@ -1,6 +1,7 @@
 #![feature(test)]
 extern crate rust_blockchain;
 use rust_blockchain::blockchain as blockchain;
+use rust_blockchain::types::{Height, Nonce};
 extern crate test;
 
 use test::Bencher;
@@ -35,23 +36,66 @@ test test_hashing_sync ... bench: 961,240,210 ns/iter (+/- 102,724,215)
 
 const DIFFICULTY: &str = "00";
 
+fn template() -> blockchain::Block {
+    blockchain::Block {
+        hash: String::new(),
+        id: Height(1),
+        prev_hash: "prev_hash".to_owned(),
+        timestamp: 1234545678,
+        nonce: Nonce(0),
+        data: "data".to_owned(),
+        version: blockchain::BLOCK_VERSION,
+        miner: "miner".to_owned(),
+        extra_nonce: Nonce(0),
+        difficulty: 0,
+        fee: 0,
+        state_root: String::new(),
+        coinbase: None,
+    }
+}
+
 #[bench]
 fn test_hashing_single_thread(b: &mut Bencher) {
-    b.iter(|| blockchain::find_hash("prev_hash", "data", 1234545678, DIFFICULTY, 1 as usize));
+    let template = template();
+    b.iter(|| blockchain::find_hash(&template, DIFFICULTY, 1));
 }
 
 #[bench]
 fn test_hashing_two_threads(b: &mut Bencher) {
-    b.iter(|| blockchain::find_hash("prev_hash", "data", 1234545678, DIFFICULTY, 2 as usize));
+    let template = template();
+    b.iter(|| blockchain::find_hash(&template, DIFFICULTY, 2));
 }
 
 #[bench]
 fn test_hashing_multithreaded(b: &mut Bencher) {
     let threads = num_cpus::get();
-    b.iter(|| blockchain::find_hash("prev_hash", "data", 1234545678, DIFFICULTY, threads));
+    let template = template();
+    b.iter(|| blockchain::find_hash(&template, DIFFICULTY, threads));
+}
+
+// Hashing the whole header per nonce vs. only the part after the nonce
+#[bench]
+fn test_digest(b: &mut Bencher) {
+    let template = template();
+    b.iter(|| {
+        for nonce in 0..1_000 {
+            test::black_box(blockchain::digest_with_nonce(&template, Nonce(nonce)));
+        }
+    });
+}
+
+#[bench]
+fn test_digest_midstate(b: &mut Bencher) {
+    let header_hasher = blockchain::HeaderHasher::new(&template());
+    b.iter(|| {
+        for nonce in 0..1_000 {
+            test::black_box(header_hasher.digest(Nonce(nonce)));
+        }
+    });
 }
 
 #[bench]
 fn test_hashing_sync(b: &mut Bencher) {
-    b.iter(|| blockchain::find_hash_sync("prev_hash", "data", 1234545678, DIFFICULTY));
+    let template = template();
+    b.iter(|| blockchain::find_hash_sync(&template, DIFFICULTY));
 }
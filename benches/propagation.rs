@@ -0,0 +1,70 @@
+#![feature(test)]
+extern crate rust_blockchain;
+extern crate test;
+
+use rust_blockchain::difficulty::{Fixed, MIN_DIFFICULTY};
+use rust_blockchain::propagation::Propagation;
+use rust_blockchain::simulation::{ChaosConfig, Simulation};
+use std::sync::Arc;
+use test::Bencher;
+use tokio::runtime::Runtime;
+
+/*
+End-to-end block propagation in the in-process simulation (see src/simulation.rs): node A mines a
+block, the benchmark measures the wall time until nodes B-E have it on their main chain. Mining is
+trivial (no hash prefix, minimal difficulty), so the time is spent on gossip routing, validation
+and storage. Besides the wall time every benchmark prints the virtual network latency in ticks,
+which tests/simulation_tests.rs pins: 1 tick with full blocks, 3 ticks with announcements
+(announcement, block request, block).
+
+Run with `cargo +nightly bench --bench propagation` and add the results of every release here, so
+p2p changes can be compared.
+*/
+
+const NODES: usize = 5;
+const MAX_TICKS: u64 = 20;
+
+fn simulation(runtime: &Runtime, propagation: Propagation, chaos: ChaosConfig) -> Simulation {
+    let mut simulation = runtime.block_on(Simulation::new(NODES, chaos, 0)).unwrap();
+    simulation.propagation = propagation;
+    for node in simulation.nodes.iter_mut() {
+        node.chain.difficulty = String::new();
+        node.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    }
+    simulation
+}
+
+fn bench_propagation(b: &mut Bencher, propagation: Propagation, chaos: ChaosConfig) {
+    let runtime = Runtime::new().unwrap();
+    let mut simulation = simulation(&runtime, propagation, chaos);
+    let mut ticks = vec![];
+    let mut round = 0;
+    b.iter(|| {
+        round += 1;
+        runtime.block_on(async {
+            let block = simulation.mine(0, &format!("block {}", round)).await.unwrap();
+            ticks.push(simulation.run_until_accepted(&block, MAX_TICKS).await.expect("block propagated"));
+            // Leftover duplicates must not count towards the next block
+            simulation.run_until_idle(MAX_TICKS).await;
+        })
+    });
+    let average = ticks.iter().sum::<u64>() as f64 / ticks.len().max(1) as f64;
+    println!("{:?} propagation: {:.2} ticks on average over {} blocks", propagation, average, ticks.len());
+}
+
+#[bench]
+fn test_propagation_full_blocks(b: &mut Bencher) {
+    bench_propagation(b, Propagation::Full, ChaosConfig::default());
+}
+
+#[bench]
+fn test_propagation_announcements(b: &mut Bencher) {
+    bench_propagation(b, Propagation::Announce, ChaosConfig::default());
+}
+
+// Delayed, duplicated and reordered but never dropped messages
+#[bench]
+fn test_propagation_full_blocks_under_chaos(b: &mut Bencher) {
+    let chaos = ChaosConfig { duplicate_probability: 0.2, delay_probability: 0.5, max_delay: 3, reorder_probability: 0.5, ..ChaosConfig::default() };
+    bench_propagation(b, Propagation::Full, chaos);
+}
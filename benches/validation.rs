@@ -0,0 +1,38 @@
+#![feature(test)]
+extern crate rust_blockchain;
+extern crate test;
+
+use rust_blockchain::blockchain::{Chain, REGTEST_DIFFICULTY};
+use rust_blockchain::chaingen::{self, GenerateConfig};
+use rust_blockchain::difficulty::{Fixed, MIN_DIFFICULTY};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use std::sync::Arc;
+use test::Bencher;
+use tokio::runtime::Runtime;
+
+/*
+Full validation (`chain validate`) of a deterministic chain of 1,000 generated blocks (see
+src/chaingen.rs) in the in-memory storage, so the time is spent on hashing and the consensus rules
+and not on the database. Larger chains and Postgres are generated with `chain generate` on a node.
+
+Run with `cargo +nightly bench --bench validation`.
+*/
+
+const BLOCKS: u64 = 1_000;
+const SEED: u64 = 42;
+
+#[bench]
+fn bench_validate_generated_chain(b: &mut Bencher) {
+    let runtime = Runtime::new().unwrap();
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let chain = runtime.block_on(async {
+        let mut chain = Chain::init(&mut storage).await.unwrap();
+        chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+        chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+        chaingen::generate(&mut chain, &mut storage, &GenerateConfig { blocks: BLOCKS, seed: SEED }, &mut |_, _| {})
+            .await
+            .unwrap();
+        chain
+    });
+    b.iter(|| runtime.block_on(chain.validate_chain(&mut storage)).unwrap());
+}
@@ -0,0 +1,21 @@
+// Embeds the commit the node is built from as BUILD_COMMIT, see nodeinfo.rs. Builds outside of a git
+// checkout (or without git) leave it unset. The target triple is embedded as BUILD_TARGET, see
+// capabilities.rs.
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=BUILD_COMMIT={}", commit.trim());
+    }
+    if let Ok(target) = std::env::var("TARGET") {
+        println!("cargo:rustc-env=BUILD_TARGET={}", target);
+    }
+}
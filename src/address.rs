@@ -0,0 +1,98 @@
+// Base58Check addresses, the format addresses are shown and entered in: a version byte and the
+// payload followed by a checksum, the first 4 bytes of the double SHA-256 of both, all of it base58
+// encoded like Bitcoin addresses. ADDRESS_VERSION addresses carry the public key of a wallet,
+// MULTISIG_ADDRESS_VERSION ones the policy hash of a multisig address (see multisig.rs). A typo breaks
+// the checksum, so a malformed address is refused before anything is sent to it.
+// Transactions and the indexes keep the hex encoded key (or the multisig- address), parse converts
+// what a user entered to that form and from_internal converts back.
+use crate::blockchain::BlockchainError;
+use crate::multisig::MULTISIG_ADDRESS_PREFIX;
+use libp2p::identity::ed25519;
+use sha2::{Digest, Sha256};
+
+pub const ADDRESS_VERSION: u8 = 0x3c;
+pub const MULTISIG_ADDRESS_VERSION: u8 = 0x3d;
+const CHECKSUM_LENGTH: usize = 4;
+// Public keys and multisig policy hashes
+const PAYLOAD_LENGTH: usize = 32;
+
+pub fn encode(version: u8, payload: &[u8]) -> String {
+    let mut bytes = vec![version];
+    bytes.extend_from_slice(payload);
+    let checksum = checksum(&bytes);
+    bytes.extend_from_slice(&checksum);
+    bs58::encode(bytes).into_string()
+}
+
+// The version byte and the payload of the address
+pub fn decode(address: &str) -> Result<(u8, Vec<u8>), BlockchainError> {
+    let invalid = |reason: String| BlockchainError::InvalidAddress(format!("{}: {}", address, reason));
+    let bytes = bs58::decode(address).into_vec().map_err(|err| invalid(err.to_string()))?;
+    if bytes.len() < 1 + CHECKSUM_LENGTH {
+        return Err(invalid("too short".to_owned()));
+    }
+    let (versioned, checksum_bytes) = bytes.split_at(bytes.len() - CHECKSUM_LENGTH);
+    if checksum(versioned)[..] != *checksum_bytes {
+        return Err(invalid("checksum does not match".to_owned()));
+    }
+    Ok((versioned[0], versioned[1..].to_vec()))
+}
+
+pub fn from_public_key(public: &ed25519::PublicKey) -> String {
+    encode(ADDRESS_VERSION, &public.encode())
+}
+
+// The Base58Check address of a hex encoded key or a multisig address
+pub fn from_internal(address: &str) -> Result<String, BlockchainError> {
+    let (version, payload) = match address.strip_prefix(MULTISIG_ADDRESS_PREFIX) {
+        Some(hash) => (MULTISIG_ADDRESS_VERSION, hash),
+        None => (ADDRESS_VERSION, address),
+    };
+    let payload = hex::decode(payload)
+        .ok()
+        .filter(|payload| payload.len() == PAYLOAD_LENGTH)
+        .ok_or_else(|| BlockchainError::InvalidAddress(format!("{}: no key or multisig address", address)))?;
+    Ok(encode(version, &payload))
+}
+
+// The address as transactions carry it: a Base58Check address is converted, a hex encoded key or a
+// multisig address is taken as it is once it is well-formed
+pub fn parse(input: &str) -> Result<String, BlockchainError> {
+    let input = input.trim();
+    let invalid = |reason: &str| BlockchainError::InvalidAddress(format!("{}: {}", input, reason));
+    if let Some(hash) = input.strip_prefix(MULTISIG_ADDRESS_PREFIX) {
+        return match is_hex_payload(hash) {
+            true => Ok(input.to_ascii_lowercase()),
+            false => Err(invalid("malformed multisig address")),
+        };
+    }
+    if is_hex_payload(input) {
+        return match hex::decode(input).ok().and_then(|key| ed25519::PublicKey::decode(&key).ok()) {
+            Some(_) => Ok(input.to_ascii_lowercase()),
+            None => Err(invalid("no valid public key")),
+        };
+    }
+    let (version, payload) = decode(input)?;
+    if payload.len() != PAYLOAD_LENGTH {
+        return Err(invalid(&format!("payload has {} bytes instead of {}", payload.len(), PAYLOAD_LENGTH)));
+    }
+    match version {
+        ADDRESS_VERSION => match ed25519::PublicKey::decode(&payload) {
+            Ok(_) => Ok(hex::encode(payload)),
+            Err(_) => Err(invalid("no valid public key")),
+        },
+        MULTISIG_ADDRESS_VERSION => Ok(format!("{}{}", MULTISIG_ADDRESS_PREFIX, hex::encode(payload))),
+        version => Err(invalid(&format!("unknown version {}", version))),
+    }
+}
+
+fn is_hex_payload(input: &str) -> bool {
+    input.len() == PAYLOAD_LENGTH * 2 && input.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let hash = Sha256::digest(Sha256::digest(bytes));
+    let mut checksum = [0u8; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LENGTH]);
+    checksum
+}
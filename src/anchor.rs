@@ -0,0 +1,170 @@
+// Data anchoring (`anchor file`, `anchor verify`): the SHA-256 digests of one or more files are
+// committed in a block, together with the root of a Merkle tree over them. The proof that a file
+// was anchored consists of the path from its digest to that root and the block, whose hash commits
+// to the root, so it can be checked without the other files of the batch.
+use crate::blockchain::{self, Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Block data of anchors starts with this, followed by the JSON encoded Anchor
+pub const ANCHOR_PREFIX: &str = "anchor ";
+
+// All digests hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Anchor {
+    pub root: String,
+    pub digests: Vec<String>,
+}
+
+// Sibling of the current node on the way from a leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub hash: String,
+    // Whether the sibling is the left child
+    pub left: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnchorProof {
+    pub digest: String,
+    pub path: Vec<ProofStep>,
+    pub root: String,
+    // The block anchoring the root
+    pub block: Block,
+    // Blocks on top of it when the proof was created
+    pub confirmations: u64,
+}
+
+// Hex encoded SHA-256 of the file's content
+pub fn hash_file(path: &Path) -> Result<String, BlockchainError> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn hash_pair(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+fn decode_digest(digest: &str) -> Result<Vec<u8>, BlockchainError> {
+    hex::decode(digest)
+        .ok()
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or_else(|| BlockchainError::Error(format!("invalid digest: {}", digest)))
+}
+
+// Levels of the Merkle tree from the leaves up to the root. A node without a sibling is paired
+// with itself.
+fn tree(digests: &[String]) -> Result<Vec<Vec<Vec<u8>>>, BlockchainError> {
+    if digests.is_empty() {
+        return Err(BlockchainError::Error("nothing to anchor".to_owned()));
+    }
+    let mut levels = vec![digests.iter().map(|digest| decode_digest(digest)).collect::<Result<Vec<_>, _>>()?];
+    while levels.last().expect("has leaves").len() > 1 {
+        let level = levels.last().expect("has leaves");
+        let next = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        levels.push(next);
+    }
+    Ok(levels)
+}
+
+pub fn merkle_root(digests: &[String]) -> Result<String, BlockchainError> {
+    Ok(hex::encode(&tree(digests)?.last().expect("has root")[0]))
+}
+
+// Path from the digest at the index to the root
+pub fn merkle_path(digests: &[String], index: usize) -> Result<Vec<ProofStep>, BlockchainError> {
+    let levels = tree(digests)?;
+    let mut index = index;
+    let mut path = vec![];
+    for level in &levels[..levels.len() - 1] {
+        let sibling = if index.is_multiple_of(2) { (index + 1).min(level.len() - 1) } else { index - 1 };
+        path.push(ProofStep { hash: hex::encode(&level[sibling]), left: sibling < index });
+        index /= 2;
+    }
+    Ok(path)
+}
+
+// Root the path leads to from the digest
+pub fn root_of_path(digest: &str, path: &[ProofStep]) -> Result<String, BlockchainError> {
+    let mut hash = decode_digest(digest)?;
+    for step in path {
+        let sibling = decode_digest(&step.hash)?;
+        hash = if step.left { hash_pair(&sibling, &hash) } else { hash_pair(&hash, &sibling) };
+    }
+    Ok(hex::encode(hash))
+}
+
+impl Anchor {
+    pub fn new(digests: Vec<String>) -> Result<Self, BlockchainError> {
+        Ok(Self { root: merkle_root(&digests)?, digests })
+    }
+
+    pub fn to_block_data(&self) -> String {
+        format!("{}{}", ANCHOR_PREFIX, serde_json::to_string(self).expect("can jsonify anchor"))
+    }
+
+    // None if the data is no valid anchor
+    pub fn from_block_data(data: &str) -> Option<Self> {
+        let anchor: Self = serde_json::from_str(data.strip_prefix(ANCHOR_PREFIX)?).ok()?;
+        (merkle_root(&anchor.digests).ok()? == anchor.root).then_some(anchor)
+    }
+}
+
+// Digests anchored by the block data, empty if the data is no anchor
+pub fn digests(data: &str) -> Vec<String> {
+    Anchor::from_block_data(data).map(|anchor| anchor.digests).unwrap_or_default()
+}
+
+impl AnchorProof {
+    // Creates the proof for the oldest main chain block anchoring the digest
+    pub async fn create(storage: &mut Storage, digest: &str) -> Result<Self, BlockchainError> {
+        let block = Chain::get_anchor_blocks(storage, digest)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| BlockchainError::Error(format!("{} is not anchored", digest)))?;
+        let anchor = Anchor::from_block_data(&block.data).ok_or_else(|| BlockchainError::BlockInvalid(block.hash.clone()))?;
+        let index = anchor
+            .digests
+            .iter()
+            .position(|anchored| anchored == digest)
+            .ok_or_else(|| BlockchainError::BlockInvalid(block.hash.clone()))?;
+        let latest = Chain::get_latest_block(storage).await?;
+        Ok(Self {
+            digest: digest.to_owned(),
+            path: merkle_path(&anchor.digests, index)?,
+            root: anchor.root,
+            confirmations: latest.id.0.saturating_sub(block.id.0),
+            block,
+        })
+    }
+
+    // Checks that the digest leads to the root and that the root is committed by the block. Whether
+    // the block is part of the chain has to be checked separately.
+    pub fn verify(&self) -> Result<(), BlockchainError> {
+        let invalid = |reason: &str| BlockchainError::Error(format!("invalid anchor proof: {}", reason));
+        if root_of_path(&self.digest, &self.path)? != self.root {
+            return Err(invalid("the path does not lead to the root"));
+        }
+        match Anchor::from_block_data(&self.block.data) {
+            Some(anchor) if anchor.root == self.root => {}
+            _ => return Err(invalid("the block does not anchor the root")),
+        }
+        if blockchain::hasher(&self.block) != self.block.hash {
+            return Err(invalid("the block hash does not match its header"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+// Encryption at rest (`--encrypt-at-rest`): for nodes whose Postgres database or snapshot files live
+// on shared or untrusted disks. Block payloads and coinbases, the payloads table, receipts, the
+// amounts of unspent outputs, the state of a restored chain, the in-memory snapshot, the write-ahead
+// journal and the mempool file are sealed with ChaCha20Poly1305 before they are written and opened
+// when they are read, so the rest of the node never sees the difference. The key is the hex encoded
+// 32 bytes in STORAGE_KEY_ENV or, without it, the storage key of --keys DIR. Values are sealed one by
+// one with a nonce of their own as SEALED_PREFIX followed by the hex encoded nonce and ciphertext. On
+// the first start with a key the values a database already holds are sealed all at once (see
+// storage.rs), from then on a value that is not sealed is refused. Files written before encryption
+// was enabled are read once and sealed when they are written again. Empty values stay empty, as an
+// empty payload marks one kept in the payloads table. Payloads are stored under lookup_key of their
+// hash, so the stored keys do not give away which payloads a node holds. What our lookups go through
+// by value (block hashes, heights, the address, transaction and anchor indexes and the outpoints and
+// addresses of outputs) stays in the clear, and full-text search only finds payloads that are not
+// sealed.
+use crate::blockchain::BlockchainError;
+use crate::keys::KeyStore;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use rand::RngCore;
+use sha2::Sha256;
+use std::env;
+
+pub const STORAGE_KEY_ENV: &str = "BLOCKCHAIN_STORAGE_KEY";
+pub const SEALED_PREFIX: &str = "sealed:";
+// Sealed into the at_rest table of a database on its first start with a key, so starting with
+// another key or without one fails instead of mixing values sealed with different keys
+pub const CHECK_VALUE: &str = "rust-blockchain storage key";
+const NONCE_LENGTH: usize = 12;
+
+static KEY: OnceCell<[u8; 32]> = OnceCell::new();
+
+// Set on start-up before the storage is opened, it can not be changed while the node runs
+pub fn set_key(key: [u8; 32]) -> Result<(), BlockchainError> {
+    KEY.set(key).map_err(|_| BlockchainError::Error("storage key is already set".to_owned()))
+}
+
+pub fn is_enabled() -> bool {
+    KEY.get().is_some()
+}
+
+// The key in STORAGE_KEY_ENV, the one in the key store otherwise
+pub fn load_key(key_store: Option<&KeyStore>) -> Result<[u8; 32], BlockchainError> {
+    if let Ok(key) = env::var(STORAGE_KEY_ENV) {
+        return parse_key(&key);
+    }
+    match key_store {
+        Some(key_store) => key_store.storage_key(),
+        None => Err(BlockchainError::Error(format!("--encrypt-at-rest requires a key in {} or --keys DIR", STORAGE_KEY_ENV))),
+    }
+}
+
+pub fn parse_key(key: &str) -> Result<[u8; 32], BlockchainError> {
+    hex::decode(key.trim())
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| BlockchainError::Error(format!("{} has to be 32 hex encoded bytes", STORAGE_KEY_ENV)))
+}
+
+// The value as it is written, unchanged without a key
+pub fn seal(plaintext: &str) -> String {
+    match KEY.get() {
+        Some(key) => seal_with(key, plaintext),
+        None => plaintext.to_owned(),
+    }
+}
+
+// The value as it was before it was sealed. Without a key values are read as they are, the
+// check value keeps us from starting on a database that needs one.
+pub fn open(stored: &str) -> Result<String, BlockchainError> {
+    match KEY.get() {
+        Some(key) => open_with(key, stored),
+        None => Ok(stored.to_owned()),
+    }
+}
+
+// Files are all ours, a sealed one can not be read without a key. One written before encryption was
+// enabled is read as it is, it is sealed when it is written again.
+pub fn open_file(contents: &str) -> Result<String, BlockchainError> {
+    match KEY.get() {
+        Some(key) if is_sealed(contents) => open_with(key, contents),
+        Some(_) => Ok(contents.to_owned()),
+        None if is_sealed(contents) => Err(BlockchainError::Error("file is encrypted at rest, start with --encrypt-at-rest".to_owned())),
+        None => Ok(contents.to_owned()),
+    }
+}
+
+// The key a value is stored under when it is looked up by value, the value itself without a key.
+// Hex encoded HMAC-SHA256 under the storage key otherwise, the same value always maps to the same key.
+pub fn lookup_key(value: &str) -> String {
+    match KEY.get() {
+        Some(key) => lookup_key_with(key, value),
+        None => value.to_owned(),
+    }
+}
+
+pub fn lookup_key_with(key: &[u8; 32], value: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(SEALED_PREFIX)
+}
+
+pub fn seal_with(key: &[u8; 32], plaintext: &str) -> String {
+    if plaintext.is_empty() {
+        return String::new();
+    }
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .expect("can encrypt value");
+    format!("{}{}{}", SEALED_PREFIX, hex::encode(nonce), hex::encode(ciphertext))
+}
+
+// Every value but an empty one has to be sealed, the ones written before encryption was enabled are
+// sealed by the migration in storage.rs
+pub fn open_with(key: &[u8; 32], stored: &str) -> Result<String, BlockchainError> {
+    let sealed = match stored.strip_prefix(SEALED_PREFIX) {
+        Some(sealed) => sealed,
+        None if stored.is_empty() => return Ok(String::new()),
+        None => return Err(BlockchainError::Error("stored value is not sealed".to_owned())),
+    };
+    let invalid = |reason: &str| BlockchainError::Error(format!("invalid sealed value: {}", reason));
+    let bytes = hex::decode(sealed).map_err(|err| invalid(&err.to_string()))?;
+    if bytes.len() < NONCE_LENGTH {
+        return Err(invalid("too short"));
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| BlockchainError::Error("can not decrypt stored value, wrong storage key?".to_owned()))?;
+    String::from_utf8(plaintext).map_err(|err| invalid(&err.to_string()))
+}
@@ -0,0 +1,16 @@
+// Standalone hasher that mines for the pool of a node via its stratum server, see src/stratum.rs:
+// cargo run --release --bin hasher NODE_STRATUM_ADDR
+use rust_blockchain::stratum;
+use std::env;
+use std::error::Error;
+use std::net::SocketAddr;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let addr = env::args()
+        .nth(1)
+        .ok_or("usage: hasher NODE_STRATUM_ADDR")?
+        .parse::<SocketAddr>()?;
+    stratum::run_hasher(addr).await?;
+    Ok(())
+}
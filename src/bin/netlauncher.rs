@@ -0,0 +1,83 @@
+// Starts a local test network of nodes and prints their output, see src/launcher.rs:
+// cargo build && cargo run --bin netlauncher -- [NODES] [OPTIONS] [-- NODE_OPTION...]
+use futures::future::join_all;
+use rust_blockchain::keys::KEY_PASSPHRASE_ENV;
+use rust_blockchain::launcher::{self, LaunchConfig, NodeSpec};
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+
+// Nodes still running this long after Ctrl-C are killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config = LaunchConfig::from_args(env::args().skip(1))?;
+    let node = match &config.node {
+        Some(path) => path.clone(),
+        None => env::current_exe()?.with_file_name(format!("rust-blockchain{}", env::consts::EXE_SUFFIX)),
+    };
+    if !node.exists() {
+        return Err(format!("node executable {} not found, build it with `cargo build` or pass --node PATH", node.display()).into());
+    }
+
+    let plan = config.plan();
+    let width = plan.iter().map(|spec| spec.name.len()).max().unwrap_or_default();
+    let mut children = vec![];
+    for spec in plan.iter() {
+        let summary = format!("p2p port {} | rpc port {} | data dir {}", spec.p2p_port, spec.rpc_port, spec.data_dir.display());
+        println!("{}", launcher::prefix_line(&spec.name, width, &summary));
+        children.push((spec.name.clone(), start(&node, spec, width)?));
+    }
+
+    tokio::select! {
+        _ = join_all(children.iter_mut().map(|(name, child)| wait(name, child, width))) => return Ok(()),
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    // Ctrl-C in the terminal reached the nodes as well, they are shutting down
+    println!("waiting for the nodes to shut down");
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, join_all(children.iter_mut().map(|(_, child)| child.wait()))).await.is_err() {
+        for (name, child) in children.iter_mut() {
+            println!("{}", launcher::prefix_line(name, width, "killed"));
+            let _ = child.kill().await;
+        }
+    }
+    Ok(())
+}
+
+fn start(node: &Path, spec: &NodeSpec, width: usize) -> std::io::Result<Child> {
+    let mut command = Command::new(node);
+    // Without a terminal the nodes do not read commands
+    command.args(&spec.args).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    if env::var(KEY_PASSPHRASE_ENV).is_err() {
+        command.env(KEY_PASSPHRASE_ENV, launcher::DEMO_PASSPHRASE);
+    }
+    let mut child = command.spawn()?;
+    tail(child.stdout.take(), spec.name.clone(), width);
+    tail(child.stderr.take(), spec.name.clone(), width);
+    Ok(child)
+}
+
+// Prints the lines of the output with the name of the node in front
+fn tail<R: AsyncRead + Unpin + Send + 'static>(output: Option<R>, name: String, width: usize) {
+    if let Some(output) = output {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(output).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                println!("{}", launcher::prefix_line(&name, width, &line));
+            }
+        });
+    }
+}
+
+async fn wait(name: &str, child: &mut Child, width: usize) {
+    let message = match child.wait().await {
+        Ok(status) => format!("exited ({})", status),
+        Err(err) => format!("error waiting for the node: {}", err),
+    };
+    println!("{}", launcher::prefix_line(name, width, &message));
+}
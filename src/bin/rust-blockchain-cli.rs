@@ -0,0 +1,69 @@
+// Drives a running node via its control socket (--ipc), see src/ipc.rs:
+// cargo run --bin rust-blockchain-cli -- --connect PATH [METHOD [PARAMS]]
+#[cfg(unix)]
+use rust_blockchain::ipc::{self, ClientConfig, IpcClient};
+#[cfg(unix)]
+use rust_blockchain::rpc::RpcReply;
+#[cfg(unix)]
+use std::env;
+use std::error::Error;
+#[cfg(unix)]
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+#[cfg(unix)]
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config = ClientConfig::from_args(env::args().skip(1))?;
+    let mut client = IpcClient::connect(&config.socket).await?;
+    let mut failed = false;
+    match &config.command {
+        Some(command) => failed = call(&mut client, command, 1).await?,
+        None => {
+            let mut lines = BufReader::new(io::stdin()).lines();
+            let mut id = 0;
+            while let Some(line) = lines.next_line().await? {
+                if !line.trim().is_empty() {
+                    id += 1;
+                    failed |= call(&mut client, &line, id).await?;
+                }
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() -> Result<(), Box<dyn Error>> {
+    Err("the control socket requires Unix domain sockets".into())
+}
+
+// Prints the reply, returns whether it holds an error
+#[cfg(unix)]
+async fn call(client: &mut IpcClient, command: &str, id: u64) -> Result<bool, Box<dyn Error>> {
+    let line = match ipc::request_line(command, id) {
+        Ok(line) => line,
+        Err(err) => {
+            eprintln!("{}", err);
+            return Ok(true);
+        }
+    };
+    let reply = client.call(&line).await?;
+    match serde_json::from_str::<RpcReply>(&reply) {
+        // Results are printed on their own, so scripts do not have to unwrap them
+        Ok(RpcReply::Single(response)) if response.error.is_none() => {
+            println!("{}", serde_json::to_string_pretty(&response.result).expect("can jsonify result"));
+            Ok(false)
+        }
+        Ok(reply) => {
+            println!("{}", serde_json::to_string_pretty(&reply).expect("can jsonify reply"));
+            Ok(ipc::is_error(&reply))
+        }
+        Err(_) => {
+            println!("{}", reply);
+            Ok(true)
+        }
+    }
+}
@@ -1,16 +1,69 @@
+use crate::consensus::{self, HashEncoding};
+use crate::difficulty::{self, BlockInfo, DifficultyAlgorithm, INITIAL_DIFFICULTY};
+use crate::fastsync::ChainBase;
+use crate::gpu;
+use crate::hooks::TemplateHooks;
+use crate::keys;
+use crate::network::NetworkParams;
+use crate::receipts::Receipt;
+use crate::state::{AccountBalance, LatestState, State};
+use crate::storage::{MemoryStorage, Storage};
+use crate::transactions::{self, Transaction};
+use crate::types::{Height, Nonce};
+use crate::utxo::{self, Utxo};
+use crate::watchdog::{self, TaskKind};
 use chrono::Utc;
-use log::{error, info, trace};
+use log::{debug, error, info, trace};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
-use tokio_postgres::types::Type;
-use tokio_postgres::Client;
+use std::time::Instant;
 
-const BLOCK_DIFFICULTY: &str = "00";
+// Hash prefix blocks are mined with before the DifficultyAdjustment upgrade (not validated)
+pub const BLOCK_DIFFICULTY: &str = "00";
+// Used by local test networks (--regtest), where blocks should be found almost instantly
+pub const REGTEST_DIFFICULTY: &str = "0";
 const GENESIS_BLOCK_DATA: &str = "some random newspaper headline from today";
 const GENESIS_BLOCK_HASH: &str = "0A31F6A1DB36EEDF9AA5C56AB90DCC76A3ABD90C77B1198336FD1AE512193F";
 const GENESIS_BLOCK_TIME: i64 = 0;
+// Version 0 blocks predate the version, miner and extra nonce header fields and are hashed without them.
+// Version 2 blocks have timestamps in milliseconds instead of seconds, which must not be lower than
+// the timestamp of their parent. Version 3 adds the difficulty to the header, see difficulty.rs.
+// Version 4 adds the fee collected by the miner, see fees.rs. Version 5 adds the root of the state
+// after the block, see state.rs. Version 6 adds the coinbase paying the miner, see transactions.rs.
+pub const BLOCK_VERSION: u8 = 6;
+// First version with millisecond timestamps
+const MILLISECOND_TIMESTAMP_VERSION: u8 = 2;
+// First version with a fee
+const FEE_VERSION: u8 = 4;
+// First version with a state root
+const STATE_ROOT_VERSION: u8 = 5;
+// First version with a coinbase
+const COINBASE_VERSION: u8 = 6;
+const MAX_MINER_LENGTH: usize = 128;
+// Number of most recent heights the stale rate is calculated for
+pub const STALE_RATE_WINDOW: u64 = 100;
+// A block is final as soon as the network's max_reorg_depth blocks have been built on top of it (or
+// one of our checkpoint signers gossiped a matching checkpoint for it). Reorgs that would replace a
+// finalized block are refused, which bounds the reorg depth. This is the depth of the default network,
+// see network.rs.
+pub const FINALITY_DEPTH: u64 = 6;
+// Threads mining on the CPU (`--mining-threads`), 0 for one per core. Can be changed while the node
+// runs, see reload.rs.
+static MINING_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_mining_threads(threads: usize) {
+    MINING_THREADS.store(threads, Ordering::Relaxed);
+}
+
+pub fn mining_threads() -> usize {
+    match MINING_THREADS.load(Ordering::Relaxed) {
+        0 => num_cpus::get(),
+        threads => threads,
+    }
+}
 
 fn error_chain_fmt(e: &dyn std::error::Error, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     writeln!(f, "{}\n", e)?;
@@ -26,6 +79,15 @@ pub enum BlockchainError {
     BlockInvalid(String),
     ChainInvalid(Box<BlockchainError>),
     BlockNotFound(String),
+    BlockStale(String),
+    ReorgBelowFinalized(Height),
+    // A malformed address entered by a user, see address.rs
+    InvalidAddress(String),
+    // A template hook refused the block we were about to mine, see hooks.rs
+    MiningVetoed(String),
+    // A transaction spending an output or a balance a pending or confirmed transaction spends
+    // already, see mempool.rs
+    DoubleSpend(String),
     IoError(std::io::Error),
     DatabaseError(tokio_postgres::Error),
     Error(String),
@@ -43,6 +105,21 @@ impl std::fmt::Display for BlockchainError {
             BlockchainError::BlockNotFound(hash) => {
                 write!(f, "block not found: {}", hash)
             }
+            BlockchainError::BlockStale(hash) => {
+                write!(f, "block stale: {}", hash)
+            }
+            BlockchainError::ReorgBelowFinalized(id) => {
+                write!(f, "reorg below finalized height: {}", id)
+            }
+            BlockchainError::MiningVetoed(reason) => {
+                write!(f, "mining vetoed by {}", reason)
+            }
+            BlockchainError::InvalidAddress(reason) => {
+                write!(f, "invalid address: {}", reason)
+            }
+            BlockchainError::DoubleSpend(reason) => {
+                write!(f, "double spend: {}", reason)
+            }
             BlockchainError::Error(err) => {
                 write!(f, "error: {}", err)
             }
@@ -58,6 +135,11 @@ impl std::error::Error for BlockchainError {
             BlockchainError::ChainInvalid(err) => Some(err),
             BlockchainError::BlockInvalid(_) => None,
             BlockchainError::BlockNotFound(_) => None,
+            BlockchainError::BlockStale(_) => None,
+            BlockchainError::ReorgBelowFinalized(_) => None,
+            BlockchainError::MiningVetoed(_) => None,
+            BlockchainError::InvalidAddress(_) => None,
+            BlockchainError::DoubleSpend(_) => None,
             BlockchainError::IoError(err) => Some(err),
             BlockchainError::DatabaseError(err) => Some(err),
             BlockchainError::Error(_) => None,
@@ -95,305 +177,826 @@ impl From<std::io::Error> for BlockchainError {
 //     }
 // }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
+// What the difficulty algorithms see of a block, blocks before the DifficultyAdjustment upgrade
+// count with the initial difficulty
+pub fn block_info(block: &Block) -> BlockInfo {
+    BlockInfo {
+        height: block.id,
+        timestamp: block.timestamp_millis(),
+        difficulty: if consensus::rules_at(block.id).difficulty_adjustment {
+            block.difficulty
+        } else {
+            INITIAL_DIFFICULTY
+        },
+    }
+}
+
+fn default_difficulty_algorithm() -> Arc<dyn DifficultyAlgorithm> {
+    difficulty::algorithm_by_name(difficulty::DEFAULT_ALGORITHM).expect("default difficulty algorithm exists")
+}
+
+// Where we search nonces when mining, selected with --hasher
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashBackend {
+    #[default]
+    Cpu,
+    // Requires the gpu feature, see gpu.rs. Mining falls back to the CPU if the GPU can not be used.
+    Gpu,
+}
+
+#[derive(Debug, Clone)]
 pub struct Chain {
     pub latest_block: Block,
+    pub finalized: Checkpoint,
+    // Identifier written into the header of blocks we mine
+    pub miner: String,
+    // Prefix the hashes of blocks we mine need to have before the DifficultyAdjustment upgrade
+    pub difficulty: String,
+    // Retargeting after the DifficultyAdjustment upgrade, all nodes of a network have to use the same
+    pub difficulty_algorithm: Arc<dyn DifficultyAlgorithm>,
+    pub hash_backend: HashBackend,
+    // First block of the chain, our own one unless we follow another chain, see chains.rs
+    pub genesis: Block,
+    // Run on every block we assemble before hashing starts, see hooks.rs
+    pub template_hooks: TemplateHooks,
+    // Peer IDs whose checkpoints finalize blocks less than max_reorg_depth blocks deep
+    pub checkpoint_signers: Vec<String>,
+    // Consensus-economic parameters of the network the chain belongs to, see network.rs
+    pub params: NetworkParams,
+    // Set if our chain was restored from a snapshot and lacks the blocks below the base, see fastsync.rs
+    pub base: Option<ChainBase>,
+    // The state after our latest block once it was derived, see state.rs
+    pub latest_state: Option<LatestState>,
+    // Address the coinbase of blocks we mine pays, the address of the node's wallet (see wallet.rs)
+    pub coinbase_address: Option<String>,
 }
 
 impl Chain {
-    pub async fn init(db_client: &mut Client) -> Result<Self, BlockchainError> {
-        if let Err(err) = db_client
-            .execute(
-                "
-    CREATE TABLE IF NOT EXISTS blocks (
-        hash            VARCHAR PRIMARY KEY,
-        id              INT8 UNIQUE NOT NULL,
-        prev_hash       VARCHAR UNIQUE NOT NULL,
-        timestamp       INT8 NOT NULL,
-        nonce           INT8 NOT NULL,
-        data            VARCHAR NOT NULL
-        )
-",
-                &[],
-            )
-            .await
-        {
-            error!("Error creating blockchain table: {:?}", err)
-        }
+    pub async fn init(storage: &mut Storage) -> Result<Self, BlockchainError> {
+        Chain::init_with_genesis(storage, Block::create_genesis()).await
+    }
 
-        let latest_block = Chain::get_latest_block(db_client).await;
+    // Initializes the chain starting with the genesis block, which is stored if the chain is empty
+    pub async fn init_with_genesis(storage: &mut Storage, genesis: Block) -> Result<Self, BlockchainError> {
+        storage.init().await?;
 
-        match latest_block {
-            Ok(block) => Ok(Chain::build(block)),
-            Err(_) => Chain::new(db_client).await,
-        }
+        let latest_block = Chain::get_latest_block(storage).await;
+
+        let mut chain = match latest_block {
+            Ok(block) => Chain::build(block),
+            Err(_) => {
+                storage.insert_block(&genesis).await?;
+                Chain::build(genesis.clone())
+            }
+        };
+        chain.genesis = genesis;
+        chain.base = storage.get_base().await?;
+        chain.finalized = chain.first_block();
+        chain.update_finalized(storage).await?;
+
+        Ok(chain)
     }
 
-    pub async fn new(db_client: &mut Client) -> Result<Self, BlockchainError> {
+    pub async fn new(storage: &mut Storage) -> Result<Self, BlockchainError> {
         let block = Block::create_genesis();
 
-        let statement = db_client.prepare_typed(
-            "INSERT INTO blocks (hash, id, prev_hash, timestamp, nonce, data) VALUES ($1, $2, $3, $4, $5, $6)",
-            &[Type::VARCHAR, Type::INT8, Type::VARCHAR, Type::INT8, Type::INT8, Type::VARCHAR],
-        ).await?;
-
-        db_client
-            .execute(
-                &statement,
-                &[
-                    &block.hash,
-                    &block.id,
-                    &block.prev_hash,
-                    &block.timestamp,
-                    &block.nonce,
-                    &block.data,
-                ],
-            )
-            .await?;
+        storage.insert_block(&block).await?;
 
         Ok(Self {
             latest_block: block,
+            finalized: Checkpoint::genesis(),
+            miner: String::new(),
+            difficulty: BLOCK_DIFFICULTY.to_owned(),
+            difficulty_algorithm: default_difficulty_algorithm(),
+            hash_backend: HashBackend::Cpu,
+            genesis: Block::create_genesis(),
+            template_hooks: TemplateHooks::default(),
+            checkpoint_signers: vec![],
+            params: NetworkParams::default(),
+            base: None,
+            latest_state: None,
+            coinbase_address: None,
         })
     }
 
     pub fn build(latest_block: Block) -> Self {
         Self {
             latest_block,
+            finalized: Checkpoint::genesis(),
+            miner: String::new(),
+            difficulty: BLOCK_DIFFICULTY.to_owned(),
+            difficulty_algorithm: default_difficulty_algorithm(),
+            hash_backend: HashBackend::Cpu,
+            genesis: Block::create_genesis(),
+            template_hooks: TemplateHooks::default(),
+            checkpoint_signers: vec![],
+            params: NetworkParams::default(),
+            base: None,
+            latest_state: None,
+            coinbase_address: None,
+        }
+    }
+
+    // Picks up the latest stored block after blocks were removed from storage, e.g. by a repair
+    pub async fn reload(&mut self, storage: &mut Storage) -> Result<(), BlockchainError> {
+        self.latest_block = match Chain::get_latest_block(storage).await {
+            Ok(block) => block,
+            Err(_) => {
+                storage.insert_block(&self.genesis).await?;
+                self.genesis.clone()
+            }
+        };
+        self.base = storage.get_base().await?;
+        self.finalized = self.first_block();
+        self.update_finalized(storage).await
+    }
+
+    // The genesis block, or the base if our chain was restored from a snapshot
+    pub fn first_block(&self) -> Checkpoint {
+        match &self.base {
+            Some(base) => Checkpoint { id: base.id, hash: base.hash.clone() },
+            None => Checkpoint { id: self.genesis.id, hash: self.genesis.hash.clone() },
+        }
+    }
+
+    // Moves our finalized checkpoint up to the block max_reorg_depth blocks below our latest block
+    pub async fn update_finalized(&mut self, storage: &mut Storage) -> Result<(), BlockchainError> {
+        if let Some(id) = self.latest_block.id.checked_sub(self.params.max_reorg_depth) {
+            if id > self.finalized.id {
+                let block = Chain::get_block_by_id(storage, id).await?;
+                self.finalized = Checkpoint { id, hash: block.hash };
+            }
+        }
+        Ok(())
+    }
+
+    // Accepts a finalized checkpoint gossiped by a peer if it matches our own chain. A signature
+    // only proves who sent a checkpoint, so only checkpoint signers can finalize blocks above the
+    // finality window, anyone else could pin us to a minority fork.
+    // Returns false if the checkpoint is not newer than ours or we do not have the block yet.
+    pub async fn accept_checkpoint(&mut self, storage: &mut Storage, checkpoint: Checkpoint, signer: &str) -> Result<bool, BlockchainError> {
+        let highest = match self.checkpoint_signers.iter().any(|trusted| trusted == signer) {
+            true => self.latest_block.id,
+            false => self.latest_block.id.saturating_sub(self.params.max_reorg_depth),
+        };
+        if checkpoint.id <= self.finalized.id || checkpoint.id > highest {
+            return Ok(false);
+        }
+        let block = Chain::get_block_by_id(storage, checkpoint.id).await?;
+        if block.hash != checkpoint.hash {
+            return Err(BlockchainError::Error(format!("checkpoint {} conflicts with our chain", checkpoint.hash)));
         }
+        self.finalized = checkpoint;
+        Ok(true)
     }
 
-    pub async fn update(&mut self, db_client: &mut Client, chain: &mut Vec<Block>) -> Result<(), BlockchainError> {
+    // Replaces our chain with a complete chain received from a peer. Nothing is stored unless every
+    // block of it is valid, and the chain is swapped in one transaction.
+    pub async fn update(&mut self, storage: &mut Storage, chain: &mut [Block]) -> Result<(), BlockchainError> {
 
-        // We simply delete all rows and insert the incoming blocks for now
-        db_client.execute("
-        DELETE FROM blocks;
-        ",
-    &[]).await?;
+        // Never replace blocks we consider final
+        if !chain.iter().any(|block| block.id == self.finalized.id && block.hash == self.finalized.hash) {
+            return Err(BlockchainError::ReorgBelowFinalized(self.finalized.id));
+        }
 
-        let statement = db_client.prepare_typed(
-            "INSERT INTO blocks (hash, id, prev_hash, timestamp, nonce, data) VALUES ($1, $2, $3, $4, $5, $6)",
-            &[Type::VARCHAR, Type::INT8, Type::VARCHAR, Type::INT8, Type::INT8, Type::VARCHAR],
-        ).await?;
+        chain.sort_by_key(|block| block.id);
+        self.check_chain(chain).await?;
+        self.update_checked(storage, chain).await
+    }
 
-        chain.sort_by(|a, b| a.id.cmp(&b.id));
+    // Like update, for a chain that already passed check_chain (see workers.rs). Our chain may have
+    // moved on since, so the finalized block is checked again.
+    pub async fn update_checked(&mut self, storage: &mut Storage, chain: &mut [Block]) -> Result<(), BlockchainError> {
+        if !chain.iter().any(|block| block.id == self.finalized.id && block.hash == self.finalized.hash) {
+            return Err(BlockchainError::ReorgBelowFinalized(self.finalized.id));
+        }
+        chain.sort_by_key(|block| block.id);
+
+        // Blocks of our current chain that are not part of the incoming chain become stale
+        let old_chain = Chain::get_chain(storage).await?;
+        let replaced = old_chain
+            .into_iter()
+            .filter(|old| !chain.iter().any(|new| new.hash == old.hash))
+            .collect::<Vec<Block>>();
+        storage.replace_chain(chain, &replaced, Utc::now().timestamp()).await?;
+
+        // The chain is complete again, the state is derived from it instead of the replaced one
+        self.base = None;
+        self.latest_block = chain.last().expect("checked chain has a genesis block").clone();
+        self.latest_state = Some(LatestState { block_hash: self.latest_block.hash.clone(), state: State::from_blocks(chain.iter()) });
+        self.update_finalized(storage).await?;
 
-        for (index, block) in chain.iter().enumerate() {
-            db_client
-            .execute(
-                &statement,
-                &[
-                    &block.hash,
-                    &block.id,
-                    &block.prev_hash,
-                    &block.timestamp,
-                    &block.nonce,
-                    &block.data,
-                ],
-            )
-            .await?;
+        Ok(())
+    }
 
-            if index == chain.len() - 1 {
-                self.latest_block = block.clone();
+    // Checks a complete chain, ordered by height, under the same rules as blocks added one by one:
+    // every block is added on top of its predecessor in an empty in-memory storage, so it is
+    // validated with the consensus rules active at its height (see consensus.rs), its proof of work
+    // and the difficulty our difficulty algorithm expects
+    pub async fn check_chain(&self, chain: &[Block]) -> Result<(), BlockchainError> {
+        let invalid = |err: BlockchainError| BlockchainError::ChainInvalid(Box::new(err));
+        let (genesis, blocks) = chain
+            .split_first()
+            .ok_or_else(|| invalid(BlockchainError::Error("chain is empty".to_owned())))?;
+        if *genesis != self.genesis {
+            return Err(invalid(BlockchainError::BlockInvalid(genesis.hash.clone())));
+        }
+        let started_at = Instant::now();
+        let mut scratch = Storage::Memory(MemoryStorage::default());
+        scratch.insert_block(genesis).await?;
+        let mut replayed = Chain { latest_block: genesis.clone(), finalized: Checkpoint::genesis(), latest_state: None, ..self.clone() };
+        let mut result = Ok(());
+        for block in blocks {
+            result = replayed.add_block(&mut scratch, block.clone()).await.map_err(invalid);
+            if result.is_err() {
+                break;
             }
         }
+        watchdog::observe(TaskKind::Validation, started_at.elapsed(), || {
+            format!("chain of {} blocks up to {}", chain.len(), chain.last().map_or("", |block| block.hash.as_str()))
+        });
+        result
+    }
 
+    // Adds the blocks following our latest block, ordered by height, as received from a peer when
+    // our chain was restored from a snapshot and only the tail above it is synced. The blocks
+    // before an invalid one are kept.
+    pub async fn extend(&mut self, storage: &mut Storage, blocks: &[Block]) -> Result<(), BlockchainError> {
+        if blocks.first().is_some_and(|block| block.prev_hash != self.latest_block.hash) {
+            return Err(BlockchainError::Error("blocks do not follow our latest block".to_owned()));
+        }
+        for block in blocks {
+            self.add_block(storage, block.clone()).await?;
+        }
         Ok(())
     }
 
+    // Adds a block on top of our latest block.
+    // Valid blocks that do not extend our latest block are stored as stale blocks
+    // and BlockchainError::BlockStale is returned.
+    pub async fn add_block(&mut self, storage: &mut Storage, block: Block) -> Result<(), BlockchainError> {
+        let started_at = Instant::now();
+        let valid = self.validate_block(storage, &block).await;
+        watchdog::observe(TaskKind::Validation, started_at.elapsed(), || format!("block {} at height {}", block.hash, block.id));
+        valid?;
+
+        if block.prev_hash != self.latest_block.hash {
+            Chain::add_stale_block(storage, &block).await?;
+            return Err(BlockchainError::BlockStale(block.hash));
+        }
+
+        storage.append_block(&block).await?;
+
+        // A state that fell behind is derived again on its next use
+        if let Some(latest) = self.latest_state.as_mut().filter(|latest| latest.block_hash == block.prev_hash) {
+            latest.state.apply(&block);
+            latest.block_hash = block.hash.clone();
+        }
+        self.latest_block = block;
+        self.update_finalized(storage).await?;
+
+        Ok(())
+    }
+
+    async fn validate_block(&self, storage: &mut Storage, block: &Block) -> Result<(), BlockchainError> {
+        match self.violation(storage, block).await? {
+            Some(reason) => {
+                debug!("Block {} at height {} is invalid: {}", block.hash, block.id, reason);
+                Err(BlockchainError::BlockInvalid(block.hash.clone()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // The first rule add_block would reject the block for, without storing anything (see dryrun.rs).
+    // The parent has to be stored.
+    pub async fn violation(&self, storage: &mut Storage, block: &Block) -> Result<Option<String>, BlockchainError> {
+        if let Some(reason) = Chain::block_violation(storage, &self.params, block).await? {
+            return Ok(Some(reason));
+        }
+        if self.can_check_difficulty(block) {
+            if let Some(reason) = self.difficulty_violation(storage, block).await? {
+                return Ok(Some(reason));
+            }
+        }
+        // The state below stale blocks is not at hand, their state root is checked once their
+        // chain is synced (see check_chain)
+        if block.prev_hash == self.latest_block.hash && !block.state_root.is_empty() && self.base.is_none() {
+            let state_root = self.state_root(storage, block).await?;
+            if block.state_root != state_root {
+                return Ok(Some(format!("state root does not match the state root {} after the block", state_root)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn add_stale_block(storage: &mut Storage, block: &Block) -> Result<(), BlockchainError> {
+        storage.insert_stale_block(block, Utc::now().timestamp()).await
+    }
+
+    // Returns the most recent stale blocks, highest first
+    pub async fn get_stale_blocks(storage: &mut Storage, limit: i64) -> Result<Vec<StaleBlock>, BlockchainError> {
+        storage.get_stale_blocks(limit).await
+    }
+
+    // Share of stale blocks among all blocks seen for the last STALE_RATE_WINDOW heights
+    pub async fn get_stale_rate(&self, storage: &mut Storage) -> Result<f64, BlockchainError> {
+        let min_id = self.latest_block.id.saturating_sub(STALE_RATE_WINDOW - 1);
+        let (stale, main) = storage.count_blocks_from(min_id).await?;
+        if stale + main == 0 {
+            return Ok(0.0);
+        }
+        Ok(stale as f64 / (stale + main) as f64)
+    }
+
+    pub async fn get_chain(storage: &mut Storage) -> Result<Vec<Block>, BlockchainError> {
+        let res = storage.get_chain().await;
+        if res.is_err() {
+            error!("Error getting chain");
+        }
+        res
+    }
+
+    pub async fn get_block(storage: &mut Storage, key: &str) -> Result<Block, BlockchainError> {
+        let res = storage.get_block(key).await;
+        if res.is_err() {
+            error!("Block not found: {:?}", key);
+        }
+        res
+    }
 
-    pub async fn add_block(&mut self, db_client: &mut Client, block: Block) -> Result<(), BlockchainError> {
+    // Returns all main chain blocks that touch the address, oldest first
+    pub async fn get_address_blocks(storage: &mut Storage, address: &str) -> Result<Vec<Block>, BlockchainError> {
+        storage.get_address_blocks(address).await
+    }
 
-       Chain::check_if_block_valid(db_client, &block).await?;
+    // Returns all main chain blocks carrying the transaction, oldest first
+    pub async fn get_transaction_blocks(storage: &mut Storage, id: &str) -> Result<Vec<Block>, BlockchainError> {
+        storage.get_transaction_blocks(id).await
+    }
 
-        let statement = db_client.prepare_typed(
-            "INSERT INTO blocks (hash, id, prev_hash, timestamp, nonce, data) VALUES ($1, $2, $3, $4, $5, $6)",
-            &[Type::VARCHAR, Type::INT8, Type::VARCHAR, Type::INT8, Type::INT8, Type::VARCHAR],
-        ).await?;
+    pub async fn get_receipt(storage: &mut Storage, block_hash: &str) -> Result<Option<Receipt>, BlockchainError> {
+        storage.get_receipt(block_hash).await
+    }
 
-            db_client
-            .execute(
-                &statement,
-                &[
-                    &block.hash,
-                    &block.id,
-                    &block.prev_hash,
-                    &block.timestamp,
-                    &block.nonce,
-                    &block.data,
-                ],
-            )
-            .await?;
+    // The unspent outputs of the address after our latest block
+    pub async fn get_utxos(storage: &mut Storage, address: &str) -> Result<Vec<Utxo>, BlockchainError> {
+        storage.get_utxos(address).await
+    }
 
-            self.latest_block = block;
+    // Checks that the transaction only spends unspent outputs of its sender covering amount and fee,
+    // so it can be mined on top of our latest block. Returns the value of its inputs. Outputs a main
+    // chain block spent already are a DoubleSpend.
+    pub async fn check_spends(storage: &mut Storage, transaction: &Transaction) -> Result<u64, BlockchainError> {
+        let spent = storage.get_spent(&transaction.inputs).await?;
+        if let Some((input, block_hash)) = transaction.inputs.iter().find_map(|input| spent.get(input).map(|hash| (input, hash))) {
+            return Err(BlockchainError::DoubleSpend(format!("output {} was spent in block {}", input, block_hash)));
+        }
+        let unspent = storage.get_unspent(&transaction.inputs).await?;
+        utxo::check_spends(transaction, |outpoint| unspent.get(outpoint).cloned())
+    }
+
+    // Number of blocks built on top of the main chain block
+    pub fn confirmations(&self, block: &Block) -> u64 {
+        self.latest_block.id.0.saturating_sub(block.id.0)
+    }
 
+    // Rebuilds the derived indexes from the main chain blocks, oldest first. progress is called with
+    // the number of blocks reindexed so far and the total.
+    pub async fn reindex(storage: &mut Storage, progress: &mut (dyn FnMut(u64, u64) + Send)) -> Result<(), BlockchainError> {
+        let total = storage.count_blocks().await?;
+        let first = storage.get_base().await?.map_or(Height::GENESIS, |base| base.id);
+        storage.clear_indexes().await?;
+        for reindexed in 1..=total {
+            let block = Chain::get_block_by_id(storage, first + (reindexed - 1)).await?;
+            storage.index_block(&block).await?;
+            progress(reindexed, total);
+        }
         Ok(())
     }
 
-    pub async fn get_chain(db_client: &mut Client) -> Result<Vec<Block>, BlockchainError> {
-        let res = db_client
-            .query(
-                
-                    "
-    SELECT * 
-    FROM blocks
-    ORDER BY id ASC
-    ",
-                &[],
-            )
-            .await;
-
-        match res {
-            Ok(row_vec) => {
-                return Ok(row_vec.iter().map(|row| Block {
-                    hash: row.get(0),
-                    id: row.get(1),
-                    prev_hash: row.get(2),
-                    timestamp: row.get(3),
-                    nonce: row.get(4),
-                    data: row.get(5),
-                }).collect::<Vec<Block>>());
-            },
-            Err(err) => {
-                error!("Error getting chain");
-                return Err(BlockchainError::DatabaseError(err));
-            }
-        }
-    }
-
-    pub async fn get_block(db_client: &mut Client, key: &str) -> Result<Block, BlockchainError> {
-        let row = db_client
-            .query_one(
-                &format!(
-                    "
-        SELECT * 
-        FROM blocks
-        WHERE hash = '{}'
-        ",
-                    key
-                ),
-                &[],
-            )
-            .await;
-
-        match row {
-            Ok(row) => Ok(Block {
-                hash: row.get(0),
-                id: row.get(1),
-                prev_hash: row.get(2),
-                timestamp: row.get(3),
-                nonce: row.get(4),
-                data: row.get(5),
-            }),
-            Err(err) => {
-                error!("Block not found: {:?}", key);
-                return Err(BlockchainError::DatabaseError(err));
-            }
-        }
-    }
-
-    pub async fn get_latest_block(db_client: &mut Client) -> Result<Block, BlockchainError> {
-        let row = db_client
-            .query_one(
-                "
-        SELECT * 
-        FROM blocks
-        ORDER BY timestamp DESC
-        LIMIT 1
-        ",
-                &[],
-            )
-            .await?;
-
-        Ok(Block {
-            hash: row.get(0),
-            id: row.get(1),
-            prev_hash: row.get(2),
-            timestamp: row.get(3),
-            nonce: row.get(4),
-            data: row.get(5),
-        })
+    // Returns all main chain blocks anchoring the digest, oldest first
+    pub async fn get_anchor_blocks(storage: &mut Storage, digest: &str) -> Result<Vec<Block>, BlockchainError> {
+        storage.get_anchor_blocks(digest).await
     }
 
+    // Full-text search over the payloads of all main chain blocks, best matches first
+    pub async fn search_blocks(storage: &mut Storage, query: &str, limit: i64) -> Result<Vec<SearchResult>, BlockchainError> {
+        storage.search_blocks(query, limit).await
+    }
+
+    pub async fn get_block_by_id(storage: &mut Storage, id: Height) -> Result<Block, BlockchainError> {
+        storage.get_block_by_id(id).await
+    }
+
+    pub async fn get_latest_block(storage: &mut Storage) -> Result<Block, BlockchainError> {
+        storage.get_latest_block().await
+    }
+
+    // Data carrying transactions is mined like mine_transactions, so their fees are collected by us
     pub async fn mine_block(
         &mut self,
         data: String,
-        db_client: &mut Client,
+        storage: &mut Storage,
     ) -> Result<Block, BlockchainError> {
+        match transactions::from_block_data(&data) {
+            Some(Ok(transactions)) => self.mine_transactions(transactions, storage).await,
+            _ => self.mine_block_with_fee(data, 0, storage).await,
+        }
+    }
+
+    // Mines a block carrying the batch of transactions, their fees are collected by us as the miner
+    pub async fn mine_transactions(&mut self, transactions: Vec<Transaction>, storage: &mut Storage) -> Result<Block, BlockchainError> {
+        if transactions.is_empty() {
+            return Err(BlockchainError::Error("no transactions to mine".to_owned()));
+        }
+        let data = transactions::to_block_data(&transactions);
+        self.mine_block_with_fee(data, transactions::total_fee(&transactions), storage).await
+    }
+
+    // The fee the data's job offered is collected by us as the miner, see fees.rs
+    pub async fn mine_block_with_fee(&mut self, data: String, fee: u64, storage: &mut Storage) -> Result<Block, BlockchainError> {
         info!("Mining block...");
         trace!("Mining block...");
 
-        let block = Block::new(&self.latest_block, data);
-
-        let statement = db_client.prepare_typed(
-            "INSERT INTO blocks (hash, id, prev_hash, timestamp, nonce, data) VALUES ($1, $2, $3, $4, $5, $6)",
-            &[Type::VARCHAR, Type::INT8, Type::VARCHAR, Type::INT8, Type::INT8, Type::VARCHAR],
-        ).await?;
-
-        db_client
-            .execute(
-                &statement,
-                &[
-                    &block.hash,
-                    &block.id,
-                    &block.prev_hash,
-                    &block.timestamp,
-                    &block.nonce,
-                    &block.data,
-                ],
-            )
-            .await?;
+        let mut template = self.block_template(storage, data).await?;
+        template.fee = fee;
+        self.template_hooks.apply(&mut template)?;
+        template.coinbase = self.coinbase(&template)?;
+        template.state_root = self.state_root(storage, &template).await?;
+        let block = Block::mine_template(template, &self.difficulty, self.hash_backend);
+
+        storage.append_block(&block).await?;
 
         //self.blocks.insert(block.hash.clone(), block);
         self.latest_block = block;
+        self.update_finalized(storage).await?;
         Ok(self.latest_block.clone())
     }
 
+    // The unmined block following our latest block, before the template hooks ran
+    pub async fn block_template(&self, storage: &mut Storage, data: String) -> Result<Block, BlockchainError> {
+        let difficulty = self.next_difficulty(storage, &self.latest_block).await?;
+        Ok(Block::template(&self.latest_block, data, self.miner.clone(), difficulty))
+    }
+
+    // The template handed out to the workers of a mining pool (see pool.rs), after the template hooks ran
+    pub async fn pool_template(&self, storage: &mut Storage, data: String) -> Result<Block, BlockchainError> {
+        let mut template = self.block_template(storage, data).await?;
+        self.template_hooks.apply(&mut template)?;
+        template.coinbase = self.coinbase(&template)?;
+        template.state_root = self.state_root(storage, &template).await?;
+        Ok(template)
+    }
+
+    // The coinbase of the block on top of our latest block, paying its reward and fee to our coinbase
+    // address. Blocks below the Coinbase upgrade can do without one if we have no address.
+    pub fn coinbase(&self, block: &Block) -> Result<Option<Transaction>, BlockchainError> {
+        if block.version < COINBASE_VERSION {
+            return Ok(None);
+        }
+        match &self.coinbase_address {
+            Some(address) => Ok(Some(Transaction::coinbase(address.clone(), self.coinbase_amount(block), block.id))),
+            None if consensus::rules_at(block.id).coinbase => {
+                Err(BlockchainError::Error("blocks have to pay a coinbase, mining requires a wallet".to_owned()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // What the coinbase of the block has to pay on our network
+    pub fn coinbase_amount(&self, block: &Block) -> u64 {
+        self.params.coinbase_amount(block.id, block.fee)
+    }
+
+    // Root of the state after the block on top of our latest block, computed once the hooks changed
+    // its data. Empty for older headers and if our chain was restored from a snapshot, which lacks
+    // the blocks the state is derived from.
+    pub async fn state_root(&self, storage: &mut Storage, block: &Block) -> Result<String, BlockchainError> {
+        if block.version < STATE_ROOT_VERSION || self.base.is_some() {
+            return Ok(String::new());
+        }
+        let mut state = match &self.latest_state {
+            Some(latest) if latest.block_hash == self.latest_block.hash => latest.state.clone(),
+            _ => State::at(storage, self.latest_block.id).await?,
+        };
+        state.apply(block);
+        Ok(state.root())
+    }
+
+    // The state after our latest block, derived from the chain on first use and kept up to date as
+    // blocks are added
+    pub async fn state(&mut self, storage: &mut Storage) -> Result<&State, BlockchainError> {
+        if !self.latest_state.as_ref().is_some_and(|latest| latest.block_hash == self.latest_block.hash) {
+            let state = State::at(storage, self.latest_block.id).await?;
+            self.latest_state = Some(LatestState { block_hash: self.latest_block.hash.clone(), state });
+        }
+        Ok(&self.latest_state.as_ref().expect("state was derived").state)
+    }
+
+    pub async fn get_balance(&mut self, storage: &mut Storage, address: &str) -> Result<AccountBalance, BlockchainError> {
+        Ok(self.state(storage).await?.balance(address))
+    }
+
+    // Difficulty the block following the parent has to have, 0 before the DifficultyAdjustment upgrade
+    pub async fn next_difficulty(&self, storage: &mut Storage, parent: &Block) -> Result<u64, BlockchainError> {
+        if !consensus::rules_at(parent.id + 1).difficulty_adjustment {
+            return Ok(0);
+        }
+        let mut blocks = vec![];
+        let mut block = parent.clone();
+        while blocks.len() < self.difficulty_algorithm.window() {
+            let prev_hash = block.prev_hash.clone();
+            let is_first = block.id == self.first_block().id;
+            blocks.push(block_info(&block));
+            if is_first {
+                break;
+            }
+            block = Chain::get_block(storage, &prev_hash).await?;
+        }
+        blocks.reverse();
+        if let Some(anchor) = self.difficulty_algorithm.anchor() {
+            if blocks.first().is_some_and(|first| first.height > anchor) {
+                let info = match &self.base {
+                    Some(base) if anchor < base.id => base
+                        .state
+                        .difficulty_anchor
+                        .ok_or_else(|| BlockchainError::BlockNotFound(anchor.to_string()))?,
+                    _ => block_info(&Chain::get_block_by_id(storage, anchor).await?),
+                };
+                blocks.insert(0, info);
+            }
+        }
+        let difficulty = self.difficulty_algorithm.next_difficulty(&blocks);
+        Ok(difficulty.clamp(difficulty::MIN_DIFFICULTY, difficulty::MAX_DIFFICULTY))
+    }
+
+    // Whether the ancestors the difficulty algorithm needs for the block are stored. A chain restored
+    // from a snapshot lacks them for the blocks right above its base, whose proof of work can only be
+    // checked against the difficulty they claim (see check_if_block_valid).
+    pub fn can_check_difficulty(&self, block: &Block) -> bool {
+        match &self.base {
+            Some(base) => {
+                !consensus::rules_at(block.id).difficulty_adjustment
+                    || block.id >= base.id + self.difficulty_algorithm.window() as u64
+            }
+            None => true,
+        }
+    }
+
+    // Checks that the block has the difficulty our difficulty algorithm expects for it, or the hash
+    // prefix of our network before the DifficultyAdjustment upgrade
+    pub async fn check_difficulty(&self, storage: &mut Storage, block: &Block) -> Result<(), BlockchainError> {
+        match self.difficulty_violation(storage, block).await? {
+            Some(_) => Err(BlockchainError::BlockInvalid(block.hash.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    async fn difficulty_violation(&self, storage: &mut Storage, block: &Block) -> Result<Option<String>, BlockchainError> {
+        if !consensus::rules_at(block.id).difficulty_adjustment {
+            if block.id != Height::GENESIS && !block.hash.starts_with(&self.difficulty) {
+                return Ok(Some(format!("hash does not start with {}", self.difficulty)));
+            }
+            return Ok(None);
+        }
+        let parent = Chain::get_block(storage, &block.prev_hash).await?;
+        let expected = self.next_difficulty(storage, &parent).await?;
+        if block.difficulty != expected {
+            return Ok(Some(format!("difficulty {} is not the expected {}", block.difficulty, expected)));
+        }
+        Ok(None)
+    }
+
+    // The main chain block below the height that rotated the signing key. Stale blocks are checked
+    // against the main chain too, which is below their height for all but deep forks.
+    pub async fn get_key_rotation(storage: &mut Storage, key: &str, below: Height) -> Result<Option<Block>, BlockchainError> {
+        Ok(storage
+            .get_address_blocks(key)
+            .await?
+            .into_iter()
+            .find(|block| block.id < below && keys::rotated_key(&block.data).as_deref() == Some(key)))
+    }
+
     pub async fn check_if_block_valid(
-        db_client: &mut Client,
+        storage: &mut Storage,
+        params: &NetworkParams,
         block: &Block,
     ) -> Result<(), BlockchainError> {
-        if block.id == 0 && block.hash == GENESIS_BLOCK_HASH {
-            return Ok(());
+        match Chain::block_violation(storage, params, block).await? {
+            Some(reason) => {
+                debug!("Block {} at height {} is invalid: {}", block.hash, block.id, reason);
+                Err(BlockchainError::BlockInvalid(block.hash.to_owned()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // The first consensus rule the block breaks on its own, against the parameters of its network and
+    // against the main chain below it, see check_if_block_valid. The parent has to be stored.
+    pub async fn block_violation(
+        storage: &mut Storage,
+        params: &NetworkParams,
+        block: &Block,
+    ) -> Result<Option<String>, BlockchainError> {
+        // Other chains have their own genesis block, the one in their storage
+        if block.id == Height::GENESIS
+            && (block.hash == GENESIS_BLOCK_HASH
+                || Chain::get_block_by_id(storage, Height::GENESIS).await.is_ok_and(|genesis| genesis.hash == block.hash))
+        {
+            return Ok(None);
         }
 
-        let prev_block = Chain::get_block(db_client, &block.prev_hash).await?;
-        if prev_block.id != block.id - 1 {
-            return Err(BlockchainError::BlockInvalid(block.hash.to_owned()));
+        let prev_block = Chain::get_block(storage, &block.prev_hash).await?;
+        if block.id.checked_sub(1) != Some(prev_block.id) {
+            return Ok(Some(format!("height {} does not follow height {} of its parent", block.id, prev_block.id)));
         }
 
-        let block_hash = hasher(&block.prev_hash, &block.data, block.timestamp, block.nonce);
+        let rules = consensus::rules_at(block.id);
+        if block.version > BLOCK_VERSION || block.version < rules.min_block_version {
+            return Ok(Some(format!("version {} is not between {} and {}", block.version, rules.min_block_version, BLOCK_VERSION)));
+        }
+        if block.miner.len() > MAX_MINER_LENGTH {
+            return Ok(Some(format!("miner is longer than {} bytes", MAX_MINER_LENGTH)));
+        }
+
+        if block.version >= MILLISECOND_TIMESTAMP_VERSION && block.timestamp_millis() < prev_block.timestamp_millis() {
+            return Ok(Some(format!("timestamp {} is before the timestamp {} of its parent", block.timestamp_millis(), prev_block.timestamp_millis())));
+        }
+
+        // Older headers do not hash the fee, it could be changed without changing the hash
+        if block.version < FEE_VERSION && block.fee != 0 {
+            return Ok(Some(format!("version {} blocks have no fee", block.version)));
+        }
+
+        // Same for the state root, which has to be set after the StateRoots upgrade. Whether it
+        // matches the state is checked by add_block, which knows the chain below the block.
+        if block.version < STATE_ROOT_VERSION && !block.state_root.is_empty() {
+            return Ok(Some(format!("version {} blocks have no state root", block.version)));
+        }
+        if rules.state_roots && block.state_root.is_empty() {
+            return Ok(Some("state root is missing".to_owned()));
+        }
+
+        // Same for the coinbase, exactly one has to be there after the Coinbase upgrade. It pays the
+        // reward of the network at the block's height and the fee the block collects.
+        if block.version < COINBASE_VERSION && block.coinbase.is_some() {
+            return Ok(Some(format!("version {} blocks have no coinbase", block.version)));
+        }
+        if rules.coinbase && block.coinbase.is_none() {
+            return Ok(Some("coinbase is missing".to_owned()));
+        }
+        if let Some(coinbase) = &block.coinbase {
+            if let Err(err) = coinbase.check_coinbase(block.id) {
+                return Ok(Some(format!("coinbase invalid: {}", err)));
+            }
+            let amount = params.coinbase_amount(block.id, block.fee);
+            if coinbase.amount != amount {
+                return Ok(Some(format!("coinbase pays {} instead of the reward and fee of {}", coinbase.amount, amount)));
+            }
+        }
+        // Invalid transaction data is rejected by the SignedTransactions rule below
+        if block.version >= COINBASE_VERSION && block.transactions().is_ok_and(|transactions| transactions.iter().any(Transaction::is_coinbase)) {
+            return Ok(Some("carries a second coinbase in its data".to_owned()));
+        }
+
+        let block_hash = hasher(block);
         if block_hash != block.hash {
-            return Err(BlockchainError::BlockInvalid(block.hash.to_owned()));
+            return Ok(Some(format!("header hashes to {}", block_hash)));
         }
 
-        Ok(())
+        if rules.difficulty_adjustment
+            && (block.difficulty == 0 || !difficulty::meets_difficulty(&digest_with_nonce(block, block.nonce), block.difficulty))
+        {
+            return Ok(Some(format!("proof of work does not meet difficulty {}", block.difficulty)));
+        }
+
+        if rules.key_rotations {
+            if let Err(err) = keys::check_block_data(&block.data) {
+                return Ok(Some(format!("key rotation invalid: {}", err)));
+            }
+        }
+
+        if rules.signed_transactions {
+            if let Err(err) = transactions::check_block_data(&block.data) {
+                return Ok(Some(format!("transaction invalid: {}", err)));
+            }
+        }
+
+        if rules.exclusive_key_rotations {
+            if let Some(key) = keys::rotated_key(&block.data) {
+                if let Some(rotation) = Chain::get_key_rotation(storage, &key, block.id).await? {
+                    return Ok(Some(format!("key {} was rotated in block {} already", key, rotation.id)));
+                }
+            }
+        }
+
+        // Like key rotations the spent outputs are looked up in the set of our main chain
+        if rules.utxo_spends {
+            // A transaction mined again would create its outputs again, spent ones included
+            for transaction in block.transactions()? {
+                let txid = transaction.id();
+                if let Some(confirmed) = Chain::get_transaction_blocks(storage, &txid).await?.iter().find(|confirmed| confirmed.id < block.id) {
+                    return Ok(Some(format!("transaction {} was confirmed in block {} already", txid, confirmed.hash)));
+                }
+            }
+            let inputs = utxo::inputs(block);
+            if !inputs.is_empty() && !utxo::apply_block(block, &storage.get_unspent(&inputs).await?).invalid.is_empty() {
+                return Ok(Some("spends outputs that are not unspent".to_owned()));
+            }
+        }
+
+        Ok(None)
     }
 
-    pub async fn validate_chain(&self, db_client: &mut Client) -> Result<(), BlockchainError> {
-        let block_count_row = db_client
-            .query_one(
-                "
-        SELECT 
-            COUNT (*)
-        FROM blocks
-        ",
-                &[],
-            )
-            .await?;
+    // Structural invariants of the stored main chain, much cheaper than validate_chain since no
+    // block is hashed or checked against the consensus rules: every height from our first block (the
+    // genesis block or the snapshot base) up to our latest block is stored exactly once, every block
+    // links to the one below it and the accumulated work grows with every block. Returns the first
+    // violation as ChainInvalid.
+    pub async fn check_invariants(&self, storage: &mut Storage) -> Result<(), BlockchainError> {
+        let violation = |reason: String| Err(BlockchainError::ChainInvalid(Box::new(BlockchainError::Error(reason))));
+        let blocks = Chain::get_chain(storage).await?;
+        let first = self.first_block();
+        match blocks.first() {
+            Some(block) if block.id == first.id && block.hash == first.hash => {}
+            Some(block) => return violation(format!("first block {} at height {} is not {}", block.hash, block.id, first.hash)),
+            None => return violation("no blocks stored".to_owned()),
+        }
 
-        let block_count: i64 = block_count_row.get(0);
+        let mut parent: Option<&Block> = None;
+        for (offset, block) in blocks.iter().enumerate() {
+            let height = first.id + offset as u64;
+            if block.id < height {
+                return violation(format!("height {} is stored more than once", block.id));
+            }
+            if block.id > height {
+                return violation(format!("height {} is missing", height));
+            }
+            if let Some(parent) = parent.filter(|parent| parent.hash != block.prev_hash) {
+                return violation(format!("block {} at height {} does not link to block {}", block.hash, block.id, parent.hash));
+            }
+            // The accumulated work only grows if every block adds some
+            if block_info(block).difficulty == 0 {
+                return violation(format!("block {} at height {} adds no work", block.hash, block.id));
+            }
+            parent = Some(block);
+        }
 
-        if block_count != self.latest_block.id + 1 {
+        match parent {
+            Some(tip) if tip.id != self.latest_block.id || tip.hash != self.latest_block.hash => {
+                violation(format!("stored tip {} at height {} is not our latest block {}", tip.hash, tip.id, self.latest_block.hash))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn validate_chain(&self, storage: &mut Storage) -> Result<(), BlockchainError> {
+        let started_at = Instant::now();
+        let valid = self.validate_stored_chain(storage).await;
+        watchdog::observe(TaskKind::Validation, started_at.elapsed(), || format!("stored chain up to {}", self.latest_block.hash));
+        valid
+    }
+
+    async fn validate_stored_chain(&self, storage: &mut Storage) -> Result<(), BlockchainError> {
+        let block_count = storage.count_blocks().await?;
+        let first = self.first_block();
+
+        if block_count != self.latest_block.id.0 + 1 - first.id.0 {
             return Err(BlockchainError::ChainInvalid(Box::new(
                 BlockchainError::Error("number of blocks != ID of latest block + 1".to_owned()),
             )));
         }
 
+        // Check what is stored, not what was cached
+        storage.clear_cache();
+
+        // The state roots are checked from genesis up, the state of a chain restored from a snapshot is unknown
+        if self.base.is_none() {
+            let mut state = State::default();
+            for block in Chain::get_chain(storage).await? {
+                state.apply(&block);
+                if !block.state_root.is_empty() && block.state_root != state.root() {
+                    return Err(BlockchainError::ChainInvalid(Box::new(BlockchainError::BlockInvalid(block.hash))));
+                }
+            }
+        }
         let mut current_block_hash = self.latest_block.hash.to_owned();
         let mut blocks_validated = 0;
         loop {
-            let current_block = Chain::get_block(db_client, &current_block_hash).await?;
-            match Chain::check_if_block_valid(db_client, &current_block).await {
+            let current_block = Chain::get_block(storage, &current_block_hash).await?;
+            let valid = match Chain::check_if_block_valid(storage, &self.params, &current_block).await {
+                Ok(()) if self.can_check_difficulty(&current_block) => self.check_difficulty(storage, &current_block).await,
+                // The parent of the base block is not stored
+                Err(BlockchainError::BlockNotFound(_)) if self.base.is_some() && current_block.id == first.id => {
+                    match hasher(&current_block) == current_block.hash {
+                        true => Ok(()),
+                        false => Err(BlockchainError::BlockInvalid(current_block.hash.to_owned())),
+                    }
+                }
+                result => result,
+            };
+            match valid {
                 Ok(()) => {
                     current_block_hash = current_block.prev_hash;
                 }
@@ -402,9 +1005,9 @@ impl Chain {
 
             blocks_validated += 1;
 
-            if current_block.id == 0 {
+            if current_block.id == first.id {
                 if blocks_validated == block_count {
-                    if current_block.hash == GENESIS_BLOCK_HASH {
+                    if current_block.hash == first.hash {
                         return Ok(());
                     }
                     return Err(BlockchainError::ChainInvalid(Box::new(
@@ -423,32 +1026,125 @@ impl Chain {
 #[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
 pub struct Block {
     pub hash: String,
-    pub id: i64,
+    pub id: Height,
     pub prev_hash: String,
     pub timestamp: i64,
-    pub nonce: i64,
+    pub nonce: Nonce,
     pub data: String,
+    pub version: u8,
+    pub miner: String,
+    pub extra_nonce: Nonce,
+    // Only part of version 3 headers, 0 for older blocks
+    #[serde(default)]
+    pub difficulty: u64,
+    // Only part of version 4 headers, 0 for older blocks
+    #[serde(default)]
+    pub fee: u64,
+    // Only part of version 5 headers, empty for older blocks and blocks below the StateRoots upgrade
+    // that were not mined by a Chain, see state.rs
+    #[serde(default)]
+    pub state_root: String,
+    // Only part of version 6 headers, which commit to its ID. Omitted if unset, so older blocks are
+    // sent and stored as before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coinbase: Option<Transaction>,
+}
+
+// Height and hash of a block that is considered final
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub id: Height,
+    pub hash: String,
+}
+
+impl Checkpoint {
+    pub fn genesis() -> Self {
+        Self {
+            id: Height::GENESIS,
+            hash: GENESIS_BLOCK_HASH.to_owned(),
+        }
+    }
+}
+
+// A valid block that did not make it into the main chain, together with the
+// local time we received it
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct StaleBlock {
+    pub block: Block,
+    pub received_at: i64,
+}
+
+// A block matching a full-text search, with the matching terms of its payload highlighted
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct SearchResult {
+    pub block: Block,
+    pub headline: String,
 }
 
 impl Block {
-    pub fn new(prev_block: &Block, data: String) -> Self {
-        let timestamp = Utc::now().timestamp();
-        let threads = num_cpus::get();
-        println!("threads: {}", threads);
-        let (hash, nonce) = find_hash(
-            &prev_block.hash,
-            &data,
-            timestamp,
-            BLOCK_DIFFICULTY,
-            threads,
-        );
+    pub fn new(prev_block: &Block, data: String, miner: String) -> Self {
+        Block::mine(prev_block, data, miner, BLOCK_DIFFICULTY, 0, HashBackend::Cpu)
+    }
+
+    // Mines the block with the given difficulty, or with the hash prefix if the difficulty is 0
+    pub fn mine(
+        prev_block: &Block,
+        data: String,
+        miner: String,
+        prefix: &str,
+        difficulty: u64,
+        backend: HashBackend,
+    ) -> Self {
+        Self::mine_template(Self::template(prev_block, data, miner, difficulty), prefix, backend)
+    }
+
+    // Mines the template with its difficulty, or with the hash prefix if the difficulty is 0
+    pub fn mine_template(mut block: Block, prefix: &str, backend: HashBackend) -> Self {
+        let difficulty = block.difficulty;
+        let threads = mining_threads();
+        debug!("Mining with {} threads", threads);
+        // Whenever the whole nonce space has been searched unsuccessfully,
+        // we continue with the next extra nonce
+        loop {
+            let gpu_result = match backend {
+                HashBackend::Gpu => gpu::find_hash(&block, prefix)
+                    .map_err(|err| error!("GPU hashing failed, falling back to the CPU: {}", err))
+                    .ok(),
+                HashBackend::Cpu => None,
+            };
+            let result = match gpu_result {
+                Some(result) => result,
+                None if difficulty > 0 => find_hash_with_difficulty(&block, difficulty, threads),
+                None => find_hash(&block, prefix, threads),
+            };
+            if let Some((hash, nonce)) = result {
+                block.hash = hash;
+                block.nonce = nonce;
+                return block;
+            }
+            block.extra_nonce = Nonce(block.extra_nonce.0 + 1);
+        }
+    }
+
+    // The block following prev_block, without hash and nonce
+    pub fn template(prev_block: &Block, data: String, miner: String, difficulty: u64) -> Self {
+        // Blocks mined within the same millisecond or with our clock behind the parent's
+        // get the timestamp of the parent
+        let timestamp = Utc::now().timestamp_millis().max(prev_block.timestamp_millis());
         Self {
-            hash,
+            hash: String::new(),
             id: prev_block.id + 1,
             prev_hash: prev_block.hash.to_owned(),
             timestamp,
-            nonce,
+            nonce: Nonce(0),
             data,
+            version: BLOCK_VERSION,
+            miner,
+            extra_nonce: Nonce(0),
+            difficulty,
+            fee: 0,
+            state_root: String::new(),
+            coinbase: None,
         }
     }
 
@@ -456,59 +1152,133 @@ impl Block {
         // let timestamp = Utc::now().timestamp();
         Self {
             hash: GENESIS_BLOCK_HASH.to_owned(),
-            id: 0,
+            id: Height::GENESIS,
             prev_hash: "null".to_owned(),
             timestamp: GENESIS_BLOCK_TIME,
-            nonce: 0,
+            nonce: Nonce(0),
             data: GENESIS_BLOCK_DATA.to_owned(),
+            version: 0,
+            miner: String::new(),
+            extra_nonce: Nonce(0),
+            difficulty: 0,
+            fee: 0,
+            state_root: String::new(),
+            coinbase: None,
+        }
+    }
+
+    // Timestamps of blocks before version 2 are in seconds
+    pub fn timestamp_millis(&self) -> i64 {
+        if self.version >= MILLISECOND_TIMESTAMP_VERSION {
+            self.timestamp
+        } else {
+            self.timestamp * 1000
         }
     }
+
+    // The transactions the block carries, in order, none if its data carries no transactions. An error
+    // if the data only looks like transactions, which blocks below the SignedTransactions upgrade may
+    // carry (see transactions::check_block_data). Readers of stored blocks take those as carrying none.
+    pub fn transactions(&self) -> Result<Vec<Transaction>, BlockchainError> {
+        transactions::from_block_data(&self.data).unwrap_or(Ok(vec![]))
+    }
+
+    // All addresses this block touches: the address of its miner and the one its coinbase pays, both
+    // keys of a key rotation it announces and the senders and recipients of the transactions it carries
+    pub fn addresses(&self) -> Vec<String> {
+        let mut addresses = vec![];
+        if !self.miner.is_empty() {
+            addresses.push(self.miner.clone());
+        }
+        if let Some(coinbase) = &self.coinbase {
+            addresses.push(coinbase.recipient.clone());
+        }
+        if let Some(Ok(rotation)) = keys::KeyRotation::from_block_data(&self.data) {
+            addresses.push(rotation.old_key);
+            addresses.push(rotation.new_key);
+        }
+        for transaction in self.transactions().unwrap_or_default() {
+            addresses.push(transaction.sender);
+            addresses.push(transaction.recipient);
+        }
+        addresses
+    }
 }
 
-// Takes the input and hashes it with a new nonce until a hash with the desired block difficulty is found
-// Returns the hash and the nonce
+// Takes the block template and hashes it with a new nonce until a hash with the desired block difficulty is found
+// Returns the hash and the nonce, or None if the whole nonce space has been searched unsuccessfully
 
 // In order to circumvent the overhead that Mutex-locking causes, each thread works on blocks of
 // 100 nonces at a time before checking again if a nonce has been found. Check the benchmark file
 // for details on performance
 
 pub fn find_hash(
-    prev_hash: &str,
-    data: &str,
-    timestamp: i64,
+    template: &Block,
     block_difficulty: &str,
     threads: usize,
-) -> (String, i64) {
-    let shared_max_nonce = Arc::new(Mutex::new(0 as i64));
-    let hash = Arc::new(Mutex::new("".to_owned()));
-    let final_nonce = Arc::new(Mutex::new(0 as i64));
+) -> Option<(String, Nonce)> {
+    search_nonce(template, threads, &|digest| encode_hash(template, digest).starts_with(block_difficulty))
+}
+
+// Like find_hash, but for blocks after the DifficultyAdjustment upgrade
+pub fn find_hash_with_difficulty(
+    template: &Block,
+    difficulty: u64,
+    threads: usize,
+) -> Option<(String, Nonce)> {
+    search_nonce(template, threads, &|digest| difficulty::meets_difficulty(digest, difficulty))
+}
+
+// Whether the digest of the block with some nonce is good enough for the block: it has to meet the
+// difficulty in the header, or start with the prefix before the DifficultyAdjustment upgrade
+pub fn meets_target(block: &Block, digest: &[u8], prefix: &str) -> bool {
+    if block.difficulty > 0 {
+        difficulty::meets_difficulty(digest, block.difficulty)
+    } else {
+        encode_hash(block, digest).starts_with(prefix)
+    }
+}
+
+// Searches for a nonce whose digest is accepted, returns it with the encoded hash
+fn search_nonce(
+    template: &Block,
+    threads: usize,
+    accept: &(dyn Fn(&[u8]) -> bool + Sync),
+) -> Option<(String, Nonce)> {
+    let header_hasher = HeaderHasher::new(template);
+    let shared_max_nonce = Arc::new(Mutex::new(0_u64));
+    let result = Arc::new(Mutex::new(None));
 
     crossbeam::scope(|s| {
         for _ in 0..threads {
             //println!("started thread nr. {}", thread);
-            let (shared_max_nonce, hash, final_nonce) = (
+            let (shared_max_nonce, result, header_hasher) = (
                 Arc::clone(&shared_max_nonce),
-                Arc::clone(&hash),
-                Arc::clone(&final_nonce),
+                Arc::clone(&result),
+                &header_hasher,
             );
             s.spawn(move |_| loop {
                 let mut shared_max_nonce = shared_max_nonce.lock().unwrap();
-                let start_nonce = shared_max_nonce.clone();
-                let end_nonce = start_nonce.clone() + 100;
+                let start_nonce = *shared_max_nonce;
+                // Nonce space exhausted
+                if start_nonce == Nonce::MAX.0 {
+                    break;
+                }
+                let end_nonce = start_nonce.saturating_add(100).min(Nonce::MAX.0);
                 *shared_max_nonce = end_nonce;
                 drop(shared_max_nonce);
                 for current_nonce in start_nonce..end_nonce {
-                    let hash_string = hasher(prev_hash, data, timestamp, current_nonce);
-                    if !hash_string.starts_with(block_difficulty) {
+                    let digest = header_hasher.digest(Nonce(current_nonce));
+                    if !accept(&digest) {
                         continue;
                     }
-                    if *final_nonce.lock().unwrap() == 0 {
-                        *hash.lock().unwrap() = hash_string;
-                        *final_nonce.lock().unwrap() = current_nonce;
+                    let mut result = result.lock().unwrap();
+                    if result.is_none() {
+                        *result = Some((encode_hash(template, &digest), Nonce(current_nonce)));
                     }
                     break;
                 }
-                if *final_nonce.lock().unwrap() != 0 {
+                if result.lock().unwrap().is_some() {
                     break;
                 }
             });
@@ -516,27 +1286,153 @@ pub fn find_hash(
     })
     .unwrap();
 
-    (
-        Arc::try_unwrap(hash).unwrap().into_inner().unwrap(),
-        Arc::try_unwrap(final_nonce).unwrap().into_inner().unwrap(),
-    )
+    Arc::try_unwrap(result).unwrap().into_inner().unwrap()
 }
 
-pub fn hasher(prev_hash: &str, data: &str, timestamp: i64, nonce: i64) -> String {
-    let json = serde_json::json!({
-        "prev_hash": prev_hash,
-        "data": data,
-        "timestamp": timestamp,
-        "nonce": nonce
-    });
-    let string = json.to_string();
-    let bytes = string.as_bytes();
+pub fn hasher(block: &Block) -> String {
+    hash_with_nonce(block, block.nonce)
+}
+
+fn hash_with_nonce(block: &Block, nonce: Nonce) -> String {
+    encode_hash(block, &digest_with_nonce(block, nonce))
+}
+
+// Hashes all header fields of the block with the given nonce instead of the block's own
+pub fn digest_with_nonce(block: &Block, nonce: Nonce) -> Vec<u8> {
     let mut hasher = Sha256::new();
-    hasher.update(bytes);
-    let byte_vec = hasher.finalize().as_slice().to_owned();
+    hasher.update(header_json(block, nonce).as_bytes());
+    hasher.finalize().as_slice().to_owned()
+}
+
+// The bytes hasher hashes, see vectors.rs
+pub fn header_bytes(block: &Block) -> Vec<u8> {
+    header_json(block, block.nonce).into_bytes()
+}
+
+// The hashed header of the block split around the nonce, so the nonce digits can be put in
+// between: prefix + nonce + suffix is the input of digest_with_nonce
+pub fn header_around_nonce(block: &Block) -> (String, String) {
+    let json = header_json(block, Nonce(0));
+    // Quotes in string values are escaped, so this can only match the nonce field
+    let field = "\"nonce\":";
+    let index = json.find(&format!("{}0", field)).expect("header has a nonce") + field.len();
+    (json[..index].to_owned(), json[index + 1..].to_owned())
+}
+
+// Hashes the header of a block with many different nonces: the part in front of the nonce is only
+// hashed once (midstate), per nonce just the nonce and the rest of the header are hashed
+#[derive(Clone)]
+pub struct HeaderHasher {
+    midstate: Sha256,
+    suffix: String,
+}
+
+impl HeaderHasher {
+    pub fn new(block: &Block) -> Self {
+        let (prefix, suffix) = header_around_nonce(block);
+        let mut midstate = Sha256::new();
+        midstate.update(prefix.as_bytes());
+        Self { midstate, suffix }
+    }
+
+    // Same as digest_with_nonce
+    pub fn digest(&self, nonce: Nonce) -> [u8; 32] {
+        // The nonce as JSON number, without allocating
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        let mut rest = nonce.0;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        let mut hasher = self.midstate.clone();
+        hasher.update(&digits[start..]);
+        hasher.update(self.suffix.as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+fn header_json(block: &Block, nonce: Nonce) -> String {
+    let json = match block.version {
+        0 => serde_json::json!({
+            "prev_hash": block.prev_hash,
+            "data": block.data,
+            "timestamp": block.timestamp,
+            "nonce": nonce
+        }),
+        1 | 2 => serde_json::json!({
+            "version": block.version,
+            "prev_hash": block.prev_hash,
+            "miner": block.miner,
+            "data": block.data,
+            "timestamp": block.timestamp,
+            "nonce": nonce,
+            "extra_nonce": block.extra_nonce
+        }),
+        3 => serde_json::json!({
+            "version": block.version,
+            "prev_hash": block.prev_hash,
+            "miner": block.miner,
+            "data": block.data,
+            "timestamp": block.timestamp,
+            "nonce": nonce,
+            "extra_nonce": block.extra_nonce,
+            "difficulty": block.difficulty
+        }),
+        4 => serde_json::json!({
+            "version": block.version,
+            "prev_hash": block.prev_hash,
+            "miner": block.miner,
+            "data": block.data,
+            "timestamp": block.timestamp,
+            "nonce": nonce,
+            "extra_nonce": block.extra_nonce,
+            "difficulty": block.difficulty,
+            "fee": block.fee
+        }),
+        5 => serde_json::json!({
+            "version": block.version,
+            "prev_hash": block.prev_hash,
+            "miner": block.miner,
+            "data": block.data,
+            "timestamp": block.timestamp,
+            "nonce": nonce,
+            "extra_nonce": block.extra_nonce,
+            "difficulty": block.difficulty,
+            "fee": block.fee,
+            "state_root": block.state_root
+        }),
+        // The ID of the coinbase, null without one
+        _ => serde_json::json!({
+            "version": block.version,
+            "prev_hash": block.prev_hash,
+            "miner": block.miner,
+            "data": block.data,
+            "timestamp": block.timestamp,
+            "nonce": nonce,
+            "extra_nonce": block.extra_nonce,
+            "difficulty": block.difficulty,
+            "fee": block.fee,
+            "state_root": block.state_root,
+            "coinbase": block.coinbase.as_ref().map(Transaction::id)
+        }),
+    };
+    json.to_string()
+}
+
+// Encodes the digest as required by the rules active at the block's height
+fn encode_hash(block: &Block, digest: &[u8]) -> String {
+    let encoding = consensus::rules_at(block.id).hash_encoding;
     // Convert Vec<u8> to Hex String
-    let string: String = byte_vec.iter().fold("".to_owned(), |mut acc, el| {
-        acc.push_str(&format!("{:X?}", el));
+    let string: String = digest.iter().fold("".to_owned(), |mut acc, el| {
+        match encoding {
+            HashEncoding::Legacy => acc.push_str(&format!("{:X?}", el)),
+            HashEncoding::PaddedHex => acc.push_str(&format!("{:02X}", el)),
+        }
         acc
     });
     string
@@ -544,31 +1440,14 @@ pub fn hasher(prev_hash: &str, data: &str, timestamp: i64, nonce: i64) -> String
 
 // Synchronous hashing function only used for benchmarking, see benches/benchmark.rs
 pub fn find_hash_sync(
-    prev_hash: &str,
-    data: &str,
-    timestamp: i64,
+    template: &Block,
     block_difficulty: &str,
-) -> (String, i64) {
-    let mut nonce = 0;
+) -> (String, Nonce) {
+    let mut nonce = Nonce(0);
     loop {
-        let json = serde_json::json!({
-            "prev_hash": prev_hash,
-            "data": data,
-            "timestamp": timestamp,
-            "nonce": nonce
-        });
-        let string = json.to_string();
-        let bytes = string.as_bytes();
-        let mut hasher = Sha256::new();
-        hasher.update(bytes);
-        let byte_vec = hasher.finalize().as_slice().to_owned();
-        // Convert Vec<u8> to Hex String
-        let string: String = byte_vec.iter().fold("".to_owned(), |mut acc, el| {
-            acc.push_str(&format!("{:X?}", el));
-            acc
-        });
+        let string = hash_with_nonce(template, nonce);
         if !string.starts_with(block_difficulty) {
-            nonce += 1;
+            nonce = Nonce(nonce.0 + 1);
             continue;
         }
         return (string, nonce);
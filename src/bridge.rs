@@ -0,0 +1,131 @@
+// Cross-chain anchor bridge (`--bridge chain:NAME|http://HOST[:PORT]/PATH`): every bridge interval
+// the tip of our default chain is committed to another chain, so rewriting our history would also
+// require rewriting the other chain's. The target is either one of the additional chains we follow
+// (see chains.rs), where the commitment is mined as block data, or an HTTP notary the commitment is
+// POSTed to as JSON. Commitments in a chain can be verified against our blocks (`bridge verify`),
+// the ones sent to a notary have to be checked with the notary.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+// Block data of bridge anchors starts with this, followed by the JSON encoded BridgeAnchor
+pub const BRIDGE_PREFIX: &str = "bridge ";
+pub const DEFAULT_BRIDGE_INTERVAL: Duration = Duration::from_secs(600);
+// Notaries not answering in time are skipped until the next interval
+const NOTARY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Commitment to a block of the source chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeAnchor {
+    // Genesis hash of the source chain, chains are told apart by it
+    pub genesis: String,
+    pub height: Height,
+    pub hash: String,
+}
+
+impl BridgeAnchor {
+    pub fn new(genesis: &Block, block: &Block) -> Self {
+        Self { genesis: genesis.hash.clone(), height: block.id, hash: block.hash.clone() }
+    }
+
+    pub fn to_block_data(&self) -> String {
+        format!("{}{}", BRIDGE_PREFIX, serde_json::to_string(self).expect("can jsonify bridge anchor"))
+    }
+
+    // None if the data is no bridge anchor
+    pub fn from_block_data(data: &str) -> Option<Self> {
+        serde_json::from_str(data.strip_prefix(BRIDGE_PREFIX)?).ok()
+    }
+
+    // Checks that the anchor commits to the block of the source chain
+    pub fn verify(&self, block: &Block) -> Result<(), BlockchainError> {
+        if block.id != self.height || block.hash != self.hash {
+            return Err(BlockchainError::Error(format!(
+                "anchored block {} at height {} is not part of our chain, found {}",
+                self.hash, self.height, block.hash
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BridgeTarget {
+    // One of the additional chains
+    Chain(String),
+    Notary { host: String, port: u16, path: String },
+}
+
+impl BridgeTarget {
+    pub fn parse(arg: &str) -> Result<Self, BlockchainError> {
+        let invalid = || BlockchainError::Error(format!("invalid bridge target {}, use chain:NAME or http://HOST[:PORT]/PATH", arg));
+        if let Some(chain) = arg.strip_prefix("chain:") {
+            return Ok(Self::Chain(chain.to_owned()));
+        }
+        let rest = arg.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self::Notary { host: host.to_owned(), port, path: path.to_owned() })
+    }
+}
+
+// Commitments to our chain found in the target chain, oldest first, together with the blocks
+// carrying them
+pub async fn find_anchors(target: &mut Storage, genesis: &Block) -> Result<Vec<(Block, BridgeAnchor)>, BlockchainError> {
+    Ok(Chain::get_chain(target)
+        .await?
+        .into_iter()
+        .filter_map(|block| BridgeAnchor::from_block_data(&block.data).map(|anchor| (block, anchor)))
+        .filter(|(_, anchor)| anchor.genesis == genesis.hash)
+        .collect())
+}
+
+// POSTs the anchor to the notary, returns the HTTP status
+pub async fn notarize(host: &str, port: u16, path: &str, anchor: &BridgeAnchor) -> Result<u16, BlockchainError> {
+    time::timeout(NOTARY_TIMEOUT, post(host, port, path, anchor))
+        .await
+        .map_err(|_| BlockchainError::Error(format!("notary {} did not answer", host)))?
+}
+
+async fn post(host: &str, port: u16, path: &str, anchor: &BridgeAnchor) -> Result<u16, BlockchainError> {
+    let body = serde_json::to_string(anchor).expect("can jsonify bridge anchor");
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await?;
+    // HTTP/1.1 200 OK
+    String::from_utf8_lossy(&response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| BlockchainError::Error(format!("invalid response from notary {}", host)))
+}
+
+// Checks an anchor found in the target chain against the source chain's storage
+pub async fn verify_anchor(source: &mut Storage, anchor: &BridgeAnchor) -> Result<(), BlockchainError> {
+    let block = Chain::get_block_by_id(source, anchor.height)
+        .await
+        .map_err(|_| BlockchainError::Error(format!("anchored height {} is above our chain", anchor.height)))?;
+    anchor.verify(&block)
+}
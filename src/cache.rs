@@ -0,0 +1,102 @@
+// Cache of recently touched main chain blocks in front of Postgres (see storage.rs), by hash and by
+// height, so validation, RPC reads and gossip handling do not query the database for every block
+// they look at. Only main chain blocks are cached. Whatever removes blocks from the main chain
+// (reorgs, truncation, a failed chain swap) invalidates the affected entries.
+use crate::blockchain::Block;
+use crate::types::Height;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+
+pub const BLOCK_CACHE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub blocks: usize,
+}
+
+pub struct BlockCache {
+    blocks: LruCache<String, Block>,
+    // Hash of the cached block at each height
+    heights: HashMap<Height, String>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new(BLOCK_CACHE_SIZE)
+    }
+}
+
+impl BlockCache {
+    pub fn new(size: usize) -> Self {
+        Self {
+            blocks: LruCache::new(NonZeroUsize::new(size.max(1)).expect("cache size is not zero")),
+            heights: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, hash: &str) -> Option<Block> {
+        let block = self.blocks.get(hash).cloned();
+        self.count(block.is_some());
+        block
+    }
+
+    pub fn get_by_id(&mut self, id: Height) -> Option<Block> {
+        let block = self.heights.get(&id).and_then(|hash| self.blocks.get(hash)).cloned();
+        self.count(block.is_some());
+        block
+    }
+
+    pub fn insert(&mut self, block: Block) {
+        // A block replacing another one at its height
+        if let Some(previous) = self.heights.insert(block.id, block.hash.clone()) {
+            if previous != block.hash {
+                self.blocks.pop(&previous);
+            }
+        }
+        // push also returns the old entry when the block was cached already, which must stay
+        let hash = block.hash.clone();
+        if let Some((evicted_hash, evicted)) = self.blocks.push(hash.clone(), block) {
+            if evicted_hash != hash && self.heights.get(&evicted.id) == Some(&evicted_hash) {
+                self.heights.remove(&evicted.id);
+            }
+        }
+    }
+
+    // Forgets the blocks above the height, e.g. after they were removed by a reorg
+    pub fn invalidate_above(&mut self, id: Height) {
+        let hashes = self
+            .heights
+            .iter()
+            .filter(|(height, _)| **height > id)
+            .map(|(_, hash)| hash.clone())
+            .collect::<Vec<String>>();
+        for hash in hashes {
+            self.blocks.pop(&hash);
+        }
+        self.heights.retain(|height, _| *height <= id);
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.heights.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits, misses: self.misses, blocks: self.blocks.len() }
+    }
+
+    fn count(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
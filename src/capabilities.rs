@@ -0,0 +1,124 @@
+// Capability report (`node capabilities`, the `getcapabilities` RPC method): what the binary was built
+// with and which protocol versions it speaks, as JSON, so the nodes of a fleet can be audited for
+// compatibility before an upgrade. Everything in it is fixed when the binary is built: there are no
+// timestamps, host names or anything else about the machine or the run, so builds of the same commit
+// for the same target with the same features report the same, and the report is only ever sent to
+// whoever asks for it.
+use crate::address::{ADDRESS_VERSION, MULTISIG_ADDRESS_VERSION};
+use crate::blockchain::BLOCK_VERSION;
+use crate::consensus::ACTIVATIONS;
+use crate::difficulty;
+use crate::handshake::{Features, HANDSHAKE_VERSION};
+use crate::network::PROTOCOL_VERSION;
+use crate::nodeinfo::{COMMIT, VERSION};
+use crate::p2p::{DIRECT_PROTOCOL, HANDSHAKE_PROTOCOL, POOL_PROTOCOL};
+use crate::rpc;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+
+// Unknown when built without build.rs, e.g. by other build systems
+pub const TARGET: &str = match option_env!("BUILD_TARGET") {
+    Some(target) => target,
+    None => "unknown",
+};
+pub const PROFILE: &str = match cfg!(debug_assertions) {
+    true => "debug",
+    false => "release",
+};
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub build: BuildInfo,
+    pub storage_backends: Vec<String>,
+    // How blocks are produced and which difficulty algorithms proof of work can follow
+    pub consensus_engines: Vec<String>,
+    pub difficulty_algorithms: Vec<String>,
+    // The consensus upgrades this build knows, with their activation heights (see consensus.rs)
+    pub consensus_upgrades: Vec<Upgrade>,
+    pub hash_algorithms: Vec<String>,
+    // Of blocks, transactions, key rotations and checkpoints
+    pub signature_schemes: Vec<String>,
+    // Where the nonce search runs (`--hasher`)
+    pub hash_backends: Vec<String>,
+    pub transports: Vec<String>,
+    pub protocols: Protocols,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: String,
+    pub commit: String,
+    pub target: String,
+    pub profile: String,
+    // Optional cargo features the binary was built with
+    pub features: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Upgrade {
+    pub name: String,
+    pub height: Height,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Protocols {
+    // Identify protocol of the default network, other networks append their genesis hash
+    pub p2p: String,
+    // Spoken next to gossipsub
+    pub request_response: Vec<String>,
+    pub handshake: u32,
+    // Block header versions we validate, the highest is the one we mine
+    pub block_versions: Vec<u8>,
+    // Version bytes of the Base58Check addresses we accept, see address.rs
+    pub address_versions: Vec<u8>,
+    // The optional features peers can announce in the handshake
+    pub peer_features: Vec<String>,
+    pub json_rpc: String,
+    pub rpc_methods: Vec<String>,
+}
+
+pub fn capabilities() -> Capabilities {
+    let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect::<Vec<_>>();
+    let mut features = vec![];
+    if cfg!(feature = "gpu") {
+        features.push("gpu");
+    }
+    let mut hash_backends = vec!["cpu"];
+    if cfg!(feature = "gpu") {
+        hash_backends.push("gpu");
+    }
+    let mut transports = vec!["tcp+noise+mplex", "mdns", "gossipsub", "json-rpc-tcp", "events-tcp", "stratum-tcp"];
+    if cfg!(unix) {
+        transports.push("ipc-unix");
+    }
+    Capabilities {
+        build: BuildInfo {
+            version: VERSION.to_owned(),
+            commit: COMMIT.to_owned(),
+            target: TARGET.to_owned(),
+            profile: PROFILE.to_owned(),
+            features: strings(&features),
+        },
+        storage_backends: strings(&["postgres", "memory"]),
+        consensus_engines: strings(&["proof-of-work", "slots"]),
+        difficulty_algorithms: strings(&difficulty::ALGORITHMS),
+        consensus_upgrades: ACTIVATIONS
+            .iter()
+            .map(|activation| Upgrade { name: format!("{:?}", activation.feature), height: activation.height })
+            .collect(),
+        hash_algorithms: strings(&["sha256"]),
+        signature_schemes: strings(&["ed25519"]),
+        hash_backends: strings(&hash_backends),
+        transports: strings(&transports),
+        protocols: Protocols {
+            p2p: PROTOCOL_VERSION.to_owned(),
+            request_response: strings(&[DIRECT_PROTOCOL, POOL_PROTOCOL, HANDSHAKE_PROTOCOL]),
+            handshake: HANDSHAKE_VERSION,
+            block_versions: (0..=BLOCK_VERSION).collect(),
+            address_versions: vec![ADDRESS_VERSION, MULTISIG_ADDRESS_VERSION],
+            peer_features: strings(&Features(u64::MAX).names()),
+            json_rpc: "2.0".to_owned(),
+            rpc_methods: rpc::METHODS.iter().map(|method| method.name.to_owned()).collect(),
+        },
+    }
+}
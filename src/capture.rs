@@ -0,0 +1,138 @@
+// Message capture (`--capture PATH`): for debugging, all p2p messages we send and receive (gossip,
+// direct sends and pool messages) can be written to a gzip compressed log, one JSON encoded
+// CapturedMessage per line, with the time, the peer and the raw message. The log is rotated once
+// --capture-max-bytes of messages have been written to it: PATH is moved to PATH.1, replacing the
+// previous one, so at most twice that is kept. `p2p replay FILE` feeds the received messages of a
+// capture through the message handler again, to reproduce a problem without the network (see
+// p2p::replay_capture). They are handled by an in-memory copy of the node, so our chain is not changed.
+use crate::blockchain::BlockchainError;
+use crate::deadletter::BadMessage;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CAPTURE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Channel {
+    Gossip,
+    Direct,
+    Pool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedMessage {
+    // Milliseconds since the epoch
+    pub at: i64,
+    pub direction: Direction,
+    pub channel: Channel,
+    // The peer we got the message from or sent it to, None for messages we gossiped
+    pub peer: Option<String>,
+    // Author of a gossiped message, if it differs from the peer forwarding it
+    pub source: Option<String>,
+    // Additional chain the message belongs to, None for the default chain
+    pub chain: Option<String>,
+    // Hex encoded message, which does not have to be valid UTF-8
+    pub data: String,
+}
+
+impl CapturedMessage {
+    pub fn bytes(&self) -> Result<Vec<u8>, BlockchainError> {
+        hex::decode(&self.data).map_err(|err| BlockchainError::Error(format!("invalid captured message: {}", err)))
+    }
+}
+
+// Outcome of replaying a capture
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaptureReplay {
+    // Received messages handed to the handler again
+    pub replayed: u64,
+    // Received messages that are not replayed, e.g. peer exchanges
+    pub skipped: u64,
+    // Replayed messages that could not be decoded
+    pub bad: Vec<BadMessage>,
+}
+
+pub struct CaptureWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    // Bytes of messages written to the current file, before compression
+    written: u64,
+    encoder: GzEncoder<BufWriter<File>>,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path, max_bytes: u64) -> Result<Self, BlockchainError> {
+        Ok(Self { path: path.to_owned(), max_bytes, written: 0, encoder: encoder(path)? })
+    }
+
+    pub fn write(&mut self, message: &CapturedMessage) -> Result<(), BlockchainError> {
+        let mut line = serde_json::to_vec(message).expect("can jsonify captured message");
+        line.push(b'\n');
+        self.encoder.write_all(&line)?;
+        // Flushed right away, so a capture is readable up to the last message even if the node crashes
+        self.encoder.flush()?;
+        self.written += line.len() as u64;
+        if self.written >= self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), BlockchainError> {
+        // The open file is moved, its gzip trailer is written under the new name
+        fs::rename(&self.path, rotated_path(&self.path))?;
+        let previous = std::mem::replace(&mut self.encoder, encoder(&self.path)?);
+        previous.finish()?.flush()?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        let _ = self.encoder.try_finish();
+    }
+}
+
+fn encoder(path: &Path) -> Result<GzEncoder<BufWriter<File>>, BlockchainError> {
+    Ok(GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default()))
+}
+
+// Where the previous messages are kept when the capture is rotated
+pub fn rotated_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".1");
+    path.with_file_name(file_name)
+}
+
+// Reads all messages of a capture, oldest first. A capture cut off by a crash ends with its last
+// complete message.
+pub fn read_capture(path: &Path) -> Result<Vec<CapturedMessage>, BlockchainError> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    let mut messages = vec![];
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        match serde_json::from_str(&line) {
+            Ok(message) => messages.push(message),
+            // Only the last line can be incomplete
+            Err(err) if err.is_eof() => break,
+            Err(err) => return Err(BlockchainError::Error(format!("invalid capture {}: {}", path.display(), err))),
+        }
+    }
+    Ok(messages)
+}
@@ -0,0 +1,125 @@
+// Deterministic chains for benchmarks and tests (`chain generate --blocks N [--seed S]`): blocks are
+// appended to our chain in its storage, with payloads drawn from an RNG seeded with S, timestamps
+// of a fixed clock (BLOCK_INTERVAL_MS after their parent) and the first nonce meeting the
+// difficulty. The same seed on top of the same chain always gives the same blocks, e.g. the same
+// 10,000 blocks in memory and in Postgres to compare validation, sync and database performance.
+// Mining has to be trivial, so chains are only generated with the minimal difficulty (--regtest).
+use crate::blockchain::{digest_with_nonce, hasher, meets_target, Block, BlockchainError, Chain};
+use crate::difficulty::MIN_DIFFICULTY;
+use crate::state::State;
+use crate::storage::Storage;
+use crate::transactions::Transaction;
+use crate::types::Nonce;
+use crate::wallet;
+use libp2p::identity::ed25519;
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub const DEFAULT_SEED: u64 = 0;
+// Time between a generated block and its parent
+pub const BLOCK_INTERVAL_MS: i64 = 1_000;
+// Miner of the generated blocks, ours would make them differ between nodes
+pub const GENERATOR_MINER: &str = "chaingen";
+// Secret key of the address the coinbases of generated blocks pay, fixed for the same reason
+const GENERATOR_SECRET: [u8; 32] = [0x67; 32];
+// Payloads of generated blocks start with this
+pub const GENERATED_PREFIX: &str = "generated ";
+const PAYLOAD_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerateConfig {
+    pub blocks: u64,
+    pub seed: u64,
+}
+
+impl GenerateConfig {
+    // Parses "--blocks N [--seed S]"
+    pub fn from_args(args: &str) -> Result<Self, BlockchainError> {
+        let mut blocks = None;
+        let mut seed = DEFAULT_SEED;
+
+        let mut args = args.split_whitespace();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--blocks" => {
+                    blocks = Some(
+                        args.next()
+                            .and_then(|n| n.parse::<u64>().ok())
+                            .filter(|n| *n > 0)
+                            .ok_or_else(|| BlockchainError::Error("--blocks requires a number of blocks".to_owned()))?,
+                    );
+                }
+                "--seed" => {
+                    seed = args
+                        .next()
+                        .and_then(|seed| seed.parse::<u64>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--seed requires a number".to_owned()))?;
+                }
+                _ => return Err(BlockchainError::Error(format!("unknown option: {}", arg))),
+            }
+        }
+        let blocks = blocks.ok_or_else(|| BlockchainError::Error("--blocks is required".to_owned()))?;
+        Ok(Self { blocks, seed })
+    }
+}
+
+// The payload of the i-th generated block (counting from 0)
+pub fn payloads(seed: u64) -> impl Iterator<Item = String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..).map(move |i: u64| {
+        let padding = (&mut rng).sample_iter(&Alphanumeric).take(PAYLOAD_SIZE).map(char::from).collect::<String>();
+        format!("{}{} {}", GENERATED_PREFIX, i, padding)
+    })
+}
+
+// Appends the blocks to the chain, reporting (generated, total) after every block. The blocks are
+// valid by construction and are written without being validated again, so large chains are quick
+// to generate. Returns the new latest block.
+pub async fn generate(
+    chain: &mut Chain,
+    storage: &mut Storage,
+    config: &GenerateConfig,
+    progress: &mut (dyn FnMut(u64, u64) + Send),
+) -> Result<Block, BlockchainError> {
+    // The state is kept up to date here instead of being derived from the whole chain for every block
+    let mut state = match chain.base {
+        Some(_) => None,
+        None => Some(State::at(storage, chain.latest_block.id).await?),
+    };
+    let coinbase_address = generator_address();
+    for (done, data) in (1..=config.blocks).zip(payloads(config.seed)) {
+        let difficulty = chain.next_difficulty(storage, &chain.latest_block).await?;
+        if difficulty > MIN_DIFFICULTY {
+            return Err(BlockchainError::Error(format!("generating requires the minimal difficulty, the next block needs {}", difficulty)));
+        }
+        let mut block = Block::template(&chain.latest_block, data, GENERATOR_MINER.to_owned(), difficulty);
+        block.timestamp = chain.latest_block.timestamp_millis() + BLOCK_INTERVAL_MS;
+        block.coinbase = Some(Transaction::coinbase(coinbase_address.clone(), chain.coinbase_amount(&block), block.id));
+        if let Some(state) = &mut state {
+            state.apply(&block);
+            block.state_root = state.root();
+        }
+        mine(&mut block, &chain.difficulty);
+        storage.append_block(&block).await?;
+        chain.latest_block = block;
+        chain.update_finalized(storage).await?;
+        progress(done, config.blocks);
+    }
+    Ok(chain.latest_block.clone())
+}
+
+pub fn generator_address() -> String {
+    let secret = ed25519::SecretKey::from_bytes(GENERATOR_SECRET).expect("32 bytes are a valid ed25519 secret");
+    wallet::address(&ed25519::Keypair::from(secret).public())
+}
+
+// Tries the nonces in order on one thread, so the nonce does not depend on thread scheduling
+fn mine(block: &mut Block, prefix: &str) {
+    let nonce = (0..)
+        .map(Nonce)
+        .find(|nonce| meets_target(block, &digest_with_nonce(block, *nonce), prefix))
+        .unwrap_or(Nonce(0));
+    block.nonce = nonce;
+    block.hash = hasher(block);
+}
@@ -0,0 +1,78 @@
+// Additional chains (`--chain NAME[:DAA]`): besides its default chain a node can follow further,
+// independent chains. Each one has its own gossipsub topic, genesis block (derived from its name),
+// storage namespace (a Postgres schema, or a snapshot file of its own) and difficulty algorithm.
+// Events of these chains travel between the p2p task and main wrapped in EventType::ForChain, and
+// commands are scoped to them with `@NAME COMMAND`.
+use crate::blockchain::{self, Block, BlockchainError};
+use crate::difficulty;
+use std::path::{Path, PathBuf};
+
+pub const MAX_CHAIN_NAME_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSpec {
+    // Lower case letters, digits and underscores, so it can be used in topic and schema names
+    pub name: String,
+    // One of difficulty::ALGORITHMS, defaults like for the default chain
+    pub difficulty_algorithm: Option<String>,
+}
+
+impl ChainSpec {
+    // Parses NAME[:DAA]
+    pub fn parse(arg: &str) -> Result<Self, BlockchainError> {
+        let (name, difficulty_algorithm) = match arg.split_once(':') {
+            Some((name, algorithm)) => (name, Some(algorithm.to_owned())),
+            None => (arg, None),
+        };
+        if !is_valid_name(name) {
+            return Err(BlockchainError::Error(format!(
+                "invalid chain name {:?}: use up to {} lower case letters, digits and underscores",
+                name, MAX_CHAIN_NAME_LENGTH
+            )));
+        }
+        if difficulty_algorithm.as_deref().is_some_and(|algorithm| !difficulty::ALGORITHMS.contains(&algorithm)) {
+            return Err(BlockchainError::Error(format!("--chain difficulty has to be one of {:?}", difficulty::ALGORITHMS)));
+        }
+        Ok(Self { name: name.to_owned(), difficulty_algorithm })
+    }
+
+    // The chain's own genesis block, so its blocks are never mistaken for ones of another chain
+    pub fn genesis(&self) -> Block {
+        let mut genesis = Block { data: format!("genesis of chain {}", self.name), ..Block::create_genesis() };
+        genesis.hash = blockchain::hasher(&genesis);
+        genesis
+    }
+
+    pub fn topic(&self) -> String {
+        topic_name(&self.name)
+    }
+
+    // Postgres schema holding the chain's tables
+    pub fn schema(&self) -> String {
+        format!("chain_{}", self.name)
+    }
+
+    // File of the chain next to the one of the default chain, e.g. the snapshot of its in-memory
+    // storage or the journal of its write-ahead queue
+    pub fn chain_file(&self, path: &Path) -> PathBuf {
+        let mut file_name = path.file_name().unwrap_or_default().to_owned();
+        file_name.push(format!(".{}", self.name));
+        path.with_file_name(file_name)
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_CHAIN_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+pub fn topic_name(chain: &str) -> String {
+    format!("blockchain/{}", chain)
+}
+
+// Splits "@NAME COMMAND" into the chain name and the command, None for commands of the default chain
+pub fn split_scope(input: &str) -> Option<(&str, &str)> {
+    let scoped = input.strip_prefix('@')?;
+    Some(scoped.split_once(' ').map(|(name, command)| (name, command.trim())).unwrap_or((scoped, "")))
+}
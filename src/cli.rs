@@ -0,0 +1,327 @@
+// Shell completions and man page (`node completions bash|zsh|fish`, `node manpage`), generated from
+// the definition of the command line: the start-up options in config::USAGE, the commands of the
+// node's prompt in COMMANDS (printed at start-up) and the methods of rust-blockchain-cli in
+// rpc::METHODS (see ipc.rs). The node parses its arguments by hand, so these tables are the
+// definition, and options and commands added to them show up in the completions and the man page
+// without further changes. Completions cover the start-up options and their values, the commands
+// are typed into the running node and only documented in the man page.
+use crate::blockchain::BlockchainError;
+use crate::config;
+use crate::rpc;
+use std::fmt;
+
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+pub const NODE_BINARY: &str = "rust-blockchain";
+pub const CLI_BINARY: &str = "rust-blockchain-cli";
+
+// A command of the node's prompt
+pub struct CliCommand {
+    pub usage: &'static str,
+    // Empty if the usage says it all
+    pub summary: &'static str,
+}
+
+impl fmt::Display for CliCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.summary {
+            "" => write!(f, "{}", self.usage),
+            summary => write!(f, "{} //{}", self.usage, summary),
+        }
+    }
+}
+
+pub const COMMANDS: &[CliCommand] = &[
+    CliCommand { usage: "block mine BLOCK_DATA", summary: "queue a block with normal priority" },
+    CliCommand { usage: "mine queue", summary: "show pending mining jobs and how full the mempool and the orphan pool are" },
+    CliCommand { usage: "mine queue add high|normal|low [--fee N] BLOCK_DATA", summary: "the fee is collected by the miner, see --min-fee-rate" },
+    CliCommand { usage: "mine queue priority JOB_ID high|normal|low", summary: "" },
+    CliCommand { usage: "mine queue top JOB_ID", summary: "mine the job next" },
+    CliCommand { usage: "mine queue cancel JOB_ID", summary: "" },
+    CliCommand { usage: "block mine --to KEY[,KEY...] BLOCK_DATA", summary: "queue a block with the data encrypted to the keys" },
+    CliCommand { usage: "block decrypt BLOCK_HASH", summary: "decrypt an encrypted payload sent to us" },
+    CliCommand { usage: "block validate BLOCK_HASH", summary: "" },
+    CliCommand { usage: "block check BLOCK_JSON|BLOCK_HEX", summary: "validate a candidate block against our latest block without storing it, shows why it is rejected" },
+    CliCommand { usage: "block get BLOCK_HASH [--min-confirmations N]", summary: "" },
+    CliCommand { usage: "block receipt BLOCK_HASH", summary: "show the outcome, gas used and events of the block's payload" },
+    CliCommand { usage: "payload get PAYLOAD_HASH", summary: "show a payload by its SHA-256" },
+    CliCommand { usage: "chain validate", summary: "" },
+    CliCommand { usage: "chain check [--repair truncate|resync]", summary: "check that the stored blocks link up to genesis" },
+    CliCommand { usage: "chain check FILE", summary: "validate a candidate chain (a JSON array of blocks) against ours without storing it" },
+    CliCommand { usage: "chain replay", summary: "execute all blocks again from genesis and compare with the stored state" },
+    CliCommand { usage: "chain generate --blocks N [--seed S]", summary: "append N deterministic blocks with seeded payloads (--regtest only)" },
+    CliCommand { usage: "chain du [--from-height H] [--to-height H] [--top N]", summary: "show the storage used and the payload sizes of the blocks, largest first" },
+    CliCommand { usage: "chain uncles", summary: "show recent stale blocks" },
+    CliCommand { usage: "chain graph --out FILE [--from-height H]", summary: "write the main chain and the stale blocks as a Graphviz graph, e.g. to look at forks" },
+    CliCommand { usage: "chain upgrades", summary: "show consensus upgrade schedule" },
+    CliCommand { usage: "chain network", summary: "show the parameters of our network" },
+    CliCommand { usage: "block confirmations BLOCK_HASH", summary: "show the number of blocks on top of the block" },
+    CliCommand { usage: "address txs ADDRESS [--min-confirmations N]", summary: "show blocks touching the address" },
+    CliCommand { usage: "address utxos ADDRESS", summary: "show the unspent outputs and balance of the address" },
+    CliCommand { usage: "address convert ADDRESS", summary: "show the Base58Check address and the key of a Base58Check address, hex key or multisig address" },
+    CliCommand { usage: "address earnings ADDRESS", summary: "show the block rewards and fees the address collected as miner" },
+    CliCommand { usage: "blocks search \"QUERY\" [--min-confirmations N]", summary: "full-text search over block data" },
+    CliCommand { usage: "anchor file PATH [PATH...]", summary: "queue a block anchoring the SHA-256 of the files" },
+    CliCommand { usage: "anchor verify PATH [--min-confirmations N]", summary: "show the proof that the file was anchored" },
+    CliCommand { usage: "state prove KEY [--height H]", summary: "prove the value of received/ADDRESS, sent/ADDRESS or rotated/KEY after the block at the height, the latest by default" },
+    CliCommand { usage: "state verify PROOF_JSON", summary: "check a state proof against the header it carries" },
+    CliCommand { usage: "state balance ADDRESS", summary: "show what the address received and sent on the main chain and its balance" },
+    CliCommand { usage: "rpc docs", summary: "print the OpenRPC document of the --rpc server, to generate clients from" },
+    CliCommand { usage: "sync sessions", summary: "show recent sync sessions" },
+    CliCommand { usage: "sync history [N]", summary: "show the last N finished sync sessions with their trigger and our tip before and after, newest first" },
+    CliCommand { usage: "node loadgen [--blocks-per-sec N] [--payload-size S]", summary: "generate blocks (--regtest only)" },
+    CliCommand { usage: "node loadgen stop", summary: "" },
+    CliCommand { usage: "node reindex", summary: "rebuild the address, anchor, transaction and search indexes and the receipts from the blocks" },
+    CliCommand { usage: "node cache", summary: "show the hits and misses of the block cache and the queued writes" },
+    CliCommand { usage: "node reload", summary: "apply changed log level, mining threads, target peers, mempool policy, min confirmations, stale tip factor and watchdog thresholds from the command line and config file" },
+    CliCommand { usage: "node latency", summary: "show percentiles of the arrival latency of received blocks and of the first acknowledgement of our mined blocks" },
+    CliCommand { usage: "node slow", summary: "show the queries, validations and event handling that took longer than the watchdog thresholds" },
+    CliCommand { usage: "node install-service [systemd|launchd]", summary: "print a service definition running the node with its current options" },
+    CliCommand { usage: "node paths", summary: "show where the node keeps its keys, config, peer store, snapshots and logs" },
+    CliCommand { usage: "node stats", summary: "show when the node was first and last started, its total uptime and the blocks mined and received, reorgs and peers seen over all runs" },
+    CliCommand { usage: "node id", summary: "show our peer id, listen addresses, genesis hash, network, storage backend and build" },
+    CliCommand { usage: "node capabilities", summary: "print the build, storage backends, consensus engines, hash algorithms, transports and protocol versions of the binary as JSON" },
+    CliCommand { usage: "node tip", summary: "show how long our latest block did not change and how often it was stale" },
+    CliCommand { usage: "node gc", summary: "show what pruning the stale blocks below the finalized block reclaimed" },
+    CliCommand { usage: "node completions bash|zsh|fish", summary: "print a completion script for the start-up options and the methods of rust-blockchain-cli" },
+    CliCommand { usage: "node manpage", summary: "print the man page of the options and commands, e.g. to install as rust-blockchain.1" },
+    CliCommand { usage: "pool start BLOCK_DATA", summary: "coordinate a mining pool mining blocks with the data" },
+    CliCommand { usage: "pool join PEER_ID", summary: "mine as a worker of the pool coordinated by the peer" },
+    CliCommand { usage: "pool leave", summary: "" },
+    CliCommand { usage: "pool stop", summary: "" },
+    CliCommand { usage: "pool status", summary: "" },
+    CliCommand { usage: "tx create [--multisig M KEY,KEY...|--from INDEX] [--fee N] [--inputs TXID:VOUT,...] RECIPIENT AMOUNT", summary: "create a transfer from our signing key, the multisig or the wallet address, spending the outputs" },
+    CliCommand { usage: "tx sign [--partial] TX_JSON", summary: "sign the transaction with our signing or wallet key, --partial adds our signatures to a multisig one" },
+    CliCommand { usage: "tx combine TX_JSON TX_JSON...", summary: "merge the signatures of copies of a multisig transaction" },
+    CliCommand { usage: "tx broadcast TX_JSON", summary: "add the signed transaction to the transaction pool, mined in batches" },
+    CliCommand { usage: "tx pending", summary: "show the transactions in the pool, in the order they are mined" },
+    CliCommand { usage: "tx get TXID", summary: "" },
+    CliCommand { usage: "tx receipt TXID", summary: "show the outcome of the transaction, see block receipt" },
+    CliCommand { usage: "tx status TXID", summary: "pending in the mempool or the transaction pool, confirmed at height H or dropped" },
+    CliCommand { usage: "wallet show", summary: "show the address of the node's wallet (--wallet or the signing key), its balance and what we mined" },
+    CliCommand { usage: "wallet new", summary: "create an HD wallet and show its mnemonic" },
+    CliCommand { usage: "wallet restore MNEMONIC", summary: "restore the HD wallet and rescan the chain for its addresses" },
+    CliCommand { usage: "wallet address", summary: "hand out the next address of the wallet" },
+    CliCommand { usage: "wallet addresses", summary: "show the addresses of the wallet and their balances" },
+    CliCommand { usage: "wallet history [--export csv [FILE]]", summary: "show the confirmed transactions of our addresses with the running balance, or export them as CSV" },
+    CliCommand { usage: "wallet watch ADDRESS [LABEL]", summary: "track the balance and transactions of the address from the next block on" },
+    CliCommand { usage: "wallet unwatch ADDRESS", summary: "" },
+    CliCommand { usage: "wallet watched", summary: "show the watch-only addresses with their balances and transactions" },
+    CliCommand { usage: "wallet rescan --from-height H", summary: "count the blocks from H on for the watch-only addresses and look for used wallet addresses" },
+    CliCommand { usage: "multisig address M KEY,KEY...", summary: "show the address of the M-of-N policy over the signing keys" },
+    CliCommand { usage: "keys show", summary: "show our peer ID, signing and encryption key" },
+    CliCommand { usage: "keys rotate", summary: "replace the signing key and announce the new one on-chain" },
+    CliCommand { usage: "ls p [--verbose]", summary: "show all peers and their scores, --verbose adds the ranking of the peers we sync from" },
+    CliCommand { usage: "ls p --services", summary: "show the public API endpoints announced by our peers (--announce-service)" },
+    CliCommand { usage: "p2p badmsgs", summary: "show the latest messages from peers that could not be decoded" },
+    CliCommand { usage: "p2p replay FILE", summary: "handle the received messages of a capture (--capture) again, on a copy of the chain" },
+    CliCommand { usage: "chains", summary: "show the additional chains we follow" },
+    CliCommand { usage: "@CHAIN COMMAND", summary: "run a command on an additional chain, see `@CHAIN help`" },
+    CliCommand { usage: "bridge status", summary: "show where our tip is anchored" },
+    CliCommand { usage: "bridge verify", summary: "check the anchors of our chain in the bridge chain" },
+    CliCommand { usage: "exit", summary: "" },
+];
+
+// A start-up option of config::USAGE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliOption {
+    pub name: String,
+    // The placeholder of its value, None for flags
+    pub value: Option<String>,
+    // Given more than once, e.g. --peer
+    pub repeatable: bool,
+}
+
+impl CliOption {
+    // The values to pick from, if the placeholder lists them like postgres|memory
+    pub fn choices(&self) -> Vec<&str> {
+        let value = match &self.value {
+            Some(value) if value.contains('|') => value,
+            _ => return vec![],
+        };
+        let choices = value.split('|').collect::<Vec<_>>();
+        match choices.iter().all(|choice| !choice.is_empty() && choice.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')) {
+            true => choices,
+            false => vec![],
+        }
+    }
+
+    // Whether the value is a file or directory
+    pub fn takes_path(&self) -> bool {
+        matches!(self.value.as_deref(), Some("PATH" | "DIR" | "FILE"))
+    }
+}
+
+// The options of the usage, in their order: the bracketed groups starting with --, their value is
+// the rest of the group
+pub fn parse_options(usage: &str) -> Vec<CliOption> {
+    let mut options = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in usage.char_indices() {
+        match c {
+            '[' => {
+                if depth == 0 {
+                    start = i + 1;
+                }
+                depth += 1;
+            }
+            ']' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    let group = &usage[start..i];
+                    if let Some(option) = group.strip_prefix("--") {
+                        let (name, value) = option.split_once(' ').unwrap_or((option, ""));
+                        options.push(CliOption {
+                            name: format!("--{}", name),
+                            value: Some(value.trim().to_owned()).filter(|value| !value.is_empty()),
+                            repeatable: usage[i + 1..].starts_with("..."),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    options
+}
+
+// The start-up options of the node
+pub fn options() -> Vec<CliOption> {
+    parse_options(config::USAGE)
+}
+
+pub fn completions(shell: &str) -> Result<String, BlockchainError> {
+    let options = options();
+    let methods = rpc::METHODS.iter().map(|method| method.name).collect::<Vec<_>>();
+    match shell {
+        "bash" => Ok(bash_completions(&options, &methods)),
+        "zsh" => Ok(zsh_completions(&options, &methods)),
+        "fish" => Ok(fish_completions(&options, &methods)),
+        _ => Err(BlockchainError::Error(format!("shell has to be one of {:?}", SHELLS))),
+    }
+}
+
+// Source it from ~/.bashrc or install it to /etc/bash_completion.d
+fn bash_completions(options: &[CliOption], methods: &[&str]) -> String {
+    let mut script = format!("# bash completion for {} and {}, generated by `node completions bash`\n", NODE_BINARY, CLI_BINARY);
+    script.push_str("_rust_blockchain() {\n    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n    case \"$prev\" in\n");
+    for option in options.iter().filter(|option| option.value.is_some()) {
+        let choices = option.choices();
+        let reply = if !choices.is_empty() {
+            format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", choices.join(" "))
+        } else if option.takes_path() {
+            "COMPREPLY=($(compgen -f -- \"$cur\"))".to_owned()
+        } else {
+            // Free-form value
+            "COMPREPLY=()".to_owned()
+        };
+        script.push_str(&format!("        {}) {}; return ;;\n", option.name, reply));
+    }
+    let names = options.iter().map(|option| option.name.as_str()).collect::<Vec<_>>();
+    script.push_str(&format!("    esac\n    COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n}}\n", names.join(" ")));
+    script.push_str(&format!("complete -F _rust_blockchain {}\n\n", NODE_BINARY));
+    script.push_str("_rust_blockchain_cli() {\n    local cur=\"${COMP_WORDS[COMP_CWORD]}\"\n    case \"$COMP_CWORD\" in\n");
+    script.push_str("        1) COMPREPLY=($(compgen -W \"--connect\" -- \"$cur\")) ;;\n");
+    script.push_str("        2) COMPREPLY=($(compgen -f -- \"$cur\")) ;;\n");
+    script.push_str(&format!("        3) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;\n", methods.join(" ")));
+    script.push_str("        *) COMPREPLY=() ;;\n    esac\n}\n");
+    script.push_str(&format!("complete -F _rust_blockchain_cli {}\n", CLI_BINARY));
+    script
+}
+
+// Install it as _rust-blockchain in a directory of $fpath
+fn zsh_completions(options: &[CliOption], methods: &[&str]) -> String {
+    let mut script = format!("#compdef {} {}\n# generated by `node completions zsh`\n\n", NODE_BINARY, CLI_BINARY);
+    script.push_str("_rust_blockchain() {\n    _arguments \\\n");
+    for option in options.iter() {
+        let repeat = if option.repeatable { "*" } else { "" };
+        let action = match &option.value {
+            None => String::new(),
+            Some(value) => {
+                // The message ends at the next colon
+                let message = if value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '|' || c == '-') { value.as_str() } else { "value" };
+                let choices = option.choices();
+                if !choices.is_empty() {
+                    format!(":{}:({})", message, choices.join(" "))
+                } else if option.takes_path() {
+                    format!(":{}:_files", message)
+                } else {
+                    format!(":{}: ", message)
+                }
+            }
+        };
+        script.push_str(&format!("        '{}{}{}' \\\n", repeat, option.name, action));
+    }
+    script.push_str("        '1::database name: '\n}\n\n");
+    script.push_str(&format!(
+        "_rust_blockchain_cli() {{\n    _arguments '1:option:(--connect)' '2:socket:_files' '3:method:({})' '*:params: '\n}}\n\n",
+        methods.join(" ")
+    ));
+    script.push_str(&format!(
+        "case $service in\n    {}) _rust_blockchain \"$@\" ;;\n    {}) _rust_blockchain_cli \"$@\" ;;\nesac\n",
+        NODE_BINARY, CLI_BINARY
+    ));
+    script
+}
+
+// Install it to ~/.config/fish/completions/rust-blockchain.fish
+fn fish_completions(options: &[CliOption], methods: &[&str]) -> String {
+    let mut script = format!("# fish completion for {} and {}, generated by `node completions fish`\n", NODE_BINARY, CLI_BINARY);
+    script.push_str(&format!("complete -c {} -f\n", NODE_BINARY));
+    for option in options.iter() {
+        let name = option.name.trim_start_matches('-');
+        let choices = option.choices();
+        let argument = match &option.value {
+            None => String::new(),
+            Some(_) if !choices.is_empty() => format!(" -x -a '{}'", choices.join(" ")),
+            Some(_) if option.takes_path() => " -r -F".to_owned(),
+            Some(_) => " -x".to_owned(),
+        };
+        script.push_str(&format!("complete -c {} -l {}{}\n", NODE_BINARY, name, argument));
+    }
+    script.push_str(&format!("complete -c {} -f\n", CLI_BINARY));
+    script.push_str(&format!("complete -c {} -l connect -r -F\n", CLI_BINARY));
+    script.push_str(&format!("complete -c {} -n '__fish_seen_argument -l connect' -a '{}'\n", CLI_BINARY, methods.join(" ")));
+    script
+}
+
+// The man page in roff, view it with `man -l FILE`
+pub fn manpage() -> String {
+    let mut page = format!(".TH RUST-BLOCKCHAIN 1 \"\" \"{} {}\" \"User Commands\"\n", NODE_BINARY, env!("CARGO_PKG_VERSION"));
+    page.push_str(&format!(".SH NAME\n{} \\- blockchain node with a command prompt\n", NODE_BINARY));
+    page.push_str(&format!(".SH SYNOPSIS\n{}\n", roff_escape(config::USAGE)));
+    page.push_str(".SH DESCRIPTION\nStarts a node with Postgres or in-memory storage, joins the network of its peers and reads commands from stdin. See the README for the details of the options and commands.\n");
+    page.push_str(".SH OPTIONS\n");
+    for option in options() {
+        page.push_str(&format!(".TP\n.B {}", roff_escape(&option.name)));
+        if let Some(value) = &option.value {
+            page.push_str(&format!(" \\fI{}\\fR", roff_escape(value)));
+        }
+        page.push('\n');
+        if option.repeatable {
+            page.push_str("Can be given more than once.\n");
+        }
+    }
+    page.push_str(".SH COMMANDS\nRead from stdin while the node runs.\n");
+    for command in COMMANDS {
+        page.push_str(&format!(".TP\n.B {}\n", roff_escape(command.usage)));
+        if !command.summary.is_empty() {
+            page.push_str(&format!("{}\n", roff_escape(command.summary)));
+        }
+    }
+    page.push_str(&format!(
+        ".SH SEE ALSO\n.B {} \\-\\-connect \\fIPATH\\fR [\\fIMETHOD\\fR [\\fIPARAMS\\fR]]\nsends requests to the control socket of a node started with \\-\\-ipc, the methods are {}.\n",
+        CLI_BINARY,
+        roff_escape(&rpc::METHODS.iter().map(|method| method.name).collect::<Vec<_>>().join(", "))
+    ));
+    page
+}
+
+// Backslashes and hyphens are escaped, lines starting with a dot or quote would be requests
+fn roff_escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    match escaped.starts_with('.') || escaped.starts_with('\'') {
+        true => format!("\\&{}", escaped),
+        false => escaped,
+    }
+}
@@ -0,0 +1,609 @@
+use crate::blockchain::{BlockchainError, HashBackend};
+use crate::bridge::{self, BridgeTarget};
+use crate::capture;
+use crate::chains::ChainSpec;
+use crate::database;
+use crate::datadir::DataDir;
+use crate::difficulty;
+use crate::endpoints::{self, Service};
+use crate::fastsync::SnapshotTrust;
+use crate::gc::StalePruning;
+use crate::integrity::{self, RepairStrategy};
+use crate::mining::{Eviction, MempoolPolicy, Priority};
+use crate::network::NetworkParams;
+use crate::orphans::{DEFAULT_MAX_ORPHANS, DEFAULT_MAX_ORPHAN_BYTES};
+use crate::p2p::DEFAULT_TARGET_PEERS;
+use crate::propagation::{self, Propagation};
+use crate::staletip::DEFAULT_STALE_TIP_FACTOR;
+use crate::watchdog::Thresholds;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+
+const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+pub const MIN_CONFIRMATIONS_OPTION: &str = "--min-confirmations";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageKind {
+    Postgres,
+    Memory,
+}
+
+// Start-up options, parsed from the command line. The shell completions and the man page are
+// generated from it, see cli.rs.
+pub const USAGE: &str = "rust-blockchain [DB_NAME] [--storage postgres|memory] [--no-p2p] [--snapshot PATH] [--snapshot-interval SECS] \
+    [--outbound-only] [--listen MULTIADDR]... [--peer MULTIADDR]... [--regtest] [--daa fixed|epoch|lwma|asert] \
+    [--stratum ADDR] [--hasher cpu|gpu] [--keys DIR] [--wallet FILE] [--slot-time SECS] [--proposer PEER_ID]... \
+    [--events ADDR] [--rpc ADDR] [--ipc PATH] [--announce-service KIND=URL]... [--min-confirmations N] [--repair truncate|resync] [--chain NAME[:DAA]]... \
+    [--bridge chain:NAME|http://HOST[:PORT]/PATH] [--bridge-interval SECS] \
+    [--capture PATH] [--capture-max-bytes BYTES] [--checkpoint-signer PEER_ID]... [--wal PATH] [--encrypt-at-rest] \
+    [--fast-sync] [--snapshot-signer PEER_ID]... [--snapshot-quorum N] [--propagation full|announce] [--network PATH] [--mempool PATH] \
+    [--mempool-max-age SECS] [--mempool-max-jobs N] [--mempool-max-bytes BYTES] \
+    [--mempool-eviction lowest-fee|oldest] [--mempool-min-priority low|normal|high] \
+    [--min-fee-rate FEE] [--max-orphans N] [--max-orphan-bytes BYTES] [--archival] [--keep-stale-headers] [--stale-tip-factor N] [--data-dir DIR] \
+    [--log-level off|error|warn|info|debug|trace] [--mining-threads N] [--target-peers N] \
+    [--db-host HOST] [--db-wait SECS] [--slow-query-ms MS] [--slow-validation-ms MS] [--slow-gossip-ms MS]";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub storage: StorageKind,
+    // Only used with Postgres storage
+    pub db_name: Option<String>,
+    // Host of the Postgres server, e.g. the service name in a docker-compose network
+    pub db_host: String,
+    // How long we wait on start-up for the Postgres server to accept connections, see database.rs
+    pub db_wait: Duration,
+    pub p2p: bool,
+    // Only used with in-memory storage, the chain is loaded from and periodically written to this file
+    pub snapshot: Option<PathBuf>,
+    pub snapshot_interval: Duration,
+    // Do not listen for incoming connections, only dial out (e.g. behind a strict firewall)
+    pub outbound_only: bool,
+    // Addresses we listen on for incoming connections, p2p::DEFAULT_LISTEN_ADDRS if none are given
+    pub listen: Vec<String>,
+    // Addresses of peers to dial on start-up and whenever we lost all connections
+    pub peers: Vec<String>,
+    // Mine with the regtest difficulty, for local test networks and load generation
+    pub regtest: bool,
+    // Difficulty adjustment algorithm of the network, see difficulty.rs. Defaults to a fixed
+    // difficulty with --regtest and to difficulty::DEFAULT_ALGORITHM otherwise.
+    pub difficulty_algorithm: Option<String>,
+    // Address the stratum server for standalone hashers listens on, see stratum.rs
+    pub stratum: Option<SocketAddr>,
+    // Where blocks are mined, see gpu.rs
+    pub hash_backend: HashBackend,
+    // Directory of the encrypted identity and signing keys, see keys.rs. Without it the node gets a
+    // new peer ID on every start and has no signing key.
+    pub keys: Option<PathBuf>,
+    // File of the node's wallet, created on the first start, see wallet.rs. Without it the signing
+    // key is the wallet.
+    pub wallet: Option<PathBuf>,
+    // Produce a block every slot of this length instead of only on request, see slots.rs
+    pub slot_time: Option<Duration>,
+    // Peer IDs of the miners taking turns producing the slot blocks
+    pub proposers: Vec<String>,
+    // Address clients connect to for the block event stream, see events.rs
+    pub events: Option<SocketAddr>,
+    // Address the JSON-RPC server for block queries listens on, see rpc.rs
+    pub rpc: Option<SocketAddr>,
+    // Local socket answering the same requests, see ipc.rs
+    pub ipc: Option<PathBuf>,
+    // Public endpoints of our APIs we announce to the network, see endpoints.rs
+    pub services: Vec<Service>,
+    // Blocks with fewer blocks on top are left out by the data retrieval commands, unless the
+    // command sets its own --min-confirmations
+    pub min_confirmations: u64,
+    // How a chain failing the start-up integrity check is repaired, see integrity.rs. Without it
+    // the node does not start on a corrupted chain.
+    pub repair: Option<RepairStrategy>,
+    // Chains followed besides the default one, see chains.rs
+    pub chains: Vec<ChainSpec>,
+    // Where the tip of the default chain is anchored, see bridge.rs
+    pub bridge: Option<BridgeTarget>,
+    pub bridge_interval: Duration,
+    // Gzip compressed log of all p2p messages, see capture.rs
+    pub capture: Option<PathBuf>,
+    // The capture is rotated once this many bytes of messages were written to it
+    pub capture_max_bytes: u64,
+    // Peers whose finalized checkpoints we accept before the blocks are max_reorg_depth deep
+    pub checkpoint_signers: Vec<String>,
+    // Only used with Postgres storage, journal of the blocks that are not written yet, see wal.rs
+    pub wal: Option<PathBuf>,
+    // Block payloads, the state of a restored chain, snapshots and the journal are encrypted on disk
+    // with the key in atrest::STORAGE_KEY_ENV or the storage key of --keys DIR, see atrest.rs
+    pub encrypt_at_rest: bool,
+    // A new node syncs from a snapshot of a long chain instead of the complete chain, see fastsync.rs
+    pub fast_sync: bool,
+    // Whose snapshots a fast sync restores and how many of them have to agree
+    pub snapshot_trust: SnapshotTrust,
+    // Whether the blocks we mine are gossiped in full or announced, see propagation.rs
+    pub propagation: Propagation,
+    // Consensus-economic parameters of the default chain's network, loaded from the file given with
+    // --network, see network.rs
+    pub network: NetworkParams,
+    // The pending mining jobs of the default chain are kept in this file across restarts, see mining.rs
+    pub mempool: Option<PathBuf>,
+    pub mempool_policy: MempoolPolicy,
+    // Caps on the number and summed data size of the blocks waiting for their parent, see orphans.rs
+    pub max_orphans: usize,
+    pub max_orphan_bytes: usize,
+    // What happens to stale blocks once they are below our finalized block, see gc.rs
+    pub stale_pruning: StalePruning,
+    // Our latest block is stale if it did not change for this multiple of the target block time,
+    // see staletip.rs
+    pub stale_tip_factor: u32,
+    // Directory with our keys, config, snapshot, mempool and logs, see datadir.rs
+    pub data_dir: Option<DataDir>,
+    // The log level, mining threads and target peers can be changed while the node runs, like the
+    // mempool policy, min_confirmations and stale_tip_factor, see reload.rs
+    pub log_level: LevelFilter,
+    // Threads mining on the CPU, 0 for one per core
+    pub mining_threads: usize,
+    // The p2p layer dials peers until we are connected to this many
+    pub target_peers: usize,
+    // Queries, validations and event handling taking longer are reported, see watchdog.rs
+    pub watchdog: Thresholds,
+}
+
+impl Config {
+    // Expects the arguments without the program name
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Self, BlockchainError> {
+        // The options in the data directory's config file come first, so the command line overrides them
+        let args = args.collect::<Vec<String>>();
+        let data_dir = match args.iter().position(|arg| arg == "--data-dir") {
+            Some(i) => Some(DataDir::new(&PathBuf::from(
+                args.get(i + 1).ok_or_else(|| BlockchainError::Error("--data-dir requires a directory".to_owned()))?,
+            ))),
+            None => None,
+        };
+        let config_args = match &data_dir {
+            Some(data_dir) => data_dir.config_args()?,
+            None => vec![],
+        };
+        let mut args = config_args.into_iter().chain(args);
+        let mut config = Config {
+            storage: StorageKind::Postgres,
+            db_name: None,
+            db_host: database::DEFAULT_DB_HOST.to_owned(),
+            db_wait: database::DEFAULT_DB_WAIT,
+            p2p: true,
+            snapshot: None,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            outbound_only: false,
+            listen: vec![],
+            peers: vec![],
+            regtest: false,
+            difficulty_algorithm: None,
+            stratum: None,
+            hash_backend: HashBackend::Cpu,
+            keys: None,
+            wallet: None,
+            slot_time: None,
+            proposers: vec![],
+            events: None,
+            rpc: None,
+            ipc: None,
+            services: vec![],
+            min_confirmations: 0,
+            repair: None,
+            chains: vec![],
+            bridge: None,
+            bridge_interval: bridge::DEFAULT_BRIDGE_INTERVAL,
+            capture: None,
+            capture_max_bytes: capture::DEFAULT_CAPTURE_MAX_BYTES,
+            checkpoint_signers: vec![],
+            wal: None,
+            encrypt_at_rest: false,
+            fast_sync: false,
+            snapshot_trust: SnapshotTrust::default(),
+            propagation: Propagation::Full,
+            network: NetworkParams::default(),
+            mempool: None,
+            mempool_policy: MempoolPolicy::default(),
+            max_orphans: DEFAULT_MAX_ORPHANS,
+            max_orphan_bytes: DEFAULT_MAX_ORPHAN_BYTES,
+            stale_pruning: StalePruning::Blocks,
+            stale_tip_factor: DEFAULT_STALE_TIP_FACTOR,
+            data_dir: None,
+            log_level: LevelFilter::INFO,
+            mining_threads: 0,
+            target_peers: DEFAULT_TARGET_PEERS,
+            watchdog: Thresholds::default(),
+        };
+        let mut archival = false;
+        let mut network = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--storage" => {
+                    config.storage = match args.next().as_deref() {
+                        Some("postgres") => StorageKind::Postgres,
+                        Some("memory") => StorageKind::Memory,
+                        other => return Err(BlockchainError::Error(format!("invalid storage: {:?}", other))),
+                    }
+                }
+                "--no-p2p" => config.p2p = false,
+                "--snapshot" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--snapshot requires a path".to_owned()))?;
+                    config.snapshot = Some(PathBuf::from(path));
+                }
+                "--snapshot-interval" => {
+                    let secs = args
+                        .next()
+                        .and_then(|secs| secs.parse::<u64>().ok())
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| BlockchainError::Error("--snapshot-interval requires a number of seconds".to_owned()))?;
+                    config.snapshot_interval = Duration::from_secs(secs);
+                }
+                "--outbound-only" => config.outbound_only = true,
+                "--listen" => {
+                    let addr = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--listen requires an address".to_owned()))?;
+                    config.listen.push(addr);
+                }
+                "--peer" => {
+                    let addr = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--peer requires an address".to_owned()))?;
+                    config.peers.push(addr);
+                }
+                "--regtest" => config.regtest = true,
+                "--fast-sync" => config.fast_sync = true,
+                "--snapshot-signer" => {
+                    let peer_id = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--snapshot-signer requires a peer ID".to_owned()))?;
+                    config.snapshot_trust.signers.push(peer_id);
+                }
+                "--snapshot-quorum" => {
+                    config.snapshot_trust.quorum = args
+                        .next()
+                        .and_then(|quorum| quorum.parse::<usize>().ok())
+                        .filter(|quorum| *quorum > 0)
+                        .ok_or_else(|| BlockchainError::Error("--snapshot-quorum requires a number of signers".to_owned()))?;
+                }
+                "--encrypt-at-rest" => config.encrypt_at_rest = true,
+                "--daa" => {
+                    let name = args
+                        .next()
+                        .filter(|name| difficulty::ALGORITHMS.contains(&name.as_str()))
+                        .ok_or_else(|| BlockchainError::Error(format!("--daa requires one of {:?}", difficulty::ALGORITHMS)))?;
+                    config.difficulty_algorithm = Some(name);
+                }
+                "--stratum" => {
+                    let addr = args
+                        .next()
+                        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--stratum requires an address like 127.0.0.1:3333".to_owned()))?;
+                    config.stratum = Some(addr);
+                }
+                "--hasher" => {
+                    config.hash_backend = match args.next().as_deref() {
+                        Some("cpu") => HashBackend::Cpu,
+                        Some("gpu") => HashBackend::Gpu,
+                        other => return Err(BlockchainError::Error(format!("invalid hasher: {:?}", other))),
+                    }
+                }
+                "--keys" => {
+                    let dir = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--keys requires a directory".to_owned()))?;
+                    config.keys = Some(PathBuf::from(dir));
+                }
+                "--wallet" => {
+                    let file = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--wallet requires a file".to_owned()))?;
+                    config.wallet = Some(PathBuf::from(file));
+                }
+                "--slot-time" => {
+                    let secs = args
+                        .next()
+                        .and_then(|secs| secs.parse::<u64>().ok())
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| BlockchainError::Error("--slot-time requires a number of seconds".to_owned()))?;
+                    config.slot_time = Some(Duration::from_secs(secs));
+                }
+                "--proposer" => {
+                    let peer_id = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--proposer requires a peer ID".to_owned()))?;
+                    config.proposers.push(peer_id);
+                }
+                "--events" => {
+                    let addr = args
+                        .next()
+                        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--events requires an address like 127.0.0.1:3334".to_owned()))?;
+                    config.events = Some(addr);
+                }
+                "--rpc" => {
+                    let addr = args
+                        .next()
+                        .and_then(|addr| addr.parse::<SocketAddr>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--rpc requires an address like 127.0.0.1:3335".to_owned()))?;
+                    config.rpc = Some(addr);
+                }
+                "--ipc" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--ipc requires a path".to_owned()))?;
+                    config.ipc = Some(PathBuf::from(path));
+                }
+                "--announce-service" => {
+                    let service = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--announce-service requires a service like rpc=https://HOST/rpc".to_owned()))?;
+                    if config.services.len() >= endpoints::MAX_SERVICES {
+                        return Err(BlockchainError::Error(format!("at most {} services can be announced", endpoints::MAX_SERVICES)));
+                    }
+                    config.services.push(service.parse::<Service>()?);
+                }
+                "--repair" => {
+                    let strategy = args
+                        .next()
+                        .and_then(|name| RepairStrategy::from_name(&name))
+                        .ok_or_else(|| BlockchainError::Error(format!("--repair requires one of {:?}", integrity::REPAIR_STRATEGIES)))?;
+                    config.repair = Some(strategy);
+                }
+                "--chain" => {
+                    let spec = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--chain requires a name".to_owned()))
+                        .and_then(|arg| ChainSpec::parse(&arg))?;
+                    if config.chains.iter().any(|chain| chain.name == spec.name) {
+                        return Err(BlockchainError::Error(format!("chain {} given twice", spec.name)));
+                    }
+                    config.chains.push(spec);
+                }
+                "--bridge" => {
+                    let target = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--bridge requires a target".to_owned()))
+                        .and_then(|arg| BridgeTarget::parse(&arg))?;
+                    config.bridge = Some(target);
+                }
+                "--bridge-interval" => {
+                    let secs = args
+                        .next()
+                        .and_then(|secs| secs.parse::<u64>().ok())
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| BlockchainError::Error("--bridge-interval requires a number of seconds".to_owned()))?;
+                    config.bridge_interval = Duration::from_secs(secs);
+                }
+                "--capture" => {
+                    let path = args.next().ok_or_else(|| BlockchainError::Error("--capture requires a path".to_owned()))?;
+                    config.capture = Some(PathBuf::from(path));
+                }
+                "--capture-max-bytes" => {
+                    config.capture_max_bytes = args
+                        .next()
+                        .and_then(|bytes| bytes.parse::<u64>().ok())
+                        .filter(|bytes| *bytes > 0)
+                        .ok_or_else(|| BlockchainError::Error("--capture-max-bytes requires a number of bytes".to_owned()))?;
+                }
+                "--checkpoint-signer" => {
+                    let peer_id = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--checkpoint-signer requires a peer ID".to_owned()))?;
+                    config.checkpoint_signers.push(peer_id);
+                }
+                "--wal" => {
+                    let path = args.next().ok_or_else(|| BlockchainError::Error("--wal requires a path".to_owned()))?;
+                    config.wal = Some(PathBuf::from(path));
+                }
+                "--propagation" => {
+                    config.propagation = args
+                        .next()
+                        .and_then(|name| Propagation::from_name(&name))
+                        .ok_or_else(|| BlockchainError::Error(format!("--propagation requires one of {:?}", propagation::PROPAGATION_POLICIES)))?;
+                }
+                "--network" => {
+                    let path = args.next().ok_or_else(|| BlockchainError::Error("--network requires a path".to_owned()))?;
+                    config.network = NetworkParams::load(&PathBuf::from(path))?;
+                    network = true;
+                }
+                "--mempool" => {
+                    let path = args.next().ok_or_else(|| BlockchainError::Error("--mempool requires a path".to_owned()))?;
+                    config.mempool = Some(PathBuf::from(path));
+                }
+                "--mempool-max-age" => {
+                    let secs = args
+                        .next()
+                        .and_then(|secs| secs.parse::<u64>().ok())
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| BlockchainError::Error("--mempool-max-age requires a number of seconds".to_owned()))?;
+                    config.mempool_policy.max_age = Some(Duration::from_secs(secs));
+                }
+                "--mempool-max-jobs" => {
+                    let jobs = args
+                        .next()
+                        .and_then(|jobs| jobs.parse::<usize>().ok())
+                        .filter(|jobs| *jobs > 0)
+                        .ok_or_else(|| BlockchainError::Error("--mempool-max-jobs requires a number of jobs".to_owned()))?;
+                    config.mempool_policy.max_jobs = Some(jobs);
+                }
+                "--mempool-max-bytes" => {
+                    let bytes = args
+                        .next()
+                        .and_then(|bytes| bytes.parse::<usize>().ok())
+                        .filter(|bytes| *bytes > 0)
+                        .ok_or_else(|| BlockchainError::Error("--mempool-max-bytes requires a number of bytes".to_owned()))?;
+                    config.mempool_policy.max_bytes = Some(bytes);
+                }
+                "--mempool-eviction" => {
+                    config.mempool_policy.eviction = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--mempool-eviction requires lowest-fee or oldest".to_owned()))?
+                        .parse::<Eviction>()?;
+                }
+                "--mempool-min-priority" => {
+                    config.mempool_policy.min_priority = args
+                        .next()
+                        .ok_or_else(|| BlockchainError::Error("--mempool-min-priority requires a priority".to_owned()))?
+                        .parse::<Priority>()?;
+                }
+                "--min-fee-rate" => {
+                    config.mempool_policy.min_fee_rate = args
+                        .next()
+                        .and_then(|fee| fee.parse::<u64>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--min-fee-rate requires a fee per 1000 bytes".to_owned()))?;
+                }
+                "--max-orphans" => {
+                    config.max_orphans = args
+                        .next()
+                        .and_then(|orphans| orphans.parse::<usize>().ok())
+                        .filter(|orphans| *orphans > 0)
+                        .ok_or_else(|| BlockchainError::Error("--max-orphans requires a number of blocks".to_owned()))?;
+                }
+                "--max-orphan-bytes" => {
+                    config.max_orphan_bytes = args
+                        .next()
+                        .and_then(|bytes| bytes.parse::<usize>().ok())
+                        .filter(|bytes| *bytes > 0)
+                        .ok_or_else(|| BlockchainError::Error("--max-orphan-bytes requires a number of bytes".to_owned()))?;
+                }
+                "--archival" => archival = true,
+                "--keep-stale-headers" => config.stale_pruning = StalePruning::Bodies,
+                "--stale-tip-factor" => {
+                    config.stale_tip_factor = args
+                        .next()
+                        .and_then(|factor| factor.parse::<u32>().ok())
+                        .filter(|factor| *factor > 0)
+                        .ok_or_else(|| BlockchainError::Error("--stale-tip-factor requires a number of block times".to_owned()))?;
+                }
+                "--log-level" => {
+                    config.log_level = args
+                        .next()
+                        .and_then(|level| level.parse::<LevelFilter>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--log-level requires one of off, error, warn, info, debug, trace".to_owned()))?;
+                }
+                "--mining-threads" => {
+                    config.mining_threads = args
+                        .next()
+                        .and_then(|threads| threads.parse::<usize>().ok())
+                        .filter(|threads| *threads > 0)
+                        .ok_or_else(|| BlockchainError::Error("--mining-threads requires a number of threads".to_owned()))?;
+                }
+                "--target-peers" => {
+                    config.target_peers = args
+                        .next()
+                        .and_then(|peers| peers.parse::<usize>().ok())
+                        .filter(|peers| *peers > 0)
+                        .ok_or_else(|| BlockchainError::Error("--target-peers requires a number of peers".to_owned()))?;
+                }
+                "--db-host" => {
+                    config.db_host = args.next().ok_or_else(|| BlockchainError::Error("--db-host requires a host".to_owned()))?;
+                }
+                "--db-wait" => {
+                    let secs = args
+                        .next()
+                        .and_then(|secs| secs.parse::<u64>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--db-wait requires a number of seconds".to_owned()))?;
+                    config.db_wait = Duration::from_secs(secs);
+                }
+                "--slow-query-ms" => config.watchdog.query = parse_millis("--slow-query-ms", args.next().as_deref())?,
+                "--slow-validation-ms" => config.watchdog.validation = parse_millis("--slow-validation-ms", args.next().as_deref())?,
+                "--slow-gossip-ms" => config.watchdog.gossip = parse_millis("--slow-gossip-ms", args.next().as_deref())?,
+                // Already read, see above
+                "--data-dir" => {
+                    args.next();
+                    config.data_dir = data_dir.clone();
+                }
+                MIN_CONFIRMATIONS_OPTION => config.min_confirmations = parse_min_confirmations(args.next().as_deref())?,
+                _ if arg.starts_with("--") => {
+                    return Err(BlockchainError::Error(format!("unknown option: {}", arg)))
+                }
+                _ => config.db_name = Some(arg),
+            }
+        }
+
+        // The files of the data directory, unless they were given explicitly
+        if let Some(data_dir) = &config.data_dir {
+            config.keys.get_or_insert_with(|| data_dir.keys());
+            config.mempool.get_or_insert_with(|| data_dir.mempool());
+            if config.storage == StorageKind::Memory {
+                config.snapshot.get_or_insert_with(|| data_dir.snapshot());
+            }
+            if !network && data_dir.network().exists() {
+                config.network = NetworkParams::load(&data_dir.network())?;
+            }
+        }
+
+        if config.outbound_only && !config.listen.is_empty() {
+            return Err(BlockchainError::Error("outbound-only nodes do not listen, --listen is no option".to_owned()));
+        }
+
+        if config.slot_time.is_none() && !config.proposers.is_empty() {
+            return Err(BlockchainError::Error("--proposer requires --slot-time".to_owned()));
+        }
+
+        if let Some(BridgeTarget::Chain(name)) = &config.bridge {
+            if !config.chains.iter().any(|chain| &chain.name == name) {
+                return Err(BlockchainError::Error(format!("--bridge chain {} requires --chain {}", name, name)));
+            }
+        }
+
+        if archival {
+            if config.stale_pruning == StalePruning::Bodies {
+                return Err(BlockchainError::Error("archival nodes keep all stale blocks, --keep-stale-headers is no option".to_owned()));
+            }
+            config.stale_pruning = StalePruning::Off;
+        }
+
+        if config.snapshot_trust != SnapshotTrust::default() && !config.fast_sync {
+            return Err(BlockchainError::Error("--snapshot-signer and --snapshot-quorum require --fast-sync".to_owned()));
+        }
+        let trust = &config.snapshot_trust;
+        if !trust.signers.is_empty() && trust.quorum > trust.signers.len() {
+            return Err(BlockchainError::Error(format!(
+                "--snapshot-quorum {} requires as many --snapshot-signer, got {}",
+                trust.quorum,
+                trust.signers.len()
+            )));
+        }
+
+        if config.storage == StorageKind::Memory && config.wal.is_some() {
+            return Err(BlockchainError::Error("--wal requires Postgres storage".to_owned()));
+        }
+
+        if config.storage == StorageKind::Postgres && config.db_name.is_none() {
+            return Err(BlockchainError::Error(
+                "DB name not set. call 'cargo run {DB_NAME}' or 'cargo run -- --storage memory'".to_owned(),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_millis(option: &str, arg: Option<&str>) -> Result<Duration, BlockchainError> {
+    arg.and_then(|millis| millis.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .ok_or_else(|| BlockchainError::Error(format!("{} requires a number of milliseconds", option)))
+}
+
+fn parse_min_confirmations(arg: Option<&str>) -> Result<u64, BlockchainError> {
+    arg.and_then(|n| n.parse::<u64>().ok())
+        .ok_or_else(|| BlockchainError::Error(format!("{} requires a number of blocks", MIN_CONFIRMATIONS_OPTION)))
+}
+
+// Takes "--min-confirmations N" out of the arguments of a command. Returns the other arguments and
+// N, or the default if the option is not given.
+pub fn split_min_confirmations(args: &str, default: u64) -> Result<(String, u64), BlockchainError> {
+    let mut rest = vec![];
+    let mut min_confirmations = default;
+    let mut args = args.split_whitespace();
+    while let Some(arg) = args.next() {
+        if arg == MIN_CONFIRMATIONS_OPTION {
+            min_confirmations = parse_min_confirmations(args.next())?;
+        } else {
+            rest.push(arg);
+        }
+    }
+    Ok((rest.join(" "), min_confirmations))
+}
@@ -0,0 +1,154 @@
+// New consensus rules are activated at a configured height instead of all at once, so that
+// nodes can be upgraded ahead of time and the network switches over without a chain split.
+// Blocks are always validated with the rule set that is active at their own height.
+use crate::types::Height;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    // Blocks have to use at least version 1 of the header (version, miner and extra nonce fields)
+    VersionedHeader,
+    // Hashes are encoded as zero-padded hex, so every hash is 64 characters long
+    PaddedHexHash,
+    // Blocks have to use at least version 2 of the header (millisecond timestamps that never
+    // go backwards)
+    MillisecondTimestamps,
+    // Blocks have to use version 3 of the header and meet the difficulty of the network's
+    // difficulty algorithm (see difficulty.rs)
+    DifficultyAdjustment,
+    // Blocks announcing a signing key rotation have to carry valid signatures (see keys.rs)
+    KeyRotations,
+    // Blocks have to use version 4 of the header (the fees collected by the miner, see fees.rs)
+    FeeMarket,
+    // A signing key can only be rotated once, later rotations of it conflict with the first one
+    // (see keys.rs)
+    ExclusiveKeyRotations,
+    // Blocks carrying a transaction have to carry one signed by its sender, or by enough keys of
+    // its multisig policy (see transactions.rs and multisig.rs)
+    SignedTransactions,
+    // Blocks have to use version 5 of the header and commit to the root of the state after them
+    // (see state.rs)
+    StateRoots,
+    // Transactions listing inputs have to spend unspent outputs of their sender that cover amount
+    // and fee (see utxo.rs)
+    UtxoSpends,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashEncoding {
+    // Every byte is encoded without leading zeros (e.g. 0x0A => "A")
+    Legacy,
+    PaddedHex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activation {
+    pub feature: Feature,
+    pub height: Height,
+}
+
+// The upgrade schedule, ordered by height
+pub const ACTIVATIONS: [Activation; 11] = [
+    Activation {
+        feature: Feature::VersionedHeader,
+        height: Height(1_000),
+    },
+    Activation {
+        feature: Feature::PaddedHexHash,
+        height: Height(2_000),
+    },
+    Activation {
+        feature: Feature::MillisecondTimestamps,
+        height: Height(3_000),
+    },
+    Activation {
+        feature: Feature::DifficultyAdjustment,
+        height: Height(4_000),
+    },
+    Activation {
+        feature: Feature::KeyRotations,
+        height: Height(5_000),
+    },
+    Activation {
+        feature: Feature::FeeMarket,
+        height: Height(6_000),
+    },
+    Activation {
+        feature: Feature::ExclusiveKeyRotations,
+        height: Height(7_000),
+    },
+    Activation {
+        feature: Feature::SignedTransactions,
+        height: Height(8_000),
+    },
+    Activation {
+        feature: Feature::StateRoots,
+        height: Height(9_000),
+    },
+    Activation {
+        feature: Feature::UtxoSpends,
+        height: Height(10_000),
+    },
+    Activation {
+        feature: Feature::Coinbase,
+        height: Height(11_000),
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSet {
+    pub min_block_version: u8,
+    pub hash_encoding: HashEncoding,
+    pub difficulty_adjustment: bool,
+    pub key_rotations: bool,
+    pub exclusive_key_rotations: bool,
+    pub signed_transactions: bool,
+    pub state_roots: bool,
+    pub utxo_spends: bool,
+    pub coinbase: bool,
+}
+
+pub fn is_active(feature: Feature, height: Height) -> bool {
+    ACTIVATIONS
+        .iter()
+        .any(|activation| activation.feature == feature && height >= activation.height)
+}
+
+pub fn activation_height(feature: Feature) -> Height {
+    ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == feature)
+        .map(|activation| activation.height)
+        .expect("every feature is scheduled")
+}
+
+pub fn rules_at(height: Height) -> RuleSet {
+    RuleSet {
+        min_block_version: if is_active(Feature::Coinbase, height) {
+            6
+        } else if is_active(Feature::StateRoots, height) {
+            5
+        } else if is_active(Feature::FeeMarket, height) {
+            4
+        } else if is_active(Feature::DifficultyAdjustment, height) {
+            3
+        } else if is_active(Feature::MillisecondTimestamps, height) {
+            2
+        } else if is_active(Feature::VersionedHeader, height) {
+            1
+        } else {
+            0
+        },
+        hash_encoding: if is_active(Feature::PaddedHexHash, height) {
+            HashEncoding::PaddedHex
+        } else {
+            HashEncoding::Legacy
+        },
+        difficulty_adjustment: is_active(Feature::DifficultyAdjustment, height),
+        key_rotations: is_active(Feature::KeyRotations, height),
+        exclusive_key_rotations: is_active(Feature::ExclusiveKeyRotations, height),
+        signed_transactions: is_active(Feature::SignedTransactions, height),
+        state_roots: is_active(Feature::StateRoots, height),
+        utxo_spends: is_active(Feature::UtxoSpends, height),
+        coinbase: is_active(Feature::Coinbase, height),
+    }
+}
@@ -0,0 +1,97 @@
+// Connecting to Postgres on start-up. The server may not accept connections yet, e.g. when it is
+// started together with the node by `docker-compose up`, so connecting is retried with exponential
+// backoff for up to --db-wait seconds. A database that does not exist is created, which requires
+// the CREATEDB privilege (the user of docker-compose.yml has it), so every node can simply be started
+// with a database name of its own.
+use crate::blockchain::BlockchainError;
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{Client, NoTls};
+
+pub const DEFAULT_DB_HOST: &str = "localhost";
+pub const DEFAULT_DB_WAIT: Duration = Duration::from_secs(60);
+pub const MIN_BACKOFF: Duration = Duration::from_millis(250);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(5);
+// Exists in every cluster, other databases are created from it
+pub const MAINTENANCE_DB: &str = "postgres";
+pub const DB_USER: &str = "user";
+const DB_PASSWORD: &str = "pw";
+
+pub fn connection_string(host: &str, db_name: &str) -> String {
+    format!("host={} dbname={} user={} password={}", host, db_name, DB_USER, DB_PASSWORD)
+}
+
+// Delay before the next attempt, doubling from MIN_BACKOFF up to MAX_BACKOFF
+pub fn backoff(attempt: u32) -> Duration {
+    MIN_BACKOFF.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF)
+}
+
+// The name is quoted, so it is created with exactly the name we connect to
+pub fn create_database_statement(db_name: &str) -> String {
+    format!("CREATE DATABASE \"{}\"", db_name.replace('"', "\"\""))
+}
+
+// Errors of a server that is not (yet) reachable or still starting up. Others, like a wrong
+// password, do not go away by waiting.
+fn unavailable(err: &tokio_postgres::Error) -> bool {
+    match err.code() {
+        None => true,
+        Some(code) => *code == SqlState::CANNOT_CONNECT_NOW,
+    }
+}
+
+// Connects to the database, waiting up to `wait` for the server and creating the database if it
+// does not exist. The connection runs on a task of its own, which logs its errors.
+pub async fn connect(host: &str, db_name: &str, wait: Duration) -> Result<(Client, JoinHandle<()>), BlockchainError> {
+    let deadline = Instant::now() + wait;
+    let mut attempt = 0;
+    let mut created = false;
+    loop {
+        match tokio_postgres::connect(&connection_string(host, db_name), NoTls).await {
+            Ok((db_client, connection)) => {
+                // The connection object performs the actual communication with the database
+                let db_task = tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        error!("DB connection error: {}", e);
+                    }
+                });
+                return Ok((db_client, db_task));
+            }
+            Err(err) if err.code() == Some(&SqlState::INVALID_CATALOG_NAME) && !created => {
+                create_database(host, db_name).await?;
+                created = true;
+            }
+            Err(err) if unavailable(&err) && Instant::now() + backoff(attempt) < deadline => {
+                warn!("Database at {} not available ({}), retrying in {:?}", host, err, backoff(attempt));
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+async fn create_database(host: &str, db_name: &str) -> Result<(), BlockchainError> {
+    let (db_client, connection) = tokio_postgres::connect(&connection_string(host, MAINTENANCE_DB), NoTls).await?;
+    let db_task = tokio::spawn(connection);
+    let result = db_client.batch_execute(&create_database_statement(db_name)).await;
+    // Closes the connection
+    drop(db_client);
+    let _ = db_task.await;
+    match result {
+        Ok(()) => {
+            info!("Created database {}", db_name);
+            Ok(())
+        }
+        // Created by another node in the meantime
+        Err(err) if err.code() == Some(&SqlState::DUPLICATE_DATABASE) => Ok(()),
+        Err(err) if err.code() == Some(&SqlState::INSUFFICIENT_PRIVILEGE) => Err(BlockchainError::Error(format!(
+            "database {} does not exist and {} may not create it, create it manually: {}",
+            db_name, DB_USER, err
+        ))),
+        Err(err) => Err(err.into()),
+    }
+}
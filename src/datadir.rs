@@ -0,0 +1,92 @@
+// Data directory (`--data-dir DIR`): all state of a node in one directory instead of one option per
+// file. The layout, created on first run:
+// DIR/
+//   node.conf       options read before the command line, one or more per line, # starts a comment
+//   network.json    definition of our network (--network), the default network if missing
+//   keys/           encrypted identity, signing and wallet keys (--keys), see keys.rs
+//   snapshot.json   chain of the in-memory storage (--snapshot), with the peer store (our peers'
+//                   scores); Postgres keeps both in the database
+//   mempool.json    pending mining jobs (--mempool)
+//   logs/node.log   log of the node, besides stdout
+// Options given explicitly (on the command line or in node.conf) take precedence over the layout.
+// `node paths` prints the resolved locations.
+use crate::blockchain::BlockchainError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "node.conf";
+pub const NETWORK_FILE: &str = "network.json";
+pub const KEYS_DIR: &str = "keys";
+pub const SNAPSHOT_FILE: &str = "snapshot.json";
+pub const MEMPOOL_FILE: &str = "mempool.json";
+pub const LOGS_DIR: &str = "logs";
+pub const LOG_FILE: &str = "node.log";
+
+// Written to node.conf on first run
+const CONFIG_TEMPLATE: &str = "# Options of the node, read before the command line, which overrides them
+# (repeatable options like --listen or --peer add up). For example:
+# --storage memory
+# --listen /ip4/0.0.0.0/tcp/4001
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    pub root: PathBuf,
+}
+
+impl DataDir {
+    pub fn new(root: &Path) -> Self {
+        Self { root: root.to_owned() }
+    }
+
+    pub fn config(&self) -> PathBuf {
+        self.root.join(CONFIG_FILE)
+    }
+
+    pub fn network(&self) -> PathBuf {
+        self.root.join(NETWORK_FILE)
+    }
+
+    pub fn keys(&self) -> PathBuf {
+        self.root.join(KEYS_DIR)
+    }
+
+    pub fn snapshot(&self) -> PathBuf {
+        self.root.join(SNAPSHOT_FILE)
+    }
+
+    pub fn mempool(&self) -> PathBuf {
+        self.root.join(MEMPOOL_FILE)
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.root.join(LOGS_DIR).join(LOG_FILE)
+    }
+
+    // Creates the directories and the config file that are missing. True if the data directory is new.
+    pub fn init(&self) -> Result<bool, BlockchainError> {
+        let created = !self.root.exists();
+        fs::create_dir_all(self.keys())?;
+        fs::create_dir_all(self.root.join(LOGS_DIR))?;
+        if !self.config().exists() {
+            fs::write(self.config(), CONFIG_TEMPLATE)?;
+        }
+        Ok(created)
+    }
+
+    // The options in node.conf, none if there is no such file
+    pub fn config_args(&self) -> Result<Vec<String>, BlockchainError> {
+        let path = self.config();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let args = fs::read_to_string(&path)?
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap_or_default().split_whitespace().map(str::to_owned).collect::<Vec<_>>())
+            .collect::<Vec<String>>();
+        if args.iter().any(|arg| arg == "--data-dir") {
+            return Err(BlockchainError::Error(format!("{} can not set --data-dir", path.display())));
+        }
+        Ok(args)
+    }
+}
@@ -0,0 +1,94 @@
+// Dead letters (`p2p badmsgs`): gossip and direct messages that do not decode as any of our message
+// types are recorded instead of being dropped silently. The latest ones are kept in a bounded ring
+// in the p2p task, every one of them lowers the sender's application score by BAD_MESSAGE_PENALTY,
+// so peers sending garbage end up banned, see p2p.rs. They are also persisted, see
+// Storage::insert_bad_message.
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+// Number of bad messages kept in memory
+pub const DEAD_LETTER_CAPACITY: usize = 100;
+// Number of leading bytes of a bad message that are kept
+pub const BAD_MESSAGE_PREFIX: usize = 32;
+// Application score added per bad message
+pub const BAD_MESSAGE_PENALTY: f64 = -10.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BadMessage {
+    pub peer: String,
+    // Size of the whole message in bytes
+    pub size: u64,
+    // Hex encoded first BAD_MESSAGE_PREFIX bytes
+    pub prefix: String,
+    pub error: String,
+    // Milliseconds since the epoch
+    pub received_at: i64,
+}
+
+impl BadMessage {
+    pub fn new(peer: String, data: &[u8], error: String, received_at: i64) -> Self {
+        Self {
+            peer,
+            size: data.len() as u64,
+            prefix: hex::encode(&data[..data.len().min(BAD_MESSAGE_PREFIX)]),
+            error,
+            received_at,
+        }
+    }
+}
+
+// Why the data is no message: either it is no JSON at all or of no known message type
+pub fn decode_error(data: &[u8]) -> String {
+    match serde_json::from_slice::<serde_json::Value>(data) {
+        Ok(_) => "unknown message type".to_owned(),
+        Err(err) => format!("invalid JSON: {}", err),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetters {
+    capacity: usize,
+    // Oldest first
+    messages: VecDeque<BadMessage>,
+    // Bad messages per peer since start-up, including the ones that were dropped from the ring
+    counts: HashMap<String, u64>,
+}
+
+impl Default for DeadLetters {
+    fn default() -> Self {
+        Self::new(DEAD_LETTER_CAPACITY)
+    }
+}
+
+impl DeadLetters {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, messages: VecDeque::new(), counts: HashMap::new() }
+    }
+
+    // Records the message, dropping the oldest one if the ring is full
+    pub fn push(&mut self, message: BadMessage) {
+        *self.counts.entry(message.peer.clone()).or_default() += 1;
+        if self.messages.len() >= self.capacity {
+            self.messages.pop_front();
+        }
+        if self.capacity > 0 {
+            self.messages.push_back(message);
+        }
+    }
+
+    // Newest first
+    pub fn recent(&self) -> impl Iterator<Item = &BadMessage> {
+        self.messages.iter().rev()
+    }
+
+    pub fn count(&self, peer: &str) -> u64 {
+        self.counts.get(peer).copied().unwrap_or_default()
+    }
+
+    // Peers and their number of bad messages, worst first
+    pub fn counts(&self) -> Vec<(&str, u64)> {
+        let mut counts = self.counts.iter().map(|(peer, count)| (peer.as_str(), *count)).collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts
+    }
+}
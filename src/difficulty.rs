@@ -0,0 +1,205 @@
+// Difficulty adjustment (retargeting). The difficulty of a block is the number of hashes it takes on
+// average to find it: the first 8 bytes of the block's SHA-256 digest, read as a big-endian number,
+// must not be above u64::MAX / difficulty. Once the DifficultyAdjustment upgrade is active (see
+// consensus.rs), every block has to carry the difficulty the network's DifficultyAlgorithm computes
+// from its ancestors, so the block time stays close to the network's target block time when hashrate changes.
+// All algorithms only use integer arithmetic, so every node comes to the same result.
+use crate::consensus::{self, Feature};
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+pub const TARGET_BLOCK_TIME_MS: i64 = 10_000;
+// Used for the blocks before the upgrade, about the work of the "00" hash prefix with legacy hash encoding
+pub const INITIAL_DIFFICULTY: u64 = 65_536;
+pub const MIN_DIFFICULTY: u64 = 1;
+// Difficulties are stored as INT8
+pub const MAX_DIFFICULTY: u64 = i64::MAX as u64;
+pub const DEFAULT_ALGORITHM: &str = "lwma";
+pub const ALGORITHMS: [&str; 4] = ["fixed", "epoch", "lwma", "asert"];
+
+// The parts of a block the algorithms look at
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub height: Height,
+    // Milliseconds since the epoch
+    pub timestamp: i64,
+    pub difficulty: u64,
+}
+
+pub trait DifficultyAlgorithm: fmt::Debug + Send + Sync {
+    // Number of most recent blocks next_difficulty needs
+    fn window(&self) -> usize;
+    // Height of a fixed block next_difficulty needs besides the window. It is passed as the first
+    // block whenever it is not part of the window already.
+    fn anchor(&self) -> Option<Height> {
+        None
+    }
+    // Difficulty of the block following the given blocks (oldest first, at most window() of them,
+    // fewer close to the genesis block)
+    fn next_difficulty(&self, blocks: &[BlockInfo]) -> u64;
+}
+
+// Returns the algorithm with its default parameters
+pub fn algorithm_by_name(name: &str) -> Option<Arc<dyn DifficultyAlgorithm>> {
+    algorithm_with_block_time(name, TARGET_BLOCK_TIME_MS)
+}
+
+// Returns the algorithm aiming for the block time of a network, see network.rs
+pub fn algorithm_with_block_time(name: &str, target_block_time: i64) -> Option<Arc<dyn DifficultyAlgorithm>> {
+    let algorithm: Arc<dyn DifficultyAlgorithm> = match name {
+        "fixed" => Arc::new(Fixed { difficulty: INITIAL_DIFFICULTY }),
+        "epoch" => Arc::new(Epoch { interval: 20, target_block_time }),
+        "lwma" => Arc::new(Lwma { window: 30, target_block_time }),
+        "asert" => Arc::new(Asert {
+            // The last block mined for the fixed hash prefix
+            anchor: consensus::activation_height(Feature::DifficultyAdjustment) - 1,
+            half_life: 12 * target_block_time,
+            target_block_time,
+        }),
+        _ => return None,
+    };
+    Some(algorithm)
+}
+
+pub fn meets_difficulty(digest: &[u8], difficulty: u64) -> bool {
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(prefix) <= u64::MAX / difficulty.max(MIN_DIFFICULTY)
+}
+
+fn clamp_difficulty(difficulty: u128) -> u64 {
+    difficulty.clamp(MIN_DIFFICULTY as u128, MAX_DIFFICULTY as u128) as u64
+}
+
+fn last_difficulty(blocks: &[BlockInfo]) -> u64 {
+    blocks.last().map_or(INITIAL_DIFFICULTY, |block| block.difficulty)
+}
+
+// Never changes, e.g. for tests and regtest networks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixed {
+    pub difficulty: u64,
+}
+
+impl DifficultyAlgorithm for Fixed {
+    fn window(&self) -> usize {
+        0
+    }
+
+    fn next_difficulty(&self, _: &[BlockInfo]) -> u64 {
+        self.difficulty
+    }
+}
+
+// Bitcoin-style: every `interval` blocks the difficulty is scaled by how far the last interval was
+// off the target, by at most a factor of 4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Epoch {
+    pub interval: u64,
+    pub target_block_time: i64,
+}
+
+impl DifficultyAlgorithm for Epoch {
+    fn window(&self) -> usize {
+        self.interval as usize + 1
+    }
+
+    fn next_difficulty(&self, blocks: &[BlockInfo]) -> u64 {
+        let last = match blocks.last() {
+            Some(last) => last,
+            None => return INITIAL_DIFFICULTY,
+        };
+        if (last.height.0 + 1) % self.interval != 0 || blocks.len() < self.window() {
+            return last.difficulty;
+        }
+        let first = &blocks[blocks.len() - self.window()];
+        let expected = self.interval as i64 * self.target_block_time;
+        let actual = (last.timestamp - first.timestamp).clamp(expected / 4, expected * 4);
+        clamp_difficulty(last.difficulty as u128 * expected as u128 / actual.max(1) as u128)
+    }
+}
+
+// Linearly weighted moving average: the average difficulty of the window is scaled by the block
+// times, with recent blocks weighted the most, so it reacts quickly to hashrate changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lwma {
+    pub window: usize,
+    pub target_block_time: i64,
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn window(&self) -> usize {
+        self.window + 1
+    }
+
+    fn next_difficulty(&self, blocks: &[BlockInfo]) -> u64 {
+        if blocks.len() < 2 {
+            return last_difficulty(blocks);
+        }
+        let target = self.target_block_time as u128;
+        let mut weighted_times = 0u128;
+        let mut difficulties = 0u128;
+        for (index, pair) in blocks.windows(2).enumerate() {
+            // Out of order timestamps and outliers must not distort the result too much
+            let block_time = (pair[1].timestamp - pair[0].timestamp).clamp(1, 6 * self.target_block_time);
+            weighted_times += (index as u128 + 1) * block_time as u128;
+            difficulties += pair[1].difficulty as u128;
+        }
+        let n = blocks.len() as u128 - 1;
+        let k = n * (n + 1) / 2;
+        // Rises by at most a factor of 10 per block
+        let weighted_times = weighted_times.max(k * target / 10);
+        clamp_difficulty(difficulties * target * k / (n * weighted_times))
+    }
+}
+
+// Absolutely scheduled exponentially rising targets (ASERT): the difficulty of the anchor block
+// halves (doubles) for every half_life the last block is behind (ahead of) the schedule of one block
+// every target_block_time since the anchor. Only the anchor and the last block are looked at, so
+// rounding errors do not add up over the blocks. 2^x is approximated like in aserti3-2d.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Asert {
+    pub anchor: Height,
+    pub half_life: i64,
+    pub target_block_time: i64,
+}
+
+impl DifficultyAlgorithm for Asert {
+    fn window(&self) -> usize {
+        1
+    }
+
+    fn anchor(&self) -> Option<Height> {
+        Some(self.anchor)
+    }
+
+    fn next_difficulty(&self, blocks: &[BlockInfo]) -> u64 {
+        let (anchor, last) = match (blocks.first(), blocks.last()) {
+            (Some(anchor), Some(last)) if anchor.height == self.anchor => (anchor, last),
+            _ => return last_difficulty(blocks),
+        };
+        let scheduled = (last.height.0 - anchor.height.0) as i128 * self.target_block_time as i128;
+        let elapsed = last.timestamp as i128 - anchor.timestamp as i128;
+        // Exponent as 16.16 fixed point number
+        let exponent = (scheduled - elapsed) * 65_536 / self.half_life as i128;
+        let shifts = exponent >> 16;
+        let fraction = (exponent & 0xffff) as u128;
+        let factor = 65_536
+            + ((195_766_423_245_049 * fraction + 971_821_376 * fraction.pow(2) + 5_127 * fraction.pow(3) + (1 << 47)) >> 48);
+        let scaled = anchor.difficulty as u128 * factor;
+        let difficulty = if shifts >= 16 {
+            // Saturate instead of losing the high bits
+            let left = (shifts - 16).min(128) as u32;
+            if left >= scaled.leading_zeros() {
+                u128::MAX
+            } else {
+                scaled << left
+            }
+        } else {
+            scaled >> (16 - shifts).min(127) as u32
+        };
+        clamp_difficulty(difficulty)
+    }
+}
@@ -0,0 +1,170 @@
+// Disk usage report (`chain du [--from-height H] [--to-height H] [--top N]`) to plan pruning (see
+// gc.rs) and spot applications stuffing oversized data into blocks: the storage the node uses and,
+// for the main chain blocks in the height range, the total, average and maximum payload size and the
+// blocks with the largest payloads. Postgres computes the sizes with SQL aggregates over the blocks and
+// payloads tables (see repository.rs), the in-memory storage reports the size of its snapshot.
+// Payloads are stored once however many blocks carry them (see payload.rs), but count for every
+// block here, so the payload total can exceed the storage used.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use crate::types::Height;
+use std::fmt;
+
+// Largest blocks listed if --top is not given
+pub const DEFAULT_TOP: usize = 10;
+// Characters of the hash shown per block
+const SHORT_HASH_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageConfig {
+    pub from: Height,
+    // Our latest block if None
+    pub to: Option<Height>,
+    pub top: usize,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self { from: Height::GENESIS, to: None, top: DEFAULT_TOP }
+    }
+}
+
+impl UsageConfig {
+    // Parses "[--from-height H] [--to-height H] [--top N]"
+    pub fn from_args(args: &str) -> Result<Self, BlockchainError> {
+        let mut config = Self::default();
+        let mut args = args.split_whitespace();
+        while let Some(arg) = args.next() {
+            let mut number = |option: &str| {
+                args.next()
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .ok_or_else(|| BlockchainError::Error(format!("{} requires a number", option)))
+            };
+            match arg {
+                "--from-height" => config.from = Height(number(arg)?),
+                "--to-height" => config.to = Some(Height(number(arg)?)),
+                "--top" => config.top = number(arg)? as usize,
+                _ => return Err(BlockchainError::Error(format!("unknown option: {}", arg))),
+            }
+        }
+        if config.to.is_some_and(|to| to < config.from) {
+            return Err(BlockchainError::Error("--to-height is below --from-height".to_owned()));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableUsage {
+    pub name: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSize {
+    pub id: Height,
+    pub hash: String,
+    pub miner: String,
+    pub bytes: u64,
+}
+
+// Payload sizes of the main chain blocks in a height range
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayloadUsage {
+    pub blocks: u64,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+    // Largest first, blocks of the same size lowest first
+    pub largest: Vec<BlockSize>,
+}
+
+impl PayloadUsage {
+    // What the SQL aggregates compute, for the in-memory storage
+    pub fn from_blocks<'a>(blocks: impl IntoIterator<Item = &'a Block>, top: usize) -> Self {
+        let mut usage = Self::default();
+        for block in blocks {
+            let bytes = block.data.len() as u64;
+            usage.blocks += 1;
+            usage.total_bytes += bytes;
+            usage.max_bytes = usage.max_bytes.max(bytes);
+            usage.largest.push(BlockSize { id: block.id, hash: block.hash.clone(), miner: block.miner.clone(), bytes });
+        }
+        usage.largest.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.id.cmp(&b.id)));
+        usage.largest.truncate(top);
+        usage
+    }
+
+    pub fn avg_bytes(&self) -> f64 {
+        match self.blocks {
+            0 => 0.0,
+            blocks => self.total_bytes as f64 / blocks as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskUsage {
+    pub from: Height,
+    pub to: Height,
+    // Largest first
+    pub tables: Vec<TableUsage>,
+    pub payloads: PayloadUsage,
+}
+
+impl DiskUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.tables.iter().map(|table| table.bytes).sum()
+    }
+}
+
+impl fmt::Display for DiskUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tables = self.tables.iter().map(|table| format!("{} {}", table.name, format_bytes(table.bytes))).collect::<Vec<_>>();
+        writeln!(f, "storage: {} ({})", format_bytes(self.total_bytes()), tables.join(", "))?;
+        let payloads = &self.payloads;
+        write!(
+            f,
+            "heights {} to {}: {} blocks | payloads: {} | avg: {} | max: {}",
+            self.from,
+            self.to,
+            payloads.blocks,
+            format_bytes(payloads.total_bytes),
+            format_bytes(payloads.avg_bytes().round() as u64),
+            format_bytes(payloads.max_bytes)
+        )?;
+        if !payloads.largest.is_empty() {
+            write!(f, "\nlargest blocks:")?;
+        }
+        for block in payloads.largest.iter() {
+            let hash = block.hash.get(..SHORT_HASH_LENGTH).unwrap_or(&block.hash);
+            write!(f, "\n  height: {} | hash: {} | miner: {} | payload: {}", block.id, hash, block.miner, format_bytes(block.bytes))?;
+        }
+        Ok(())
+    }
+}
+
+// Binary units with one decimal, plain bytes below a KiB
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// The report for the height range, capped at our latest block
+pub async fn report(chain: &Chain, storage: &mut Storage, config: &UsageConfig) -> Result<DiskUsage, BlockchainError> {
+    let to = config.to.map_or(chain.latest_block.id, |to| to.min(chain.latest_block.id));
+    let payloads = match config.from <= to {
+        true => storage.get_payload_usage(config.from, to, config.top).await?,
+        false => PayloadUsage::default(),
+    };
+    let tables = storage.get_table_usage().await?;
+    Ok(DiskUsage { from: config.from, to, tables, payloads })
+}
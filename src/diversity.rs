@@ -0,0 +1,137 @@
+// Eclipse attack mitigations: an attacker controlling all the peers we dial controls what we see of
+// the network. So our outbound connections have to be diverse: at least MIN_DIVERSITY of them go to
+// peers we learned about through different mechanisms (bootstrap addresses, mDNS, peer exchange,
+// stored peer scores) and at least MIN_DIVERSITY to peers in distinct address ranges (the /16 of
+// IPv4 and the /32 of IPv6 addresses). While they are not, the peers that add a missing mechanism
+// or range are dialed first, even if we have enough connections already (see p2p.rs). Every
+// ROTATION_INTERVAL one outbound peer, the longest connected one of the most common range, is
+// disconnected to make room for another one.
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+// Minimum number of discovery mechanisms and of address ranges among our outbound connections
+pub const MIN_DIVERSITY: usize = 2;
+pub const ROTATION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// How we learned about a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Discovery {
+    // Given with --peer
+    Bootstrap,
+    Mdns,
+    // Gossipsub peer exchange or the addresses our peers sent us
+    PeerExchange,
+    // Stored peer scores of a previous run
+    Restored,
+    // It connected to us
+    Inbound,
+}
+
+// The address range of the address, None for addresses without an IP or DNS name
+pub fn address_group(addr: &Multiaddr) -> Option<String> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => {
+            let octets = ip.octets();
+            Some(format!("{}.{}.0.0/16", octets[0], octets[1]))
+        }
+        Protocol::Ip6(ip) => {
+            let segments = ip.segments();
+            Some(format!("{:x}:{:x}::/32", segments[0], segments[1]))
+        }
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) | Protocol::Dnsaddr(name) => Some(name.to_string()),
+        _ => None,
+    })
+}
+
+#[derive(Debug, Clone)]
+struct OutboundPeer {
+    discovery: Discovery,
+    group: Option<String>,
+    connected_at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct Diversity {
+    // How we learned about each peer, the first mechanism counts
+    discovered: HashMap<PeerId, Discovery>,
+    outbound: HashMap<PeerId, OutboundPeer>,
+    // Not redialed until the next rotation
+    rotated: Option<PeerId>,
+}
+
+impl Diversity {
+    pub fn discovered(&mut self, peer_id: PeerId, discovery: Discovery) {
+        self.discovered.entry(peer_id).or_insert(discovery);
+    }
+
+    pub fn discovery(&self, peer_id: &PeerId) -> Option<Discovery> {
+        self.discovered.get(peer_id).copied()
+    }
+
+    // We dialed the peer at the address and are connected now
+    pub fn connected(&mut self, peer_id: PeerId, addr: &Multiaddr, now: Instant) {
+        let discovery = self.discovery(&peer_id).unwrap_or(Discovery::Bootstrap);
+        self.outbound.insert(peer_id, OutboundPeer { discovery, group: address_group(addr), connected_at: now });
+    }
+
+    pub fn disconnected(&mut self, peer_id: &PeerId) {
+        self.outbound.remove(peer_id);
+    }
+
+    pub fn is_outbound(&self, peer_id: &PeerId) -> bool {
+        self.outbound.contains_key(peer_id)
+    }
+
+    pub fn mechanisms(&self) -> usize {
+        self.outbound.values().map(|peer| peer.discovery).collect::<HashSet<_>>().len()
+    }
+
+    pub fn groups(&self) -> usize {
+        self.outbound.values().filter_map(|peer| peer.group.as_ref()).collect::<HashSet<_>>().len()
+    }
+
+    pub fn is_diverse(&self) -> bool {
+        self.mechanisms() >= MIN_DIVERSITY && self.groups() >= MIN_DIVERSITY
+    }
+
+    // Whether dialing the peer at the address adds a mechanism or range our outbound connections lack
+    pub fn adds_diversity(&self, peer_id: &PeerId, addr: &Multiaddr) -> bool {
+        let new_mechanism = self.mechanisms() < MIN_DIVERSITY
+            && self
+                .discovery(peer_id)
+                .is_some_and(|discovery| self.outbound.values().all(|peer| peer.discovery != discovery));
+        let new_group = self.groups() < MIN_DIVERSITY
+            && address_group(addr).is_some_and(|group| self.outbound.values().all(|peer| peer.group.as_ref() != Some(&group)));
+        new_mechanism || new_group
+    }
+
+    // Picks the outbound peer to disconnect for rotation: the longest connected one of the most
+    // common address range. None if dropping a peer would leave too few outbound connections.
+    pub fn rotate(&mut self) -> Option<PeerId> {
+        if self.outbound.len() <= MIN_DIVERSITY {
+            return None;
+        }
+        let mut counts: HashMap<Option<&String>, usize> = HashMap::new();
+        for peer in self.outbound.values() {
+            *counts.entry(peer.group.as_ref()).or_default() += 1;
+        }
+        let peer_id = self
+            .outbound
+            .iter()
+            .max_by(|(a_id, a), (b_id, b)| {
+                counts[&a.group.as_ref()]
+                    .cmp(&counts[&b.group.as_ref()])
+                    .then(b.connected_at.cmp(&a.connected_at))
+                    .then(b_id.cmp(a_id))
+            })
+            .map(|(peer_id, _)| *peer_id)?;
+        self.rotated = Some(peer_id);
+        Some(peer_id)
+    }
+
+    pub fn was_rotated(&self, peer_id: &PeerId) -> bool {
+        self.rotated.as_ref() == Some(peer_id)
+    }
+}
@@ -0,0 +1,200 @@
+// Dry runs of block and chain validation (`block check`, `chain check FILE`): a candidate block or
+// chain is validated against our current chain under the same rules as blocks received from peers,
+// nothing is stored or broadcast. Meant for debugging why peers reject the blocks of a modified
+// miner. Instead of the bare BlockInvalid of add_block the first rule a block breaks is reported.
+// Candidates are given as JSON like blocks are gossiped, a block also as the hex encoding of that
+// JSON (like the data of captured messages, see capture.rs). Chains are checked on a copy of our chain in memory.
+use crate::blockchain::{Block, BlockchainError, Chain, Checkpoint};
+use crate::node::Node;
+use crate::storage::{MemoryStorage, Storage};
+use crate::types::Height;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum BlockVerdict {
+    // Valid and on top of our latest block, it would become our new latest block
+    Extends,
+    // Valid, but on top of another block of ours, it would be stored as a stale block
+    Stale,
+    // Stored already
+    Known,
+    // The parent is not stored, the block could only be checked once its chain is synced
+    UnknownParent,
+    Invalid(String),
+}
+
+impl fmt::Display for BlockVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockVerdict::Extends => write!(f, "valid, extends our latest block"),
+            BlockVerdict::Stale => write!(f, "valid, would be stored as a stale block"),
+            BlockVerdict::Known => write!(f, "stored already"),
+            BlockVerdict::UnknownParent => write!(f, "parent unknown"),
+            BlockVerdict::Invalid(reason) => write!(f, "invalid: {}", reason),
+        }
+    }
+}
+
+// The block of a candidate chain that is rejected first and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rejection {
+    pub hash: String,
+    pub height: Height,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ChainVerdict {
+    // The blocks follow our latest block and would be appended
+    Extends,
+    // A valid fork, our blocks from the fork height up would become stale once it is synced
+    Replaces { fork: Height, replaced: u64 },
+    // Valid, but not longer than our chain, so it is not synced
+    NotLonger,
+    // Valid, but it would replace our finalized block at the height
+    BelowFinalized(Height),
+    Invalid(Rejection),
+}
+
+impl fmt::Display for ChainVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainVerdict::Extends => write!(f, "valid, extends our latest block"),
+            ChainVerdict::Replaces { fork, replaced } => write!(f, "valid, replaces {} of our blocks from height {}", replaced, fork),
+            ChainVerdict::NotLonger => write!(f, "valid, but not longer than our chain"),
+            ChainVerdict::BelowFinalized(id) => write!(f, "valid, but replaces our finalized block at height {}", id),
+            ChainVerdict::Invalid(rejection) => {
+                write!(f, "invalid: block {} at height {}: {}", rejection.hash, rejection.height, rejection.reason)
+            }
+        }
+    }
+}
+
+// A block given as JSON or as the hex encoding of its JSON
+pub fn parse_block(input: &str) -> Result<Block, BlockchainError> {
+    let input = input.trim();
+    let json = match input.starts_with('{') {
+        true => input.as_bytes().to_vec(),
+        false => hex::decode(input).map_err(|err| BlockchainError::Error(format!("block is neither JSON nor hex: {}", err)))?,
+    };
+    serde_json::from_slice::<Block>(&json).map_err(|err| BlockchainError::Error(format!("can not parse block: {}", err)))
+}
+
+// The blocks of a JSON file holding an array of blocks, ordered by height
+pub fn read_chain(path: &Path) -> Result<Vec<Block>, BlockchainError> {
+    let contents = fs::read_to_string(path).map_err(BlockchainError::IoError)?;
+    let mut blocks = serde_json::from_str::<Vec<Block>>(&contents)
+        .map_err(|err| BlockchainError::Error(format!("{} holds no array of blocks: {}", path.display(), err)))?;
+    if blocks.is_empty() {
+        return Err(BlockchainError::Error(format!("{} holds no blocks", path.display())));
+    }
+    blocks.sort_by_key(|block| block.id);
+    Ok(blocks)
+}
+
+// Validates the block like add_block would when it is received from a peer
+pub async fn check_block(chain: &Chain, storage: &mut Storage, block: &Block) -> Result<BlockVerdict, BlockchainError> {
+    if storage.get_block(&block.hash).await.is_ok() {
+        return Ok(BlockVerdict::Known);
+    }
+    // Our genesis block is stored
+    if block.id == Height::GENESIS {
+        return Ok(BlockVerdict::Invalid("not our genesis block".to_owned()));
+    }
+    if storage.get_block(&block.prev_hash).await.is_err() {
+        return Ok(BlockVerdict::UnknownParent);
+    }
+    Ok(match chain.violation(storage, block).await? {
+        Some(reason) => BlockVerdict::Invalid(reason),
+        None if block.prev_hash == chain.latest_block.hash => BlockVerdict::Extends,
+        None => BlockVerdict::Stale,
+    })
+}
+
+// Validates the chain, ordered by height, like a chain synced from a peer: a complete chain is
+// replayed from the genesis block, a tail on top of the main chain block it follows. Whether the
+// node would switch to it is decided like in handle_chain.
+pub async fn check_chain(node: &mut Node, blocks: &[Block]) -> Result<ChainVerdict, BlockchainError> {
+    let (first, last) = match (blocks.first(), blocks.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(BlockchainError::Error("chain is empty".to_owned())),
+    };
+    let (mut replayed, mut scratch, replay) = match first.id == Height::GENESIS {
+        true => {
+            if *first != node.chain.genesis {
+                let reason = "not our genesis block".to_owned();
+                return Ok(ChainVerdict::Invalid(Rejection { hash: first.hash.clone(), height: first.id, reason }));
+            }
+            let mut scratch = Storage::Memory(MemoryStorage::default());
+            scratch.insert_block(first).await?;
+            let replayed = Chain { latest_block: first.clone(), finalized: Checkpoint::genesis(), latest_state: None, base: None, ..node.chain.clone() };
+            (replayed, scratch, &blocks[1..])
+        }
+        false => {
+            let parent = Chain::get_block(&mut node.storage, &first.prev_hash)
+                .await
+                .map_err(|_| BlockchainError::Error(format!("block {} at height {} follows no block of our chain", first.hash, first.id)))?;
+            let (replayed, scratch) = copy_below(node, &parent).await?;
+            (replayed, scratch, blocks)
+        }
+    };
+
+    for block in replay {
+        let rejection = |reason: String| ChainVerdict::Invalid(Rejection { hash: block.hash.clone(), height: block.id, reason });
+        if block.prev_hash != replayed.latest_block.hash {
+            return Ok(rejection(format!("does not follow block {}", replayed.latest_block.hash)));
+        }
+        if let Some(reason) = replayed.violation(&mut scratch, block).await? {
+            return Ok(rejection(reason));
+        }
+        replayed.add_block(&mut scratch, block.clone()).await?;
+    }
+
+    // The lowest height where the candidate differs from our chain, blocks below our snapshot base
+    // can not be compared
+    let first_stored = node.chain.base.as_ref().map_or(Height::GENESIS, |base| base.id);
+    let mut fork = Height(last.id.0 + 1);
+    for block in blocks.iter().filter(|block| block.id >= first_stored) {
+        if !Chain::get_block_by_id(&mut node.storage, block.id).await.is_ok_and(|ours| ours.hash == block.hash) {
+            fork = block.id;
+            break;
+        }
+    }
+    let ours = node.chain.latest_block.id;
+    Ok(if last.id <= ours {
+        ChainVerdict::NotLonger
+    } else if fork > ours {
+        ChainVerdict::Extends
+    } else if fork <= node.chain.finalized.id {
+        ChainVerdict::BelowFinalized(node.chain.finalized.id)
+    } else {
+        ChainVerdict::Replaces { fork, replaced: ours.0 + 1 - fork.0 }
+    })
+}
+
+// Our chain up to the block, in memory, like Node::isolated
+async fn copy_below(node: &mut Node, top: &Block) -> Result<(Chain, Storage), BlockchainError> {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let blocks = Chain::get_chain(&mut node.storage)
+        .await?
+        .into_iter()
+        .filter(|block| block.id <= top.id)
+        .collect::<Vec<_>>();
+    match &node.chain.base {
+        Some(base) => storage.restore_snapshot(base, &blocks).await?,
+        None => {
+            for block in &blocks {
+                storage.insert_block(block).await?;
+            }
+        }
+    }
+    let finalized = match node.chain.finalized.id <= top.id {
+        true => node.chain.finalized.clone(),
+        false => Checkpoint { id: top.id, hash: top.hash.clone() },
+    };
+    let chain = Chain { latest_block: top.clone(), finalized, latest_state: None, ..node.chain.clone() };
+    Ok((chain, storage))
+}
@@ -0,0 +1,161 @@
+// Service announcements (`--announce-service KIND=URL`, `ls p --services`): a node exposing a public
+// API, e.g. its JSON-RPC server (see rpc.rs) behind a reverse proxy, gossips the URLs it is reachable
+// at every SERVICE_ANNOUNCE_INTERVAL, so light clients find API access to the network through their
+// peers instead of out-of-band configuration. Like finalized checkpoints the announcement is signed
+// with the identity key of the announcing peer (see p2p.rs) and names its peer ID, so relays can not
+// attribute URLs to other peers. Each peer's latest announcement is kept until it is replaced or
+// expires after SERVICE_TTL_MILLIS without being renewed. Nothing is checked about the URLs beyond
+// their form, whether an endpoint answers is up to the client.
+use crate::blockchain::BlockchainError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const SERVICE_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(120);
+// Announcements that were not renewed for that long are dropped, a few intervals
+pub const SERVICE_TTL_MILLIS: i64 = 5 * SERVICE_ANNOUNCE_INTERVAL.as_millis() as i64;
+// Announcements dated further ahead of our clock are rejected
+const MAX_CLOCK_SKEW_MILLIS: i64 = 60_000;
+pub const MAX_SERVICES: usize = 8;
+const MAX_KIND_LENGTH: usize = 32;
+const MAX_URL_LENGTH: usize = 256;
+const URL_SCHEMES: [&str; 5] = ["http://", "https://", "ws://", "wss://", "tcp://"];
+// Peers whose services we keep, further announcements are dropped until others expired
+pub const MAX_ANNOUNCING_PEERS: usize = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Service {
+    // What the endpoint serves, e.g. rpc or events
+    pub kind: String,
+    pub url: String,
+}
+
+impl Service {
+    pub fn check(&self) -> Result<(), BlockchainError> {
+        if self.kind.is_empty()
+            || self.kind.len() > MAX_KIND_LENGTH
+            || !self.kind.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err(BlockchainError::Error(format!("invalid service kind: {:?} (lowercase letters, digits and -)", self.kind)));
+        }
+        let host = URL_SCHEMES.iter().find_map(|scheme| self.url.strip_prefix(scheme));
+        if self.url.len() > MAX_URL_LENGTH || self.url.contains(|c: char| c.is_whitespace() || c.is_control()) || !host.is_some_and(|host| !host.is_empty()) {
+            return Err(BlockchainError::Error(format!("invalid service url: {:?} (one of {:?})", self.url, URL_SCHEMES)));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.kind, self.url)
+    }
+}
+
+// Parses "KIND=URL"
+impl FromStr for Service {
+    type Err = BlockchainError;
+
+    fn from_str(service: &str) -> Result<Self, Self::Err> {
+        let (kind, url) = service
+            .split_once('=')
+            .ok_or_else(|| BlockchainError::Error(format!("invalid service: {} (KIND=URL)", service)))?;
+        let service = Service { kind: kind.to_owned(), url: url.to_owned() };
+        service.check()?;
+        Ok(service)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceAnnouncement {
+    pub peer_id: String,
+    pub services: Vec<Service>,
+    // Milliseconds since the epoch, newer announcements of a peer replace older ones
+    pub announced_at: i64,
+}
+
+impl ServiceAnnouncement {
+    // What the announcing peer signs
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("can jsonify announcement")
+    }
+
+    pub fn check(&self, now: i64) -> Result<(), BlockchainError> {
+        if self.services.is_empty() || self.services.len() > MAX_SERVICES {
+            return Err(BlockchainError::Error(format!("announcements carry 1 to {} services", MAX_SERVICES)));
+        }
+        if self.announced_at > now + MAX_CLOCK_SKEW_MILLIS {
+            return Err(BlockchainError::Error("announcement from the future".to_owned()));
+        }
+        self.services.iter().try_for_each(Service::check)
+    }
+}
+
+// The latest announcement of every peer
+#[derive(Debug, Clone, Default)]
+pub struct ServiceDirectory {
+    announcements: HashMap<String, ServiceAnnouncement>,
+}
+
+impl ServiceDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keeps the announcement if it is valid, not expired and newer than the one we have of the peer.
+    // Returns whether it was kept.
+    pub fn record(&mut self, announcement: ServiceAnnouncement, now: i64) -> Result<bool, BlockchainError> {
+        announcement.check(now)?;
+        if announcement.announced_at + SERVICE_TTL_MILLIS < now {
+            return Ok(false);
+        }
+        match self.announcements.get(&announcement.peer_id) {
+            Some(known) if known.announced_at >= announcement.announced_at => return Ok(false),
+            None if self.announcements.len() >= MAX_ANNOUNCING_PEERS => {
+                self.expire(now);
+                if self.announcements.len() >= MAX_ANNOUNCING_PEERS {
+                    return Ok(false);
+                }
+            }
+            _ => {}
+        }
+        self.announcements.insert(announcement.peer_id.clone(), announcement);
+        Ok(true)
+    }
+
+    // Returns the number of dropped announcements
+    pub fn expire(&mut self, now: i64) -> usize {
+        let count = self.announcements.len();
+        self.announcements.retain(|_, announcement| announcement.announced_at + SERVICE_TTL_MILLIS >= now);
+        count - self.announcements.len()
+    }
+
+    // Ordered by peer ID
+    pub fn list(&self) -> Vec<&ServiceAnnouncement> {
+        let mut announcements = self.announcements.values().collect::<Vec<_>>();
+        announcements.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        announcements
+    }
+
+    // The endpoints of that kind, to pick one from
+    pub fn urls(&self, kind: &str) -> Vec<&str> {
+        self.list()
+            .into_iter()
+            .flat_map(|announcement| announcement.services.iter())
+            .filter(|service| service.kind == kind)
+            .map(|service| service.url.as_str())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.announcements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.announcements.is_empty()
+    }
+}
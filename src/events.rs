@@ -0,0 +1,124 @@
+// Block event stream (`--events ADDR`): clients connect via TCP and get the blocks added to our main
+// chain, one JSON encoded EventMessage per line. A client sends a BlockFilter (one JSON object per
+// line) to subscribe, which is confirmed with Subscribed, and from then on only gets the blocks
+// matching its latest filter. `{}` matches all blocks. Reorgs are sent to every subscribed client,
+// followed by the matching blocks of the new branch (see head.rs). So are mining jobs of our mempool
+// that were replaced by a conflicting job offering a higher fee (see mining.rs).
+use crate::blockchain::Block;
+use crate::head::HeadEvent;
+use crate::mining::{MempoolEvent, MiningJob};
+use crate::rpc;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use tokio::io::{self, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+// Number of blocks buffered for slow clients, older ones are skipped
+pub const EVENT_BUFFER: usize = 1_000;
+
+// All conditions have to match, unset ones match every block
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockFilter {
+    // Substring of the block data
+    pub data_contains: Option<String>,
+    // Address the block touches, see Block::addresses
+    pub address: Option<String>,
+    pub min_height: Option<Height>,
+    pub max_height: Option<Height>,
+}
+
+impl BlockFilter {
+    pub fn matches(&self, block: &Block) -> bool {
+        self.data_contains.as_ref().is_none_or(|data| block.data.contains(data.as_str()))
+            && self.address.as_ref().is_none_or(|address| block.addresses().contains(address))
+            && self.min_height.is_none_or(|height| block.id >= height)
+            && self.max_height.is_none_or(|height| block.id <= height)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EventMessage {
+    // The filter now in use
+    Subscribed(BlockFilter),
+    Block(Block),
+    // The blocks of our chain above old_tip.id - depth were replaced
+    Reorged { old_tip: Block, new_tip: Block, depth: u64 },
+    // The pending mining job was dropped for the conflicting one
+    Replaced { replaced: MiningJob, by: MiningJob },
+    // The line sent by the client is no valid filter, the previous filter stays in use
+    Invalid(String),
+}
+
+// Accepts client connections and streams the head events sent to head_sender and the mempool events
+// sent to mempool_sender to them
+pub async fn init_events(
+    listener: TcpListener,
+    head_sender: broadcast::Sender<HeadEvent>,
+    mempool_sender: broadcast::Sender<MempoolEvent>,
+) -> Result<(), std::io::Error> {
+    println!("Events listening on {}", listener.local_addr()?);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("Event client {} connected", addr);
+        tokio::spawn(handle_connection(stream, addr.to_string(), head_sender.subscribe(), mempool_sender.subscribe()));
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    name: String,
+    mut head_events: broadcast::Receiver<HeadEvent>,
+    mut mempool_events: broadcast::Receiver<MempoolEvent>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let (mut reader, mut buffer) = (BufReader::new(reader), vec![]);
+    // Nothing is sent until the client subscribed
+    let mut filter: Option<BlockFilter> = None;
+    loop {
+        let message = tokio::select! {
+            // A filter line longer than a request closes the connection
+            line = rpc::read_line(&mut reader, &mut buffer, rpc::MAX_REQUEST_LENGTH) => match line {
+                Ok(Some(line)) => match serde_json::from_str::<BlockFilter>(&line) {
+                    Ok(new_filter) => {
+                        filter = Some(new_filter.clone());
+                        EventMessage::Subscribed(new_filter)
+                    }
+                    Err(err) => EventMessage::Invalid(err.to_string()),
+                },
+                _ => break,
+            },
+            event = head_events.recv() => match event {
+                Ok(HeadEvent::Extended(block)) if filter.as_ref().is_some_and(|filter| filter.matches(&block)) => EventMessage::Block(block),
+                Ok(HeadEvent::Reorged{old_tip, new_tip, depth}) if filter.is_some() => EventMessage::Reorged{old_tip, new_tip, depth},
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Event client {} is too slow, skipped {} events", name, skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            event = mempool_events.recv() => match event {
+                Ok(MempoolEvent::Replaced{replaced, by}) if filter.is_some() => EventMessage::Replaced{replaced, by},
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Event client {} is too slow, skipped {} mempool events", name, skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if write_message(&mut writer, &message).await.is_err() {
+            break;
+        }
+    }
+    info!("Event client {} disconnected", name);
+}
+
+async fn write_message<W: AsyncWriteExt + Unpin>(writer: &mut W, message: &EventMessage) -> io::Result<()> {
+    let mut line = serde_json::to_string(message).expect("can jsonify event");
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
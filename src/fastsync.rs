@@ -0,0 +1,245 @@
+// Snapshot fast sync (`--fast-sync`): a new node joining a long chain asks a peer for a snapshot of
+// its chain instead of the complete chain. A snapshot holds the links (height, hash and parent hash)
+// of the blocks from the genesis block up to the recent ones, the recent blocks in full and the state
+// the blocks below them leave behind. It is signed by the peer that created it (see p2p.rs). We check
+// that it links to our genesis block and that its full blocks are valid, then store it as a pruned
+// chain starting at its first full block, the base. Afterwards only the tail above our latest block
+// is requested from our peers and added block by block, nothing is replayed from the genesis block.
+// The blocks below the base are trusted on the signature of the snapshot, so SnapshotTrust limits
+// whose signature counts (`--snapshot-signer`) and how many signers have to agree on the base
+// (`--snapshot-quorum`) before a snapshot is restored.
+use crate::blockchain::{self, Block, BlockchainError, Chain, Checkpoint};
+use crate::consensus;
+use crate::difficulty::{self, BlockInfo};
+use crate::storage::{MemoryStorage, Storage};
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// Minimum number of recent blocks a snapshot holds in full. More are included if the difficulty
+// algorithm looks further back, so the blocks following the snapshot can be validated.
+pub const SNAPSHOT_BLOCKS: u64 = 100;
+// With --fast-sync, peers at least this high are synced from a snapshot, shorter chains completely
+pub const FAST_SYNC_MIN_HEIGHT: Height = Height(1_000);
+
+// Height, hash and parent hash of a block whose body is not part of a snapshot
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct BlockLink {
+    pub id: Height,
+    pub hash: String,
+    pub prev_hash: String,
+}
+
+// What the blocks below the base of a snapshot leave behind that is needed to validate the blocks
+// above it. The chain has no ledger (yet), only the difficulty algorithm looks further back.
+#[derive(Serialize, Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotState {
+    // The block the difficulty algorithm is anchored at (see DifficultyAlgorithm::anchor), if below the base
+    pub difficulty_anchor: Option<BlockInfo>,
+}
+
+// Where a chain restored from a snapshot starts, the blocks below it were never downloaded
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ChainBase {
+    pub id: Height,
+    pub hash: String,
+    pub state: SnapshotState,
+}
+
+// The snapshots we accept: signed by one of the signers if any are given, every peer otherwise, and
+// only once quorum different signers sent snapshots agreeing on the base
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotTrust {
+    pub signers: Vec<String>,
+    pub quorum: usize,
+}
+
+impl Default for SnapshotTrust {
+    fn default() -> Self {
+        Self { signers: vec![], quorum: 1 }
+    }
+}
+
+impl SnapshotTrust {
+    pub fn trusts(&self, signer: &str) -> bool {
+        self.signers.is_empty() || self.signers.iter().any(|trusted| trusted == signer)
+    }
+}
+
+// Verified snapshots waiting for the quorum, the latest one of every signer
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotVotes {
+    snapshots: BTreeMap<String, ChainSnapshot>,
+}
+
+impl SnapshotVotes {
+    pub fn has_voted(&self, signer: &str) -> bool {
+        self.snapshots.contains_key(signer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    // Adds the verified snapshot of the signer. Returns the snapshot to restore once quorum signers
+    // vouch for its base, the one reaching furthest if there are several, and forgets the others.
+    pub fn add(&mut self, signer: String, snapshot: ChainSnapshot, quorum: usize) -> Option<ChainSnapshot> {
+        self.snapshots.insert(signer, snapshot);
+        let agreed = self
+            .snapshots
+            .values()
+            .filter(|snapshot| self.snapshots.values().filter(|other| other.vouches_for(snapshot)).count() >= quorum)
+            .max_by_key(|snapshot| snapshot.blocks.last().map(|block| block.id))
+            .cloned();
+        if agreed.is_some() {
+            self.snapshots.clear();
+        }
+        agreed
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct ChainSnapshot {
+    // From the genesis block up to the parent of the first full block
+    pub links: Vec<BlockLink>,
+    // Oldest first
+    pub blocks: Vec<Block>,
+    pub state: SnapshotState,
+}
+
+impl ChainSnapshot {
+    // Snapshot of our main chain. A chain restored from a snapshot itself lacks the links below its
+    // base, so it can not be snapshotted.
+    pub async fn create(chain: &Chain, storage: &mut Storage) -> Result<Self, BlockchainError> {
+        if let Some(base) = &chain.base {
+            return Err(BlockchainError::Error(format!("chain was restored from a snapshot at height {}", base.id)));
+        }
+        Self::from_blocks(chain, Chain::get_chain(storage).await?)
+    }
+
+    // Snapshot of the main chain blocks of the chain, from the genesis block up, e.g. of a copy of
+    // them (see workers.rs)
+    pub fn from_blocks(chain: &Chain, mut blocks: Vec<Block>) -> Result<Self, BlockchainError> {
+        if blocks.first().is_none_or(|genesis| *genesis != chain.genesis) {
+            return Err(BlockchainError::Error("blocks do not start with the genesis block".to_owned()));
+        }
+        let full = snapshot_blocks(chain).min(blocks.len().saturating_sub(1) as u64);
+        let recent = blocks.split_off(blocks.len() - full as usize);
+        let base = recent.first().ok_or_else(|| BlockchainError::Error("chain has no blocks above the genesis block".to_owned()))?;
+
+        let difficulty_anchor = match chain.difficulty_algorithm.anchor() {
+            Some(anchor) if anchor < base.id => Some(blockchain::block_info(&blocks[anchor.0 as usize])),
+            _ => None,
+        };
+        let links = blocks
+            .into_iter()
+            .map(|block| BlockLink { id: block.id, hash: block.hash, prev_hash: block.prev_hash })
+            .collect();
+        Ok(Self { links, blocks: recent, state: SnapshotState { difficulty_anchor } })
+    }
+
+    // The bytes the peer that created the snapshot signs
+    pub fn digest(&self) -> Vec<u8> {
+        Sha256::digest(serde_json::to_vec(self).expect("can jsonify snapshot")).to_vec()
+    }
+
+    pub fn base(&self) -> Option<ChainBase> {
+        self.blocks.first().map(|block| ChainBase { id: block.id, hash: block.hash.clone(), state: self.state.clone() })
+    }
+
+    // Whether the snapshot has the base block of the other one, as a link or in full, and leaves the
+    // same state behind. Snapshots of peers at different heights agree on the base of the lower one.
+    pub fn vouches_for(&self, other: &ChainSnapshot) -> bool {
+        let base = match other.base() {
+            Some(base) => base,
+            None => return false,
+        };
+        self.state == base.state
+            && self
+                .links
+                .iter()
+                .map(|link| (link.id, &link.hash))
+                .chain(self.blocks.iter().map(|block| (block.id, &block.hash)))
+                .any(|(id, hash)| id == base.id && *hash == base.hash)
+    }
+
+    // Checks that the snapshot starts with our genesis block, that its links and blocks form one
+    // chain and that its full blocks are valid under the rules of our chain. The bodies of the linked
+    // blocks are not part of the snapshot, they are trusted on the signature of the snapshot.
+    pub async fn verify(&self, chain: &Chain) -> Result<ChainBase, BlockchainError> {
+        let invalid = |reason: &str| BlockchainError::ChainInvalid(Box::new(BlockchainError::Error(reason.to_owned())));
+        if self.links.first().is_none_or(|genesis| genesis.id != Height::GENESIS || genesis.hash != chain.genesis.hash) {
+            return Err(invalid("snapshot does not start with our genesis block"));
+        }
+        let base = self.base().ok_or_else(|| invalid("snapshot has no blocks"))?;
+        let parent = self.links.last().expect("snapshot has a genesis link");
+        let linked = self.links.windows(2).all(|links| links[1].id == links[0].id + 1 && links[1].prev_hash == links[0].hash);
+        if !linked || base.id != parent.id + 1 || self.blocks[0].prev_hash != parent.hash {
+            return Err(invalid("snapshot is not linked"));
+        }
+        if base.id > Height(1) && (self.blocks.len() as u64) < snapshot_blocks(chain) {
+            return Err(invalid("snapshot has too few blocks"));
+        }
+        let anchor = chain.difficulty_algorithm.anchor().filter(|anchor| *anchor < base.id);
+        if anchor != base.state.difficulty_anchor.map(|info| info.height) {
+            return Err(invalid("snapshot has no difficulty anchor"));
+        }
+
+        // The base block is checked on its own, its parent is not part of the snapshot
+        let block = &self.blocks[0];
+        let proof_of_work = match consensus::rules_at(block.id).difficulty_adjustment {
+            true => difficulty::meets_difficulty(&blockchain::digest_with_nonce(block, block.nonce), block.difficulty),
+            false => block.hash.starts_with(&chain.difficulty),
+        };
+        if blockchain::hasher(block) != block.hash || !proof_of_work {
+            return Err(BlockchainError::ChainInvalid(Box::new(BlockchainError::BlockInvalid(block.hash.clone()))));
+        }
+        let mut scratch = Storage::Memory(MemoryStorage::default());
+        scratch.insert_block(block).await?;
+        let mut restored = Chain {
+            latest_block: block.clone(),
+            finalized: Checkpoint { id: base.id, hash: base.hash.clone() },
+            base: Some(base.clone()),
+            ..chain.clone()
+        };
+        for block in &self.blocks[1..] {
+            restored.add_block(&mut scratch, block.clone()).await.map_err(|err| BlockchainError::ChainInvalid(Box::new(err)))?;
+        }
+        Ok(base)
+    }
+}
+
+// Replaces our chain with the verified snapshot, which has to be ahead of it and agree with our
+// finalized block. Our own blocks are dropped.
+pub async fn restore(chain: &mut Chain, storage: &mut Storage, snapshot: &ChainSnapshot) -> Result<(), BlockchainError> {
+    let base = snapshot.verify(chain).await?;
+    let latest = snapshot.blocks.last().expect("verified snapshot has blocks");
+    if latest.id <= chain.latest_block.id {
+        return Err(BlockchainError::Error("snapshot is not ahead of our chain".to_owned()));
+    }
+    let finalized = snapshot
+        .links
+        .iter()
+        .map(|link| (link.id, &link.hash))
+        .chain(snapshot.blocks.iter().map(|block| (block.id, &block.hash)))
+        .any(|(id, hash)| id == chain.finalized.id && *hash == chain.finalized.hash);
+    if !finalized {
+        return Err(BlockchainError::ReorgBelowFinalized(chain.finalized.id));
+    }
+
+    storage.restore_snapshot(&base, &snapshot.blocks).await?;
+    chain.latest_block = latest.clone();
+    chain.finalized = Checkpoint { id: base.id, hash: base.hash.clone() };
+    chain.base = Some(base);
+    chain.update_finalized(storage).await
+}
+
+// Number of recent blocks a snapshot of the chain holds in full
+pub fn snapshot_blocks(chain: &Chain) -> u64 {
+    SNAPSHOT_BLOCKS.max(chain.difficulty_algorithm.window() as u64 + chain.params.max_reorg_depth + 1)
+}
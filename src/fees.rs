@@ -0,0 +1,50 @@
+// Fee market: a mining job can offer a fee for its block data (`mine queue add PRIORITY --fee N`).
+// The mempool only admits jobs paying at least the minimum fee rate (`--min-fee-rate`) and within one
+// priority mines the jobs paying the highest fee rate first, see mining.rs. The fee of a mined job is
+// part of the block header (version 4) and is collected by the block's miner together with the block
+// reward of the network (see network.rs). The fee is declared by the job, for blocks carrying
+// transactions it is the sum of their fees (see transactions.rs), instead of being paid from a balance.
+use crate::blockchain::{Block, BlockchainError};
+use crate::network::NetworkParams;
+
+// Fee rates are fees per this many bytes of block data
+pub const FEE_RATE_BYTES: u64 = 1_000;
+const FEE_OPTION: &str = "--fee ";
+
+// Splits "[--fee N] BLOCK_DATA" into the fee (0 without the option) and the block data, which is kept as is
+pub fn split_fee(args: &str) -> Result<(u64, &str), BlockchainError> {
+    let rest = match args.strip_prefix(FEE_OPTION) {
+        Some(rest) => rest.trim_start(),
+        None => return Ok((0, args)),
+    };
+    let (fee, data) = rest.split_once(' ').unwrap_or((rest, ""));
+    let fee = fee.parse::<u64>().map_err(|_| BlockchainError::Error("--fee requires an amount".to_owned()))?;
+    Ok((fee, data))
+}
+
+// Empty data pays the rate of one byte
+pub fn fee_rate(fee: u64, data_len: usize) -> u64 {
+    fee.saturating_mul(FEE_RATE_BYTES) / (data_len.max(1) as u64)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Earnings {
+    pub blocks: u64,
+    pub rewards: u64,
+    pub fees: u64,
+}
+
+impl Earnings {
+    pub fn total(&self) -> u64 {
+        self.rewards.saturating_add(self.fees)
+    }
+}
+
+// What the miner collected with the blocks it mined among the given ones
+pub fn earnings(params: &NetworkParams, miner: &str, blocks: &[Block]) -> Earnings {
+    blocks.iter().filter(|block| block.miner == miner).fold(Earnings::default(), |earnings, block| Earnings {
+        blocks: earnings.blocks + 1,
+        rewards: earnings.rewards.saturating_add(params.reward_at(block.id)),
+        fees: earnings.fees.saturating_add(block.fee),
+    })
+}
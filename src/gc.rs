@@ -0,0 +1,68 @@
+// Garbage collection of side-chain blocks: valid blocks that lost the race for their height are kept
+// as stale blocks (`chain uncles`, the stale rate). Once they are at or below our finalized block no
+// reorg can bring them back, so every GC_INTERVAL they are pruned, completely or, with
+// `--keep-stale-headers`, only their payloads while the headers stay. Archival nodes (`--archival`)
+// keep everything. `node gc` shows how many blocks were pruned and how many bytes that reclaimed.
+use crate::blockchain::{BlockchainError, Chain, StaleBlock};
+use crate::storage::Storage;
+use std::time::Duration;
+
+pub const GC_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StalePruning {
+    Blocks,
+    // The headers are kept
+    Bodies,
+    Off,
+}
+
+// Totals since start-up, or of a single run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub runs: u64,
+    pub blocks_pruned: u64,
+    pub bodies_pruned: u64,
+    // Of the pruned blocks as JSON, or of the pruned payloads
+    pub bytes_reclaimed: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StaleCollector {
+    pub pruning: StalePruning,
+    pub stats: GcStats,
+}
+
+impl StaleCollector {
+    pub fn new(pruning: StalePruning) -> Self {
+        Self { pruning, stats: GcStats::default() }
+    }
+
+    // Prunes the stale blocks at or below the finalized block of the chain, returns what this run reclaimed
+    pub async fn collect(&mut self, storage: &mut Storage, chain: &Chain) -> Result<GcStats, BlockchainError> {
+        if self.pruning == StalePruning::Off {
+            return Ok(GcStats::default());
+        }
+        let keep_headers = self.pruning == StalePruning::Bodies;
+        let pruned = storage.prune_stale_blocks(chain.finalized.id, keep_headers).await?;
+        let count = pruned.len() as u64;
+        let run = GcStats {
+            runs: 1,
+            blocks_pruned: if keep_headers { 0 } else { count },
+            bodies_pruned: if keep_headers { count } else { 0 },
+            bytes_reclaimed: pruned.iter().map(|stale| reclaimed(stale, keep_headers)).sum(),
+        };
+        self.stats.runs += run.runs;
+        self.stats.blocks_pruned += run.blocks_pruned;
+        self.stats.bodies_pruned += run.bodies_pruned;
+        self.stats.bytes_reclaimed += run.bytes_reclaimed;
+        Ok(run)
+    }
+}
+
+fn reclaimed(stale: &StaleBlock, keep_headers: bool) -> u64 {
+    match keep_headers {
+        true => stale.block.data.len() as u64,
+        false => serde_json::to_vec(&stale.block).expect("can jsonify block").len() as u64,
+    }
+}
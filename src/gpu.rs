@@ -0,0 +1,178 @@
+// Nonce search on the GPU (`--hasher gpu`), only available when built with the gpu feature, which
+// needs an OpenCL driver. The kernel in sha256.cl only compares the first 8 bytes of the digest with
+// a threshold, so targets that can not be expressed that way are left to the CPU search in
+// blockchain.rs, as is everything after a GPU error.
+use crate::blockchain::Block;
+use crate::consensus::{self, HashEncoding};
+use crate::difficulty;
+use crate::types::Nonce;
+
+// The largest digest prefix (first 8 bytes, big endian) that meets the target of the block, if the
+// target can be checked that way
+pub fn threshold(block: &Block, prefix: &str) -> Option<u64> {
+    if block.difficulty > 0 {
+        return Some(u64::MAX / block.difficulty.max(difficulty::MIN_DIFFICULTY));
+    }
+    if prefix.chars().any(|char| char != '0') {
+        return None;
+    }
+    // Legacy hashes encode a zero byte as a single 0, padded ones as two
+    let bits_per_zero = match consensus::rules_at(block.id).hash_encoding {
+        HashEncoding::Legacy => 8,
+        HashEncoding::PaddedHex => 4,
+    };
+    let bits = prefix.len() * bits_per_zero;
+    if bits >= 64 {
+        return None;
+    }
+    Some(u64::MAX >> bits)
+}
+
+// Looks for a GPU, returns its name
+pub fn init() -> Result<String, String> {
+    #[cfg(feature = "gpu")]
+    return opencl::init();
+    #[cfg(not(feature = "gpu"))]
+    Err("built without the gpu feature".to_owned())
+}
+
+// Like blockchain::find_hash, but on the GPU
+pub fn find_hash(template: &Block, prefix: &str) -> Result<Option<(String, Nonce)>, String> {
+    let threshold = threshold(template, prefix).ok_or("the target can not be checked on the GPU")?;
+    #[cfg(feature = "gpu")]
+    return opencl::find_hash(template, prefix, threshold);
+    #[cfg(not(feature = "gpu"))]
+    {
+        let _ = threshold;
+        Err("built without the gpu feature".to_owned())
+    }
+}
+
+#[cfg(feature = "gpu")]
+mod opencl {
+    use crate::blockchain::{self, Block};
+    use crate::types::Nonce;
+    use once_cell::sync::Lazy;
+    use opencl3::command_queue::CommandQueue;
+    use opencl3::context::Context;
+    use opencl3::device::{Device, CL_DEVICE_TYPE_GPU};
+    use opencl3::kernel::{ExecuteKernel, Kernel};
+    use opencl3::memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_READ_WRITE};
+    use opencl3::platform;
+    use opencl3::program::Program;
+    use opencl3::types::{cl_uint, cl_ulong, CL_BLOCKING};
+    use sha2::digest::generic_array::GenericArray;
+    use std::ptr;
+    use std::sync::Mutex;
+
+    const KERNEL: &str = include_str!("sha256.cl");
+    // Nonces hashed per kernel run
+    const BATCH_SIZE: u64 = 1 << 20;
+    // Initial SHA-256 state
+    const IV: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    struct Gpu {
+        name: String,
+        context: Context,
+        queue: CommandQueue,
+        kernel: Kernel,
+    }
+
+    // Set up on first use, an error is kept so we only try once
+    static GPU: Lazy<Mutex<Result<Gpu, String>>> = Lazy::new(|| Mutex::new(Gpu::new()));
+
+    impl Gpu {
+        fn new() -> Result<Self, String> {
+            let device_id = platform::get_platforms()
+                .map_err(|err| err.to_string())?
+                .iter()
+                .filter_map(|platform| platform.get_devices(CL_DEVICE_TYPE_GPU).ok())
+                .flatten()
+                .next()
+                .ok_or("no OpenCL GPU found")?;
+            let device = Device::new(device_id);
+            let name = device.name().map_err(|err| err.to_string())?;
+            let context = Context::from_device(&device).map_err(|err| err.to_string())?;
+            let queue = CommandQueue::create(&context, device_id, 0).map_err(|err| err.to_string())?;
+            let program = Program::create_and_build_from_source(&context, KERNEL, "")?;
+            let kernel = Kernel::create(&program, "search").map_err(|err| err.to_string())?;
+            Ok(Self { name, context, queue, kernel })
+        }
+
+        fn buffer<T>(&self, flags: u64, data: &[T]) -> Result<Buffer<T>, String> {
+            // OpenCL does not allow empty buffers
+            let mut buffer = Buffer::<T>::create(&self.context, flags, data.len().max(1), ptr::null_mut())
+                .map_err(|err| err.to_string())?;
+            if !data.is_empty() {
+                self.queue
+                    .enqueue_write_buffer(&mut buffer, CL_BLOCKING, 0, data, &[])
+                    .map_err(|err| err.to_string())?;
+            }
+            Ok(buffer)
+        }
+    }
+
+    pub fn init() -> Result<String, String> {
+        let gpu = GPU.lock().expect("can lock gpu");
+        gpu.as_ref().map(|gpu| gpu.name.clone()).map_err(Clone::clone)
+    }
+
+    pub fn find_hash(template: &Block, prefix: &str, threshold: u64) -> Result<Option<(String, Nonce)>, String> {
+        let gpu = GPU.lock().expect("can lock gpu");
+        let gpu = gpu.as_ref().map_err(Clone::clone)?;
+
+        // Only the part of the header after the last full block before the nonce is hashed per nonce
+        let (head, suffix) = blockchain::header_around_nonce(template);
+        let hashed_len = head.len() / 64 * 64;
+        let mut midstate = IV;
+        let blocks: Vec<_> = head.as_bytes()[..hashed_len].chunks(64).map(GenericArray::clone_from_slice).collect();
+        sha2::compress256(&mut midstate, &blocks);
+        let tail = &head.as_bytes()[hashed_len..];
+
+        let midstate_buffer = gpu.buffer(CL_MEM_READ_ONLY, &midstate)?;
+        let tail_buffer = gpu.buffer(CL_MEM_READ_ONLY, tail)?;
+        let suffix_buffer = gpu.buffer(CL_MEM_READ_ONLY, suffix.as_bytes())?;
+        let mut found_buffer = gpu.buffer::<cl_uint>(CL_MEM_READ_WRITE, &[0, 0])?;
+
+        let mut base = 0;
+        while base < Nonce::MAX.0 {
+            let count = BATCH_SIZE.min(Nonce::MAX.0 - base);
+            ExecuteKernel::new(&gpu.kernel)
+                .set_arg(&midstate_buffer)
+                .set_arg(&tail_buffer)
+                .set_arg(&(tail.len() as cl_uint))
+                .set_arg(&suffix_buffer)
+                .set_arg(&(suffix.len() as cl_uint))
+                .set_arg(&(hashed_len as cl_ulong))
+                .set_arg(&(base as cl_ulong))
+                .set_arg(&(threshold as cl_ulong))
+                .set_arg(&found_buffer)
+                .set_global_work_size(count as usize)
+                .enqueue_nd_range(&gpu.queue)
+                .map_err(|err| err.to_string())?;
+            let mut found = [0 as cl_uint; 2];
+            gpu.queue
+                .enqueue_read_buffer(&found_buffer, CL_BLOCKING, 0, &mut found, &[])
+                .map_err(|err| err.to_string())?;
+
+            if found[0] == 1 {
+                // Double checked, a broken driver must not get us to publish invalid blocks
+                let nonce = Nonce(base + found[1] as u64);
+                let digest = blockchain::digest_with_nonce(template, nonce);
+                if !blockchain::meets_target(template, &digest, prefix) {
+                    return Err(format!("the GPU found the invalid nonce {}", nonce));
+                }
+                let mut block = template.clone();
+                block.nonce = nonce;
+                return Ok(Some((blockchain::hasher(&block), nonce)));
+            }
+            gpu.queue
+                .enqueue_write_buffer(&mut found_buffer, CL_BLOCKING, 0, &[0, 0], &[])
+                .map_err(|err| err.to_string())?;
+            base += count;
+        }
+        Ok(None)
+    }
+}
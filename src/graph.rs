@@ -0,0 +1,121 @@
+// Graphviz export of the blocks we know (`chain graph --out chain.dot [--from-height H]`) to look at
+// forks when debugging consensus: the main chain and the stale blocks of the side chains, each
+// labelled with its height, hash and miner and linked to its parent. Stale blocks are dashed and our
+// latest block is bold. Orphans are not kept (we fetch the sender's chain instead), but the parent of
+// a block can still be missing, e.g. below --from-height or after stale blocks were pruned (see
+// gc.rs); such parents are drawn as dotted placeholders. Render with `dot -Tsvg chain.dot -o chain.svg`.
+use crate::blockchain::{Block, BlockchainError, Chain, StaleBlock};
+use crate::storage::Storage;
+use crate::types::Height;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// Characters of the hash shown in the labels, node IDs use the complete hash
+pub const LABEL_HASH_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphConfig {
+    pub out: PathBuf,
+    pub from: Height,
+}
+
+impl GraphConfig {
+    // Parses "--out PATH [--from-height H]"
+    pub fn from_args(args: &str) -> Result<Self, BlockchainError> {
+        let mut out = None;
+        let mut from = Height::GENESIS;
+
+        let mut args = args.split_whitespace();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--out" => {
+                    out = Some(PathBuf::from(args.next().ok_or_else(|| BlockchainError::Error("--out requires a path".to_owned()))?));
+                }
+                "--from-height" => {
+                    from = args
+                        .next()
+                        .and_then(|height| height.parse::<u64>().ok())
+                        .map(Height)
+                        .ok_or_else(|| BlockchainError::Error("--from-height requires a height".to_owned()))?;
+                }
+                _ => return Err(BlockchainError::Error(format!("unknown option: {}", arg))),
+            }
+        }
+        let out = out.ok_or_else(|| BlockchainError::Error("--out is required".to_owned()))?;
+        Ok(Self { out, from })
+    }
+}
+
+// The DOT graph of the main chain and stale blocks from the height on
+pub async fn export(chain: &Chain, storage: &mut Storage, from: Height) -> Result<String, BlockchainError> {
+    let main = match from <= chain.latest_block.id {
+        true => storage.get_blocks_in_range(from, chain.latest_block.id).await?,
+        false => vec![],
+    };
+    let mut stale = storage.get_stale_blocks(i64::MAX).await?;
+    stale.retain(|stale| stale.block.id >= from);
+    stale.reverse();
+    Ok(to_dot(&main, &stale, &chain.latest_block.hash))
+}
+
+// Blocks are laid out left to right, parents before their children
+pub fn to_dot(main: &[Block], stale: &[StaleBlock], tip: &str) -> String {
+    let mut dot = String::new();
+    dot.push_str("digraph chain {\n");
+    dot.push_str("    rankdir=LR;\n");
+    dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    let known = main.iter().chain(stale.iter().map(|stale| &stale.block)).map(|block| block.hash.as_str()).collect::<HashSet<_>>();
+    let mut missing = vec![];
+    for (block, is_stale) in main.iter().map(|block| (block, false)).chain(stale.iter().map(|stale| (&stale.block, true))) {
+        let mut style = vec![];
+        if is_stale {
+            style.push("dashed");
+        }
+        if block.hash == tip {
+            style.push("bold");
+        }
+        let _ = writeln!(
+            dot,
+            "    {} [label=\"{}\\n{}\\n{}\"{}];",
+            quote(&block.hash),
+            block.id,
+            escape(short_hash(&block.hash)),
+            escape(&block.miner),
+            match style.is_empty() {
+                true => String::new(),
+                false => format!(", style=\"{}\"", style.join(",")),
+            }
+        );
+        // The genesis block has no parent
+        if block.id == Height::GENESIS {
+            continue;
+        }
+        if !known.contains(block.prev_hash.as_str()) && !missing.contains(&&block.prev_hash) {
+            missing.push(&block.prev_hash);
+        }
+        let _ = writeln!(dot, "    {} -> {};", quote(&block.prev_hash), quote(&block.hash));
+    }
+    for hash in missing {
+        let _ = writeln!(dot, "    {} [label=\"{}\", style=\"dotted\"];", quote(hash), escape(short_hash(hash)));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn short_hash(hash: &str) -> &str {
+    match hash.char_indices().nth(LABEL_HASH_LENGTH) {
+        Some((end, _)) => &hash[..end],
+        None => hash,
+    }
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", escape(id))
+}
+
+// Hashes are hex, but miners and the hashes of received blocks are whatever the sender put there
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
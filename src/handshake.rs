@@ -0,0 +1,107 @@
+// Handshake between peers: right after a connection is established both peers send their Handshake
+// over the `/blockchain/handshake/1` protocol and answer the other's with their own, so each learns
+// the other's genesis block, handshake version, best block, features and node info before any other
+// traffic.
+// Gossip and direct messages of peers that did not complete the handshake are dropped, and peers
+// that are of another chain, speak an incompatible version, do not support the protocol or do not
+// answer within HANDSHAKE_TIMEOUT are disconnected (see p2p.rs).
+use crate::blockchain::{Chain, Checkpoint};
+use crate::config::Config;
+use crate::gc::StalePruning;
+use crate::nodeinfo::NodeInfo;
+use crate::propagation::Propagation;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Peers with another handshake version are disconnected. Version 2 added the node info.
+pub const HANDSHAKE_VERSION: u32 = 2;
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Bit set of the optional features of a peer, unknown bits are ignored
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Features(pub u64);
+
+impl Features {
+    // Keeps stale blocks (`--archival`), see gc.rs
+    pub const ARCHIVAL: Features = Features(1);
+    // Serves snapshots of its chain for fast sync, see fastsync.rs
+    pub const SNAPSHOTS: Features = Features(1 << 1);
+    // Announces the blocks it mines instead of gossiping them (`--propagation announce`), see propagation.rs
+    pub const ANNOUNCEMENTS: Features = Features(1 << 2);
+    // Serves its complete chain with the full blocks from the genesis block on
+    pub const BODIES: Features = Features(1 << 3);
+    // Can prove the state after its blocks (`state prove`), see state.rs
+    pub const STATE_PROOFS: Features = Features(1 << 4);
+
+    const NAMES: [(Features, &'static str); 5] = [
+        (Features::ARCHIVAL, "archival"),
+        (Features::SNAPSHOTS, "snapshots"),
+        (Features::ANNOUNCEMENTS, "announcements"),
+        (Features::BODIES, "bodies"),
+        (Features::STATE_PROOFS, "state-proofs"),
+    ];
+
+    // The features a node gets from its config
+    pub fn of(config: &Config) -> Self {
+        let mut features = Features::default();
+        if config.stale_pruning == StalePruning::Off {
+            features = features.with(Features::ARCHIVAL);
+        }
+        if config.propagation == Propagation::Announce {
+            features = features.with(Features::ANNOUNCEMENTS);
+        }
+        features
+    }
+
+    // The features a node gets from its chain. A chain restored from a snapshot lacks the blocks
+    // below its base, so it can only serve the blocks above it: no complete chains, no snapshots
+    // and no state.
+    pub fn of_chain(chain: &Chain) -> Self {
+        match chain.base {
+            Some(_) => Features::default(),
+            None => Features::BODIES.with(Features::SNAPSHOTS).with(Features::STATE_PROOFS),
+        }
+    }
+
+    pub fn with(self, features: Features) -> Self {
+        Features(self.0 | features.0)
+    }
+
+    pub fn contains(self, features: Features) -> bool {
+        self.0 & features.0 == features.0
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Features::NAMES.iter().filter(|(feature, _)| self.contains(*feature)).map(|(_, name)| *name).collect()
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Handshake {
+    pub version: u32,
+    pub genesis_hash: String,
+    // Our latest block, the chain with the highest one is the best chain
+    pub best_block: Checkpoint,
+    pub features: Features,
+    // Who the peer is and what it runs, for the inventory of a fleet, see nodeinfo.rs
+    pub node: NodeInfo,
+}
+
+impl Handshake {
+    pub fn new(genesis_hash: String, best_block: Checkpoint, features: Features) -> Self {
+        Self { version: HANDSHAKE_VERSION, genesis_hash, best_block, features, node: NodeInfo::default() }
+    }
+
+    // Why we can not peer with the sender of the handshake, if we can not
+    pub fn mismatch(&self, ours: &Handshake) -> Option<String> {
+        if self.version != ours.version {
+            return Some(format!("handshake version {}, ours is {}", self.version, ours.version));
+        }
+        if self.genesis_hash != ours.genesis_hash {
+            return Some(format!("genesis block {}, ours is {}", self.genesis_hash, ours.genesis_hash));
+        }
+        None
+    }
+}
@@ -0,0 +1,207 @@
+// Hierarchical deterministic wallet (`wallet new`, `wallet restore`): all wallet keys are derived from
+// one seed, so backing up its mnemonic is enough to regenerate every address. The mnemonic encodes
+// 16 random bytes and a checksum byte as one word per byte. The words are generated from syllables
+// instead of taken from the BIP39 list, so mnemonics are not interchangeable with other wallets.
+// The seed is stretched from the mnemonic like in BIP39 and keys are derived along hardened paths
+// like in BIP32 for ed25519 (SLIP-0010), the address of a key being its hex encoded public key as
+// for the node's signing key (see keys.rs). The wallet is stored encrypted in the key directory.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use hmac::{Hmac, Mac};
+use libp2p::identity::ed25519;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+const ENTROPY_BYTES: usize = 16;
+const SEED_KDF_ROUNDS: u32 = 2048;
+const SEED_SALT: &str = "mnemonic";
+const MASTER_KEY_SALT: &[u8] = b"ed25519 seed";
+const HARDENED: u32 = 0x8000_0000;
+const FROM_OPTION: &str = "--from ";
+// A restore stops looking for used addresses after this many unused ones in a row
+pub const GAP_LIMIT: u32 = 20;
+const ONSETS: [&str; 16] = ["b", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v", "z"];
+const RIMES: [&str; 16] = ["a", "e", "i", "o", "u", "al", "en", "ir", "os", "um", "ax", "eb", "id", "ov", "uk", "ay"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HdWallet {
+    pub mnemonic: String,
+    // Addresses 0 up to this one (exclusive) were handed out or found in use
+    pub addresses: u32,
+}
+
+impl HdWallet {
+    pub fn generate() -> Self {
+        let mut entropy = [0u8; ENTROPY_BYTES];
+        rand::thread_rng().fill_bytes(&mut entropy);
+        Self { mnemonic: entropy_to_mnemonic(&entropy), addresses: 0 }
+    }
+
+    pub fn restore(mnemonic: &str) -> Result<Self, BlockchainError> {
+        let mnemonic = mnemonic.split_whitespace().collect::<Vec<_>>().join(" ");
+        mnemonic_to_entropy(&mnemonic)?;
+        Ok(Self { mnemonic, addresses: 0 })
+    }
+
+    // The master key and chain code. Stretching the seed takes a while, so derive several keys
+    // from one master key.
+    fn master(&self) -> ([u8; 32], [u8; 32]) {
+        let mut seed = [0u8; 64];
+        pbkdf2::pbkdf2::<Hmac<Sha512>>(self.mnemonic.as_bytes(), SEED_SALT.as_bytes(), SEED_KDF_ROUNDS, &mut seed);
+        split(hmac_sha512(MASTER_KEY_SALT, &[&seed]))
+    }
+
+    // The key at the path of hardened indexes below the master key
+    pub fn derive(&self, path: &[u32]) -> ed25519::Keypair {
+        derive(self.master(), path)
+    }
+
+    pub fn keypair(&self, index: u32) -> ed25519::Keypair {
+        self.derive(&[0, index])
+    }
+
+    // The keys of the addresses handed out
+    pub fn keypairs(&self) -> Vec<ed25519::Keypair> {
+        let master = self.master();
+        (0..self.addresses).map(|index| derive(master, &[0, index])).collect()
+    }
+
+    pub fn address(&self, index: u32) -> String {
+        hex::encode(self.keypair(index).public().encode())
+    }
+
+    // Hands out the next address
+    pub fn next_address(&mut self) -> (u32, String) {
+        let index = self.addresses;
+        self.addresses += 1;
+        (index, self.address(index))
+    }
+
+    // The key of an address handed out before
+    pub fn find_keypair(&self, address: &str) -> Option<ed25519::Keypair> {
+        self.keypairs().into_iter().find(|keypair| hex::encode(keypair.public().encode()) == address)
+    }
+
+    // Looks for used addresses until GAP_LIMIT unused ones in a row and returns them with their
+    // balances. Addresses up to the last used one are handed out afterwards.
+    pub async fn rescan(&mut self, storage: &mut Storage) -> Result<Vec<(u32, String, Balance)>, BlockchainError> {
+        let master = self.master();
+        let mut used = vec![];
+        let mut unused = 0;
+        let mut index = 0;
+        while unused < GAP_LIMIT {
+            let address = hex::encode(derive(master, &[0, index]).public().encode());
+            let blocks = Chain::get_address_blocks(storage, &address).await?;
+            if blocks.is_empty() {
+                unused += 1;
+            } else {
+                unused = 0;
+                self.addresses = self.addresses.max(index + 1);
+                used.push((index, address.clone(), balance(&address, &blocks)));
+            }
+            index += 1;
+        }
+        Ok(used)
+    }
+}
+
+// Splits "[--from INDEX] REST" into the index of the wallet address (None without the option) and the rest
+pub fn split_from(args: &str) -> Result<(Option<u32>, &str), BlockchainError> {
+    let rest = match args.strip_prefix(FROM_OPTION) {
+        Some(rest) => rest.trim_start(),
+        None => return Ok((None, args)),
+    };
+    let (index, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let index = index.parse::<u32>().map_err(|_| BlockchainError::Error("--from requires the index of a wallet address".to_owned()))?;
+    Ok((Some(index), rest))
+}
+
+// Derivation path of the address, for display
+pub fn derivation_path(index: u32) -> String {
+    format!("m/0'/{}'", index)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+    pub received: u64,
+    // Including the fees
+    pub sent: u64,
+    pub transactions: u64,
+}
+
+impl Balance {
+    // Amounts are not checked against balances yet, so more can have been sent than received
+    pub fn available(&self) -> u64 {
+        self.received.saturating_sub(self.sent)
+    }
+}
+
+// What the transactions among the blocks, their coinbases included, moved to and from the address
+pub fn balance(address: &str, blocks: &[Block]) -> Balance {
+    let transactions = blocks.iter().flat_map(|block| block.coinbase.clone().into_iter().chain(block.transactions().unwrap_or_default()));
+    transactions.fold(Balance::default(), |mut balance, transaction| {
+        if transaction.recipient == address {
+            balance.received = balance.received.saturating_add(transaction.amount);
+        }
+        if transaction.sender == address {
+            balance.sent = balance.sent.saturating_add(transaction.amount).saturating_add(transaction.fee);
+        }
+        if transaction.recipient == address || transaction.sender == address {
+            balance.transactions += 1;
+        }
+        balance
+    })
+}
+
+pub fn entropy_to_mnemonic(entropy: &[u8]) -> String {
+    let checksum = Sha256::digest(entropy)[0];
+    entropy
+        .iter()
+        .chain([checksum].iter())
+        .map(|byte| format!("{}{}", ONSETS[(byte >> 4) as usize], RIMES[(byte & 0x0f) as usize]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn mnemonic_to_entropy(mnemonic: &str) -> Result<Vec<u8>, BlockchainError> {
+    let mut bytes = mnemonic.split_whitespace().map(decode_word).collect::<Result<Vec<u8>, _>>()?;
+    if bytes.len() != ENTROPY_BYTES + 1 {
+        return Err(BlockchainError::Error(format!("a mnemonic has {} words", ENTROPY_BYTES + 1)));
+    }
+    let checksum = bytes.pop();
+    if checksum != Some(Sha256::digest(&bytes)[0]) {
+        return Err(BlockchainError::Error("invalid mnemonic checksum, is a word misspelled?".to_owned()));
+    }
+    Ok(bytes)
+}
+
+fn decode_word(word: &str) -> Result<u8, BlockchainError> {
+    let (onset, rime) = (word.get(..1).unwrap_or_default(), word.get(1..).unwrap_or_default());
+    match (ONSETS.iter().position(|kept| *kept == onset), RIMES.iter().position(|kept| *kept == rime)) {
+        (Some(high), Some(low)) => Ok(((high as u8) << 4) | low as u8),
+        _ => Err(BlockchainError::Error(format!("unknown mnemonic word: {}", word))),
+    }
+}
+
+fn derive((mut key, mut chain_code): ([u8; 32], [u8; 32]), path: &[u32]) -> ed25519::Keypair {
+    for index in path {
+        (key, chain_code) = split(hmac_sha512(&chain_code, &[&[0], &key, &(index | HARDENED).to_be_bytes()]));
+    }
+    let secret = ed25519::SecretKey::from_bytes(&mut key).expect("32 bytes are a valid ed25519 secret");
+    ed25519::Keypair::from(secret)
+}
+
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+// Into the key and the chain code
+fn split(bytes: [u8; 64]) -> ([u8; 32], [u8; 32]) {
+    let (key, chain_code) = bytes.split_at(32);
+    (key.try_into().expect("32 bytes"), chain_code.try_into().expect("32 bytes"))
+}
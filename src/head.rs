@@ -0,0 +1,102 @@
+// Chain head watcher: applications tracking our main chain (indexers, bridges, the event stream)
+// subscribe to the changes of its head instead of polling the latest block. A new head on top of
+// the previous one is reported block by block as Extended. When the head moved to another branch,
+// Reorged reports how many blocks of the old branch are gone (depth, counted from the old tip down to
+// the last block both branches share) and the blocks of the new branch follow as Extended, so
+// subscribers can roll their own state back and apply the new blocks in order.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use crate::types::Height;
+use futures::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use tokio::sync::broadcast;
+
+// Number of head events buffered for slow subscribers
+pub const HEAD_BUFFER: usize = 1_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadEvent {
+    Extended(Block),
+    Reorged { old_tip: Block, new_tip: Block, depth: u64 },
+}
+
+// A subscriber falling more than HEAD_BUFFER events behind missed changes it can not reconstruct,
+// its stream ends and it has to subscribe again and resync
+pub type HeadStream = Pin<Box<dyn Stream<Item = HeadEvent> + Send>>;
+
+pub struct HeadWatcher {
+    sender: broadcast::Sender<HeadEvent>,
+    // Height and hash of the blocks of the branch last reported, from our finalized block up to its
+    // tip. Reorgs never go below the finalized block, so the fork point is among them.
+    branch: VecDeque<(Height, String)>,
+    tip: Block,
+}
+
+impl HeadWatcher {
+    pub async fn new(chain: &Chain, storage: &mut Storage) -> Result<Self, BlockchainError> {
+        let (sender, _) = broadcast::channel(HEAD_BUFFER);
+        let mut branch = VecDeque::new();
+        for id in chain.finalized.id.0..=chain.latest_block.id.0 {
+            let block = Chain::get_block_by_id(storage, Height(id)).await?;
+            branch.push_back((block.id, block.hash));
+        }
+        Ok(Self { sender, branch, tip: chain.latest_block.clone() })
+    }
+
+    pub fn subscribe_head(&self) -> HeadStream {
+        Box::pin(stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            receiver.recv().await.ok().map(|event| (event, receiver))
+        }))
+    }
+
+    // Compares the head of the chain with the one last reported and sends the changes to the
+    // subscribers. Returns the changes, oldest first.
+    pub async fn update(&mut self, chain: &Chain, storage: &mut Storage) -> Result<Vec<HeadEvent>, BlockchainError> {
+        let new_tip = &chain.latest_block;
+        if new_tip.hash == self.tip.hash {
+            return Ok(vec![]);
+        }
+
+        // The highest block of the reported branch that is still part of the chain
+        let oldest = self.branch.front().map_or(self.tip.id, |(height, _)| *height);
+        let mut fork = None;
+        while let Some((height, hash)) = self.branch.back() {
+            if *height <= new_tip.id && Chain::get_block_by_id(storage, *height).await.is_ok_and(|block| block.hash == *hash) {
+                fork = Some(*height);
+                break;
+            }
+            self.branch.pop_back();
+        }
+
+        let mut events = vec![];
+        let first_new = match fork {
+            Some(height) if height == self.tip.id => height + 1,
+            Some(height) => {
+                events.push(HeadEvent::Reorged { old_tip: self.tip.clone(), new_tip: new_tip.clone(), depth: self.tip.id.0 - height.0 });
+                height + 1
+            }
+            // Nothing we reported is left, e.g. after the chain was restored from a snapshot
+            None => {
+                let depth = self.tip.id.0 + 1 - oldest.0;
+                events.push(HeadEvent::Reorged { old_tip: self.tip.clone(), new_tip: new_tip.clone(), depth });
+                chain.first_block().id
+            }
+        };
+        for id in first_new.0..=new_tip.id.0 {
+            let block = Chain::get_block_by_id(storage, Height(id)).await?;
+            self.branch.push_back((block.id, block.hash.clone()));
+            events.push(HeadEvent::Extended(block));
+        }
+        while self.branch.front().is_some_and(|(height, _)| *height < chain.finalized.id) {
+            self.branch.pop_front();
+        }
+        self.tip = new_tip.clone();
+
+        for event in &events {
+            // No subscribers is no error
+            let _ = self.sender.send(event.clone());
+        }
+        Ok(events)
+    }
+}
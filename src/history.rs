@@ -0,0 +1,179 @@
+// Wallet transaction history (`wallet history [--export csv [FILE]]`): the confirmed transactions
+// touching any address we hold a key for, i.e. the signing key and the addresses the HD wallet
+// handed out (see keys.rs and hdwallet.rs), oldest first with the balance after each of them and
+// their confirmations. A transfer between two of our addresses is listed once and only costs its fee.
+// Like the balances of watch-only addresses (see watch.rs) the history is looked up in the address
+// index when it is shown, so new blocks and reorgs are reflected without tracking anything. Amounts
+// are not checked against balances for transactions without inputs, so the running balance can go
+// negative. The CSV export has one row per transaction for accounting tools.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use crate::types::Height;
+use chrono::{TimeZone, Utc};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+pub const CSV_HEADER: &str = "height,time,block_hash,txid,direction,counterparty,amount,fee,change,balance,confirmations";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+    // Between two of our addresses
+    Internal,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::In => write!(f, "in"),
+            Direction::Out => write!(f, "out"),
+            Direction::Internal => write!(f, "internal"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub height: Height,
+    // Milliseconds since the epoch, the timestamp of the block
+    pub timestamp: i64,
+    pub block_hash: String,
+    pub txid: String,
+    pub direction: Direction,
+    // The recipient of outgoing and internal transfers, the sender of incoming ones
+    pub counterparty: String,
+    pub amount: u64,
+    pub fee: u64,
+    // What the transaction changed our balance by, and the balance after it
+    pub change: i64,
+    pub balance: i64,
+    pub confirmations: u64,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "height: {} | {} | txid: {} | {}: {} | fee: {} | {:+} | balance: {} | confirmations: {}",
+            self.height,
+            format_time(self.timestamp),
+            self.txid,
+            self.direction,
+            self.counterparty,
+            self.amount,
+            self.change,
+            self.balance,
+            self.confirmations
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryConfig {
+    // CSV is the only format
+    pub export: bool,
+    // Printed if None
+    pub path: Option<PathBuf>,
+}
+
+impl HistoryConfig {
+    // Parses "[--export csv [FILE]]"
+    pub fn from_args(args: &str) -> Result<Self, BlockchainError> {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next(), args.next()) {
+            (None, ..) => Ok(Self::default()),
+            (Some("--export"), Some("csv"), path, None) => Ok(Self { export: true, path: path.map(PathBuf::from) }),
+            (Some("--export"), Some(format), ..) if format != "csv" => Err(BlockchainError::Error(format!("unsupported export format: {}", format))),
+            _ => Err(BlockchainError::Error("usage: wallet history [--export csv [FILE]]".to_owned())),
+        }
+    }
+}
+
+// The entries of the transactions in the blocks touching the addresses, blocks may be listed more
+// than once and in any order
+pub fn entries(addresses: &HashSet<String>, blocks: &[Block], latest: Height) -> Vec<HistoryEntry> {
+    let blocks = blocks.iter().map(|block| (block.id, block)).collect::<BTreeMap<_, _>>();
+    let mut balance: i64 = 0;
+    let mut entries = vec![];
+    for block in blocks.values() {
+        for transaction in block.transactions().unwrap_or_default() {
+            let (sent, received) = (addresses.contains(&transaction.sender), addresses.contains(&transaction.recipient));
+            let (direction, counterparty) = match (sent, received) {
+                (false, false) => continue,
+                (true, true) => (Direction::Internal, &transaction.recipient),
+                (true, false) => (Direction::Out, &transaction.recipient),
+                (false, true) => (Direction::In, &transaction.sender),
+            };
+            let mut change: i64 = 0;
+            if received {
+                change = change.saturating_add(i64::try_from(transaction.amount).unwrap_or(i64::MAX));
+            }
+            if sent {
+                change = change.saturating_sub(i64::try_from(transaction.amount.saturating_add(transaction.fee)).unwrap_or(i64::MAX));
+            }
+            balance = balance.saturating_add(change);
+            entries.push(HistoryEntry {
+                height: block.id,
+                timestamp: block.timestamp_millis(),
+                block_hash: block.hash.clone(),
+                txid: transaction.id(),
+                direction,
+                counterparty: counterparty.clone(),
+                amount: transaction.amount,
+                fee: transaction.fee,
+                change,
+                balance,
+                confirmations: latest.0.saturating_sub(block.id.0),
+            });
+        }
+    }
+    entries
+}
+
+// The history of the addresses on our main chain
+pub async fn wallet_history(chain: &Chain, storage: &mut Storage, addresses: &[String]) -> Result<Vec<HistoryEntry>, BlockchainError> {
+    let mut blocks = vec![];
+    for address in addresses.iter() {
+        blocks.extend(Chain::get_address_blocks(storage, address).await?);
+    }
+    let addresses = addresses.iter().cloned().collect::<HashSet<_>>();
+    Ok(entries(&addresses, &blocks, chain.latest_block.id))
+}
+
+// The header and one row per entry
+pub fn to_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for entry in entries.iter() {
+        let row = [
+            entry.height.to_string(),
+            format_time(entry.timestamp),
+            entry.block_hash.clone(),
+            entry.txid.clone(),
+            entry.direction.to_string(),
+            csv_field(&entry.counterparty),
+            entry.amount.to_string(),
+            entry.fee.to_string(),
+            entry.change.to_string(),
+            entry.balance.to_string(),
+            entry.confirmations.to_string(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// Recipients are arbitrary strings, fields with separators or quotes are quoted
+fn csv_field(value: &str) -> String {
+    match value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_owned(),
+    }
+}
+
+// RFC 3339 in UTC
+fn format_time(timestamp: i64) -> String {
+    Utc.timestamp_millis_opt(timestamp).single().map_or_else(|| timestamp.to_string(), |time| time.to_rfc3339())
+}
@@ -0,0 +1,64 @@
+// Block template hooks: applications embedding the node can register hooks on a Chain
+// (`chain.template_hooks.add(NAME, HOOK)`) that see every block we assemble right before hashing
+// starts, for blocks mined by us as well as the templates handed out to pool workers. A hook may
+// change the data and the miner field (e.g. inject a payload or tag the block with metadata) or
+// veto mining the block by returning an error. The height, parent, difficulty and version are fixed
+// by consensus, a hook changing them vetoes the block too. Hooks run in the order they were added.
+use crate::blockchain::{Block, BlockchainError};
+use std::fmt;
+use std::sync::Arc;
+
+pub trait TemplateHook: Send + Sync {
+    // Adjusts the template, an error vetoes mining it
+    fn prepare(&self, template: &mut Block) -> Result<(), String>;
+}
+
+impl<F> TemplateHook for F
+where
+    F: Fn(&mut Block) -> Result<(), String> + Send + Sync,
+{
+    fn prepare(&self, template: &mut Block) -> Result<(), String> {
+        self(template)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TemplateHooks {
+    hooks: Vec<(String, Arc<dyn TemplateHook>)>,
+}
+
+impl fmt::Debug for TemplateHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl TemplateHooks {
+    pub fn add<H: TemplateHook + 'static>(&mut self, name: &str, hook: H) {
+        self.hooks.push((name.to_owned(), Arc::new(hook)));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.hooks.retain(|(hook_name, _)| hook_name != name);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.hooks.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    // Runs all hooks on the template, stopping at the first veto
+    pub fn apply(&self, template: &mut Block) -> Result<(), BlockchainError> {
+        for (name, hook) in &self.hooks {
+            let header = (template.id, template.prev_hash.clone(), template.difficulty, template.version);
+            hook.prepare(template).map_err(|reason| BlockchainError::MiningVetoed(format!("{}: {}", name, reason)))?;
+            if (template.id, template.prev_hash.clone(), template.difficulty, template.version) != header {
+                return Err(BlockchainError::MiningVetoed(format!("{}: changed the consensus fields of the block", name)));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+// Start-up integrity check: before the stored chain is served, every block is checked to sit at its
+// height, to link to its parent and to carry the hash of its header, and the genesis block has to be
+// ours (or the base, for a chain restored from a snapshot, see fastsync.rs). A corrupted chain is only repaired when asked to (`--repair truncate|resync` or
+// `chain check --repair ...`), otherwise the node refuses to start.
+use crate::blockchain::{self, Block, BlockchainError, Chain};
+use crate::storage::Storage;
+use crate::types::Height;
+
+pub const REPAIR_STRATEGIES: [&str; 2] = ["truncate", "resync"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairStrategy {
+    // Drop everything above the last valid block
+    Truncate,
+    // Truncate and ask our peers for their chain to fill the gap
+    Resync,
+}
+
+impl RepairStrategy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "truncate" => Some(Self::Truncate),
+            "resync" => Some(Self::Resync),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    // Height of the last block of the valid prefix, None if not even the genesis block is valid
+    pub last_valid: Option<Height>,
+    pub reason: String,
+}
+
+// Returns the first problem found from the genesis block up, or None if the chain is intact. An
+// empty chain is intact, the genesis block is added when the chain is initialized.
+pub async fn check(storage: &mut Storage, genesis: &Block) -> Result<Option<Corruption>, BlockchainError> {
+    let chain = Chain::get_chain(storage).await?;
+    let base = storage.get_base().await?;
+    let first = base.as_ref().map_or(Height::GENESIS, |base| base.id);
+    let mut parent: Option<&Block> = None;
+    for (offset, block) in chain.iter().enumerate() {
+        let height = first + offset as u64;
+        let reason = match parent {
+            _ if block.id != height => Some(format!("block at height {} is missing, found {} instead", height, block.id)),
+            None => match &base {
+                Some(base) => (block.hash != base.hash || blockchain::hasher(block) != block.hash)
+                    .then(|| format!("block {} is not the base of the snapshot", block.hash)),
+                None => (block != genesis).then(|| format!("genesis block {} is not ours", block.hash)),
+            },
+            Some(parent) if parent.hash != block.prev_hash => {
+                Some(format!("block {} does not link to block {}", block.hash, parent.hash))
+            }
+            Some(_) => (blockchain::hasher(block) != block.hash).then(|| format!("block {} does not match its header", block.hash)),
+        };
+        if let Some(reason) = reason {
+            return Ok(Some(Corruption { last_valid: parent.map(|parent| parent.id), reason }));
+        }
+        parent = Some(block);
+    }
+    Ok(None)
+}
+
+// Removes the blocks above the last valid one, or all blocks if not even the genesis block is valid.
+// A chain built before has to be reloaded, see Chain::reload.
+pub async fn truncate(storage: &mut Storage, corruption: &Corruption) -> Result<(), BlockchainError> {
+    match corruption.last_valid {
+        Some(height) => storage.remove_blocks_above(height).await,
+        None => storage.clear_blocks().await,
+    }
+}
@@ -0,0 +1,192 @@
+// Local control socket (`--ipc PATH`): the JSON-RPC requests of rpc.rs over a Unix domain socket, one
+// request or batch per line, so scripts on the same host can drive a running node without a TCP port.
+// The socket file is only accessible to the user running the node, which is all the access control
+// there is. A stale socket file of a previous run is replaced, the file is removed when the server
+// stops. The rust-blockchain-cli binary is the client:
+// cargo run --bin rust-blockchain-cli -- --connect PATH [METHOD [PARAMS]]
+// sends one request built from the method and its params (a JSON object or array) and prints the
+// result, without a method it sends the lines read from stdin, either such commands or raw requests.
+// Only available on Unix.
+use crate::blockchain::BlockchainError;
+use crate::rpc::{RpcReply, RpcRequest};
+use serde_json::Value;
+use std::path::PathBuf;
+
+// Connection names start with this, the rest counts the clients
+pub const IPC_PREFIX: &str = "ipc/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub socket: PathBuf,
+    // "METHOD [PARAMS]", read from stdin if None
+    pub command: Option<String>,
+}
+
+impl ClientConfig {
+    // Expects the arguments without the program name: "--connect PATH [METHOD [PARAMS]]"
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, BlockchainError> {
+        let usage = || BlockchainError::Error("usage: rust-blockchain-cli --connect PATH [METHOD [PARAMS]]".to_owned());
+        if args.next().as_deref() != Some("--connect") {
+            return Err(usage());
+        }
+        let socket = PathBuf::from(args.next().ok_or_else(usage)?);
+        let command = args.collect::<Vec<_>>().join(" ");
+        Ok(Self { socket, command: Some(command).filter(|command| !command.is_empty()) })
+    }
+}
+
+// The request line for "METHOD [PARAMS]", lines starting with { or [ are requests already and sent as
+// they are
+pub fn request_line(command: &str, id: u64) -> Result<String, BlockchainError> {
+    let command = command.trim();
+    if command.starts_with('{') || command.starts_with('[') {
+        return Ok(command.to_owned());
+    }
+    let (method, params) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    if method.is_empty() {
+        return Err(BlockchainError::Error("a command starts with the method".to_owned()));
+    }
+    let params = match params.trim() {
+        "" => Value::Null,
+        params => serde_json::from_str::<Value>(params).map_err(|err| BlockchainError::Error(format!("params are no JSON: {}", err)))?,
+    };
+    let request = RpcRequest { jsonrpc: "2.0".to_owned(), method: method.to_owned(), params, id: Value::from(id) };
+    Ok(serde_json::to_string(&request).expect("can jsonify request"))
+}
+
+// Whether the reply holds an error, for the exit code of the client
+pub fn is_error(reply: &RpcReply) -> bool {
+    match reply {
+        RpcReply::Single(response) => response.error.is_some(),
+        RpcReply::Batch(responses) => responses.iter().any(|response| response.error.is_some()),
+    }
+}
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(not(unix))]
+pub struct IpcListener;
+
+#[cfg(not(unix))]
+pub fn bind(_path: &std::path::Path) -> std::io::Result<IpcListener> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "--ipc requires Unix domain sockets"))
+}
+
+#[cfg(not(unix))]
+pub async fn init_ipc(
+    _listener: IpcListener,
+    _rx_rcv: tokio::sync::mpsc::UnboundedReceiver<crate::types::EventType>,
+    _main_sender: tokio::sync::mpsc::UnboundedSender<crate::types::EventType>,
+    _head_sender: tokio::sync::broadcast::Sender<crate::head::HeadEvent>,
+) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::IPC_PREFIX;
+    use crate::head::HeadEvent;
+    use crate::rpc::{self, RpcReply};
+    use crate::types::EventType;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use tokio::io::{self, AsyncWriteExt, BufReader};
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::sync::{broadcast, mpsc};
+    use tracing::{debug, info};
+
+    // Removes the socket file when dropped
+    pub struct IpcListener {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Drop for IpcListener {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    // Files that are no socket are left alone, so a typo does not delete them
+    pub fn bind(path: &Path) -> io::Result<IpcListener> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is no socket", path.display()))),
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        Ok(IpcListener { listener, path: path.to_owned() })
+    }
+
+    pub async fn init_ipc(
+        listener: IpcListener,
+        mut rx_rcv: mpsc::UnboundedReceiver<EventType>,
+        main_sender: mpsc::UnboundedSender<EventType>,
+        head_sender: broadcast::Sender<HeadEvent>,
+    ) -> Result<(), std::io::Error> {
+        println!("IPC listening on {}", listener.path.display());
+        let mut connections: HashMap<String, mpsc::UnboundedSender<RpcReply>> = HashMap::new();
+        let (closed_sender, mut closed_rcv) = mpsc::unbounded_channel::<String>();
+        let mut clients: u64 = 0;
+        loop {
+            tokio::select! {
+                accepted = listener.listener.accept() => {
+                    let (stream, _) = accepted?;
+                    clients += 1;
+                    let name = format!("{}{}", IPC_PREFIX, clients);
+                    info!("IPC client {} connected", name);
+                    let (sender, rcv) = mpsc::unbounded_channel();
+                    connections.insert(name.clone(), sender);
+                    let (reader, writer) = stream.into_split();
+                    tokio::spawn(rpc::handle_connection(reader, writer, name, rcv, head_sender.subscribe(), main_sender.clone(), closed_sender.clone()));
+                },
+                Some(name) = closed_rcv.recv() => {
+                    info!("IPC client {} disconnected", name);
+                    connections.remove(&name);
+                },
+                event = rx_rcv.recv() => match event {
+                    Some(EventType::SendRpcReply{receiver, reply}) => {
+                        match connections.get(&receiver) {
+                            Some(connection) => {
+                                let _ = connection.send(reply);
+                            }
+                            None => debug!("IPC client {} is not connected", receiver),
+                        }
+                    },
+                    Some(_) => {},
+                    None => {
+                        debug!("ipc channel closed.");
+                        return Ok(());
+                    },
+                },
+            }
+        }
+    }
+
+    // A connection to the control socket of a node
+    pub struct IpcClient {
+        reader: BufReader<OwnedReadHalf>,
+        buffer: Vec<u8>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl IpcClient {
+        pub async fn connect(path: &Path) -> io::Result<Self> {
+            let (reader, writer) = UnixStream::connect(path).await?.into_split();
+            Ok(Self { reader: BufReader::new(reader), buffer: vec![], writer })
+        }
+
+        // Sends the request line and waits for the reply line, at most rpc::MAX_REPLY_LENGTH bytes
+        pub async fn call(&mut self, line: &str) -> io::Result<String> {
+            self.writer.write_all(format!("{}\n", line).as_bytes()).await?;
+            rpc::read_line(&mut self.reader, &mut self.buffer, rpc::MAX_REPLY_LENGTH)
+                .await?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the node closed the connection"))
+        }
+    }
+}
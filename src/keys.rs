@@ -0,0 +1,294 @@
+// Node keys (`--keys DIR`): the p2p identity (our peer ID) and the signing key of the node's wallet
+// are separate ed25519 keys, so the signing key can be replaced without changing the peer ID. The
+// X25519 key others encrypt block payloads to (see payload.rs) is a third one. The HD wallet (see
+// hdwallet.rs), the watch-only addresses (see watch.rs) and the key the storage is encrypted with at
+// rest (see atrest.rs) are kept next to them. All of them are stored in DIR, encrypted with a key
+// derived from the passphrase in KEY_PASSPHRASE_ENV.
+// A new signing key is announced on-chain with a KeyRotation, signed by both the old and the new
+// key, so others can follow a long-lived key to its replacement.
+use crate::blockchain::BlockchainError;
+use crate::hdwallet::HdWallet;
+use crate::payload;
+use crate::watch::WatchList;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use hmac::Hmac;
+use libp2p::identity::{self, ed25519};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs;
+use std::path::{Path, PathBuf};
+use x25519_dalek::StaticSecret;
+
+pub const KEY_PASSPHRASE_ENV: &str = "BLOCKCHAIN_KEY_PASSPHRASE";
+// Block data of key rotation announcements starts with this, followed by the JSON encoded KeyRotation
+pub const KEY_ROTATION_PREFIX: &str = "key-rotation ";
+const IDENTITY_FILE: &str = "identity.key";
+const SIGNING_KEY_FILE: &str = "signing.key";
+const ENCRYPTION_KEY_FILE: &str = "encryption.key";
+const WALLET_FILE: &str = "wallet.key";
+const WATCH_LIST_FILE: &str = "watch.key";
+const STORAGE_KEY_FILE: &str = "storage.key";
+// Rotated signing keys are kept in this subdirectory
+const RETIRED_DIR: &str = "retired";
+const KDF: &str = "pbkdf2-sha256";
+const KDF_ROUNDS: u32 = 100_000;
+// Rounds accepted from a key file: fewer give up the brute-force protection, more would stall the
+// start-up for minutes
+const MIN_KDF_ROUNDS: u32 = 10_000;
+const MAX_KDF_ROUNDS: u32 = 10_000_000;
+
+// File format of an encrypted key, all binary fields hex encoded
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedKey {
+    kdf: String,
+    rounds: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+// PBKDF2-HMAC-SHA256 with a single output block
+pub fn derive_key(passphrase: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+pub fn encrypt_key(secret: &[u8], passphrase: &str) -> String {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let key = derive_key(passphrase, &salt, KDF_ROUNDS);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(chacha20poly1305::Nonce::from_slice(&nonce), secret)
+        .expect("can encrypt key");
+    let encrypted = EncryptedKey {
+        kdf: KDF.to_owned(),
+        rounds: KDF_ROUNDS,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    };
+    serde_json::to_string_pretty(&encrypted).expect("can jsonify key")
+}
+
+pub fn decrypt_key(json: &str, passphrase: &str) -> Result<Vec<u8>, BlockchainError> {
+    let invalid = |err: String| BlockchainError::Error(format!("invalid key file: {}", err));
+    let encrypted: EncryptedKey = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+    if encrypted.kdf != KDF {
+        return Err(invalid(format!("unknown kdf {}", encrypted.kdf)));
+    }
+    let salt = hex::decode(&encrypted.salt).map_err(|err| invalid(err.to_string()))?;
+    let nonce = hex::decode(&encrypted.nonce).map_err(|err| invalid(err.to_string()))?;
+    let ciphertext = hex::decode(&encrypted.ciphertext).map_err(|err| invalid(err.to_string()))?;
+    if nonce.len() != 12 {
+        return Err(invalid("nonce has to be 12 bytes".to_owned()));
+    }
+    if !(MIN_KDF_ROUNDS..=MAX_KDF_ROUNDS).contains(&encrypted.rounds) {
+        return Err(invalid(format!("{} kdf rounds, expected {} to {}", encrypted.rounds, MIN_KDF_ROUNDS, MAX_KDF_ROUNDS)));
+    }
+    let key = derive_key(passphrase, &salt, encrypted.rounds);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(chacha20poly1305::Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| BlockchainError::Error("can not decrypt key, wrong passphrase?".to_owned()))
+}
+
+pub struct KeyStore {
+    dir: PathBuf,
+    passphrase: String,
+}
+
+impl KeyStore {
+    pub fn open(dir: &Path, passphrase: String) -> Result<Self, BlockchainError> {
+        fs::create_dir_all(dir.join(RETIRED_DIR))?;
+        Ok(Self { dir: dir.to_owned(), passphrase })
+    }
+
+    // The p2p identity, created on first use
+    pub fn identity(&self) -> Result<identity::Keypair, BlockchainError> {
+        Ok(identity::Keypair::Ed25519(self.load_or_create_keypair(&self.dir.join(IDENTITY_FILE))?))
+    }
+
+    // The current signing key, created on first use
+    pub fn signing_key(&self) -> Result<ed25519::Keypair, BlockchainError> {
+        self.load_or_create_keypair(&self.dir.join(SIGNING_KEY_FILE))
+    }
+
+    // The key payloads are encrypted to, created on first use
+    pub fn encryption_key(&self) -> Result<StaticSecret, BlockchainError> {
+        let path = self.dir.join(ENCRYPTION_KEY_FILE);
+        let secret: [u8; 32] = match self.read(&path)? {
+            Some(secret) => secret
+                .try_into()
+                .map_err(|_| BlockchainError::Error(format!("invalid key in {}", path.display())))?,
+            None => {
+                let secret = payload::generate_secret().to_bytes();
+                self.write(&path, &secret)?;
+                secret
+            }
+        };
+        Ok(StaticSecret::from(secret))
+    }
+
+    // The key the storage is encrypted with at rest (see atrest.rs), created on first use
+    pub fn storage_key(&self) -> Result<[u8; 32], BlockchainError> {
+        let path = self.dir.join(STORAGE_KEY_FILE);
+        match self.read(&path)? {
+            Some(key) => key.try_into().map_err(|_| BlockchainError::Error(format!("invalid key in {}", path.display()))),
+            None => {
+                let mut key = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                self.write(&path, &key)?;
+                Ok(key)
+            }
+        }
+    }
+
+    // None until a wallet was created or restored
+    pub fn wallet(&self) -> Result<Option<HdWallet>, BlockchainError> {
+        match self.read(&self.dir.join(WALLET_FILE))? {
+            Some(json) => serde_json::from_slice(&json)
+                .map(Some)
+                .map_err(|err| BlockchainError::Error(format!("invalid wallet: {}", err))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_wallet(&self, wallet: &HdWallet) -> Result<(), BlockchainError> {
+        self.write(&self.dir.join(WALLET_FILE), &serde_json::to_vec(wallet).expect("can jsonify wallet"))
+    }
+
+    // Empty until an address was watched
+    pub fn watch_list(&self) -> Result<WatchList, BlockchainError> {
+        match self.read(&self.dir.join(WATCH_LIST_FILE))? {
+            Some(json) => serde_json::from_slice(&json).map_err(|err| BlockchainError::Error(format!("invalid watch list: {}", err))),
+            None => Ok(WatchList::default()),
+        }
+    }
+
+    pub fn save_watch_list(&self, watch_list: &WatchList) -> Result<(), BlockchainError> {
+        self.write(&self.dir.join(WATCH_LIST_FILE), &serde_json::to_vec(watch_list).expect("can jsonify watch list"))
+    }
+
+    // The keys we can sign transactions with: the signing key and the keys of the addresses the
+    // wallet handed out
+    pub fn transaction_keys(&self) -> Result<Vec<ed25519::Keypair>, BlockchainError> {
+        let mut keys = vec![self.signing_key()?];
+        if let Some(wallet) = self.wallet()? {
+            keys.extend(wallet.keypairs());
+        }
+        Ok(keys)
+    }
+
+    // Replaces the signing key and returns the announcement of the new one. The old key is kept in
+    // the retired directory.
+    pub fn rotate_signing_key(&self) -> Result<KeyRotation, BlockchainError> {
+        let old = self.signing_key()?;
+        let new = ed25519::Keypair::generate();
+        let retired = self.dir.join(RETIRED_DIR).join(format!("{}.key", hex::encode(old.public().encode())));
+        fs::copy(self.dir.join(SIGNING_KEY_FILE), retired)?;
+        self.write(&self.dir.join(SIGNING_KEY_FILE), &new.encode())?;
+        Ok(KeyRotation::new(&old, &new))
+    }
+
+    fn load_or_create_keypair(&self, path: &Path) -> Result<ed25519::Keypair, BlockchainError> {
+        match self.read(path)? {
+            Some(mut secret) => ed25519::Keypair::decode(&mut secret)
+                .map_err(|err| BlockchainError::Error(format!("invalid key in {}: {}", path.display(), err))),
+            None => {
+                let keypair = ed25519::Keypair::generate();
+                self.write(path, &keypair.encode())?;
+                Ok(keypair)
+            }
+        }
+    }
+
+    // None if there is no key yet
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>, BlockchainError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        decrypt_key(&fs::read_to_string(path)?, &self.passphrase).map(Some)
+    }
+
+    // Written to a temporary file first, so a crash never leaves us with half a key
+    fn write(&self, path: &Path, secret: &[u8]) -> Result<(), BlockchainError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, encrypt_key(secret, &self.passphrase))?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+// Announces that new_key replaces old_key, both keys hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub old_key: String,
+    pub new_key: String,
+    // Signatures of the rotation message by the old key (authorizing it) and the new key (proving
+    // it is held by the same owner)
+    pub old_signature: String,
+    pub new_signature: String,
+}
+
+impl KeyRotation {
+    pub fn new(old: &ed25519::Keypair, new: &ed25519::Keypair) -> Self {
+        let (old_key, new_key) = (hex::encode(old.public().encode()), hex::encode(new.public().encode()));
+        let message = Self::message(&old_key, &new_key);
+        Self {
+            old_signature: hex::encode(old.sign(&message)),
+            new_signature: hex::encode(new.sign(&message)),
+            old_key,
+            new_key,
+        }
+    }
+
+    fn message(old_key: &str, new_key: &str) -> Vec<u8> {
+        format!("rotate signing key {} to {}", old_key, new_key).into_bytes()
+    }
+
+    pub fn verify(&self) -> Result<(), BlockchainError> {
+        let message = Self::message(&self.old_key, &self.new_key);
+        for (key, signature) in [(&self.old_key, &self.old_signature), (&self.new_key, &self.new_signature)] {
+            let key = hex::decode(key)
+                .ok()
+                .and_then(|key| ed25519::PublicKey::decode(&key).ok())
+                .ok_or_else(|| BlockchainError::Error(format!("invalid key: {}", key)))?;
+            let signature = hex::decode(signature).unwrap_or_default();
+            if !key.verify(&message, &signature) {
+                return Err(BlockchainError::Error("invalid key rotation signature".to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_block_data(&self) -> String {
+        format!("{}{}", KEY_ROTATION_PREFIX, serde_json::to_string(self).expect("can jsonify key rotation"))
+    }
+
+    // None if the data is no key rotation announcement
+    pub fn from_block_data(data: &str) -> Option<Result<Self, BlockchainError>> {
+        let json = data.strip_prefix(KEY_ROTATION_PREFIX)?;
+        Some(serde_json::from_str(json).map_err(|err| BlockchainError::Error(format!("invalid key rotation: {}", err))))
+    }
+}
+
+// The signing key a rotation announcement retires, a key can only be rotated once (see mining.rs and
+// Chain::check_if_block_valid)
+pub fn rotated_key(data: &str) -> Option<String> {
+    match KeyRotation::from_block_data(data)? {
+        Ok(rotation) => Some(rotation.old_key),
+        Err(_) => None,
+    }
+}
+
+// Blocks announcing a key rotation have to carry a valid one
+pub fn check_block_data(data: &str) -> Result<(), BlockchainError> {
+    match KeyRotation::from_block_data(data) {
+        Some(rotation) => rotation?.verify(),
+        None => Ok(()),
+    }
+}
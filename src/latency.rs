@@ -0,0 +1,126 @@
+// Block latency metrics for tuning gossip and transport settings: the arrival latency of every block
+// we accept from gossip (our receive time minus the block's timestamp) and, for every block we mine,
+// the time until the receipt of the first peer comes back (every peer sends a receipt for the new
+// blocks it receives, see node.rs). `node latency` and the `getblocklatency` RPC method show the
+// percentiles over the last LATENCY_SAMPLES blocks of each kind. The block timestamp comes from the
+// miner's clock, so arrival latencies include the clock offset between the nodes; the first
+// acknowledgement is measured on our clock alone and includes the way back of the receipt.
+use crate::loadgen::LatencyStats;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+
+// Latencies per kind the percentiles are computed over
+pub const LATENCY_SAMPLES: usize = 1_000;
+// Mined blocks waiting for their first receipt, receipts for older ones are ignored
+const MAX_PENDING_BLOCKS: usize = 1_000;
+
+static LATENCY: Lazy<Mutex<LatencyTracker>> = Lazy::new(|| Mutex::new(LatencyTracker::default()));
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyReport {
+    // Blocks counted since the start, the stats only cover the latest LATENCY_SAMPLES
+    pub arrivals: u64,
+    pub arrival: Option<LatencyStats>,
+    pub acknowledged: u64,
+    pub first_ack: Option<LatencyStats>,
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = |stats: &Option<LatencyStats>| match stats {
+            Some(stats) => format!("min {} / avg {:.1} / p50 {} / p95 {} / max {}", stats.min, stats.avg, stats.p50, stats.p95, stats.max),
+            None => "none".to_owned(),
+        };
+        writeln!(f, "block arrival ms ({} blocks): {}", self.arrivals, stats(&self.arrival))?;
+        write!(f, "first acknowledgement of mined blocks ms ({} blocks): {}", self.acknowledged, stats(&self.first_ack))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Samples {
+    latest: VecDeque<i64>,
+    count: u64,
+}
+
+impl Samples {
+    fn push(&mut self, latency: i64) {
+        self.count += 1;
+        self.latest.push_back(latency);
+        if self.latest.len() > LATENCY_SAMPLES {
+            self.latest.pop_front();
+        }
+    }
+
+    fn stats(&self) -> Option<LatencyStats> {
+        LatencyStats::from_latencies(&self.latest.iter().copied().collect::<Vec<_>>())
+    }
+}
+
+// Times are milliseconds since the epoch
+#[derive(Debug, Clone, Default)]
+pub struct LatencyTracker {
+    arrivals: Samples,
+    first_acks: Samples,
+    // When we mined the blocks that did not get a receipt yet, by hash
+    pending: HashMap<String, i64>,
+    pending_order: VecDeque<String>,
+}
+
+impl LatencyTracker {
+    // Records a block accepted from gossip and returns its arrival latency
+    pub fn record_arrival(&mut self, timestamp: i64, received_at: i64) -> i64 {
+        // Blocks of a miner whose clock is ahead must not result in negative latencies
+        let latency = (received_at - timestamp).max(0);
+        self.arrivals.push(latency);
+        latency
+    }
+
+    pub fn record_mined(&mut self, hash: &str, mined_at: i64) {
+        self.pending.insert(hash.to_owned(), mined_at);
+        self.pending_order.push_back(hash.to_owned());
+        if self.pending_order.len() > MAX_PENDING_BLOCKS {
+            if let Some(hash) = self.pending_order.pop_front() {
+                self.pending.remove(&hash);
+            }
+        }
+    }
+
+    // Records the receipt of a peer for a block. Returns the time until the first receipt, or None
+    // for later receipts and blocks we did not mine.
+    pub fn record_receipt(&mut self, hash: &str, now: i64) -> Option<i64> {
+        let mined_at = self.pending.remove(hash)?;
+        let latency = (now - mined_at).max(0);
+        self.first_acks.push(latency);
+        Some(latency)
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            arrivals: self.arrivals.count,
+            arrival: self.arrivals.stats(),
+            acknowledged: self.first_acks.count,
+            first_ack: self.first_acks.stats(),
+        }
+    }
+}
+
+// Records with the global tracker, mined blocks and receipts at the current time
+pub fn record_arrival(timestamp: i64, received_at: i64) -> i64 {
+    LATENCY.lock().unwrap().record_arrival(timestamp, received_at)
+}
+
+pub fn record_mined(hash: &str) {
+    LATENCY.lock().unwrap().record_mined(hash, Utc::now().timestamp_millis());
+}
+
+pub fn record_receipt(hash: &str) -> Option<i64> {
+    LATENCY.lock().unwrap().record_receipt(hash, Utc::now().timestamp_millis())
+}
+
+pub fn report() -> LatencyReport {
+    LATENCY.lock().unwrap().report()
+}
@@ -0,0 +1,147 @@
+// Local test networks: the netlauncher binary (src/bin/netlauncher.rs) starts a number of nodes on
+// this machine and prints their output, every line prefixed with the name of the node:
+// cargo build && cargo run --bin netlauncher -- [NODES] [--storage postgres|memory] [--db-prefix NAME]
+//           [--db-host HOST] [--base-port PORT] [--dir DIR] [--node PATH] [-- NODE_OPTION...]
+// Node i (node0, node1, ...) gets
+// - the data directory DIR/node<i> (see datadir.rs)
+// - the database <db-prefix>_node<i>, created on start-up (see database.rs), with Postgres storage
+// - p2p port base-port + i on 127.0.0.1, dialing all nodes started before it
+// - the RPC server on port base-port + RPC_PORT_OFFSET + i
+// - the node options after `--`
+// Nodes run without a terminal, Ctrl-C reaches all of them and they shut down like on `exit`.
+use crate::blockchain::BlockchainError;
+use crate::config::StorageKind;
+use crate::database;
+use std::path::PathBuf;
+
+pub const DEFAULT_NODES: usize = 3;
+pub const DEFAULT_BASE_PORT: u16 = 4100;
+pub const RPC_PORT_OFFSET: u16 = 100;
+pub const DEFAULT_DB_PREFIX: &str = "netlauncher";
+pub const DEFAULT_DIR: &str = "netlauncher";
+// Passphrase of the nodes' keys (see keys.rs) unless one is set, the keys only protect a demo network
+pub const DEMO_PASSPHRASE: &str = "netlauncher";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchConfig {
+    pub nodes: usize,
+    pub storage: StorageKind,
+    pub db_prefix: String,
+    pub db_host: String,
+    pub base_port: u16,
+    // Parent of the nodes' data directories
+    pub dir: PathBuf,
+    // The node executable, the one next to netlauncher if not set
+    pub node: Option<PathBuf>,
+    // Passed to every node
+    pub node_args: Vec<String>,
+}
+
+impl LaunchConfig {
+    // Expects the arguments without the program name
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, BlockchainError> {
+        let mut config = Self {
+            nodes: DEFAULT_NODES,
+            storage: StorageKind::Postgres,
+            db_prefix: DEFAULT_DB_PREFIX.to_owned(),
+            db_host: database::DEFAULT_DB_HOST.to_owned(),
+            base_port: DEFAULT_BASE_PORT,
+            dir: PathBuf::from(DEFAULT_DIR),
+            node: None,
+            node_args: vec![],
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--storage" => {
+                    config.storage = match args.next().as_deref() {
+                        Some("postgres") => StorageKind::Postgres,
+                        Some("memory") => StorageKind::Memory,
+                        other => return Err(BlockchainError::Error(format!("invalid storage: {:?}", other))),
+                    }
+                }
+                "--db-prefix" => {
+                    config.db_prefix = args
+                        .next()
+                        .filter(|prefix| !prefix.is_empty() && prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+                        .ok_or_else(|| BlockchainError::Error("--db-prefix requires a name of letters, digits and _".to_owned()))?;
+                }
+                "--db-host" => {
+                    config.db_host = args.next().ok_or_else(|| BlockchainError::Error("--db-host requires a host".to_owned()))?;
+                }
+                "--base-port" => {
+                    config.base_port = args
+                        .next()
+                        .and_then(|port| port.parse::<u16>().ok())
+                        .filter(|port| *port > 0)
+                        .ok_or_else(|| BlockchainError::Error("--base-port requires a port".to_owned()))?;
+                }
+                "--dir" => {
+                    config.dir = PathBuf::from(args.next().ok_or_else(|| BlockchainError::Error("--dir requires a directory".to_owned()))?);
+                }
+                "--node" => {
+                    config.node = Some(PathBuf::from(args.next().ok_or_else(|| BlockchainError::Error("--node requires a path".to_owned()))?));
+                }
+                "--" => config.node_args.extend(args.by_ref()),
+                _ if arg.starts_with("--") => return Err(BlockchainError::Error(format!("unknown option: {}", arg))),
+                _ => {
+                    config.nodes = arg
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|nodes| *nodes > 0)
+                        .ok_or_else(|| BlockchainError::Error(format!("invalid number of nodes: {}", arg)))?;
+                }
+            }
+        }
+        // The RPC ports of the last node have to fit as well
+        if config.base_port as usize + RPC_PORT_OFFSET as usize + config.nodes > u16::MAX as usize + 1 {
+            return Err(BlockchainError::Error(format!("{} nodes do not fit above port {}", config.nodes, config.base_port)));
+        }
+        Ok(config)
+    }
+
+    pub fn plan(&self) -> Vec<NodeSpec> {
+        (0..self.nodes).map(|i| self.node_spec(i)).collect()
+    }
+
+    fn node_spec(&self, i: usize) -> NodeSpec {
+        let name = format!("node{}", i);
+        let p2p_port = self.base_port + i as u16;
+        let rpc_port = self.base_port + RPC_PORT_OFFSET + i as u16;
+        let data_dir = self.dir.join(&name);
+        let mut args = vec![];
+        match self.storage {
+            StorageKind::Postgres => {
+                args.push(format!("{}_{}", self.db_prefix, name));
+                args.extend(["--db-host".to_owned(), self.db_host.clone()]);
+            }
+            StorageKind::Memory => args.extend(["--storage".to_owned(), "memory".to_owned()]),
+        }
+        args.extend(["--data-dir".to_owned(), data_dir.display().to_string()]);
+        args.extend(["--listen".to_owned(), listen_addr(p2p_port)]);
+        for peer in 0..i {
+            args.extend(["--peer".to_owned(), listen_addr(self.base_port + peer as u16)]);
+        }
+        args.extend(["--rpc".to_owned(), format!("127.0.0.1:{}", rpc_port)]);
+        args.extend(self.node_args.iter().cloned());
+        NodeSpec { name, data_dir, p2p_port, rpc_port, args }
+    }
+}
+
+fn listen_addr(port: u16) -> String {
+    format!("/ip4/127.0.0.1/tcp/{}", port)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSpec {
+    pub name: String,
+    pub data_dir: PathBuf,
+    pub p2p_port: u16,
+    pub rpc_port: u16,
+    // The command line of the node
+    pub args: Vec<String>,
+}
+
+// A line of a node's output, the names are padded to the longest one so the lines align
+pub fn prefix_line(name: &str, width: usize, line: &str) -> String {
+    format!("{:<width$} | {}", name, line, width = width)
+}
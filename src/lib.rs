@@ -1,3 +1,73 @@
+pub mod address;
+pub mod anchor;
+pub mod atrest;
 pub mod blockchain;
+pub mod bridge;
+pub mod cache;
+pub mod capabilities;
+pub mod capture;
+pub mod chaingen;
+pub mod chains;
+pub mod cli;
+pub mod config;
+pub mod consensus;
+pub mod database;
+pub mod datadir;
+pub mod deadletter;
+pub mod diskusage;
+pub mod diversity;
+pub mod difficulty;
+pub mod dryrun;
+pub mod endpoints;
+pub mod events;
+pub mod fastsync;
+pub mod fees;
+pub mod gc;
+pub mod gpu;
+pub mod graph;
+pub mod handshake;
+pub mod hdwallet;
+pub mod head;
+pub mod history;
+pub mod hooks;
+pub mod integrity;
+pub mod ipc;
+pub mod keys;
+pub mod latency;
+pub mod launcher;
+pub mod lifecycle;
+pub mod loadgen;
+pub mod mempool;
+pub mod mining;
+pub mod multisig;
+pub mod network;
+pub mod node;
+pub mod nodeinfo;
+pub mod orphans;
 pub mod p2p;
-pub mod types;
\ No newline at end of file
+pub mod payload;
+pub mod pool;
+pub mod propagation;
+pub mod receipts;
+pub mod reload;
+pub mod replay;
+pub mod repository;
+pub mod service;
+pub mod rpc;
+pub mod simulation;
+pub mod slots;
+pub mod staletip;
+pub mod stats;
+pub mod state;
+pub mod storage;
+pub mod stratum;
+pub mod sync;
+pub mod transactions;
+pub mod types;
+pub mod utxo;
+pub mod vectors;
+pub mod wal;
+pub mod wallet;
+pub mod watch;
+pub mod watchdog;
+pub mod workers;
@@ -0,0 +1,85 @@
+// Lifecycle hooks: applications embedding the node can register hooks on a Node
+// (`node.lifecycle.add(NAME, HOOK)`) to run their own logic on the events of the node instead of
+// patching the main loop. A hook implements the methods of LifecycleHook it is interested in:
+// - on_block_accepted: a block became part of our main chain (mined by us, received or synced),
+//   reported in order and after on_reorg for the blocks of a new branch
+// - on_reorg: our main chain switched to another branch, `depth` blocks of the old one are gone
+// - before_mine: we are about to mine a queued job (`mine_next`, slot blocks, load generation), an
+//   error vetoes mining it and the job is dropped. Template hooks can change the block, see hooks.rs.
+// - on_peer_connected: a peer completed the handshake with us
+// Hooks run in the order they were added and are awaited on the node's event loop, so long-running
+// work should be spawned as a task of its own. The main chain events are reported by
+// Node::watch_head, the node binary only calls it for the default chain.
+use crate::blockchain::{Block, BlockchainError};
+use crate::handshake::Handshake;
+use crate::head::HeadEvent;
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+
+#[async_trait]
+pub trait LifecycleHook: Send + Sync {
+    async fn on_block_accepted(&self, _block: &Block) {}
+
+    async fn on_reorg(&self, _old_tip: &Block, _new_tip: &Block, _depth: u64) {}
+
+    // Sees the data of the block we are about to mine, an error vetoes mining it
+    async fn before_mine(&self, _data: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn on_peer_connected(&self, _peer: &str, _handshake: &Handshake) {}
+}
+
+#[derive(Clone, Default)]
+pub struct LifecycleHooks {
+    hooks: Vec<(String, Arc<dyn LifecycleHook>)>,
+}
+
+impl fmt::Debug for LifecycleHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl LifecycleHooks {
+    pub fn add<H: LifecycleHook + 'static>(&mut self, name: &str, hook: H) {
+        self.hooks.push((name.to_owned(), Arc::new(hook)));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.hooks.retain(|(hook_name, _)| hook_name != name);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.hooks.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    // Reports a change of our chain's head to all hooks
+    pub async fn head_changed(&self, event: &HeadEvent) {
+        for (_, hook) in &self.hooks {
+            match event {
+                HeadEvent::Extended(block) => hook.on_block_accepted(block).await,
+                HeadEvent::Reorged { old_tip, new_tip, depth } => hook.on_reorg(old_tip, new_tip, *depth).await,
+            }
+        }
+    }
+
+    // Asks all hooks whether the data may be mined, stopping at the first veto
+    pub async fn before_mine(&self, data: &str) -> Result<(), BlockchainError> {
+        for (name, hook) in &self.hooks {
+            hook.before_mine(data).await.map_err(|reason| BlockchainError::MiningVetoed(format!("{}: {}", name, reason)))?;
+        }
+        Ok(())
+    }
+
+    pub async fn peer_connected(&self, peer: &str, handshake: &Handshake) {
+        for (_, hook) in &self.hooks {
+            hook.on_peer_connected(peer, handshake).await;
+        }
+    }
+}
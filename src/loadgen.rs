@@ -0,0 +1,211 @@
+// Load generation (`node loadgen`): mines blocks with padded payloads at a fixed rate and collects
+// receipts from the peers that received them, to measure throughput and end-to-end propagation
+// latency. Latency is the difference between the wall clock time we broadcast a block and the time
+// a peer received it, so the clocks of the nodes have to be in sync (e.g. all nodes on one machine).
+use crate::blockchain::{Block, BlockchainError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Payloads of generated blocks start with this
+pub const LOADGEN_PREFIX: &str = "loadgen ";
+// How often the current throughput and latency are printed while load is generated
+pub const LOADGEN_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+// Number of blocks we keep the broadcast time of, receipts for older blocks are ignored
+const MAX_TRACKED_BLOCKS: usize = 1_000;
+const DEFAULT_BLOCKS_PER_SEC: f64 = 1.0;
+const DEFAULT_PAYLOAD_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadgenConfig {
+    pub blocks_per_sec: f64,
+    // Size of the block data in bytes
+    pub payload_size: usize,
+}
+
+impl LoadgenConfig {
+    // Parses "[--blocks-per-sec N] [--payload-size S]"
+    pub fn from_args(args: &str) -> Result<Self, BlockchainError> {
+        let mut config = LoadgenConfig {
+            blocks_per_sec: DEFAULT_BLOCKS_PER_SEC,
+            payload_size: DEFAULT_PAYLOAD_SIZE,
+        };
+
+        let mut args = args.split_whitespace();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--blocks-per-sec" => {
+                    config.blocks_per_sec = args
+                        .next()
+                        .and_then(|n| n.parse::<f64>().ok())
+                        .filter(|n| n.is_finite() && *n > 0.0)
+                        .ok_or_else(|| BlockchainError::Error("--blocks-per-sec requires a positive number".to_owned()))?;
+                }
+                "--payload-size" => {
+                    config.payload_size = args
+                        .next()
+                        .and_then(|size| size.parse::<usize>().ok())
+                        .ok_or_else(|| BlockchainError::Error("--payload-size requires a number of bytes".to_owned()))?;
+                }
+                _ => return Err(BlockchainError::Error(format!("unknown option: {}", arg))),
+            }
+        }
+        Ok(config)
+    }
+
+    // Time between two generated blocks
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.blocks_per_sec)
+    }
+}
+
+pub fn is_loadgen_block(block: &Block) -> bool {
+    block.data.starts_with(LOADGEN_PREFIX)
+}
+
+struct SentBlock {
+    sent_at: i64,
+    // Peers we already got a receipt from, duplicates are not counted twice
+    receipts: HashSet<String>,
+}
+
+pub struct LoadGenerator {
+    pub config: LoadgenConfig,
+    started_at: Instant,
+    blocks: usize,
+    bytes: usize,
+    sent: HashMap<String, SentBlock>,
+    sent_order: VecDeque<String>,
+    // Propagation latencies in milliseconds
+    latencies: Vec<i64>,
+    peers: HashSet<String>,
+}
+
+impl LoadGenerator {
+    pub fn new(config: LoadgenConfig, now: Instant) -> Self {
+        Self {
+            config,
+            started_at: now,
+            blocks: 0,
+            bytes: 0,
+            sent: HashMap::new(),
+            sent_order: VecDeque::new(),
+            latencies: vec![],
+            peers: HashSet::new(),
+        }
+    }
+
+    // Data for the next block, padded to the configured payload size
+    pub fn payload(&self) -> String {
+        let mut data = format!("{}{}", LOADGEN_PREFIX, self.blocks);
+        if data.len() < self.config.payload_size {
+            data.push_str(&".".repeat(self.config.payload_size - data.len()));
+        }
+        data
+    }
+
+    // Remembers when the block was broadcast (milliseconds since the epoch)
+    pub fn record_block(&mut self, block: &Block, sent_at: i64) {
+        self.blocks += 1;
+        self.bytes += block.data.len();
+        self.sent.insert(block.hash.clone(), SentBlock { sent_at, receipts: HashSet::new() });
+        self.sent_order.push_back(block.hash.clone());
+        if self.sent_order.len() > MAX_TRACKED_BLOCKS {
+            if let Some(hash) = self.sent_order.pop_front() {
+                self.sent.remove(&hash);
+            }
+        }
+    }
+
+    // Records that the peer received the block at the given time (milliseconds since the epoch).
+    // Returns the propagation latency, or None for unknown blocks and duplicate receipts.
+    pub fn record_receipt(&mut self, peer: &str, hash: &str, received_at: i64) -> Option<i64> {
+        let sent = self.sent.get_mut(hash)?;
+        if !sent.receipts.insert(peer.to_owned()) {
+            return None;
+        }
+        // Clocks that are slightly off must not result in negative latencies
+        let latency = (received_at - sent.sent_at).max(0);
+        self.latencies.push(latency);
+        self.peers.insert(peer.to_owned());
+        Some(latency)
+    }
+
+    pub fn report(&self, now: Instant) -> LoadReport {
+        let elapsed = now.duration_since(self.started_at);
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        LoadReport {
+            elapsed,
+            blocks: self.blocks,
+            blocks_per_sec: self.blocks as f64 / secs,
+            bytes_per_sec: self.bytes as f64 / secs,
+            receipts: self.latencies.len(),
+            peers: self.peers.len(),
+            latency: LatencyStats::from_latencies(&self.latencies),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadReport {
+    pub elapsed: Duration,
+    pub blocks: usize,
+    pub blocks_per_sec: f64,
+    pub bytes_per_sec: f64,
+    pub receipts: usize,
+    // Number of peers that sent at least one receipt
+    pub peers: usize,
+    pub latency: Option<LatencyStats>,
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "elapsed: {:.1}s | blocks: {} | throughput: {:.2} blocks/s ({:.1} KB/s) | receipts: {} from {} peers",
+            self.elapsed.as_secs_f64(),
+            self.blocks,
+            self.blocks_per_sec,
+            self.bytes_per_sec / 1024.0,
+            self.receipts,
+            self.peers
+        )?;
+        if let Some(latency) = &self.latency {
+            write!(
+                f,
+                " | latency ms: min {} / avg {:.1} / p50 {} / p95 {} / max {}",
+                latency.min, latency.avg, latency.p50, latency.p95, latency.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Propagation latencies in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub min: i64,
+    pub avg: f64,
+    pub p50: i64,
+    pub p95: i64,
+    pub max: i64,
+}
+
+impl LatencyStats {
+    pub fn from_latencies(latencies: &[i64]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = latencies.to_vec();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        Some(Self {
+            min: sorted[0],
+            avg: sorted.iter().sum::<i64>() as f64 / sorted.len() as f64,
+            p50: percentile(50),
+            p95: percentile(95),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
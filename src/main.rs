@@ -1,53 +1,208 @@
 use rust_blockchain::{
-    blockchain::{BlockchainError, Chain},
+    address,
+    anchor::{self, Anchor, AnchorProof},
+    atrest,
+    bridge::{self, BridgeAnchor, BridgeTarget},
+    blockchain::{self, Block, BlockchainError, Chain, Checkpoint, HashBackend, REGTEST_DIFFICULTY, STALE_RATE_WINDOW},
+    capabilities,
+    chaingen::{self, GenerateConfig},
+    chains::{self, ChainSpec},
+    cli,
+    config::{self, Config, StorageKind},
+    consensus,
+    database,
+    datadir::DataDir,
+    difficulty::{self, Fixed, MIN_DIFFICULTY},
+    dryrun,
+    endpoints::{ServiceAnnouncement, SERVICE_ANNOUNCE_INTERVAL},
+    diskusage::{self, UsageConfig},
+    events::{self, EVENT_BUFFER},
+    fees,
+    gc::{StaleCollector, StalePruning, GC_INTERVAL},
+    graph::{self, GraphConfig},
+    gpu,
+    handshake::Features,
+    hdwallet::{self, HdWallet},
+    head::HeadEvent,
+    history::{self, HistoryConfig},
+    integrity::{self, RepairStrategy},
+    ipc,
+    keys::{KeyStore, KEY_PASSPHRASE_ENV},
+    latency,
+    loadgen::{LoadGenerator, LoadgenConfig, LOADGEN_REPORT_INTERVAL},
+    mempool,
+    multisig::{self, Multisig},
+    mining::{Enqueued, MempoolEvent, MiningQueue, Priority, MEMPOOL_EXPIRY_INTERVAL, REPLACEMENT_FEE_INCREASE},
     p2p,
-    types::{EventType},
+    payload,
+    pool::{Coordinator, PoolMessage, PoolShare, PoolWorker, DEFAULT_SHARE_DIFFICULTY},
+    receipts::Receipt,
+    reload::{ConfigChanges, FileWatcher, SetLogLevel, CONFIG_WATCH_INTERVAL},
+    replay,
+    repository::Repository,
+    rpc,
+    service::{self, ServiceManager, ShutdownSignals},
+    slots::SlotSchedule,
+    staletip::STALE_TIP_CHECK_INTERVAL,
+    state::StateProof,
+    stats::STATS_FLUSH_INTERVAL,
+    storage::{MemoryStorage, Storage},
+    transactions::{self, Transaction},
+    stratum,
+    node::Node,
+    nodeinfo::NodeInfo,
+    orphans::OrphanPool,
+    types::{EventType, Height},
+    utxo,
+    wal::{WriteQueue, WAL_FLUSH_INTERVAL},
+    wallet::Wallet,
+    watch::{self, WatchList},
+    watchdog,
+    workers::{self, ChainServer, ServingJob, SyncJob, ValidationJob},
 };
+use chrono::{TimeZone, Utc};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::{
     io::{self, AsyncBufReadExt},
-    sync::{mpsc},
+    net::TcpListener,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
 };
-use tokio_postgres;
-use tracing::{error, debug, info, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::{error, info, warn};
+use tracing_subscriber::{
+    fmt::writer::{BoxMakeWriter, MakeWriterExt},
+    FmtSubscriber,
+};
+
+// How often we gossip our finalized checkpoint to our peers
+const FINALITY_GOSSIP_INTERVAL: Duration = Duration::from_secs(30);
+// How often we look for queued mining jobs while the queue is empty
+const MINING_QUEUE_INTERVAL: Duration = Duration::from_millis(100);
+// How often we check whether a new slot started in slot-based mode
+const SLOT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// Reindexing progress is printed every that many blocks
+const REINDEX_PROGRESS_STEP: u64 = 1_000;
+// How often we check for sync sessions that did not get a response
+const SYNC_TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// Number of records `sync history` shows without a count
+const SYNC_HISTORY_DEFAULT: i64 = 20;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    // Get storage and p2p options passed via cmd line on startup
+    let config = Config::from_args(env::args().skip(1))?;
+
+    // With a data directory the log is written to its log file as well. Its level can be reloaded, see reload.rs.
+    let writer = match &config.data_dir {
+        Some(data_dir) => {
+            if data_dir.init()? {
+                println!("Created data directory {}", data_dir.root.display());
+            }
+            let log_file = OpenOptions::new().create(true).append(true).open(data_dir.log_file())?;
+            BoxMakeWriter::new(std::io::stdout.and(Mutex::new(log_file)))
+        }
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    let builder = FmtSubscriber::builder()
+        .with_max_level(config.log_level)
+        .with_ansi(config.data_dir.is_none())
+        .with_writer(writer)
+        .with_filter_reloading();
+    let log_level = builder.reload_handle();
+    let set_log_level: SetLogLevel = Box::new(move |level| log_level.reload(level).map_err(|err| err.to_string()));
+    tracing::subscriber::set_global_default(builder.finish()).expect("setting default subscriber failed");
+    blockchain::set_mining_threads(config.mining_threads);
+    watchdog::set_thresholds(config.watchdog);
 
     info!("starting app...");
 
-    // Get name for DB to use for this node from args passed via cmd line on startup
-    let DB_NAME = env::args()
-        .nth(1)
-        .ok_or_else(|| "DB name not set. call 'cargo run {DB_NAME}'")?;
-    // Connect to the postgres database
-    let (db_client, connection) = tokio_postgres::connect(
-        &format!("host=localhost dbname={} user=user password=pw", DB_NAME),
-        tokio_postgres::NoTls,
-    )
-    .await?;
+    // Our peer ID has to be set before the p2p layer starts
+    let key_store = match &config.keys {
+        Some(dir) => {
+            let passphrase = env::var(KEY_PASSPHRASE_ENV)
+                .map_err(|_| BlockchainError::Error(format!("--keys and --data-dir require the passphrase in {}", KEY_PASSPHRASE_ENV)))?;
+            let key_store = KeyStore::open(dir, passphrase)?;
+            p2p::set_identity(key_store.identity()?);
+            // Fails early on a wrong passphrase
+            key_store.signing_key()?;
+            Some(key_store)
+        }
+        None => None,
+    };
+
+    // The storage key has to be set before the storage is opened
+    if config.encrypt_at_rest {
+        atrest::set_key(atrest::load_key(key_store.as_ref())?)?;
+    }
+
+    let (storage, db_task) = open_storage(&config, None).await?;
+    // Every additional chain has a storage of its own. Their connections are not watched like the
+    // one of the default chain, errors are logged.
+    let mut chain_storages = vec![];
+    for chain in config.chains.iter() {
+        let (storage, _) = open_storage(&config, Some(chain)).await?;
+        chain_storages.push((chain.clone(), storage));
+    }
 
     let (main_sender, main_rcv) = mpsc::unbounded_channel::<EventType>();
     let (p2p_sender, p2p_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (stratum_sender, stratum_rcv) = mpsc::unbounded_channel::<EventType>();
 
-    let p2p_task = tokio::spawn(p2p::init_p2p(p2p_rcv, main_sender));
-    let app_task = tokio::spawn(run(db_client, p2p_sender, main_rcv));
+    let stratum_task = match config.stratum {
+        Some(addr) => tokio::spawn(stratum::init_stratum(TcpListener::bind(addr).await?, stratum_rcv, main_sender.clone())),
+        // Messages for hashers are dropped, there can not be any
+        None => tokio::spawn(futures::future::pending::<Result<(), std::io::Error>>()),
+    };
 
-    // The connection object performs the actual communication with the database, so spawn it off to run on its own
-    let db_task = tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            error!("DB connection error: {}", e);
-        }
-    });
+    // Changes of our chain's head and replacements in our mempool, streamed to the clients of the event server
+    let (head_sender, _) = broadcast::channel::<HeadEvent>(EVENT_BUFFER);
+    let (mempool_sender, _) = broadcast::channel::<MempoolEvent>(EVENT_BUFFER);
+    let events_task = match config.events {
+        Some(addr) => tokio::spawn(events::init_events(TcpListener::bind(addr).await?, head_sender.clone(), mempool_sender.clone())),
+        None => tokio::spawn(futures::future::pending::<Result<(), std::io::Error>>()),
+    };
+
+    // Block queries of explorers and indexers, answered by the main loop, long-polls end with a change of our head
+    let (rpc_sender, rpc_rcv) = mpsc::unbounded_channel::<EventType>();
+    let rpc_task = match config.rpc {
+        Some(addr) => tokio::spawn(rpc::init_rpc(TcpListener::bind(addr).await?, rpc_rcv, main_sender.clone(), head_sender.clone())),
+        None => tokio::spawn(futures::future::pending::<Result<(), std::io::Error>>()),
+    };
+    // The same for scripts on this host, over a local socket
+    let (ipc_sender, ipc_rcv) = mpsc::unbounded_channel::<EventType>();
+    let ipc_task = match &config.ipc {
+        Some(path) => tokio::spawn(ipc::init_ipc(ipc::bind(path)?, ipc_rcv, main_sender.clone(), head_sender.clone())),
+        None => tokio::spawn(futures::future::pending::<Result<(), std::io::Error>>()),
+    };
+
+    // The sync worker hands the chains it checked back to the main loop, see workers.rs
+    let worker_sender = main_sender.clone();
+    let p2p_task = if config.p2p {
+        tokio::spawn(p2p::init_p2p(p2p_rcv, main_sender, config.clone()))
+    } else {
+        // Without p2p we are ready right away. Events sent to the p2p task are simply dropped,
+        // the sender is kept alive so the app does not see a closed channel.
+        tokio::spawn(async move {
+            let _ = main_sender.send(EventType::InitDone);
+            futures::future::pending::<Result<(), std::io::Error>>().await
+        })
+    };
+    let app_task = tokio::spawn(run(storage, chain_storages, config, key_store, p2p_sender, stratum_sender, rpc_sender, ipc_sender, head_sender, mempool_sender, worker_sender, main_rcv, set_log_level));
 
     tokio::select! {
         res = p2p_task => info!("p2p exited {:?}", res),
+        res = stratum_task => info!("stratum exited {:?}", res),
+        res = events_task => info!("events exited {:?}", res),
+        res = rpc_task => info!("rpc exited {:?}", res),
+        res = ipc_task => info!("ipc exited {:?}", res),
         res = app_task => info!("app exited {:?}", res),
         res = db_task => info!("db connection lost {:?}", res),
     };
@@ -55,10 +210,54 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Opens the storage of the default chain, or of the additional chain. With Postgres the tables of an
+// additional chain are kept in a schema of its own, in memory it has a snapshot file of its own.
+async fn open_storage(config: &Config, chain: Option<&ChainSpec>) -> Result<(Storage, JoinHandle<()>), Box<dyn Error>> {
+    match config.storage {
+        StorageKind::Postgres => {
+            // Connect to the postgres database, waiting for the server and creating the database if needed
+            let db_name = config.db_name.clone().unwrap_or_default();
+            let (db_client, db_task) = database::connect(&config.db_host, &db_name, config.db_wait).await?;
+            if let Some(chain) = chain {
+                // Chain names are restricted to characters that are safe in identifiers
+                Repository::new(&db_client).use_schema(&chain.schema()).await?;
+            }
+            let write_queue = match &config.wal {
+                Some(path) => Some(WriteQueue::open(&chain.map_or_else(|| path.clone(), |chain| chain.chain_file(path)))?),
+                None => None,
+            };
+            Ok((Storage::postgres(db_client, write_queue), db_task))
+        }
+        StorageKind::Memory => {
+            let snapshot = config.snapshot.as_ref().map(|path| match chain {
+                Some(chain) => chain.chain_file(path),
+                None => path.clone(),
+            });
+            let memory = match &snapshot {
+                Some(path) => MemoryStorage::load(path)?,
+                None => MemoryStorage::default(),
+            };
+            // There is no connection that could be lost
+            Ok((Storage::Memory(memory), tokio::spawn(futures::future::pending::<()>())))
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run(
-    mut db_client: tokio_postgres::Client,
+    storage: Storage,
+    chain_storages: Vec<(ChainSpec, Storage)>,
+    mut config: Config,
+    key_store: Option<KeyStore>,
     p2p_sender: mpsc::UnboundedSender<EventType>,
+    stratum_sender: mpsc::UnboundedSender<EventType>,
+    rpc_sender: mpsc::UnboundedSender<EventType>,
+    ipc_sender: mpsc::UnboundedSender<EventType>,
+    head_sender: broadcast::Sender<HeadEvent>,
+    mempool_sender: broadcast::Sender<MempoolEvent>,
+    worker_sender: mpsc::UnboundedSender<EventType>,
     mut main_rcv: mpsc::UnboundedReceiver<EventType>,
+    set_log_level: SetLogLevel,
 ) -> Result<(), BlockchainError> {
     // We wait until the P2P service is ready
     loop {
@@ -73,108 +272,1349 @@ async fn run(
         }
     }
 
-    let mut chain = Chain::init(&mut db_client).await?;
+    // Refuse to serve a corrupted chain, unless we were told how to repair it
+    let mut storage = storage;
+    storage.init().await?;
+    let genesis = config.network.genesis();
+    let repaired = repair_chain(&mut storage, &genesis, config.repair).await?;
+
+    let mut node = Node::init_with_genesis(storage, p2p::LOCAL_PEER_ID.to_string(), genesis).await?;
+    node.set_wallet(load_wallet(&config, key_store.as_ref())?);
+    if let Some(wallet) = &node.wallet {
+        info!("Wallet address {}", wallet.address());
+    }
+    if let Err(err) = node.storage.record_start(Utc::now().timestamp_millis()).await {
+        error!("Error recording start in node stats: {:?}", err);
+    }
+    if repaired == Some(RepairStrategy::Resync) {
+        let _ = p2p_sender.send(EventType::RequestLatestBlocks);
+    }
+    let _ = p2p_sender.send(update_handshake(&node, &config));
+    node.chain.params = config.network.clone();
+    node.chain.checkpoint_signers = config.checkpoint_signers.clone();
+    node.fast_sync = config.fast_sync;
+    node.snapshot_trust = config.snapshot_trust.clone();
+    node.stale_tip.factor = config.stale_tip_factor;
+    if config.regtest {
+        node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+        node.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    }
+    // The difficulty algorithms aim for the block time of our network
+    let algorithm = config.difficulty_algorithm.as_deref().or((!config.regtest).then_some(difficulty::DEFAULT_ALGORITHM));
+    if let Some(algorithm) = algorithm.and_then(|name| difficulty::algorithm_with_block_time(name, config.network.target_block_time_ms)) {
+        node.chain.difficulty_algorithm = algorithm;
+    }
+    node.mining_queue = match &config.mempool {
+        Some(path) => MiningQueue::open(path, config.mempool_policy, Utc::now().timestamp_millis())?,
+        None => MiningQueue::with_policy(config.mempool_policy),
+    };
+    node.mining_queue.set_event_sender(mempool_sender);
+    node.orphans = OrphanPool::new(config.max_orphans, config.max_orphan_bytes);
+    if let Some(slot_time) = config.slot_time {
+        node.slots = Some(SlotSchedule::new(slot_time, config.proposers.clone()));
+        println!("Producing a block every {}s", slot_time.as_secs());
+    }
+    if config.hash_backend == HashBackend::Gpu {
+        match gpu::init() {
+            Ok(name) => {
+                println!("Mining on GPU {}", name);
+                node.chain.hash_backend = HashBackend::Gpu;
+            }
+            Err(err) => println!("GPU not available ({}), mining on the CPU", err),
+        }
+    }
+
+    // Slow work of the default chain is done next to the main loop, see workers.rs
+    let (validation_sender, validation_rcv) = mpsc::unbounded_channel::<ValidationJob>();
+    tokio::spawn(workers::init_validation_worker(validation_rcv));
+    let (sync_sender, sync_rcv) = mpsc::unbounded_channel::<SyncJob>();
+    tokio::spawn(workers::init_sync_worker(sync_rcv, worker_sender));
+    let server = ChainServer::new(node.chain.clone(), Chain::get_chain(&mut node.storage).await?);
+    let (serving_sender, serving_rcv) = mpsc::unbounded_channel::<ServingJob>();
+    tokio::spawn(workers::init_serving_worker(server, serving_rcv, p2p_sender.clone()));
 
+    // The additional chains, by name. Slots, pools, load generation and the event stream are only
+    // available on the default chain.
+    let mut chain_nodes = BTreeMap::new();
+    for (spec, mut storage) in chain_storages {
+        storage.init().await?;
+        let repaired = repair_chain(&mut storage, &spec.genesis(), config.repair).await?;
+        let mut chain_node = Node::init_with_genesis(storage, p2p::LOCAL_PEER_ID.to_string(), spec.genesis()).await?;
+        chain_node.chain.checkpoint_signers = config.checkpoint_signers.clone();
+        if config.regtest {
+            chain_node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+            chain_node.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+        }
+        if let Some(algorithm) = spec.difficulty_algorithm.as_deref().or(config.difficulty_algorithm.as_deref()).and_then(difficulty::algorithm_by_name) {
+            chain_node.chain.difficulty_algorithm = algorithm;
+        }
+        chain_node.chain.hash_backend = node.chain.hash_backend;
+        chain_node.stale_tip.factor = config.stale_tip_factor;
+        if repaired == Some(RepairStrategy::Resync) {
+            let _ = p2p_sender.send(for_chain(&spec.name, EventType::RequestLatestBlocks));
+        }
+        println!("Following chain {} at height {}", spec.name, chain_node.chain.latest_block.id);
+        chain_nodes.insert(spec.name, chain_node);
+    }
+
+    // Reconnect to previously good peers and keep banned peers banned across restarts
+    match node.storage.get_peer_scores().await {
+        Ok(scores) => {
+            let _ = p2p_sender.send(EventType::RestorePeerScores{scores});
+        }
+        Err(err) => error!("Error loading peer scores: {:?}", err),
+    }
+
+    // Start-up banner, the listen addresses are updated once the p2p layer listens
+    let mut node_info = NodeInfo::new(p2p::LOCAL_PEER_ID.to_string(), &config);
+    println!("---------------------------");
+    println!("{}", node_info);
     println!("---------------------------");
     println!("Commands available:");
-    println!("block mine BLOCK_DATA");
-    println!("block validate BLOCK_HASH");
-    println!("block get BLOCK_HASH");
-    println!("chain validate");
-    println!("ls p //show all peers");
-    println!("exit");
+    for command in cli::COMMANDS {
+        println!("{}", command);
+    }
     println!("---------------------------");
     println!("Enter command:");
 
+    // Storage, p2p layer and servers are up, see service.rs
+    service::notify(&format!("READY=1\nSTATUS=height {}", node.chain.latest_block.id));
+    let mut shutdown = ShutdownSignals::new()?;
+    // Run as a service there is no terminal to read commands from
     let mut stdin = io::BufReader::new(io::stdin()).lines();
+    let mut stdin_open = true;
+    let mut sync_timeout_interval = time::interval(SYNC_TIMEOUT_CHECK_INTERVAL);
+    let mut stale_tip_interval = time::interval(STALE_TIP_CHECK_INTERVAL);
+    let mut finality_interval = time::interval(FINALITY_GOSSIP_INTERVAL);
+    let mut service_interval = time::interval(SERVICE_ANNOUNCE_INTERVAL);
+    let mut snapshot_interval = time::interval(config.snapshot_interval);
+    let mut wal_interval = time::interval(WAL_FLUSH_INTERVAL);
+    let mut mining_interval = time::interval(MINING_QUEUE_INTERVAL);
+    let mut chain_mining_interval = time::interval(MINING_QUEUE_INTERVAL);
+    let mut mempool_expiry_interval = time::interval(MEMPOOL_EXPIRY_INTERVAL);
+    let mut slot_interval = time::interval(SLOT_CHECK_INTERVAL);
+    let mut gc_interval = time::interval(GC_INTERVAL);
+    let mut stats_interval = time::interval_at(time::Instant::now() + STATS_FLUSH_INTERVAL, STATS_FLUSH_INTERVAL);
+    let mut stale_collector = StaleCollector::new(config.stale_pruning);
+    // Both are reset when load generation is started
+    let mut loadgen_interval = time::interval(Duration::from_secs(1));
+    let mut loadgen_report_interval = time::interval(LOADGEN_REPORT_INTERVAL);
+    // Set while we mine for a pool (`pool join`), its search thread sends the shares it finds to share_rcv
+    let mut pool_worker: Option<PoolWorker> = None;
+    let (share_sender, mut share_rcv) = mpsc::unbounded_channel::<PoolShare>();
+    // Latest anchor committed to the bridge target, notaries report back on notary_rcv
+    let mut bridge_interval = time::interval(config.bridge_interval);
+    let mut bridged: Option<BridgeAnchor> = None;
+    let (notary_sender, mut notary_rcv) = mpsc::unbounded_channel::<(BridgeAnchor, Result<u16, String>)>();
+    // Transactions queued with `tx broadcast`, by ID, so `tx status` can tell dropped ones from unknown ones
+    let mut broadcast_txs: HashMap<String, Transaction> = HashMap::new();
+    // Changes of the data directory's config file are reloaded, see reload.rs
+    let mut config_watcher = config.data_dir.as_ref().map(|data_dir| FileWatcher::new(&data_dir.config()));
+    let mut config_watch_interval = time::interval(CONFIG_WATCH_INTERVAL);
     loop {
         tokio::select! {
+            Some(share) = share_rcv.recv() => {
+                // Shares of replaced jobs would be rejected as stale anyway
+                if let Some(worker) = pool_worker.as_mut().filter(|worker| worker.job_id == Some(share.job_id)) {
+                    worker.shares_found += 1;
+                    let _ = p2p_sender.send(EventType::SendPoolMessage{receiver: worker.coordinator.clone(), message: PoolMessage::Share(share)});
+                }
+            },
+            _ = loadgen_interval.tick(), if node.loadgen.is_some() => {
+                match node.generate_load().await {
+                    Ok(outgoing) => {
+                        for event in outgoing {
+                            let _ = p2p_sender.send(event);
+                        }
+                    }
+                    Err(err) => error!("Error generating load: {:?}", err),
+                }
+            },
+            _ = loadgen_report_interval.tick(), if node.loadgen.is_some() => {
+                if let Some(loadgen) = &node.loadgen {
+                    println!("loadgen: {}", loadgen.report(Instant::now()));
+                }
+            },
+            _ = mempool_expiry_interval.tick(), if node.mining_queue.policy().max_age.is_some() => {
+                for job in node.mining_queue.expire(Utc::now().timestamp_millis()) {
+                    info!("Dropped mining job {}, it was queued too long", job.id);
+                }
+            },
+            _ = gc_interval.tick(), if stale_collector.pruning != StalePruning::Off => {
+                match stale_collector.collect(&mut node.storage, &node.chain).await {
+                    Ok(run) if run.blocks_pruned + run.bodies_pruned > 0 => {
+                        info!("Pruned {} stale blocks and {} stale payloads, reclaimed {} bytes", run.blocks_pruned, run.bodies_pruned, run.bytes_reclaimed);
+                    }
+                    Ok(_) => {}
+                    Err(err) => error!("Error pruning stale blocks: {:?}", err),
+                }
+            },
+            // Jobs are mined one at a time, commands entered meanwhile are handled in between
+            _ = mining_interval.tick(), if (!node.mining_queue.is_empty() || !node.transactions.is_empty()) && node.slots.is_none() => {
+                match node.mine_next().await {
+                    Ok(Some(block)) => {
+                        let _ = p2p_sender.send(EventType::SendNewBlock(block.clone()));
+                        refresh_pool(&mut node, &p2p_sender, &stratum_sender).await;
+                        println!("added new block");
+                        println!("{:#?}", block);
+                    }
+                    Ok(None) => {}
+                    Err(err) => println!("{:?}", err),
+                }
+            },
+            _ = chain_mining_interval.tick(), if chain_nodes.values().any(|chain_node| !chain_node.mining_queue.is_empty()) => {
+                for (name, chain_node) in chain_nodes.iter_mut().filter(|(_, chain_node)| !chain_node.mining_queue.is_empty()) {
+                    match chain_node.mine_next().await {
+                        Ok(Some(block)) => {
+                            let _ = p2p_sender.send(for_chain(name, EventType::SendNewBlock(block.clone())));
+                            println!("added new block to chain {}", name);
+                            println!("{:#?}", block);
+                        }
+                        Ok(None) => {}
+                        Err(err) => println!("{:?}", err),
+                    }
+                }
+            },
+            _ = bridge_interval.tick(), if config.bridge.is_some() => {
+                let anchor = BridgeAnchor::new(&node.chain.genesis, &node.chain.latest_block);
+                // Nothing new to commit, the genesis block is the same on every node
+                if node.chain.latest_block.id > Height::GENESIS && bridged.as_ref() != Some(&anchor) {
+                    match config.bridge.as_ref() {
+                        Some(BridgeTarget::Chain(name)) => match chain_nodes.get_mut(name) {
+                            Some(chain_node) => {
+                                chain_node.mining_queue.push(anchor.to_block_data(), Priority::Normal);
+                                info!("Anchoring block {} at height {} in chain {}", anchor.hash, anchor.height, name);
+                                bridged = Some(anchor);
+                            }
+                            None => warn!("Cannot anchor in unknown chain {}", name),
+                        },
+                        Some(BridgeTarget::Notary{host, port, path}) => {
+                            let (host, port, path, notary_sender) = (host.clone(), *port, path.clone(), notary_sender.clone());
+                            tokio::spawn(async move {
+                                let result = bridge::notarize(&host, port, &path, &anchor).await.map_err(|err| format!("{:?}", err));
+                                let _ = notary_sender.send((anchor, result));
+                            });
+                        }
+                        None => {}
+                    }
+                }
+            },
+            Some((anchor, result)) = notary_rcv.recv() => {
+                match result {
+                    Ok(status) if (200..300).contains(&status) => {
+                        info!("Notarized block {} at height {}", anchor.hash, anchor.height);
+                        bridged = Some(anchor);
+                    }
+                    // Retried on the next bridge interval
+                    Ok(status) => warn!("Notary rejected block {} with status {}", anchor.hash, status),
+                    Err(err) => warn!("Error notarizing block {}: {}", anchor.hash, err),
+                }
+            },
+            // In slot-based mode queued jobs are only mined in our slots
+            _ = slot_interval.tick(), if node.slots.is_some() => {
+                match node.produce_slot_block(Utc::now().timestamp_millis()).await {
+                    Ok(Some(block)) => {
+                        let _ = p2p_sender.send(EventType::SendNewBlock(block.clone()));
+                        refresh_pool(&mut node, &p2p_sender, &stratum_sender).await;
+                        info!("Produced block {} at height {}", block.hash, block.id);
+                    }
+                    Ok(None) => {}
+                    Err(err) => error!("Error producing slot block: {:?}", err),
+                }
+            },
+            _ = wal_interval.tick(), if node.storage.queued_writes() > 0 || chain_nodes.values().any(|chain_node| chain_node.storage.queued_writes() > 0) => {
+                // Failed writes stay queued and are retried
+                if let Err(err) = node.storage.flush_writes().await {
+                    error!("Error writing queued blocks: {:?}", err);
+                }
+                for (name, chain_node) in chain_nodes.iter_mut() {
+                    if let Err(err) = chain_node.storage.flush_writes().await {
+                        error!("Error writing queued blocks of chain {}: {:?}", name, err);
+                    }
+                }
+            },
+            _ = stats_interval.tick() => {
+                // Failed counts are kept and added on the next flush
+                if let Err(err) = node.flush_stats(Instant::now()).await {
+                    error!("Error saving node stats: {:?}", err);
+                }
+            },
+            _ = snapshot_interval.tick() => {
+                write_snapshot(&node.storage, &config.snapshot);
+                write_chain_snapshots(&chain_nodes, &config);
+            },
+            _ = config_watch_interval.tick() => {
+                if config_watcher.as_mut().is_some_and(FileWatcher::changed) {
+                    println!("Config file changed, reloading");
+                    reload_config(&mut config, &mut node, &mut chain_nodes, &p2p_sender, &set_log_level);
+                }
+            },
+            _ = sync_timeout_interval.tick() => {
+                node.expire_sync_sessions(Instant::now()).await;
+                for chain_node in chain_nodes.values_mut() {
+                    chain_node.expire_sync_sessions(Instant::now()).await;
+                }
+            },
+            _ = stale_tip_interval.tick() => {
+                for event in node.check_stale_tip(Instant::now()) {
+                    let _ = p2p_sender.send(event);
+                }
+                for (name, chain_node) in chain_nodes.iter_mut() {
+                    for event in chain_node.check_stale_tip(Instant::now()) {
+                        let _ = p2p_sender.send(for_chain(name, event));
+                    }
+                }
+            },
+            _ = finality_interval.tick() => {
+                // The genesis block is final by definition, there is nothing to gossip yet
+                if node.chain.finalized.id > Height::GENESIS {
+                    let _ = p2p_sender.send(EventType::SendFinalizedCheckpoint{checkpoint: node.chain.finalized.clone()});
+                }
+                for (name, chain_node) in chain_nodes.iter().filter(|(_, chain_node)| chain_node.chain.finalized.id > Height::GENESIS) {
+                    let _ = p2p_sender.send(for_chain(name, EventType::SendFinalizedCheckpoint{checkpoint: chain_node.chain.finalized.clone()}));
+                }
+            },
+            _ = service_interval.tick() => {
+                let now = Utc::now().timestamp_millis();
+                node.services.expire(now);
+                if config.p2p && !config.services.is_empty() {
+                    let announcement = ServiceAnnouncement{peer_id: p2p::LOCAL_PEER_ID.to_string(), services: config.services.clone(), announced_at: now};
+                    let _ = p2p_sender.send(EventType::SendServiceAnnouncement{announcement});
+                }
+            },
             event = main_rcv.recv() => {
                 match event {
-                    Some(EventType::SendLatestBlockRequest{receiver}) => {
-                        info!("Get latest block for: {:?}", receiver);
-                        let block = chain.latest_block.clone();
-                        let _ = p2p_sender.send(EventType::SendLatestBlock{receiver, block});
-                        },
-                    Some(EventType::ReceivedChain{chain: mut incoming_chain}) => {
-                        info!("Received chain");
-                        println!("Chain: {:?}", incoming_chain);
-                        match chain.update(&mut db_client, &mut incoming_chain).await {
-                            Ok(_) => info!("Successfully updated chain."),
-                            Err(err) => error!("Error updating chain: {:?}", err)
+                    Some(EventType::ReceivedPoolMessage{sender, message: PoolMessage::Job(job)}) => {
+                        match pool_worker.as_mut().filter(|worker| worker.coordinator == sender) {
+                            Some(worker) => {
+                                info!("Mining pool job {} for block {}", job.job_id, job.template.id);
+                                worker.start_job(job, share_sender.clone());
+                            }
+                            None => warn!("Ignoring pool job from {}, we did not join its pool", sender),
                         }
-                        },
-                    Some(EventType::ReceivedChainRequest{receiver}) => {
-                        info!("Received chain request");
-                        match Chain::get_chain(&mut db_client).await {
-                            Ok(chain) => {
-                                info!("SEND CHAIN");
-                                let _ = p2p_sender.send(EventType::SendChain{receiver, chain});
-                            },
-                            Err(err) => error!("{:?}", err)
+                    }
+                    Some(EventType::ReceivedPoolMessage{sender, message: message @ (PoolMessage::Accepted(_) | PoolMessage::Rejected{..})}) => {
+                        if let Some(worker) = pool_worker.as_mut().filter(|worker| worker.coordinator == sender) {
+                            worker.record_result(&message);
                         }
-                        },
-                    Some(EventType::ReceivedLatestBlock{sender, block}) => {
-                            info!("Got latest block: {:?}", block);
-                            // Check if our chain is the longest
-                            // TODO improve/extend checks
-                            if &chain.latest_block.id < &block.id {
-                                    let _ = p2p_sender.send(EventType::SendChainRequest{receiver: sender});
-                            } else {
-                                info!("We got the longest chain, not syncing");
+                    }
+                    Some(event @ (EventType::ReceivedChainRequest{..} | EventType::ReceivedSnapshotRequest{..})) => {
+                        let _ = serving_sender.send(ServingJob::Request(event));
+                    }
+                    // Complete chains we asked for are checked by the sync worker, tails are added block by block
+                    Some(EventType::ReceivedChain{sender, session_id, chain})
+                        if chain.iter().any(|block| block.id == Height::GENESIS) && node.sync_manager.is_pending(&session_id) =>
+                    {
+                        let _ = sync_sender.send(SyncJob{chain: node.chain.clone(), sender, session_id, blocks: chain});
+                    }
+                    Some(EventType::ListenAddressesChanged{addresses}) => node_info.listen_addresses = addresses,
+                    Some(EventType::ReceivedRpcCall{sender, call}) => {
+                        let reply = rpc::answer(&mut node.storage, &node_info, &node.mining_queue, &node.orphans, call).await;
+                        let server = if sender.starts_with(ipc::IPC_PREFIX) { &ipc_sender } else { &rpc_sender };
+                        let _ = server.send(EventType::SendRpcReply{receiver: sender, reply});
+                    }
+                    Some(EventType::ReplayEvents{events}) => {
+                        if let Err(err) = replay_events(&mut node, &mut chain_nodes, events).await {
+                            println!("replay failed: {:?}", err);
+                        }
+                    }
+                    Some(EventType::ForChain{chain, event}) => match chain_nodes.get_mut(&chain) {
+                        Some(chain_node) => {
+                            for outgoing in chain_node.handle_event(*event, Instant::now()).await {
+                                let _ = p2p_sender.send(for_chain(&chain, outgoing));
                             }
-                        },
-                    Some(EventType::ReceivedNewBlock(block)) => {
-                            info!("Received new block: {:?}", block);
-                            // Check if our chain is the longest
-                            // TODO improve/extend checks
-                           match chain.add_block(&mut db_client, block).await {
-                            Ok(()) => info!("Added new block"),
-                            Err(err) => error!("Error adding new block: {:?}", err)
-                           }
                         }
-                 _ => {}
+                        None => warn!("Ignoring event of unknown chain {}", chain),
+                    },
+                    Some(event) => {
+                        for outgoing in node.handle_event(event, Instant::now()).await {
+                            dispatch(outgoing, &p2p_sender, &stratum_sender);
+                        }
+                    }
+                    None => {}
                 }
             },
-            user_input = stdin.next_line() => {
-                let input = user_input.expect("can read line").expect("can read line");
+            _ = shutdown.recv() => {
+                println!("Shutting down");
+                shut_down(&mut node, &mut chain_nodes, &config).await;
+                return Ok(());
+            },
+            user_input = stdin.next_line(), if stdin_open => {
+                let input = match user_input {
+                    Ok(Some(input)) => input,
+                    Ok(None) => {
+                        info!("No more commands, stdin is closed");
+                        stdin_open = false;
+                        continue;
+                    }
+                    Err(err) => {
+                        error!("Error reading command: {}", err);
+                        continue;
+                    }
+                };
                 match input {
+                    _ if input.starts_with('@') => {
+                        if let Some((name, command)) = chains::split_scope(&input) {
+                            match chain_nodes.get_mut(name) {
+                                Some(chain_node) => chain_command(name, chain_node, command, &config, &p2p_sender).await,
+                                None => println!("unknown chain {}, see `chains`", name),
+                            }
+                        }
+                    }
+                    _ if input.starts_with("chains") => {
+                        if chain_nodes.is_empty() {
+                            println!("no additional chains, start with --chain NAME to follow one");
+                        }
+                        for (name, chain_node) in chain_nodes.iter() {
+                            println!("chain: {} | height: {} | latest: {} | genesis: {}", name, chain_node.chain.latest_block.id, chain_node.chain.latest_block.hash, chain_node.chain.genesis.hash);
+                        }
+                    }
+
                     // libp2p commands
+                    _ if input.starts_with("ls p") && input.contains("--services") => {
+                        for service in config.services.iter() {
+                            println!("peer: {} (us) | {}: {}", *p2p::LOCAL_PEER_ID, service.kind, service.url);
+                        }
+                        let now = Utc::now().timestamp_millis();
+                        node.services.expire(now);
+                        for announcement in node.services.list() {
+                            for service in announcement.services.iter() {
+                                println!(
+                                    "peer: {} | {}: {} | announced {}s ago",
+                                    announcement.peer_id, service.kind, service.url, (now - announcement.announced_at).max(0) / 1000,
+                                );
+                            }
+                        }
+                        if config.services.is_empty() && node.services.is_empty() {
+                            println!("No services announced");
+                        }
+                    }
                     _ if input.starts_with("ls p") => {
                         let _ = p2p_sender.send(EventType::ListPeers);
+                        if input.contains("--verbose") {
+                            for (rank, (peer, score, stats)) in node.sync_manager.ranking().iter().enumerate() {
+                                let latency = stats.latency.map_or("-".to_owned(), |latency| format!("{:.2}s", latency.as_secs_f64()));
+                                println!(
+                                    "sync rank: {} | peer: {} | score: {:.3} | best block: {} | valid: {} | invalid: {} | latency: {}",
+                                    rank + 1, peer, score, stats.best_block, stats.valid, stats.invalid, latency,
+                                );
+                            }
+                        }
+                    }
+                    _ if input.starts_with("p2p badmsgs") => {
+                        let _ = p2p_sender.send(EventType::ListBadMessages);
+                    }
+                    _ if input.starts_with("p2p replay ") => {
+                        if config.p2p {
+                            let path = PathBuf::from(input.replace("p2p replay ", "").trim());
+                            let _ = p2p_sender.send(EventType::ReplayCapture{path});
+                        } else {
+                            println!("p2p is disabled (--no-p2p)");
+                        }
                     }
 
                     // Blockchain commands
                     _ if input.starts_with("chain validate") => {
-                        if let Ok(_) = chain
-                            .validate_chain(&mut db_client)
-                            .await
-                            .map_err(|err| println!("{:?}", err))
-                        {
-                            println!("chain valid.")
+                        match Chain::get_chain(&mut node.storage).await {
+                            Ok(blocks) => {
+                                println!("validating {} blocks in the background", blocks.len());
+                                let _ = validation_sender.send(ValidationJob{chain: node.chain.clone(), blocks});
+                            }
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    // A file instead of --repair is a candidate chain, see dryrun.rs
+                    _ if input.starts_with("chain check ") && !input.trim_start_matches("chain check").trim().starts_with("--") => {
+                        let path = PathBuf::from(input.trim_start_matches("chain check").trim());
+                        match dryrun::read_chain(&path) {
+                            Ok(blocks) => match dryrun::check_chain(&mut node, &blocks).await {
+                                Ok(verdict) => println!("{} blocks up to height {}: {}", blocks.len(), blocks[blocks.len() - 1].id, verdict),
+                                Err(err) => println!("{}", err),
+                            },
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("chain check") => {
+                        let repair = match input.trim_start_matches("chain check").split_whitespace().collect::<Vec<_>>()[..] {
+                            [] => Ok(None),
+                            ["--repair", name] => RepairStrategy::from_name(name).map(Some).ok_or(()),
+                            _ => Err(()),
+                        };
+                        match repair {
+                            Ok(repair) => match repair_chain(&mut node.storage, &node.chain.genesis, repair).await {
+                                Ok(None) => println!("chain intact."),
+                                Ok(Some(strategy)) => {
+                                    if let Err(err) = node.chain.reload(&mut node.storage).await {
+                                        println!("{:?}", err);
+                                    }
+                                    println!("latest block is now {}", node.chain.latest_block.id);
+                                    if strategy == RepairStrategy::Resync {
+                                        let _ = p2p_sender.send(EventType::RequestLatestBlocks);
+                                    }
+                                }
+                                Err(err) => println!("{}", err),
+                            },
+                            Err(()) => println!("usage: chain check [--repair {}]", integrity::REPAIR_STRATEGIES.join("|")),
+                        }
+                    }
+                    _ if input.starts_with("bridge status") => {
+                        match &config.bridge {
+                            Some(BridgeTarget::Chain(name)) => println!("bridge: chain {} | every {}s", name, config.bridge_interval.as_secs()),
+                            Some(BridgeTarget::Notary{host, port, path}) => println!("bridge: notary http://{}:{}{} | every {}s", host, port, path, config.bridge_interval.as_secs()),
+                            None => println!("no bridge, start with --bridge chain:NAME or --bridge http://HOST[:PORT]/PATH"),
+                        }
+                        if let Some(anchor) = &bridged {
+                            println!("last anchored: {} at height {}", anchor.hash, anchor.height);
+                        }
+                    }
+                    _ if input.starts_with("bridge verify") => {
+                        match config.bridge.as_ref() {
+                            Some(BridgeTarget::Chain(name)) => match chain_nodes.get_mut(name) {
+                                Some(chain_node) => match bridge::find_anchors(&mut chain_node.storage, &node.chain.genesis).await {
+                                    Ok(anchors) => {
+                                        if anchors.is_empty() {
+                                            println!("no anchors of our chain in chain {} yet", name);
+                                        }
+                                        for (block, anchor) in anchors {
+                                            match bridge::verify_anchor(&mut node.storage, &anchor).await {
+                                                Ok(()) => println!("height: {} | hash: {} | anchored in block {} | confirmations: {} | valid", anchor.height, anchor.hash, block.hash, chain_node.chain.confirmations(&block)),
+                                                Err(err) => println!("height: {} | hash: {} | anchored in block {} | INVALID: {:?}", anchor.height, anchor.hash, block.hash, err),
+                                            }
+                                        }
+                                    }
+                                    Err(err) => println!("{:?}", err),
+                                },
+                                None => println!("unknown chain {}", name),
+                            },
+                            Some(BridgeTarget::Notary{..}) => println!("anchors sent to a notary have to be checked with the notary"),
+                            None => println!("no bridge configured"),
+                        }
+                    }
+                    _ if input.starts_with("chain replay") => {
+                        let started_at = Instant::now();
+                        let mut progress = |done: u64, total: u64| {
+                            if done.is_multiple_of(REINDEX_PROGRESS_STEP) || done == total {
+                                println!("replayed {}/{} blocks", done, total);
+                            }
+                        };
+                        match replay::replay(&node.chain, &mut node.storage, &mut progress).await {
+                            Ok(report) => match report.divergence {
+                                None => println!("replayed {} blocks in {:.1}s, state matches.", report.blocks, started_at.elapsed().as_secs_f64()),
+                                Some(divergence) => println!("first divergent block: height: {} | hash: {} | {}", divergence.height, divergence.hash, divergence.reason),
+                            },
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("chain generate") => {
+                        if !config.regtest {
+                            println!("chain generate requires a node started with --regtest");
+                        } else {
+                            match GenerateConfig::from_args(&input.replace("chain generate", "")) {
+                                Ok(generate_config) => {
+                                    let started_at = Instant::now();
+                                    let mut progress = |done: u64, total: u64| {
+                                        if done.is_multiple_of(REINDEX_PROGRESS_STEP) || done == total {
+                                            println!("generated {}/{} blocks", done, total);
+                                        }
+                                    };
+                                    match chaingen::generate(&mut node.chain, &mut node.storage, &generate_config, &mut progress).await {
+                                        Ok(block) => println!(
+                                            "generated {} blocks in {:.1}s, latest block: height: {} | hash: {}",
+                                            generate_config.blocks,
+                                            started_at.elapsed().as_secs_f64(),
+                                            block.id,
+                                            block.hash
+                                        ),
+                                        Err(err) => println!("{:?}", err),
+                                    }
+                                }
+                                Err(err) => println!("{}", err),
+                            }
+                        }
+                    }
+                    _ if input.starts_with("chain graph") => {
+                        match GraphConfig::from_args(&input.replace("chain graph", "")) {
+                            Ok(graph_config) => match graph::export(&node.chain, &mut node.storage, graph_config.from).await {
+                                Ok(dot) => match fs::write(&graph_config.out, dot) {
+                                    Ok(()) => println!("wrote chain graph to {}, render it with `dot -Tsvg {} -o chain.svg`", graph_config.out.display(), graph_config.out.display()),
+                                    Err(err) => println!("can not write {}: {}", graph_config.out.display(), err),
+                                },
+                                Err(err) => println!("{:?}", err),
+                            },
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("chain du") => match UsageConfig::from_args(&input.replace("chain du", "")) {
+                        Ok(usage_config) => match diskusage::report(&node.chain, &mut node.storage, &usage_config).await {
+                            Ok(usage) => println!("{}", usage),
+                            Err(err) => println!("{:?}", err),
+                        },
+                        Err(err) => println!("{}", err),
+                    },
+                    _ if input.starts_with("chain uncles") => {
+                        match Chain::get_stale_blocks(&mut node.storage, 10).await {
+                            Ok(stale_blocks) => {
+                                for stale in stale_blocks {
+                                    println!("height: {} | hash: {} | miner: {}", stale.block.id, stale.block.hash, stale.block.miner);
+                                }
+                            }
+                            Err(err) => println!("{:?}", err),
+                        }
+                        if let Ok(rate) = node.chain.get_stale_rate(&mut node.storage).await.map_err(|err| println!("{:?}", err)) {
+                            println!("stale rate (last {} heights): {:.2}%", STALE_RATE_WINDOW, rate * 100.0);
+                        }
+                    }
+                    _ if input.starts_with("sync history") => print_sync_history(&mut node.storage, input.replace("sync history", "").trim()).await,
+                    _ if input.starts_with("sync sessions") => {
+                        for session in node.sync_manager.sessions() {
+                            let duration = session.duration.unwrap_or_else(|| session.started_at.elapsed());
+                            println!("session: {} | peer: {} | blocks: {} | duration: {:.2}s | {:?}", session.id, session.peer, session.blocks_transferred, duration.as_secs_f64(), session.outcome);
+                        }
+                    }
+                    _ if input.starts_with("node reload") => reload_config(&mut config, &mut node, &mut chain_nodes, &p2p_sender, &set_log_level),
+                    _ if input.starts_with("node install-service") => print_service_definition(&input.replace("node install-service", "")),
+                    _ if input.starts_with("node paths") => print_paths(&config),
+                    _ if input.starts_with("node completions") => match cli::completions(input.replace("node completions", "").trim()) {
+                        Ok(script) => print!("{}", script),
+                        Err(err) => println!("{}", err),
+                    },
+                    _ if input.starts_with("node manpage") => print!("{}", cli::manpage()),
+                    _ if input.starts_with("node latency") => println!("{}", latency::report()),
+                    _ if input.starts_with("node slow") => {
+                        let report = watchdog::report();
+                        println!("slow queries (over {}ms): {}", config.watchdog.query.as_millis(), report.query);
+                        println!("slow validations (over {}ms): {}", config.watchdog.validation.as_millis(), report.validation);
+                        println!("slow event handling (over {}ms): {}", config.watchdog.gossip.as_millis(), report.gossip);
+                    }
+                    _ if input.starts_with("node stats") => {
+                        // Flushed first, so the counts of the current run are included
+                        if let Err(err) = node.flush_stats(Instant::now()).await {
+                            println!("counts since the last flush are missing: {:?}", err);
+                        }
+                        match node.storage.get_node_stats().await {
+                            Ok(stats) => println!("{}", stats),
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("node id") => println!("{}", node_info),
+                    _ if input.starts_with("node capabilities") => {
+                        println!("{}", serde_json::to_string_pretty(&capabilities::capabilities()).expect("can jsonify capabilities"));
+                    }
+                    _ if input.starts_with("node tip") => {
+                        let now = Instant::now();
+                        let threshold = node.stale_tip.threshold(node.chain.params.target_block_time_ms);
+                        println!(
+                            "height: {} | unchanged for: {}s | stale after: {}s | stale tips: {}",
+                            node.chain.latest_block.id, node.stale_tip.age(now).as_secs(), threshold.as_secs(), node.stale_tip.stale_tips
+                        );
+                        for (name, chain_node) in chain_nodes.iter() {
+                            println!("@{} height: {} | unchanged for: {}s | stale tips: {}", name, chain_node.chain.latest_block.id, chain_node.stale_tip.age(now).as_secs(), chain_node.stale_tip.stale_tips);
+                        }
+                    }
+                    _ if input.starts_with("node cache") => match node.storage.cache_stats() {
+                        Some(stats) => println!("cached blocks: {} | hits: {} | misses: {} | queued writes: {}", stats.blocks, stats.hits, stats.misses, node.storage.queued_writes()),
+                        None => println!("the in-memory storage has no block cache"),
+                    },
+                    _ if input.starts_with("node gc") => {
+                        let stats = stale_collector.stats;
+                        println!(
+                            "pruning: {:?} | runs: {} | blocks pruned: {} | payloads pruned: {} | bytes reclaimed: {}",
+                            stale_collector.pruning, stats.runs, stats.blocks_pruned, stats.bodies_pruned, stats.bytes_reclaimed
+                        );
+                    }
+                    _ if input.starts_with("node reindex") => {
+                        let started_at = Instant::now();
+                        let mut progress = |done: u64, total: u64| {
+                            if done.is_multiple_of(REINDEX_PROGRESS_STEP) || done == total {
+                                println!("reindexed {}/{} blocks", done, total);
+                            }
+                        };
+                        match Chain::reindex(&mut node.storage, &mut progress).await {
+                            Ok(()) => println!("reindex done in {:.1}s", started_at.elapsed().as_secs_f64()),
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("node loadgen stop") => {
+                        match node.loadgen.take() {
+                            Some(loadgen) => println!("loadgen stopped: {}", loadgen.report(Instant::now())),
+                            None => println!("loadgen is not running"),
+                        }
+                    }
+                    _ if input.starts_with("node loadgen") => {
+                        if !config.regtest {
+                            println!("loadgen requires a node started with --regtest");
+                        } else if node.loadgen.is_some() {
+                            println!("loadgen is already running, stop it with 'node loadgen stop'");
+                        } else {
+                            match LoadgenConfig::from_args(&input.replace("node loadgen", "")) {
+                                Ok(loadgen_config) => {
+                                    println!("generating {} blocks/s with {} byte payloads", loadgen_config.blocks_per_sec, loadgen_config.payload_size);
+                                    // Blocks that take longer to mine than the interval delay the following ones
+                                    loadgen_interval = time::interval(loadgen_config.interval());
+                                    loadgen_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                                    loadgen_report_interval = time::interval_at(time::Instant::now() + LOADGEN_REPORT_INTERVAL, LOADGEN_REPORT_INTERVAL);
+                                    node.loadgen = Some(LoadGenerator::new(loadgen_config, Instant::now()));
+                                }
+                                Err(err) => println!("{}", err),
+                            }
+                        }
+                    }
+                    _ if input.starts_with("pool start ") => {
+                        if node.pool.is_some() {
+                            println!("pool is already running, stop it with 'pool stop'");
+                        } else {
+                            let data = input.replace("pool start ", "");
+                            node.pool = Some(Coordinator::new(data, DEFAULT_SHARE_DIFFICULTY));
+                            refresh_pool(&mut node, &p2p_sender, &stratum_sender).await;
+                            println!("pool started, workers can join with 'pool join {}'", node.chain.miner);
+                        }
+                    }
+                    _ if input.starts_with("pool stop") => {
+                        match node.pool.take() {
+                            Some(_) => println!("pool stopped"),
+                            None => println!("pool is not running"),
+                        }
+                    }
+                    _ if input.starts_with("pool join ") => {
+                        let coordinator = input.replace("pool join ", "").trim().to_owned();
+                        if let Some(worker) = &pool_worker {
+                            let _ = p2p_sender.send(EventType::SendPoolMessage{receiver: worker.coordinator.clone(), message: PoolMessage::Unsubscribe});
+                        }
+                        let _ = p2p_sender.send(EventType::SendPoolMessage{receiver: coordinator.clone(), message: PoolMessage::Subscribe});
+                        println!("joining pool of {}", coordinator);
+                        pool_worker = Some(PoolWorker::new(coordinator));
+                    }
+                    _ if input.starts_with("pool leave") => {
+                        match pool_worker.take() {
+                            Some(worker) => {
+                                let _ = p2p_sender.send(EventType::SendPoolMessage{receiver: worker.coordinator.clone(), message: PoolMessage::Unsubscribe});
+                                println!("left pool of {} after {} shares", worker.coordinator, worker.shares_found);
+                            }
+                            None => println!("not mining for a pool"),
+                        }
+                    }
+                    _ if input.starts_with("pool status") => {
+                        if let Some(pool) = &node.pool {
+                            println!("coordinating pool | job: {} | share difficulty: {}", pool.job_id(), pool.share_difficulty);
+                            for (worker, stats) in pool.workers() {
+                                println!("worker: {} | accepted: {} | rejected: {} | blocks: {}", worker, stats.accepted, stats.rejected, stats.blocks);
+                            }
+                        }
+                        if let Some(worker) = &pool_worker {
+                            println!("mining for pool of {} | job: {:?} | shares: {} | accepted: {} | rejected: {}", worker.coordinator, worker.job_id, worker.shares_found, worker.shares_accepted, worker.shares_rejected);
+                        }
+                        if node.pool.is_none() && pool_worker.is_none() {
+                            println!("not part of a pool");
+                        }
+                    }
+                    _ if input.starts_with("keys show") => {
+                        println!("peer ID: {}", *p2p::LOCAL_PEER_ID);
+                        match key_store.as_ref().map(KeyStore::signing_key) {
+                            Some(Ok(signing_key)) => println!("signing key: {}", hex::encode(signing_key.public().encode())),
+                            Some(Err(err)) => println!("{:?}", err),
+                            None => println!("no signing key, start the node with --keys DIR"),
+                        }
+                        if let Some(Ok(encryption_key)) = key_store.as_ref().map(KeyStore::encryption_key) {
+                            println!("encryption key: {}", payload::encode_public_key(&(&encryption_key).into()));
+                        }
+                    }
+                    _ if input.starts_with("keys rotate") => {
+                        match key_store.as_ref().map(KeyStore::rotate_signing_key) {
+                            Some(Ok(rotation)) => {
+                                println!("signing key {} replaced by {}", rotation.old_key, rotation.new_key);
+                                println!("announcement: {}", rotation.to_block_data());
+                                print_enqueued(node.mining_queue.push(rotation.to_block_data(), Priority::High));
+                                // The signing key is our wallet unless we have a wallet file
+                                if config.wallet.is_none() {
+                                    node.set_wallet(key_store.as_ref().and_then(|key_store| key_store.signing_key().ok()).map(Wallet::from_keypair));
+                                }
+                            }
+                            Some(Err(err)) => println!("{:?}", err),
+                            None => println!("no signing key, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("tx create ") => {
+                        let args = input.replace("tx create ", "");
+                        let transfer = multisig::split_multisig(args.trim()).and_then(|(policy, args)| {
+                            let (from, args) = hdwallet::split_from(args)?;
+                            let (fee, args) = fees::split_fee(args)?;
+                            let (inputs, args) = utxo::split_inputs(args)?;
+                            match args.split_once(' ') {
+                                Some((recipient, amount)) => {
+                                    let recipient = address::parse(recipient)?;
+                                    amount
+                                        .trim()
+                                        .parse::<u64>()
+                                        .map(|amount| (policy, from, recipient, amount, fee, inputs))
+                                        .map_err(|_| BlockchainError::Error(format!("invalid amount: {}", amount)))
+                                }
+                                None => Err(BlockchainError::Error(
+                                    "usage: tx create [--multisig M KEY,KEY...|--from INDEX] [--fee N] [--inputs TXID:VOUT,...] RECIPIENT AMOUNT".to_owned(),
+                                )),
+                            }
+                        });
+                        let (transfer, inputs) = match transfer {
+                            Ok((policy, from, recipient, amount, fee, inputs)) => (Ok((policy, from, recipient, amount, fee)), inputs),
+                            Err(err) => (Err(err), vec![]),
+                        };
+                        let transaction = match (transfer, key_store.as_ref()) {
+                            (Ok((Some(policy), _, recipient, amount, fee)), _) => Ok(Transaction::new_multisig(policy, recipient, amount, fee)),
+                            (Ok((None, Some(index), recipient, amount, fee)), Some(key_store)) => match key_store.wallet() {
+                                Ok(Some(wallet)) if index < wallet.addresses => Ok(Transaction::new(&wallet.keypair(index).public(), recipient, amount, fee)),
+                                Ok(Some(_)) => Err(BlockchainError::Error(format!("the wallet did not hand out address {} yet", index))),
+                                Ok(None) => Err(BlockchainError::Error("no wallet, create one with wallet new".to_owned())),
+                                Err(err) => Err(err),
+                            },
+                            (Ok((None, None, recipient, amount, fee)), Some(key_store)) => {
+                                key_store.signing_key().map(|signing_key| Transaction::new(&signing_key.public(), recipient, amount, fee))
+                            }
+                            (Ok((None, None, recipient, amount, fee)), None) => match &node.wallet {
+                                Some(wallet) => Ok(Transaction::new(&wallet.keypair().public(), recipient, amount, fee)),
+                                None => Err(BlockchainError::Error("no signing key, start the node with --keys DIR or --wallet FILE".to_owned())),
+                            },
+                            (Ok(_), None) => Err(BlockchainError::Error("no signing key, start the node with --keys DIR".to_owned())),
+                            (Err(err), _) => Err(err),
+                        };
+                        match transaction.map(|transaction| Transaction { inputs, ..transaction }) {
+                            Ok(transaction) => {
+                                println!("txid: {}", transaction.id());
+                                println!("{}", transaction.to_json());
+                            }
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("tx sign ") => {
+                        let args = input.replace("tx sign ", "");
+                        let (partial, json) = match args.strip_prefix("--partial ") {
+                            Some(json) => (true, json),
+                            None => (false, args.as_str()),
+                        };
+                        let keys = key_store
+                            .as_ref()
+                            .map(KeyStore::transaction_keys)
+                            .or_else(|| node.wallet.as_ref().map(|wallet| Ok(vec![wallet.keypair().clone()])));
+                        match (Transaction::from_json(json), keys) {
+                            (Ok(mut transaction), Some(Ok(keys))) => {
+                                let signers = transaction.signers(&keys);
+                                let signed = match signers.first() {
+                                    None => Err(BlockchainError::Error(format!("none of our keys can sign for {}", transaction.sender))),
+                                    Some(_) if partial => signers.iter().try_fold(0, |_, signer| transaction.sign_partial(signer)).map(Some),
+                                    Some(signer) => transaction.sign(signer).map(|_| None),
+                                };
+                                match signed {
+                                    Ok(Some(signed)) => {
+                                        let threshold = transaction.multisig.as_ref().map_or(0, |multisig| multisig.threshold);
+                                        println!("{} of {} required signatures", signed, threshold);
+                                        println!("{}", transaction.to_json());
+                                    }
+                                    Ok(None) => println!("{}", transaction.to_json()),
+                                    Err(err) => println!("{}", err),
+                                }
+                            }
+                            (Err(err), _) | (_, Some(Err(err))) => println!("{}", err),
+                            (_, None) => println!("no signing key, start the node with --keys DIR or --wallet FILE"),
+                        }
+                    }
+                    _ if input.starts_with("tx combine ") => {
+                        let copies = input.replace("tx combine ", "").split_whitespace().map(Transaction::from_json).collect::<Result<Vec<_>, _>>();
+                        match copies {
+                            Ok(copies) if !copies.is_empty() => {
+                                let mut combined = copies[0].clone();
+                                match copies[1..].iter().try_for_each(|copy| combined.combine(copy)) {
+                                    Ok(()) => println!("{}", combined.to_json()),
+                                    Err(err) => println!("{}", err),
+                                }
+                            }
+                            Ok(_) => println!("usage: tx combine TX_JSON TX_JSON..."),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("wallet show") => {
+                        match &node.wallet {
+                            Some(wallet) => {
+                                let address = wallet.address();
+                                println!("address: {} | key: {}", address::from_public_key(&wallet.keypair().public()), address);
+                                match Chain::get_address_blocks(&mut node.storage, &address).await {
+                                    Ok(blocks) => {
+                                        let balance = hdwallet::balance(&address, &blocks);
+                                        println!("transactions: {} | balance: {}", balance.transactions, balance.available());
+                                    }
+                                    Err(err) => println!("{:?}", err),
+                                }
+                                match Chain::get_chain(&mut node.storage).await {
+                                    Ok(blocks) => {
+                                        let earnings = fees::earnings(&node.chain.params, &node.chain.miner, &blocks);
+                                        println!("blocks mined by us: {} | earned: {}", earnings.blocks, earnings.total());
+                                    }
+                                    Err(err) => println!("{:?}", err),
+                                }
+                            }
+                            None => println!("no wallet, start the node with --wallet FILE or --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet new") => {
+                        match key_store.as_ref().map(|key_store| (key_store.wallet(), key_store)) {
+                            Some((Ok(None), key_store)) => {
+                                let wallet = HdWallet::generate();
+                                match key_store.save_wallet(&wallet) {
+                                    Ok(()) => println!("mnemonic (write it down, it restores all addresses): {}", wallet.mnemonic),
+                                    Err(err) => println!("{:?}", err),
+                                }
+                            }
+                            Some((Ok(Some(_)), _)) => println!("there is a wallet already, wallet restore replaces it"),
+                            Some((Err(err), _)) => println!("{:?}", err),
+                            None => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet restore ") => {
+                        match (HdWallet::restore(&input.replace("wallet restore ", "")), key_store.as_ref()) {
+                            (Ok(mut wallet), Some(key_store)) => match wallet.rescan(&mut node.storage).await {
+                                Ok(used) => {
+                                    for (index, address, balance) in used.iter() {
+                                        println!("{} | {} | transactions: {} | balance: {}", hdwallet::derivation_path(*index), address, balance.transactions, balance.available());
+                                    }
+                                    println!("{} used addresses | balance: {}", used.len(), used.iter().map(|(_, _, balance)| balance.available()).sum::<u64>());
+                                    if let Err(err) = key_store.save_wallet(&wallet) {
+                                        println!("{:?}", err);
+                                    }
+                                }
+                                Err(err) => println!("{:?}", err),
+                            },
+                            (Err(err), _) => println!("{}", err),
+                            (_, None) => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet history") => {
+                        let config = HistoryConfig::from_args(&input.replace("wallet history", ""));
+                        let keys = key_store.as_ref().map(KeyStore::transaction_keys);
+                        match (config, keys) {
+                            (Ok(config), Some(Ok(keys))) => {
+                                let addresses = keys.iter().map(|key| hex::encode(key.public().encode())).collect::<Vec<_>>();
+                                match history::wallet_history(&node.chain, &mut node.storage, &addresses).await {
+                                    Ok(entries) if config.export => match config.path {
+                                        Some(path) => match fs::write(&path, history::to_csv(&entries)) {
+                                            Ok(()) => println!("exported {} transactions to {}", entries.len(), path.display()),
+                                            Err(err) => println!("can not write {}: {}", path.display(), err),
+                                        },
+                                        None => print!("{}", history::to_csv(&entries)),
+                                    },
+                                    Ok(entries) => {
+                                        for entry in entries.iter() {
+                                            println!("{}", entry);
+                                        }
+                                        println!("transactions: {} | balance: {}", entries.len(), entries.last().map_or(0, |entry| entry.balance));
+                                    }
+                                    Err(err) => println!("{:?}", err),
+                                }
+                            }
+                            (Err(err), _) | (_, Some(Err(err))) => println!("{}", err),
+                            (_, None) => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet addresses") => {
+                        match key_store.as_ref().map(KeyStore::wallet) {
+                            Some(Ok(Some(wallet))) => {
+                                for index in 0..wallet.addresses {
+                                    let address = wallet.address(index);
+                                    match Chain::get_address_blocks(&mut node.storage, &address).await {
+                                        Ok(blocks) => {
+                                            let balance = hdwallet::balance(&address, &blocks);
+                                            println!("{} | {} | transactions: {} | balance: {}", hdwallet::derivation_path(index), address, balance.transactions, balance.available());
+                                        }
+                                        Err(err) => println!("{:?}", err),
+                                    }
+                                }
+                            }
+                            Some(Ok(None)) => println!("no wallet, create one with wallet new"),
+                            Some(Err(err)) => println!("{:?}", err),
+                            None => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet address") => {
+                        match key_store.as_ref().map(|key_store| (key_store.wallet(), key_store)) {
+                            Some((Ok(Some(mut wallet)), key_store)) => {
+                                let (index, address) = wallet.next_address();
+                                match key_store.save_wallet(&wallet) {
+                                    Ok(()) => println!("{} | {}", hdwallet::derivation_path(index), address),
+                                    Err(err) => println!("{:?}", err),
+                                }
+                            }
+                            Some((Ok(None), _)) => println!("no wallet, create one with wallet new"),
+                            Some((Err(err), _)) => println!("{:?}", err),
+                            None => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet watched") => {
+                        match key_store.as_ref().map(KeyStore::watch_list) {
+                            Some(Ok(watch_list)) => print_watched(&mut node.storage, &watch_list).await,
+                            Some(Err(err)) => println!("{:?}", err),
+                            None => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet watch ") => {
+                        let args = input.replace("wallet watch ", "");
+                        let (address, label) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+                        match key_store.as_ref().map(|key_store| (key_store.watch_list(), key_store)) {
+                            Some((Ok(mut watch_list), key_store)) => {
+                                let from = node.chain.latest_block.id + 1;
+                                let watched = address::parse(address)
+                                    .and_then(|address| watch_list.watch(address, label.trim().to_owned(), from))
+                                    .and_then(|_| key_store.save_watch_list(&watch_list));
+                                match watched {
+                                    Ok(()) => println!("watching {} from height {} on, see wallet rescan for earlier blocks", address, from),
+                                    Err(err) => println!("{}", err),
+                                }
+                            }
+                            Some((Err(err), _)) => println!("{:?}", err),
+                            None => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet unwatch ") => {
+                        let address = input.replace("wallet unwatch ", "").trim().to_owned();
+                        match key_store.as_ref().map(|key_store| (key_store.watch_list(), key_store)) {
+                            Some((Ok(mut watch_list), key_store)) => {
+                                if !watch_list.unwatch(&address) {
+                                    println!("not watching {}", address);
+                                } else if let Err(err) = key_store.save_watch_list(&watch_list) {
+                                    println!("{:?}", err);
+                                }
+                            }
+                            Some((Err(err), _)) => println!("{:?}", err),
+                            None => println!("no wallet, start the node with --keys DIR"),
+                        }
+                    }
+                    _ if input.starts_with("wallet rescan") => {
+                        let from = input.replace("wallet rescan", "").trim().strip_prefix("--from-height").map(|height| height.trim().parse::<u64>());
+                        match (from, key_store.as_ref()) {
+                            (Some(Ok(from)), Some(key_store)) => {
+                                let rescanned = async {
+                                    let mut watch_list = key_store.watch_list()?;
+                                    watch_list.rescan_from(Height(from));
+                                    key_store.save_watch_list(&watch_list)?;
+                                    if let Some(mut wallet) = key_store.wallet()? {
+                                        let used = wallet.rescan(&mut node.storage).await?;
+                                        key_store.save_wallet(&wallet)?;
+                                        println!("{} used wallet addresses", used.len());
+                                    }
+                                    Ok::<WatchList, BlockchainError>(watch_list)
+                                };
+                                match rescanned.await {
+                                    Ok(watch_list) => print_watched(&mut node.storage, &watch_list).await,
+                                    Err(err) => println!("{:?}", err),
+                                }
+                            }
+                            (_, None) => println!("no wallet, start the node with --keys DIR"),
+                            _ => println!("usage: wallet rescan --from-height H"),
+                        }
+                    }
+                    _ if input.starts_with("multisig address ") => {
+                        match Multisig::parse(&input.replace("multisig address ", "")) {
+                            Ok(policy) => println!("{}", policy.address()),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("tx broadcast ") => {
+                        match Transaction::from_json(&input.replace("tx broadcast ", "")).and_then(|transaction| transaction.verify().map(|_| transaction)) {
+                            Ok(transaction) => {
+                                println!("txid: {}", transaction.id());
+                                match mempool::admit(&mut node.chain, &mut node.storage, &node.transactions, &node.mining_queue, transaction.clone()).await {
+                                    Ok(true) => {
+                                        println!("added to the transaction pool ({} pending)", node.transactions.len());
+                                        broadcast_txs.insert(transaction.id(), transaction);
+                                    }
+                                    Ok(false) => println!("already pending"),
+                                    Err(err) => println!("{}", err),
+                                }
+                            }
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("tx receipt ") => {
+                        let id = input.replace("tx receipt ", "").trim().to_owned();
+                        match Chain::get_transaction_blocks(&mut node.storage, &id).await.map(|blocks| blocks.into_iter().next()) {
+                            Ok(Some(block)) => print_receipt(Chain::get_receipt(&mut node.storage, &block.hash).await),
+                            Ok(None) => println!("transaction {} is not on our main chain, see tx status", id),
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("block receipt ") => {
+                        let hash = input.replace("block receipt ", "").trim().to_owned();
+                        print_receipt(Chain::get_receipt(&mut node.storage, &hash).await);
+                    }
+                    _ if input.starts_with("tx pending") => {
+                        let pending = node.transactions.pending();
+                        if pending.is_empty() {
+                            println!("no pending transactions");
+                        }
+                        for transaction in pending {
+                            println!(
+                                "txid: {} | sender: {} | recipient: {} | amount: {} | fee: {}",
+                                transaction.id(),
+                                transaction.sender,
+                                transaction.recipient,
+                                transaction.amount,
+                                transaction.fee
+                            );
+                        }
+                    }
+                    _ if input.starts_with("tx get ") || input.starts_with("tx status ") => {
+                        let id = input.replace("tx get ", "").replace("tx status ", "").trim().to_owned();
+                        match transactions::find(&node.chain, &mut node.storage, &node.mining_queue, &node.transactions, &broadcast_txs, &id).await {
+                            Ok(Some((transaction, status))) if input.starts_with("tx get ") => {
+                                println!("{}", transaction.to_json());
+                                println!("status: {}", status);
+                            }
+                            Ok(Some((_, status))) => println!("{}", status),
+                            Ok(None) => println!("unknown transaction {}", id),
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("chain upgrades") => {
+                        for activation in consensus::ACTIVATIONS.iter() {
+                            let status = if node.chain.latest_block.id >= activation.height { "active" } else { "pending" };
+                            println!("{:?} | height: {} | {}", activation.feature, activation.height, status);
+                        }
+                    }
+                    _ if input.starts_with("chain network") => {
+                        let params = &node.chain.params;
+                        println!("genesis: {} | parameters digest: {}", node.chain.genesis.hash, params.digest());
+                        println!("target block time: {}ms | max reorg depth: {}", params.target_block_time_ms, params.max_reorg_depth);
+                        println!(
+                            "initial reward: {} | halving interval: {} | max supply: {} | supply at {}: {}",
+                            params.initial_reward, params.halving_interval, params.max_supply, node.chain.latest_block.id, params.supply_at(node.chain.latest_block.id)
+                        );
+                    }
+                    _ if input.starts_with("address txs ") => {
+                        if let Some((address, min_confirmations)) = min_confirmations_args(&input.replace("address txs ", ""), &config) {
+                            match Chain::get_address_blocks(&mut node.storage, &address).await {
+                                Ok(blocks) => {
+                                    for block in blocks.iter().filter(|block| node.chain.confirmations(block) >= min_confirmations) {
+                                        println!("height: {} | hash: {} | confirmations: {}", block.id, block.hash, node.chain.confirmations(block));
+                                    }
+                                }
+                                Err(err) => println!("{:?}", err),
+                            }
+                        }
+                    }
+                    _ if input.starts_with("address utxos ") => {
+                        let address = input.replace("address utxos ", "").trim().to_owned();
+                        match Chain::get_utxos(&mut node.storage, &address).await {
+                            Ok(utxos) => {
+                                for utxo in utxos.iter() {
+                                    println!("{} | amount: {} | block: {}", utxo.outpoint, utxo.amount, utxo.block_hash);
+                                }
+                                println!("outputs: {} | balance: {}", utxos.len(), utxo::balance(&utxos));
+                            }
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("address convert ") => {
+                        let input = input.replace("address convert ", "");
+                        match address::parse(&input).and_then(|internal| address::from_internal(&internal).map(|address| (address, internal))) {
+                            Ok((address, internal)) => println!("address: {} | key: {}", address, internal),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("address earnings ") => {
+                        let address = input.replace("address earnings ", "").trim().to_owned();
+                        match Chain::get_address_blocks(&mut node.storage, &address).await {
+                            Ok(blocks) => {
+                                let earnings = fees::earnings(&node.chain.params, &address, &blocks);
+                                println!(
+                                    "blocks mined: {} | rewards: {} | fees: {} | total: {}",
+                                    earnings.blocks, earnings.rewards, earnings.fees, earnings.total()
+                                );
+                            }
+                            Err(err) => println!("{:?}", err),
+                        }
+                    }
+                    _ if input.starts_with("blocks search ") => {
+                        if let Some((query, min_confirmations)) = min_confirmations_args(&input.replace("blocks search ", ""), &config) {
+                            match Chain::search_blocks(&mut node.storage, query.trim_matches('"'), 10).await {
+                                Ok(results) => {
+                                    for result in results.iter().filter(|result| node.chain.confirmations(&result.block) >= min_confirmations) {
+                                        println!("height: {} | hash: {} | {}", result.block.id, result.block.hash, result.headline);
+                                    }
+                                }
+                                Err(err) => println!("{:?}", err),
+                            }
+                        }
+                    }
+                    _ if input.starts_with("block confirmations ") => {
+                        let hash = input.replace("block confirmations ", "");
+                        match Chain::get_block(&mut node.storage, hash.trim()).await {
+                            Ok(block) => println!("{}", node.chain.confirmations(&block)),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("anchor file ") => {
+                        let paths = input.replace("anchor file ", "");
+                        let digests = paths
+                            .split_whitespace()
+                            .map(|path| anchor::hash_file(&PathBuf::from(path)))
+                            .collect::<Result<Vec<String>, BlockchainError>>();
+                        match digests.and_then(Anchor::new) {
+                            Ok(anchor) => {
+                                for (path, digest) in paths.split_whitespace().zip(&anchor.digests) {
+                                    println!("{} {}", digest, path);
+                                }
+                                print_enqueued(node.mining_queue.push(anchor.to_block_data(), Priority::Normal));
+                            }
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("anchor verify ") => {
+                        if let Some((path, min_confirmations)) = min_confirmations_args(&input.replace("anchor verify ", ""), &config) {
+                            let proof = match anchor::hash_file(&PathBuf::from(path)) {
+                                Ok(digest) => AnchorProof::create(&mut node.storage, &digest).await,
+                                Err(err) => Err(err),
+                            };
+                            match proof.and_then(|proof| proof.verify().map(|_| proof)) {
+                                Ok(proof) if proof.confirmations < min_confirmations => {
+                                    println!("anchored in block {} with only {} confirmations", proof.block.hash, proof.confirmations);
+                                }
+                                Ok(proof) => {
+                                    println!("{}", serde_json::to_string_pretty(&proof).expect("can jsonify proof"));
+                                    println!(
+                                        "anchored in block {} at height {} with {} confirmations",
+                                        proof.block.hash, proof.block.id, proof.confirmations
+                                    );
+                                }
+                                Err(err) => println!("{}", err),
+                            }
+                        }
+                    }
+                    _ if input.starts_with("state prove ") => {
+                        let args = input.replace("state prove ", "");
+                        let (key, height) = match args.split_once("--height") {
+                            Some((key, height)) => (key.trim(), height.trim().parse::<u64>().map(Height)),
+                            None => (args.trim(), Ok(node.chain.latest_block.id)),
+                        };
+                        match height {
+                            Ok(height) => match StateProof::create(&mut node.storage, key, height).await {
+                                Ok(proof) => {
+                                    println!("{}", serde_json::to_string(&proof).expect("can jsonify proof"));
+                                    println!("{} = {} after block {} at height {}", proof.key, proof.value, proof.header.hash, proof.header.id);
+                                }
+                                Err(err) => println!("{}", err),
+                            },
+                            Err(_) => println!("usage: state prove KEY [--height H]"),
+                        }
+                    }
+                    _ if input.starts_with("rpc docs") => {
+                        println!("{}", serde_json::to_string_pretty(&rpc::openrpc()).expect("can jsonify document"));
+                    }
+                    _ if input.starts_with("state balance ") => {
+                        let address = input.replace("state balance ", "").trim().to_owned();
+                        match node.chain.get_balance(&mut node.storage, &address).await {
+                            Ok(balance) => println!("received: {} | sent: {} | balance: {}", balance.received, balance.sent, balance.available()),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("state verify ") => {
+                        let proof = serde_json::from_str::<StateProof>(&input.replace("state verify ", ""))
+                            .map_err(|err| BlockchainError::Error(format!("invalid state proof: {}", err)));
+                        match proof.and_then(|proof| proof.verify().map(|_| proof)) {
+                            Ok(proof) => println!("{} = {} after block {} at height {}", proof.key, proof.value, proof.header.hash, proof.header.id),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("block mine --to ") => {
+                        let args = input.replace("block mine --to ", "");
+                        let (keys, data) = args.split_once(' ').unwrap_or((&args, ""));
+                        let mut recipients = keys.split(',').map(payload::decode_public_key).collect::<Result<Vec<_>, _>>();
+                        // We can always read our own payloads
+                        if let (Ok(recipients), Some(Ok(encryption_key))) = (&mut recipients, key_store.as_ref().map(KeyStore::encryption_key)) {
+                            recipients.push((&encryption_key).into());
+                        }
+                        match recipients.and_then(|recipients| payload::encrypt_payload(data, &recipients)) {
+                            Ok(data) => print_enqueued(node.mining_queue.push(data, Priority::Normal)),
+                            Err(err) => println!("{}", err),
+                        }
+                    }
+                    _ if input.starts_with("block decrypt ") => {
+                        let hash = input.replace("block decrypt ", "");
+                        match (Chain::get_block(&mut node.storage, hash.trim()).await, key_store.as_ref().map(KeyStore::encryption_key)) {
+                            (Err(err), _) => println!("{:?}", err),
+                            (_, None) => println!("no encryption key, start the node with --keys DIR"),
+                            (_, Some(Err(err))) => println!("{:?}", err),
+                            (Ok(block), Some(Ok(encryption_key))) => match payload::decrypt_payload(&block.data, &encryption_key) {
+                                Some(Ok(data)) => println!("{}", data),
+                                Some(Err(err)) => println!("{}", err),
+                                None => println!("the payload of the block is not encrypted"),
+                            },
                         }
                     }
                     _ if input.starts_with("block mine ") => {
                         let data = input.replace("block mine ", "");
-                        println!("Mining...");
-                        if let Ok(block) = chain
-                            .mine_block(data, &mut db_client)
-                            .await
-                            .map_err(|err| println!("{:?}", err))
-                        {
-                            let _ = p2p_sender.send(EventType::SendNewBlock(block.clone()));
-                            println!("added new block");
-                            println!("{:#?}", block);
+                        print_enqueued(node.mining_queue.push(data, Priority::Normal));
+                    }
+                    _ if input.starts_with("mine queue add ") => {
+                        let args = input.replace("mine queue add ", "");
+                        match args.split_once(' ').map(|(priority, rest)| (priority.parse::<Priority>(), fees::split_fee(rest))) {
+                            Some((Ok(priority), Ok((fee, data)))) => print_enqueued(node.mining_queue.push_with_fee(data.to_owned(), priority, fee)),
+                            Some((Err(err), _) | (_, Err(err))) => println!("{}", err),
+                            None => println!("usage: mine queue add high|normal|low [--fee N] BLOCK_DATA"),
+                        }
+                    }
+                    _ if input.starts_with("mine queue priority ") => {
+                        let args = input.replace("mine queue priority ", "");
+                        let mut args = args.split_whitespace();
+                        match (args.next().and_then(|id| id.parse::<u64>().ok()), args.next().map(str::parse::<Priority>)) {
+                            (Some(id), Some(Ok(priority))) => {
+                                if !node.mining_queue.set_priority(id, priority) {
+                                    println!("no pending job {}", id);
+                                }
+                            }
+                            (_, Some(Err(err))) => println!("{}", err),
+                            _ => println!("usage: mine queue priority JOB_ID high|normal|low"),
+                        }
+                    }
+                    _ if input.starts_with("mine queue top ") => {
+                        match input.replace("mine queue top ", "").trim().parse::<u64>() {
+                            Ok(id) if node.mining_queue.move_to_front(id) => {}
+                            _ => println!("no pending job {}", input.replace("mine queue top ", "").trim()),
+                        }
+                    }
+                    _ if input.starts_with("mine queue cancel ") => {
+                        match input.replace("mine queue cancel ", "").trim().parse::<u64>().ok().and_then(|id| node.mining_queue.remove(id)) {
+                            Some(job) => println!("cancelled job {}", job.id),
+                            None => println!("no pending job {}", input.replace("mine queue cancel ", "").trim()),
                         }
                     }
+                    _ if input.starts_with("mine queue") => {
+                        if node.mining_queue.is_empty() {
+                            println!("no pending mining jobs");
+                        }
+                        for job in node.mining_queue.jobs() {
+                            println!("job: {} | priority: {} | fee: {} | data: {}", job.id, job.priority, job.fee, job.data);
+                        }
+                        println!("{}", node.mining_queue.occupancy());
+                        println!("{}", node.orphans.occupancy());
+                    }
                     _ if input.starts_with("block get ") => {
-                        let data = input.replace("block get ", "");
-                        if let Ok(block) = Chain::get_block(&mut db_client, &data).await {
-                            println!("{:#?}", block)
+                        if let Some((hash, min_confirmations)) = min_confirmations_args(&input.replace("block get ", ""), &config) {
+                            if let Ok(block) = Chain::get_block(&mut node.storage, &hash).await {
+                                let confirmations = node.chain.confirmations(&block);
+                                if confirmations < min_confirmations {
+                                    println!("block {} has only {} confirmations", block.hash, confirmations);
+                                } else {
+                                    println!("{:#?}", block);
+                                    println!("payload: {}", payload::payload_hash(&block.data));
+                                    println!("confirmations: {}", confirmations);
+                                }
+                            }
+                        }
+                    }
+                    _ if input.starts_with("payload get ") => {
+                        let hash = input.replace("payload get ", "");
+                        match node.storage.get_payload(hash.trim()).await {
+                            Ok(data) => println!("{}", data),
+                            Err(err) => println!("{}", err),
                         }
                     }
                     _ if input.starts_with("block latest") => {
-                        if let Ok(block) = Chain::get_latest_block(&mut db_client)
+                        if let Ok(block) = Chain::get_latest_block(&mut node.storage)
                             .await
                             .map_err(|err| {
                                 println!("Error getting latest block: {:?}", err);
@@ -183,10 +1623,19 @@ async fn run(
                             println!("{:#?}", block)
                         }
                     }
+                    _ if input.starts_with("block check ") => {
+                        match dryrun::parse_block(input.trim_start_matches("block check ")) {
+                            Ok(block) => match dryrun::check_block(&node.chain, &mut node.storage, &block).await {
+                                Ok(verdict) => println!("block {} at height {}: {}", block.hash, block.id, verdict),
+                                Err(err) => println!("{}", err),
+                            },
+                            Err(err) => println!("{}", err),
+                        }
+                    }
                     _ if input.starts_with("block validate ") => {
                         let data = input.replace("block validate ", "");
-                        if let Ok(block) = Chain::get_block(&mut db_client, &data).await {
-                            match Chain::check_if_block_valid(&mut db_client, &block).await {
+                        if let Ok(block) = Chain::get_block(&mut node.storage, &data).await {
+                            match Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &block).await {
                                 Ok(()) => {
                                     println!("Valid block. ID of block: {}", block.id)
                                 }
@@ -197,6 +1646,7 @@ async fn run(
                         }
                     }
                     _ if input.starts_with("exit") => {
+                        shut_down(&mut node, &mut chain_nodes, &config).await;
                         return Ok(());
                     }
                     _ => {
@@ -207,5 +1657,399 @@ async fn run(
                 println!("Enter command:");
             }
         }
+
+        match node.watch_head().await {
+            Ok(events) => {
+                if !events.is_empty() {
+                    let _ = p2p_sender.send(update_handshake(&node, &config));
+                    service::notify(&format!("STATUS=height {}", node.chain.latest_block.id));
+                }
+                for event in events {
+                    let _ = serving_sender.send(ServingJob::Head(event.clone()));
+                    let _ = head_sender.send(event);
+                }
+            }
+            Err(err) => error!("Error publishing head events: {:?}", err),
+        }
+    }
+}
+
+// Writes the queued blocks and the snapshots, on `exit` and when we are told to stop
+async fn shut_down(node: &mut Node, chain_nodes: &mut BTreeMap<String, Node>, config: &Config) {
+    service::notify("STOPPING=1");
+    if let Err(err) = node.flush_stats(Instant::now()).await {
+        println!("node stats of this run are lost: {:?}", err);
+    }
+    if let Err(err) = node.storage.flush_writes().await {
+        println!("queued blocks are written on the next start: {:?}", err);
+    }
+    for chain_node in chain_nodes.values_mut() {
+        if let Err(err) = chain_node.storage.flush_writes().await {
+            println!("queued blocks are written on the next start: {:?}", err);
+        }
+    }
+    write_snapshot(&node.storage, &config.snapshot);
+    write_chain_snapshots(chain_nodes, config);
+}
+
+// Prints the definition of a service running the node with our options, see service.rs
+fn print_service_definition(args: &str) {
+    let manager = match args.trim() {
+        "" => ServiceManager::current(),
+        name => ServiceManager::from_name(name),
+    };
+    let manager = match manager {
+        Some(manager) => manager,
+        None => {
+            println!("service manager has to be one of {:?}, Windows services are not supported yet", service::SERVICE_MANAGERS);
+            return;
+        }
+    };
+    let definition = env::current_exe()
+        .and_then(|exe| Ok((exe, env::current_dir()?)))
+        .map(|(exe, working_dir)| manager.definition(&exe, &env::args().skip(1).collect::<Vec<_>>(), &working_dir));
+    match definition {
+        Ok(definition) => {
+            println!("{}", definition);
+            println!("{}", manager.instructions());
+        }
+        Err(err) => println!("{}", err),
+    }
+}
+
+// Parses the config again and applies the changed settings that can be reloaded, see reload.rs
+fn reload_config(
+    config: &mut Config,
+    node: &mut Node,
+    chain_nodes: &mut BTreeMap<String, Node>,
+    p2p_sender: &mpsc::UnboundedSender<EventType>,
+    set_log_level: &SetLogLevel,
+) {
+    let new = match Config::from_args(env::args().skip(1)) {
+        Ok(new) => new,
+        Err(err) => {
+            println!("Not reloading, invalid config: {}", err);
+            return;
+        }
+    };
+    let changes = ConfigChanges::between(config, &new);
+    if changes.restart_required {
+        println!("Some of the changed options take effect after a restart");
+    }
+    if changes.reloaded.is_empty() {
+        println!("No reloadable option changed");
+        return;
+    }
+    if let Err(err) = set_log_level(new.log_level) {
+        error!("Error setting the log level: {}", err);
+    }
+    blockchain::set_mining_threads(new.mining_threads);
+    watchdog::set_thresholds(new.watchdog);
+    if new.target_peers != config.target_peers {
+        let _ = p2p_sender.send(EventType::UpdateTargetPeers{target_peers: new.target_peers});
+    }
+    if new.mempool_policy != config.mempool_policy {
+        let dropped = node.mining_queue.set_policy(new.mempool_policy, Utc::now().timestamp_millis());
+        println!("Dropped {} mining jobs the new mempool policy does not admit", dropped);
+    }
+    node.stale_tip.factor = new.stale_tip_factor;
+    for chain_node in chain_nodes.values_mut() {
+        chain_node.stale_tip.factor = new.stale_tip_factor;
+    }
+    config.log_level = new.log_level;
+    config.mining_threads = new.mining_threads;
+    config.target_peers = new.target_peers;
+    config.mempool_policy = new.mempool_policy;
+    config.min_confirmations = new.min_confirmations;
+    config.stale_tip_factor = new.stale_tip_factor;
+    config.watchdog = new.watchdog;
+    println!("Reloaded {}", changes.reloaded.join(", "));
+}
+
+// The resolved locations of the node's files, see datadir.rs
+fn print_paths(config: &Config) {
+    let path = |path: Option<PathBuf>| path.map_or_else(|| "-".to_owned(), |path| path.display().to_string());
+    let data_dir = config.data_dir.as_ref();
+    println!("data dir: {}", path(data_dir.map(|data_dir| data_dir.root.clone())));
+    println!("config: {}", path(data_dir.map(DataDir::config).filter(|file| file.exists())));
+    match data_dir.map(DataDir::network).filter(|network| network.exists()) {
+        Some(network) => println!("network: {}", network.display()),
+        None => println!("network: {}", config.network.name()),
+    }
+    println!("keys: {}", path(config.keys.clone()));
+    match (&config.storage, &config.db_name) {
+        (StorageKind::Postgres, Some(db_name)) => println!("chain and peer store: database {} on {}", db_name, config.db_host),
+        _ => println!("chain and peer store: {}", path(config.snapshot.clone())),
+    }
+    println!("mempool: {}", path(config.mempool.clone()));
+    println!("write-ahead queue: {}", path(config.wal.clone()));
+    println!("capture: {}", path(config.capture.clone()));
+    println!("logs: {}", path(data_dir.map(DataDir::log_file)));
+}
+
+// Our best block and features for the handshakes with new peers
+fn update_handshake(node: &Node, config: &Config) -> EventType {
+    let best_block = Checkpoint { id: node.chain.latest_block.id, hash: node.chain.latest_block.hash.clone() };
+    EventType::UpdateHandshake{best_block, features: Features::of(config).with(Features::of_chain(&node.chain))}
+}
+
+// Splits the --min-confirmations option off the arguments of a data retrieval command, prints the
+// error if it is invalid
+fn min_confirmations_args(args: &str, config: &Config) -> Option<(String, u64)> {
+    match config::split_min_confirmations(args, config.min_confirmations) {
+        Ok(args) => Some(args),
+        Err(err) => {
+            println!("{}", err);
+            None
+        }
+    }
+}
+
+// Checks the stored chain and, if it is corrupted, truncates it to its valid part when a repair
+// strategy is given. Returns the strategy if the chain was repaired.
+// The node's wallet: the --wallet file, created on the first start, else our signing key
+fn load_wallet(config: &Config, key_store: Option<&KeyStore>) -> Result<Option<Wallet>, BlockchainError> {
+    match (&config.wallet, key_store) {
+        (Some(path), _) => {
+            let passphrase = env::var(KEY_PASSPHRASE_ENV)
+                .map_err(|_| BlockchainError::Error(format!("--wallet requires the passphrase in {}", KEY_PASSPHRASE_ENV)))?;
+            Wallet::load_or_generate(path, &passphrase).map(Some)
+        }
+        (None, Some(key_store)) => key_store.signing_key().map(|signing_key| Some(Wallet::from_keypair(signing_key))),
+        (None, None) => Ok(None),
+    }
+}
+
+async fn repair_chain(storage: &mut Storage, genesis: &Block, repair: Option<RepairStrategy>) -> Result<Option<RepairStrategy>, BlockchainError> {
+    let corruption = match integrity::check(storage, genesis).await? {
+        Some(corruption) => corruption,
+        None => return Ok(None),
+    };
+    let strategy = repair.ok_or_else(|| {
+        BlockchainError::Error(format!(
+            "chain corrupted: {}. Repair it with --repair truncate to drop the blocks above the last valid one, or --repair resync to also sync from peers again",
+            corruption.reason
+        ))
+    })?;
+    println!("chain corrupted: {}", corruption.reason);
+    integrity::truncate(storage, &corruption).await?;
+    match corruption.last_valid {
+        Some(height) => println!("removed the blocks above {}", height),
+        None => println!("removed all blocks"),
+    }
+    Ok(Some(strategy))
+}
+
+// Messages for hashers connected via stratum go to the stratum server, all other events to the p2p layer
+fn dispatch(
+    event: EventType,
+    p2p_sender: &mpsc::UnboundedSender<EventType>,
+    stratum_sender: &mpsc::UnboundedSender<EventType>,
+) {
+    let sender = match &event {
+        EventType::SendPoolMessage{receiver, ..} if stratum::is_stratum_worker(receiver) => stratum_sender,
+        _ => p2p_sender,
+    };
+    let _ = sender.send(event);
+}
+
+// Hands out jobs for the next block to our pool workers, if the pool's template is outdated
+async fn refresh_pool(
+    node: &mut Node,
+    p2p_sender: &mpsc::UnboundedSender<EventType>,
+    stratum_sender: &mpsc::UnboundedSender<EventType>,
+) {
+    match node.refresh_pool().await {
+        Ok(events) => {
+            for event in events {
+                dispatch(event, p2p_sender, stratum_sender);
+            }
+        }
+        Err(err) => error!("Error refreshing pool template: {:?}", err),
+    }
+}
+
+async fn print_watched(storage: &mut Storage, watch_list: &WatchList) {
+    for watched in watch_list.addresses.iter() {
+        match watch::history(storage, &watched.address, watched.from).await {
+            Ok(history) => {
+                println!(
+                    "{} {} | from height {} | transactions: {} | received: {} | sent: {} | balance: {}",
+                    watched.address, watched.label, watched.from, history.transactions.len(), history.balance.received, history.balance.sent, history.balance.available()
+                );
+                for (height, transaction) in history.transactions.iter() {
+                    let direction = if transaction.recipient == watched.address { "in" } else { "out" };
+                    println!("    height: {} | txid: {} | {}: {}", height, transaction.id(), direction, transaction.amount);
+                }
+            }
+            Err(err) => println!("{:?}", err),
+        }
+    }
+}
+
+async fn print_sync_history(storage: &mut Storage, count: &str) {
+    let limit = match count {
+        "" => SYNC_HISTORY_DEFAULT,
+        count => match count.parse::<i64>() {
+            Ok(limit) => limit,
+            Err(_) => return println!("invalid count: {}", count),
+        },
+    };
+    match storage.get_sync_history(limit).await {
+        Ok(history) => {
+            for record in history.iter() {
+                let finished_at = Utc.timestamp_millis_opt(record.finished_at).single().map_or_else(|| record.finished_at.to_string(), |at| at.to_string());
+                println!(
+                    "{} | session: {} | peer: {} | trigger: {} | blocks: {} | duration: {}ms | {} | tip: {} {} -> {} {}{}",
+                    finished_at,
+                    record.session_id,
+                    record.peer,
+                    record.trigger,
+                    record.blocks_transferred,
+                    record.duration_ms,
+                    record.outcome,
+                    record.old_tip.id,
+                    record.old_tip.hash,
+                    record.new_tip.id,
+                    record.new_tip.hash,
+                    if record.replaced { " | replaced our chain" } else { "" }
+                );
+            }
+        }
+        Err(err) => println!("{:?}", err),
+    }
+}
+
+fn print_receipt(receipt: Result<Option<Receipt>, BlockchainError>) {
+    match receipt {
+        Ok(Some(receipt)) => {
+            println!("height: {} | hash: {} | outcome: {:?} | gas used: {}", receipt.height, receipt.block_hash, receipt.outcome, receipt.gas_used);
+            for event in receipt.events.iter() {
+                println!("    {:?}", event);
+            }
+        }
+        Ok(None) => println!("no receipt, the block is not on our main chain or carries no state-changing payload"),
+        Err(err) => println!("{:?}", err),
+    }
+}
+
+fn print_enqueued(enqueued: Enqueued) {
+    match enqueued {
+        Enqueued::Added(id) => println!("queued as mining job {}", id),
+        Enqueued::Merged(id) => println!("already queued as mining job {}", id),
+        Enqueued::Replaced { id, replaced } => println!("queued as mining job {}, replacing the conflicting job {}", id, replaced),
+        Enqueued::Conflicting(id) => println!(
+            "not queued, conflicts with mining job {} (a replacement has to offer a fee at least {}% higher)",
+            id, REPLACEMENT_FEE_INCREASE
+        ),
+        Enqueued::Rejected => println!("not queued, below the priority floor or the minimum fee rate or beyond the caps of the full mempool"),
+    }
+}
+
+// Wraps an event of an additional chain, see chains.rs
+// Handles the events of a replayed capture on in-memory copies of the nodes, so a replay neither
+// changes our chains nor sends anything to peers
+async fn replay_events(node: &mut Node, chain_nodes: &mut BTreeMap<String, Node>, events: Vec<EventType>) -> Result<(), BlockchainError> {
+    let mut isolated = node.isolated().await?;
+    let mut isolated_chains = BTreeMap::new();
+    let mut dropped = 0;
+    for event in events {
+        let outgoing = match event {
+            EventType::ForChain{chain, event} => match chain_nodes.get_mut(&chain) {
+                Some(chain_node) => {
+                    let isolated_chain = match isolated_chains.entry(chain) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => entry.insert(chain_node.isolated().await?),
+                    };
+                    isolated_chain.handle_event(*event, Instant::now()).await
+                }
+                None => vec![],
+            },
+            event => isolated.handle_event(event, Instant::now()).await,
+        };
+        dropped += outgoing.len();
+    }
+    println!("replayed on a copy of the chain: height {} -> {}", node.chain.latest_block.id, isolated.chain.latest_block.id);
+    for (name, isolated_chain) in isolated_chains.iter() {
+        println!("replayed on a copy of chain {}: height {} -> {}", name, chain_nodes[name].chain.latest_block.id, isolated_chain.chain.latest_block.id);
+    }
+    println!("{} outgoing messages were not sent", dropped);
+    Ok(())
+}
+
+fn for_chain(chain: &str, event: EventType) -> EventType {
+    EventType::ForChain{chain: chain.to_owned(), event: Box::new(event)}
+}
+
+// The commands available on the additional chains (`@CHAIN COMMAND`)
+async fn chain_command(name: &str, node: &mut Node, command: &str, config: &Config, p2p_sender: &mpsc::UnboundedSender<EventType>) {
+    match command {
+        _ if command.starts_with("block mine ") => {
+            print_enqueued(node.mining_queue.push(command.replace("block mine ", ""), Priority::Normal));
+        }
+        _ if command.starts_with("block latest") => println!("{:#?}", node.chain.latest_block),
+        _ if command.starts_with("block get ") => {
+            if let Some((hash, min_confirmations)) = min_confirmations_args(&command.replace("block get ", ""), config) {
+                match Chain::get_block(&mut node.storage, &hash).await {
+                    Ok(block) if node.chain.confirmations(&block) < min_confirmations => {
+                        println!("block {} has only {} confirmations", block.hash, node.chain.confirmations(&block));
+                    }
+                    Ok(block) => {
+                        println!("{:#?}", block);
+                        println!("confirmations: {}", node.chain.confirmations(&block));
+                    }
+                    Err(err) => println!("{}", err),
+                }
+            }
+        }
+        _ if command.starts_with("mine queue") => {
+            if node.mining_queue.is_empty() {
+                println!("no pending mining jobs");
+            }
+            for job in node.mining_queue.jobs() {
+                println!("job: {} | priority: {} | data: {}", job.id, job.priority, job.data);
+            }
+        }
+        _ if command.starts_with("chain validate") => match node.chain.validate_chain(&mut node.storage).await {
+            Ok(()) => println!("chain valid."),
+            Err(err) => println!("{:?}", err),
+        },
+        _ if command.starts_with("chain sync") => {
+            let _ = p2p_sender.send(for_chain(name, EventType::RequestLatestBlocks));
+        }
+        _ if command.starts_with("sync history") => print_sync_history(&mut node.storage, command.replace("sync history", "").trim()).await,
+        _ if command.starts_with("sync sessions") => {
+            for session in node.sync_manager.sessions() {
+                let duration = session.duration.unwrap_or_else(|| session.started_at.elapsed());
+                println!("session: {} | peer: {} | blocks: {} | duration: {:.2}s | {:?}", session.id, session.peer, session.blocks_transferred, duration.as_secs_f64(), session.outcome);
+            }
+        }
+        _ => {
+            println!("commands of chain {}:", name);
+            println!("@{} block mine BLOCK_DATA", name);
+            println!("@{} block latest", name);
+            println!("@{} block get BLOCK_HASH [--min-confirmations N]", name);
+            println!("@{} mine queue", name);
+            println!("@{} chain validate", name);
+            println!("@{} chain sync //ask our peers on the chain for their latest block", name);
+            println!("@{} sync sessions", name);
+            println!("@{} sync history [N]", name);
+        }
+    }
+}
+
+fn write_chain_snapshots(chain_nodes: &BTreeMap<String, Node>, config: &Config) {
+    for spec in config.chains.iter() {
+        if let Some(chain_node) = chain_nodes.get(&spec.name) {
+            write_snapshot(&chain_node.storage, &config.snapshot.as_ref().map(|path| spec.chain_file(path)));
+        }
+    }
+}
+
+fn write_snapshot(storage: &Storage, path: &Option<PathBuf>) {
+    if let Some(path) = path {
+        if let Err(err) = storage.snapshot(path) {
+            error!("Error writing snapshot: {:?}", err);
+        }
     }
 }
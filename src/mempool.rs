@@ -0,0 +1,141 @@
+// Transaction pool (`tx broadcast`, `tx pending`): signed transactions waiting to be mined. While no
+// mining job is queued (see mining.rs) the node drains the pool into a block carrying a batch of up to
+// MAX_BLOCK_TRANSACTIONS of them, the highest fees first (see transactions.rs). A transaction is held
+// once however often it is broadcast, and leaves the pool when it is mined or shows up in a block of
+// the main chain. The pool is shared by its clones, so it can be handed to other tasks.
+// Double spends are refused with a DoubleSpend error (see admit): a transaction confirmed already, one
+// spending an output a main chain block spent already or a pending or queued transaction spends, or
+// a transfer without inputs sending more than its sender has left after the transfers pending or
+// queued from it. Blocks only check the outputs, the balance check is a policy of the pool.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::mining::MiningQueue;
+use crate::storage::Storage;
+use crate::transactions::Transaction;
+use std::sync::{Arc, Mutex};
+
+// Transactions beyond this are rejected until blocks made room
+pub const MAX_PENDING_TRANSACTIONS: usize = 10_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct TransactionPool {
+    // By ID, in the order they were added
+    pending: Arc<Mutex<Vec<(String, Transaction)>>>,
+}
+
+impl TransactionPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds the transaction if its signatures are valid and it spends no output a pending transaction
+    // spends. Returns false if it is pending already.
+    pub fn insert(&self, transaction: Transaction) -> Result<bool, BlockchainError> {
+        transaction.verify()?;
+        let id = transaction.id();
+        let mut pending = self.pending.lock().unwrap();
+        if pending.iter().any(|(pending_id, _)| *pending_id == id) {
+            return Ok(false);
+        }
+        for (pending_id, other) in pending.iter() {
+            if let Some(input) = transaction.inputs.iter().find(|input| other.inputs.contains(input)) {
+                return Err(BlockchainError::DoubleSpend(format!("output {} is spent by pending transaction {}", input, pending_id)));
+            }
+        }
+        if pending.len() >= MAX_PENDING_TRANSACTIONS {
+            return Err(BlockchainError::Error(format!("the transaction pool is full ({} transactions)", MAX_PENDING_TRANSACTIONS)));
+        }
+        pending.push((id, transaction));
+        Ok(true)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Transaction> {
+        self.pending.lock().unwrap().iter().find(|(pending_id, _)| pending_id == id).map(|(_, transaction)| transaction.clone())
+    }
+
+    // In the order they are mined: the highest fee first, otherwise the oldest first
+    pub fn pending(&self) -> Vec<Transaction> {
+        let mut pending = self.pending.lock().unwrap().iter().map(|(_, transaction)| transaction.clone()).collect::<Vec<_>>();
+        pending.sort_by(|a, b| b.fee.cmp(&a.fee));
+        pending
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+
+    // Takes the next transactions to mine, at most max
+    pub fn drain(&self, max: usize) -> Vec<Transaction> {
+        let drained = self.pending().into_iter().take(max).collect::<Vec<_>>();
+        let ids = drained.iter().map(Transaction::id).collect::<Vec<_>>();
+        self.remove(&ids);
+        drained
+    }
+
+    // Amount and fees of the pending transfers without inputs of the sender
+    pub fn pending_spend(&self, sender: &str) -> u64 {
+        spend_without_inputs(self.pending.lock().unwrap().iter().map(|(_, transaction)| transaction), sender)
+    }
+
+    // Returns the number of removed transactions
+    pub fn remove(&self, ids: &[String]) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        let count = pending.len();
+        pending.retain(|(id, _)| !ids.contains(id));
+        count - pending.len()
+    }
+
+    // Drops the transactions the block carries, it made it into the main chain
+    pub fn remove_mined(&self, block: &Block) -> usize {
+        self.remove(&block.transactions().unwrap_or_default().iter().map(Transaction::id).collect::<Vec<_>>())
+    }
+}
+
+// Adds the transaction to the pool (`tx broadcast`) unless it spends what is spent already: it was
+// confirmed already, spends outputs spent on our main chain or by a pending or queued transaction,
+// or a balance the confirmed, pending and queued transfers of its sender used up. Returns false if it
+// is pending or queued already.
+pub async fn admit(
+    chain: &mut Chain,
+    storage: &mut Storage,
+    pool: &TransactionPool,
+    queue: &MiningQueue,
+    transaction: Transaction,
+) -> Result<bool, BlockchainError> {
+    transaction.verify()?;
+    let id = transaction.id();
+    if let Some(block) = Chain::get_transaction_blocks(storage, &id).await?.first() {
+        return Err(BlockchainError::DoubleSpend(format!("transaction {} was confirmed in block {} already", id, block.hash)));
+    }
+    let queued = queue.transactions();
+    if queued.iter().any(|other| other.id() == id) {
+        return Ok(false);
+    }
+    Chain::check_spends(storage, &transaction).await?;
+    for other in queued.iter() {
+        if let Some(input) = transaction.inputs.iter().find(|input| other.inputs.contains(input)) {
+            return Err(BlockchainError::DoubleSpend(format!("output {} is spent by queued transaction {}", input, other.id())));
+        }
+    }
+    if transaction.inputs.is_empty() && pool.get(&id).is_none() {
+        let available = chain.get_balance(storage, &transaction.sender).await?.available();
+        let pending = pool.pending_spend(&transaction.sender).saturating_add(spend_without_inputs(queued.iter(), &transaction.sender));
+        let spend = transaction.amount.saturating_add(transaction.fee);
+        if pending.saturating_add(spend) > available {
+            return Err(BlockchainError::DoubleSpend(format!(
+                "{} has {} available and {} pending, sending {} more exceeds it",
+                transaction.sender, available, pending, spend
+            )));
+        }
+    }
+    pool.insert(transaction)
+}
+
+fn spend_without_inputs<'a>(transactions: impl Iterator<Item = &'a Transaction>, sender: &str) -> u64 {
+    transactions
+        .filter(|transaction| transaction.sender == sender && transaction.inputs.is_empty())
+        .fold(0, |spend, transaction| spend.saturating_add(transaction.amount).saturating_add(transaction.fee))
+}
@@ -0,0 +1,457 @@
+// Mining job queue (`block mine`, `mine queue`): requests to mine a block are queued and mined one
+// at a time by the node, the highest priority first and within one priority the highest fee rate
+// first (see fees.rs), otherwise in the order they were added. A request for data that is already
+// queued is merged into the pending job.
+// The queue is the node's mempool of mining jobs, signed transactions wait in the transaction pool
+// instead (see mempool.rs). With `--mempool PATH` it is written to the file after every change
+// and restored on start-up, so pending jobs survive a restart. The admission and expiry policy (max
+// age, count and size caps, priority floor and minimum fee rate) is enforced on the restored jobs as
+// well as on the running queue. A full queue makes room by its eviction policy: lowest fee evicts the
+// jobs mined last, so a new job that would be mined last is rejected, oldest evicts the jobs queued
+// first. A job larger than the size cap is rejected right away. Blocks waiting for their parent are
+// kept in the orphan pool instead, see orphans.rs.
+// Jobs conflict if they spend the same thing, which currently are rotations of the same signing key
+// (see keys.rs). A conflicting job replaces the pending one if it offers a sufficiently higher fee
+// (replace-by-fee), the replacement is reported to the event stream (see events.rs).
+use crate::atrest;
+use crate::blockchain::BlockchainError;
+use crate::fees;
+use crate::keys;
+use crate::transactions::{self, Transaction};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+// How often jobs older than the max age are dropped
+pub const MEMPOOL_EXPIRY_INTERVAL: Duration = Duration::from_secs(10);
+// A replacement has to offer a fee at least this many percent above the fee of the job it replaces
+pub const REPLACEMENT_FEE_INCREASE: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl FromStr for Priority {
+    type Err = BlockchainError;
+
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        match priority {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(BlockchainError::Error(format!("invalid priority: {} (low, normal or high)", priority))),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Priority::Low => write!(f, "low"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::High => write!(f, "high"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MiningJob {
+    pub id: u64,
+    // Data of the block to mine
+    pub data: String,
+    pub priority: Priority,
+    // Unix timestamp in milliseconds, the age survives a restart
+    pub added_at: i64,
+    // Collected by the miner of the block, see fees.rs
+    #[serde(default)]
+    pub fee: u64,
+}
+
+impl MiningJob {
+    pub fn fee_rate(&self) -> u64 {
+        fees::fee_rate(self.fee, self.data.len())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueued {
+    Added(u64),
+    // The data was already queued by the job, which keeps the higher of both priorities and fees
+    Merged(u64),
+    // Added, the conflicting job offering a lower fee was dropped
+    Replaced { id: u64, replaced: u64 },
+    // The job conflicts with the pending one, whose fee was not outbid by enough
+    Conflicting(u64),
+    // Below the priority floor or the minimum fee rate, larger than the size cap, or the queue is full of
+    // jobs that are mined before it
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MempoolEvent {
+    Replaced { replaced: MiningJob, by: MiningJob },
+}
+
+// Which jobs make room in a full queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    // The jobs mined last: the lowest priority, within it the lowest fee rate
+    #[default]
+    LowestFee,
+    // The jobs queued first
+    Oldest,
+}
+
+impl FromStr for Eviction {
+    type Err = BlockchainError;
+
+    fn from_str(eviction: &str) -> Result<Self, Self::Err> {
+        match eviction {
+            "lowest-fee" => Ok(Eviction::LowestFee),
+            "oldest" => Ok(Eviction::Oldest),
+            _ => Err(BlockchainError::Error(format!("invalid eviction policy: {} (lowest-fee or oldest)", eviction))),
+        }
+    }
+}
+
+impl fmt::Display for Eviction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Eviction::LowestFee => write!(f, "lowest-fee"),
+            Eviction::Oldest => write!(f, "oldest"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolPolicy {
+    // Jobs queued longer than this are dropped
+    pub max_age: Option<Duration>,
+    // Caps on the number of jobs and their summed data size, jobs are evicted beyond them
+    pub max_jobs: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub eviction: Eviction,
+    // Jobs below this priority are not queued
+    pub min_priority: Priority,
+    // Jobs offering a lower fee rate are not queued, see fees.rs
+    pub min_fee_rate: u64,
+}
+
+impl Default for MempoolPolicy {
+    fn default() -> Self {
+        Self { max_age: None, max_jobs: None, max_bytes: None, eviction: Eviction::LowestFee, min_priority: Priority::Low, min_fee_rate: 0 }
+    }
+}
+
+// How full the queue is, for `mine queue` and the getmempoolinfo RPC method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolOccupancy {
+    pub jobs: usize,
+    pub max_jobs: Option<usize>,
+    pub bytes: usize,
+    pub max_bytes: Option<usize>,
+    // Jobs evicted to make room since start-up
+    pub evicted: u64,
+}
+
+impl fmt::Display for MempoolOccupancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cap = |cap: Option<usize>| cap.map_or("unlimited".to_owned(), |cap| cap.to_string());
+        write!(
+            f,
+            "jobs: {} of {} | size: {} of {} bytes | evicted: {}",
+            self.jobs,
+            cap(self.max_jobs),
+            self.bytes,
+            cap(self.max_bytes),
+            self.evicted
+        )
+    }
+}
+
+// Contents of the --mempool file, IDs of dropped jobs are not reused after a restart
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MempoolFile {
+    next_id: u64,
+    jobs: Vec<MiningJob>,
+}
+
+#[derive(Debug, Default)]
+pub struct MiningQueue {
+    // In the order they are mined
+    jobs: Vec<MiningJob>,
+    next_id: u64,
+    policy: MempoolPolicy,
+    // The jobs are written to this file after every change
+    path: Option<PathBuf>,
+    // Replacements are sent to this, see events.rs
+    events: Option<broadcast::Sender<MempoolEvent>>,
+    evicted: u64,
+}
+
+impl MiningQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_policy(policy: MempoolPolicy) -> Self {
+        Self { policy, ..Self::default() }
+    }
+
+    // Restores the jobs written to the file, dropping those the policy no longer admits
+    pub fn open(path: &Path, policy: MempoolPolicy, now_millis: i64) -> Result<Self, BlockchainError> {
+        let file = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str::<MempoolFile>(&atrest::open_file(&json)?)
+                .map_err(|err| BlockchainError::Error(format!("invalid mempool {}: {}", path.display(), err)))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => MempoolFile::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let restored = file.jobs.len();
+        let mut queue = Self { next_id: file.next_id, path: Some(path.to_owned()), ..Self::with_policy(policy) };
+        for job in file.jobs.into_iter().filter(|job| job.priority >= policy.min_priority && job.fee_rate() >= policy.min_fee_rate) {
+            queue.insert(job);
+        }
+        queue.expire(now_millis);
+        queue.enforce_caps();
+        info!("Restored {} of {} pending mining jobs from {}", queue.jobs.len(), restored, path.display());
+        queue.save()?;
+        Ok(queue)
+    }
+
+    pub fn policy(&self) -> MempoolPolicy {
+        self.policy
+    }
+
+    // Applies a new policy to the queued jobs as well, dropping the ones it no longer admits. Returns
+    // the number of dropped jobs.
+    pub fn set_policy(&mut self, policy: MempoolPolicy, now_millis: i64) -> usize {
+        let queued = self.jobs.len();
+        self.policy = policy;
+        self.jobs.retain(|job| job.priority >= policy.min_priority && job.fee_rate() >= policy.min_fee_rate);
+        self.expire(now_millis);
+        self.enforce_caps();
+        self.persist();
+        queued - self.jobs.len()
+    }
+
+    pub fn set_event_sender(&mut self, sender: broadcast::Sender<MempoolEvent>) {
+        self.events = Some(sender);
+    }
+
+    pub fn push(&mut self, data: String, priority: Priority) -> Enqueued {
+        self.push_with_fee(data, priority, 0)
+    }
+
+    pub fn push_with_fee(&mut self, data: String, priority: Priority, fee: u64) -> Enqueued {
+        self.push_at(data, priority, fee, Utc::now().timestamp_millis())
+    }
+
+    pub fn push_at(&mut self, data: String, priority: Priority, fee: u64, now_millis: i64) -> Enqueued {
+        if let Some(job) = self.jobs.iter().find(|job| job.data == data) {
+            let id = job.id;
+            if priority > job.priority || fee > job.fee {
+                let job = self.take(id).expect("job is queued");
+                self.insert(MiningJob { priority: priority.max(job.priority), fee: fee.max(job.fee), ..job });
+                self.persist();
+            }
+            return Enqueued::Merged(id);
+        }
+        if priority < self.policy.min_priority || fees::fee_rate(fee, data.len()) < self.policy.min_fee_rate {
+            return Enqueued::Rejected;
+        }
+        // It would evict every other job and then itself
+        if self.policy.max_bytes.is_some_and(|max_bytes| data.len() > max_bytes) {
+            return Enqueued::Rejected;
+        }
+        let conflicting = conflict_key(&data).and_then(|key| self.jobs.iter().find(|job| conflict_key(&job.data) == Some(key.clone())));
+        if let Some(conflicting) = conflicting {
+            if !outbids(fee, conflicting.fee) {
+                return Enqueued::Conflicting(conflicting.id);
+            }
+        }
+        let replaced = conflicting.map(|job| job.id).and_then(|id| self.take(id));
+
+        self.next_id += 1;
+        let id = self.next_id;
+        self.insert(MiningJob { id, data, priority, added_at: now_millis, fee });
+        self.enforce_caps();
+        let added = self.jobs.iter().find(|job| job.id == id).cloned();
+        let enqueued = match (added, replaced) {
+            (Some(by), Some(replaced)) => {
+                info!("Mining job {} replaced job {}", by.id, replaced.id);
+                let enqueued = Enqueued::Replaced { id, replaced: replaced.id };
+                if let Some(events) = &self.events {
+                    let _ = events.send(MempoolEvent::Replaced { replaced, by });
+                }
+                enqueued
+            }
+            (Some(_), None) => Enqueued::Added(id),
+            // The replacement does not fit the caps, the replaced job stays
+            (None, Some(replaced)) => {
+                self.insert(replaced);
+                self.enforce_caps();
+                Enqueued::Rejected
+            }
+            (None, None) => Enqueued::Rejected,
+        };
+        self.persist();
+        enqueued
+    }
+
+    // The next job to mine
+    pub fn pop(&mut self) -> Option<MiningJob> {
+        if self.jobs.is_empty() {
+            return None;
+        }
+        let job = self.jobs.remove(0);
+        self.persist();
+        Some(job)
+    }
+
+    // Drops the jobs older than the max age and returns them
+    pub fn expire(&mut self, now_millis: i64) -> Vec<MiningJob> {
+        let max_age = match self.policy.max_age {
+            Some(max_age) => max_age.as_millis() as i64,
+            None => return vec![],
+        };
+        let (expired, kept) = self.jobs.drain(..).partition(|job| now_millis - job.added_at > max_age);
+        self.jobs = kept;
+        if !expired.is_empty() {
+            self.persist();
+        }
+        expired
+    }
+
+    // Summed data size of all jobs, in bytes
+    pub fn size(&self) -> usize {
+        self.jobs.iter().map(|job| job.data.len()).sum()
+    }
+
+    pub fn occupancy(&self) -> MempoolOccupancy {
+        MempoolOccupancy {
+            jobs: self.jobs.len(),
+            max_jobs: self.policy.max_jobs,
+            bytes: self.size(),
+            max_bytes: self.policy.max_bytes,
+            evicted: self.evicted,
+        }
+    }
+
+    pub fn jobs(&self) -> &[MiningJob] {
+        &self.jobs
+    }
+
+    // The transactions the queued jobs carry, in queue order
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.jobs.iter().flat_map(|job| transactions::from_block_data(&job.data).and_then(Result::ok).unwrap_or_default()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<MiningJob> {
+        let job = self.take(id)?;
+        self.persist();
+        Some(job)
+    }
+
+    // Moves the job behind the other jobs of the new priority
+    pub fn set_priority(&mut self, id: u64, priority: Priority) -> bool {
+        match self.take(id) {
+            Some(job) => {
+                self.insert(MiningJob { priority, ..job });
+                self.persist();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Mines the job next, it gets the priority of the current first job if that is higher
+    pub fn move_to_front(&mut self, id: u64) -> bool {
+        match self.take(id) {
+            Some(mut job) => {
+                if let Some(first) = self.jobs.first() {
+                    job.priority = job.priority.max(first.priority);
+                }
+                self.jobs.insert(0, job);
+                self.persist();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn take(&mut self, id: u64) -> Option<MiningJob> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    // Behind all jobs with a higher priority, or the same priority and the same or a higher fee rate
+    fn insert(&mut self, job: MiningJob) {
+        let index = self
+            .jobs
+            .iter()
+            .position(|queued| (queued.priority, queued.fee_rate()) < (job.priority, job.fee_rate()))
+            .unwrap_or(self.jobs.len());
+        self.jobs.insert(index, job);
+    }
+
+    // Evicts jobs by the eviction policy until the jobs fit the count and size caps
+    fn enforce_caps(&mut self) {
+        let mut size = self.size();
+        while self.policy.max_jobs.is_some_and(|max_jobs| self.jobs.len() > max_jobs)
+            || self.policy.max_bytes.is_some_and(|max_bytes| size > max_bytes)
+        {
+            let index = match self.policy.eviction {
+                Eviction::LowestFee => self.jobs.len().checked_sub(1),
+                Eviction::Oldest => self.jobs.iter().enumerate().min_by_key(|(_, job)| (job.added_at, job.id)).map(|(index, _)| index),
+            };
+            let job = match index {
+                Some(index) => self.jobs.remove(index),
+                None => break,
+            };
+            info!("Evicted mining job {} from the full mempool ({})", job.id, self.policy.eviction);
+            size -= job.data.len();
+            self.evicted += 1;
+        }
+    }
+
+    fn save(&self) -> Result<(), BlockchainError> {
+        if let Some(path) = &self.path {
+            let tmp_path = path.with_extension("tmp");
+            let file = MempoolFile { next_id: self.next_id, jobs: self.jobs.clone() };
+            // The jobs carry the payloads of the blocks to mine, see atrest.rs
+            fs::write(&tmp_path, atrest::seal(&serde_json::to_string(&file).expect("can jsonify mining jobs")))?;
+            fs::rename(&tmp_path, path)?;
+        }
+        Ok(())
+    }
+
+    // Mining goes on if the file can not be written, the jobs are only lost on a restart
+    fn persist(&self) {
+        if let Err(err) = self.save() {
+            error!("Error writing the mempool: {:?}", err);
+        }
+    }
+}
+
+// Jobs with the same key spend the same thing, see keys::rotated_key
+fn conflict_key(data: &str) -> Option<String> {
+    keys::rotated_key(data)
+}
+
+fn outbids(fee: u64, replaced_fee: u64) -> bool {
+    fee > replaced_fee && u128::from(fee) * 100 >= u128::from(replaced_fee) * u128::from(100 + REPLACEMENT_FEE_INCREASE)
+}
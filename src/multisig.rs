@@ -0,0 +1,134 @@
+// Multisignature addresses (`multisig address M KEY,KEY...`): an m-of-n policy over signing keys (see
+// keys.rs) has an address derived from it, and transactions sending from that address (see
+// transactions.rs) carry the policy and need the signatures of at least m of its keys. The owners
+// sign one after another (`tx sign --partial`) or in parallel and merge their signatures afterwards
+// (`tx combine`).
+use crate::blockchain::BlockchainError;
+use libp2p::identity::ed25519;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Multisig addresses start with this, followed by the hex encoded SHA-256 of the policy
+pub const MULTISIG_ADDRESS_PREFIX: &str = "multisig-";
+pub const MAX_MULTISIG_KEYS: usize = 16;
+const MULTISIG_OPTION: &str = "--multisig ";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Multisig {
+    pub threshold: usize,
+    // Hex encoded signing keys, sorted, so the order they were given in does not change the address
+    pub keys: Vec<String>,
+}
+
+// Signature of one of the keys of a policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialSignature {
+    pub key: String,
+    pub signature: String,
+}
+
+impl Multisig {
+    pub fn new(threshold: usize, mut keys: Vec<String>) -> Result<Self, BlockchainError> {
+        keys.sort();
+        keys.dedup();
+        let multisig = Self { threshold, keys };
+        multisig.check()?;
+        Ok(multisig)
+    }
+
+    // Parses "M KEY,KEY..."
+    pub fn parse(args: &str) -> Result<Self, BlockchainError> {
+        let usage = || BlockchainError::Error("usage: M KEY,KEY...".to_owned());
+        let (threshold, keys) = args.trim().split_once(' ').ok_or_else(usage)?;
+        let threshold = threshold.parse::<usize>().map_err(|_| usage())?;
+        Multisig::new(threshold, keys.trim().split(',').map(str::to_owned).collect())
+    }
+
+    // Policies from transactions are checked as they are, they have to be normalized already
+    pub fn check(&self) -> Result<(), BlockchainError> {
+        if self.keys.is_empty() || self.keys.len() > MAX_MULTISIG_KEYS {
+            return Err(BlockchainError::Error(format!("a multisig policy needs 1 to {} keys", MAX_MULTISIG_KEYS)));
+        }
+        if self.threshold == 0 || self.threshold > self.keys.len() {
+            return Err(BlockchainError::Error(format!("threshold has to be between 1 and {}", self.keys.len())));
+        }
+        if self.keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(BlockchainError::Error("multisig keys have to be sorted and distinct".to_owned()));
+        }
+        for key in self.keys.iter() {
+            decode_key(key)?;
+        }
+        Ok(())
+    }
+
+    pub fn address(&self) -> String {
+        let policy = serde_json::to_vec(self).expect("can jsonify multisig policy");
+        format!("{}{}", MULTISIG_ADDRESS_PREFIX, hex::encode(Sha256::digest(policy)))
+    }
+
+    // The signature of the message by the keypair, which has to be one of the policy's
+    pub fn sign(&self, keypair: &ed25519::Keypair, message: &[u8]) -> Result<PartialSignature, BlockchainError> {
+        let key = hex::encode(keypair.public().encode());
+        if !self.keys.contains(&key) {
+            return Err(BlockchainError::Error(format!("{} is no key of the multisig address {}", key, self.address())));
+        }
+        Ok(PartialSignature { key, signature: hex::encode(keypair.sign(message)) })
+    }
+
+    // The number of distinct keys of the policy with a valid signature of the message, fails on
+    // signatures of other keys or invalid ones
+    pub fn count_signatures(&self, message: &[u8], signatures: &[PartialSignature]) -> Result<usize, BlockchainError> {
+        let mut signers = vec![];
+        for signature in signatures {
+            if !self.keys.contains(&signature.key) {
+                return Err(BlockchainError::Error(format!("{} is no key of the multisig address {}", signature.key, self.address())));
+            }
+            if !decode_key(&signature.key)?.verify(message, &hex::decode(&signature.signature).unwrap_or_default()) {
+                return Err(BlockchainError::Error(format!("invalid signature of {}", signature.key)));
+            }
+            if !signers.contains(&&signature.key) {
+                signers.push(&signature.key);
+            }
+        }
+        Ok(signers.len())
+    }
+
+    pub fn verify(&self, message: &[u8], signatures: &[PartialSignature]) -> Result<(), BlockchainError> {
+        self.check()?;
+        let signed = self.count_signatures(message, signatures)?;
+        if signed < self.threshold {
+            return Err(BlockchainError::Error(format!("{} of {} required signatures", signed, self.threshold)));
+        }
+        Ok(())
+    }
+}
+
+// Splits "[--multisig M KEY,KEY...] REST" into the policy (None without the option) and the rest
+pub fn split_multisig(args: &str) -> Result<(Option<Multisig>, &str), BlockchainError> {
+    let rest = match args.strip_prefix(MULTISIG_OPTION) {
+        Some(rest) => rest.trim_start(),
+        None => return Ok((None, args)),
+    };
+    let mut parts = rest.splitn(3, ' ');
+    let policy = match (parts.next(), parts.next()) {
+        (Some(threshold), Some(keys)) => Multisig::parse(&format!("{} {}", threshold, keys))?,
+        _ => return Err(BlockchainError::Error("--multisig requires M KEY,KEY...".to_owned())),
+    };
+    Ok((Some(policy), parts.next().unwrap_or("")))
+}
+
+// Adds the signatures of keys that did not sign yet
+pub fn merge_signatures(signatures: &mut Vec<PartialSignature>, other: &[PartialSignature]) {
+    for signature in other {
+        if !signatures.iter().any(|kept| kept.key == signature.key) {
+            signatures.push(signature.clone());
+        }
+    }
+}
+
+fn decode_key(key: &str) -> Result<ed25519::PublicKey, BlockchainError> {
+    hex::decode(key)
+        .ok()
+        .and_then(|bytes| ed25519::PublicKey::decode(&bytes).ok())
+        .ok_or_else(|| BlockchainError::Error(format!("invalid key: {}", key)))
+}
@@ -0,0 +1,126 @@
+// Network definition (`--network PATH`): the consensus-economic parameters all nodes of a network have
+// to agree on, loaded from a JSON file at start-up instead of being compiled into the binary. Fields
+// missing from the file keep the parameters of the default network. The digest of the parameters is
+// part of the genesis block, so nodes of differently parameterized networks have different genesis
+// blocks, and they refuse to peer with each other (see p2p.rs). The default network keeps the genesis
+// block it had before its parameters were configurable.
+use crate::blockchain::{self, Block, BlockchainError, FINALITY_DEPTH};
+use crate::difficulty::TARGET_BLOCK_TIME_MS;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+// Identify protocol version of the default network, other networks append their genesis hash
+pub const PROTOCOL_VERSION: &str = "/blockchain/1";
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkParams {
+    // Block time the difficulty algorithms aim for, see difficulty.rs
+    pub target_block_time_ms: i64,
+    // Reward of the blocks up to the first halving
+    pub initial_reward: u64,
+    // Number of blocks after which the reward is halved
+    pub halving_interval: u64,
+    // The rewards of all blocks never add up to more than this
+    pub max_supply: u64,
+    // Blocks this deep are final, see Chain::update_finalized
+    pub max_reorg_depth: u64,
+}
+
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self {
+            target_block_time_ms: TARGET_BLOCK_TIME_MS,
+            initial_reward: 50,
+            halving_interval: 210_000,
+            max_supply: 21_000_000,
+            max_reorg_depth: FINALITY_DEPTH,
+        }
+    }
+}
+
+impl NetworkParams {
+    pub fn load(path: &Path) -> Result<Self, BlockchainError> {
+        let params = serde_json::from_str::<Self>(&fs::read_to_string(path)?)
+            .map_err(|err| BlockchainError::Error(format!("invalid network definition {}: {}", path.display(), err)))?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), BlockchainError> {
+        let invalid = |reason: &str| Err(BlockchainError::Error(format!("invalid network definition: {}", reason)));
+        if self.target_block_time_ms <= 0 {
+            return invalid("target_block_time_ms has to be positive");
+        }
+        if self.halving_interval == 0 || self.max_reorg_depth == 0 {
+            return invalid("halving_interval and max_reorg_depth have to be positive");
+        }
+        if self.initial_reward > self.max_supply {
+            return invalid("initial_reward is above max_supply");
+        }
+        Ok(())
+    }
+
+    // SHA-256 of the parameters, hex encoded
+    pub fn digest(&self) -> String {
+        hex::encode(Sha256::digest(serde_json::to_vec(self).expect("can jsonify network parameters")))
+    }
+
+    pub fn genesis(&self) -> Block {
+        if *self == Self::default() {
+            return Block::create_genesis();
+        }
+        let mut genesis = Block { data: format!("genesis of network {}", self.digest()), ..Block::create_genesis() };
+        genesis.hash = blockchain::hasher(&genesis);
+        genesis
+    }
+
+    // Name for humans: `default`, or `custom-` and the start of the digest of the parameters
+    pub fn name(&self) -> String {
+        match *self == Self::default() {
+            true => "default".to_owned(),
+            false => format!("custom-{}", &self.digest()[..8]),
+        }
+    }
+
+    // Sent to our peers via identify, peers with another one are of another network
+    pub fn protocol_version(&self) -> String {
+        match *self == Self::default() {
+            true => PROTOCOL_VERSION.to_owned(),
+            false => format!("{}/{}", PROTOCOL_VERSION, self.genesis().hash),
+        }
+    }
+
+    // Sum of the rewards of the blocks up to the height
+    pub fn supply_at(&self, height: Height) -> u64 {
+        let mut supply = 0u64;
+        let mut blocks = height.0;
+        let mut reward = self.initial_reward;
+        while blocks > 0 && reward > 0 {
+            let epoch = blocks.min(self.halving_interval);
+            supply = supply.saturating_add(epoch.saturating_mul(reward));
+            if supply >= self.max_supply {
+                return self.max_supply;
+            }
+            blocks -= epoch;
+            reward /= 2;
+        }
+        supply
+    }
+
+    // What the coinbase of a block has to pay: the reward at its height and the fee it collects
+    pub fn coinbase_amount(&self, height: Height, fee: u64) -> u64 {
+        self.reward_at(height).saturating_add(fee)
+    }
+
+    // The genesis block has no reward, the last reward is cut so the supply ends at max_supply
+    pub fn reward_at(&self, height: Height) -> u64 {
+        match height.checked_sub(1) {
+            Some(parent) => self.supply_at(height) - self.supply_at(parent),
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,751 @@
+use crate::blockchain::{Block, BlockchainError, Chain, Checkpoint};
+use crate::endpoints::ServiceDirectory;
+use crate::fastsync::{self, ChainSnapshot, SnapshotTrust, SnapshotVotes, FAST_SYNC_MIN_HEIGHT};
+use crate::handshake::Features;
+use crate::head::{HeadEvent, HeadStream, HeadWatcher};
+use crate::latency;
+use crate::lifecycle::LifecycleHooks;
+use crate::loadgen::LoadGenerator;
+use crate::mempool::TransactionPool;
+use crate::mining::MiningQueue;
+use crate::orphans::OrphanPool;
+use crate::pool::{Coordinator, PoolJob, PoolMessage, ShareOutcome};
+use crate::slots::SlotSchedule;
+use crate::staletip::StaleTipMonitor;
+use crate::stats::StatsRecorder;
+use crate::storage::{MemoryStorage, Storage};
+use crate::sync::{SyncManager, SyncOutcome, SyncRecord, SyncSession, SyncTrigger};
+use crate::transactions::{self, MAX_BLOCK_TRANSACTIONS};
+use crate::types::{EventType, Height};
+use crate::wallet::Wallet;
+use crate::watchdog::{self, TaskKind};
+use crate::workers;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::time::Instant;
+use tracing::{error, info, warn};
+
+// The state of a full node and its reactions to events from the p2p layer, independent of
+// the actual network so it can also be driven by the simulation (see simulation.rs)
+pub struct Node {
+    pub chain: Chain,
+    pub storage: Storage,
+    pub sync_manager: SyncManager,
+    // Set while `node loadgen` is running
+    pub loadgen: Option<LoadGenerator>,
+    // Set while we coordinate a mining pool (`pool start`)
+    pub pool: Option<Coordinator>,
+    // Blocks waiting to be mined, see mining.rs
+    pub mining_queue: MiningQueue,
+    // Transactions waiting to be mined while no job is queued, see mempool.rs
+    pub transactions: TransactionPool,
+    // Received blocks waiting for their parent, see orphans.rs
+    pub orphans: OrphanPool,
+    // Set in slot-based mode (`--slot-time`), the mining queue is then only mined in our slots
+    pub slots: Option<SlotSchedule>,
+    // Sync from a snapshot instead of the complete chain when we only have the genesis block (`--fast-sync`)
+    pub fast_sync: bool,
+    // Whose snapshots we restore (`--snapshot-signer`, `--snapshot-quorum`), see fastsync.rs
+    pub snapshot_trust: SnapshotTrust,
+    // Verified snapshots waiting for more signers to agree on their base
+    pub snapshot_votes: SnapshotVotes,
+    // Reports the changes of our chain's head to subscribers, see head.rs
+    pub head: HeadWatcher,
+    // Notices when our latest block did not change for too long, see staletip.rs
+    pub stale_tip: StaleTipMonitor,
+    // Hooks of embedding applications, see lifecycle.rs. An isolated copy of the node has none.
+    pub lifecycle: LifecycleHooks,
+    // Counts since the lifetime stats were last flushed to the storage, see stats.rs
+    pub stats: StatsRecorder,
+    // The public endpoints our peers announced, see endpoints.rs
+    pub services: ServiceDirectory,
+    // The keypair our blocks and transactions are attributed to (`--wallet`), see wallet.rs
+    pub wallet: Option<Wallet>,
+}
+
+impl Node {
+    pub async fn init(storage: Storage, miner: String) -> Result<Self, BlockchainError> {
+        Node::init_with_genesis(storage, miner, Block::create_genesis()).await
+    }
+
+    // A node following another chain than our own, see chains.rs
+    pub async fn init_with_genesis(mut storage: Storage, miner: String, genesis: Block) -> Result<Self, BlockchainError> {
+        let mut chain = Chain::init_with_genesis(&mut storage, genesis).await?;
+        chain.miner = miner;
+        let head = HeadWatcher::new(&chain, &mut storage).await?;
+
+        Ok(Self {
+            chain,
+            storage,
+            sync_manager: SyncManager::new(),
+            loadgen: None,
+            pool: None,
+            mining_queue: MiningQueue::new(),
+            transactions: TransactionPool::new(),
+            orphans: OrphanPool::default(),
+            slots: None,
+            fast_sync: false,
+            snapshot_trust: SnapshotTrust::default(),
+            snapshot_votes: SnapshotVotes::default(),
+            head,
+            stale_tip: StaleTipMonitor::default(),
+            lifecycle: LifecycleHooks::default(),
+            stats: StatsRecorder::new(Instant::now()),
+            services: ServiceDirectory::new(),
+            wallet: None,
+        })
+    }
+
+    // The coinbases of the blocks we mine pay the address of the wallet
+    pub fn set_wallet(&mut self, wallet: Option<Wallet>) {
+        self.chain.coinbase_address = wallet.as_ref().map(Wallet::address);
+        self.wallet = wallet;
+    }
+
+    // A copy of the node on an in-memory copy of its main chain, to handle events without touching
+    // our state (see `p2p replay`)
+    pub async fn isolated(&mut self) -> Result<Node, BlockchainError> {
+        let mut storage = Storage::Memory(MemoryStorage::default());
+        let blocks = Chain::get_chain(&mut self.storage).await?;
+        match &self.chain.base {
+            Some(base) => storage.restore_snapshot(base, &blocks).await?,
+            None => {
+                for block in blocks {
+                    storage.insert_block(&block).await?;
+                }
+            }
+        }
+
+        let head = HeadWatcher::new(&self.chain, &mut storage).await?;
+
+        Ok(Self {
+            chain: self.chain.clone(),
+            storage,
+            sync_manager: SyncManager::new(),
+            loadgen: None,
+            pool: None,
+            mining_queue: MiningQueue::new(),
+            transactions: TransactionPool::new(),
+            orphans: OrphanPool::default(),
+            slots: None,
+            fast_sync: false,
+            snapshot_trust: SnapshotTrust::default(),
+            snapshot_votes: SnapshotVotes::default(),
+            head,
+            stale_tip: StaleTipMonitor::default(),
+            lifecycle: LifecycleHooks::default(),
+            stats: StatsRecorder::new(Instant::now()),
+            services: ServiceDirectory::new(),
+            wallet: None,
+        })
+    }
+
+    // Changes of our chain's head, from now on
+    pub fn subscribe_head(&self) -> HeadStream {
+        self.head.subscribe_head()
+    }
+
+    // Reports the changes of our chain's head since the last call to the subscribers and the
+    // lifecycle hooks and returns them
+    pub async fn watch_head(&mut self) -> Result<Vec<HeadEvent>, BlockchainError> {
+        let events = self.head.update(&self.chain, &mut self.storage).await?;
+        for event in events.iter() {
+            match event {
+                HeadEvent::Extended(block) => {
+                    self.transactions.remove_mined(block);
+                }
+                HeadEvent::Reorged{new_tip, ..} => {
+                    self.stats.reorged();
+                    self.transactions.remove_mined(new_tip);
+                }
+            }
+            self.lifecycle.head_changed(event).await;
+        }
+        Ok(events)
+    }
+
+    // Adds the counts since the last flush to the lifetime stats in the storage
+    pub async fn flush_stats(&mut self, now: Instant) -> Result<(), BlockchainError> {
+        let counts = self.stats.take(now);
+        if counts.is_empty() {
+            return Ok(());
+        }
+        if let Err(err) = self.storage.add_node_stats(&counts, Utc::now().timestamp_millis()).await {
+            self.stats.put_back(counts);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Opens a sync session and returns the chain request to send. The peer told us about the block
+    // that triggered the sync, we sync from it or a higher ranking peer having it (see sync.rs). A
+    // chain restored from a snapshot can only be extended, so only the tail above it is requested,
+    // which every peer serves. Complete chains are only requested from peers serving bodies, the
+    // other peers that are ahead of us answer our latest block requests as well.
+    fn request_chain(&mut self, peer: String, trigger: SyncTrigger, now: Instant) -> Option<EventType> {
+        let (from, features) = match self.chain.base {
+            Some(_) => (self.chain.latest_block.id + 1, Features::default()),
+            None => (Height::GENESIS, Features::BODIES),
+        };
+        let source = self.sync_manager.select_source(&peer, features, trigger.height());
+        if !self.sync_manager.serves(&source, features) {
+            info!("Not requesting chain from {}, it does not serve complete chains", source);
+            return None;
+        }
+        if source != peer {
+            info!("Syncing from {} instead of {}, it ranks higher", source, peer);
+        }
+        match self.sync_manager.start(&source, trigger, now) {
+            Some(session_id) => {
+                info!(session = %session_id, "Requesting chain from {} starting at {}", source, from);
+                Some(EventType::SendChainRequest{receiver: source, session_id, from})
+            }
+            None => {
+                info!("Already syncing with {}", source);
+                None
+            }
+        }
+    }
+
+    // Restores the snapshot of a trusted signer once enough signers agree on its base, or the
+    // snapshot they agree on. Returns whether our chain was restored.
+    async fn accept_snapshot(&mut self, signer: String, snapshot: &ChainSnapshot) -> Result<bool, BlockchainError> {
+        if !self.snapshot_trust.trusts(&signer) {
+            return Err(BlockchainError::Error(format!("{} is no trusted snapshot signer", signer)));
+        }
+        snapshot.verify(&self.chain).await?;
+        match self.snapshot_votes.add(signer, snapshot.clone(), self.snapshot_trust.quorum) {
+            Some(agreed) => fastsync::restore(&mut self.chain, &mut self.storage, &agreed).await.map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    // Opens a sync session and returns the snapshot request to send, or the chain request if
+    // neither the peer nor a higher ranking one serves snapshots. Snapshots are only requested from
+    // trusted signers that did not send us one yet, the complete chain is requested from the others.
+    fn request_snapshot(&mut self, peer: String, trigger: SyncTrigger, now: Instant) -> Option<EventType> {
+        let mut source = self.sync_manager.select_source(&peer, Features::SNAPSHOTS, trigger.height());
+        if !self.snapshot_trust.trusts(&source) || self.snapshot_votes.has_voted(&source) {
+            source = peer.clone();
+        }
+        if !self.sync_manager.serves(&source, Features::SNAPSHOTS) {
+            info!("{} does not serve snapshots", source);
+            return self.request_chain(peer, trigger, now);
+        }
+        if !self.snapshot_trust.trusts(&source) {
+            info!("{} is no trusted snapshot signer", source);
+            return self.request_chain(peer, trigger, now);
+        }
+        if self.snapshot_votes.has_voted(&source) {
+            info!("Already got a snapshot from {}, waiting for other signers", source);
+            return None;
+        }
+        match self.sync_manager.start(&source, trigger, now) {
+            Some(session_id) => {
+                info!(session = %session_id, "Requesting snapshot from {}", source);
+                Some(EventType::SendSnapshotRequest{receiver: source, session_id})
+            }
+            None => {
+                info!("Already syncing with {}", source);
+                None
+            }
+        }
+    }
+
+    // Asks our peers for their latest block if our tip is stale, any peer that is ahead of us is
+    // then synced from as usual
+    pub fn check_stale_tip(&mut self, now: Instant) -> Vec<EventType> {
+        let target_block_time_ms = self.chain.params.target_block_time_ms;
+        let connected_peers = self.sync_manager.connected_peers();
+        if !self.stale_tip.check(&self.chain.latest_block.hash, target_block_time_ms, connected_peers, now) {
+            return vec![];
+        }
+        warn!(
+            "No new block for {}s at height {} with {} peers connected, asking them for their latest block",
+            self.stale_tip.age(now).as_secs(),
+            self.chain.latest_block.id,
+            connected_peers
+        );
+        vec![EventType::RequestLatestBlocks]
+    }
+
+    // Times out sync sessions that did not get a response
+    pub async fn expire_sync_sessions(&mut self, now: Instant) {
+        for session in self.sync_manager.expire(now) {
+            warn!(session = %session.id, "Sync with {} timed out", session.peer);
+            let tip = self.tip();
+            self.record_sync(&session, tip).await;
+        }
+    }
+
+    fn tip(&self) -> Checkpoint {
+        Checkpoint { id: self.chain.latest_block.id, hash: self.chain.latest_block.hash.clone() }
+    }
+
+    // Persists the finished session for `sync history`. The sync replaced blocks if the block at the
+    // height of our old tip is another one now.
+    async fn record_sync(&mut self, session: &SyncSession, old_tip: Checkpoint) {
+        let replaced = match Chain::get_block_by_id(&mut self.storage, old_tip.id).await {
+            Ok(block) => block.hash != old_tip.hash,
+            Err(_) => true,
+        };
+        if session.outcome == SyncOutcome::Success {
+            self.stats.blocks_received(self.chain.latest_block.id.0.saturating_sub(old_tip.id.0));
+        }
+        let record = SyncRecord::new(session, old_tip, self.tip(), replaced, Utc::now().timestamp_millis());
+        if let Err(err) = self.storage.insert_sync_record(&record).await {
+            error!(session = %session.id, "Error recording sync session: {:?}", err);
+        }
+    }
+
+    // Mines the next job of the mining queue on top of our latest block
+    pub async fn mine_next(&mut self) -> Result<Option<Block>, BlockchainError> {
+        match self.mining_queue.pop() {
+            Some(job) => {
+                info!("Mining job {} ({} priority, fee {})", job.id, job.priority, job.fee);
+                self.mine(job.data, job.fee).await.map(Some)
+            }
+            None => self.mine_transactions().await,
+        }
+    }
+
+    // Mines a block carrying the next pending transactions, if there are any. Transactions a block of
+    // another miner carried meanwhile are dropped, the ones of a block we fail to mine stay pending.
+    async fn mine_transactions(&mut self) -> Result<Option<Block>, BlockchainError> {
+        let mut batch = vec![];
+        let mut spent = HashSet::new();
+        for transaction in self.transactions.drain(MAX_BLOCK_TRANSACTIONS) {
+            if !Chain::get_transaction_blocks(&mut self.storage, &transaction.id()).await?.is_empty() {
+                continue;
+            }
+            // Outputs spent meanwhile or by an earlier transaction of the batch would invalidate the block
+            if transaction.inputs.iter().any(|input| spent.contains(input)) || Chain::check_spends(&mut self.storage, &transaction).await.is_err() {
+                info!("Dropping pending transaction {} spending unavailable outputs", transaction.id());
+                continue;
+            }
+            spent.extend(transaction.inputs.iter().cloned());
+            batch.push(transaction);
+        }
+        if batch.is_empty() {
+            return Ok(None);
+        }
+        info!("Mining {} pending transactions", batch.len());
+        let (data, fee) = (transactions::to_block_data(&batch), transactions::total_fee(&batch));
+        match self.mine(data, fee).await {
+            Ok(block) => Ok(Some(block)),
+            Err(err) => {
+                for transaction in batch {
+                    let _ = self.transactions.insert(transaction);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // Main chain blocks added since `previous` was our latest block, oldest first. If it was
+    // replaced by a reorg, all blocks after the finalized one are returned.
+    pub async fn blocks_since(&mut self, previous: &Block) -> Result<Vec<Block>, BlockchainError> {
+        let latest = self.chain.latest_block.id;
+        let start = match Chain::get_block_by_id(&mut self.storage, previous.id).await {
+            Ok(block) if block.hash == previous.hash => previous.id.0 + 1,
+            _ => self.chain.finalized.id.0 + 1,
+        };
+        let mut blocks = vec![];
+        for id in start..=latest.0 {
+            blocks.push(Chain::get_block_by_id(&mut self.storage, Height(id)).await?);
+        }
+        Ok(blocks)
+    }
+
+    // Produces the block of the current slot if it is ours and there is none yet, with the next
+    // queued job or empty data
+    pub async fn produce_slot_block(&mut self, now_millis: i64) -> Result<Option<Block>, BlockchainError> {
+        let slots = match &self.slots {
+            Some(slots) => slots,
+            None => return Ok(None),
+        };
+        let slot = slots.slot_at(now_millis);
+        if !slots.is_proposer(slot, &self.chain.miner) || slots.slot_at(self.chain.latest_block.timestamp_millis()) >= slot {
+            return Ok(None);
+        }
+        let (data, fee) = match self.mining_queue.pop() {
+            Some(job) => {
+                info!("Mining job {} ({} priority, fee {}) in slot {}", job.id, job.priority, job.fee, slot);
+                (job.data, job.fee)
+            }
+            None => (String::new(), 0),
+        };
+        self.mine(data, fee).await.map(Some)
+    }
+
+    // Mines the data on top of our latest block unless a lifecycle hook vetoes it
+    async fn mine(&mut self, data: String, fee: u64) -> Result<Block, BlockchainError> {
+        self.lifecycle.before_mine(&data).await?;
+        let block = self.chain.mine_block_with_fee(data, fee, &mut self.storage).await?;
+        self.stats.block_mined();
+        latency::record_mined(&block.hash);
+        Ok(block)
+    }
+
+    // Mines the next block of the running load generator and returns the events to broadcast it
+    pub async fn generate_load(&mut self) -> Result<Vec<EventType>, BlockchainError> {
+        let data = match &self.loadgen {
+            Some(loadgen) => loadgen.payload(),
+            None => return Ok(vec![]),
+        };
+        let block = self.mine(data, 0).await?;
+        if let Some(loadgen) = &mut self.loadgen {
+            loadgen.record_block(&block, Utc::now().timestamp_millis());
+        }
+        Ok(vec![EventType::SendNewBlock(block)])
+    }
+
+    // Hands out jobs for a new template when the pool's template is no longer based on our latest block
+    pub async fn refresh_pool(&mut self) -> Result<Vec<EventType>, BlockchainError> {
+        let data = match &self.pool {
+            Some(pool) if pool.template().is_none_or(|template| template.prev_hash != self.chain.latest_block.hash) => pool.data.clone(),
+            _ => return Ok(vec![]),
+        };
+        let template = self.chain.pool_template(&mut self.storage, data).await?;
+        let prefix = self.chain.difficulty.clone();
+        let jobs = self.pool.as_mut().map(|pool| pool.set_template(template, &prefix)).unwrap_or_default();
+        Ok(job_events(jobs))
+    }
+
+    // Handles the messages of our pool workers. Jobs we get as a worker are handled by the caller,
+    // which runs the search (see PoolWorker).
+    async fn handle_pool_message(&mut self, sender: String, message: PoolMessage) -> Vec<EventType> {
+        let pool = match &mut self.pool {
+            Some(pool) => pool,
+            None => return vec![],
+        };
+        let jobs = match message {
+            PoolMessage::Subscribe => {
+                info!("Pool worker {} joined", sender);
+                pool.add_worker(&sender)
+            },
+            PoolMessage::Unsubscribe => {
+                info!("Pool worker {} left", sender);
+                pool.remove_worker(&sender)
+            },
+            PoolMessage::Share(share) => {
+                let (result, block) = match pool.submit_share(&sender, share) {
+                    ShareOutcome::Accepted => (PoolMessage::Accepted(share), None),
+                    ShareOutcome::Rejected(reason) => {
+                        warn!("Rejected share of pool worker {}: {}", sender, reason);
+                        (PoolMessage::Rejected{share, reason}, None)
+                    },
+                    ShareOutcome::BlockFound(block) => {
+                        info!("Pool worker {} found block {}", sender, block.hash);
+                        (PoolMessage::Accepted(share), Some(block))
+                    },
+                };
+                let mut outgoing = vec![EventType::SendPoolMessage{receiver: sender, message: result}];
+                // The jobs for the next block are handed out once our chain was updated
+                if let Some(block) = block {
+                    match self.chain.add_block(&mut self.storage, block.clone()).await {
+                        Ok(()) => {
+                            self.stats.block_mined();
+                            latency::record_mined(&block.hash);
+                            outgoing.push(EventType::SendNewBlock(block));
+                        },
+                        Err(err) => error!("Error adding pool block: {:?}", err),
+                    }
+                }
+                return outgoing;
+            },
+            PoolMessage::Job(_) | PoolMessage::Accepted(_) | PoolMessage::Rejected{..} => vec![],
+        };
+        job_events(jobs)
+    }
+
+    // Adds the chain we asked the peer for. `checked` is the outcome of Chain::check_chain if the
+    // sync worker ran it already, see workers.rs.
+    async fn handle_chain(&mut self, sender: String, session_id: String, mut incoming_chain: Vec<Block>, checked: Option<Result<(), String>>, now: Instant) {
+        // Only chains we asked for are accepted
+        if !self.sync_manager.is_pending(&session_id) {
+            warn!(session = %session_id, "Ignoring chain from {} for unknown sync session", sender);
+            return;
+        }
+        info!(session = %session_id, "Received chain of {} blocks from {}", incoming_chain.len(), sender);
+        let old_tip = self.tip();
+        let blocks_transferred = incoming_chain.len();
+        let incoming_height = incoming_chain.iter().map(|block| block.id).max();
+        // Our chain may have grown while the response was on its way
+        let outcome = if incoming_height.is_none_or(|height| height <= self.chain.latest_block.id) {
+            info!(session = %session_id, "Received chain is not longer than ours");
+            SyncOutcome::Failed("chain not longer than ours".to_owned())
+        } else if incoming_chain.first().is_some_and(|block| block.id > Height::GENESIS) {
+            // The tail we asked for, see request_chain
+            incoming_chain.sort_by_key(|block| block.id);
+            match self.chain.extend(&mut self.storage, &incoming_chain).await {
+                Ok(_) => {
+                    info!(session = %session_id, "Successfully extended chain.");
+                    SyncOutcome::Success
+                },
+                Err(err) => {
+                    error!(session = %session_id, "Error extending chain: {:?}", err);
+                    SyncOutcome::Failed(err.to_string())
+                }
+            }
+        } else {
+            let result = match checked {
+                None => self.chain.update(&mut self.storage, &mut incoming_chain).await,
+                Some(Ok(())) => self.chain.update_checked(&mut self.storage, &mut incoming_chain).await,
+                Some(Err(err)) => Err(BlockchainError::Error(err)),
+            };
+            match result {
+                Ok(_) => {
+                    info!(session = %session_id, "Successfully updated chain.");
+                    SyncOutcome::Success
+                },
+                Err(err) => {
+                    error!(session = %session_id, "Error updating chain: {:?}", err);
+                    SyncOutcome::Failed(err.to_string())
+                }
+            }
+        };
+        let session = self.sync_manager.finish(&session_id, blocks_transferred, outcome, now).cloned();
+        if let Some(session) = session {
+            self.record_sync(&session, old_tip).await;
+        }
+        self.connect_orphans().await;
+    }
+
+    // Adds the pooled orphans that extend our latest block, as long as there are any
+    async fn connect_orphans(&mut self) {
+        self.orphans.prune(self.chain.latest_block.id);
+        while !self.orphans.is_empty() {
+            let children = self.orphans.take_children(&self.chain.latest_block.hash);
+            if children.is_empty() {
+                break;
+            }
+            for child in children {
+                let hash = child.hash.clone();
+                match self.chain.add_block(&mut self.storage, child).await {
+                    Ok(()) => {
+                        info!("Added orphan block {} at height {}", hash, self.chain.latest_block.id);
+                        self.stats.blocks_received(1);
+                        break;
+                    }
+                    Err(err) => warn!("Error adding orphan block {}: {:?}", hash, err),
+                }
+            }
+            self.orphans.prune(self.chain.latest_block.id);
+        }
+    }
+
+    // Handles an event received from the p2p layer and returns the events to send back to it
+    pub async fn handle_event(&mut self, event: EventType, now: Instant) -> Vec<EventType> {
+        let subject = event_subject(&event);
+        let started_at = Instant::now();
+        let outgoing = self.dispatch_event(event, now).await;
+        watchdog::observe(TaskKind::Gossip, started_at.elapsed(), || subject);
+        outgoing
+    }
+
+    async fn dispatch_event(&mut self, event: EventType, now: Instant) -> Vec<EventType> {
+        let mut outgoing = vec![];
+        match event {
+            EventType::SendLatestBlockRequest{receiver} => {
+                info!("Get latest block for: {:?}", receiver);
+                let block = self.chain.latest_block.clone();
+                outgoing.push(EventType::SendLatestBlock{receiver, block});
+            },
+            EventType::ReceivedChain{sender, session_id, chain} => {
+                self.handle_chain(sender, session_id, chain, None, now).await;
+            },
+            EventType::ReceivedCheckedChain{sender, session_id, chain, error} => {
+                self.handle_chain(sender, session_id, chain, Some(error.map_or(Ok(()), Err)), now).await;
+            },
+            EventType::ReceivedChainRequest{receiver, session_id, from} => {
+                match Chain::get_chain(&mut self.storage).await {
+                    Ok(chain) => outgoing.extend(workers::chain_response(chain, receiver, session_id, from)),
+                    Err(err) => error!(session = %session_id, "{:?}", err)
+                }
+            },
+            EventType::ReceivedSnapshotRequest{receiver, session_id} => {
+                match Chain::get_chain(&mut self.storage).await {
+                    Ok(chain) => outgoing.extend(workers::snapshot_response(&self.chain, chain, receiver, session_id)),
+                    Err(err) => error!(session = %session_id, "{:?}", err)
+                }
+            },
+            EventType::ReceivedSnapshot{sender, session_id, snapshot} => {
+                if !self.sync_manager.is_pending(&session_id) {
+                    warn!(session = %session_id, "Ignoring snapshot from {} for unknown sync session", sender);
+                    return outgoing;
+                }
+                info!(session = %session_id, "Received snapshot of {} blocks from {}", snapshot.blocks.len(), sender);
+                let old_tip = self.tip();
+                let outcome = match self.accept_snapshot(sender, &snapshot).await {
+                    Ok(true) => {
+                        info!(session = %session_id, "Restored chain from snapshot up to {}", self.chain.latest_block.id);
+                        SyncOutcome::Success
+                    },
+                    Ok(false) => {
+                        info!(
+                            session = %session_id,
+                            "Snapshot is valid, waiting for {} signers to agree on a base, {} sent one so far",
+                            self.snapshot_trust.quorum,
+                            self.snapshot_votes.len()
+                        );
+                        SyncOutcome::Success
+                    },
+                    Err(err) => {
+                        error!(session = %session_id, "Error restoring snapshot: {:?}", err);
+                        SyncOutcome::Failed(err.to_string())
+                    }
+                };
+                let session = self.sync_manager.finish(&session_id, snapshot.blocks.len(), outcome, now).cloned();
+                if let Some(session) = session {
+                    self.record_sync(&session, old_tip).await;
+                }
+            },
+            EventType::ReceivedLatestBlock{sender, block} => {
+                info!("Got latest block: {:?}", block);
+                self.sync_manager.record_block(&sender, block.id);
+                // Check if our chain is the longest
+                // TODO improve/extend checks
+                if self.fast_sync && self.chain.latest_block.id == Height::GENESIS && block.id >= FAST_SYNC_MIN_HEIGHT {
+                    outgoing.extend(self.request_snapshot(sender, SyncTrigger::LatestBlock(block.id), now));
+                } else if self.chain.latest_block.id < block.id {
+                    outgoing.extend(self.request_chain(sender, SyncTrigger::LatestBlock(block.id), now));
+                } else {
+                    info!("We got the longest chain, not syncing");
+                }
+            },
+            EventType::ReceivedNewBlock{sender, block} => {
+                info!("Received new block: {:?}", block);
+                // The miner measures propagation with the receipts, see latency.rs and loadgen.rs
+                let received_at = Utc::now().timestamp_millis();
+                outgoing.push(EventType::SendBlockReceipt{receiver: sender.clone(), hash: block.hash.clone(), received_at});
+                let id = block.id;
+                let timestamp = block.timestamp_millis();
+                self.sync_manager.record_block(&sender, id);
+                // We are missing the parent of the block (e.g. because we missed a block or the sender is on a
+                // longer fork), so the block waits in the orphan pool while we fetch the sender's chain
+                if id > self.chain.latest_block.id && self.storage.get_block(&block.prev_hash).await.is_err() {
+                    info!("Received orphan block at height {} from {}", id, sender);
+                    if let Err(err) = self.orphans.insert(block) {
+                        warn!("Not pooling orphan block from {}: {:?}", sender, err);
+                    }
+                    outgoing.extend(self.request_chain(sender, SyncTrigger::OrphanBlock(id), now));
+                    return outgoing;
+                }
+                match self.chain.add_block(&mut self.storage, block).await {
+                    Ok(()) => {
+                        let latency = latency::record_arrival(timestamp, received_at);
+                        info!("Added new block, {}ms after its timestamp", latency);
+                        self.stats.blocks_received(1);
+                        self.connect_orphans().await;
+                    },
+                    Err(BlockchainError::BlockStale(hash)) => info!("Stored stale block {} from {}", hash, sender),
+                    Err(err) => error!("Error adding new block: {:?}", err)
+                }
+            },
+            EventType::ReceivedBlockAnnouncement{sender, announcement} => {
+                info!("Received announcement of block {} at height {} from {}", announcement.hash, announcement.id, sender);
+                self.sync_manager.record_block(&sender, announcement.id);
+                if self.storage.get_block(&announcement.hash).await.is_ok() {
+                    return outgoing;
+                }
+                // Without its parent the body would be an orphan, so we fetch the sender's chain right away
+                if announcement.id > self.chain.latest_block.id && self.storage.get_block(&announcement.prev_hash).await.is_err() {
+                    info!("Announced block at height {} from {} is an orphan", announcement.id, sender);
+                    outgoing.extend(self.request_chain(sender, SyncTrigger::OrphanAnnouncement(announcement.id), now));
+                } else {
+                    outgoing.push(EventType::SendBlockRequest{receiver: sender, hash: announcement.hash});
+                }
+            },
+            EventType::ReceivedBlockRequest{receiver, hash} => {
+                match self.storage.get_block(&hash).await {
+                    Ok(block) => outgoing.push(EventType::SendBlock{receiver, block}),
+                    Err(err) => warn!("Can not send block {} to {}: {:?}", hash, receiver, err)
+                }
+            },
+            EventType::ReceivedFinalizedCheckpoint{sender, checkpoint} => {
+                match self.chain.accept_checkpoint(&mut self.storage, checkpoint, &sender).await {
+                    Ok(true) => info!("Accepted finalized checkpoint from {}: {:?}", sender, self.chain.finalized),
+                    Ok(false) => {},
+                    Err(err) => error!("Error accepting checkpoint from {}: {:?}", sender, err)
+                }
+            },
+            EventType::ReceivedServiceAnnouncement{sender, announcement} => {
+                match self.services.record(announcement, Utc::now().timestamp_millis()) {
+                    Ok(true) => info!("Recorded the services of {}", sender),
+                    Ok(false) => {},
+                    Err(err) => warn!("Invalid service announcement from {}: {:?}", sender, err)
+                }
+            },
+            EventType::ReceivedBlockReceipt{sender, hash, received_at} => {
+                if let Some(latency) = latency::record_receipt(&hash) {
+                    info!("First receipt of block {} from {} after {}ms", hash, sender, latency);
+                }
+                if let Some(loadgen) = &mut self.loadgen {
+                    if let Some(latency) = loadgen.record_receipt(&sender, &hash, received_at) {
+                        info!("Block {} reached {} after {}ms", hash, sender, latency);
+                    }
+                }
+            },
+            EventType::ReceivedPoolMessage{sender, message} => {
+                outgoing.extend(self.handle_pool_message(sender, message).await);
+            },
+            EventType::PeerHandshake{peer, handshake} => {
+                self.sync_manager.set_handshake(&peer, handshake.as_ref());
+                if let Some(handshake) = handshake {
+                    self.stats.peer_seen(&peer);
+                    self.lifecycle.peer_connected(&peer, &handshake).await;
+                }
+            },
+            EventType::PeerScoresUpdated{scores} => {
+                if let Err(err) = self.storage.save_peer_scores(&scores).await {
+                    error!("Error saving peer scores: {:?}", err);
+                }
+            },
+            EventType::ReceivedBadMessage(message) => {
+                if let Err(err) = self.storage.insert_bad_message(&message).await {
+                    error!("Error saving bad message: {:?}", err);
+                }
+            },
+            _ => {}
+        }
+        match self.refresh_pool().await {
+            Ok(events) => outgoing.extend(events),
+            Err(err) => error!("Error refreshing pool template: {:?}", err),
+        }
+        outgoing
+    }
+}
+
+fn job_events(jobs: Vec<(String, PoolJob)>) -> Vec<EventType> {
+    jobs.into_iter()
+        .map(|(receiver, job)| EventType::SendPoolMessage{receiver, message: PoolMessage::Job(job)})
+        .collect()
+}
+
+// What the watchdog reports about a slow event: the peer and the block, without whole chains. Built
+// for every event, so the events carrying many blocks are summarized.
+fn event_subject(event: &EventType) -> String {
+    match event {
+        EventType::ReceivedNewBlock{sender, block} => format!("new block {} at height {} from {}", block.hash, block.id, sender),
+        EventType::ReceivedLatestBlock{sender, block} => format!("latest block {} at height {} from {}", block.hash, block.id, sender),
+        EventType::ReceivedBlockAnnouncement{sender, announcement} => format!("announcement of {} at height {} from {}", announcement.hash, announcement.id, sender),
+        EventType::ReceivedBlockRequest{receiver, hash} => format!("request for block {} from {}", hash, receiver),
+        EventType::ReceivedChain{sender, chain, ..} | EventType::ReceivedCheckedChain{sender, chain, ..} => {
+            format!("chain of {} blocks up to {} from {}", chain.len(), chain.last().map_or("", |block| block.hash.as_str()), sender)
+        }
+        EventType::ReceivedChainRequest{receiver, from, ..} => format!("chain request from height {} from {}", from, receiver),
+        EventType::ReceivedSnapshot{sender, snapshot, ..} => format!("snapshot of {} blocks from {}", snapshot.blocks.len(), sender),
+        EventType::ReceivedSnapshotRequest{receiver, ..} => format!("snapshot request from {}", receiver),
+        EventType::ReceivedFinalizedCheckpoint{sender, checkpoint} => format!("checkpoint {} at height {} from {}", checkpoint.hash, checkpoint.id, sender),
+        EventType::ReceivedServiceAnnouncement{sender, announcement} => format!("{} services from {}", announcement.services.len(), sender),
+        EventType::SendLatestBlockRequest{receiver} => format!("latest block request from {}", receiver),
+        EventType::ReceivedBlockReceipt{sender, hash, ..} => format!("receipt of {} from {}", hash, sender),
+        EventType::ReceivedPoolMessage{sender, ..} => format!("pool message from {}", sender),
+        EventType::ReceivedRpcCall{sender, ..} => format!("rpc call from {}", sender),
+        EventType::ReplayEvents{events} => format!("replay of {} events", events.len()),
+        EventType::PeerHandshake{peer, ..} => format!("handshake of {}", peer),
+        EventType::ForChain{chain, event} => format!("{} of chain {}", event_subject(event), chain),
+        event => format!("{:?}", event).chars().take(watchdog::MAX_SUBJECT_LENGTH).collect(),
+    }
+}
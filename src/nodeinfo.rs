@@ -0,0 +1,96 @@
+// Node fingerprint for fleet inventory: who a node is (peer id, listen addresses), which chain it
+// follows (genesis hash, network) and what it runs (storage backend, build version and commit). It
+// is printed as a banner at start-up and by `node id`, sent to our peers in the handshake and
+// answered by the `getnodeinfo` RPC method. `gethealth` adds our tip to it and is our counterpart of
+// an HTTP `/healthz` endpoint, which the node does not have. The version and commit are embedded at
+// compile time, the commit by build.rs from git.
+use crate::blockchain::Block;
+use crate::config::{Config, StorageKind};
+use crate::p2p::DEFAULT_LISTEN_ADDRS;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Builds outside of a git checkout do not know their commit
+pub const COMMIT: &str = match option_env!("BUILD_COMMIT") {
+    Some(commit) => commit,
+    None => "unknown",
+};
+
+#[derive(Serialize, Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct NodeInfo {
+    pub peer_id: String,
+    pub listen_addresses: Vec<String>,
+    pub genesis_hash: String,
+    pub network: String,
+    pub storage: String,
+    pub version: String,
+    pub commit: String,
+}
+
+impl NodeInfo {
+    // A node started with the config. Until the p2p layer reports the addresses it listens on, they
+    // are the ones we asked for.
+    pub fn new(peer_id: String, config: &Config) -> Self {
+        let listen_addresses = match (config.p2p && !config.outbound_only, config.listen.is_empty()) {
+            (false, _) => vec![],
+            (true, true) => DEFAULT_LISTEN_ADDRS.iter().map(|addr| addr.to_string()).collect(),
+            (true, false) => config.listen.clone(),
+        };
+        let network = match config.regtest {
+            true => format!("{} (regtest)", config.network.name()),
+            false => config.network.name(),
+        };
+        let storage = match config.storage {
+            StorageKind::Postgres => "postgres",
+            StorageKind::Memory => "memory",
+        };
+        Self {
+            peer_id,
+            listen_addresses,
+            genesis_hash: config.network.genesis().hash,
+            network,
+            storage: storage.to_owned(),
+            version: VERSION.to_owned(),
+            commit: COMMIT.to_owned(),
+        }
+    }
+
+    // Version and commit in one, e.g. for the peer list
+    pub fn build(&self) -> String {
+        format!("{} ({})", self.version, self.commit)
+    }
+}
+
+// Answer of the gethealth RPC method. A node whose storage can not answer for its latest block
+// answers gethealth with an error instead.
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub status: String,
+    pub height: Height,
+    pub latest_hash: String,
+    #[serde(flatten)]
+    pub node: NodeInfo,
+}
+
+impl Health {
+    pub fn new(node: &NodeInfo, latest: &Block) -> Self {
+        Self { status: "ok".to_owned(), height: latest.id, latest_hash: latest.hash.clone(), node: node.clone() }
+    }
+}
+
+impl fmt::Display for NodeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "peer id: {}", self.peer_id)?;
+        match self.listen_addresses.is_empty() {
+            true => writeln!(f, "listening on: -")?,
+            false => writeln!(f, "listening on: {}", self.listen_addresses.join(", "))?,
+        }
+        writeln!(f, "genesis: {}", self.genesis_hash)?;
+        writeln!(f, "network: {}", self.network)?;
+        writeln!(f, "storage: {}", self.storage)?;
+        write!(f, "build: {}", self.build())
+    }
+}
@@ -0,0 +1,109 @@
+// Orphan pool: a block whose parent we do not have (e.g. because it overtook its parent on the
+// network, or the sender is on a longer fork) waits here while we fetch the sender's chain, and is
+// added once its parent is our latest block (see node.rs). The pool is capped by the number of
+// orphans (`--max-orphans N`) and their summed data size (`--max-orphan-bytes BYTES`). A full pool
+// evicts the oldest orphans first, an orphan larger than the size cap is rejected right away.
+// Orphans at or below our height are dropped, they can not extend our chain anymore.
+use crate::blockchain::{Block, BlockchainError};
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+pub const DEFAULT_MAX_ORPHANS: usize = 100;
+pub const DEFAULT_MAX_ORPHAN_BYTES: usize = 16 << 20;
+
+// How full the pool is, for `mine queue` and the getorphaninfo RPC method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrphanOccupancy {
+    pub blocks: usize,
+    pub max_blocks: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    // Orphans evicted to make room since start-up
+    pub evicted: u64,
+}
+
+impl fmt::Display for OrphanOccupancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "orphans: {} of {} | size: {} of {} bytes | evicted: {}",
+            self.blocks, self.max_blocks, self.bytes, self.max_bytes, self.evicted
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OrphanPool {
+    // Oldest first
+    blocks: VecDeque<Block>,
+    bytes: usize,
+    max_blocks: usize,
+    max_bytes: usize,
+    evicted: u64,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ORPHANS, DEFAULT_MAX_ORPHAN_BYTES)
+    }
+}
+
+impl OrphanPool {
+    pub fn new(max_blocks: usize, max_bytes: usize) -> Self {
+        Self { blocks: VecDeque::new(), bytes: 0, max_blocks, max_bytes, evicted: 0 }
+    }
+
+    // Pools the orphan, evicting the oldest ones beyond the caps. An orphan pooled before is ignored.
+    pub fn insert(&mut self, block: Block) -> Result<(), BlockchainError> {
+        if self.blocks.iter().any(|orphan| orphan.hash == block.hash) {
+            return Ok(());
+        }
+        if block.data.len() > self.max_bytes {
+            return Err(BlockchainError::Error(format!(
+                "orphan block of {} bytes exceeds the orphan pool cap of {} bytes",
+                block.data.len(),
+                self.max_bytes
+            )));
+        }
+        self.bytes += block.data.len();
+        self.blocks.push_back(block);
+        while self.blocks.len() > self.max_blocks || self.bytes > self.max_bytes {
+            match self.blocks.pop_front() {
+                Some(evicted) => {
+                    self.bytes -= evicted.data.len();
+                    self.evicted += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // Removes and returns the orphans whose parent is the block with the hash, oldest first
+    pub fn take_children(&mut self, hash: &str) -> Vec<Block> {
+        let (children, rest) = self.blocks.drain(..).partition::<Vec<_>, _>(|orphan| orphan.prev_hash == hash);
+        self.blocks = rest.into();
+        self.bytes -= children.iter().map(|child| child.data.len()).sum::<usize>();
+        children
+    }
+
+    // Drops the orphans at or below the height
+    pub fn prune(&mut self, height: Height) {
+        self.blocks.retain(|orphan| orphan.id > height);
+        self.bytes = self.blocks.iter().map(|orphan| orphan.data.len()).sum();
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn occupancy(&self) -> OrphanOccupancy {
+        OrphanOccupancy { blocks: self.blocks.len(), max_blocks: self.max_blocks, bytes: self.bytes, max_bytes: self.max_bytes, evicted: self.evicted }
+    }
+}
@@ -1,13 +1,24 @@
-use futures::{prelude::*, select};
+use async_trait::async_trait;
+use futures::prelude::*;
 use libp2p::{
-    core::transport::upgrade,
+    core::{
+        transport::upgrade,
+        upgrade::{read_length_prefixed, write_length_prefixed},
+    },
     gossipsub::{
-        Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, IdentTopic as Topic,
-        MessageAuthenticity, MessageId, ValidationMode,
+        error::PublishError, Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage,
+        IdentTopic as Topic, MessageAuthenticity, MessageId,
+        TopicHash, PeerScoreParams, PeerScoreThresholds,
+        TopicScoreParams, ValidationMode,
     },
+    identify::{Identify, IdentifyConfig, IdentifyEvent},
     identity,
     mdns::{MdnsEvent, TokioMdns},
     mplex, noise,
+    request_response::{
+        ProtocolName, ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+        RequestResponseEvent, RequestResponseMessage,
+    },
     swarm::{
         dial_opts::{DialOpts, PeerCondition},
         SwarmBuilder, SwarmEvent,
@@ -15,30 +26,85 @@ use libp2p::{
     tcp::{GenTcpConfig, TokioTcpTransport},
     Multiaddr, NetworkBehaviour, PeerId, Swarm, Transport,
 };
-use once_cell::sync::Lazy;
+use chrono::Utc;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
 use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
-use tokio::{
-    io::{self, AsyncBufReadExt},
-    sync::{mpsc},
-};
-use tracing::{debug, error, Level};
+use std::io;
+use std::iter;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::{sync::mpsc, time};
+use tracing::debug;
+
+use crate::blockchain::{Block, BlockchainError, Checkpoint};
+use crate::capture::{self, CaptureReplay, CaptureWriter, CapturedMessage, Channel, Direction};
+use crate::chains;
+use crate::config::Config;
+use crate::deadletter::{self, BadMessage, DeadLetters, BAD_MESSAGE_PENALTY};
+use crate::diversity::{Discovery, Diversity, ROTATION_INTERVAL};
+use crate::endpoints::ServiceAnnouncement;
+use crate::fastsync::ChainSnapshot;
+use crate::handshake::{Features, Handshake, HANDSHAKE_TIMEOUT};
+use crate::nodeinfo::NodeInfo;
+use crate::pool::PoolMessage;
+use crate::propagation::{BlockAnnouncement, Propagation};
+use crate::types::{EventType, Height, PeerScore};
+
+// Identity loaded from the key store (--keys), see set_identity
+static IDENTITY: OnceCell<identity::Keypair> = OnceCell::new();
+// Generate local keypair, unless we have a stored identity
+static LOCAL_KEY: Lazy<identity::Keypair> =
+    Lazy::new(|| IDENTITY.get().cloned().unwrap_or_else(identity::Keypair::generate_ed25519));
+pub static LOCAL_PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(LOCAL_KEY.public()));
+// Uses the keypair as our peer identity, has to be called before the p2p layer is started.
+// Returns false if the identity was already set or used.
+pub fn set_identity(keypair: identity::Keypair) -> bool {
+    Lazy::get(&LOCAL_KEY).is_none() && IDENTITY.set(keypair).is_ok()
+}
 
-use crate::blockchain::Block;
-use crate::types::EventType;
+// Request-response protocols next to gossipsub, see DirectProtocol, PoolProtocol and HandshakeProtocol
+pub const DIRECT_PROTOCOL: &str = "/blockchain/direct/1";
+pub const POOL_PROTOCOL: &str = "/blockchain/pool/1";
+pub const HANDSHAKE_PROTOCOL: &str = "/blockchain/handshake/1";
 
-// Generate local keypair
-static LOCAL_KEY: Lazy<identity::Keypair> = Lazy::new(identity::Keypair::generate_ed25519);
-static LOCAL_PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(LOCAL_KEY.public()));
 // Create a gossipsub topic
 static TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("blockchain"));
+// Upper bound for messages sent directly to a peer (a whole chain can be sent this way)
+const MAX_DIRECT_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+// Upper bound for pool protocol messages (a job carries a block template)
+const MAX_POOL_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+// Upper bound for handshakes, see handshake.rs
+const MAX_HANDSHAKE_SIZE: usize = 64 * 1024;
+// Addresses we listen on without --listen: every IPv4 and IPv6 interface, on ports picked by the OS
+pub const DEFAULT_LISTEN_ADDRS: [&str; 2] = ["/ip4/0.0.0.0/tcp/0", "/ip6/::/tcp/0"];
+// Number of peers we hand out on prune and via peer exchange
+const PX_PEERS: usize = 16;
+// We keep dialing peers learned via peer exchange until we are connected to this many peers
+// (the default gossipsub mesh size), unless set with --target-peers
+pub const DEFAULT_TARGET_PEERS: usize = 6;
+// How often we check whether we have to dial more peers
+const REDIAL_INTERVAL: Duration = Duration::from_secs(30);
+// How often the scores of our peers are persisted
+const PEER_SCORE_INTERVAL: Duration = Duration::from_secs(60);
+// Peers whose score drops below this are banned (the gossipsub graylist threshold)
+const BAN_THRESHOLD: f64 = -80.0;
+// Upper bound for the reputation carried over from previous runs, so good scores do not add up across restarts
+const MAX_RESTORED_SCORE: f64 = 100.0;
+// Latest blocks and chains sent longer ago than this are ignored, so delayed or replayed messages
+// can not start syncs with outdated chains or make our view of the tip flap
+pub const MAX_MESSAGE_AGE: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct ReceivedLatestBlock {
     receiver: String,
     block: Block,
+    // Milliseconds since the epoch, messages of older nodes have none
+    #[serde(default)]
+    sent_at: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,17 +113,63 @@ struct ReceivedNewBlock {
     block: Block,
 }
 
+// Gossiped instead of ReceivedNewBlock with --propagation announce, see propagation.rs
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewBlockAnnouncement {
+    announcement: BlockAnnouncement,
+}
+
+// Asks the peer that announced a block for its body
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockRequest {
+    receiver: String,
+    hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockBody {
+    receiver: String,
+    body: Block,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct ReceivedChain {
     receiver: String,
+    // Echoes the session id of the chain request, see sync.rs
+    session_id: String,
     chain: Vec<Block>,
+    // Milliseconds since the epoch, messages of older nodes have none
+    #[serde(default)]
+    sent_at: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct ChainRequest {
     receiver: String,
+    session_id: String,
+    // Height of the first block wanted, requests of older nodes ask for the complete chain
+    #[serde(default)]
+    from: Height,
+    // Asks for a snapshot of the chain instead, see fastsync.rs
+    #[serde(default)]
+    snapshot: bool,
+}
+
+// Signed with the identity key of the peer that created the snapshot, which vouches for the blocks
+// the snapshot only links to
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReceivedSnapshot {
+    receiver: String,
+    session_id: String,
+    snapshot: ChainSnapshot,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -67,16 +179,242 @@ struct LatestBlockRequest {
     random: bool,
 }
 
+// Signed with the identity key of the peer that finalized the checkpoint, so it
+// can be verified independently of the gossipsub message it was relayed in
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FinalizedCheckpoint {
+    checkpoint: Checkpoint,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+// Signed like FinalizedCheckpoint, the announcement names the peer ID of the key, see endpoints.rs
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignedServiceAnnouncement {
+    announcement: ServiceAnnouncement,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+// Sent to the miner of a generated block, see loadgen.rs
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockReceipt {
+    receiver: String,
+    hash: String,
+    received_at: i64,
+}
+
+// Addresses of peers we know about, sent directly to newly identified peers so they can dial
+// the peers gossipsub hands out on prune (peer exchange only carries peer IDs)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeerExchange {
+    peers: Vec<PeerAddresses>,
+}
+
+// Gossip message of one of the additional chains, sent directly when gossipsub can not publish it
+// (direct messages do not carry a topic the chain could be told by)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainMessage {
+    chain: String,
+    message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeerAddresses {
+    peer_id: String,
+    addresses: Vec<String>,
+}
+
+// Protocol used to send gossip messages directly to our peers when gossipsub can not publish them
+// because our mesh is too sparse (e.g. in networks of only two or three nodes)
+#[derive(Debug, Clone)]
+struct DirectProtocol;
+
+impl ProtocolName for DirectProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        DIRECT_PROTOCOL.as_bytes()
+    }
+}
+
+// Requests are the JSON gossip messages, responses are empty acknowledgements
+#[derive(Clone)]
+struct DirectCodec;
+
+#[async_trait]
+impl RequestResponseCodec for DirectCodec {
+    type Protocol = DirectProtocol;
+    type Request = Vec<u8>;
+    type Response = ();
+
+    async fn read_request<T>(&mut self, _: &DirectProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, MAX_DIRECT_MESSAGE_SIZE).await?;
+        if data.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(data)
+    }
+
+    async fn read_response<T>(&mut self, _: &DirectProtocol, _: &mut T) -> io::Result<()>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Ok(())
+    }
+
+    async fn write_request<T>(&mut self, _: &DirectProtocol, io: &mut T, data: Vec<u8>) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, data).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &DirectProtocol, io: &mut T, _: ()) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.close().await
+    }
+}
+
+// Protocol between a mining pool coordinator and its workers, see pool.rs
+#[derive(Debug, Clone)]
+struct PoolProtocol;
+
+impl ProtocolName for PoolProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        POOL_PROTOCOL.as_bytes()
+    }
+}
+
+// Requests are JSON encoded pool messages, responses are empty acknowledgements
+#[derive(Clone)]
+struct PoolCodec;
+
+#[async_trait]
+impl RequestResponseCodec for PoolCodec {
+    type Protocol = PoolProtocol;
+    type Request = PoolMessage;
+    type Response = ();
+
+    async fn read_request<T>(&mut self, _: &PoolProtocol, io: &mut T) -> io::Result<PoolMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, MAX_POOL_MESSAGE_SIZE).await?;
+        serde_json::from_slice(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn read_response<T>(&mut self, _: &PoolProtocol, _: &mut T) -> io::Result<()>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Ok(())
+    }
+
+    async fn write_request<T>(&mut self, _: &PoolProtocol, io: &mut T, message: PoolMessage) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let data = serde_json::to_vec(&message).expect("can jsonify pool message");
+        write_length_prefixed(io, data).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &PoolProtocol, io: &mut T, _: ()) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.close().await
+    }
+}
+
+// Protocol both peers send their handshake over right after connecting, see handshake.rs
+#[derive(Debug, Clone)]
+struct HandshakeProtocol;
+
+impl ProtocolName for HandshakeProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        HANDSHAKE_PROTOCOL.as_bytes()
+    }
+}
+
+// Requests and responses are JSON encoded handshakes, the response is the receiver's own
+#[derive(Clone)]
+struct HandshakeCodec;
+
+#[async_trait]
+impl RequestResponseCodec for HandshakeCodec {
+    type Protocol = HandshakeProtocol;
+    type Request = Handshake;
+    type Response = Handshake;
+
+    async fn read_request<T>(&mut self, _: &HandshakeProtocol, io: &mut T) -> io::Result<Handshake>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_handshake(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &HandshakeProtocol, io: &mut T) -> io::Result<Handshake>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_handshake(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &HandshakeProtocol, io: &mut T, handshake: Handshake) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_handshake(io, &handshake).await
+    }
+
+    async fn write_response<T>(&mut self, _: &HandshakeProtocol, io: &mut T, handshake: Handshake) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_handshake(io, &handshake).await
+    }
+}
+
+async fn read_handshake<T: AsyncRead + Unpin + Send>(io: &mut T) -> io::Result<Handshake> {
+    let data = read_length_prefixed(io, MAX_HANDSHAKE_SIZE).await?;
+    serde_json::from_slice(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+async fn write_handshake<T: AsyncWrite + Unpin + Send>(io: &mut T, handshake: &Handshake) -> io::Result<()> {
+    write_length_prefixed(io, serde_json::to_vec(handshake).expect("can jsonify handshake")).await?;
+    io.close().await
+}
+
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "NetworkEvent")]
 struct BlockchainBehavior {
     gossipsub: Gossipsub,
     mdns: TokioMdns,
+    direct: RequestResponse<DirectCodec>,
+    pool: RequestResponse<PoolCodec>,
+    handshake: RequestResponse<HandshakeCodec>,
+    identify: Identify,
 }
 
 enum NetworkEvent {
     Gossipsub(GossipsubEvent),
     TokioMdns(MdnsEvent),
+    Direct(RequestResponseEvent<Vec<u8>, ()>),
+    Pool(RequestResponseEvent<PoolMessage, ()>),
+    Handshake(RequestResponseEvent<Handshake, Handshake>),
+    Identify(Box<IdentifyEvent>),
 }
 
 impl From<GossipsubEvent> for NetworkEvent {
@@ -91,16 +429,93 @@ impl From<MdnsEvent> for NetworkEvent {
     }
 }
 
+impl From<RequestResponseEvent<Vec<u8>, ()>> for NetworkEvent {
+    fn from(event: RequestResponseEvent<Vec<u8>, ()>) -> Self {
+        Self::Direct(event)
+    }
+}
+
+impl From<RequestResponseEvent<PoolMessage, ()>> for NetworkEvent {
+    fn from(event: RequestResponseEvent<PoolMessage, ()>) -> Self {
+        Self::Pool(event)
+    }
+}
+
+impl From<RequestResponseEvent<Handshake, Handshake>> for NetworkEvent {
+    fn from(event: RequestResponseEvent<Handshake, Handshake>) -> Self {
+        Self::Handshake(event)
+    }
+}
+
+impl From<IdentifyEvent> for NetworkEvent {
+    fn from(event: IdentifyEvent) -> Self {
+        Self::Identify(Box::new(event))
+    }
+}
+
 pub async fn init_p2p(
     mut rx_rcv: mpsc::UnboundedReceiver<EventType>,
     main_sender: mpsc::UnboundedSender<EventType>,
+    config: Config,
 ) -> Result<(), std::io::Error> {
-    println!("Local PeerId: {:?}", LOCAL_PEER_ID.clone());
+    println!("Local PeerId: {:?}", *LOCAL_PEER_ID);
+
+    let bootstrap_peers = config
+        .peers
+        .iter()
+        .map(|addr| addr.parse::<Multiaddr>())
+        .collect::<Result<Vec<Multiaddr>, _>>()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let listen_addrs = match config.listen.is_empty() {
+        true => DEFAULT_LISTEN_ADDRS.iter().map(|addr| addr.parse::<Multiaddr>()).collect::<Result<Vec<Multiaddr>, _>>(),
+        false => config.listen.iter().map(|addr| addr.parse::<Multiaddr>()).collect::<Result<Vec<Multiaddr>, _>>(),
+    }
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
 
     // We manually keep track of all currently connected gossipsub peers
     // in order to keep the borrow checker happy (otherwise we would need
     // acces to both the gossipsub and mdns behaviours at the same time)
     let mut gossipsub_peers: HashSet<PeerId> = HashSet::<PeerId>::new();
+    // Listen addresses of all peers we learned about via mDNS, identify or peer exchange
+    let mut address_book: HashMap<PeerId, HashSet<Multiaddr>> = HashMap::new();
+    // How we learned about our peers and what our outbound connections go to, see diversity.rs
+    let mut diversity = Diversity::default();
+    // Last known scores of all peers we have been connected to, including the restored ones
+    let mut peer_scores: HashMap<PeerId, PeerScore> = HashMap::new();
+    // Application specific scores we set for connected peers: the restored reputation plus the
+    // penalties for bad messages
+    let mut application_scores: HashMap<PeerId, f64> = HashMap::new();
+    // Latest messages we could not decode
+    let mut dead_letters = DeadLetters::default();
+    // Our handshake, its best block and features are kept current by main. The handshakes of the peers that
+    // completed it, and since when we wait for the others.
+    let genesis = config.network.genesis();
+    let mut local_handshake = Handshake::new(genesis.hash.clone(), Checkpoint { id: genesis.id, hash: genesis.hash }, Features::of(&config));
+    local_handshake.node = NodeInfo::new(LOCAL_PEER_ID.to_string(), &config);
+    let mut handshakes: HashMap<PeerId, Handshake> = HashMap::new();
+    // Number of peers we dial up to, can be changed while we run (see reload.rs)
+    let mut target_peers = config.target_peers;
+    let mut pending_handshakes: HashMap<PeerId, Instant> = HashMap::new();
+    // Log of all messages sent and received, if enabled
+    let mut capture = match &config.capture {
+        Some(path) => match CaptureWriter::create(path, config.capture_max_bytes) {
+            Ok(writer) => {
+                println!("Capturing p2p messages to {}", path.display());
+                Some(writer)
+            }
+            Err(err) => {
+                println!("Cannot capture p2p messages to {}: {:?}", path.display(), err);
+                None
+            }
+        },
+        None => None,
+    };
+    // Additional chains we follow and the topics their messages are published on, see chains.rs
+    let chain_names = config.chains.iter().map(|chain| chain.name.clone()).collect::<Vec<String>>();
+    let chain_topics = chain_names
+        .iter()
+        .map(|chain| (Topic::new(chains::topic_name(chain)).hash(), chain.clone()))
+        .collect::<HashMap<TopicHash, String>>();
 
     // Create a keypair for authenticated encryption of the transport.
     let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
@@ -117,14 +532,33 @@ pub async fn init_p2p(
 
     // Create a swarm to manage peers and events
     let mut swarm = {
-        let mut blockchain_behavior = BlockchainBehavior {
-            gossipsub: build_gossipsub_behavior(),
+        let blockchain_behavior = BlockchainBehavior {
+            gossipsub: build_gossipsub_behavior(&chain_names),
             mdns: TokioMdns::new(Default::default())
                 .await
                 .expect("can create mdns"),
+            direct: RequestResponse::new(
+                DirectCodec,
+                iter::once((DirectProtocol, ProtocolSupport::Full)),
+                RequestResponseConfig::default(),
+            ),
+            pool: RequestResponse::new(
+                PoolCodec,
+                iter::once((PoolProtocol, ProtocolSupport::Full)),
+                RequestResponseConfig::default(),
+            ),
+            handshake: RequestResponse::new(
+                HandshakeCodec,
+                iter::once((HandshakeProtocol, ProtocolSupport::Full)),
+                RequestResponseConfig::default(),
+            ),
+            identify: Identify::new(IdentifyConfig::new(
+                config.network.protocol_version(),
+                LOCAL_KEY.public(),
+            )),
         };
 
-        SwarmBuilder::new(transport, blockchain_behavior, LOCAL_PEER_ID.clone())
+        SwarmBuilder::new(transport, blockchain_behavior, *LOCAL_PEER_ID)
             // We want the connection background tasks to be spawned
             // onto the tokio runtime.
             .executor(Box::new(|fut| {
@@ -133,19 +567,83 @@ pub async fn init_p2p(
             .build()
     };
 
-    swarm
-        .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
-        .unwrap();
+    // Without a listener we are only reachable through the connections we dial ourselves.
+    // Gossip, sync requests and direct sends all work over these connections, peers just
+    // can not connect to us (mDNS and identify advertise no addresses for us).
+    if config.outbound_only {
+        println!("Outbound-only mode, not listening for incoming connections");
+    } else {
+        let mut listening = false;
+        for addr in listen_addrs.iter() {
+            match swarm.listen_on(addr.clone()) {
+                Ok(_) => listening = true,
+                Err(err) => println!("Cannot listen on {}: {:?}", addr, err),
+            }
+        }
+        if !listening {
+            return Err(std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, "can not listen on any address"));
+        }
+    }
+
+    for addr in bootstrap_peers.iter() {
+        dial_addr(&mut swarm, addr);
+    }
 
     if let Err(err) = main_sender.send(EventType::InitDone) {
         println!("P2P init sending error: {:?}", err);
     }
 
+    let mut redial_interval = time::interval(REDIAL_INTERVAL);
+    let mut peer_score_interval = time::interval(PEER_SCORE_INTERVAL);
+    let mut rotation_interval = time::interval(ROTATION_INTERVAL);
     loop {
         tokio::select! {
+            _ = redial_interval.tick() => {
+                // Peers that did not complete the handshake in time are dropped before we dial new ones
+                let expired = pending_handshakes
+                    .iter()
+                    .filter(|(_, since)| since.elapsed() > HANDSHAKE_TIMEOUT)
+                    .map(|(peer_id, _)| *peer_id)
+                    .collect::<Vec<PeerId>>();
+                for peer_id in expired {
+                    println!("Disconnecting {}: no handshake within {:?}", peer_id, HANDSHAKE_TIMEOUT);
+                    pending_handshakes.remove(&peer_id);
+                    let _ = swarm.disconnect_peer_id(peer_id);
+                }
+                redial(&mut swarm, &address_book, &peer_scores, &diversity, &bootstrap_peers, target_peers);
+            },
+            _ = rotation_interval.tick() => {
+                // Only if there is another peer to take the slot
+                let replaceable = address_book
+                    .keys()
+                    .any(|peer_id| !swarm.is_connected(peer_id) && !peer_scores.get(peer_id).map(|score| score.banned).unwrap_or_default());
+                if replaceable {
+                    if let Some(peer_id) = diversity.rotate() {
+                        println!("Rotating outbound peer {}", peer_id);
+                        let _ = swarm.disconnect_peer_id(peer_id);
+                    }
+                }
+            },
+            _ = peer_score_interval.tick() => {
+                update_peer_scores(&mut swarm, &address_book, &mut peer_scores);
+                if !peer_scores.is_empty() {
+                    let scores = peer_scores.values().cloned().collect::<Vec<PeerScore>>();
+                    let _ = main_sender.send(EventType::PeerScoresUpdated{scores});
+                }
+            },
             event = rx_rcv.recv() => {
+                // Events of the additional chains are published on their topic
+                let (chain_name, event) = match event {
+                    Some(EventType::ForChain{chain, event}) => (Some(chain), Some(*event)),
+                    event => (None, event),
+                };
                 match event {
                     Some(EventType::ListPeers) => {
+                        println!("listening on: {:?}", swarm.listeners().map(|addr| addr.to_string()).collect::<Vec<_>>());
+                        println!(
+                            "outbound diversity: {} discovery mechanisms | {} address ranges | {}",
+                            diversity.mechanisms(), diversity.groups(), if diversity.is_diverse() { "ok" } else { "too low" },
+                        );
                         println!("discovered nodes (mdns): {:?}", swarm
                         .behaviour_mut()
                         .mdns
@@ -155,57 +653,184 @@ pub async fn init_p2p(
                         .behaviour_mut()
                         .gossipsub
                         .all_peers().collect::<Vec<_>>());
+
+                        for peer_id in swarm.connected_peers() {
+                            let score = swarm.behaviour().gossipsub.peer_score(peer_id).unwrap_or_default();
+                            match handshakes.get(peer_id) {
+                                Some(handshake) => println!(
+                                    "peer: {} | score: {:.2} | best block: {} | features: {:?} | network: {} | build: {}",
+                                    peer_id, score, handshake.best_block.id, handshake.features.names(), handshake.node.network, handshake.node.build(),
+                                ),
+                                None => println!("peer: {} | score: {:.2} | no handshake yet", peer_id, score),
+                            }
+                        }
+                        for score in peer_scores.values().filter(|score| score.banned) {
+                            println!("banned peer: {} | score: {:.2}", score.peer_id, score.score);
+                        }
+                    },
+                    Some(EventType::ListBadMessages) => {
+                        for (peer, count) in dead_letters.counts() {
+                            println!("peer: {} | bad messages: {}", peer, count);
+                        }
+                        for message in dead_letters.recent() {
+                            println!(
+                                "received at: {} | peer: {} | size: {} | error: {} | prefix: {}",
+                                message.received_at, message.peer, message.size, message.error, message.prefix,
+                            );
+                        }
+                    },
+                    Some(EventType::ReplayCapture{path}) => {
+                        // The decoded messages go to a copy of the node, not to the live one
+                        let (replay_sender, mut replay_rcv) = mpsc::unbounded_channel();
+                        match replay_capture(&path, &chain_names, &replay_sender) {
+                            Ok(replay) => {
+                                for message in replay.bad.iter() {
+                                    println!("peer: {} | size: {} | error: {} | prefix: {}", message.peer, message.size, message.error, message.prefix);
+                                }
+                                println!("replayed {} messages, skipped {}, {} could not be decoded", replay.replayed, replay.skipped, replay.bad.len());
+                                let mut events = vec![];
+                                while let Ok(event) = replay_rcv.try_recv() {
+                                    events.push(event);
+                                }
+                                let _ = main_sender.send(EventType::ReplayEvents{events});
+                            }
+                            Err(err) => println!("{:?}", err),
+                        }
+                    },
+                    Some(EventType::RequestLatestBlocks) => {
+                        for peer_id in gossipsub_peers.iter() {
+                            let req = LatestBlockRequest{receiver: peer_id.to_string(), random: true};
+                            let json = serde_json::to_string(&req).expect("can jsonify request");
+                            publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                        }
+                    },
+                    Some(EventType::UpdateTargetPeers{target_peers: peers}) => {
+                        println!("Keeping {} peers connected", peers);
+                        target_peers = peers;
+                    },
+                    Some(EventType::UpdateHandshake{best_block, features}) => {
+                        local_handshake.best_block = best_block;
+                        local_handshake.features = features;
+                    },
+                    Some(EventType::RestorePeerScores{scores}) => {
+                        debug!("Restoring {} peer scores", scores.len());
+                        restore_peer_scores(&mut swarm, &mut address_book, &mut peer_scores, &mut diversity, scores, target_peers);
                     },
                     Some(EventType::SendLatestBlock{block, receiver}) => {
                         debug!("Send latest block to {:?}", receiver);
-                        let req = ReceivedLatestBlock{receiver, block};
+                        let req = ReceivedLatestBlock{receiver, block, sent_at: Some(Utc::now().timestamp_millis())};
                         let json = serde_json::to_string(&req).expect("can jsonify request");
 
-                        if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(TOPIC.clone(), json.as_bytes())
-                        {
-                            println!("Publish error: {:?}", e);
-                        }
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
                     },
                     Some(EventType::SendNewBlock(block)) => {
-                        debug!("Broadcast new block");
-                        let req = ReceivedNewBlock{block};
+                        let json = match config.propagation {
+                            Propagation::Full => {
+                                debug!("Broadcast new block");
+                                serde_json::to_string(&ReceivedNewBlock{block}).expect("can jsonify request")
+                            }
+                            Propagation::Announce => {
+                                debug!("Announce new block {}", block.hash);
+                                let req = NewBlockAnnouncement{announcement: BlockAnnouncement::of(&block)};
+                                serde_json::to_string(&req).expect("can jsonify request")
+                            }
+                        };
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendBlockRequest{receiver, hash}) => {
+                        debug!("Request block {} from {:?}", hash, receiver);
+                        let req = BlockRequest{receiver: receiver.clone(), hash};
                         let json = serde_json::to_string(&req).expect("can jsonify request");
 
-                        if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(TOPIC.clone(), json.as_bytes())
-                        {
-                            println!("Publish error: {:?}", e);
-                        }
+                        send_to(&mut swarm, &mut capture, chain_name.as_deref(), &receiver, json);
                     },
-                    Some(EventType::SendChainRequest{receiver}) => {
-                        debug!("Send chain request to {:?}", receiver);
-                        let req = ChainRequest{receiver};
+                    Some(EventType::SendBlock{receiver, block}) => {
+                        debug!("Send block {} to {:?}", block.hash, receiver);
+                        let req = BlockBody{receiver: receiver.clone(), body: block};
                         let json = serde_json::to_string(&req).expect("can jsonify request");
 
-                        if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(TOPIC.clone(), json.as_bytes())
-                        {
-                            println!("Publish error: {:?}", e);
-                        }
+                        send_to(&mut swarm, &mut capture, chain_name.as_deref(), &receiver, json);
                     },
-                    Some(EventType::SendChain{receiver, chain}) => {
-                        debug!("Send chain to {:?}", receiver);
-                        let req = ReceivedChain{receiver, chain};
+                    Some(EventType::SendChainRequest{receiver, session_id, from}) => {
+                        debug!(session = %session_id, "Send chain request to {:?}", receiver);
+                        let req = ChainRequest{receiver, session_id, from, snapshot: false};
                         let json = serde_json::to_string(&req).expect("can jsonify request");
 
-                        if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(TOPIC.clone(), json.as_bytes())
-                        {
-                            println!("Publish error: {:?}", e);
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendSnapshotRequest{receiver, session_id}) => {
+                        debug!(session = %session_id, "Send snapshot request to {:?}", receiver);
+                        let req = ChainRequest{receiver, session_id, from: Height::GENESIS, snapshot: true};
+                        let json = serde_json::to_string(&req).expect("can jsonify request");
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendSnapshot{receiver, session_id, snapshot}) => {
+                        debug!(session = %session_id, "Send snapshot to {:?}", receiver);
+                        let signature = match LOCAL_KEY.sign(&snapshot.digest()) {
+                            Ok(signature) => signature,
+                            Err(e) => {
+                                println!("Signing error: {:?}", e);
+                                continue;
+                            }
+                        };
+                        let req = ReceivedSnapshot{receiver, session_id, snapshot, public_key: LOCAL_KEY.public().to_protobuf_encoding(), signature};
+                        let json = serde_json::to_string(&req).expect("can jsonify request");
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendFinalizedCheckpoint{checkpoint}) => {
+                        debug!("Broadcast finalized checkpoint {:?}", checkpoint);
+                        let signature = match LOCAL_KEY.sign(&checkpoint_signing_bytes(&checkpoint)) {
+                            Ok(signature) => signature,
+                            Err(e) => {
+                                println!("Signing error: {:?}", e);
+                                continue;
+                            }
+                        };
+                        let req = FinalizedCheckpoint{checkpoint, public_key: LOCAL_KEY.public().to_protobuf_encoding(), signature};
+                        let json = serde_json::to_string(&req).expect("can jsonify request");
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendServiceAnnouncement{announcement}) => {
+                        debug!("Broadcast service announcement {:?}", announcement);
+                        let signature = match LOCAL_KEY.sign(&announcement.signing_bytes()) {
+                            Ok(signature) => signature,
+                            Err(e) => {
+                                println!("Signing error: {:?}", e);
+                                continue;
+                            }
+                        };
+                        let req = SignedServiceAnnouncement{announcement, public_key: LOCAL_KEY.public().to_protobuf_encoding(), signature};
+                        let json = serde_json::to_string(&req).expect("can jsonify request");
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendChain{receiver, session_id, chain}) => {
+                        debug!(session = %session_id, "Send chain to {:?}", receiver);
+                        let req = ReceivedChain{receiver, session_id, chain, sent_at: Some(Utc::now().timestamp_millis())};
+                        let json = serde_json::to_string(&req).expect("can jsonify request");
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendBlockReceipt{receiver, hash, received_at}) => {
+                        debug!("Send receipt for block {} to {:?}", hash, receiver);
+                        let req = BlockReceipt{receiver, hash, received_at};
+                        let json = serde_json::to_string(&req).expect("can jsonify request");
+
+                        publish(&mut swarm, &mut capture, chain_name.as_deref(), json);
+                    },
+                    Some(EventType::SendPoolMessage{receiver, message}) => {
+                        debug!("Send pool message to {:?}", receiver);
+                        match receiver.parse::<PeerId>() {
+                            Ok(peer_id) => {
+                                let data = serde_json::to_vec(&message).expect("can jsonify pool message");
+                                record(&mut capture, Direction::Sent, Channel::Pool, Some(&peer_id), None, None, &data);
+                                swarm.behaviour_mut().pool.send_request(&peer_id, message);
+                            },
+                            Err(_) => println!("Invalid pool peer ID: {}", receiver),
                         }
                     },
                     None => {
@@ -223,71 +848,33 @@ pub async fn init_p2p(
                         match event {
                             GossipsubEvent::Subscribed{peer_id, topic} => {
                                 debug!("Gossipsub Subscribed | PeerId: {:?}, Topic: {:?}", peer_id, topic);
-                                if gossipsub_peers.len() == 0 {
-                                    gossipsub_peers.insert(peer_id);
-                                    // Request latest block from peer on first connect/reconnect
+                                if let Some(chain) = chain_topics.get(&topic) {
+                                    // Peers following one of our other chains are asked for its latest block
                                     let req = LatestBlockRequest{receiver: peer_id.to_string(), random: true};
                                     let json = serde_json::to_string(&req).expect("can jsonify request");
-
-                                    if let Err(e) = swarm
-                                    .behaviour_mut()
-                                    .gossipsub
-                                    .publish(TOPIC.clone(), json.as_bytes())
-                                    {
-                                        println!("Publish error: {:?}", e);
-                                    }
+                                    publish(&mut swarm, &mut capture, Some(chain), json);
                                     continue;
                                 }
+                                // Peers ahead of us are asked for their latest block once the handshake told us so
                                 gossipsub_peers.insert(peer_id);
                             },
                             GossipsubEvent::Message{propagation_source, message_id: _, message} => {
-                                if let Ok(resp) = serde_json::from_slice::<ReceivedLatestBlock>(&message.data) {
-                                    if resp.receiver == LOCAL_PEER_ID.to_string() {
-                                        debug!("ReceivedLatestBlock from {:?}:", message.source);
-                                        if let Some(source) = message.source {
-                                            if let Err(err) = main_sender.send(EventType::ReceivedLatestBlock{sender: source.to_string(), block: resp.block}) {
-                                                debug!("P2P to main ReceivedLatestBlock error: {:?}", err);
-                                            }
-                                        } else {
-                                                debug!("no message source")
-                                            }
-                                    }
-                                } else if let Ok(req) = serde_json::from_slice::<LatestBlockRequest>(&message.data) {
-                                    if req.receiver == LOCAL_PEER_ID.to_string() {
-                                        debug!("SendLatestBlockRequest from {:?}:", message.source);
-                                        if let Some(source) = message.source {
-                                        if let Err(err) = main_sender.send(EventType::SendLatestBlockRequest{receiver: source.to_string()}) {
-                                            debug!("P2P to main SendLatestBlockRequest error: {:?}", err);
-                                        }
-                                    } else {
-                                            debug!("no message source")
-                                        }
-                                    }
-                                } else if let Ok(req) = serde_json::from_slice::<ChainRequest>(&message.data) {
-                                    if req.receiver == LOCAL_PEER_ID.to_string() {
-                                        debug!("ChainRequest from {:?}:", message.source);
-                                        if let Some(source) = message.source {
-                                            if let Err(err) = main_sender.send(EventType::ReceivedChainRequest{receiver: source.to_string()}) {
-                                                debug!("P2P to main ReceivedChainRequest error: {:?}", err);
-                                            }
-                                        } else {
-                                            debug!("no message source")
-                                        }
-                                    }
-                                } else if let Ok(res) = serde_json::from_slice::<ReceivedChain>(&message.data) {
-                                    if res.receiver == LOCAL_PEER_ID.to_string() {
-                                        debug!("ReceivedChain from {:?}:", message.source);
-                                        if let Err(err) = main_sender.send(EventType::ReceivedChain{chain: res.chain}) {
-                                            debug!("P2P to main ReceivedChainRequest error: {:?}", err);
-                                        }
-                                    }
-                                } else if let Ok(res) = serde_json::from_slice::<ReceivedNewBlock>(&message.data) {
-                                    if propagation_source != LOCAL_PEER_ID.clone() {
-                                        debug!("ReceivedNewBlock from {:?}:", message.source);
-                                        if let Err(err) = main_sender.send(EventType::ReceivedNewBlock(res.block)) {
-                                            debug!("P2P to main ReceivedNewBlock error: {:?}", err);
-                                        }
-                                    }
+                                // Messages on topics we do not know are of no chain we follow
+                                let chain = match chain_topics.get(&message.topic) {
+                                    Some(chain) => Some(chain.as_str()),
+                                    None if message.topic == TOPIC.hash() => None,
+                                    None => continue,
+                                };
+                                if !handshakes.contains_key(&propagation_source) {
+                                    debug!("Dropping message from {:?} before the handshake", propagation_source);
+                                    continue;
+                                }
+                                record(&mut capture, Direction::Received, Channel::Gossip, Some(&propagation_source), message.source.as_ref(), chain, &message.data);
+                                if let Err(error) = handle_message(&message.data, message.source, propagation_source, chain, Utc::now().timestamp_millis(), &main_sender) {
+                                    // Gossipsub forwards only messages it validated, so the bad message is
+                                    // from the source if it is signed, otherwise from the peer forwarding it
+                                    let sender = message.source.unwrap_or(propagation_source);
+                                    record_bad_message(&mut swarm, &mut dead_letters, &mut application_scores, &main_sender, sender, &message.data, error);
                                 }
                                 //debug!("Gossipsub Message | PropagationSource: {:?}, MesssageId: {:?}, Message: {:?}", propagation_source, message_id, message);
                             },
@@ -301,6 +888,109 @@ pub async fn init_p2p(
                             },
                         }
                     },
+                SwarmEvent::Behaviour(NetworkEvent::Direct(event)) =>
+                    match event {
+                        RequestResponseEvent::Message{peer, message: RequestResponseMessage::Request{request, channel, ..}} => {
+                            debug!("Direct message from {:?}", peer);
+                            if !handshakes.contains_key(&peer) {
+                                debug!("Dropping direct message from {:?} before the handshake", peer);
+                                let _ = swarm.behaviour_mut().direct.send_response(channel, ());
+                                continue;
+                            }
+                            record(&mut capture, Direction::Received, Channel::Direct, Some(&peer), None, None, &request);
+                            if let Ok(px) = serde_json::from_slice::<PeerExchange>(&request) {
+                                handle_peer_exchange(&mut swarm, &mut address_book, &mut diversity, px, target_peers);
+                            } else if let Ok(wrapped) = serde_json::from_slice::<ChainMessage>(&request) {
+                                // Messages of chains we do not follow are dropped
+                                if chain_names.contains(&wrapped.chain) {
+                                    let data = wrapped.message.into_bytes();
+                                    if let Err(error) = handle_message(&data, Some(peer), peer, Some(&wrapped.chain), Utc::now().timestamp_millis(), &main_sender) {
+                                        record_bad_message(&mut swarm, &mut dead_letters, &mut application_scores, &main_sender, peer, &data, error);
+                                    }
+                                }
+                            } else {
+                                // The sender is authenticated by the transport, so it is the source of the message
+                                if let Err(error) = handle_message(&request, Some(peer), peer, None, Utc::now().timestamp_millis(), &main_sender) {
+                                    record_bad_message(&mut swarm, &mut dead_letters, &mut application_scores, &main_sender, peer, &request, error);
+                                }
+                            }
+                            let _ = swarm.behaviour_mut().direct.send_response(channel, ());
+                        },
+                        RequestResponseEvent::OutboundFailure{peer, error, ..} => {
+                            println!("Direct send to {:?} failed: {:?}", peer, error);
+                        },
+                        _ => {},
+                    },
+                SwarmEvent::Behaviour(NetworkEvent::Pool(event)) =>
+                    match event {
+                        RequestResponseEvent::Message{peer, message: RequestResponseMessage::Request{request, channel, ..}} => {
+                            debug!("Pool message from {:?}", peer);
+                            if !handshakes.contains_key(&peer) {
+                                debug!("Dropping pool message from {:?} before the handshake", peer);
+                                let _ = swarm.behaviour_mut().pool.send_response(channel, ());
+                                continue;
+                            }
+                            record(&mut capture, Direction::Received, Channel::Pool, Some(&peer), None, None, &serde_json::to_vec(&request).expect("can jsonify pool message"));
+                            if let Err(err) = main_sender.send(EventType::ReceivedPoolMessage{sender: peer.to_string(), message: request}) {
+                                debug!("P2P to main ReceivedPoolMessage error: {:?}", err);
+                            }
+                            let _ = swarm.behaviour_mut().pool.send_response(channel, ());
+                        },
+                        RequestResponseEvent::OutboundFailure{peer, error, ..} => {
+                            println!("Pool message to {:?} failed: {:?}", peer, error);
+                        },
+                        _ => {},
+                    },
+                SwarmEvent::Behaviour(NetworkEvent::Handshake(event)) =>
+                    match event {
+                        RequestResponseEvent::Message{peer, message: RequestResponseMessage::Request{request, channel, ..}} => {
+                            let _ = swarm.behaviour_mut().handshake.send_response(channel, local_handshake.clone());
+                            complete_handshake(&mut swarm, &main_sender, &mut handshakes, &mut pending_handshakes, &local_handshake, peer, request);
+                        },
+                        // The peer answered our handshake, so it accepts our messages now. Peers with a
+                        // better chain than ours are asked for their latest block, which starts a sync.
+                        RequestResponseEvent::Message{peer, message: RequestResponseMessage::Response{response, ..}} => {
+                            let ahead = response.best_block.id > local_handshake.best_block.id;
+                            if !complete_handshake(&mut swarm, &main_sender, &mut handshakes, &mut pending_handshakes, &local_handshake, peer, response) {
+                                continue;
+                            }
+                            if ahead {
+                                let req = LatestBlockRequest{receiver: peer.to_string(), random: true};
+                                let json = serde_json::to_string(&req).expect("can jsonify request");
+                                send_to(&mut swarm, &mut capture, None, &peer.to_string(), json);
+                            }
+                            // Share the peers we know about with the new peer
+                            let px = PeerExchange{peers: known_peers(&address_book, &peer)};
+                            if !px.peers.is_empty() {
+                                let json = serde_json::to_string(&px).expect("can jsonify request");
+                                record(&mut capture, Direction::Sent, Channel::Direct, Some(&peer), None, None, json.as_bytes());
+                                swarm.behaviour_mut().direct.send_request(&peer, json.into_bytes());
+                            }
+                        },
+                        RequestResponseEvent::OutboundFailure{peer, error, ..} => {
+                            // Peers not speaking the handshake protocol are of older versions
+                            if !handshakes.contains_key(&peer) {
+                                println!("Disconnecting {}: handshake failed: {:?}", peer, error);
+                                pending_handshakes.remove(&peer);
+                                let _ = swarm.disconnect_peer_id(peer);
+                            }
+                        },
+                        _ => {},
+                    },
+                SwarmEvent::Behaviour(NetworkEvent::Identify(event)) => {
+                    if let IdentifyEvent::Received{peer_id, info} = *event {
+                        // Peers of a network with other parameters have another genesis block, see network.rs
+                        if info.protocol_version != config.network.protocol_version() {
+                            println!("Disconnecting {} of another network ({})", peer_id, info.protocol_version);
+                            let _ = swarm.disconnect_peer_id(peer_id);
+                            continue;
+                        }
+                        debug!("Identified {:?} listening on {:?}", peer_id, info.listen_addrs);
+                        for addr in info.listen_addrs {
+                            add_address(&mut swarm, &mut address_book, &peer_id, addr);
+                        }
+                    }
+                },
                 SwarmEvent::Behaviour(NetworkEvent::TokioMdns(event)) =>
                     match event {
                         // On each Discovered event, we connect to all newly discovered peers
@@ -309,7 +999,9 @@ pub async fn init_p2p(
                             let mut unique_peers = HashMap::<PeerId, Multiaddr>::new();
                             for (peer, addr) in peers {
                                 debug!("discovered peer {} {}", peer, addr);
-                                    unique_peers.entry(peer).or_insert(addr);
+                                add_address(&mut swarm, &mut address_book, &peer, addr.clone());
+                                diversity.discovered(peer, Discovery::Mdns);
+                                unique_peers.entry(peer).or_insert(addr);
                             }
                             let unique_vec = unique_peers.iter().collect::<Vec<_>>();
                             for (peer, addr) in unique_vec {
@@ -329,22 +1021,52 @@ pub async fn init_p2p(
                 debug!("SwarmEvent IncomingConnection Address: {:?}", local_addr),
                 SwarmEvent::IncomingConnectionError { local_addr, send_back_addr: _, error } =>
                 debug!("SwarmEvent IncomingConnectionError Address: {:?} Error: {:?}", local_addr, error),
-                SwarmEvent::NewListenAddr { address, .. } =>
-                debug!("SwarmEvent NewListenAddr Address: {:?}", address),
-                SwarmEvent::ConnectionClosed{ peer_id, endpoint: _, num_established: _, cause } => {
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    println!("Listening on {}/p2p/{}", address, *LOCAL_PEER_ID);
+                    update_listen_addresses(&swarm, &mut local_handshake, &main_sender);
+                },
+                SwarmEvent::ConnectionClosed{ peer_id, endpoint: _, num_established, cause } => {
                     // As soon as a Peer disconnects, we have to manually remove him from our gossipsub peers
                     debug!("SwarmEvent ConnectionClosed PeerId: {:?} | Cause: {:?}", peer_id, cause);
                     swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
                     gossipsub_peers.remove(&peer_id);
+                    // A reconnecting peer has to shake hands again
+                    if num_established == 0 {
+                        diversity.disconnected(&peer_id);
+                        if handshakes.remove(&peer_id).is_some() {
+                            let _ = main_sender.send(EventType::PeerHandshake{peer: peer_id.to_string(), handshake: None});
+                        }
+                        pending_handshakes.remove(&peer_id);
+                    }
                 },
-                SwarmEvent::ConnectionEstablished{peer_id, ..} => {
+                SwarmEvent::ConnectionEstablished{peer_id, endpoint, ..} => {
                     debug!("SwarmEvent ConnectionEstablished PeerId: {:?}", peer_id);
+                    match endpoint.is_dialer() {
+                        true => {
+                            if bootstrap_peers.contains(endpoint.get_remote_address()) {
+                                diversity.discovered(peer_id, Discovery::Bootstrap);
+                            }
+                            diversity.connected(peer_id, endpoint.get_remote_address(), Instant::now());
+                        }
+                        false => diversity.discovered(peer_id, Discovery::Inbound),
+                    }
+                    if !handshakes.contains_key(&peer_id) && !pending_handshakes.contains_key(&peer_id) {
+                        pending_handshakes.insert(peer_id, Instant::now());
+                        swarm.behaviour_mut().handshake.send_request(&peer_id, local_handshake.clone());
+                    }
+                    // Scoring only starts once we are connected, so the restored reputation is applied now
+                    if let Some(score) = peer_scores.get(&peer_id) {
+                        let score = score.score.min(MAX_RESTORED_SCORE);
+                        application_scores.insert(peer_id, score);
+                        swarm.behaviour_mut().gossipsub.set_application_score(&peer_id, score);
+                    }
                 },
                 SwarmEvent::OutgoingConnectionError{peer_id, ..} => {
                     debug!("SwarmEvent OutgoingConnectionError PeerId: {:?}", peer_id);
                 },
                 SwarmEvent::ExpiredListenAddr{listener_id, ..} => {
                     debug!("SwarmEvent ExpiredListenAddr ListenerId: {:?}", listener_id);
+                    update_listen_addresses(&swarm, &mut local_handshake, &main_sender);
                 },
                 SwarmEvent::ListenerClosed{listener_id, ..} => {
                     debug!("SwarmEvent ListenerClosed ListenerId: {:?}", listener_id);
@@ -360,8 +1082,526 @@ pub async fn init_p2p(
     }
 }
 
+// Handles a message received via gossipsub or sent directly by a peer
+// Passes the message on to main, wrapped if it is of one of the additional chains. Returns why the
+// data could not be decoded if it is no message. `received_at` is in milliseconds since the epoch.
+fn handle_message(
+    data: &[u8],
+    source: Option<PeerId>,
+    propagation_source: PeerId,
+    chain: Option<&str>,
+    received_at: i64,
+    main_sender: &mpsc::UnboundedSender<EventType>,
+) -> Result<(), String> {
+    let main_sender = |event: EventType| {
+        main_sender.send(match chain {
+            Some(chain) => EventType::ForChain{chain: chain.to_owned(), event: Box::new(event)},
+            None => event,
+        })
+        .map_err(|err| err.to_string())
+    };
+    if let Ok(resp) = serde_json::from_slice::<ReceivedLatestBlock>(data) {
+        if is_expired(resp.sent_at, received_at) {
+            debug!("Ignoring latest block from {:?} sent at {:?}", source, resp.sent_at);
+        } else if resp.receiver == LOCAL_PEER_ID.to_string() {
+            debug!("ReceivedLatestBlock from {:?}:", source);
+            if let Some(source) = source {
+                if let Err(err) = main_sender(EventType::ReceivedLatestBlock{sender: source.to_string(), block: resp.block}) {
+                    debug!("P2P to main ReceivedLatestBlock error: {:?}", err);
+                }
+            } else {
+                debug!("no message source")
+            }
+        }
+    } else if let Ok(req) = serde_json::from_slice::<LatestBlockRequest>(data) {
+        if req.receiver == LOCAL_PEER_ID.to_string() {
+            debug!("SendLatestBlockRequest from {:?}:", source);
+            if let Some(source) = source {
+                if let Err(err) = main_sender(EventType::SendLatestBlockRequest{receiver: source.to_string()}) {
+                    debug!("P2P to main SendLatestBlockRequest error: {:?}", err);
+                }
+            } else {
+                debug!("no message source")
+            }
+        }
+    } else if let Ok(req) = serde_json::from_slice::<ChainRequest>(data) {
+        if req.receiver == LOCAL_PEER_ID.to_string() {
+            debug!(session = %req.session_id, "ChainRequest from {:?}:", source);
+            if let Some(source) = source {
+                let event = match req.snapshot {
+                    true => EventType::ReceivedSnapshotRequest{receiver: source.to_string(), session_id: req.session_id},
+                    false => EventType::ReceivedChainRequest{receiver: source.to_string(), session_id: req.session_id, from: req.from},
+                };
+                if let Err(err) = main_sender(event) {
+                    debug!("P2P to main ReceivedChainRequest error: {:?}", err);
+                }
+            } else {
+                debug!("no message source")
+            }
+        }
+    } else if let Ok(res) = serde_json::from_slice::<ReceivedChain>(data) {
+        if is_expired(res.sent_at, received_at) {
+            debug!(session = %res.session_id, "Ignoring chain from {:?} sent at {:?}", source, res.sent_at);
+        } else if res.receiver == LOCAL_PEER_ID.to_string() {
+            debug!(session = %res.session_id, "ReceivedChain from {:?}:", source);
+            let sender = source.map(|source| source.to_string()).unwrap_or_else(|| "unknown".to_owned());
+            if let Err(err) = main_sender(EventType::ReceivedChain{sender, session_id: res.session_id, chain: res.chain}) {
+                debug!("P2P to main ReceivedChain error: {:?}", err);
+            }
+        }
+    } else if let Ok(res) = serde_json::from_slice::<ReceivedSnapshot>(data) {
+        if res.receiver == LOCAL_PEER_ID.to_string() {
+            debug!(session = %res.session_id, "ReceivedSnapshot from {:?}:", source);
+            match source {
+                Some(source) if verify_snapshot(&res, &source) => {
+                    let event = EventType::ReceivedSnapshot{sender: source.to_string(), session_id: res.session_id, snapshot: res.snapshot};
+                    if let Err(err) = main_sender(event) {
+                        debug!("P2P to main ReceivedSnapshot error: {:?}", err);
+                    }
+                },
+                _ => debug!("invalid snapshot signature"),
+            }
+        }
+    } else if let Ok(res) = serde_json::from_slice::<ReceivedNewBlock>(data) {
+        if propagation_source != *LOCAL_PEER_ID {
+            debug!("ReceivedNewBlock from {:?}:", source);
+            // The source of a new block message is the peer that mined it
+            let sender = source.map(|source| source.to_string()).unwrap_or_else(|| "unknown".to_owned());
+            if let Err(err) = main_sender(EventType::ReceivedNewBlock{sender, block: res.block}) {
+                debug!("P2P to main ReceivedNewBlock error: {:?}", err);
+            }
+        }
+    } else if let Ok(res) = serde_json::from_slice::<NewBlockAnnouncement>(data) {
+        if propagation_source != *LOCAL_PEER_ID {
+            debug!("NewBlockAnnouncement from {:?}:", source);
+            // Like a new block, the announcement's source is the peer that mined the block and has its body
+            let sender = source.map(|source| source.to_string()).unwrap_or_else(|| "unknown".to_owned());
+            if let Err(err) = main_sender(EventType::ReceivedBlockAnnouncement{sender, announcement: res.announcement}) {
+                debug!("P2P to main ReceivedBlockAnnouncement error: {:?}", err);
+            }
+        }
+    } else if let Ok(req) = serde_json::from_slice::<BlockRequest>(data) {
+        if req.receiver == LOCAL_PEER_ID.to_string() {
+            debug!("BlockRequest from {:?}:", source);
+            if let Some(source) = source {
+                if let Err(err) = main_sender(EventType::ReceivedBlockRequest{receiver: source.to_string(), hash: req.hash}) {
+                    debug!("P2P to main ReceivedBlockRequest error: {:?}", err);
+                }
+            } else {
+                debug!("no message source")
+            }
+        }
+    } else if let Ok(res) = serde_json::from_slice::<BlockBody>(data) {
+        if res.receiver == LOCAL_PEER_ID.to_string() {
+            debug!("BlockBody from {:?}:", source);
+            let sender = source.map(|source| source.to_string()).unwrap_or_else(|| "unknown".to_owned());
+            if let Err(err) = main_sender(EventType::ReceivedNewBlock{sender, block: res.body}) {
+                debug!("P2P to main ReceivedNewBlock error: {:?}", err);
+            }
+        }
+    } else if let Ok(res) = serde_json::from_slice::<FinalizedCheckpoint>(data) {
+        debug!("FinalizedCheckpoint from {:?}:", source);
+        match source {
+            Some(source) if verify_checkpoint(&res, &source) => {
+                if let Err(err) = main_sender(EventType::ReceivedFinalizedCheckpoint{sender: source.to_string(), checkpoint: res.checkpoint}) {
+                    debug!("P2P to main ReceivedFinalizedCheckpoint error: {:?}", err);
+                }
+            },
+            _ => debug!("invalid checkpoint signature"),
+        }
+    } else if let Ok(res) = serde_json::from_slice::<SignedServiceAnnouncement>(data) {
+        debug!("SignedServiceAnnouncement from {:?}:", source);
+        match source {
+            Some(source) if verify_announcement(&res, &source) => {
+                if let Err(err) = main_sender(EventType::ReceivedServiceAnnouncement{sender: source.to_string(), announcement: res.announcement}) {
+                    debug!("P2P to main ReceivedServiceAnnouncement error: {:?}", err);
+                }
+            },
+            _ => debug!("invalid service announcement signature"),
+        }
+    } else if let Ok(res) = serde_json::from_slice::<BlockReceipt>(data) {
+        if res.receiver == LOCAL_PEER_ID.to_string() {
+            debug!("BlockReceipt from {:?}:", source);
+            let sender = source.map(|source| source.to_string()).unwrap_or_else(|| "unknown".to_owned());
+            if let Err(err) = main_sender(EventType::ReceivedBlockReceipt{sender, hash: res.hash, received_at: res.received_at}) {
+                debug!("P2P to main ReceivedBlockReceipt error: {:?}", err);
+            }
+        }
+    } else {
+        return Err(deadletter::decode_error(data));
+    }
+    Ok(())
+}
+
+// Whether a message sent at the time (in milliseconds since the epoch) is older than
+// MAX_MESSAGE_AGE. Messages without a time are of older nodes and accepted.
+fn is_expired(sent_at: Option<i64>, received_at: i64) -> bool {
+    sent_at.is_some_and(|sent_at| received_at - sent_at > MAX_MESSAGE_AGE.as_millis() as i64)
+}
+
+// Remembers the handshake of the peer and hands it to main, or disconnects it if we
+// can not peer with it. Returns whether the peer was accepted.
+fn complete_handshake(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    main_sender: &mpsc::UnboundedSender<EventType>,
+    handshakes: &mut HashMap<PeerId, Handshake>,
+    pending_handshakes: &mut HashMap<PeerId, Instant>,
+    local_handshake: &Handshake,
+    peer: PeerId,
+    handshake: Handshake,
+) -> bool {
+    pending_handshakes.remove(&peer);
+    if let Some(reason) = handshake.mismatch(local_handshake) {
+        println!("Disconnecting {}: {}", peer, reason);
+        handshakes.remove(&peer);
+        let _ = swarm.disconnect_peer_id(peer);
+        return false;
+    }
+    debug!("Handshake with {:?} | best block: {:?}", peer, handshake.best_block);
+    let _ = main_sender.send(EventType::PeerHandshake{peer: peer.to_string(), handshake: Some(handshake.clone())});
+    handshakes.insert(peer, handshake);
+    true
+}
+
+// The addresses we listen on are part of our node info, in our handshake and for main
+fn update_listen_addresses(swarm: &Swarm<BlockchainBehavior>, local_handshake: &mut Handshake, main_sender: &mpsc::UnboundedSender<EventType>) {
+    let addresses = swarm.listeners().map(|addr| addr.to_string()).collect::<Vec<String>>();
+    local_handshake.node.listen_addresses = addresses.clone();
+    let _ = main_sender.send(EventType::ListenAddressesChanged{addresses});
+}
+
+// Keeps the message as dead letter, penalizes the peer and hands the message to main to be persisted
+fn record_bad_message(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    dead_letters: &mut DeadLetters,
+    application_scores: &mut HashMap<PeerId, f64>,
+    main_sender: &mpsc::UnboundedSender<EventType>,
+    peer: PeerId,
+    data: &[u8],
+    error: String,
+) {
+    debug!("Bad message from {:?}: {}", peer, error);
+    let message = BadMessage::new(peer.to_string(), data, error, Utc::now().timestamp_millis());
+    dead_letters.push(message.clone());
+    let score = application_scores.entry(peer).or_default();
+    *score += BAD_MESSAGE_PENALTY;
+    swarm.behaviour_mut().gossipsub.set_application_score(&peer, *score);
+    if let Err(err) = main_sender.send(EventType::ReceivedBadMessage(message)) {
+        debug!("P2P to main ReceivedBadMessage error: {:?}", err);
+    }
+}
+
+// Publishes the message via gossipsub. If there are not enough peers for gossipsub to publish
+// to (e.g. while the mesh is still being built), the message is sent directly to all connected peers.
+fn publish(swarm: &mut Swarm<BlockchainBehavior>, capture: &mut Option<CaptureWriter>, chain: Option<&str>, json: String) {
+    let topic = chain.map(|chain| Topic::new(chains::topic_name(chain))).unwrap_or_else(|| TOPIC.clone());
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic, json.as_bytes())
+    {
+        Ok(_) => record(capture, Direction::Sent, Channel::Gossip, None, None, chain, json.as_bytes()),
+        Err(PublishError::InsufficientPeers) => {
+            let peers = swarm.connected_peers().copied().collect::<Vec<PeerId>>();
+            if peers.is_empty() {
+                println!("Publish error: no connected peers");
+            }
+            let data = match chain {
+                Some(chain) => serde_json::to_vec(&ChainMessage{chain: chain.to_owned(), message: json}).expect("can jsonify request"),
+                None => json.into_bytes(),
+            };
+            for peer in peers {
+                debug!("Mesh too sparse, sending message directly to {:?}", peer);
+                record(capture, Direction::Sent, Channel::Direct, Some(&peer), None, None, &data);
+                swarm
+                    .behaviour_mut()
+                    .direct
+                    .send_request(&peer, data.clone());
+            }
+        }
+        Err(e) => println!("Publish error: {:?}", e),
+    }
+}
+
+// Sends the message directly to the receiver if we are connected to it, so it is not gossiped to
+// everyone (block bodies are large). Otherwise it is published like our other addressed messages.
+fn send_to(swarm: &mut Swarm<BlockchainBehavior>, capture: &mut Option<CaptureWriter>, chain: Option<&str>, receiver: &str, json: String) {
+    match receiver.parse::<PeerId>() {
+        Ok(peer) if swarm.is_connected(&peer) => {
+            let data = match chain {
+                Some(chain) => serde_json::to_vec(&ChainMessage{chain: chain.to_owned(), message: json}).expect("can jsonify request"),
+                None => json.into_bytes(),
+            };
+            record(capture, Direction::Sent, Channel::Direct, Some(&peer), None, None, &data);
+            swarm.behaviour_mut().direct.send_request(&peer, data);
+        }
+        _ => publish(swarm, capture, chain, json),
+    }
+}
+
+// Writes the message to the capture, which is closed if that fails
+fn record(
+    capture: &mut Option<CaptureWriter>,
+    direction: Direction,
+    channel: Channel,
+    peer: Option<&PeerId>,
+    source: Option<&PeerId>,
+    chain: Option<&str>,
+    data: &[u8],
+) {
+    if let Some(writer) = capture {
+        let message = CapturedMessage {
+            at: Utc::now().timestamp_millis(),
+            direction,
+            channel,
+            peer: peer.map(|peer| peer.to_string()),
+            // Only kept if it differs from the forwarding peer
+            source: source.filter(|source| Some(*source) != peer).map(|source| source.to_string()),
+            chain: chain.map(|chain| chain.to_owned()),
+            data: hex::encode(data),
+        };
+        if let Err(err) = writer.write(&message) {
+            println!("Capture error, no longer capturing p2p messages: {:?}", err);
+            *capture = None;
+        }
+    }
+}
+
+// Hands the received messages of the capture to the sender like they were just received. Messages
+// addressed to a peer are only handled if we have the ID they were sent to, see --keys. Peer
+// exchanges are skipped, they would make us dial the peers.
+pub fn replay_capture(
+    path: &Path,
+    chain_names: &[String],
+    main_sender: &mpsc::UnboundedSender<EventType>,
+) -> Result<CaptureReplay, BlockchainError> {
+    let mut replay = CaptureReplay::default();
+    for message in capture::read_capture(path)? {
+        if message.direction != Direction::Received {
+            continue;
+        }
+        let data = message.bytes()?;
+        let peer = message
+            .peer
+            .as_deref()
+            .and_then(|peer| PeerId::from_str(peer).ok())
+            .ok_or_else(|| BlockchainError::Error(format!("invalid peer in capture: {:?}", message.peer)))?;
+        let source = message.source.as_deref().and_then(|source| PeerId::from_str(source).ok()).unwrap_or(peer);
+        let result = match message.channel {
+            Channel::Gossip => handle_message(&data, Some(source), peer, message.chain.as_deref(), message.at, main_sender),
+            Channel::Direct if serde_json::from_slice::<PeerExchange>(&data).is_ok() => {
+                replay.skipped += 1;
+                continue;
+            }
+            Channel::Direct => match serde_json::from_slice::<ChainMessage>(&data) {
+                Ok(wrapped) if chain_names.contains(&wrapped.chain) => {
+                    handle_message(wrapped.message.as_bytes(), Some(peer), peer, Some(&wrapped.chain), message.at, main_sender)
+                }
+                Ok(_) => {
+                    replay.skipped += 1;
+                    continue;
+                }
+                Err(_) => handle_message(&data, Some(peer), peer, None, message.at, main_sender),
+            },
+            Channel::Pool => match serde_json::from_slice::<PoolMessage>(&data) {
+                Ok(pool_message) => main_sender
+                    .send(EventType::ReceivedPoolMessage{sender: peer.to_string(), message: pool_message})
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(format!("invalid pool message: {}", err)),
+            },
+        };
+        replay.replayed += 1;
+        if let Err(error) = result {
+            replay.bad.push(BadMessage::new(peer.to_string(), &data, error, message.at));
+        }
+    }
+    Ok(replay)
+}
+
+// Remembers the address and makes it available to the swarm, so peers that are dialed by
+// their ID only (e.g. by gossipsub after a peer exchange) can be reached
+fn add_address(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    address_book: &mut HashMap<PeerId, HashSet<Multiaddr>>,
+    peer_id: &PeerId,
+    addr: Multiaddr,
+) {
+    if *peer_id != *LOCAL_PEER_ID && address_book.entry(*peer_id).or_default().insert(addr.clone()) {
+        swarm.behaviour_mut().direct.add_address(peer_id, addr.clone());
+        swarm.behaviour_mut().pool.add_address(peer_id, addr);
+    }
+}
+
+// Up to PX_PEERS peers with their addresses, excluding the peer we send them to
+fn known_peers(address_book: &HashMap<PeerId, HashSet<Multiaddr>>, receiver: &PeerId) -> Vec<PeerAddresses> {
+    address_book
+        .iter()
+        .filter(|(peer_id, _)| *peer_id != receiver)
+        .take(PX_PEERS)
+        .map(|(peer_id, addresses)| PeerAddresses {
+            peer_id: peer_id.to_string(),
+            addresses: addresses.iter().map(|addr| addr.to_string()).collect(),
+        })
+        .collect()
+}
+
+// Adds the exchanged addresses to our address book and dials new peers while we are below target_peers
+fn handle_peer_exchange(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    address_book: &mut HashMap<PeerId, HashSet<Multiaddr>>,
+    diversity: &mut Diversity,
+    px: PeerExchange,
+    target_peers: usize,
+) {
+    for peer in px.peers {
+        let peer_id = match peer.peer_id.parse::<PeerId>() {
+            Ok(peer_id) => peer_id,
+            Err(_) => continue,
+        };
+        let addresses = peer
+            .addresses
+            .iter()
+            .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+            .collect::<Vec<Multiaddr>>();
+        for addr in addresses.iter() {
+            add_address(swarm, address_book, &peer_id, addr.clone());
+        }
+        if !addresses.is_empty() {
+            diversity.discovered(peer_id, Discovery::PeerExchange);
+        }
+        if peer_id != *LOCAL_PEER_ID
+            && !swarm.is_connected(&peer_id)
+            && swarm.connected_peers().count() < target_peers
+        {
+            if let Some(addr) = addresses.first() {
+                dial_peer(swarm, &peer_id, addr);
+            }
+        }
+    }
+}
+
+// Peers can not reconnect to nodes that do not listen, so we have to keep enough
+// connections open ourselves: known peers are dialed while we are below target_peers,
+// the bootstrap peers whenever we lost all connections. Peers that make our outbound
+// connections more diverse are dialed first, one of them even above target_peers.
+fn redial(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    address_book: &HashMap<PeerId, HashSet<Multiaddr>>,
+    peer_scores: &HashMap<PeerId, PeerScore>,
+    diversity: &Diversity,
+    bootstrap_peers: &[Multiaddr],
+    target_peers: usize,
+) {
+    let connected = swarm.connected_peers().count();
+    if connected == 0 {
+        for addr in bootstrap_peers.iter() {
+            dial_addr(swarm, addr);
+        }
+    }
+    // Previously good peers are dialed first, banned ones never
+    let score = |peer_id: &PeerId| peer_scores.get(peer_id).map(|score| score.score).unwrap_or_default();
+    let mut candidates = address_book
+        .iter()
+        .filter(|(peer_id, _)| !swarm.is_connected(peer_id) && !diversity.was_rotated(peer_id))
+        .filter(|(peer_id, _)| !peer_scores.get(peer_id).map(|score| score.banned).unwrap_or_default())
+        .filter_map(|(peer_id, addresses)| addresses.iter().next().map(|addr| (*peer_id, addr.clone())))
+        .collect::<Vec<(PeerId, Multiaddr)>>();
+    candidates.sort_by(|(a, _), (b, _)| score(b).total_cmp(&score(a)));
+    candidates.sort_by_key(|(peer_id, addr)| !diversity.adds_diversity(peer_id, addr));
+    let slots = match candidates.first() {
+        Some((peer_id, addr)) if diversity.adds_diversity(peer_id, addr) => target_peers.saturating_sub(connected).max(1),
+        _ => target_peers.saturating_sub(connected),
+    };
+    candidates.truncate(slots);
+    for (peer_id, addr) in candidates.iter() {
+        dial_peer(swarm, peer_id, addr);
+    }
+}
+
+// Takes over the scores of our connected peers and bans peers that dropped below BAN_THRESHOLD
+fn update_peer_scores(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    address_book: &HashMap<PeerId, HashSet<Multiaddr>>,
+    peer_scores: &mut HashMap<PeerId, PeerScore>,
+) {
+    let connected = swarm.connected_peers().copied().collect::<Vec<PeerId>>();
+    for peer_id in connected {
+        let score = match swarm.behaviour().gossipsub.peer_score(&peer_id) {
+            Some(score) => score,
+            None => continue,
+        };
+        let banned = score < BAN_THRESHOLD;
+        if banned {
+            println!("Banning peer {} with score {:.2}", peer_id, score);
+            ban_peer(swarm, &peer_id);
+        }
+        peer_scores.insert(peer_id, PeerScore {
+            peer_id: peer_id.to_string(),
+            score,
+            banned,
+            addresses: address_book
+                .get(&peer_id)
+                .map(|addresses| addresses.iter().map(|addr| addr.to_string()).collect())
+                .unwrap_or_default(),
+            updated_at: Utc::now().timestamp(),
+        });
+    }
+}
+
+// Bans the previously banned peers again and dials the previously good peers, best first
+fn restore_peer_scores(
+    swarm: &mut Swarm<BlockchainBehavior>,
+    address_book: &mut HashMap<PeerId, HashSet<Multiaddr>>,
+    peer_scores: &mut HashMap<PeerId, PeerScore>,
+    diversity: &mut Diversity,
+    scores: Vec<PeerScore>,
+    target_peers: usize,
+) {
+    let mut dialed = 0;
+    for score in scores {
+        let peer_id = match score.peer_id.parse::<PeerId>() {
+            Ok(peer_id) => peer_id,
+            Err(_) => continue,
+        };
+        if score.banned {
+            ban_peer(swarm, &peer_id);
+        } else {
+            let addresses = score
+                .addresses
+                .iter()
+                .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+                .collect::<Vec<Multiaddr>>();
+            for addr in addresses.iter() {
+                add_address(swarm, address_book, &peer_id, addr.clone());
+            }
+            if !addresses.is_empty() {
+                diversity.discovered(peer_id, Discovery::Restored);
+            }
+            if score.score >= 0.0 && dialed < target_peers {
+                if let Some(addr) = addresses.first() {
+                    dial_peer(swarm, &peer_id, addr);
+                    dialed += 1;
+                }
+            }
+        }
+        peer_scores.insert(peer_id, score);
+    }
+}
+
+fn ban_peer(swarm: &mut Swarm<BlockchainBehavior>, peer_id: &PeerId) {
+    swarm.behaviour_mut().gossipsub.blacklist_peer(peer_id);
+    swarm.ban_peer_id(*peer_id);
+}
+
+fn dial_addr(swarm: &mut Swarm<BlockchainBehavior>, addr: &Multiaddr) {
+    match swarm.dial(addr.clone()) {
+        Ok(_) => println!("Dialed {:?}", addr),
+        Err(e) => println!("Dial {:?} failed: {:?}", addr, e),
+    };
+}
+
 fn dial_peer(swarm: &mut Swarm<BlockchainBehavior>, peer_id: &PeerId, addr: &Multiaddr) {
-    let dial_opts = DialOpts::peer_id(peer_id.clone())
+    let dial_opts = DialOpts::peer_id(*peer_id)
         // NotDialing == not dialing + not connected
         .condition(PeerCondition::NotDialing)
         .addresses(vec![addr.clone()])
@@ -375,7 +1615,42 @@ fn dial_peer(swarm: &mut Swarm<BlockchainBehavior>, peer_id: &PeerId, addr: &Mul
     };
 }
 
-fn build_gossipsub_behavior() -> Gossipsub {
+fn checkpoint_signing_bytes(checkpoint: &Checkpoint) -> Vec<u8> {
+    format!("{}:{}", checkpoint.id, checkpoint.hash).into_bytes()
+}
+
+// The checkpoint has to be signed by the key of the peer that published it
+fn verify_checkpoint(req: &FinalizedCheckpoint, source: &PeerId) -> bool {
+    match identity::PublicKey::from_protobuf_encoding(&req.public_key) {
+        Ok(public_key) => {
+            public_key.to_peer_id() == *source
+                && public_key.verify(&checkpoint_signing_bytes(&req.checkpoint), &req.signature)
+        }
+        Err(_) => false,
+    }
+}
+
+// The announcement has to be signed by the key of the peer that published it and is named in it
+fn verify_announcement(req: &SignedServiceAnnouncement, source: &PeerId) -> bool {
+    match identity::PublicKey::from_protobuf_encoding(&req.public_key) {
+        Ok(public_key) => {
+            public_key.to_peer_id() == *source
+                && req.announcement.peer_id == source.to_string()
+                && public_key.verify(&req.announcement.signing_bytes(), &req.signature)
+        }
+        Err(_) => false,
+    }
+}
+
+// The snapshot has to be signed by the key of the peer that sent it
+fn verify_snapshot(res: &ReceivedSnapshot, source: &PeerId) -> bool {
+    match identity::PublicKey::from_protobuf_encoding(&res.public_key) {
+        Ok(public_key) => public_key.to_peer_id() == *source && public_key.verify(&res.snapshot.digest(), &res.signature),
+        Err(_) => false,
+    }
+}
+
+fn build_gossipsub_behavior(chains: &[String]) -> Gossipsub {
     // To content-address message, we can take the hash of message and use it as an ID.
     let message_id_fn = |message: &GossipsubMessage| {
         let mut s = DefaultHasher::new();
@@ -387,6 +1662,9 @@ fn build_gossipsub_behavior() -> Gossipsub {
     let gossipsub_config = GossipsubConfigBuilder::default()
         .validation_mode(ValidationMode::Strict)
         .message_id_fn(message_id_fn)
+        // Hand out other peers when pruning a peer from our mesh, see handle_peer_exchange
+        .do_px()
+        .prune_peers(PX_PEERS)
         .build()
         .expect("valid config");
 
@@ -397,7 +1675,33 @@ fn build_gossipsub_behavior() -> Gossipsub {
     )
     .expect("correct configuration");
 
-    gossipsub.subscribe(&TOPIC).unwrap();
+    // Restored scores are applied as application specific score, see restore_peer_scores
+    let mut score_params = PeerScoreParams {
+        app_specific_weight: 1.0,
+        ..Default::default()
+    };
+    let topics = iter::once(TOPIC.clone())
+        .chain(chains.iter().map(|chain| Topic::new(chains::topic_name(chain))))
+        .collect::<Vec<Topic>>();
+    for topic in topics.iter() {
+        score_params.topics.insert(topic.hash(), TopicScoreParams {
+            time_in_mesh_quantum: Duration::from_secs(1),
+            ..Default::default()
+        });
+    }
+    let score_thresholds = PeerScoreThresholds {
+        graylist_threshold: BAN_THRESHOLD,
+        // Accept peer exchange from every peer that has not misbehaved yet
+        accept_px_threshold: 0.0,
+        ..Default::default()
+    };
+    gossipsub
+        .with_peer_score(score_params, score_thresholds)
+        .expect("valid peer score params");
+
+    for topic in topics.iter() {
+        gossipsub.subscribe(topic).unwrap();
+    }
 
     gossipsub
 }
@@ -0,0 +1,137 @@
+// Encrypted block payloads (`block mine --to KEYS`): the data of a block can be encrypted to the
+// X25519 encryption keys of one or more recipients (see keys.rs), the header stays public. The data
+// is encrypted once with a random content key, which is wrapped for every recipient with a key
+// derived from an ephemeral X25519 exchange. Recipients are not listed, a node finds the content
+// key meant for it by trying to unwrap each of them.
+// Payloads are content-addressed by payload_hash (`payload get HASH`), see storage.rs.
+use crate::blockchain::BlockchainError;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Block data of encrypted payloads starts with this, followed by the JSON encoded EncryptedPayload
+pub const ENCRYPTED_PREFIX: &str = "encrypted ";
+// Content keys are never reused, so the wrapped keys can all use the same nonce
+const WRAP_NONCE: [u8; 12] = [0; 12];
+
+// All binary fields hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedPayload {
+    // Public key of the ephemeral X25519 key of the sender
+    pub ephemeral_key: String,
+    // The content key, wrapped for each recipient
+    pub recipients: Vec<String>,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+// Hex encoded SHA-256 of the block data
+pub fn payload_hash(data: &str) -> String {
+    hex::encode(Sha256::digest(data.as_bytes()))
+}
+
+pub fn generate_secret() -> StaticSecret {
+    StaticSecret::from(random_bytes::<32>())
+}
+
+pub fn encode_public_key(key: &PublicKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+pub fn decode_public_key(key: &str) -> Result<PublicKey, BlockchainError> {
+    let bytes: [u8; 32] = hex::decode(key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| BlockchainError::Error(format!("invalid encryption key: {}", key)))?;
+    Ok(PublicKey::from(bytes))
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+// Key the content key is wrapped with for the recipient
+fn wrap_key(shared_secret: &[u8; 32], ephemeral_key: &PublicKey, recipient: &PublicKey) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"rust-blockchain payload key");
+    hasher.update(shared_secret);
+    hasher.update(ephemeral_key.as_bytes());
+    hasher.update(recipient.as_bytes());
+    hasher.finalize().into()
+}
+
+// Returns the block data carrying the encrypted payload
+pub fn encrypt_payload(payload: &str, recipients: &[PublicKey]) -> Result<String, BlockchainError> {
+    if recipients.is_empty() {
+        return Err(BlockchainError::Error("encrypted payloads need at least one recipient".to_owned()));
+    }
+    let content_key = random_bytes::<32>();
+    let nonce = random_bytes::<12>();
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&content_key))
+        .encrypt(Nonce::from_slice(&nonce), payload.as_bytes())
+        .expect("can encrypt payload");
+
+    let ephemeral = generate_secret();
+    let ephemeral_key = PublicKey::from(&ephemeral);
+    let recipients = recipients
+        .iter()
+        .map(|recipient| {
+            let shared_secret = ephemeral.diffie_hellman(recipient);
+            let key = wrap_key(shared_secret.as_bytes(), &ephemeral_key, recipient);
+            let wrapped = ChaCha20Poly1305::new(Key::from_slice(&key))
+                .encrypt(Nonce::from_slice(&WRAP_NONCE), content_key.as_slice())
+                .expect("can wrap content key");
+            hex::encode(wrapped)
+        })
+        .collect();
+
+    let encrypted = EncryptedPayload {
+        ephemeral_key: encode_public_key(&ephemeral_key),
+        recipients,
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    };
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, serde_json::to_string(&encrypted).expect("can jsonify payload")))
+}
+
+pub fn is_encrypted(data: &str) -> bool {
+    data.starts_with(ENCRYPTED_PREFIX)
+}
+
+// Decrypts the payload of the block data with our key. None if the data is not encrypted.
+pub fn decrypt_payload(data: &str, secret: &StaticSecret) -> Option<Result<String, BlockchainError>> {
+    let json = data.strip_prefix(ENCRYPTED_PREFIX)?;
+    Some(decrypt(json, secret))
+}
+
+fn decrypt(json: &str, secret: &StaticSecret) -> Result<String, BlockchainError> {
+    let invalid = |err: String| BlockchainError::Error(format!("invalid encrypted payload: {}", err));
+    let encrypted: EncryptedPayload = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+    let ephemeral_key = decode_public_key(&encrypted.ephemeral_key)?;
+    let nonce = hex::decode(&encrypted.nonce).map_err(|err| invalid(err.to_string()))?;
+    let ciphertext = hex::decode(&encrypted.ciphertext).map_err(|err| invalid(err.to_string()))?;
+    if nonce.len() != 12 {
+        return Err(invalid("nonce has to be 12 bytes".to_owned()));
+    }
+
+    let recipient = PublicKey::from(secret);
+    let shared_secret = secret.diffie_hellman(&ephemeral_key);
+    let key = wrap_key(shared_secret.as_bytes(), &ephemeral_key, &recipient);
+    let wrapper = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let content_key = encrypted
+        .recipients
+        .iter()
+        .filter_map(|wrapped| hex::decode(wrapped).ok())
+        .find_map(|wrapped| wrapper.decrypt(Nonce::from_slice(&WRAP_NONCE), wrapped.as_slice()).ok())
+        .filter(|content_key| content_key.len() == 32)
+        .ok_or_else(|| BlockchainError::Error("the payload is not encrypted to our key".to_owned()))?;
+    let payload = ChaCha20Poly1305::new(Key::from_slice(&content_key))
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| invalid("ciphertext was modified".to_owned()))?;
+    String::from_utf8(payload).map_err(|err| invalid(err.to_string()))
+}
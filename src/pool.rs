@@ -0,0 +1,302 @@
+// Mining pool (`pool start` / `pool join`): a coordinator node splits the nonce space of its block
+// template among the workers that joined it over the pool protocol (see p2p.rs). Workers search
+// their range and submit every nonce whose digest meets the share difficulty, which is far lower than
+// the difficulty of the block, so the coordinator sees the work of every worker, even of slow ones.
+// A share that also meets the target of the block completes it, the coordinator adds it to its
+// chain, broadcasts it and hands out jobs for the next block.
+use crate::blockchain::{self, Block};
+use crate::difficulty;
+use crate::types::Nonce;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::mpsc;
+
+// Expected number of hashes per share
+pub const DEFAULT_SHARE_DIFFICULTY: u64 = 4_096;
+// Number of nonces a worker hashes before checking whether its job was replaced
+const CANCEL_CHECK_INTERVAL: u64 = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolJob {
+    pub job_id: u64,
+    pub template: Block,
+    // Hash prefix of blocks before the DifficultyAdjustment upgrade
+    pub prefix: String,
+    pub share_difficulty: u64,
+    // Nonces assigned to the worker, the end is exclusive
+    pub nonce_start: Nonce,
+    pub nonce_end: Nonce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolShare {
+    pub job_id: u64,
+    pub nonce: Nonce,
+}
+
+// Messages of the pool protocol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PoolMessage {
+    // Sent by workers to the coordinator
+    Subscribe,
+    Unsubscribe,
+    Share(PoolShare),
+    // Sent by the coordinator, replaces the current job of the worker
+    Job(PoolJob),
+    // Sent by the coordinator in response to a share
+    Accepted(PoolShare),
+    Rejected {
+        share: PoolShare,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareOutcome {
+    Accepted,
+    // The share completes the block
+    BlockFound(Block),
+    Rejected(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub blocks: u64,
+}
+
+pub struct Coordinator {
+    // Data of the blocks the pool mines
+    pub data: String,
+    pub share_difficulty: u64,
+    template: Option<Block>,
+    prefix: String,
+    job_id: u64,
+    workers: BTreeMap<String, WorkerStats>,
+    // Nonces submitted for the current job
+    submitted: HashSet<Nonce>,
+}
+
+impl Coordinator {
+    pub fn new(data: String, share_difficulty: u64) -> Self {
+        Self {
+            data,
+            share_difficulty,
+            template: None,
+            prefix: String::new(),
+            job_id: 0,
+            workers: BTreeMap::new(),
+            submitted: HashSet::new(),
+        }
+    }
+
+    // None after a block was found, until the template for the next block is set
+    pub fn template(&self) -> Option<&Block> {
+        self.template.as_ref()
+    }
+
+    pub fn job_id(&self) -> u64 {
+        self.job_id
+    }
+
+    pub fn workers(&self) -> &BTreeMap<String, WorkerStats> {
+        &self.workers
+    }
+
+    // Starts mining the template and returns the new jobs of all workers
+    pub fn set_template(&mut self, template: Block, prefix: &str) -> Vec<(String, PoolJob)> {
+        self.template = Some(template);
+        self.prefix = prefix.to_owned();
+        self.next_job()
+    }
+
+    pub fn add_worker(&mut self, worker: &str) -> Vec<(String, PoolJob)> {
+        self.workers.entry(worker.to_owned()).or_default();
+        self.reassign()
+    }
+
+    pub fn remove_worker(&mut self, worker: &str) -> Vec<(String, PoolJob)> {
+        if self.workers.remove(worker).is_none() {
+            return vec![];
+        }
+        self.reassign()
+    }
+
+    // The nonce ranges depend on the number of workers, so they are handed out again whenever workers
+    // join or leave. The extra nonce is increased at the same time, so no nonce is searched twice.
+    fn reassign(&mut self) -> Vec<(String, PoolJob)> {
+        if let Some(template) = &mut self.template {
+            template.extra_nonce = Nonce(template.extra_nonce.0 + 1);
+        }
+        self.next_job()
+    }
+
+    fn next_job(&mut self) -> Vec<(String, PoolJob)> {
+        self.job_id += 1;
+        self.submitted.clear();
+        self.jobs()
+    }
+
+    // The current jobs of all workers
+    pub fn jobs(&self) -> Vec<(String, PoolJob)> {
+        let template = match &self.template {
+            Some(template) => template,
+            None => return vec![],
+        };
+        self.workers
+            .keys()
+            .zip(split_nonces(self.workers.len()))
+            .map(|(worker, (nonce_start, nonce_end))| {
+                let job = PoolJob {
+                    job_id: self.job_id,
+                    template: template.clone(),
+                    prefix: self.prefix.clone(),
+                    share_difficulty: share_difficulty(template, self.share_difficulty),
+                    nonce_start,
+                    nonce_end,
+                };
+                (worker.clone(), job)
+            })
+            .collect()
+    }
+
+    pub fn submit_share(&mut self, worker: &str, share: PoolShare) -> ShareOutcome {
+        let index = match self.workers.keys().position(|known| known == worker) {
+            Some(index) => index,
+            None => return ShareOutcome::Rejected("unknown worker".to_owned()),
+        };
+        let (nonce_start, nonce_end) = split_nonces(self.workers.len())[index];
+        let outcome = match &self.template {
+            Some(_) if share.job_id != self.job_id => ShareOutcome::Rejected("stale job".to_owned()),
+            None => ShareOutcome::Rejected("stale job".to_owned()),
+            Some(_) if share.nonce < nonce_start || share.nonce >= nonce_end => {
+                ShareOutcome::Rejected("nonce outside of the worker's range".to_owned())
+            }
+            Some(_) if !self.submitted.insert(share.nonce) => ShareOutcome::Rejected("duplicate share".to_owned()),
+            Some(template) => {
+                let digest = blockchain::digest_with_nonce(template, share.nonce);
+                if blockchain::meets_target(template, &digest, &self.prefix) {
+                    let mut block = template.clone();
+                    block.nonce = share.nonce;
+                    block.hash = blockchain::hasher(&block);
+                    ShareOutcome::BlockFound(block)
+                } else if difficulty::meets_difficulty(&digest, share_difficulty(template, self.share_difficulty)) {
+                    ShareOutcome::Accepted
+                } else {
+                    ShareOutcome::Rejected("share does not meet the share difficulty".to_owned())
+                }
+            }
+        };
+
+        let stats = self.workers.get_mut(worker).expect("worker is known");
+        match outcome {
+            ShareOutcome::Accepted => stats.accepted += 1,
+            ShareOutcome::BlockFound(_) => {
+                stats.accepted += 1;
+                stats.blocks += 1;
+                // Shares for the found block are stale from now on
+                self.template = None;
+            }
+            ShareOutcome::Rejected(_) => stats.rejected += 1,
+        }
+        outcome
+    }
+}
+
+// Shares must not be harder to find than the block itself
+fn share_difficulty(template: &Block, share_difficulty: u64) -> u64 {
+    if template.difficulty > 0 {
+        share_difficulty.min(template.difficulty)
+    } else {
+        share_difficulty
+    }
+}
+
+// Splits the nonce space into one range per worker
+pub fn split_nonces(workers: usize) -> Vec<(Nonce, Nonce)> {
+    if workers == 0 {
+        return vec![];
+    }
+    let step = Nonce::MAX.0 / workers as u64;
+    (0..workers as u64)
+        .map(|index| {
+            let end = if index + 1 == workers as u64 { Nonce::MAX.0 } else { (index + 1) * step };
+            (Nonce(index * step), Nonce(end))
+        })
+        .collect()
+}
+
+// Hashes the nonces of the job until its range is exhausted, it is cancelled or on_share returns false.
+// Nonces that complete the block are submitted as shares as well.
+pub fn search_shares(job: &PoolJob, cancel: &AtomicBool, mut on_share: impl FnMut(PoolShare) -> bool) {
+    let header_hasher = blockchain::HeaderHasher::new(&job.template);
+    for nonce in job.nonce_start.0..job.nonce_end.0 {
+        if (nonce - job.nonce_start.0).is_multiple_of(CANCEL_CHECK_INTERVAL) && cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let digest = header_hasher.digest(Nonce(nonce));
+        if (difficulty::meets_difficulty(&digest, job.share_difficulty)
+            || blockchain::meets_target(&job.template, &digest, &job.prefix))
+            && !on_share(PoolShare { job_id: job.job_id, nonce: Nonce(nonce) })
+        {
+            return;
+        }
+    }
+}
+
+// A node that joined a coordinator. The current job is searched on its own thread.
+pub struct PoolWorker {
+    // Peer ID of the coordinator
+    pub coordinator: String,
+    pub job_id: Option<u64>,
+    pub shares_found: u64,
+    pub shares_accepted: u64,
+    pub shares_rejected: u64,
+    cancel: Arc<AtomicBool>,
+}
+
+impl PoolWorker {
+    pub fn new(coordinator: String) -> Self {
+        Self {
+            coordinator,
+            job_id: None,
+            shares_found: 0,
+            shares_accepted: 0,
+            shares_rejected: 0,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Stops the current job and starts searching the new one, found shares are sent to `shares`
+    pub fn start_job(&mut self, job: PoolJob, shares: mpsc::UnboundedSender<PoolShare>) {
+        self.stop();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.cancel = Arc::clone(&cancel);
+        self.job_id = Some(job.job_id);
+        thread::spawn(move || search_shares(&job, &cancel, |share| shares.send(share).is_ok()));
+    }
+
+    // Counts the coordinator's response to one of our shares
+    pub fn record_result(&mut self, message: &PoolMessage) {
+        match message {
+            PoolMessage::Accepted(_) => self.shares_accepted += 1,
+            PoolMessage::Rejected { .. } => self.shares_rejected += 1,
+            _ => {}
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.job_id = None;
+    }
+}
+
+impl Drop for PoolWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
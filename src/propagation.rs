@@ -0,0 +1,41 @@
+// Propagation policy (`--propagation full|announce`): how the blocks we mine are gossiped. With full
+// propagation every peer receives the whole block, whether it already has it or not. With announce
+// only a compact announcement (hash, height and parent hash) is gossiped, and peers that do not know
+// the block yet fetch its body from the announcing peer, which saves bandwidth in well connected
+// meshes and with large payloads. Every node handles both, the policy only decides what we send.
+use crate::blockchain::Block;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+
+pub const PROPAGATION_POLICIES: [&str; 2] = ["full", "announce"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Propagation {
+    #[default]
+    Full,
+    Announce,
+}
+
+impl Propagation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "full" => Some(Self::Full),
+            "announce" => Some(Self::Announce),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BlockAnnouncement {
+    pub hash: String,
+    pub id: Height,
+    pub prev_hash: String,
+}
+
+impl BlockAnnouncement {
+    pub fn of(block: &Block) -> Self {
+        Self { hash: block.hash.clone(), id: block.id, prev_hash: block.prev_hash.clone() }
+    }
+}
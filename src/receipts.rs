@@ -0,0 +1,108 @@
+// Receipts (`block receipt`, `tx receipt`): the outcome of executing the state-changing payload of a
+// main chain block, so applications learn whether their submission took effect instead of only that
+// it was mined. Payloads are transactions (see transactions.rs, a batch succeeds or fails as a whole), key rotations (see keys.rs) and
+// anchors (see anchor.rs), other block data changes no state and gets no receipt. A payload fails if
+// it can not be decoded or its signatures are invalid, which blocks below the upgrades rejecting such
+// payloads (see consensus.rs) can carry. Execution is metered in gas: every byte of the payload and
+// every signature checked costs a fixed amount, failed payloads are charged too. Receipts are derived
+// from their block alone and stored along with the indexes (see storage.rs).
+use crate::anchor::{Anchor, ANCHOR_PREFIX};
+use crate::blockchain::{Block, BlockchainError};
+use crate::keys::KeyRotation;
+use crate::transactions::{self, Transaction};
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+
+pub const GAS_PER_BYTE: u64 = 1;
+pub const GAS_PER_SIGNATURE: u64 = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Success,
+    // With the reason
+    Failure(String),
+}
+
+// What a successful payload did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptEvent {
+    Transfer { txid: String, sender: String, recipient: String, amount: u64, fee: u64 },
+    KeyRotated { old_key: String, new_key: String },
+    Anchored { root: String, digests: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub block_hash: String,
+    pub height: Height,
+    pub outcome: Outcome,
+    pub gas_used: u64,
+    // Empty if the payload failed
+    pub events: Vec<ReceiptEvent>,
+}
+
+impl Receipt {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("can jsonify receipt")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, BlockchainError> {
+        serde_json::from_str(json).map_err(|err| BlockchainError::Error(format!("invalid receipt: {}", err)))
+    }
+}
+
+// Executes the payload of the block, None if it carries none
+pub fn execute(block: &Block) -> Option<Receipt> {
+    let (signatures, executed) = if let Some(transactions) = transactions::from_block_data(&block.data) {
+        execute_transactions(transactions)
+    } else if let Some(rotation) = KeyRotation::from_block_data(&block.data) {
+        (2, rotation.and_then(|rotation| {
+            rotation.verify()?;
+            Ok(vec![ReceiptEvent::KeyRotated { old_key: rotation.old_key, new_key: rotation.new_key }])
+        }))
+    } else if block.data.starts_with(ANCHOR_PREFIX) {
+        (0, match Anchor::from_block_data(&block.data) {
+            Some(anchor) => Ok(vec![ReceiptEvent::Anchored { root: anchor.root, digests: anchor.digests.len() }]),
+            None => Err(BlockchainError::Error("invalid anchor".to_owned())),
+        })
+    } else {
+        return None;
+    };
+    let (outcome, events) = match executed {
+        Ok(events) => (Outcome::Success, events),
+        Err(err) => (Outcome::Failure(err.to_string()), vec![]),
+    };
+    Some(Receipt {
+        block_hash: block.hash.clone(),
+        height: block.id,
+        outcome,
+        gas_used: (block.data.len() as u64).saturating_mul(GAS_PER_BYTE).saturating_add(signatures * GAS_PER_SIGNATURE),
+        events,
+    })
+}
+
+// The number of signatures checked and the events. A batch of transactions is executed as a whole,
+// one invalid transaction fails all of them.
+fn execute_transactions(transactions: Result<Vec<Transaction>, BlockchainError>) -> (u64, Result<Vec<ReceiptEvent>, BlockchainError>) {
+    let transactions = match transactions {
+        Ok(transactions) => transactions,
+        Err(err) => return (0, Err(err)),
+    };
+    let signatures = transactions
+        .iter()
+        .map(|transaction| if transaction.multisig.is_some() { transaction.signatures.len() as u64 } else { 1 })
+        .sum();
+    let executed = transactions.iter().try_for_each(Transaction::verify).map(|_| {
+        transactions
+            .into_iter()
+            .map(|transaction| ReceiptEvent::Transfer {
+                txid: transaction.id(),
+                sender: transaction.sender,
+                recipient: transaction.recipient,
+                amount: transaction.amount,
+                fee: transaction.fee,
+            })
+            .collect()
+    });
+    (signatures, executed)
+}
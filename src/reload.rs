@@ -0,0 +1,97 @@
+// Hot config reload: the settings that do not affect consensus can be changed while the node runs,
+// by editing the config file of the data directory (see datadir.rs), which is checked every
+// CONFIG_WATCH_INTERVAL, or by running `node reload`. The config is parsed again from the config
+// file and our command line, and of the changes only the reloadable ones are applied:
+// - the log level (--log-level)
+// - the threads mining on the CPU (--mining-threads)
+// - the peers the p2p layer keeps connected (--target-peers)
+// - the mempool policy (--mempool-max-age, --mempool-max-jobs, --mempool-max-bytes, --mempool-eviction,
+//   --mempool-min-priority, --min-fee-rate), which is applied to the queued jobs as well
+// - --min-confirmations and --stale-tip-factor
+// - the thresholds of the watchdog (--slow-query-ms, --slow-validation-ms, --slow-gossip-ms)
+// All other changes take effect after a restart, reloading only reports them.
+use crate::config::Config;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::level_filters::LevelFilter;
+
+pub const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Changes the level of the global log subscriber, see main.rs
+pub type SetLogLevel = Box<dyn Fn(LevelFilter) -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigChanges {
+    // The changed settings that can be reloaded
+    pub reloaded: Vec<&'static str>,
+    // Whether other settings changed as well
+    pub restart_required: bool,
+}
+
+impl ConfigChanges {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let mut reloaded = vec![];
+        if old.log_level != new.log_level {
+            reloaded.push("log level");
+        }
+        if old.mining_threads != new.mining_threads {
+            reloaded.push("mining threads");
+        }
+        if old.target_peers != new.target_peers {
+            reloaded.push("target peers");
+        }
+        if old.mempool_policy != new.mempool_policy {
+            reloaded.push("mempool policy");
+        }
+        if old.min_confirmations != new.min_confirmations {
+            reloaded.push("min confirmations");
+        }
+        if old.stale_tip_factor != new.stale_tip_factor {
+            reloaded.push("stale tip factor");
+        }
+        if old.watchdog != new.watchdog {
+            reloaded.push("watchdog thresholds");
+        }
+        let restart_required = *old != reloadable(new.clone(), old);
+        Self { reloaded, restart_required }
+    }
+}
+
+// The new config with the reloadable settings taken over from the old one
+fn reloadable(new: Config, old: &Config) -> Config {
+    Config {
+        log_level: old.log_level,
+        mining_threads: old.mining_threads,
+        target_peers: old.target_peers,
+        mempool_policy: old.mempool_policy,
+        min_confirmations: old.min_confirmations,
+        stale_tip_factor: old.stale_tip_factor,
+        watchdog: old.watchdog,
+        ..new
+    }
+}
+
+// Tells whether a file was created, modified or removed since the last check
+#[derive(Debug, Clone)]
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_owned(), modified: modified(path) }
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,105 @@
+// Deterministic replay (`chain replay`): every main chain block is executed again from the genesis
+// block up into a fresh in-memory storage, with the same validation and difficulty rules a block
+// from a peer gets. After each block the state derived from the blocks (the block as stored, its
+// payload, its entries in the address, anchor and transaction indexes and its receipt) is compared
+// with the live storage, and the first block whose replayed state diverges is reported. State roots
+// (see state.rs) do not cover the indexes, so the live state is compared entry by entry.
+use crate::anchor;
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::payload::payload_hash;
+use crate::storage::{MemoryStorage, Storage};
+use crate::transactions;
+use crate::types::Height;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub height: Height,
+    pub hash: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    // Number of blocks replayed, including the genesis block and a divergent one
+    pub blocks: u64,
+    pub divergence: Option<Divergence>,
+}
+
+// Replays the chain stored in live with the rules of the live chain. progress is called with the
+// number of blocks replayed so far and the total.
+pub async fn replay(
+    live_chain: &Chain,
+    live: &mut Storage,
+    progress: &mut (dyn FnMut(u64, u64) + Send),
+) -> Result<ReplayReport, BlockchainError> {
+    if let Some(base) = &live_chain.base {
+        return Err(BlockchainError::Error(format!("chain was restored from a snapshot, it lacks the blocks below {}", base.id)));
+    }
+    let blocks = Chain::get_chain(live).await?;
+    let total = blocks.len() as u64;
+    let mut fresh = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init_with_genesis(&mut fresh, live_chain.genesis.clone()).await?;
+    chain.difficulty = live_chain.difficulty.clone();
+    chain.difficulty_algorithm = live_chain.difficulty_algorithm.clone();
+    chain.params = live_chain.params.clone();
+
+    for (replayed, block) in blocks.into_iter().enumerate() {
+        let reason = match block.id {
+            Height::GENESIS if block != live_chain.genesis => Some("genesis block is not ours".to_owned()),
+            Height::GENESIS => None,
+            _ => match chain.add_block(&mut fresh, block.clone()).await {
+                Ok(()) => compare_state(live, &mut fresh, &block).await?,
+                Err(err) => Some(format!("block does not replay: {}", err)),
+            },
+        };
+        progress(replayed as u64 + 1, total);
+        if let Some(reason) = reason {
+            return Ok(ReplayReport {
+                blocks: replayed as u64 + 1,
+                divergence: Some(Divergence { height: block.id, hash: block.hash, reason }),
+            });
+        }
+    }
+    Ok(ReplayReport { blocks: total, divergence: None })
+}
+
+// Compares the state the block derived in the live and the replayed storage, None if it matches
+async fn compare_state(live: &mut Storage, replayed: &mut Storage, block: &Block) -> Result<Option<String>, BlockchainError> {
+    if Chain::get_block_by_id(replayed, block.id).await? != *block {
+        return Ok(Some("replayed block differs from the stored one".to_owned()));
+    }
+    if live.get_payload(&payload_hash(&block.data)).await.ok().as_ref() != Some(&block.data) {
+        return Ok(Some("payload is missing".to_owned()));
+    }
+    for address in block.addresses() {
+        let live_blocks = up_to(Chain::get_address_blocks(live, &address).await?, block.id);
+        if live_blocks != hashes(Chain::get_address_blocks(replayed, &address).await?) {
+            return Ok(Some(format!("address index of {} differs", address)));
+        }
+    }
+    for digest in anchor::digests(&block.data) {
+        let live_blocks = up_to(Chain::get_anchor_blocks(live, &digest).await?, block.id);
+        if live_blocks != hashes(Chain::get_anchor_blocks(replayed, &digest).await?) {
+            return Ok(Some(format!("anchor index of {} differs", digest)));
+        }
+    }
+    for id in transactions::transaction_ids(&block.data) {
+        let live_blocks = up_to(Chain::get_transaction_blocks(live, &id).await?, block.id);
+        if live_blocks != hashes(Chain::get_transaction_blocks(replayed, &id).await?) {
+            return Ok(Some(format!("transaction index of {} differs", id)));
+        }
+    }
+    if Chain::get_receipt(live, &block.hash).await? != Chain::get_receipt(replayed, &block.hash).await? {
+        return Ok(Some("receipt differs".to_owned()));
+    }
+    Ok(None)
+}
+
+// The live storage already holds the blocks above the one compared
+fn up_to(blocks: Vec<Block>, height: Height) -> Vec<String> {
+    hashes(blocks.into_iter().filter(|block| block.id <= height).collect())
+}
+
+fn hashes(blocks: Vec<Block>) -> Vec<String> {
+    blocks.into_iter().map(|block| block.hash).collect()
+}
@@ -0,0 +1,1244 @@
+// Typed access to the Postgres tables: all SQL of the node lives here, Storage (see storage.rs)
+// picks between these queries and the in-memory storage. Blocks are always read through
+// select_blocks and its BlockQuery and written through insert_block, so the column list and the
+// order of the values are spelled out exactly once. Rows are mapped by column name (see FromRow),
+// so reordering the columns of a query or a table cannot shift values into the wrong fields.
+use crate::atrest;
+use crate::blockchain::{Block, BlockchainError, Checkpoint, SearchResult, StaleBlock};
+use crate::deadletter::BadMessage;
+use crate::diskusage::{BlockSize, PayloadUsage, TableUsage};
+use crate::fastsync::ChainBase;
+use crate::payload::payload_hash;
+use crate::stats::{self, NodeStats};
+use crate::storage::{BAD_MESSAGES_KEPT, PAYLOAD_INLINE_LIMIT, SYNC_HISTORY_KEPT};
+use crate::sync::SyncRecord;
+use crate::transactions::Transaction;
+use crate::types::{Height, Nonce, PeerScore};
+use crate::utxo::{OutPoint, Utxo};
+use crate::watchdog::{self, TaskKind};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Row};
+
+// The payload of a block, stored either inline or in the payloads table
+macro_rules! block_data {
+    () => {
+        "COALESCE((SELECT payloads.data FROM payloads WHERE payloads.hash = payload_hash), data)"
+    };
+}
+
+// Values read from a row by column name, a missing column or a column of another type is an error
+// instead of a panic. COLUMNS are the columns from_row reads, selected by select_list.
+pub trait FromRow: Sized {
+    const COLUMNS: &'static [&'static str];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError>;
+}
+
+// Heights and nonces are stored as INT8, negative values are rejected
+impl FromRow for Block {
+    const COLUMNS: &'static [&'static str] =
+        &["hash", "id", "prev_hash", "timestamp", "nonce", "data", "version", "miner", "extra_nonce", "difficulty", "fee", "state_root", "coinbase"];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(Block {
+            hash: row.try_get("hash")?,
+            id: Height::try_from(row.try_get::<_, i64>("id")?)?,
+            prev_hash: row.try_get("prev_hash")?,
+            timestamp: row.try_get("timestamp")?,
+            nonce: Nonce::try_from(row.try_get::<_, i64>("nonce")?)?,
+            data: atrest::open(row.try_get("data")?)?,
+            version: u8::try_from(row.try_get::<_, i16>("version")?)
+                .map_err(|_| BlockchainError::Error("invalid version".to_owned()))?,
+            miner: row.try_get("miner")?,
+            extra_nonce: Nonce::try_from(row.try_get::<_, i64>("extra_nonce")?)?,
+            difficulty: u64::try_from(row.try_get::<_, i64>("difficulty")?)
+                .map_err(|_| BlockchainError::Error("invalid difficulty".to_owned()))?,
+            fee: u64::try_from(row.try_get::<_, i64>("fee")?).map_err(|_| BlockchainError::Error("invalid fee".to_owned()))?,
+            state_root: row.try_get("state_root")?,
+            coinbase: match atrest::open(row.try_get("coinbase")?)? {
+                coinbase if coinbase.is_empty() => None,
+                coinbase => Some(Transaction::from_json(&coinbase)?),
+            },
+        })
+    }
+}
+
+impl FromRow for StaleBlock {
+    const COLUMNS: &'static [&'static str] = &[
+        "hash", "id", "prev_hash", "timestamp", "nonce", "data", "version", "miner", "extra_nonce", "difficulty", "fee", "state_root", "coinbase", "received_at",
+    ];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(StaleBlock { block: Block::from_row(row)?, received_at: row.try_get("received_at")? })
+    }
+}
+
+// The headline is computed by search_blocks, it is no column of a table
+impl FromRow for SearchResult {
+    const COLUMNS: &'static [&'static str] = &[
+        "hash", "id", "prev_hash", "timestamp", "nonce", "data", "version", "miner", "extra_nonce", "difficulty", "fee", "state_root", "coinbase", "headline",
+    ];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(SearchResult { block: Block::from_row(row)?, headline: row.try_get("headline")? })
+    }
+}
+
+// The size is computed by select_payload_usage, it is no column of a table
+impl FromRow for BlockSize {
+    const COLUMNS: &'static [&'static str] = &["id", "hash", "miner", "bytes"];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(BlockSize {
+            id: Height::try_from(row.try_get::<_, i64>("id")?)?,
+            hash: row.try_get("hash")?,
+            miner: row.try_get("miner")?,
+            bytes: u64::try_from(row.try_get::<_, i64>("bytes")?).map_err(|_| BlockchainError::Error("invalid payload size".to_owned()))?,
+        })
+    }
+}
+
+impl FromRow for PeerScore {
+    const COLUMNS: &'static [&'static str] = &["peer_id", "score", "banned", "addresses", "updated_at"];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(PeerScore {
+            peer_id: row.try_get("peer_id")?,
+            score: row.try_get("score")?,
+            banned: row.try_get("banned")?,
+            addresses: row.try_get("addresses")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+// Encrypted at rest the amount is kept sealed in sealed_amount, see atrest.rs
+impl FromRow for Utxo {
+    const COLUMNS: &'static [&'static str] = &["txid", "vout", "address", "amount", "sealed_amount", "block_hash"];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(Utxo {
+            outpoint: OutPoint {
+                txid: row.try_get("txid")?,
+                vout: u32::try_from(row.try_get::<_, i64>("vout")?).map_err(|_| BlockchainError::Error("invalid output index".to_owned()))?,
+            },
+            address: row.try_get("address")?,
+            amount: match atrest::is_enabled() {
+                true => atrest::open(row.try_get("sealed_amount")?)?.parse().map_err(|_| BlockchainError::Error("invalid amount".to_owned()))?,
+                false => u64::try_from(row.try_get::<_, i64>("amount")?).map_err(|_| BlockchainError::Error("invalid amount".to_owned()))?,
+            },
+            block_hash: row.try_get("block_hash")?,
+        })
+    }
+}
+
+impl FromRow for ChainBase {
+    const COLUMNS: &'static [&'static str] = &["id", "hash", "state"];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(ChainBase {
+            id: Height::try_from(row.try_get::<_, i64>("id")?)?,
+            hash: row.try_get("hash")?,
+            state: serde_json::from_str(&atrest::open(row.try_get("state")?)?)
+                .map_err(|err| BlockchainError::Error(format!("invalid chain base state: {}", err)))?,
+        })
+    }
+}
+
+impl FromRow for BadMessage {
+    const COLUMNS: &'static [&'static str] = &["peer", "size", "prefix", "error", "received_at"];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        Ok(BadMessage {
+            peer: row.try_get("peer")?,
+            size: u64::try_from(row.try_get::<_, i64>("size")?)
+                .map_err(|_| BlockchainError::Error("invalid message size".to_owned()))?,
+            prefix: row.try_get("prefix")?,
+            error: row.try_get("error")?,
+            received_at: row.try_get("received_at")?,
+        })
+    }
+}
+
+impl FromRow for SyncRecord {
+    const COLUMNS: &'static [&'static str] = &[
+        "session_id", "peer", "trigger", "blocks_transferred", "duration_ms", "outcome",
+        "old_tip_id", "old_tip_hash", "new_tip_id", "new_tip_hash", "replaced", "finished_at",
+    ];
+
+    fn from_row(row: &Row) -> Result<Self, BlockchainError> {
+        let count = |column: &str| -> Result<u64, BlockchainError> {
+            u64::try_from(row.try_get::<_, i64>(column)?).map_err(|_| BlockchainError::Error(format!("invalid {}", column.replace('_', " "))))
+        };
+        Ok(SyncRecord {
+            session_id: row.try_get("session_id")?,
+            peer: row.try_get("peer")?,
+            trigger: row.try_get("trigger")?,
+            blocks_transferred: count("blocks_transferred")?,
+            duration_ms: count("duration_ms")?,
+            outcome: row.try_get("outcome")?,
+            old_tip: Checkpoint { id: Height::try_from(row.try_get::<_, i64>("old_tip_id")?)?, hash: row.try_get("old_tip_hash")? },
+            new_tip: Checkpoint { id: Height::try_from(row.try_get::<_, i64>("new_tip_id")?)?, hash: row.try_get("new_tip_hash")? },
+            replaced: row.try_get("replaced")?,
+            finished_at: row.try_get("finished_at")?,
+        })
+    }
+}
+
+fn from_rows<T: FromRow>(rows: &[Row]) -> Result<Vec<T>, BlockchainError> {
+    rows.iter().map(T::from_row).collect()
+}
+
+// The columns to select for T, the data of blocks is looked up in the payloads table first
+fn select_list<T: FromRow>() -> String {
+    T::COLUMNS
+        .iter()
+        .map(|column| match *column {
+            "data" => concat!(block_data!(), " AS data").to_owned(),
+            column => column.to_owned(),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Removes payloads no block refers to anymore
+const PRUNE_PAYLOADS: &str = "
+    DELETE FROM payloads
+    WHERE hash NOT IN (SELECT payload_hash FROM blocks WHERE payload_hash IS NOT NULL)
+    AND hash NOT IN (SELECT payload_hash FROM stale_blocks WHERE payload_hash IS NOT NULL)
+";
+
+// Executed in order on every start-up, so every statement has to be idempotent. {PAYLOAD_INLINE_LIMIT}
+// is replaced by the limit, so migrated blocks follow the same rule as the ones stored later.
+const SCHEMA: [(&str, &str); 27] = [
+    (
+        "creating blockchain table",
+        "
+    CREATE TABLE IF NOT EXISTS blocks (
+        hash            VARCHAR PRIMARY KEY,
+        id              INT8 UNIQUE NOT NULL,
+        prev_hash       VARCHAR UNIQUE NOT NULL,
+        timestamp       INT8 NOT NULL,
+        nonce           INT8 NOT NULL,
+        data            VARCHAR NOT NULL,
+        version         INT2 NOT NULL DEFAULT 0,
+        miner           VARCHAR NOT NULL DEFAULT '',
+        extra_nonce     INT8 NOT NULL DEFAULT 0,
+        difficulty      INT8 NOT NULL DEFAULT 0,
+        fee             INT8 NOT NULL DEFAULT 0,
+        state_root      VARCHAR NOT NULL DEFAULT '',
+        coinbase        VARCHAR NOT NULL DEFAULT ''
+        )
+",
+    ),
+    // Tables created before the header was versioned only contain version 0 blocks
+    (
+        "migrating blockchain table",
+        "
+    ALTER TABLE blocks
+        ADD COLUMN IF NOT EXISTS version        INT2 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS miner          VARCHAR NOT NULL DEFAULT '',
+        ADD COLUMN IF NOT EXISTS extra_nonce    INT8 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS difficulty     INT8 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS fee            INT8 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS state_root     VARCHAR NOT NULL DEFAULT '',
+        ADD COLUMN IF NOT EXISTS coinbase       VARCHAR NOT NULL DEFAULT ''
+",
+    ),
+    // Valid blocks that lost the race for their height are kept for diagnostics
+    (
+        "creating stale blocks table",
+        "
+    CREATE TABLE IF NOT EXISTS stale_blocks (
+        hash            VARCHAR PRIMARY KEY,
+        id              INT8 NOT NULL,
+        prev_hash       VARCHAR NOT NULL,
+        timestamp       INT8 NOT NULL,
+        nonce           INT8 NOT NULL,
+        data            VARCHAR NOT NULL,
+        miner           VARCHAR NOT NULL,
+        received_at     INT8 NOT NULL,
+        version         INT2 NOT NULL DEFAULT 0,
+        extra_nonce     INT8 NOT NULL DEFAULT 0,
+        difficulty      INT8 NOT NULL DEFAULT 0,
+        fee             INT8 NOT NULL DEFAULT 0,
+        state_root      VARCHAR NOT NULL DEFAULT '',
+        coinbase        VARCHAR NOT NULL DEFAULT ''
+        )
+",
+    ),
+    (
+        "migrating stale blocks table",
+        "
+    ALTER TABLE stale_blocks
+        ADD COLUMN IF NOT EXISTS version        INT2 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS extra_nonce    INT8 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS difficulty     INT8 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS fee            INT8 NOT NULL DEFAULT 0,
+        ADD COLUMN IF NOT EXISTS state_root     VARCHAR NOT NULL DEFAULT '',
+        ADD COLUMN IF NOT EXISTS coinbase       VARCHAR NOT NULL DEFAULT ''
+",
+    ),
+    // Full-text index over the block payloads, see search_blocks
+    (
+        "creating block search index",
+        "
+    CREATE INDEX IF NOT EXISTS blocks_data_search ON blocks USING GIN (to_tsvector('english', data))
+",
+    ),
+    // Maps addresses to the main chain blocks that touch them
+    (
+        "creating address index table",
+        "
+    CREATE TABLE IF NOT EXISTS address_index (
+        address         VARCHAR NOT NULL,
+        block_hash      VARCHAR NOT NULL,
+        PRIMARY KEY (address, block_hash)
+        )
+",
+    ),
+    // Index blocks that were stored before the index existed
+    (
+        "backfilling address index",
+        "
+    INSERT INTO address_index (address, block_hash)
+    SELECT miner, hash FROM blocks WHERE miner <> ''
+    ON CONFLICT DO NOTHING
+",
+    ),
+    // Content-addressed payloads, keyed by the hex encoded SHA-256 of the data
+    (
+        "creating payloads table",
+        "
+    CREATE TABLE IF NOT EXISTS payloads (
+        hash            VARCHAR PRIMARY KEY,
+        data            VARCHAR NOT NULL
+        )
+",
+    ),
+    (
+        "creating payload search index",
+        "
+    CREATE INDEX IF NOT EXISTS payloads_data_search ON payloads USING GIN (to_tsvector('english', data))
+",
+    ),
+    // Blocks with a payload_hash keep their data in the payloads table if it is not inline
+    (
+        "adding payload hashes",
+        "
+    ALTER TABLE blocks ADD COLUMN IF NOT EXISTS payload_hash VARCHAR
+",
+    ),
+    (
+        "adding stale payload hashes",
+        "
+    ALTER TABLE stale_blocks ADD COLUMN IF NOT EXISTS payload_hash VARCHAR
+",
+    ),
+    (
+        "creating payload hash index",
+        "
+    CREATE INDEX IF NOT EXISTS blocks_payload_hash ON blocks (payload_hash)
+",
+    ),
+    // Hash the payloads of blocks stored before, moving the large ones (see PAYLOAD_INLINE_LIMIT)
+    // to the payloads table
+    (
+        "backfilling payloads",
+        "
+    INSERT INTO payloads (hash, data)
+    SELECT encode(sha256(convert_to(data, 'UTF8')), 'hex'), data FROM blocks
+    WHERE payload_hash IS NULL AND octet_length(data) >= {PAYLOAD_INLINE_LIMIT}
+    ON CONFLICT DO NOTHING
+",
+    ),
+    (
+        "backfilling payload hashes",
+        "
+    UPDATE blocks
+    SET payload_hash = encode(sha256(convert_to(data, 'UTF8')), 'hex'),
+        data = CASE WHEN octet_length(data) >= {PAYLOAD_INLINE_LIMIT} THEN '' ELSE data END
+    WHERE payload_hash IS NULL
+",
+    ),
+    // Maps anchored digests to the main chain blocks that anchor them
+    (
+        "creating anchor index table",
+        "
+    CREATE TABLE IF NOT EXISTS anchor_index (
+        digest          VARCHAR NOT NULL,
+        block_hash      VARCHAR NOT NULL,
+        PRIMARY KEY (digest, block_hash)
+        )
+",
+    ),
+    // The receipts of the payloads of main chain blocks (see receipts.rs), JSON encoded
+    (
+        "creating receipts table",
+        "
+    CREATE TABLE IF NOT EXISTS receipts (
+        receipt         VARCHAR NOT NULL,
+        block_hash      VARCHAR PRIMARY KEY
+        )
+",
+    ),
+    // Maps transaction IDs to the main chain blocks that carry them
+    (
+        "creating transaction index table",
+        "
+    CREATE TABLE IF NOT EXISTS transaction_index (
+        txid            VARCHAR NOT NULL,
+        block_hash      VARCHAR NOT NULL,
+        PRIMARY KEY (txid, block_hash)
+        )
+",
+    ),
+    (
+        "creating peer scores table",
+        "
+    CREATE TABLE IF NOT EXISTS peer_scores (
+        peer_id         VARCHAR PRIMARY KEY,
+        score           FLOAT8 NOT NULL,
+        banned          BOOL NOT NULL,
+        addresses       VARCHAR[] NOT NULL,
+        updated_at      INT8 NOT NULL
+        )
+",
+    ),
+    (
+        "creating bad messages table",
+        "
+    CREATE TABLE IF NOT EXISTS bad_messages (
+        id              BIGSERIAL PRIMARY KEY,
+        peer            VARCHAR NOT NULL,
+        size            INT8 NOT NULL,
+        prefix          VARCHAR NOT NULL,
+        error           VARCHAR NOT NULL,
+        received_at     INT8 NOT NULL
+        )
+",
+    ),
+    // At most one row, set if the chain was restored from a snapshot (see fastsync.rs). The state is
+    // stored as JSON, it is only ever read as a whole.
+    (
+        "creating chain base table",
+        "
+    CREATE TABLE IF NOT EXISTS chain_base (
+        id              INT8 NOT NULL,
+        hash            VARCHAR NOT NULL,
+        state           VARCHAR NOT NULL
+        )
+",
+    ),
+    // Finished sync sessions for `sync history`, the latest SYNC_HISTORY_KEPT are kept
+    (
+        "creating sync history table",
+        "
+    CREATE TABLE IF NOT EXISTS sync_history (
+        id                  BIGSERIAL PRIMARY KEY,
+        session_id          VARCHAR NOT NULL,
+        peer                VARCHAR NOT NULL,
+        trigger             VARCHAR NOT NULL,
+        blocks_transferred  INT8 NOT NULL,
+        duration_ms         INT8 NOT NULL,
+        outcome             VARCHAR NOT NULL,
+        old_tip_id          INT8 NOT NULL,
+        old_tip_hash        VARCHAR NOT NULL,
+        new_tip_id          INT8 NOT NULL,
+        new_tip_hash        VARCHAR NOT NULL,
+        replaced            BOOL NOT NULL,
+        finished_at         INT8 NOT NULL
+        )
+",
+    ),
+    // Lifetime counters and start times of the node by name for `node stats`, see stats.rs
+    (
+        "creating node stats table",
+        "
+    CREATE TABLE IF NOT EXISTS node_stats (
+        name            VARCHAR PRIMARY KEY,
+        value           INT8 NOT NULL
+        )
+",
+    ),
+    (
+        "creating seen peers table",
+        "
+    CREATE TABLE IF NOT EXISTS seen_peers (
+        peer_id         VARCHAR PRIMARY KEY,
+        first_seen_at   INT8 NOT NULL
+        )
+",
+    ),
+    // The outputs created by main chain blocks (see utxo.rs). Spent outputs keep the hash of the
+    // block spending them, so removing that block makes them unspent again.
+    (
+        "creating utxos table",
+        "
+    CREATE TABLE IF NOT EXISTS utxos (
+        txid            VARCHAR NOT NULL,
+        vout            INT8 NOT NULL,
+        address         VARCHAR NOT NULL,
+        amount          INT8 NOT NULL,
+        block_hash      VARCHAR NOT NULL,
+        spent_in        VARCHAR,
+        PRIMARY KEY (txid, vout)
+        )
+",
+    ),
+    (
+        "creating utxo address index",
+        "
+    CREATE INDEX IF NOT EXISTS utxos_address ON utxos (address) WHERE spent_in IS NULL
+",
+    ),
+    // Encrypted at rest the amount is sealed here and the amount column is 0, see atrest.rs
+    (
+        "adding sealed utxo amounts",
+        "
+    ALTER TABLE utxos ADD COLUMN IF NOT EXISTS sealed_amount VARCHAR NOT NULL DEFAULT ''
+",
+    ),
+    // At most one row, the check value sealed with the key the database is encrypted with at rest,
+    // see atrest.rs
+    (
+        "creating at rest table",
+        "
+    CREATE TABLE IF NOT EXISTS at_rest (
+        check_value     VARCHAR NOT NULL
+        )
+",
+    ),
+];
+
+type Params<'a> = Vec<&'a (dyn ToSql + Sync)>;
+
+// Table a block is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockTable {
+    // The main chain
+    Blocks,
+    // Valid blocks that did not make it into the main chain, with the time we received them
+    StaleBlocks { received_at: i64 },
+}
+
+// Main chain blocks selected by Repository::select_blocks, always oldest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockQuery<'a> {
+    All,
+    Hash(&'a str),
+    Id(Height),
+    // Main chain blocks from the first to the second height, both included
+    Range(Height, Height),
+    Latest,
+    // Blocks touching the address, see the address index
+    Address(&'a str),
+    // Blocks anchoring the digest, see the anchor index
+    Anchor(&'a str),
+    // Blocks carrying the transaction, see the transaction index
+    Transaction(&'a str),
+}
+
+// Tables mapping a key to the main chain blocks it occurs in. The receipts table fits the same
+// shape with the receipt as the key, so it is cleared and rebuilt along with the indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Index {
+    Address,
+    Anchor,
+    Transaction,
+    Receipt,
+}
+
+impl Index {
+    pub const ALL: [Index; 4] = [Index::Address, Index::Anchor, Index::Transaction, Index::Receipt];
+
+    fn table(self) -> &'static str {
+        match self {
+            Index::Address => "address_index",
+            Index::Anchor => "anchor_index",
+            Index::Transaction => "transaction_index",
+            Index::Receipt => "receipts",
+        }
+    }
+
+    fn key_column(self) -> &'static str {
+        match self {
+            Index::Address => "address",
+            Index::Anchor => "digest",
+            Index::Transaction => "txid",
+            Index::Receipt => "receipt",
+        }
+    }
+}
+
+pub struct Repository<'a> {
+    client: &'a Client,
+}
+
+impl<'a> Repository<'a> {
+    pub fn new(client: &'a Client) -> Self {
+        Self { client }
+    }
+
+    // All statements but the transaction batches go through these, so slow ones are reported with
+    // their parameters (see watchdog.rs)
+    async fn execute(&self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, tokio_postgres::Error> {
+        let started_at = Instant::now();
+        let result = self.client.execute(statement, params).await;
+        observe_query(statement, params, started_at);
+        result
+    }
+
+    async fn query(&self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, tokio_postgres::Error> {
+        let started_at = Instant::now();
+        let result = self.client.query(statement, params).await;
+        observe_query(statement, params, started_at);
+        result
+    }
+
+    async fn query_one(&self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Row, tokio_postgres::Error> {
+        let started_at = Instant::now();
+        let result = self.client.query_one(statement, params).await;
+        observe_query(statement, params, started_at);
+        result
+    }
+
+    async fn query_opt(&self, statement: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, tokio_postgres::Error> {
+        let started_at = Instant::now();
+        let result = self.client.query_opt(statement, params).await;
+        observe_query(statement, params, started_at);
+        result
+    }
+
+    // Creates and migrates the schema, returning the description and error of every failed statement
+    pub async fn migrate(&self) -> Vec<(&'static str, tokio_postgres::Error)> {
+        let mut failed = vec![];
+        for (description, statement) in SCHEMA.iter() {
+            let statement = statement.replace("{PAYLOAD_INLINE_LIMIT}", &PAYLOAD_INLINE_LIMIT.to_string());
+            if let Err(err) = self.execute(&statement, &[]).await {
+                failed.push((*description, err));
+            }
+        }
+        failed
+    }
+
+    // Creates the schema if needed and makes it the one all tables are created and looked up in,
+    // used for the additional chains (see chains.rs)
+    pub async fn use_schema(&self, schema: &str) -> Result<(), BlockchainError> {
+        self.client.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {0}; SET search_path TO {0}", schema)).await?;
+        Ok(())
+    }
+
+    // Statements up to commit or rollback are applied all at once or not at all
+    pub async fn begin(&self) -> Result<(), BlockchainError> {
+        self.client.batch_execute("BEGIN").await?;
+        Ok(())
+    }
+
+    pub async fn commit(&self) -> Result<(), BlockchainError> {
+        self.client.batch_execute("COMMIT").await?;
+        Ok(())
+    }
+
+    pub async fn rollback(&self) -> Result<(), BlockchainError> {
+        self.client.batch_execute("ROLLBACK").await?;
+        Ok(())
+    }
+
+    // Stores the block, large payloads go to the payloads table. Main chain blocks have to be new,
+    // stale blocks stored before are ignored.
+    pub async fn insert_block(&self, table: BlockTable, block: &Block) -> Result<(), BlockchainError> {
+        let (data, payload_hash) = self.store_payload(&block.data).await?;
+        let id = i64::try_from(block.id)?;
+        let nonce = i64::try_from(block.nonce)?;
+        let version = i16::from(block.version);
+        let extra_nonce = i64::try_from(block.extra_nonce)?;
+        let difficulty = difficulty_to_int8(block.difficulty)?;
+        let fee = i64::try_from(block.fee).map_err(|_| BlockchainError::Error(format!("fee out of range: {}", block.fee)))?;
+        // Stored as JSON, empty without one
+        let coinbase = atrest::seal(&block.coinbase.as_ref().map(Transaction::to_json).unwrap_or_default());
+        let mut params: Params = vec![
+            &block.hash, &id, &block.prev_hash, &block.timestamp, &nonce, &data, &version, &block.miner, &extra_nonce,
+            &difficulty, &fee, &block.state_root, &coinbase, &payload_hash,
+        ];
+        let columns = "hash, id, prev_hash, timestamp, nonce, data, version, miner, extra_nonce, difficulty, fee, state_root, coinbase, payload_hash";
+        let statement = match &table {
+            BlockTable::Blocks => format!("INSERT INTO blocks ({}) VALUES ({})", columns, placeholders(params.len())),
+            BlockTable::StaleBlocks { received_at } => {
+                params.push(received_at);
+                format!(
+                    "INSERT INTO stale_blocks ({}, received_at) VALUES ({}) ON CONFLICT (hash) DO NOTHING",
+                    columns,
+                    placeholders(params.len())
+                )
+            }
+        };
+        self.execute(&statement, &params).await?;
+        Ok(())
+    }
+
+    pub async fn select_blocks(&self, query: BlockQuery<'_>) -> Result<Vec<Block>, BlockchainError> {
+        let id;
+        let range;
+        let (condition, params): (&str, Params) = match &query {
+            BlockQuery::All => ("TRUE", vec![]),
+            BlockQuery::Hash(hash) => ("hash = $1", vec![hash]),
+            BlockQuery::Id(height) => {
+                id = i64::try_from(*height)?;
+                ("id = $1", vec![&id])
+            }
+            BlockQuery::Range(from, to) => {
+                range = (i64::try_from(*from)?, i64::try_from(*to)?);
+                ("id >= $1 AND id <= $2", vec![&range.0, &range.1])
+            }
+            BlockQuery::Latest => ("id = (SELECT MAX(id) FROM blocks)", vec![]),
+            BlockQuery::Address(address) => ("hash IN (SELECT block_hash FROM address_index WHERE address = $1)", vec![address]),
+            BlockQuery::Anchor(digest) => ("hash IN (SELECT block_hash FROM anchor_index WHERE digest = $1)", vec![digest]),
+            BlockQuery::Transaction(id) => ("hash IN (SELECT block_hash FROM transaction_index WHERE txid = $1)", vec![id]),
+        };
+        let rows = self
+            .query(&format!("SELECT {} FROM blocks WHERE {} ORDER BY id ASC", select_list::<Block>(), condition), &params)
+            .await?;
+        from_rows(&rows)
+    }
+
+    // Like select_blocks, for queries selecting at most one block
+    pub async fn select_block(&self, query: BlockQuery<'_>) -> Result<Option<Block>, BlockchainError> {
+        Ok(self.select_blocks(query).await?.pop())
+    }
+
+    pub async fn count_blocks(&self) -> Result<u64, BlockchainError> {
+        let row = self.query_one("SELECT COUNT (*) AS count FROM blocks", &[]).await?;
+        Ok(row.try_get::<_, i64>("count")? as u64)
+    }
+
+    // Number of stale and of non-genesis main chain blocks with an id of at least min_id
+    pub async fn count_blocks_from(&self, min_id: Height) -> Result<(i64, i64), BlockchainError> {
+        let row = self
+            .query_one(
+                "
+    SELECT
+        (SELECT COUNT (*) FROM stale_blocks WHERE id >= $1) AS stale,
+        (SELECT COUNT (*) FROM blocks WHERE id >= $1 AND id > 0) AS main
+    ",
+                &[&i64::try_from(min_id)?],
+            )
+            .await?;
+        Ok((row.try_get("stale")?, row.try_get("main")?))
+    }
+
+    // Removes all main chain blocks, or the ones above the height, with their index entries and payloads
+    pub async fn delete_blocks(&self, above: Option<Height>) -> Result<(), BlockchainError> {
+        match above {
+            Some(height) => {
+                let id = i64::try_from(height)?;
+                for index in Index::ALL {
+                    self
+                        .execute(
+                            &format!("DELETE FROM {} WHERE block_hash IN (SELECT hash FROM blocks WHERE id > $1)", index.table()),
+                            &[&id],
+                        )
+                        .await?;
+                }
+                self
+                    .execute("UPDATE utxos SET spent_in = NULL WHERE spent_in IN (SELECT hash FROM blocks WHERE id > $1)", &[&id])
+                    .await?;
+                self.execute("DELETE FROM utxos WHERE block_hash IN (SELECT hash FROM blocks WHERE id > $1)", &[&id]).await?;
+                self.execute("DELETE FROM blocks WHERE id > $1", &[&id]).await?;
+            }
+            None => {
+                for index in Index::ALL {
+                    self.clear_index(index).await?;
+                }
+                self.clear_utxos().await?;
+                self.execute("DELETE FROM blocks", &[]).await?;
+            }
+        }
+        self.prune_payloads().await
+    }
+
+    // The most recent stale blocks, highest first
+    pub async fn select_stale_blocks(&self, limit: i64) -> Result<Vec<StaleBlock>, BlockchainError> {
+        let rows = self
+            .query(
+                &format!("SELECT {} FROM stale_blocks ORDER BY id DESC, received_at DESC LIMIT $1", select_list::<StaleBlock>()),
+                &[&limit],
+            )
+            .await?;
+        from_rows(&rows)
+    }
+
+    // The stale blocks at or below the height, with keep_headers only those whose payload was not
+    // pruned yet. The blocks are returned as they were before they were pruned, see gc.rs
+    pub async fn prune_stale_blocks(&self, height: Height, keep_headers: bool) -> Result<Vec<StaleBlock>, BlockchainError> {
+        let id = i64::try_from(height)?;
+        let condition = match keep_headers {
+            true => "id <= $1 AND (payload_hash IS NOT NULL OR data <> '')",
+            false => "id <= $1",
+        };
+        let rows = self
+            .query(&format!("SELECT {} FROM stale_blocks WHERE {}", select_list::<StaleBlock>(), condition), &[&id])
+            .await?;
+        let pruned: Vec<StaleBlock> = from_rows(&rows)?;
+        let hashes = pruned.iter().map(|stale| stale.block.hash.clone()).collect::<Vec<String>>();
+        match keep_headers {
+            true => {
+                self
+                    .execute("UPDATE stale_blocks SET data = '', payload_hash = NULL WHERE hash = ANY($1)", &[&hashes])
+                    .await?;
+                self.prune_payloads().await?;
+            }
+            false => self.delete_stale_blocks(&hashes).await?,
+        }
+        Ok(pruned)
+    }
+
+    pub async fn delete_stale_blocks(&self, hashes: &[String]) -> Result<(), BlockchainError> {
+        self.execute("DELETE FROM stale_blocks WHERE hash = ANY($1)", &[&hashes]).await?;
+        self.prune_payloads().await
+    }
+
+    // Full-text search over the payloads of all main chain blocks, best matches first
+    pub async fn search_blocks(&self, query: &str, limit: i64) -> Result<Vec<SearchResult>, BlockchainError> {
+        let rows = self
+            .query(
+                &format!(
+                    "
+        SELECT {columns}, ts_headline('english', {data}, query, 'StartSel=*, StopSel=*') AS headline
+        FROM blocks, websearch_to_tsquery('english', $1) query
+        WHERE to_tsvector('english', data) @@ query
+        OR payload_hash IN (SELECT hash FROM payloads WHERE to_tsvector('english', payloads.data) @@ query)
+        ORDER BY ts_rank(to_tsvector('english', {data}), query) DESC, id DESC
+        LIMIT $2
+        ",
+                    columns = select_list::<Block>(),
+                    data = block_data!()
+                ),
+                &[&query, &limit],
+            )
+            .await?;
+        from_rows(&rows)
+    }
+
+    // Receipts are only looked up by block, they are sealed when encrypted at rest (see atrest.rs)
+    pub async fn insert_index_entry(&self, index: Index, key: &str, block_hash: &str) -> Result<(), BlockchainError> {
+        let key = match index {
+            Index::Receipt => atrest::seal(key),
+            _ => key.to_owned(),
+        };
+        self
+            .execute(
+                &format!("INSERT INTO {} ({}, block_hash) VALUES ($1, $2) ON CONFLICT DO NOTHING", index.table(), index.key_column()),
+                &[&key, &block_hash],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // The JSON encoded receipt of the main chain block
+    pub async fn select_receipt(&self, block_hash: &str) -> Result<Option<String>, BlockchainError> {
+        let row = self.query_opt("SELECT receipt FROM receipts WHERE block_hash = $1", &[&block_hash]).await?;
+        row.map(|row| atrest::open(row.try_get("receipt")?)).transpose()
+    }
+
+    pub async fn clear_index(&self, index: Index) -> Result<(), BlockchainError> {
+        self.execute(&format!("DELETE FROM {}", index.table()), &[]).await?;
+        Ok(())
+    }
+
+    // The outputs that are unspent, by outpoint
+    pub async fn select_unspent(&self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, Utxo>, BlockchainError> {
+        let (txids, vouts) = outpoint_arrays(outpoints);
+        let rows = self
+            .query(
+                &format!(
+                    "SELECT {} FROM utxos WHERE spent_in IS NULL AND (txid, vout) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::INT8[]))",
+                    select_list::<Utxo>()
+                ),
+                &[&txids, &vouts],
+            )
+            .await?;
+        let utxos: Vec<Utxo> = from_rows(&rows)?;
+        Ok(utxos.into_iter().map(|utxo| (utxo.outpoint.clone(), utxo)).collect())
+    }
+
+    // The spent outputs among the outpoints, with the hash of the block spending them
+    pub async fn select_spent(&self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, String>, BlockchainError> {
+        let (txids, vouts) = outpoint_arrays(outpoints);
+        let rows = self
+            .query(
+                "SELECT txid, vout, spent_in FROM utxos WHERE spent_in IS NOT NULL AND (txid, vout) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::INT8[]))",
+                &[&txids, &vouts],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| -> Result<(OutPoint, String), BlockchainError> {
+                let vout = u32::try_from(row.try_get::<_, i64>("vout")?).map_err(|_| BlockchainError::Error("invalid output index".to_owned()))?;
+                Ok((OutPoint { txid: row.try_get("txid")?, vout }, row.try_get("spent_in")?))
+            })
+            .collect()
+    }
+
+    // The unspent outputs of the address, ordered by transaction ID and output
+    pub async fn select_utxos(&self, address: &str) -> Result<Vec<Utxo>, BlockchainError> {
+        let rows = self
+            .query(
+                &format!("SELECT {} FROM utxos WHERE spent_in IS NULL AND address = $1 ORDER BY txid, vout", select_list::<Utxo>()),
+                &[&address],
+            )
+            .await?;
+        from_rows(&rows)
+    }
+
+    pub async fn insert_utxo(&self, utxo: &Utxo) -> Result<(), BlockchainError> {
+        let amount = i64::try_from(utxo.amount).map_err(|_| BlockchainError::Error(format!("amount out of range: {}", utxo.amount)))?;
+        let (amount, sealed_amount) = match atrest::is_enabled() {
+            true => (0, atrest::seal(&utxo.amount.to_string())),
+            false => (amount, String::new()),
+        };
+        self
+            .execute(
+                "INSERT INTO utxos (txid, vout, address, amount, sealed_amount, block_hash) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING",
+                &[&utxo.outpoint.txid, &i64::from(utxo.outpoint.vout), &utxo.address, &amount, &sealed_amount, &utxo.block_hash],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Marks the outputs as spent by the main chain block
+    pub async fn spend_utxos(&self, outpoints: &[OutPoint], block_hash: &str) -> Result<(), BlockchainError> {
+        let (txids, vouts) = outpoint_arrays(outpoints);
+        self
+            .execute(
+                "UPDATE utxos SET spent_in = $3 WHERE (txid, vout) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::INT8[]))",
+                &[&txids, &vouts, &block_hash],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn clear_utxos(&self) -> Result<(), BlockchainError> {
+        self.execute("DELETE FROM utxos", &[]).await?;
+        Ok(())
+    }
+
+    // Rebuilds the full-text and height indexes
+    pub async fn reindex_tables(&self) -> Result<(), BlockchainError> {
+        self.execute("REINDEX TABLE blocks", &[]).await?;
+        self.execute("REINDEX TABLE payloads", &[]).await?;
+        Ok(())
+    }
+
+    // Stores large payloads in the payloads table, returns the data to store inline and the hash of
+    // the payload. Encrypted at rest the hash is stored as its lookup key, see atrest.rs.
+    async fn store_payload(&self, data: &str) -> Result<(String, String), BlockchainError> {
+        let hash = atrest::lookup_key(&payload_hash(data));
+        if data.len() < PAYLOAD_INLINE_LIMIT {
+            return Ok((atrest::seal(data), hash));
+        }
+        self
+            .execute("INSERT INTO payloads (hash, data) VALUES ($1, $2) ON CONFLICT DO NOTHING", &[&hash, &atrest::seal(data)])
+            .await?;
+        Ok((String::new(), hash))
+    }
+
+    // The payload with the given hash of a main chain or stale block
+    pub async fn select_payload(&self, hash: &str) -> Result<Option<String>, BlockchainError> {
+        let row = self
+            .query_one(
+                "
+        SELECT COALESCE(
+            (SELECT data FROM payloads WHERE hash = $1),
+            (SELECT data FROM blocks WHERE payload_hash = $1 LIMIT 1),
+            (SELECT data FROM stale_blocks WHERE payload_hash = $1 LIMIT 1)
+        ) AS data
+        ",
+                &[&atrest::lookup_key(hash)],
+            )
+            .await?;
+        row.try_get::<_, Option<&str>>("data")?.map(atrest::open).transpose()
+    }
+
+    pub async fn prune_payloads(&self) -> Result<(), BlockchainError> {
+        self.execute(PRUNE_PAYLOADS, &[]).await?;
+        Ok(())
+    }
+
+    // Inserts or updates the scores of the given peers
+    pub async fn upsert_peer_scores(&self, scores: &[PeerScore]) -> Result<(), BlockchainError> {
+        let sql = "INSERT INTO peer_scores (peer_id, score, banned, addresses, updated_at) VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (peer_id) DO UPDATE SET score = $2, banned = $3, addresses = $4, updated_at = $5";
+        let statement = self.client.prepare(sql).await?;
+        for score in scores {
+            let params: Params = vec![&score.peer_id, &score.score, &score.banned, &score.addresses, &score.updated_at];
+            let started_at = Instant::now();
+            self.client.execute(&statement, &params).await?;
+            observe_query(sql, &params, started_at);
+        }
+        Ok(())
+    }
+
+    pub async fn select_peer_scores(&self) -> Result<Vec<PeerScore>, BlockchainError> {
+        let rows = self.query(&format!("SELECT {} FROM peer_scores", select_list::<PeerScore>()), &[]).await?;
+        from_rows(&rows)
+    }
+
+    // Records the bad message, keeping the latest BAD_MESSAGES_KEPT
+    pub async fn insert_bad_message(&self, message: &BadMessage) -> Result<(), BlockchainError> {
+        self
+            .execute(
+                "INSERT INTO bad_messages (peer, size, prefix, error, received_at) VALUES ($1, $2, $3, $4, $5)",
+                &[&message.peer, &(message.size as i64), &message.prefix, &message.error, &message.received_at],
+            )
+            .await?;
+        self
+            .execute("DELETE FROM bad_messages WHERE id <= (SELECT MAX(id) FROM bad_messages) - $1", &[&BAD_MESSAGES_KEPT])
+            .await?;
+        Ok(())
+    }
+
+    // The latest bad messages, newest first
+    pub async fn select_bad_messages(&self, limit: i64) -> Result<Vec<BadMessage>, BlockchainError> {
+        let rows = self
+            .query(&format!("SELECT {} FROM bad_messages ORDER BY id DESC LIMIT $1", select_list::<BadMessage>()), &[&limit])
+            .await?;
+        from_rows(&rows)
+    }
+
+    // Records the finished sync session, keeping the latest SYNC_HISTORY_KEPT
+    pub async fn insert_sync_record(&self, record: &SyncRecord) -> Result<(), BlockchainError> {
+        let old_tip_id = i64::try_from(record.old_tip.id)?;
+        let new_tip_id = i64::try_from(record.new_tip.id)?;
+        self
+            .execute(
+                "INSERT INTO sync_history (session_id, peer, trigger, blocks_transferred, duration_ms, outcome, old_tip_id, old_tip_hash, new_tip_id, new_tip_hash, replaced, finished_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+                &[
+                    &record.session_id,
+                    &record.peer,
+                    &record.trigger,
+                    &(record.blocks_transferred as i64),
+                    &(record.duration_ms as i64),
+                    &record.outcome,
+                    &old_tip_id,
+                    &record.old_tip.hash,
+                    &new_tip_id,
+                    &record.new_tip.hash,
+                    &record.replaced,
+                    &record.finished_at,
+                ],
+            )
+            .await?;
+        self
+            .execute("DELETE FROM sync_history WHERE id <= (SELECT MAX(id) FROM sync_history) - $1", &[&SYNC_HISTORY_KEPT])
+            .await?;
+        Ok(())
+    }
+
+    // The latest sync records, newest first
+    pub async fn select_sync_history(&self, limit: i64) -> Result<Vec<SyncRecord>, BlockchainError> {
+        let rows = self
+            .query(&format!("SELECT {} FROM sync_history ORDER BY id DESC LIMIT $1", select_list::<SyncRecord>()), &[&limit])
+            .await?;
+        from_rows(&rows)
+    }
+
+    pub async fn select_chain_base(&self) -> Result<Option<ChainBase>, BlockchainError> {
+        let rows = self.query(&format!("SELECT {} FROM chain_base", select_list::<ChainBase>()), &[]).await?;
+        Ok(from_rows(&rows)?.pop())
+    }
+
+    pub async fn insert_chain_base(&self, base: &ChainBase) -> Result<(), BlockchainError> {
+        let state = atrest::seal(&serde_json::to_string(&base.state).expect("can jsonify snapshot state"));
+        self.delete_chain_base().await?;
+        self
+            .execute("INSERT INTO chain_base (id, hash, state) VALUES ($1, $2, $3)", &[&i64::try_from(base.id)?, &base.hash, &state])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_chain_base(&self) -> Result<(), BlockchainError> {
+        self.execute("DELETE FROM chain_base", &[]).await?;
+        Ok(())
+    }
+
+    // None until the database was first opened with a storage key
+    pub async fn select_at_rest_check(&self) -> Result<Option<String>, BlockchainError> {
+        let row = self.query_opt("SELECT check_value FROM at_rest LIMIT 1", &[]).await?;
+        Ok(row.map(|row| row.try_get("check_value")).transpose()?)
+    }
+
+    pub async fn insert_at_rest_check(&self, check_value: &str) -> Result<(), BlockchainError> {
+        self.execute("INSERT INTO at_rest (check_value) VALUES ($1)", &[&check_value]).await?;
+        Ok(())
+    }
+
+    // Seals the values written before encryption was enabled and moves the payloads to their lookup
+    // keys, see atrest.rs. Every non-empty value is sealed, including one that happens to start like a
+    // sealed one.
+    pub async fn seal_legacy_values(&self) -> Result<(), BlockchainError> {
+        let columns = [
+            ("blocks", "hash", "data"),
+            ("blocks", "hash", "coinbase"),
+            ("stale_blocks", "hash", "data"),
+            ("stale_blocks", "hash", "coinbase"),
+            ("payloads", "hash", "data"),
+            ("chain_base", "hash", "state"),
+            ("receipts", "block_hash", "receipt"),
+        ];
+        for (table, key, column) in columns {
+            let statement = format!("SELECT {0}, {1} FROM {2} WHERE {1} <> ''", key, column, table);
+            for row in self.query(&statement, &[]).await? {
+                let value = atrest::seal(row.try_get(column)?);
+                let statement = format!("UPDATE {} SET {} = $1 WHERE {} = $2", table, column, key);
+                self.execute(&statement, &[&value, &row.try_get::<_, &str>(key)?]).await?;
+            }
+        }
+        for row in self.query("SELECT txid, vout, amount FROM utxos", &[]).await? {
+            let amount = atrest::seal(&row.try_get::<_, i64>("amount")?.to_string());
+            self
+                .execute(
+                    "UPDATE utxos SET amount = 0, sealed_amount = $1 WHERE txid = $2 AND vout = $3",
+                    &[&amount, &row.try_get::<_, &str>("txid")?, &row.try_get::<_, i64>("vout")?],
+                )
+                .await?;
+        }
+        // Payloads are stored under the lookup key of their hash from now on
+        for (table, column) in [("payloads", "hash"), ("blocks", "payload_hash"), ("stale_blocks", "payload_hash")] {
+            for row in self.query(&format!("SELECT DISTINCT {0} FROM {1} WHERE {0} IS NOT NULL", column, table), &[]).await? {
+                let hash: &str = row.try_get(column)?;
+                let statement = format!("UPDATE {0} SET {1} = $1 WHERE {1} = $2", table, column);
+                self.execute(&statement, &[&atrest::lookup_key(hash), &hash]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Counts one more start, the first start time is only set once
+    pub async fn record_start(&self, started_at: i64) -> Result<(), BlockchainError> {
+        self.add_node_stats(&[(stats::STARTS, 1)]).await?;
+        self
+            .execute(
+                "INSERT INTO node_stats (name, value) VALUES ($1, $2) ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value",
+                &[&stats::LAST_STARTED_AT, &started_at],
+            )
+            .await?;
+        self
+            .execute("INSERT INTO node_stats (name, value) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING", &[&stats::FIRST_STARTED_AT, &started_at])
+            .await?;
+        Ok(())
+    }
+
+    // Adds the values to the counters of the same name
+    pub async fn add_node_stats(&self, values: &[(&str, i64)]) -> Result<(), BlockchainError> {
+        for (name, value) in values {
+            self
+                .execute(
+                    "INSERT INTO node_stats (name, value) VALUES ($1, $2) ON CONFLICT (name) DO UPDATE SET value = node_stats.value + EXCLUDED.value",
+                    &[name, value],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Peers seen before keep the time we first saw them
+    pub async fn insert_seen_peers(&self, peers: &[&str], seen_at: i64) -> Result<(), BlockchainError> {
+        for peer in peers {
+            self
+                .execute("INSERT INTO seen_peers (peer_id, first_seen_at) VALUES ($1, $2) ON CONFLICT (peer_id) DO NOTHING", &[peer, &seen_at])
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn select_node_stats(&self) -> Result<NodeStats, BlockchainError> {
+        let rows = self.query("SELECT name, value FROM node_stats", &[]).await?;
+        let values = rows
+            .iter()
+            .map(|row| Ok((row.try_get::<_, &str>("name")?, row.try_get::<_, i64>("value")?)))
+            .collect::<Result<Vec<_>, BlockchainError>>()?;
+        let peers_seen = self.query_one("SELECT COUNT(*) AS count FROM seen_peers", &[]).await?.try_get::<_, i64>("count")?;
+        Ok(NodeStats::from_values(values, u64::try_from(peers_seen).unwrap_or_default()))
+    }
+
+    // Payload sizes of the main chain blocks in the height range, with the top largest blocks
+    pub async fn select_payload_usage(&self, from: Height, to: Height, top: i64) -> Result<PayloadUsage, BlockchainError> {
+        let range = (i64::try_from(from)?, i64::try_from(to)?);
+        let row = self
+            .query_one(
+                &format!(
+                    "
+        SELECT COUNT(*) AS blocks, COALESCE(SUM(size), 0)::INT8 AS total_bytes, COALESCE(MAX(size), 0)::INT8 AS max_bytes
+        FROM (SELECT octet_length({data}) AS size FROM blocks WHERE id >= $1 AND id <= $2) sizes
+        ",
+                    data = block_data!()
+                ),
+                &[&range.0, &range.1],
+            )
+            .await?;
+        let count = |column: &str| -> Result<u64, BlockchainError> {
+            u64::try_from(row.try_get::<_, i64>(column)?).map_err(|_| BlockchainError::Error(format!("invalid {}", column.replace('_', " "))))
+        };
+        let rows = self
+            .query(
+                &format!(
+                    "
+        SELECT id, hash, miner, octet_length({data})::INT8 AS bytes
+        FROM blocks WHERE id >= $1 AND id <= $2
+        ORDER BY bytes DESC, id ASC
+        LIMIT $3
+        ",
+                    data = block_data!()
+                ),
+                &[&range.0, &range.1, &top],
+            )
+            .await?;
+        Ok(PayloadUsage { blocks: count("blocks")?, total_bytes: count("total_bytes")?, max_bytes: count("max_bytes")?, largest: from_rows(&rows)? })
+    }
+
+    // The size of every table of our schema including its indexes and TOAST data, largest first
+    pub async fn select_table_usage(&self) -> Result<Vec<TableUsage>, BlockchainError> {
+        let rows = self
+            .query(
+                "
+        SELECT c.relname::TEXT AS name, pg_total_relation_size(c.oid) AS bytes
+        FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE n.nspname = current_schema() AND c.relkind = 'r'
+        ORDER BY bytes DESC, name ASC
+        ",
+                &[],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(TableUsage {
+                    name: row.try_get("name")?,
+                    bytes: u64::try_from(row.try_get::<_, i64>("bytes")?).map_err(|_| BlockchainError::Error("invalid table size".to_owned()))?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn observe_query(statement: &str, params: &[&(dyn ToSql + Sync)], started_at: Instant) {
+    watchdog::observe(TaskKind::Query, started_at.elapsed(), || {
+        format!("{} {:?}", statement.split_whitespace().collect::<Vec<_>>().join(" "), params)
+    });
+}
+
+// The transaction IDs and output indexes as arrays for UNNEST
+fn outpoint_arrays(outpoints: &[OutPoint]) -> (Vec<String>, Vec<i64>) {
+    outpoints.iter().map(|outpoint| (outpoint.txid.clone(), i64::from(outpoint.vout))).unzip()
+}
+
+// $1, $2, ... $count
+fn placeholders(count: usize) -> String {
+    (1..=count).map(|index| format!("${}", index)).collect::<Vec<String>>().join(", ")
+}
+
+fn difficulty_to_int8(difficulty: u64) -> Result<i64, BlockchainError> {
+    i64::try_from(difficulty).map_err(|_| BlockchainError::Error(format!("difficulty out of range: {}", difficulty)))
+}
@@ -0,0 +1,740 @@
+// JSON-RPC 2.0 server for block queries (`--rpc ADDR`): clients connect via TCP and send one request
+// per line, answered with one response per line. A line holding an array of requests is a batch,
+// answered with an array of responses, so explorers and indexers fetch many blocks in one round trip
+// instead of one request per block. `getblocks` takes a list of hashes or heights and answers with
+// the blocks in the same order, null for unknown ones, `getblockrange` the main chain blocks between
+// two heights. Like stratum.rs the connections only pass the requests on to the main task, which
+// answers them from the storage of our default chain.
+//
+// The hash of our latest block tags the state of the chain, like an HTTP ETag. `getlatestblock` and
+// `getblockrange` take it as `if_none_match` and answer with the NOT_MODIFIED error as long as it is
+// still our tip, so polling clients do not download unchanged blocks again. With `wait` (seconds, at
+// most MAX_WAIT_SECS) a single request is held by its connection until our head changes and answered
+// then, or with NOT_MODIFIED once the time is up (long-polling).
+//
+// The same requests are answered on a local socket, for scripts driving the node (`--ipc PATH`, see
+// ipc.rs).
+//
+// `rpc.discover` returns an OpenRPC document (the JSON-RPC counterpart of OpenAPI) generated from
+// METHODS, from which clients in other languages can be generated. `rpc docs` prints it.
+//
+// `getnodeinfo` answers with our node info (see nodeinfo.rs), for the inventory of a fleet, and
+// `gethealth` with the same plus our tip, for health checks: it is the counterpart of an HTTP
+// `/healthz` endpoint and answers with an error if our storage does not. `getcapabilities` with what our binary supports (see capabilities.rs), to check a fleet's
+// compatibility before an upgrade. `getslowtasks` with the slow queries, validations and event
+// handling the watchdog counted (see watchdog.rs), `getblocklatency` with the arrival and
+// acknowledgement latencies of blocks (see latency.rs), `getmempoolinfo` with the occupancy of our
+// mining queue (see mining.rs) and `getorphaninfo` with the one of our orphan pool (see orphans.rs),
+// for monitoring.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::capabilities;
+use crate::head::HeadEvent;
+use crate::latency;
+use crate::mining::MiningQueue;
+use crate::nodeinfo::{Health, NodeInfo};
+use crate::orphans::OrphanPool;
+use crate::storage::Storage;
+use crate::types::{EventType, Height};
+use crate::watchdog;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{self, Instant};
+use tracing::{debug, info, warn};
+
+// Connection names start with this, the rest is the remote address
+pub const RPC_PREFIX: &str = "rpc/";
+// Requests in a single batch
+pub const MAX_BATCH: usize = 100;
+// Blocks returned by a single getblocks or getblockrange request
+pub const MAX_BLOCKS: u64 = 500;
+// Longest wait of a long-polling request
+pub const MAX_WAIT_SECS: u64 = 60;
+// Longest request line we read, a longer one is answered with PARSE_ERROR and closes the connection
+pub const MAX_REQUEST_LENGTH: usize = 1 << 20;
+// Longest reply line clients read (see ipc.rs), replies carry up to MAX_BLOCKS blocks
+pub const MAX_REPLY_LENGTH: usize = 64 << 20;
+
+// Error codes of the JSON-RPC spec, BLOCK_NOT_FOUND is one of the codes left to servers
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const BLOCK_NOT_FOUND: i64 = -32001;
+// The tip given as if_none_match is still our latest block
+pub const NOT_MODIFIED: i64 = -32002;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default)]
+    pub id: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+// Either result or error is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcCall {
+    Batch(Vec<RpcRequest>),
+    Single(RpcRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RpcReply {
+    Batch(Vec<RpcResponse>),
+    Single(RpcResponse),
+}
+
+// A method answered by the server, as documented by the OpenRPC document
+pub struct RpcMethod {
+    pub name: &'static str,
+    pub summary: &'static str,
+    // The params it takes by name, see param_schema, the required ones first
+    pub params: &'static [&'static str],
+    pub required: usize,
+    // Schema of the result in the components of the document
+    pub result: &'static str,
+}
+
+pub const METHODS: &[RpcMethod] = &[
+    RpcMethod {
+        name: "getblock",
+        summary: "The block with the hash, or the main chain block at the height",
+        params: &["hash", "height"],
+        required: 0,
+        result: "Block",
+    },
+    RpcMethod {
+        name: "getblocks",
+        summary: "The blocks with the hashes or the main chain blocks at the heights, in their order, null for unknown ones",
+        params: &["hashes", "heights"],
+        required: 0,
+        result: "Blocks",
+    },
+    RpcMethod {
+        name: "getblockrange",
+        summary: "The main chain blocks from one height to the other, both included",
+        params: &["from", "to", "if_none_match", "wait"],
+        required: 2,
+        result: "Blocks",
+    },
+    RpcMethod {
+        name: "getlatestblock",
+        summary: "The latest block of the main chain",
+        params: &["if_none_match", "wait"],
+        required: 0,
+        result: "Block",
+    },
+    RpcMethod {
+        name: "getreceipt",
+        summary: "The receipt of the block's payload, null for payloads changing no state",
+        params: &["hash"],
+        required: 1,
+        result: "Receipt",
+    },
+    RpcMethod {
+        name: "getnodeinfo",
+        summary: "Our peer id, listen addresses, genesis hash, network, storage backend and build",
+        params: &[],
+        required: 0,
+        result: "NodeInfo",
+    },
+    RpcMethod {
+        name: "gethealth",
+        summary: "Health check, the counterpart of an HTTP /healthz endpoint: ok, our latest block and our node info",
+        params: &[],
+        required: 0,
+        result: "Health",
+    },
+    RpcMethod {
+        name: "getcapabilities",
+        summary: "The build, storage backends, consensus engines, hash algorithms, transports and protocol versions of our binary",
+        params: &[],
+        required: 0,
+        result: "Capabilities",
+    },
+    RpcMethod {
+        name: "getslowtasks",
+        summary: "Count, total and slowest duration of the queries, validations and event handling over the watchdog thresholds",
+        params: &[],
+        required: 0,
+        result: "WatchdogReport",
+    },
+    RpcMethod {
+        name: "getblocklatency",
+        summary: "Percentiles of the arrival latency of received blocks and of the time until the first peer acknowledged our mined blocks",
+        params: &[],
+        required: 0,
+        result: "LatencyReport",
+    },
+    RpcMethod {
+        name: "getmempoolinfo",
+        summary: "The number and summed size of the pending mining jobs, their caps and the jobs evicted to make room",
+        params: &[],
+        required: 0,
+        result: "MempoolOccupancy",
+    },
+    RpcMethod {
+        name: "getorphaninfo",
+        summary: "The number and summed size of the blocks waiting for their parent, their caps and the orphans evicted to make room",
+        params: &[],
+        required: 0,
+        result: "OrphanOccupancy",
+    },
+    RpcMethod {
+        name: "rpc.discover",
+        summary: "This OpenRPC document",
+        params: &[],
+        required: 0,
+        result: "OpenRPC",
+    },
+];
+
+// Parameters of the block methods, each method uses its own subset
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BlockParams {
+    hash: Option<String>,
+    height: Option<Height>,
+    hashes: Vec<String>,
+    heights: Vec<Height>,
+    from: Option<Height>,
+    to: Option<Height>,
+    // Hash of the latest block the client knows
+    if_none_match: Option<String>,
+    // Seconds to wait for a new tip if the latest block is still if_none_match
+    wait: Option<u64>,
+}
+
+// A long-polling request of a connection, resent to the main task whenever our head changes
+struct Poll {
+    call: RpcCall,
+    deadline: Instant,
+    // Number of head changes the connection had seen when the call was last sent
+    sent_at: u64,
+    // The NOT_MODIFIED reply, sent once the deadline passed
+    reply: Option<RpcReply>,
+}
+
+impl RpcResponse {
+    pub fn result(id: Value, result: Value) -> Self {
+        Self { jsonrpc: "2.0".to_owned(), result: Some(result), error: None, id }
+    }
+
+    pub fn error(id: Value, code: i64, message: String) -> Self {
+        Self { jsonrpc: "2.0".to_owned(), result: None, error: Some(RpcError { code, message }), id }
+    }
+}
+
+// A line that is no JSON, or no request or batch of requests, is answered right away
+pub fn parse_call(line: &str) -> Result<RpcCall, RpcReply> {
+    let value = serde_json::from_str::<Value>(line).map_err(|err| RpcReply::Single(RpcResponse::error(Value::Null, PARSE_ERROR, err.to_string())))?;
+    serde_json::from_value::<RpcCall>(value).map_err(|_| RpcReply::Single(RpcResponse::error(Value::Null, INVALID_REQUEST, "invalid request".to_owned())))
+}
+
+// Answers the requests of a call in order
+pub async fn answer(storage: &mut Storage, node: &NodeInfo, queue: &MiningQueue, orphans: &OrphanPool, call: RpcCall) -> RpcReply {
+    match call {
+        RpcCall::Single(request) => RpcReply::Single(respond(storage, node, queue, orphans, request).await),
+        RpcCall::Batch(requests) if requests.is_empty() || requests.len() > MAX_BATCH => RpcReply::Single(RpcResponse::error(
+            Value::Null,
+            INVALID_REQUEST,
+            format!("a batch holds 1 to {} requests", MAX_BATCH),
+        )),
+        RpcCall::Batch(requests) => {
+            let mut responses = vec![];
+            for request in requests {
+                responses.push(respond(storage, node, queue, orphans, request).await);
+            }
+            RpcReply::Batch(responses)
+        }
+    }
+}
+
+async fn respond(storage: &mut Storage, node: &NodeInfo, queue: &MiningQueue, orphans: &OrphanPool, request: RpcRequest) -> RpcResponse {
+    if request.jsonrpc != "2.0" {
+        return RpcResponse::error(request.id, INVALID_REQUEST, "jsonrpc has to be \"2.0\"".to_owned());
+    }
+    let params = match block_params(&request.params) {
+        Ok(params) => params,
+        Err(err) => return RpcResponse::error(request.id, INVALID_PARAMS, err),
+    };
+    if params.wait.is_some_and(|wait| wait > MAX_WAIT_SECS) {
+        return RpcResponse::error(request.id, INVALID_PARAMS, format!("wait is at most {} seconds", MAX_WAIT_SECS));
+    }
+    let conditional = matches!(request.method.as_str(), "getlatestblock" | "getblockrange");
+    if let (true, Some(tag)) = (conditional, &params.if_none_match) {
+        match Chain::get_latest_block(storage).await {
+            Ok(latest) if &latest.hash == tag => return RpcResponse::error(request.id, NOT_MODIFIED, "not modified".to_owned()),
+            Ok(_) => {}
+            Err(err) => return RpcResponse::error(request.id, INTERNAL_ERROR, err.to_string()),
+        }
+    }
+    let result = match request.method.as_str() {
+        "getblock" => get_block(storage, &params).await,
+        "getblocks" => get_blocks(storage, &params).await,
+        "getblockrange" => get_block_range(storage, &params).await,
+        "getlatestblock" => Chain::get_latest_block(storage).await.map(to_value),
+        "getreceipt" => match &params.hash {
+            Some(hash) => Chain::get_receipt(storage, hash).await.map(to_value),
+            None => return RpcResponse::error(request.id, INVALID_PARAMS, "getreceipt requires a hash".to_owned()),
+        },
+        "getnodeinfo" => Ok(to_value(node)),
+        "gethealth" => Chain::get_latest_block(storage).await.map(|latest| to_value(Health::new(node, &latest))),
+        "getcapabilities" => Ok(to_value(capabilities::capabilities())),
+        "getslowtasks" => Ok(to_value(watchdog::report())),
+        "getblocklatency" => Ok(to_value(latency::report())),
+        "getmempoolinfo" => Ok(to_value(queue.occupancy())),
+        "getorphaninfo" => Ok(to_value(orphans.occupancy())),
+        "rpc.discover" => Ok(openrpc()),
+        method => return RpcResponse::error(request.id, METHOD_NOT_FOUND, format!("unknown method {}", method)),
+    };
+    match result {
+        Ok(result) => RpcResponse::result(request.id, result),
+        Err(BlockchainError::BlockNotFound(key)) => RpcResponse::error(request.id, BLOCK_NOT_FOUND, format!("block not found: {}", key)),
+        Err(BlockchainError::Error(message)) => RpcResponse::error(request.id, INVALID_PARAMS, message),
+        Err(err) => RpcResponse::error(request.id, INTERNAL_ERROR, err.to_string()),
+    }
+}
+
+// OpenRPC document describing METHODS
+pub fn openrpc() -> Value {
+    let methods: Vec<Value> = METHODS
+        .iter()
+        .map(|method| {
+            let params: Vec<Value> = method
+                .params
+                .iter()
+                .enumerate()
+                .map(|(i, name)| json!({"name": name, "required": i < method.required, "schema": param_schema(name)}))
+                .collect();
+            json!({
+                "name": method.name,
+                "summary": method.summary,
+                "paramStructure": "by-name",
+                "params": params,
+                "result": {"name": method.result, "schema": {"$ref": format!("#/components/schemas/{}", method.result)}},
+            })
+        })
+        .collect();
+    let height = json!({"type": "integer", "minimum": 0});
+    let hash = json!({"type": "string"});
+    json!({
+        "openrpc": "1.2.6",
+        "info": {"title": "rust-blockchain", "version": env!("CARGO_PKG_VERSION")},
+        "methods": methods,
+        "components": {
+            "schemas": {
+                "Block": {
+                    "type": "object",
+                    "properties": {
+                        "hash": hash, "id": height, "prev_hash": hash, "timestamp": {"type": "integer"},
+                        "nonce": {"type": "integer"}, "data": {"type": "string"}, "version": {"type": "integer"},
+                        "miner": {"type": "string"}, "extra_nonce": {"type": "integer"}, "difficulty": {"type": "integer"},
+                        "fee": {"type": "integer"}, "state_root": hash,
+                        "coinbase": {"type": "object"},
+                    },
+                },
+                "Blocks": {"type": "array", "items": {"oneOf": [{"$ref": "#/components/schemas/Block"}, {"type": "null"}]}},
+                "Receipt": {
+                    "oneOf": [
+                        {
+                            "type": "object",
+                            "properties": {
+                                "block_hash": hash, "height": height, "outcome": {}, "gas_used": {"type": "integer"},
+                                "events": {"type": "array"},
+                            },
+                        },
+                        {"type": "null"},
+                    ],
+                },
+                "NodeInfo": {
+                    "type": "object",
+                    "properties": {
+                        "peer_id": {"type": "string"}, "listen_addresses": {"type": "array", "items": {"type": "string"}},
+                        "genesis_hash": hash, "network": {"type": "string"}, "storage": {"type": "string"},
+                        "version": {"type": "string"}, "commit": {"type": "string"},
+                    },
+                },
+                "Health": {
+                    "allOf": [
+                        {
+                            "type": "object",
+                            "properties": {"status": {"type": "string", "enum": ["ok"]}, "height": height, "latest_hash": hash},
+                        },
+                        {"$ref": "#/components/schemas/NodeInfo"},
+                    ],
+                },
+                "Capabilities": {
+                    "type": "object",
+                    "properties": {
+                        "build": {
+                            "type": "object",
+                            "properties": {
+                                "version": {"type": "string"}, "commit": {"type": "string"}, "target": {"type": "string"},
+                                "profile": {"type": "string"}, "features": {"type": "array", "items": {"type": "string"}},
+                            },
+                        },
+                        "storage_backends": {"type": "array", "items": {"type": "string"}},
+                        "consensus_engines": {"type": "array", "items": {"type": "string"}},
+                        "difficulty_algorithms": {"type": "array", "items": {"type": "string"}},
+                        "consensus_upgrades": {
+                            "type": "array",
+                            "items": {"type": "object", "properties": {"name": {"type": "string"}, "height": height}},
+                        },
+                        "hash_algorithms": {"type": "array", "items": {"type": "string"}},
+                        "signature_schemes": {"type": "array", "items": {"type": "string"}},
+                        "hash_backends": {"type": "array", "items": {"type": "string"}},
+                        "transports": {"type": "array", "items": {"type": "string"}},
+                        "protocols": {"type": "object"},
+                    },
+                },
+                "SlowStats": {
+                    "type": "object",
+                    "properties": {
+                        "count": {"type": "integer"}, "total_ms": {"type": "integer"}, "slowest_ms": {"type": "integer"},
+                        "slowest_subject": {"type": "string"},
+                    },
+                },
+                "WatchdogReport": {
+                    "type": "object",
+                    "properties": {
+                        "query": {"$ref": "#/components/schemas/SlowStats"},
+                        "validation": {"$ref": "#/components/schemas/SlowStats"},
+                        "gossip": {"$ref": "#/components/schemas/SlowStats"},
+                    },
+                },
+                "LatencyStats": {
+                    "type": "object",
+                    "properties": {
+                        "min": {"type": "integer"}, "avg": {"type": "number"}, "p50": {"type": "integer"},
+                        "p95": {"type": "integer"}, "max": {"type": "integer"},
+                    },
+                },
+                "LatencyReport": {
+                    "type": "object",
+                    "properties": {
+                        "arrivals": {"type": "integer"},
+                        "arrival": {"oneOf": [{"$ref": "#/components/schemas/LatencyStats"}, {"type": "null"}]},
+                        "acknowledged": {"type": "integer"},
+                        "first_ack": {"oneOf": [{"$ref": "#/components/schemas/LatencyStats"}, {"type": "null"}]},
+                    },
+                },
+                "MempoolOccupancy": {
+                    "type": "object",
+                    "properties": {
+                        "jobs": {"type": "integer"}, "max_jobs": {"type": ["integer", "null"]}, "bytes": {"type": "integer"},
+                        "max_bytes": {"type": ["integer", "null"]}, "evicted": {"type": "integer"},
+                    },
+                },
+                "OrphanOccupancy": {
+                    "type": "object",
+                    "properties": {
+                        "blocks": {"type": "integer"}, "max_blocks": {"type": "integer"}, "bytes": {"type": "integer"},
+                        "max_bytes": {"type": "integer"}, "evicted": {"type": "integer"},
+                    },
+                },
+                "OpenRPC": {"type": "object"},
+            },
+            "errors": {
+                "BlockNotFound": {"code": BLOCK_NOT_FOUND, "message": "block not found"},
+                "NotModified": {"code": NOT_MODIFIED, "message": "not modified"},
+            },
+        },
+    })
+}
+
+fn param_schema(name: &str) -> Value {
+    match name {
+        "hash" | "if_none_match" => json!({"type": "string"}),
+        "height" | "from" | "to" => json!({"type": "integer", "minimum": 0}),
+        "hashes" => json!({"type": "array", "items": {"type": "string"}, "maxItems": MAX_BLOCKS}),
+        "heights" => json!({"type": "array", "items": {"type": "integer", "minimum": 0}, "maxItems": MAX_BLOCKS}),
+        "wait" => json!({"type": "integer", "minimum": 0, "maximum": MAX_WAIT_SECS}),
+        _ => json!({}),
+    }
+}
+
+fn block_params(params: &Value) -> Result<BlockParams, String> {
+    match params {
+        Value::Null => Ok(BlockParams::default()),
+        params => serde_json::from_value::<BlockParams>(params.clone()).map_err(|err| err.to_string()),
+    }
+}
+
+// The id and wait of a single conditional request
+fn long_poll(call: &RpcCall) -> Option<(String, Duration)> {
+    match call {
+        RpcCall::Single(request) => match block_params(&request.params) {
+            Ok(BlockParams { if_none_match: Some(_), wait: Some(wait), .. }) if wait <= MAX_WAIT_SECS => {
+                Some((request.id.to_string(), Duration::from_secs(wait)))
+            }
+            _ => None,
+        },
+        RpcCall::Batch(_) => None,
+    }
+}
+
+// The id of a NOT_MODIFIED reply to a single request
+fn not_modified(reply: &RpcReply) -> Option<String> {
+    match reply {
+        RpcReply::Single(RpcResponse { error: Some(RpcError { code: NOT_MODIFIED, .. }), id, .. }) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).expect("can jsonify rpc result")
+}
+
+async fn get_block(storage: &mut Storage, params: &BlockParams) -> Result<Value, BlockchainError> {
+    match (&params.hash, params.height) {
+        (Some(hash), None) => Chain::get_block(storage, hash).await.map(to_value),
+        (None, Some(height)) => Chain::get_block_by_id(storage, height).await.map(to_value),
+        _ => Err(BlockchainError::Error("getblock requires either a hash or a height".to_owned())),
+    }
+}
+
+// The blocks in the order of the keys, null for unknown ones
+async fn get_blocks(storage: &mut Storage, params: &BlockParams) -> Result<Value, BlockchainError> {
+    if params.hashes.len() + params.heights.len() > MAX_BLOCKS as usize {
+        return Err(BlockchainError::Error(format!("getblocks returns at most {} blocks", MAX_BLOCKS)));
+    }
+    let mut blocks: Vec<Option<Block>> = vec![];
+    match (params.hashes.is_empty(), params.heights.is_empty()) {
+        (false, true) => {
+            for hash in params.hashes.iter() {
+                blocks.push(found(storage.get_block(hash).await)?);
+            }
+        }
+        (true, false) => {
+            for height in params.heights.iter() {
+                blocks.push(found(storage.get_block_by_id(*height).await)?);
+            }
+        }
+        _ => return Err(BlockchainError::Error("getblocks requires either hashes or heights".to_owned())),
+    }
+    Ok(to_value(blocks))
+}
+
+fn found(res: Result<Block, BlockchainError>) -> Result<Option<Block>, BlockchainError> {
+    match res {
+        Ok(block) => Ok(Some(block)),
+        Err(BlockchainError::BlockNotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// The main chain blocks from one height to the other, both included
+async fn get_block_range(storage: &mut Storage, params: &BlockParams) -> Result<Value, BlockchainError> {
+    let (from, to) = match (params.from, params.to) {
+        (Some(from), Some(to)) if from <= to => (from, to),
+        _ => return Err(BlockchainError::Error("getblockrange requires from and to, from not above to".to_owned())),
+    };
+    if to.0 - from.0 >= MAX_BLOCKS {
+        return Err(BlockchainError::Error(format!("getblockrange returns at most {} blocks", MAX_BLOCKS)));
+    }
+    storage.get_blocks_in_range(from, to).await.map(to_value)
+}
+
+// Accepts client connections and passes their calls on to the main task, which sends the replies
+// back via rx_rcv (as SendRpcReply events). The changes of our head sent to head_sender end the
+// long-polls of the connections.
+pub async fn init_rpc(
+    listener: TcpListener,
+    mut rx_rcv: mpsc::UnboundedReceiver<EventType>,
+    main_sender: mpsc::UnboundedSender<EventType>,
+    head_sender: broadcast::Sender<HeadEvent>,
+) -> Result<(), std::io::Error> {
+    println!("RPC listening on {}", listener.local_addr()?);
+    let mut connections: HashMap<String, mpsc::UnboundedSender<RpcReply>> = HashMap::new();
+    let (closed_sender, mut closed_rcv) = mpsc::unbounded_channel::<String>();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, addr) = accepted?;
+                let name = format!("{}{}", RPC_PREFIX, addr);
+                info!("RPC client {} connected", name);
+                let (sender, rcv) = mpsc::unbounded_channel();
+                connections.insert(name.clone(), sender);
+                let (reader, writer) = stream.into_split();
+                tokio::spawn(handle_connection(reader, writer, name, rcv, head_sender.subscribe(), main_sender.clone(), closed_sender.clone()));
+            },
+            Some(name) = closed_rcv.recv() => {
+                info!("RPC client {} disconnected", name);
+                connections.remove(&name);
+            },
+            event = rx_rcv.recv() => match event {
+                Some(EventType::SendRpcReply{receiver, reply}) => {
+                    match connections.get(&receiver) {
+                        Some(connection) => {
+                            let _ = connection.send(reply);
+                        }
+                        None => debug!("RPC client {} is not connected", receiver),
+                    }
+                },
+                Some(_) => {},
+                None => {
+                    debug!("rpc channel closed.");
+                    return Ok(());
+                },
+            },
+        }
+    }
+}
+
+// Serves the requests of a client, over TCP or the local socket (see ipc.rs)
+pub async fn handle_connection<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: R,
+    mut writer: W,
+    name: String,
+    mut outgoing: mpsc::UnboundedReceiver<RpcReply>,
+    mut head_events: broadcast::Receiver<HeadEvent>,
+    main_sender: mpsc::UnboundedSender<EventType>,
+    closed_sender: mpsc::UnboundedSender<String>,
+) {
+    let mut reader = BufReader::new(reader);
+    let mut buffer = vec![];
+    // Long-polls by request id (as JSON)
+    let mut polls: HashMap<String, Poll> = HashMap::new();
+    let mut head_changes = 0;
+    loop {
+        let next_deadline = polls.values().filter(|poll| poll.reply.is_some()).map(|poll| poll.deadline).min();
+        let expired = async {
+            match next_deadline {
+                Some(deadline) => time::sleep_until(deadline).await,
+                None => futures::future::pending::<()>().await,
+            }
+        };
+        let replies = tokio::select! {
+            line = read_line(&mut reader, &mut buffer, MAX_REQUEST_LENGTH) => match line {
+                Ok(Some(line)) => match parse_call(&line) {
+                    Ok(call) => {
+                        if let Some((id, wait)) = long_poll(&call) {
+                            polls.insert(id, Poll{call: call.clone(), deadline: Instant::now() + wait, sent_at: head_changes, reply: None});
+                        }
+                        let _ = main_sender.send(EventType::ReceivedRpcCall{sender: name.clone(), call});
+                        continue;
+                    }
+                    Err(reply) => vec![reply],
+                },
+                Ok(None) => break,
+                // Too long or no UTF-8, we can not tell where the next request starts
+                Err(err) => {
+                    if err.kind() == io::ErrorKind::InvalidData {
+                        let _ = write_reply(&mut writer, &RpcReply::Single(RpcResponse::error(Value::Null, PARSE_ERROR, err.to_string()))).await;
+                    }
+                    break;
+                }
+            },
+            reply = outgoing.recv() => match reply {
+                Some(reply) => match not_modified(&reply).and_then(|id| polls.get_mut(&id)) {
+                    // Still waiting: held until the head changes, asked again if it changed in the meantime
+                    Some(poll) if poll.deadline > Instant::now() => {
+                        if poll.sent_at == head_changes {
+                            poll.reply = Some(reply);
+                        } else {
+                            poll.sent_at = head_changes;
+                            let _ = main_sender.send(EventType::ReceivedRpcCall{sender: name.clone(), call: poll.call.clone()});
+                        }
+                        continue;
+                    }
+                    _ => {
+                        if let RpcReply::Single(response) = &reply {
+                            polls.remove(&response.id.to_string());
+                        }
+                        vec![reply]
+                    }
+                },
+                // The server was shut down
+                None => break,
+            },
+            event = head_events.recv() => {
+                match event {
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => warn!("RPC client {} skipped {} head events", name, skipped),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                head_changes += 1;
+                for poll in polls.values_mut().filter(|poll| poll.reply.is_some()) {
+                    poll.reply = None;
+                    poll.sent_at = head_changes;
+                    let _ = main_sender.send(EventType::ReceivedRpcCall{sender: name.clone(), call: poll.call.clone()});
+                }
+                continue;
+            },
+            _ = expired => {
+                let now = Instant::now();
+                let ids: Vec<String> = polls.iter().filter(|(_, poll)| poll.reply.is_some() && poll.deadline <= now).map(|(id, _)| id.clone()).collect();
+                ids.iter().filter_map(|id| polls.remove(id).and_then(|poll| poll.reply)).collect()
+            },
+        };
+        if write_replies(&mut writer, &replies).await.is_err() {
+            break;
+        }
+    }
+    let _ = closed_sender.send(name);
+}
+
+// Reads the next line without its line break, None once the stream ended. A line longer than max
+// bytes is an InvalidData error, so a peer can not make us buffer a line that never ends. What was
+// read of a line is kept in buffer until the line is complete, so like AsyncBufReadExt::lines it can
+// be awaited in select!.
+pub async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, buffer: &mut Vec<u8>, max: usize) -> io::Result<Option<String>> {
+    let limit = (max + 1).saturating_sub(buffer.len());
+    (&mut *reader).take(limit as u64).read_until(b'\n', buffer).await?;
+    let complete = buffer.last() == Some(&b'\n');
+    if !complete && buffer.len() > max {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line longer than {} bytes", max)));
+    }
+    // The stream ended, with the last line unless it ended with a line break
+    if !complete && buffer.is_empty() {
+        return Ok(None);
+    }
+    let mut line = std::mem::take(buffer);
+    if complete {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    String::from_utf8(line).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+async fn write_replies<W: AsyncWriteExt + Unpin>(writer: &mut W, replies: &[RpcReply]) -> io::Result<()> {
+    for reply in replies {
+        write_reply(writer, reply).await?;
+    }
+    Ok(())
+}
+
+async fn write_reply<W: AsyncWriteExt + Unpin>(writer: &mut W, reply: &RpcReply) -> io::Result<()> {
+    let mut line = serde_json::to_string(reply).expect("can jsonify rpc reply");
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
@@ -0,0 +1,196 @@
+// Running the node as a daemon: `node install-service [systemd|launchd]` prints the definition of a
+// service starting the node with the options it is running with, to be installed with the service
+// manager. Under systemd (Type=notify) the node reports READY=1 once its storage is open and the p2p
+// layer and the servers listen, its status whenever its tip changes and STOPPING=1 when it shuts
+// down. SIGTERM (sent by systemd and launchd) and Ctrl-C shut the node down like `exit`: queued
+// blocks are written and the snapshots saved. Without a terminal (stdin is closed) the node keeps
+// running without reading commands.
+// There is no service manager for Windows yet: a Windows service needs a service control handler
+// turning Stop and Shutdown into the same shutdown as SIGTERM, until then the node is run by a
+// service wrapper there.
+use log::warn;
+use std::env;
+use std::path::Path;
+
+pub const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+pub const SERVICE_NAME: &str = "rust-blockchain";
+// Label of the launchd job
+pub const LAUNCHD_LABEL: &str = "org.rust-blockchain.node";
+pub const SERVICE_MANAGERS: [&str; 2] = ["systemd", "launchd"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceManager {
+    Systemd,
+    Launchd,
+}
+
+impl ServiceManager {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "systemd" => Some(Self::Systemd),
+            "launchd" => Some(Self::Launchd),
+            _ => None,
+        }
+    }
+
+    // The service manager of the platform we were built for, None on Windows
+    pub fn current() -> Option<Self> {
+        if cfg!(target_os = "macos") {
+            Some(Self::Launchd)
+        } else if cfg!(windows) {
+            None
+        } else {
+            Some(Self::Systemd)
+        }
+    }
+
+    // Service definition running the executable with the arguments in the working directory
+    pub fn definition(self, exe: &Path, args: &[String], working_dir: &Path) -> String {
+        match self {
+            Self::Systemd => systemd_unit(exe, args, working_dir),
+            Self::Launchd => launchd_plist(exe, args, working_dir),
+        }
+    }
+
+    // Where the definition is installed and how the service is started
+    pub fn instructions(self) -> &'static str {
+        match self {
+            Self::Systemd => "save as /etc/systemd/system/rust-blockchain.service, then `systemctl daemon-reload && systemctl enable --now rust-blockchain`",
+            Self::Launchd => "save as ~/Library/LaunchAgents/org.rust-blockchain.node.plist, then `launchctl load -w ~/Library/LaunchAgents/org.rust-blockchain.node.plist`",
+        }
+    }
+}
+
+fn systemd_unit(exe: &Path, args: &[String], working_dir: &Path) -> String {
+    let command = std::iter::once(exe.display().to_string()).chain(args.iter().cloned()).map(|arg| systemd_quote(&arg)).collect::<Vec<_>>();
+    format!(
+        "[Unit]
+Description=rust-blockchain node
+Wants=network-online.target
+After=network-online.target postgresql.service
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={}
+WorkingDirectory={}
+# The passphrase of --keys and --data-dir, e.g. BLOCKCHAIN_KEY_PASSPHRASE=...
+EnvironmentFile=-/etc/default/{}
+Restart=on-failure
+TimeoutStopSec=60
+
+[Install]
+WantedBy=multi-user.target
+",
+        command.join(" "),
+        systemd_quote(&working_dir.display().to_string()),
+        SERVICE_NAME,
+    )
+}
+
+// Arguments with whitespace, quotes or specifiers are quoted, % is escaped in any case
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    match arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '\'' || c == ';' || c == '$') {
+        true => format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "$$")),
+        false => arg,
+    }
+}
+
+fn launchd_plist(exe: &Path, args: &[String], working_dir: &Path) -> String {
+    let arguments = std::iter::once(exe.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+        .collect::<String>();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
+<plist version=\"1.0\">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>WorkingDirectory</key>
+    <string>{}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ExitTimeOut</key>
+    <integer>60</integer>
+</dict>
+</plist>
+",
+        LAUNCHD_LABEL,
+        arguments,
+        xml_escape(&working_dir.display().to_string()),
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Tells systemd about our state (e.g. READY=1) if it started us with Type=notify, does nothing
+// otherwise
+pub fn notify(state: &str) {
+    if let Ok(socket) = env::var(NOTIFY_SOCKET_ENV) {
+        if let Err(err) = send_notification(&socket, state) {
+            warn!("Can not notify the service manager: {}", err);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn send_notification(socket: &str, state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+    let datagram = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        // Abstract socket
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn send_notification(_socket: &str, _state: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+// SIGTERM of the service manager and Ctrl-C
+pub struct ShutdownSignals {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ShutdownSignals {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            terminate: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?,
+        })
+    }
+
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = self.terminate.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
@@ -0,0 +1,121 @@
+// Nonce search for gpu.rs. Every work item hashes the block header with the nonce base + its global
+// id: the host passes the SHA-256 state after the full 64 byte blocks of the header before the nonce
+// (midstate), the rest of it (tail) and the header after the nonce (suffix). The first 8 bytes of
+// the digest are compared big endian against the threshold, the first match is written to found.
+
+#define ROTR(x, n) rotate((uint)(x), (uint)(32 - (n)))
+
+__constant uint K[64] = {
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+};
+
+typedef struct {
+    uint state[8];
+    uchar block[64];
+    uint used;
+    ulong length;
+} Sha256;
+
+void compress(uint *state, const uchar *block) {
+    uint w[64];
+    for (int i = 0; i < 16; i++) {
+        w[i] = ((uint)block[4 * i] << 24) | ((uint)block[4 * i + 1] << 16) | ((uint)block[4 * i + 2] << 8)
+            | (uint)block[4 * i + 3];
+    }
+    for (int i = 16; i < 64; i++) {
+        uint s0 = ROTR(w[i - 15], 7) ^ ROTR(w[i - 15], 18) ^ (w[i - 15] >> 3);
+        uint s1 = ROTR(w[i - 2], 17) ^ ROTR(w[i - 2], 19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16] + s0 + w[i - 7] + s1;
+    }
+
+    uint a = state[0], b = state[1], c = state[2], d = state[3];
+    uint e = state[4], f = state[5], g = state[6], h = state[7];
+    for (int i = 0; i < 64; i++) {
+        uint t1 = h + (ROTR(e, 6) ^ ROTR(e, 11) ^ ROTR(e, 25)) + ((e & f) ^ (~e & g)) + K[i] + w[i];
+        uint t2 = (ROTR(a, 2) ^ ROTR(a, 13) ^ ROTR(a, 22)) + ((a & b) ^ (a & c) ^ (b & c));
+        h = g;
+        g = f;
+        f = e;
+        e = d + t1;
+        d = c;
+        c = b;
+        b = a;
+        a = t1 + t2;
+    }
+    state[0] += a;
+    state[1] += b;
+    state[2] += c;
+    state[3] += d;
+    state[4] += e;
+    state[5] += f;
+    state[6] += g;
+    state[7] += h;
+}
+
+void update(Sha256 *sha, uchar byte) {
+    sha->block[sha->used++] = byte;
+    sha->length++;
+    if (sha->used == 64) {
+        compress(sha->state, sha->block);
+        sha->used = 0;
+    }
+}
+
+__kernel void search(
+    __global const uint *midstate,
+    __global const uchar *tail,
+    uint tail_len,
+    __global const uchar *suffix,
+    uint suffix_len,
+    ulong hashed_len,
+    ulong base,
+    ulong threshold,
+    __global volatile uint *found
+) {
+    uint offset = get_global_id(0);
+    ulong nonce = base + offset;
+
+    Sha256 sha;
+    for (int i = 0; i < 8; i++) {
+        sha.state[i] = midstate[i];
+    }
+    sha.used = 0;
+    sha.length = hashed_len;
+    for (uint i = 0; i < tail_len; i++) {
+        update(&sha, tail[i]);
+    }
+    // The nonce is hashed as decimal JSON number
+    uchar digits[20];
+    int count = 0;
+    do {
+        digits[count++] = '0' + nonce % 10;
+        nonce /= 10;
+    } while (nonce > 0);
+    while (count > 0) {
+        update(&sha, digits[--count]);
+    }
+    for (uint i = 0; i < suffix_len; i++) {
+        update(&sha, suffix[i]);
+    }
+
+    ulong bits = sha.length * 8;
+    update(&sha, 0x80);
+    while (sha.used != 56) {
+        update(&sha, 0);
+    }
+    for (int i = 7; i >= 0; i--) {
+        update(&sha, (uchar)(bits >> (8 * i)));
+    }
+
+    ulong head = ((ulong)sha.state[0] << 32) | sha.state[1];
+    if (head <= threshold && atomic_cmpxchg(&found[0], 0, 1) == 0) {
+        found[1] = offset;
+    }
+}
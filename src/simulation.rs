@@ -0,0 +1,321 @@
+// In-process network of nodes for tests. Nodes are fully connected and exchange the same events
+// the p2p layer would deliver, with virtual time advancing one tick per step. All messages pass
+// through the ChaosNetwork, which can drop, delay, duplicate and reorder them to validate the sync
+// and orphan handling under adverse conditions. Not used by the node itself.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::node::Node;
+use crate::propagation::{BlockAnnouncement, Propagation};
+use crate::storage::{MemoryStorage, Storage};
+use crate::types::EventType;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+// Virtual time that passes per simulation step
+pub const TICK: Duration = Duration::from_secs(1);
+
+// Probabilities (0.0 - 1.0) with which the chaos network misbehaves
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChaosConfig {
+    pub drop_probability: f64,
+    pub duplicate_probability: f64,
+    pub delay_probability: f64,
+    // Delayed messages arrive up to this many ticks late
+    pub max_delay: u64,
+    // Probability that the messages delivered in the same tick are shuffled
+    pub reorder_probability: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub sent: usize,
+    pub dropped: usize,
+    pub duplicated: usize,
+    pub delayed: usize,
+    pub reordered: usize,
+}
+
+struct Envelope {
+    deliver_at: u64,
+    from: usize,
+    to: usize,
+    event: EventType,
+}
+
+// Messages in flight between the nodes of a simulation
+pub struct ChaosNetwork {
+    pub config: ChaosConfig,
+    pub stats: ChaosStats,
+    rng: StdRng,
+    in_flight: Vec<Envelope>,
+}
+
+impl ChaosNetwork {
+    // The seed makes runs reproducible
+    pub fn new(config: ChaosConfig, seed: u64) -> Self {
+        Self {
+            config,
+            stats: ChaosStats::default(),
+            rng: StdRng::seed_from_u64(seed),
+            in_flight: vec![],
+        }
+    }
+
+    // Queues the event for delivery in the next tick, unless chaos decides otherwise
+    pub fn send(&mut self, now: u64, from: usize, to: usize, event: EventType) {
+        self.stats.sent += 1;
+        if self.rng.gen_bool(self.config.drop_probability) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let copies = if self.rng.gen_bool(self.config.duplicate_probability) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut deliver_at = now + 1;
+            if self.config.max_delay > 0 && self.rng.gen_bool(self.config.delay_probability) {
+                self.stats.delayed += 1;
+                deliver_at += self.rng.gen_range(1..=self.config.max_delay);
+            }
+            self.in_flight.push(Envelope {
+                deliver_at,
+                from,
+                to,
+                event: event.clone(),
+            });
+        }
+    }
+
+    // Removes and returns all messages due at the given tick as (from, to, event)
+    pub fn deliver(&mut self, now: u64) -> Vec<(usize, usize, EventType)> {
+        let (due, pending): (Vec<Envelope>, Vec<Envelope>) = self
+            .in_flight
+            .drain(..)
+            .partition(|envelope| envelope.deliver_at <= now);
+        self.in_flight = pending;
+
+        let mut due = due
+            .into_iter()
+            .map(|envelope| (envelope.from, envelope.to, envelope.event))
+            .collect::<Vec<(usize, usize, EventType)>>();
+        if due.len() > 1 && self.rng.gen_bool(self.config.reorder_probability) {
+            self.stats.reordered += 1;
+            due.shuffle(&mut self.rng);
+        }
+        due
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
+
+pub struct Simulation {
+    pub nodes: Vec<Node>,
+    pub network: ChaosNetwork,
+    // How the nodes gossip the blocks they mine, full blocks unless set otherwise
+    pub propagation: Propagation,
+    tick: u64,
+    start: Instant,
+}
+
+impl Simulation {
+    // Starts the given number of nodes with in-memory storage
+    pub async fn new(nodes: usize, chaos: ChaosConfig, seed: u64) -> Result<Self, BlockchainError> {
+        let mut simulation = Self {
+            nodes: vec![],
+            network: ChaosNetwork::new(chaos, seed),
+            propagation: Propagation::Full,
+            tick: 0,
+            start: Instant::now(),
+        };
+        for index in 0..nodes {
+            let node = Node::init(Storage::Memory(MemoryStorage::default()), peer_name(index)).await?;
+            simulation.nodes.push(node);
+        }
+        Ok(simulation)
+    }
+
+    pub fn now(&self) -> Instant {
+        self.start + TICK * self.tick as u32
+    }
+
+    // Mines a block on the node and broadcasts it
+    pub async fn mine(&mut self, index: usize, data: &str) -> Result<Block, BlockchainError> {
+        let node = &mut self.nodes[index];
+        let block = node.chain.mine_block(data.to_owned(), &mut node.storage).await?;
+        self.route(index, EventType::SendNewBlock(block.clone()));
+        Ok(block)
+    }
+
+    // Mines the next block of the node's load generator and broadcasts it
+    pub async fn generate_load(&mut self, index: usize) -> Result<(), BlockchainError> {
+        for event in self.nodes[index].generate_load().await? {
+            self.route(index, event);
+        }
+        Ok(())
+    }
+
+    // Asks all other nodes for their latest block, as the p2p layer does when it connects to a peer
+    pub fn announce(&mut self, index: usize) {
+        for to in (0..self.nodes.len()).filter(|to| *to != index) {
+            let event = EventType::SendLatestBlockRequest{receiver: peer_name(index)};
+            self.network.send(self.tick, index, to, event);
+        }
+    }
+
+    // Advances the simulation by one tick, delivering all messages that are due
+    pub async fn step(&mut self) {
+        self.tick += 1;
+        let now = self.now();
+        for (_, to, event) in self.network.deliver(self.tick) {
+            let outgoing = self.nodes[to].handle_event(event, now).await;
+            for event in outgoing {
+                self.route(to, event);
+            }
+        }
+        for index in 0..self.nodes.len() {
+            self.nodes[index].expire_sync_sessions(now).await;
+            for event in self.nodes[index].check_stale_tip(now) {
+                self.route(index, event);
+            }
+        }
+    }
+
+    pub async fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step().await;
+        }
+    }
+
+    // Runs until no messages are in flight anymore. Returns false if that did not happen within max_ticks.
+    pub async fn run_until_idle(&mut self, max_ticks: u64) -> bool {
+        for _ in 0..max_ticks {
+            if self.network.is_idle() {
+                return true;
+            }
+            self.step().await;
+        }
+        self.network.is_idle()
+    }
+
+    // Runs until every node has the block on its main chain. Returns the number of ticks that took,
+    // None if some node did not accept it within max_ticks.
+    pub async fn run_until_accepted(&mut self, block: &Block, max_ticks: u64) -> Option<u64> {
+        for ticks in 0..=max_ticks {
+            if self.accepted(block).await {
+                return Some(ticks);
+            }
+            if ticks < max_ticks {
+                self.step().await;
+            }
+        }
+        None
+    }
+
+    async fn accepted(&mut self, block: &Block) -> bool {
+        for node in self.nodes.iter_mut() {
+            if !Chain::get_block_by_id(&mut node.storage, block.id).await.is_ok_and(|stored| stored.hash == block.hash) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // True if all nodes agree on the latest block
+    pub fn converged(&self) -> bool {
+        self.nodes
+            .windows(2)
+            .all(|nodes| nodes[0].chain.latest_block == nodes[1].chain.latest_block)
+    }
+
+    // Turns an event a node sent to the p2p layer into the events its peers receive
+    fn route(&mut self, from: usize, event: EventType) {
+        let sender = peer_name(from);
+        let others = (0..self.nodes.len()).filter(|to| *to != from).collect::<Vec<usize>>();
+        match event {
+            EventType::SendNewBlock(block) => {
+                for to in others {
+                    let event = match self.propagation {
+                        Propagation::Full => EventType::ReceivedNewBlock{sender: sender.clone(), block: block.clone()},
+                        Propagation::Announce => {
+                            EventType::ReceivedBlockAnnouncement{sender: sender.clone(), announcement: BlockAnnouncement::of(&block)}
+                        }
+                    };
+                    self.network.send(self.tick, from, to, event);
+                }
+            }
+            EventType::SendBlockRequest{receiver, hash} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedBlockRequest{receiver: sender, hash});
+                }
+            }
+            EventType::SendBlock{receiver, block} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedNewBlock{sender, block});
+                }
+            }
+            EventType::SendFinalizedCheckpoint{checkpoint} => {
+                for to in others {
+                    let event = EventType::ReceivedFinalizedCheckpoint{sender: sender.clone(), checkpoint: checkpoint.clone()};
+                    self.network.send(self.tick, from, to, event);
+                }
+            }
+            EventType::SendServiceAnnouncement{announcement} => {
+                for to in others {
+                    let event = EventType::ReceivedServiceAnnouncement{sender: sender.clone(), announcement: announcement.clone()};
+                    self.network.send(self.tick, from, to, event);
+                }
+            }
+            EventType::SendLatestBlock{receiver, block} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedLatestBlock{sender, block});
+                }
+            }
+            EventType::SendChainRequest{receiver, session_id, from: height} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedChainRequest{receiver: sender, session_id, from: height});
+                }
+            }
+            EventType::SendSnapshotRequest{receiver, session_id} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedSnapshotRequest{receiver: sender, session_id});
+                }
+            }
+            EventType::SendSnapshot{receiver, session_id, snapshot} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedSnapshot{sender, session_id, snapshot});
+                }
+            }
+            EventType::SendChain{receiver, session_id, chain} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedChain{sender, session_id, chain});
+                }
+            }
+            EventType::SendBlockReceipt{receiver, hash, received_at} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedBlockReceipt{sender, hash, received_at});
+                }
+            }
+            EventType::SendPoolMessage{receiver, message} => {
+                if let Some(to) = peer_index(&receiver) {
+                    self.network.send(self.tick, from, to, EventType::ReceivedPoolMessage{sender, message});
+                }
+            }
+            EventType::RequestLatestBlocks => self.announce(from),
+            _ => {}
+        }
+    }
+}
+
+pub fn peer_name(index: usize) -> String {
+    format!("node-{}", index)
+}
+
+fn peer_index(name: &str) -> Option<usize> {
+    name.strip_prefix("node-")?.parse().ok()
+}
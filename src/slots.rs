@@ -0,0 +1,41 @@
+// Slot-based block production (`--slot-time SECS [--proposer PEER_ID]...`): time is divided into
+// slots of a fixed length, counted from the Unix epoch so all nodes agree on them, and the proposer
+// of a slot produces one block in it, with the next queued job or empty data. The proposers take
+// turns in the order they are configured. Without proposers we produce every block ourselves.
+// Proposers are a local schedule, blocks of other miners are not rejected.
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotSchedule {
+    pub slot_time: Duration,
+    // Peer IDs of the miners producing blocks, in turn
+    pub proposers: Vec<String>,
+}
+
+impl SlotSchedule {
+    pub fn new(slot_time: Duration, proposers: Vec<String>) -> Self {
+        Self { slot_time, proposers }
+    }
+
+    // The slot the Unix timestamp (in milliseconds) falls into
+    pub fn slot_at(&self, timestamp_millis: i64) -> u64 {
+        (timestamp_millis.max(0) as u128 / self.slot_time.as_millis().max(1)) as u64
+    }
+
+    // Unix timestamp (in milliseconds) the slot starts at
+    pub fn slot_start(&self, slot: u64) -> i64 {
+        (slot as u128 * self.slot_time.as_millis()) as i64
+    }
+
+    // None if anyone may propose
+    pub fn proposer(&self, slot: u64) -> Option<&str> {
+        if self.proposers.is_empty() {
+            return None;
+        }
+        Some(&self.proposers[(slot % self.proposers.len() as u64) as usize])
+    }
+
+    pub fn is_proposer(&self, slot: u64, miner: &str) -> bool {
+        self.proposer(slot).is_none_or(|proposer| proposer == miner)
+    }
+}
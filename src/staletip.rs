@@ -0,0 +1,63 @@
+// Stale tip detection: we only learn about longer chains from the blocks our peers gossip and from
+// their answers to our latest block requests, which are sent when a peer subscribes. If both get
+// lost (e.g. gossip dropped by a flaky network) we silently fall behind. So if our latest block did
+// not change for `--stale-tip-factor` times the target block time of our network while peers are
+// connected, we ask all peers for their latest block again, which starts a sync with any peer that
+// is ahead (see node.rs). The detection then waits another period before asking again.
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_STALE_TIP_FACTOR: u32 = 3;
+// How often the main loop checks for a stale tip
+pub const STALE_TIP_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct StaleTipMonitor {
+    // Multiple of the target block time after which our tip counts as stale
+    pub factor: u32,
+    tip: String,
+    // When we saw the tip change, None until the first check
+    changed_at: Option<Instant>,
+    // When we last asked our peers because of the tip
+    recovered_at: Option<Instant>,
+    // Number of times the tip was stale, for `node tip`
+    pub stale_tips: u64,
+}
+
+impl Default for StaleTipMonitor {
+    fn default() -> Self {
+        Self { factor: DEFAULT_STALE_TIP_FACTOR, tip: String::new(), changed_at: None, recovered_at: None, stale_tips: 0 }
+    }
+}
+
+impl StaleTipMonitor {
+    pub fn threshold(&self, target_block_time_ms: i64) -> Duration {
+        Duration::from_millis(target_block_time_ms.max(0) as u64) * self.factor
+    }
+
+    // Whether the tip with the hash is stale and we should ask our peers for their latest block.
+    // Without connected peers there is nobody to ask, so the tip never counts as stale.
+    pub fn check(&mut self, tip: &str, target_block_time_ms: i64, connected_peers: usize, now: Instant) -> bool {
+        let changed_at = match self.changed_at {
+            Some(changed_at) if self.tip == tip => changed_at,
+            _ => {
+                self.tip = tip.to_owned();
+                self.changed_at = Some(now);
+                self.recovered_at = None;
+                return false;
+            }
+        };
+        let threshold = self.threshold(target_block_time_ms);
+        let since = self.recovered_at.unwrap_or(changed_at);
+        if connected_peers == 0 || now.duration_since(since) < threshold {
+            return false;
+        }
+        self.recovered_at = Some(now);
+        self.stale_tips += 1;
+        true
+    }
+
+    // How long the tip did not change, as far as we checked
+    pub fn age(&self, now: Instant) -> Duration {
+        self.changed_at.map_or(Duration::ZERO, |changed_at| now.duration_since(changed_at))
+    }
+}
@@ -0,0 +1,177 @@
+// State proofs (`state prove`, `state verify`): the state derived from the main chain is a set of
+// keys with values, the amounts received and sent by every address (`received/ADDRESS`,
+// `sent/ADDRESS`, sent including the fees) and the successor of every rotated signing key
+// (`rotated/OLD_KEY`). Only successful payloads and coinbases change it (see receipts.rs and
+// transactions.rs), a coinbase is received by its recipient without being sent. Version 5 headers commit
+// to the root of a Merkle tree over the state after their block (see blockchain.rs), so a light
+// client holding a header can check the value of a key from a proof, without the blocks the state
+// is derived from. The leaves are the SHA-256 of key and value, ordered by key. Keys without a value
+// can not be proven (yet).
+// The state doubles as the account model (`state balance ADDRESS`): the balance of an address is
+// what it received minus what it sent. Chain keeps the state after its latest block (LatestState),
+// applies every block it adds to it and derives it again from the new chain when the chain is
+// replaced (see Chain::update), so balances are not recomputed from the whole chain every time.
+use crate::anchor::{self, ProofStep};
+use crate::blockchain::{self, Block, BlockchainError, Chain};
+use crate::receipts::{self, ReceiptEvent};
+use crate::storage::Storage;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// Root of the state before the first payload changed it
+pub const EMPTY_STATE_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    values: BTreeMap<String, String>,
+}
+
+// The state after the main chain block with the hash
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatestState {
+    pub block_hash: String,
+    pub state: State,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountBalance {
+    pub received: u64,
+    // Including the fees
+    pub sent: u64,
+}
+
+impl AccountBalance {
+    // Transfers without inputs are not checked against balances (see utxo.rs), so more can have
+    // been sent than received
+    pub fn available(&self) -> u64 {
+        self.received.saturating_sub(self.sent)
+    }
+}
+
+// The value of a key and the path from its leaf to the state root committed by the header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateProof {
+    pub key: String,
+    pub value: String,
+    pub path: Vec<ProofStep>,
+    pub root: String,
+    pub header: Block,
+}
+
+impl State {
+    // The state after the main chain block at the height. A chain restored from a snapshot lacks the
+    // blocks below its base, so its state is unknown.
+    pub async fn at(storage: &mut Storage, height: Height) -> Result<Self, BlockchainError> {
+        if storage.get_base().await?.is_some() {
+            return Err(BlockchainError::Error("the state of a chain restored from a snapshot is unknown".to_owned()));
+        }
+        let mut state = State::default();
+        for block in Chain::get_chain(storage).await?.iter().take_while(|block| block.id <= height) {
+            state.apply(block);
+        }
+        Ok(state)
+    }
+
+    // The state after the blocks, oldest first
+    pub fn from_blocks<'a>(blocks: impl IntoIterator<Item = &'a Block>) -> Self {
+        let mut state = State::default();
+        for block in blocks {
+            state.apply(block);
+        }
+        state
+    }
+
+    pub fn apply(&mut self, block: &Block) {
+        if let Some(coinbase) = &block.coinbase {
+            self.add(format!("received/{}", coinbase.recipient), coinbase.amount);
+        }
+        let events = receipts::execute(block).map(|receipt| receipt.events).unwrap_or_default();
+        for event in events {
+            match event {
+                ReceiptEvent::Transfer { sender, recipient, amount, fee, .. } => {
+                    self.add(format!("received/{}", recipient), amount);
+                    self.add(format!("sent/{}", sender), amount.saturating_add(fee));
+                }
+                ReceiptEvent::KeyRotated { old_key, new_key } => {
+                    self.values.insert(format!("rotated/{}", old_key), new_key);
+                }
+                ReceiptEvent::Anchored { .. } => {}
+            }
+        }
+    }
+
+    fn add(&mut self, key: String, amount: u64) {
+        let value = self.values.entry(key).or_default();
+        *value = value.parse::<u64>().unwrap_or_default().saturating_add(amount).to_string();
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn balance(&self, address: &str) -> AccountBalance {
+        let amount = |key: String| self.get(&key).and_then(|value| value.parse::<u64>().ok()).unwrap_or_default();
+        AccountBalance { received: amount(format!("received/{}", address)), sent: amount(format!("sent/{}", address)) }
+    }
+
+    pub fn root(&self) -> String {
+        if self.values.is_empty() {
+            return EMPTY_STATE_ROOT.to_owned();
+        }
+        anchor::merkle_root(&self.leaves()).expect("leaves are digests")
+    }
+
+    // The value of the key with the path from its leaf to the root
+    pub fn prove(&self, key: &str) -> Result<(String, Vec<ProofStep>), BlockchainError> {
+        let index = self
+            .values
+            .keys()
+            .position(|stored| stored == key)
+            .ok_or_else(|| BlockchainError::Error(format!("{} has no value", key)))?;
+        Ok((self.values[key].clone(), anchor::merkle_path(&self.leaves(), index)?))
+    }
+
+    fn leaves(&self) -> Vec<String> {
+        self.values.iter().map(|(key, value)| leaf(key, value)).collect()
+    }
+}
+
+// Keys and values are separated by a NUL byte, which neither contains
+fn leaf(key: &str, value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(key.as_bytes());
+    hasher.update([0]);
+    hasher.update(value.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+impl StateProof {
+    // Proves the value of the key after the main chain block at the height
+    pub async fn create(storage: &mut Storage, key: &str, height: Height) -> Result<Self, BlockchainError> {
+        let header = Chain::get_block_by_id(storage, height).await?;
+        if header.state_root.is_empty() {
+            return Err(BlockchainError::Error(format!("block {} at height {} commits to no state root", header.hash, height)));
+        }
+        let (value, path) = State::at(storage, height).await?.prove(key)?;
+        Ok(Self { key: key.to_owned(), value, path, root: header.state_root.clone(), header })
+    }
+
+    // Checks that the key's value leads to the root and that the root is committed by the header.
+    // Whether the header is part of the chain has to be checked separately, e.g. against the headers
+    // a light client synced.
+    pub fn verify(&self) -> Result<(), BlockchainError> {
+        let invalid = |reason: &str| BlockchainError::Error(format!("invalid state proof: {}", reason));
+        if anchor::root_of_path(&leaf(&self.key, &self.value), &self.path)? != self.root {
+            return Err(invalid("the path does not lead to the root"));
+        }
+        if self.header.state_root != self.root {
+            return Err(invalid("the header does not commit to the root"));
+        }
+        if blockchain::hasher(&self.header) != self.header.hash {
+            return Err(invalid("the block hash does not match its header"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+// Lifetime counters of the node that survive restarts (`node stats`): when it was first and last
+// started, how often it was started and how long it ran in total, the blocks it mined and received,
+// the reorgs of its main chain and the peers it ever completed a handshake with. The node counts in
+// memory (see StatsRecorder) and adds the counts to the storage every STATS_FLUSH_INTERVAL and when
+// it shuts down, so a crash loses at most the counts of one interval.
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Names of the values in the node_stats table
+pub const FIRST_STARTED_AT: &str = "first_started_at";
+pub const LAST_STARTED_AT: &str = "last_started_at";
+pub const STARTS: &str = "starts";
+pub const UPTIME_SECS: &str = "uptime_secs";
+pub const BLOCKS_MINED: &str = "blocks_mined";
+pub const BLOCKS_RECEIVED: &str = "blocks_received";
+pub const REORGS: &str = "reorgs";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStats {
+    // Unix milliseconds, unset before the first start was recorded
+    pub first_started_at: Option<i64>,
+    pub last_started_at: Option<i64>,
+    pub starts: u64,
+    pub uptime_secs: u64,
+    pub blocks_mined: u64,
+    pub blocks_received: u64,
+    pub reorgs: u64,
+    pub peers_seen: u64,
+}
+
+impl NodeStats {
+    // The stats from the stored (name, value) pairs, unknown names are ignored
+    pub fn from_values<'a>(values: impl IntoIterator<Item = (&'a str, i64)>, peers_seen: u64) -> Self {
+        let mut stats = NodeStats { peers_seen, ..NodeStats::default() };
+        for (name, value) in values {
+            let count = u64::try_from(value).unwrap_or_default();
+            match name {
+                FIRST_STARTED_AT => stats.first_started_at = Some(value),
+                LAST_STARTED_AT => stats.last_started_at = Some(value),
+                STARTS => stats.starts = count,
+                UPTIME_SECS => stats.uptime_secs = count,
+                BLOCKS_MINED => stats.blocks_mined = count,
+                BLOCKS_RECEIVED => stats.blocks_received = count,
+                REORGS => stats.reorgs = count,
+                _ => {}
+            }
+        }
+        stats
+    }
+}
+
+impl fmt::Display for NodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = |millis: Option<i64>| match millis.and_then(|millis| Utc.timestamp_millis_opt(millis).single()) {
+            Some(time) => time.to_rfc3339(),
+            None => "never".to_owned(),
+        };
+        writeln!(f, "first started: {} | last started: {} | starts: {}", time(self.first_started_at), time(self.last_started_at), self.starts)?;
+        writeln!(f, "uptime: {}", format_duration(self.uptime_secs))?;
+        write!(
+            f,
+            "blocks mined: {} | blocks received: {} | reorgs: {} | peers seen: {}",
+            self.blocks_mined, self.blocks_received, self.reorgs, self.peers_seen
+        )
+    }
+}
+
+// E.g. "3d 4h 5m 6s"
+pub fn format_duration(secs: u64) -> String {
+    let (days, hours, minutes, secs) = (secs / 86_400, secs % 86_400 / 3_600, secs % 3_600 / 60, secs % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", secs),
+        (0, 0, _) => format!("{}m {}s", minutes, secs),
+        (0, _, _) => format!("{}h {}m {}s", hours, minutes, secs),
+        _ => format!("{}d {}h {}m {}s", days, hours, minutes, secs),
+    }
+}
+
+// What the node counted since the last flush, added to the stored stats by Storage::add_node_stats
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub uptime_secs: u64,
+    pub blocks_mined: u64,
+    pub blocks_received: u64,
+    pub reorgs: u64,
+    // Peer IDs, only the ones not stored yet count as new peers
+    pub peers: BTreeSet<String>,
+}
+
+impl Counts {
+    // The counters to add by name, without the ones that did not change
+    pub fn values(&self) -> Vec<(&'static str, i64)> {
+        [(UPTIME_SECS, self.uptime_secs), (BLOCKS_MINED, self.blocks_mined), (BLOCKS_RECEIVED, self.blocks_received), (REORGS, self.reorgs)]
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| (name, i64::try_from(count).unwrap_or(i64::MAX)))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values().is_empty() && self.peers.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatsRecorder {
+    pending: Counts,
+    // Uptime is counted in whole seconds from here, the rest carries over to the next flush
+    counted_until: Instant,
+}
+
+impl StatsRecorder {
+    pub fn new(now: Instant) -> Self {
+        Self { pending: Counts::default(), counted_until: now }
+    }
+
+    pub fn block_mined(&mut self) {
+        self.pending.blocks_mined += 1;
+    }
+
+    pub fn blocks_received(&mut self, count: u64) {
+        self.pending.blocks_received += count;
+    }
+
+    pub fn reorged(&mut self) {
+        self.pending.reorgs += 1;
+    }
+
+    pub fn peer_seen(&mut self, peer: &str) {
+        self.pending.peers.insert(peer.to_owned());
+    }
+
+    // Returns the counts since the last flush, including the uptime up to now, and starts counting again
+    pub fn take(&mut self, now: Instant) -> Counts {
+        let uptime_secs = now.saturating_duration_since(self.counted_until).as_secs();
+        let counts = Counts { uptime_secs, ..std::mem::take(&mut self.pending) };
+        self.counted_until += Duration::from_secs(counts.uptime_secs);
+        counts
+    }
+
+    // Counts that could not be stored are added again on the next flush
+    pub fn put_back(&mut self, counts: Counts) {
+        self.pending.blocks_mined += counts.blocks_mined;
+        self.pending.blocks_received += counts.blocks_received;
+        self.pending.reorgs += counts.reorgs;
+        self.pending.peers.extend(counts.peers);
+        self.counted_until -= Duration::from_secs(counts.uptime_secs);
+    }
+}
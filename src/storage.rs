@@ -0,0 +1,822 @@
+use crate::anchor;
+use crate::atrest;
+use crate::blockchain::{Block, BlockchainError, SearchResult, StaleBlock};
+use crate::cache::{BlockCache, CacheStats};
+use crate::deadletter::BadMessage;
+use crate::diskusage::{PayloadUsage, TableUsage};
+use crate::fastsync::ChainBase;
+use crate::payload::payload_hash;
+use crate::receipts::{self, Receipt};
+use crate::repository::{BlockQuery, BlockTable, Index, Repository};
+use crate::stats::{self, Counts, NodeStats};
+use crate::sync::SyncRecord;
+use crate::transactions;
+use crate::types::{Height, PeerScore};
+use crate::utxo::{self, OutPoint, Utxo, UtxoSet};
+use crate::wal::WriteQueue;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use tokio_postgres::Client;
+
+// Payloads of at least this many bytes are stored once in the payloads table and referenced by
+// their hash, so re-anchoring the same data does not store it again
+pub const PAYLOAD_INLINE_LIMIT: usize = 1024;
+
+// Number of bad messages kept in storage, older ones are deleted
+pub const BAD_MESSAGES_KEPT: i64 = 10_000;
+// Number of finished sync sessions kept in storage, older ones are deleted
+pub const SYNC_HISTORY_KEPT: i64 = 10_000;
+
+// Where the chain is persisted. Postgres is the default, with a cache of recent main chain blocks
+// in front of it (see cache.rs) and optionally a write-ahead queue of appended blocks that are not
+// written yet (see wal.rs). The in-memory storage is meant for demos and development and can
+// optionally be snapshotted to disk.
+pub enum Storage {
+    Postgres(Client, BlockCache, Option<Box<WriteQueue>>),
+    Memory(MemoryStorage),
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
+pub struct MemoryStorage {
+    // Main chain, ordered by id
+    blocks: Vec<Block>,
+    stale_blocks: Vec<StaleBlock>,
+    // Snapshots written before peer scores were persisted do not contain them
+    #[serde(default)]
+    peer_scores: Vec<PeerScore>,
+    // Oldest first
+    #[serde(default)]
+    bad_messages: Vec<BadMessage>,
+    // Set if the chain was restored from a snapshot, see fastsync.rs
+    #[serde(default)]
+    base: Option<ChainBase>,
+    // Oldest first
+    #[serde(default)]
+    sync_history: Vec<SyncRecord>,
+    // Lifetime counters and start times by name, see stats.rs
+    #[serde(default)]
+    node_stats: BTreeMap<String, i64>,
+    // Peer IDs with the time we first saw them
+    #[serde(default)]
+    seen_peers: BTreeMap<String, i64>,
+}
+
+impl MemoryStorage {
+    // Loads a snapshot written by save, or starts empty if there is none yet
+    pub fn load(path: &Path) -> Result<Self, BlockchainError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = atrest::open_file(&fs::read_to_string(path)?)?;
+        serde_json::from_str(&json)
+            .map_err(|err| BlockchainError::Error(format!("invalid snapshot: {}", err)))
+    }
+
+    // Sealed as a whole when encrypted at rest, see atrest.rs
+    pub fn save(&self, path: &Path) -> Result<(), BlockchainError> {
+        let json = serde_json::to_string(self)
+            .map_err(|err| BlockchainError::Error(format!("can not serialize snapshot: {}", err)))?;
+        let json = atrest::seal(&json);
+        // Write to a temporary file first, so a crash never leaves a half-written snapshot behind
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl Storage {
+    pub fn postgres(db_client: Client, write_queue: Option<WriteQueue>) -> Self {
+        Storage::Postgres(db_client, BlockCache::default(), write_queue.map(Box::new))
+    }
+
+    // Creates and migrates the schema and writes the blocks left in the write-ahead queue by a crash
+    pub async fn init(&mut self) -> Result<(), BlockchainError> {
+        if let Storage::Postgres(db_client, _, write_queue) = self {
+            let repository = Repository::new(db_client);
+            for (description, err) in repository.migrate().await {
+                error!("Error {}: {:?}", description, err)
+            }
+            check_at_rest(&repository).await?;
+            // Blocks are written in order, so the ones that were stored come first
+            if let Some(queue) = write_queue {
+                while let Some(block) = queue.front() {
+                    if repository.select_block(BlockQuery::Hash(&block.hash)).await?.is_none() {
+                        break;
+                    }
+                    queue.pop()?;
+                }
+            }
+        }
+        self.flush_writes().await
+    }
+
+    // Appends the block to the main chain. With a write-ahead queue this returns once the block is
+    // journaled, it is written to Postgres by the next flush_writes.
+    pub async fn append_block(&mut self, block: &Block) -> Result<(), BlockchainError> {
+        if let Storage::Postgres(_, _, Some(queue)) = self {
+            return queue.push(block.clone());
+        }
+        self.insert_block(block).await
+    }
+
+    // Writes the queued blocks to Postgres, oldest first
+    pub async fn flush_writes(&mut self) -> Result<(), BlockchainError> {
+        if let Storage::Postgres(db_client, cache, Some(queue)) = self {
+            while let Some(block) = queue.front() {
+                write_block(&Repository::new(db_client), block).await?;
+                cache.insert(block.clone());
+                queue.pop()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Number of blocks in the write-ahead queue
+    pub fn queued_writes(&self) -> usize {
+        match self {
+            Storage::Postgres(_, _, Some(queue)) => queue.len(),
+            _ => 0,
+        }
+    }
+
+    // Inserts the block into the main chain and updates the address index. Queued blocks are
+    // written first, so the blocks are stored in order.
+    pub async fn insert_block(&mut self, block: &Block) -> Result<(), BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => write_block(&Repository::new(db_client), block).await?,
+            Storage::Memory(memory) => {
+                // Mirror the unique constraints of the blocks table
+                if memory.blocks.iter().any(|stored| {
+                    stored.hash == block.hash || stored.id == block.id || stored.prev_hash == block.prev_hash
+                }) {
+                    return Err(BlockchainError::Error(format!("duplicate block: {}", block.hash)));
+                }
+                memory.blocks.push(block.clone());
+                memory.blocks.sort_by_key(|stored| stored.id);
+            }
+        }
+        // The block that was just added is the parent of the next one we validate
+        if let Storage::Postgres(_, cache, _) = self {
+            cache.insert(block.clone());
+        }
+        Ok(())
+    }
+
+    // Adds the main chain block to the address, anchor and transaction indexes, stores its receipt
+    // and applies it to the unspent outputs. The in-memory storage has no indexes, its queries scan
+    // the blocks.
+    pub async fn index_block(&mut self, block: &Block) -> Result<(), BlockchainError> {
+        if let Storage::Postgres(db_client, ..) = self {
+            index_block(&Repository::new(db_client), block).await?;
+        }
+        Ok(())
+    }
+
+    // Drops the data derived from the blocks before it is rebuilt by indexing every block again:
+    // the address, anchor and transaction indexes, the receipts and the unspent outputs are emptied,
+    // the full-text and height indexes rebuilt by Postgres and unreferenced payloads removed
+    pub async fn clear_indexes(&mut self) -> Result<(), BlockchainError> {
+        self.flush_writes().await?;
+        if let Storage::Postgres(db_client, ..) = self {
+            let repository = Repository::new(db_client);
+            for index in Index::ALL {
+                repository.clear_index(index).await?;
+            }
+            repository.clear_utxos().await?;
+            repository.prune_payloads().await?;
+            repository.reindex_tables().await?;
+        }
+        Ok(())
+    }
+
+    // Removes all main chain blocks together with the derived indexes, and the base of a chain
+    // restored from a snapshot
+    pub async fn clear_blocks(&mut self) -> Result<(), BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, cache, _) => {
+                cache.clear();
+                let repository = Repository::new(db_client);
+                repository.delete_blocks(None).await?;
+                repository.delete_chain_base().await?
+            }
+            Storage::Memory(memory) => {
+                memory.blocks.clear();
+                memory.base = None;
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the main chain with the blocks, ordered by id. The replaced blocks are kept as stale
+    // blocks. Either all of it is stored or, if anything fails, the stored chain is left as it was:
+    // Postgres runs it in a transaction, the in-memory storage swaps in a changed copy.
+    pub async fn replace_chain(&mut self, blocks: &[Block], replaced: &[Block], received_at: i64) -> Result<(), BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).begin().await?,
+            Storage::Memory(memory) => {
+                let mut copy = Storage::Memory(memory.clone());
+                copy.swap_chain(blocks, replaced, received_at).await?;
+                *self = copy;
+                return Ok(());
+            }
+        }
+        let result = self.swap_chain(blocks, replaced, received_at).await;
+        self.finish_transaction(result).await
+    }
+
+    // Replaces the main chain with the blocks of a snapshot, ordered by id and starting at the base
+    // (see fastsync.rs). Like replace_chain, either all of it is stored or nothing.
+    pub async fn restore_snapshot(&mut self, base: &ChainBase, blocks: &[Block]) -> Result<(), BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).begin().await?,
+            Storage::Memory(memory) => {
+                let mut copy = Storage::Memory(memory.clone());
+                copy.swap_snapshot(base, blocks).await?;
+                *self = copy;
+                return Ok(());
+            }
+        }
+        let result = self.swap_snapshot(base, blocks).await;
+        self.finish_transaction(result).await
+    }
+
+    // Where the chain starts if it was restored from a snapshot, None if it starts with the genesis block
+    pub async fn get_base(&mut self) -> Result<Option<ChainBase>, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_chain_base().await,
+            Storage::Memory(memory) => Ok(memory.base.clone()),
+        }
+    }
+
+    async fn swap_snapshot(&mut self, base: &ChainBase, blocks: &[Block]) -> Result<(), BlockchainError> {
+        self.clear_blocks().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).insert_chain_base(base).await?,
+            Storage::Memory(memory) => memory.base = Some(base.clone()),
+        }
+        for block in blocks {
+            self.insert_block(block).await?;
+        }
+        Ok(())
+    }
+
+    // Commits the transaction begun by replace_chain or restore_snapshot, or rolls it back if the
+    // changes failed
+    async fn finish_transaction(&mut self, result: Result<(), BlockchainError>) -> Result<(), BlockchainError> {
+        if let Storage::Postgres(db_client, cache, _) = self {
+            let repository = Repository::new(db_client);
+            match result {
+                Ok(()) => repository.commit().await?,
+                Err(_) => {
+                    // The cache may hold blocks of the rolled back chain
+                    cache.clear();
+                    repository.rollback().await?
+                }
+            }
+        }
+        result
+    }
+
+    async fn swap_chain(&mut self, blocks: &[Block], replaced: &[Block], received_at: i64) -> Result<(), BlockchainError> {
+        for block in replaced {
+            self.insert_stale_block(block, received_at).await?;
+        }
+        self.clear_blocks().await?;
+        for block in blocks {
+            self.insert_block(block).await?;
+        }
+        // Blocks that are part of the main chain again are no longer stale
+        let hashes = blocks.iter().map(|block| block.hash.clone()).collect::<Vec<String>>();
+        self.remove_stale_blocks(&hashes).await
+    }
+
+    // Removes the main chain blocks above the height together with their index entries
+    pub async fn remove_blocks_above(&mut self, id: Height) -> Result<(), BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, cache, _) => {
+                cache.invalidate_above(id);
+                Repository::new(db_client).delete_blocks(Some(id)).await?
+            }
+            Storage::Memory(memory) => memory.blocks.retain(|block| block.id <= id),
+        }
+        Ok(())
+    }
+
+    pub async fn get_chain(&mut self) -> Result<Vec<Block>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_blocks(BlockQuery::All).await,
+            Storage::Memory(memory) => Ok(memory.blocks.clone()),
+        }
+    }
+
+    pub async fn get_block(&mut self, hash: &str) -> Result<Block, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, cache, write_queue) => match write_queue.as_ref().and_then(|queue| queue.get(hash)).cloned().or_else(|| cache.get(hash)) {
+                Some(block) => Some(block),
+                None => Repository::new(db_client).select_block(BlockQuery::Hash(hash)).await?.inspect(|block| cache.insert(block.clone())),
+            },
+            Storage::Memory(memory) => memory.blocks.iter().find(|block| block.hash == hash).cloned(),
+        }
+        .ok_or_else(|| BlockchainError::BlockNotFound(hash.to_owned()))
+    }
+
+    pub async fn get_block_by_id(&mut self, id: Height) -> Result<Block, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, cache, write_queue) => match write_queue.as_ref().and_then(|queue| queue.get_by_id(id)).cloned().or_else(|| cache.get_by_id(id)) {
+                Some(block) => Some(block),
+                None => Repository::new(db_client).select_block(BlockQuery::Id(id)).await?.inspect(|block| cache.insert(block.clone())),
+            },
+            Storage::Memory(memory) => memory.blocks.iter().find(|block| block.id == id).cloned(),
+        }
+        .ok_or_else(|| BlockchainError::BlockNotFound(id.to_string()))
+    }
+
+    // Main chain blocks from the first to the last height, both included, oldest first
+    pub async fn get_blocks_in_range(&mut self, first: Height, last: Height) -> Result<Vec<Block>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_blocks(BlockQuery::Range(first, last)).await,
+            Storage::Memory(memory) => Ok(memory.blocks.iter().filter(|block| block.id >= first && block.id <= last).cloned().collect()),
+        }
+    }
+
+    pub async fn get_latest_block(&mut self) -> Result<Block, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, _, write_queue) => match write_queue.as_ref().and_then(|queue| queue.last()) {
+                Some(block) => Some(block.clone()),
+                None => Repository::new(db_client).select_block(BlockQuery::Latest).await?,
+            },
+            Storage::Memory(memory) => memory.blocks.last().cloned(),
+        }
+        .ok_or_else(|| BlockchainError::BlockNotFound("latest".to_owned()))
+    }
+
+    pub async fn count_blocks(&mut self) -> Result<u64, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, _, write_queue) => {
+                let queued = write_queue.as_ref().map_or(0, |queue| queue.len() as u64);
+                Ok(Repository::new(db_client).count_blocks().await? + queued)
+            }
+            Storage::Memory(memory) => Ok(memory.blocks.len() as u64),
+        }
+    }
+
+    pub async fn insert_stale_block(&mut self, block: &Block, received_at: i64) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => {
+                Repository::new(db_client).insert_block(BlockTable::StaleBlocks { received_at }, block).await?
+            }
+            Storage::Memory(memory) => {
+                if !memory.stale_blocks.iter().any(|stale| stale.block.hash == block.hash) {
+                    memory.stale_blocks.push(StaleBlock {
+                        block: block.clone(),
+                        received_at,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn remove_stale_blocks(&mut self, hashes: &[String]) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).delete_stale_blocks(hashes).await?,
+            Storage::Memory(memory) => memory
+                .stale_blocks
+                .retain(|stale| !hashes.contains(&stale.block.hash)),
+        }
+        Ok(())
+    }
+
+    // Prunes the stale blocks at or below the height, completely or only their payloads (see gc.rs).
+    // Returns the pruned blocks as they were before.
+    pub async fn prune_stale_blocks(&mut self, height: Height, keep_headers: bool) -> Result<Vec<StaleBlock>, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).prune_stale_blocks(height, keep_headers).await,
+            Storage::Memory(memory) => {
+                let prunable = |stale: &StaleBlock| stale.block.id <= height && !(keep_headers && stale.block.data.is_empty());
+                let pruned = memory.stale_blocks.iter().filter(|stale| prunable(stale)).cloned().collect::<Vec<_>>();
+                match keep_headers {
+                    true => memory.stale_blocks.iter_mut().filter(|stale| stale.block.id <= height).for_each(|stale| stale.block.data.clear()),
+                    false => memory.stale_blocks.retain(|stale| stale.block.id > height),
+                }
+                Ok(pruned)
+            }
+        }
+    }
+
+    // Returns the most recent stale blocks, highest first
+    pub async fn get_stale_blocks(&mut self, limit: i64) -> Result<Vec<StaleBlock>, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_stale_blocks(limit).await,
+            Storage::Memory(memory) => {
+                let mut stale_blocks = memory.stale_blocks.clone();
+                stale_blocks.sort_by(|a, b| {
+                    b.block.id.cmp(&a.block.id).then(b.received_at.cmp(&a.received_at))
+                });
+                stale_blocks.truncate(limit.max(0) as usize);
+                Ok(stale_blocks)
+            }
+        }
+    }
+
+    // Number of stale and of non-genesis main chain blocks with an id of at least min_id
+    pub async fn count_blocks_from(&mut self, min_id: Height) -> Result<(i64, i64), BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).count_blocks_from(min_id).await,
+            Storage::Memory(memory) => Ok((
+                memory.stale_blocks.iter().filter(|stale| stale.block.id >= min_id).count() as i64,
+                memory.blocks.iter().filter(|block| block.id >= min_id && block.id > Height::GENESIS).count() as i64,
+            )),
+        }
+    }
+
+    // Returns all main chain blocks that touch the address, oldest first
+    pub async fn get_address_blocks(&mut self, address: &str) -> Result<Vec<Block>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_blocks(BlockQuery::Address(address)).await,
+            Storage::Memory(memory) => Ok(memory
+                .blocks
+                .iter()
+                .filter(|block| block.addresses().iter().any(|touched| touched == address))
+                .cloned()
+                .collect::<Vec<Block>>()),
+        }
+    }
+
+    // Returns all main chain blocks anchoring the digest, oldest first
+    pub async fn get_anchor_blocks(&mut self, digest: &str) -> Result<Vec<Block>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_blocks(BlockQuery::Anchor(digest)).await,
+            Storage::Memory(memory) => Ok(memory
+                .blocks
+                .iter()
+                .filter(|block| anchor::digests(&block.data).iter().any(|anchored| anchored == digest))
+                .cloned()
+                .collect::<Vec<Block>>()),
+        }
+    }
+
+    // Returns all main chain blocks carrying the transaction, oldest first
+    pub async fn get_transaction_blocks(&mut self, id: &str) -> Result<Vec<Block>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_blocks(BlockQuery::Transaction(id)).await,
+            Storage::Memory(memory) => Ok(memory
+                .blocks
+                .iter()
+                .filter(|block| transactions::transaction_ids(&block.data).iter().any(|txid| txid == id))
+                .cloned()
+                .collect::<Vec<Block>>()),
+        }
+    }
+
+    // The outputs that are unspent after our latest block, by outpoint
+    pub async fn get_unspent(&mut self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, Utxo>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_unspent(outpoints).await,
+            Storage::Memory(memory) => {
+                let set = UtxoSet::from_blocks(&memory.blocks);
+                Ok(outpoints.iter().filter_map(|outpoint| set.get(outpoint)).map(|utxo| (utxo.outpoint.clone(), utxo.clone())).collect())
+            }
+        }
+    }
+
+    // The outputs main chain blocks spent, with the hash of the block spending them
+    pub async fn get_spent(&mut self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, String>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_spent(outpoints).await,
+            Storage::Memory(memory) => {
+                let set = UtxoSet::from_blocks(&memory.blocks);
+                Ok(outpoints
+                    .iter()
+                    .filter_map(|outpoint| set.spent_in(outpoint).map(|block_hash| (outpoint.clone(), block_hash.clone())))
+                    .collect())
+            }
+        }
+    }
+
+    // The unspent outputs of the address, ordered by transaction ID and output
+    pub async fn get_utxos(&mut self, address: &str) -> Result<Vec<Utxo>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_utxos(address).await,
+            Storage::Memory(memory) => Ok(UtxoSet::from_blocks(&memory.blocks).for_address(address)),
+        }
+    }
+
+    // The receipt of the payload of the main chain block, None if it carries none or is no main chain block
+    pub async fn get_receipt(&mut self, block_hash: &str) -> Result<Option<Receipt>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => match Repository::new(db_client).select_receipt(block_hash).await? {
+                Some(json) => Receipt::from_json(&json).map(Some),
+                None => Ok(None),
+            },
+            Storage::Memory(memory) => Ok(memory.blocks.iter().find(|block| block.hash == block_hash).and_then(receipts::execute)),
+        }
+    }
+
+    // Full-text search over the payloads of all main chain blocks, best matches first.
+    // Postgres supports web search syntax ("quoted phrases", OR, -excluded), the in-memory
+    // storage only matches blocks containing all words and none of the -excluded ones.
+    pub async fn search_blocks(&mut self, query: &str, limit: i64) -> Result<Vec<SearchResult>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).search_blocks(query, limit).await,
+            Storage::Memory(memory) => {
+                let query = query.to_lowercase();
+                let (excluded, included): (Vec<&str>, Vec<&str>) = query
+                    .split_whitespace()
+                    .map(|word| word.trim_matches('"'))
+                    .filter(|word| !word.is_empty())
+                    .partition(|word| word.starts_with('-'));
+                if included.is_empty() {
+                    return Ok(vec![]);
+                }
+
+                Ok(memory
+                    .blocks
+                    .iter()
+                    .rev()
+                    .filter(|block| {
+                        let words = block.data.to_lowercase();
+                        let words = words.split_whitespace().collect::<Vec<&str>>();
+                        included.iter().all(|word| words.contains(word))
+                            && !excluded.iter().any(|word| words.contains(&&word[1..]))
+                    })
+                    .take(limit.max(0) as usize)
+                    .map(|block| SearchResult {
+                        block: block.clone(),
+                        headline: block
+                            .data
+                            .split_whitespace()
+                            .map(|word| {
+                                if included.contains(&word.to_lowercase().as_str()) {
+                                    format!("*{}*", word)
+                                } else {
+                                    word.to_owned()
+                                }
+                            })
+                            .collect::<Vec<String>>()
+                            .join(" "),
+                    })
+                    .collect::<Vec<SearchResult>>())
+            }
+        }
+    }
+
+    // Returns the payload with the given hash of a main chain or stale block
+    pub async fn get_payload(&mut self, hash: &str) -> Result<String, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_payload(hash).await?,
+            Storage::Memory(memory) => memory
+                .blocks
+                .iter()
+                .chain(memory.stale_blocks.iter().map(|stale| &stale.block))
+                .find(|block| payload_hash(&block.data) == hash)
+                .map(|block| block.data.clone()),
+        }
+        .ok_or_else(|| BlockchainError::Error(format!("payload not found: {}", hash)))
+    }
+
+    // Inserts or updates the scores of the given peers
+    pub async fn save_peer_scores(&mut self, scores: &[PeerScore]) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).upsert_peer_scores(scores).await?,
+            Storage::Memory(memory) => {
+                for score in scores {
+                    memory.peer_scores.retain(|stored| stored.peer_id != score.peer_id);
+                    memory.peer_scores.push(score.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns all persisted peer scores, best first
+    pub async fn get_peer_scores(&mut self) -> Result<Vec<PeerScore>, BlockchainError> {
+        let mut scores = match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_peer_scores().await?,
+            Storage::Memory(memory) => memory.peer_scores.clone(),
+        };
+        scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(scores)
+    }
+
+    // Records a message received from a peer that could not be decoded, keeping the latest
+    // BAD_MESSAGES_KEPT
+    pub async fn insert_bad_message(&mut self, message: &BadMessage) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).insert_bad_message(message).await?,
+            Storage::Memory(memory) => {
+                memory.bad_messages.push(message.clone());
+                let excess = memory.bad_messages.len().saturating_sub(BAD_MESSAGES_KEPT as usize);
+                memory.bad_messages.drain(..excess);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the latest bad messages, newest first
+    pub async fn get_bad_messages(&mut self, limit: i64) -> Result<Vec<BadMessage>, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_bad_messages(limit).await,
+            Storage::Memory(memory) => Ok(memory.bad_messages.iter().rev().take(limit.max(0) as usize).cloned().collect()),
+        }
+    }
+
+    // Records a finished sync session, keeping the latest SYNC_HISTORY_KEPT
+    pub async fn insert_sync_record(&mut self, record: &SyncRecord) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).insert_sync_record(record).await?,
+            Storage::Memory(memory) => {
+                memory.sync_history.push(record.clone());
+                let excess = memory.sync_history.len().saturating_sub(SYNC_HISTORY_KEPT as usize);
+                memory.sync_history.drain(..excess);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the latest finished sync sessions, newest first
+    pub async fn get_sync_history(&mut self, limit: i64) -> Result<Vec<SyncRecord>, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_sync_history(limit).await,
+            Storage::Memory(memory) => Ok(memory.sync_history.iter().rev().take(limit.max(0) as usize).cloned().collect()),
+        }
+    }
+
+    // Counts a start of the node at the time, see stats.rs
+    pub async fn record_start(&mut self, started_at: i64) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).record_start(started_at).await?,
+            Storage::Memory(memory) => {
+                *memory.node_stats.entry(stats::STARTS.to_owned()).or_default() += 1;
+                memory.node_stats.insert(stats::LAST_STARTED_AT.to_owned(), started_at);
+                memory.node_stats.entry(stats::FIRST_STARTED_AT.to_owned()).or_insert(started_at);
+            }
+        }
+        Ok(())
+    }
+
+    // Adds the counts of the running node to the lifetime stats, the peers are recorded as seen at `now`
+    pub async fn add_node_stats(&mut self, counts: &Counts, now: i64) -> Result<(), BlockchainError> {
+        let peers = counts.peers.iter().map(String::as_str).collect::<Vec<_>>();
+        match self {
+            Storage::Postgres(db_client, ..) => {
+                let repository = Repository::new(db_client);
+                repository.add_node_stats(&counts.values()).await?;
+                repository.insert_seen_peers(&peers, now).await?;
+            }
+            Storage::Memory(memory) => {
+                for (name, value) in counts.values() {
+                    *memory.node_stats.entry(name.to_owned()).or_default() += value;
+                }
+                for peer in peers {
+                    memory.seen_peers.entry(peer.to_owned()).or_insert(now);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_node_stats(&mut self) -> Result<NodeStats, BlockchainError> {
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_node_stats().await,
+            Storage::Memory(memory) => Ok(NodeStats::from_values(
+                memory.node_stats.iter().map(|(name, value)| (name.as_str(), *value)),
+                memory.seen_peers.len() as u64,
+            )),
+        }
+    }
+
+    // Payload sizes of the main chain blocks in the height range, see diskusage.rs
+    pub async fn get_payload_usage(&mut self, from: Height, to: Height, top: usize) -> Result<PayloadUsage, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => {
+                let top = i64::try_from(top).unwrap_or(i64::MAX);
+                Repository::new(db_client).select_payload_usage(from, to, top).await
+            }
+            Storage::Memory(memory) => Ok(PayloadUsage::from_blocks(memory.blocks.iter().filter(|block| block.id >= from && block.id <= to), top)),
+        }
+    }
+
+    // The sizes of the tables, for the in-memory storage the size of its snapshot
+    pub async fn get_table_usage(&mut self) -> Result<Vec<TableUsage>, BlockchainError> {
+        self.flush_writes().await?;
+        match self {
+            Storage::Postgres(db_client, ..) => Repository::new(db_client).select_table_usage().await,
+            Storage::Memory(memory) => {
+                let bytes = serde_json::to_vec(memory)
+                    .map_err(|err| BlockchainError::Error(format!("can not serialize snapshot: {}", err)))?
+                    .len() as u64;
+                Ok(vec![TableUsage { name: "snapshot".to_owned(), bytes }])
+            }
+        }
+    }
+
+    pub fn clear_cache(&mut self) {
+        if let Storage::Postgres(_, cache, _) = self {
+            cache.clear();
+        }
+    }
+
+    // Hits and misses of the block cache, the in-memory storage has none
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        match self {
+            Storage::Postgres(_, cache, _) => Some(cache.stats()),
+            Storage::Memory(_) => None,
+        }
+    }
+
+    // Writes a snapshot of the in-memory storage, Postgres persists on its own
+    pub fn snapshot(&self, path: &Path) -> Result<(), BlockchainError> {
+        match self {
+            Storage::Postgres(..) => Ok(()),
+            Storage::Memory(memory) => memory.save(path),
+        }
+    }
+}
+
+// A database encrypted at rest is only opened with the key it was encrypted with, see atrest.rs.
+// On the first start with a key the values stored before are sealed together with the check value,
+// so a database is either sealed as a whole or not at all.
+async fn check_at_rest(repository: &Repository<'_>) -> Result<(), BlockchainError> {
+    match repository.select_at_rest_check().await? {
+        Some(_) if !atrest::is_enabled() => {
+            Err(BlockchainError::Error("the database is encrypted at rest, start with --encrypt-at-rest".to_owned()))
+        }
+        Some(check_value) => match atrest::open(&check_value)? == atrest::CHECK_VALUE {
+            true => Ok(()),
+            false => Err(BlockchainError::Error("the database is encrypted with another storage key".to_owned())),
+        },
+        None if atrest::is_enabled() => {
+            repository.begin().await?;
+            match seal_database(repository).await {
+                Ok(()) => repository.commit().await,
+                Err(err) => {
+                    repository.rollback().await?;
+                    Err(err)
+                }
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+async fn seal_database(repository: &Repository<'_>) -> Result<(), BlockchainError> {
+    repository.seal_legacy_values().await?;
+    repository.insert_at_rest_check(&atrest::seal(atrest::CHECK_VALUE)).await
+}
+
+// Stores a main chain block together with its index entries
+async fn write_block(repository: &Repository<'_>, block: &Block) -> Result<(), BlockchainError> {
+    repository.insert_block(BlockTable::Blocks, block).await?;
+    index_block(repository, block).await
+}
+
+async fn index_block(repository: &Repository<'_>, block: &Block) -> Result<(), BlockchainError> {
+    for address in block.addresses() {
+        repository.insert_index_entry(Index::Address, &address, &block.hash).await?;
+    }
+    for digest in anchor::digests(&block.data) {
+        repository.insert_index_entry(Index::Anchor, &digest, &block.hash).await?;
+    }
+    for id in transactions::transaction_ids(&block.data) {
+        repository.insert_index_entry(Index::Transaction, &id, &block.hash).await?;
+    }
+    if let Some(receipt) = receipts::execute(block) {
+        repository.insert_index_entry(Index::Receipt, &receipt.to_json(), &block.hash).await?;
+    }
+    let inputs = utxo::inputs(block);
+    let unspent = match inputs.is_empty() {
+        true => HashMap::new(),
+        false => repository.select_unspent(&inputs).await?,
+    };
+    let changes = utxo::apply_block(block, &unspent);
+    if !changes.spent.is_empty() {
+        repository.spend_utxos(&changes.spent, &block.hash).await?;
+    }
+    for utxo in changes.created.iter() {
+        repository.insert_utxo(utxo).await?;
+    }
+    Ok(())
+}
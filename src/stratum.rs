@@ -0,0 +1,153 @@
+// Stratum-like TCP protocol for standalone hashers (`--stratum ADDR` on the node, the `hasher` binary
+// on the hashing machines). Connections speak the pool protocol of pool.rs, one JSON encoded
+// PoolMessage per line: hashers send Subscribe and Share, the node answers with Job, Accepted and
+// Rejected. Every connection is a worker of the node's pool, so the node has to run `pool start`.
+use crate::pool::{PoolMessage, PoolShare, PoolWorker};
+use crate::rpc;
+use crate::types::EventType;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::io::{self, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+// Worker names of stratum connections start with this, the rest is the remote address
+pub const STRATUM_PREFIX: &str = "stratum/";
+// Longest line we read from a hasher, a longer one closes the connection. Jobs the hasher reads
+// carry a block template and may be longer.
+pub const MAX_MESSAGE_LENGTH: usize = 1 << 20;
+pub const MAX_JOB_LENGTH: usize = 64 << 20;
+
+pub fn is_stratum_worker(name: &str) -> bool {
+    name.starts_with(STRATUM_PREFIX)
+}
+
+// Accepts hasher connections and passes their messages on to the main task, which sends the
+// messages for them back via rx_rcv (as SendPoolMessage events)
+pub async fn init_stratum(
+    listener: TcpListener,
+    mut rx_rcv: mpsc::UnboundedReceiver<EventType>,
+    main_sender: mpsc::UnboundedSender<EventType>,
+) -> Result<(), std::io::Error> {
+    println!("Stratum listening on {}", listener.local_addr()?);
+    let mut connections: HashMap<String, mpsc::UnboundedSender<PoolMessage>> = HashMap::new();
+    let (closed_sender, mut closed_rcv) = mpsc::unbounded_channel::<String>();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, addr) = accepted?;
+                let name = format!("{}{}", STRATUM_PREFIX, addr);
+                info!("Hasher {} connected", name);
+                let (sender, rcv) = mpsc::unbounded_channel();
+                connections.insert(name.clone(), sender);
+                tokio::spawn(handle_connection(stream, name, rcv, main_sender.clone(), closed_sender.clone()));
+            },
+            Some(name) = closed_rcv.recv() => {
+                info!("Hasher {} disconnected", name);
+                connections.remove(&name);
+                let _ = main_sender.send(EventType::ReceivedPoolMessage{sender: name, message: PoolMessage::Unsubscribe});
+            },
+            event = rx_rcv.recv() => match event {
+                Some(EventType::SendPoolMessage{receiver, message}) => {
+                    match connections.get(&receiver) {
+                        Some(connection) => {
+                            let _ = connection.send(message);
+                        }
+                        None => debug!("Hasher {} is not connected", receiver),
+                    }
+                },
+                Some(_) => {},
+                None => {
+                    debug!("stratum channel closed.");
+                    return Ok(());
+                },
+            },
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    name: String,
+    mut outgoing: mpsc::UnboundedReceiver<PoolMessage>,
+    main_sender: mpsc::UnboundedSender<EventType>,
+    closed_sender: mpsc::UnboundedSender<String>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let (mut reader, mut buffer) = (BufReader::new(reader), vec![]);
+    loop {
+        tokio::select! {
+            line = rpc::read_line(&mut reader, &mut buffer, MAX_MESSAGE_LENGTH) => match line {
+                Ok(Some(line)) => match serde_json::from_str::<PoolMessage>(&line) {
+                    Ok(message @ (PoolMessage::Subscribe | PoolMessage::Unsubscribe | PoolMessage::Share(_))) => {
+                        let _ = main_sender.send(EventType::ReceivedPoolMessage{sender: name.clone(), message});
+                    }
+                    _ => warn!("Invalid message from hasher {}: {}", name, line),
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("Closing connection of hasher {}: {}", name, err);
+                    break;
+                }
+            },
+            message = outgoing.recv() => match message {
+                Some(message) => {
+                    if write_message(&mut writer, &message).await.is_err() {
+                        break;
+                    }
+                }
+                // The server was shut down
+                None => break,
+            },
+        }
+    }
+    let _ = closed_sender.send(name);
+}
+
+async fn write_message<W: AsyncWriteExt + Unpin>(writer: &mut W, message: &PoolMessage) -> io::Result<()> {
+    let mut line = serde_json::to_string(message).expect("can jsonify pool message");
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+// Connects to the stratum server of a node and hashes the jobs it gets until the connection is closed
+pub async fn run_hasher(addr: SocketAddr) -> io::Result<()> {
+    let stream = TcpStream::connect(addr).await?;
+    println!("Connected to {}", addr);
+    let (reader, mut writer) = stream.into_split();
+    let (mut reader, mut buffer) = (BufReader::new(reader), vec![]);
+    write_message(&mut writer, &PoolMessage::Subscribe).await?;
+
+    let mut worker = PoolWorker::new(addr.to_string());
+    let (share_sender, mut share_rcv) = mpsc::unbounded_channel::<PoolShare>();
+    loop {
+        tokio::select! {
+            line = rpc::read_line(&mut reader, &mut buffer, MAX_JOB_LENGTH) => match line? {
+                Some(line) => match serde_json::from_str::<PoolMessage>(&line) {
+                    Ok(PoolMessage::Job(job)) => {
+                        println!("job: {} | block: {} | nonces: {}..{}", job.job_id, job.template.id, job.nonce_start, job.nonce_end);
+                        worker.start_job(job, share_sender.clone());
+                    }
+                    Ok(message @ (PoolMessage::Accepted(_) | PoolMessage::Rejected{..})) => {
+                        if let PoolMessage::Rejected{share, reason} = &message {
+                            println!("share {} rejected: {}", share.nonce, reason);
+                        }
+                        worker.record_result(&message);
+                    }
+                    _ => warn!("Invalid message from node: {}", line),
+                },
+                None => {
+                    println!("Connection closed | accepted: {} | rejected: {}", worker.shares_accepted, worker.shares_rejected);
+                    return Ok(());
+                }
+            },
+            Some(share) = share_rcv.recv() => {
+                if worker.job_id == Some(share.job_id) {
+                    worker.shares_found += 1;
+                    write_message(&mut writer, &PoolMessage::Share(share)).await?;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,335 @@
+// Every chain request we send opens a sync session. Its id is sent along with the request and
+// echoed in the response, so the logs on both sides of a sync can be correlated and responses
+// can be matched to the request they answer. Requests are only sent to peers that serve what we
+// ask for, according to the features of their handshake (see handshake.rs): complete chains to
+// peers serving bodies, snapshots to peers serving snapshots. Peers without a handshake, e.g. in
+// the simulation, are assumed to serve everything.
+//
+// Among the peers that serve what we ask for and have the block we sync to, we sync from the one
+// ranking highest. The ranking combines how often a peer's sync sessions succeeded (honesty), how
+// fast it answered (latency) and how high its best block is compared to the other peers' (work).
+//
+// Every finished session is also persisted as a SyncRecord (`sync history`), with what started it
+// and our latest block before and after it, so a replaced chain can be explained after the fact.
+use crate::blockchain::Checkpoint;
+use crate::handshake::{Features, Handshake};
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Number of finished sessions we keep for `sync sessions`
+pub const SYNC_SESSION_HISTORY: usize = 20;
+// Sessions without a response after this long are considered timed out
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+// Assumed response time of peers we did not sync from yet
+const DEFAULT_LATENCY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncOutcome {
+    Pending,
+    Success,
+    Failed(String),
+    TimedOut,
+}
+
+impl fmt::Display for SyncOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncOutcome::Pending => write!(f, "pending"),
+            SyncOutcome::Success => write!(f, "success"),
+            SyncOutcome::Failed(reason) => write!(f, "failed: {}", reason),
+            SyncOutcome::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+// Why we started a sync, with the height of the block that made us start it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncTrigger {
+    // A peer answered our latest block request with a higher block
+    LatestBlock(Height),
+    // A peer gossiped a block whose parent we do not have
+    OrphanBlock(Height),
+    // A peer announced a block whose parent we do not have, see propagation.rs
+    OrphanAnnouncement(Height),
+}
+
+impl SyncTrigger {
+    pub fn height(self) -> Height {
+        match self {
+            SyncTrigger::LatestBlock(height) | SyncTrigger::OrphanBlock(height) | SyncTrigger::OrphanAnnouncement(height) => height,
+        }
+    }
+}
+
+impl fmt::Display for SyncTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncTrigger::LatestBlock(height) => write!(f, "latest block at {}", height),
+            SyncTrigger::OrphanBlock(height) => write!(f, "orphan block at {}", height),
+            SyncTrigger::OrphanAnnouncement(height) => write!(f, "orphan announcement at {}", height),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncSession {
+    pub id: String,
+    pub peer: String,
+    pub trigger: SyncTrigger,
+    pub started_at: Instant,
+    // Set once the session is finished
+    pub duration: Option<Duration>,
+    pub blocks_transferred: usize,
+    pub outcome: SyncOutcome,
+}
+
+// What we know about a peer we may sync from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerStats {
+    // From the peer's handshake, None for peers without one
+    pub features: Option<Features>,
+    pub connected: bool,
+    // The highest block the peer told us about, in its handshake or its messages
+    pub best_block: Height,
+    // Sync sessions that delivered blocks we could add, and those that failed
+    pub valid: u64,
+    pub invalid: u64,
+    // Moving average of the response times of the peer's sync sessions, timeouts count as SYNC_TIMEOUT
+    pub latency: Option<Duration>,
+}
+
+impl PeerStats {
+    // Between 0 and 1, higher is better
+    pub fn score(&self, best_block: Height) -> f64 {
+        let honesty = (self.valid + 1) as f64 / (self.valid + self.invalid + 2) as f64;
+        let speed = 1.0 / (1.0 + self.latency.unwrap_or(DEFAULT_LATENCY).as_secs_f64());
+        let work = match best_block {
+            Height::GENESIS => 1.0,
+            best_block => self.best_block.0.min(best_block.0) as f64 / best_block.0 as f64,
+        };
+        honesty * speed * work
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncManager {
+    // Most recent session first
+    sessions: VecDeque<SyncSession>,
+    peers: HashMap<String, PeerStats>,
+}
+
+impl SyncManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Opens a session with the peer and returns its id, or None if a sync with the peer is already running
+    pub fn start(&mut self, peer: &str, trigger: SyncTrigger, now: Instant) -> Option<String> {
+        if self.is_syncing_with(peer) {
+            return None;
+        }
+
+        let id = new_session_id();
+        self.sessions.push_front(SyncSession {
+            id: id.clone(),
+            peer: peer.to_owned(),
+            trigger,
+            started_at: now,
+            duration: None,
+            blocks_transferred: 0,
+            outcome: SyncOutcome::Pending,
+        });
+        self.sessions.truncate(SYNC_SESSION_HISTORY);
+        Some(id)
+    }
+
+    pub fn is_pending(&self, id: &str) -> bool {
+        self.sessions
+            .iter()
+            .any(|session| session.id == id && session.outcome == SyncOutcome::Pending)
+    }
+
+    // Records the outcome of a pending session. Returns None for unknown or already finished sessions.
+    pub fn finish(
+        &mut self,
+        id: &str,
+        blocks_transferred: usize,
+        outcome: SyncOutcome,
+        now: Instant,
+    ) -> Option<&SyncSession> {
+        let session = self
+            .sessions
+            .iter_mut()
+            .find(|session| session.id == id && session.outcome == SyncOutcome::Pending)?;
+        session.duration = Some(now.duration_since(session.started_at));
+        session.blocks_transferred = blocks_transferred;
+        session.outcome = outcome;
+        let stats = self.peers.entry(session.peer.clone()).or_insert_with(new_peer);
+        match session.outcome {
+            SyncOutcome::Success => stats.valid += 1,
+            _ => stats.invalid += 1,
+        }
+        record_latency(stats, now.duration_since(session.started_at));
+        Some(session)
+    }
+
+    // Times out all pending sessions older than SYNC_TIMEOUT and returns them
+    pub fn expire(&mut self, now: Instant) -> Vec<SyncSession> {
+        let mut expired = vec![];
+        for session in self.sessions.iter_mut() {
+            let age = now.duration_since(session.started_at);
+            if session.outcome == SyncOutcome::Pending && age >= SYNC_TIMEOUT {
+                session.duration = Some(age);
+                session.outcome = SyncOutcome::TimedOut;
+                record_latency(self.peers.entry(session.peer.clone()).or_insert_with(new_peer), SYNC_TIMEOUT);
+                expired.push(session.clone());
+            }
+        }
+        expired
+    }
+
+    // Remembers what the peer serves and its best block, or marks it disconnected. What the peer
+    // delivered before is kept.
+    pub fn set_handshake(&mut self, peer: &str, handshake: Option<&Handshake>) {
+        let stats = self.peers.entry(peer.to_owned()).or_insert_with(new_peer);
+        stats.features = handshake.map(|handshake| handshake.features);
+        stats.connected = handshake.is_some();
+        if let Some(handshake) = handshake {
+            stats.best_block = stats.best_block.max(handshake.best_block.id);
+        }
+    }
+
+    // The peer told us about a block
+    pub fn record_block(&mut self, peer: &str, height: Height) {
+        let stats = self.peers.entry(peer.to_owned()).or_insert_with(new_peer);
+        stats.best_block = stats.best_block.max(height);
+    }
+
+    pub fn serves(&self, peer: &str, features: Features) -> bool {
+        self.peers
+            .get(peer)
+            .and_then(|stats| stats.features)
+            .is_none_or(|served| served.contains(features))
+    }
+
+    // The peer to sync to the block at the height from, after the peer that told us about it. That
+    // peer is kept unless a connected peer serving the features, having the block and not syncing
+    // with us already ranks higher.
+    pub fn select_source(&self, peer: &str, features: Features, height: Height) -> String {
+        let best_block = self.best_block();
+        let score = |peer: &str| {
+            self.peers
+                .get(peer)
+                .map_or_else(|| PeerStats { best_block: height, ..new_peer() }, |stats| stats.clone())
+                .score(best_block)
+        };
+        let mut source = (peer.to_owned(), score(peer));
+        for (candidate, stats) in self.peers.iter() {
+            if candidate == peer
+                || !stats.connected
+                || !self.serves(candidate, features)
+                || stats.best_block < height
+                || self.is_syncing_with(candidate)
+            {
+                continue;
+            }
+            let candidate_score = stats.score(best_block);
+            if candidate_score > source.1 {
+                source = (candidate.clone(), candidate_score);
+            }
+        }
+        source.0
+    }
+
+    // Connected peers, the highest ranking first
+    pub fn ranking(&self) -> Vec<(String, f64, PeerStats)> {
+        let best_block = self.best_block();
+        let mut ranking = self
+            .peers
+            .iter()
+            .filter(|(_, stats)| stats.connected)
+            .map(|(peer, stats)| (peer.clone(), stats.score(best_block), stats.clone()))
+            .collect::<Vec<_>>();
+        ranking.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranking
+    }
+
+    pub fn connected_peers(&self) -> usize {
+        self.peers.values().filter(|stats| stats.connected).count()
+    }
+
+    fn best_block(&self) -> Height {
+        self.peers
+            .values()
+            .filter(|stats| stats.connected)
+            .map(|stats| stats.best_block)
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn is_syncing_with(&self, peer: &str) -> bool {
+        self.sessions
+            .iter()
+            .any(|session| session.peer == peer && session.outcome == SyncOutcome::Pending)
+    }
+
+    // Most recent session first
+    pub fn sessions(&self) -> impl Iterator<Item = &SyncSession> {
+        self.sessions.iter()
+    }
+}
+
+// Peers we learn about from their messages count as connected until their handshake says otherwise
+fn new_peer() -> PeerStats {
+    PeerStats { connected: true, ..PeerStats::default() }
+}
+
+fn record_latency(stats: &mut PeerStats, latency: Duration) {
+    stats.latency = Some(match stats.latency {
+        Some(average) => (average * 3 + latency) / 4,
+        None => latency,
+    });
+}
+
+// A finished sync session as persisted for `sync history`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRecord {
+    pub session_id: String,
+    pub peer: String,
+    pub trigger: String,
+    pub blocks_transferred: u64,
+    pub duration_ms: u64,
+    pub outcome: String,
+    // Our latest block before and after the sync
+    pub old_tip: Checkpoint,
+    pub new_tip: Checkpoint,
+    // Whether the old tip is no longer part of our chain, i.e. the sync replaced blocks instead of
+    // only adding some
+    pub replaced: bool,
+    // Milliseconds since the epoch
+    pub finished_at: i64,
+}
+
+impl SyncRecord {
+    pub fn new(session: &SyncSession, old_tip: Checkpoint, new_tip: Checkpoint, replaced: bool, finished_at: i64) -> Self {
+        Self {
+            session_id: session.id.clone(),
+            peer: session.peer.clone(),
+            trigger: session.trigger.to_string(),
+            blocks_transferred: session.blocks_transferred as u64,
+            duration_ms: session.duration.unwrap_or_default().as_millis() as u64,
+            outcome: session.outcome.to_string(),
+            old_tip,
+            new_tip,
+            replaced,
+            finished_at,
+        }
+    }
+}
+
+pub fn new_session_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
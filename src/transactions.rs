@@ -0,0 +1,333 @@
+// Value transfers from the terminal (`tx create`, `tx sign`, `tx broadcast`, `tx get`, `tx status`):
+// a transaction moves an amount from the signing key of a wallet (see keys.rs) to a recipient address
+// and is signed by that key. The data of a block carries a single transaction (TRANSACTION_PREFIX)
+// or a batch of them (TRANSACTIONS_PREFIX, mined with Chain::mine_transactions, which Chain::mine_block
+// hands such data to), whose fees add up to the fee of the block. Block::transactions reads them back
+// and fails on data that only looks like transactions. A broadcast transaction waits in the transaction pool (see mempool.rs)
+// until it is mined in a batch, offering its fee to the miner. Transactions queued as the data of a
+// mining job (see mining.rs) are mined on their own. A transaction listing inputs spends unspent
+// outputs of its sender (see utxo.rs), for the others the amount is recorded but not checked against
+// what the sender owns.
+// Transactions from a multisig address carry its policy and the signatures of its keys instead of
+// the sender's signature (see multisig.rs).
+// Transactions are looked up by their ID, the SHA-256 of the signed fields, in the mining queue, the
+// transaction pool and the transaction index of the main chain.
+// The coinbase of a block (see blockchain.rs) is a transaction without sender that pays the block
+// reward and the fee of the block to its miner. It is part of the header, not of the block data.
+use crate::address;
+use crate::blockchain::{BlockchainError, Chain};
+use crate::mempool::TransactionPool;
+use crate::mining::{MiningJob, MiningQueue};
+use crate::multisig::{self, Multisig, PartialSignature};
+use crate::storage::Storage;
+use crate::types::Height;
+use crate::utxo::OutPoint;
+use libp2p::identity::ed25519;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Block data of transactions starts with this, followed by the JSON encoded Transaction
+pub const TRANSACTION_PREFIX: &str = "tx ";
+// Block data of a batch of transactions starts with this, followed by the JSON array of them
+pub const TRANSACTIONS_PREFIX: &str = "txs ";
+// Transactions a single block can carry
+pub const MAX_BLOCK_TRANSACTIONS: usize = 1_000;
+// Sender of coinbase transactions, the reward they pay is created by the block instead of being sent
+pub const COINBASE_SENDER: &str = "";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    // Hex encoded signing key of the sender, or the address of its multisig policy
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u64,
+    // Collected by the miner of the block, see fees.rs
+    pub fee: u64,
+    // Random, so that repeating a transfer gives a new transaction
+    pub nonce: u64,
+    // Hex encoded signature of the other fields by the sender's key, empty until signed
+    #[serde(default)]
+    pub signature: String,
+    // Set if the sender is a multisig address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<Multisig>,
+    // Signatures of the multisig keys, collected one after another
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<PartialSignature>,
+    // Outputs of the sender spent by the transaction (see utxo.rs). Omitted if empty, so transactions
+    // created before keep their ID.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<OutPoint>,
+}
+
+impl Transaction {
+    pub fn new(sender: &ed25519::PublicKey, recipient: String, amount: u64, fee: u64) -> Self {
+        Self {
+            sender: hex::encode(sender.encode()),
+            recipient,
+            amount,
+            fee,
+            nonce: rand::thread_rng().next_u64(),
+            signature: String::new(),
+            multisig: None,
+            signatures: vec![],
+            inputs: vec![],
+        }
+    }
+
+    pub fn new_multisig(sender: Multisig, recipient: String, amount: u64, fee: u64) -> Self {
+        Self {
+            sender: sender.address(),
+            recipient,
+            amount,
+            fee,
+            nonce: rand::thread_rng().next_u64(),
+            signature: String::new(),
+            multisig: Some(sender),
+            signatures: vec![],
+            inputs: vec![],
+        }
+    }
+
+    // Pays the reward and the fee of the block at the height to the recipient. The nonce is the
+    // height, so the coinbases of different blocks have different IDs.
+    pub fn coinbase(recipient: String, amount: u64, height: Height) -> Self {
+        Self {
+            sender: COINBASE_SENDER.to_owned(),
+            recipient,
+            amount,
+            fee: 0,
+            nonce: height.0,
+            signature: String::new(),
+            multisig: None,
+            signatures: vec![],
+            inputs: vec![],
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == COINBASE_SENDER
+    }
+
+    // Whether it is a well-formed coinbase of the block at the height: it pays an address and nothing
+    // else, so it has neither fee, inputs nor signatures. The amount is checked by Chain::violation,
+    // which knows the reward of the network.
+    pub fn check_coinbase(&self, height: Height) -> Result<(), BlockchainError> {
+        let invalid = |reason: String| Err(BlockchainError::Error(reason));
+        if !self.is_coinbase() {
+            return invalid(format!("coinbase has the sender {}", self.sender));
+        }
+        if self.fee != 0 || !self.inputs.is_empty() {
+            return invalid("coinbase pays a fee or spends inputs".to_owned());
+        }
+        if !self.signature.is_empty() || self.multisig.is_some() || !self.signatures.is_empty() {
+            return invalid("coinbase is signed".to_owned());
+        }
+        if self.nonce != height.0 {
+            return invalid(format!("coinbase nonce {} is not the height {}", self.nonce, height));
+        }
+        if address::parse(&self.recipient).ok().as_deref() != Some(self.recipient.as_str()) {
+            return invalid(format!("coinbase recipient {} is no address", self.recipient));
+        }
+        Ok(())
+    }
+
+    // The fields covered by the signatures
+    fn message(&self) -> Vec<u8> {
+        let unsigned = Self { signature: String::new(), signatures: vec![], ..self.clone() };
+        serde_json::to_vec(&unsigned).expect("can jsonify transaction")
+    }
+
+    // Hex encoded, the same before and after signing
+    pub fn id(&self) -> String {
+        hex::encode(Sha256::digest(self.message()))
+    }
+
+    // Whether it carries all signatures it needs, not whether they are valid
+    pub fn is_signed(&self) -> bool {
+        match &self.multisig {
+            Some(multisig) => self.signatures.len() >= multisig.threshold,
+            None => !self.signature.is_empty(),
+        }
+    }
+
+    pub fn sign(&mut self, keypair: &ed25519::Keypair) -> Result<(), BlockchainError> {
+        if self.multisig.is_some() {
+            return Err(BlockchainError::Error("multisig transactions are signed with tx sign --partial".to_owned()));
+        }
+        if hex::encode(keypair.public().encode()) != self.sender {
+            return Err(BlockchainError::Error(format!("transaction has to be signed by the sender {}", self.sender)));
+        }
+        self.signature = hex::encode(keypair.sign(&self.message()));
+        Ok(())
+    }
+
+    // Those of the keys that can sign the transaction: the sender's or those of its multisig policy
+    pub fn signers<'a>(&self, keys: &'a [ed25519::Keypair]) -> Vec<&'a ed25519::Keypair> {
+        let can_sign = |key: &String| match &self.multisig {
+            Some(multisig) => multisig.keys.contains(key),
+            None => *key == self.sender,
+        };
+        keys.iter().filter(|keypair| can_sign(&hex::encode(keypair.public().encode()))).collect()
+    }
+
+    // Adds the signature of one of the multisig keys and returns the number of keys that signed
+    pub fn sign_partial(&mut self, keypair: &ed25519::Keypair) -> Result<usize, BlockchainError> {
+        let multisig = self.multisig.as_ref().ok_or_else(|| BlockchainError::Error("no multisig transaction".to_owned()))?;
+        let signature = multisig.sign(keypair, &self.message())?;
+        self.signatures.retain(|kept| kept.key != signature.key);
+        self.signatures.push(signature);
+        multisig.count_signatures(&self.message(), &self.signatures)
+    }
+
+    // Adds the signatures of another copy of the transaction
+    pub fn combine(&mut self, other: &Transaction) -> Result<(), BlockchainError> {
+        if other.id() != self.id() {
+            return Err(BlockchainError::Error(format!("can not combine transaction {} with {}", self.id(), other.id())));
+        }
+        multisig::merge_signatures(&mut self.signatures, &other.signatures);
+        Ok(())
+    }
+
+    pub fn verify(&self) -> Result<(), BlockchainError> {
+        if let Some(multisig) = &self.multisig {
+            if self.sender != multisig.address() {
+                return Err(BlockchainError::Error(format!("sender {} is not the address of the multisig policy", self.sender)));
+            }
+            return multisig.verify(&self.message(), &self.signatures);
+        }
+        let key = hex::decode(&self.sender)
+            .ok()
+            .and_then(|key| ed25519::PublicKey::decode(&key).ok())
+            .ok_or_else(|| BlockchainError::Error(format!("invalid sender: {}", self.sender)))?;
+        let signature = hex::decode(&self.signature).unwrap_or_default();
+        if !key.verify(&self.message(), &signature) {
+            return Err(BlockchainError::Error("invalid transaction signature".to_owned()));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("can jsonify transaction")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, BlockchainError> {
+        serde_json::from_str(json).map_err(|err| BlockchainError::Error(format!("invalid transaction: {}", err)))
+    }
+
+    pub fn to_block_data(&self) -> String {
+        format!("{}{}", TRANSACTION_PREFIX, self.to_json())
+    }
+
+    // None if the data is no transaction
+    pub fn from_block_data(data: &str) -> Option<Result<Self, BlockchainError>> {
+        data.strip_prefix(TRANSACTION_PREFIX).map(Self::from_json)
+    }
+}
+
+// The block data carrying the transactions, a single one as Transaction::to_block_data does
+pub fn to_block_data(transactions: &[Transaction]) -> String {
+    match transactions {
+        [transaction] => transaction.to_block_data(),
+        _ => format!("{}{}", TRANSACTIONS_PREFIX, serde_json::to_string(transactions).expect("can jsonify transactions")),
+    }
+}
+
+// The transactions the block data carries, in order. None if the data carries none.
+pub fn from_block_data(data: &str) -> Option<Result<Vec<Transaction>, BlockchainError>> {
+    if let Some(transaction) = Transaction::from_block_data(data) {
+        return Some(transaction.map(|transaction| vec![transaction]));
+    }
+    let json = data.strip_prefix(TRANSACTIONS_PREFIX)?;
+    Some(serde_json::from_str(json).map_err(|err| BlockchainError::Error(format!("invalid transactions: {}", err))))
+}
+
+// The IDs of the transactions the block data carries, see the transaction index
+pub fn transaction_ids(data: &str) -> Vec<String> {
+    match from_block_data(data) {
+        Some(Ok(transactions)) => transactions.iter().map(Transaction::id).collect(),
+        _ => vec![],
+    }
+}
+
+// Blocks carrying transactions have to carry signed ones. A batch holds 1 to MAX_BLOCK_TRANSACTIONS
+// transactions, each of them once.
+pub fn check_block_data(data: &str) -> Result<(), BlockchainError> {
+    let transactions = match from_block_data(data) {
+        Some(transactions) => transactions?,
+        None => return Ok(()),
+    };
+    if transactions.is_empty() || transactions.len() > MAX_BLOCK_TRANSACTIONS {
+        return Err(BlockchainError::Error(format!("a block carries 1 to {} transactions", MAX_BLOCK_TRANSACTIONS)));
+    }
+    let mut ids = HashSet::new();
+    for transaction in transactions.iter() {
+        if !ids.insert(transaction.id()) {
+            return Err(BlockchainError::Error(format!("transaction {} is carried twice", transaction.id())));
+        }
+        transaction.verify()?;
+    }
+    Ok(())
+}
+
+// The fee of a block carrying the transactions
+pub fn total_fee(transactions: &[Transaction]) -> u64 {
+    transactions.iter().fold(0, |fee, transaction| fee.saturating_add(transaction.fee))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    // Waiting in the mempool as the mining job
+    Pending(u64),
+    // Waiting in the transaction pool
+    Pooled,
+    Confirmed { height: Height, confirmations: u64 },
+    // Broadcast by us, but neither in the mempool, the transaction pool nor on the main chain anymore:
+    // it expired, was evicted or its block was reorged out
+    Dropped,
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxStatus::Pending(job) => write!(f, "pending as mining job {}", job),
+            TxStatus::Pooled => write!(f, "pending in the transaction pool"),
+            TxStatus::Confirmed { height, confirmations } => write!(f, "confirmed at height {} ({} confirmations)", height, confirmations),
+            TxStatus::Dropped => write!(f, "dropped"),
+        }
+    }
+}
+
+// Looks the transaction up in the mempool, the transaction pool, the main chain and the transactions
+// we broadcast, in that order. None if we never saw it.
+pub async fn find(
+    chain: &Chain,
+    storage: &mut Storage,
+    queue: &MiningQueue,
+    pool: &TransactionPool,
+    broadcast: &HashMap<String, Transaction>,
+    id: &str,
+) -> Result<Option<(Transaction, TxStatus)>, BlockchainError> {
+    if let Some((job, transaction)) = queue.jobs().iter().find_map(|job| pending(job).filter(|(_, transaction)| transaction.id() == id)) {
+        return Ok(Some((transaction, TxStatus::Pending(job))));
+    }
+    if let Some(transaction) = pool.get(id) {
+        return Ok(Some((transaction, TxStatus::Pooled)));
+    }
+    if let Some(block) = Chain::get_transaction_blocks(storage, id).await?.into_iter().next() {
+        if let Some(transaction) = block.transactions()?.into_iter().find(|transaction| transaction.id() == id) {
+            return Ok(Some((transaction, TxStatus::Confirmed { height: block.id, confirmations: chain.confirmations(&block) })));
+        }
+    }
+    Ok(broadcast.get(id).map(|transaction| (transaction.clone(), TxStatus::Dropped)))
+}
+
+fn pending(job: &MiningJob) -> Option<(u64, Transaction)> {
+    match Transaction::from_block_data(&job.data)? {
+        Ok(transaction) => Some((job.id, transaction)),
+        Err(_) => None,
+    }
+}
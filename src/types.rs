@@ -1,9 +1,134 @@
-use crate::blockchain::Block;
+use crate::blockchain::{Block, BlockchainError, Checkpoint};
+use crate::deadletter::BadMessage;
+use crate::endpoints::ServiceAnnouncement;
+use crate::fastsync::ChainSnapshot;
+use crate::handshake::{Features, Handshake};
+use crate::pool::PoolMessage;
+use crate::propagation::BlockAnnouncement;
+use crate::rpc::{RpcCall, RpcReply};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::path::PathBuf;
 
-#[derive(Debug, PartialEq)]
+// Height of a block in the chain, the genesis block has height 0
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct Height(pub u64);
+
+impl Height {
+    pub const GENESIS: Height = Height(0);
+
+    pub fn checked_sub(self, blocks: u64) -> Option<Height> {
+        self.0.checked_sub(blocks).map(Height)
+    }
+
+    pub fn saturating_sub(self, blocks: u64) -> Height {
+        Height(self.0.saturating_sub(blocks))
+    }
+}
+
+impl Add<u64> for Height {
+    type Output = Height;
+
+    fn add(self, blocks: u64) -> Height {
+        Height(self.0 + blocks)
+    }
+}
+
+impl Sub<u64> for Height {
+    type Output = Height;
+
+    fn sub(self, blocks: u64) -> Height {
+        Height(self.0 - blocks)
+    }
+}
+
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Postgres has no unsigned integers, heights are stored as INT8
+impl TryFrom<i64> for Height {
+    type Error = BlockchainError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        u64::try_from(value)
+            .map(Height)
+            .map_err(|_| BlockchainError::Error(format!("invalid height: {}", value)))
+    }
+}
+
+impl TryFrom<Height> for i64 {
+    type Error = BlockchainError;
+
+    fn try_from(value: Height) -> Result<Self, Self::Error> {
+        i64::try_from(value.0).map_err(|_| BlockchainError::Error(format!("height out of range: {}", value)))
+    }
+}
+
+// Nonce (or extra nonce) of a block
+#[derive(Serialize, Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct Nonce(pub u64);
+
+impl Nonce {
+    // Nonces are stored as INT8, so larger ones are never used
+    pub const MAX: Nonce = Nonce(i64::MAX as u64);
+}
+
+impl fmt::Display for Nonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl TryFrom<i64> for Nonce {
+    type Error = BlockchainError;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        u64::try_from(value)
+            .map(Nonce)
+            .map_err(|_| BlockchainError::Error(format!("invalid nonce: {}", value)))
+    }
+}
+
+impl TryFrom<Nonce> for i64 {
+    type Error = BlockchainError;
+
+    fn try_from(value: Nonce) -> Result<Self, Self::Error> {
+        i64::try_from(value.0).map_err(|_| BlockchainError::Error(format!("nonce out of range: {}", value)))
+    }
+}
+
+// Gossipsub score of a peer together with our own ban decision, persisted so a restart
+// does not reset a peer's reputation
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct PeerScore {
+    pub peer_id: String,
+    pub score: f64,
+    pub banned: bool,
+    // Listen addresses the peer was last reachable at
+    pub addresses: Vec<String>,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventType {
     InitDone,
     ListPeers,
+    // Asks all connected peers for their latest block, to sync with them
+    RequestLatestBlocks,
+    // Prints the dead letters kept by the p2p task, see deadletter.rs
+    ListBadMessages,
+    // Feeds the received messages of a capture through the message handler again, see capture.rs
+    ReplayCapture{path: PathBuf},
+    // The events decoded from a capture, to be handled by a copy of the node
+    ReplayEvents{events: Vec<EventType>},
+    // A message of a peer that could not be decoded, to be persisted
+    ReceivedBadMessage(BadMessage),
     SendLatestBlockRequest {
         receiver: String
     },
@@ -16,18 +141,151 @@ pub enum EventType {
         block: Block
     },
     SendNewBlock(Block),
-    ReceivedNewBlock(Block),
+    ReceivedNewBlock {
+        sender: String,
+        block: Block
+    },
+    // Gossiped instead of the new block with `--propagation announce`, see propagation.rs
+    ReceivedBlockAnnouncement {
+        sender: String,
+        announcement: BlockAnnouncement
+    },
+    // Asks the announcing peer for the body of an announced block, which arrives as ReceivedNewBlock
+    SendBlockRequest {
+        receiver: String,
+        hash: String
+    },
+    ReceivedBlockRequest {
+        receiver: String,
+        hash: String
+    },
+    SendBlock {
+        receiver: String,
+        block: Block
+    },
     SendChain {
         receiver: String,
+        session_id: String,
         chain: Vec<Block>
     },
+    // Asks for the blocks from the height on, the complete chain from the genesis block or the tail
+    // above our latest block if our chain was restored from a snapshot
     SendChainRequest {
-        receiver: String
+        receiver: String,
+        session_id: String,
+        from: Height
     },
     ReceivedChainRequest {
-        receiver: String
+        receiver: String,
+        session_id: String,
+        from: Height
     },
     ReceivedChain {
+        sender: String,
+        session_id: String,
         chain: Vec<Block>
+    },
+    // A received complete chain after the sync worker checked it, see workers.rs. The error is set
+    // if it is invalid.
+    ReceivedCheckedChain {
+        sender: String,
+        session_id: String,
+        chain: Vec<Block>,
+        error: Option<String>
+    },
+    // Asks for a snapshot of the chain instead of the chain, see fastsync.rs
+    SendSnapshotRequest {
+        receiver: String,
+        session_id: String
+    },
+    ReceivedSnapshotRequest {
+        receiver: String,
+        session_id: String
+    },
+    SendSnapshot {
+        receiver: String,
+        session_id: String,
+        snapshot: ChainSnapshot
+    },
+    ReceivedSnapshot {
+        sender: String,
+        session_id: String,
+        snapshot: ChainSnapshot
+    },
+    SendFinalizedCheckpoint {
+        checkpoint: Checkpoint
+    },
+    ReceivedFinalizedCheckpoint {
+        sender: String,
+        checkpoint: Checkpoint
+    },
+    // The public endpoints of our APIs, see endpoints.rs. Signed by the p2p layer.
+    SendServiceAnnouncement {
+        announcement: ServiceAnnouncement
+    },
+    ReceivedServiceAnnouncement {
+        sender: String,
+        announcement: ServiceAnnouncement
+    },
+    // Our latest block or features changed, they are sent to new peers in our handshake, see handshake.rs
+    UpdateHandshake {
+        best_block: Checkpoint,
+        features: Features
+    },
+    // The handshake of a peer, with what it serves and its best block. None once it disconnected.
+    PeerHandshake {
+        peer: String,
+        handshake: Option<Handshake>
+    },
+    // The number of peers the p2p layer keeps connected changed, see reload.rs
+    UpdateTargetPeers {
+        target_peers: usize
+    },
+    // The addresses we listen on changed, see nodeinfo.rs
+    ListenAddressesChanged {
+        addresses: Vec<String>
+    },
+    // Scores loaded from storage on start-up
+    RestorePeerScores {
+        scores: Vec<PeerScore>
+    },
+    // Current scores of our peers, to be persisted
+    PeerScoresUpdated {
+        scores: Vec<PeerScore>
+    },
+    // Tells the miner of a generated block when we received it (milliseconds since the epoch), see loadgen.rs
+    SendBlockReceipt {
+        receiver: String,
+        hash: String,
+        received_at: i64
+    },
+    ReceivedBlockReceipt {
+        sender: String,
+        hash: String,
+        received_at: i64
+    },
+    // Sent over the pool protocol between a pool coordinator and its workers, see pool.rs
+    SendPoolMessage {
+        receiver: String,
+        message: PoolMessage
+    },
+    ReceivedPoolMessage {
+        sender: String,
+        message: PoolMessage
+    },
+    // A call of a client of the RPC server and the reply to it, see rpc.rs
+    ReceivedRpcCall {
+        sender: String,
+        call: RpcCall
+    },
+    SendRpcReply {
+        receiver: String,
+        reply: RpcReply
+    },
+    // An event of one of the additional chains we follow, see chains.rs. Events of the default
+    // chain are not wrapped.
+    ForChain {
+        chain: String,
+        event: Box<EventType>
     }
 }
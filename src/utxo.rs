@@ -0,0 +1,220 @@
+// Unspent transaction outputs (`address utxos ADDRESS`): every transaction of a main chain block
+// creates outputs, output 0 pays the amount to the recipient and, if the transaction spends inputs,
+// output 1 pays the change (the inputs minus amount and fee) back to the sender. A transaction
+// listing inputs (`tx create --inputs TXID:VOUT,...`) spends those outputs, which have to be unspent
+// and belong to its sender, and their value has to cover amount and fee. Transactions without inputs
+// are transfers as before, whose amount is not checked: they only create the recipient's output.
+// The coinbase of a block (see transactions.rs) creates an output paying the miner, which can be
+// spent from the next block on. Transactions spending other outputs have no effect, from the
+// UtxoSpends upgrade on blocks carrying them are rejected (see consensus.rs).
+// With Postgres the set is kept in the utxos table, updated along with the indexes when blocks are
+// added and removed (see storage.rs); the in-memory storage derives it from its blocks. A chain
+// restored from a snapshot lacks the outputs created below its base.
+use crate::blockchain::{Block, BlockchainError};
+use crate::transactions::{self, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+// Outputs of a transaction
+pub const RECIPIENT_OUTPUT: u32 = 0;
+pub const CHANGE_OUTPUT: u32 = 1;
+// Option of `tx create` listing the outputs to spend
+const INPUTS_OPTION: &str = "--inputs ";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub txid: String,
+    pub vout: u32,
+}
+
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.txid, self.vout)
+    }
+}
+
+// Parses "TXID:VOUT"
+impl FromStr for OutPoint {
+    type Err = BlockchainError;
+
+    fn from_str(outpoint: &str) -> Result<Self, Self::Err> {
+        match outpoint.split_once(':') {
+            Some((txid, vout)) if !txid.is_empty() => match vout.parse::<u32>() {
+                Ok(vout) => Ok(OutPoint { txid: txid.to_owned(), vout }),
+                Err(_) => Err(BlockchainError::Error(format!("invalid output: {} (TXID:VOUT)", outpoint))),
+            },
+            _ => Err(BlockchainError::Error(format!("invalid output: {} (TXID:VOUT)", outpoint))),
+        }
+    }
+}
+
+// Splits "--inputs TXID:VOUT,... REST" into the outputs and the rest, no outputs without the option
+pub fn split_inputs(args: &str) -> Result<(Vec<OutPoint>, &str), BlockchainError> {
+    let rest = match args.strip_prefix(INPUTS_OPTION) {
+        Some(rest) => rest.trim_start(),
+        None => return Ok((vec![], args)),
+    };
+    let (inputs, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    if inputs.is_empty() {
+        return Err(BlockchainError::Error("--inputs requires outputs (TXID:VOUT,...)".to_owned()));
+    }
+    let inputs = inputs.split(',').map(OutPoint::from_str).collect::<Result<Vec<_>, _>>()?;
+    Ok((inputs, rest))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub address: String,
+    pub amount: u64,
+    // The main chain block that created it
+    pub block_hash: String,
+}
+
+// What a block changes in the set: the outputs it spends that were unspent below it and the outputs
+// it creates that are still unspent after it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockUtxos {
+    pub spent: Vec<OutPoint>,
+    pub created: Vec<Utxo>,
+    // IDs of the transactions spending outputs they may not, with the reason
+    pub invalid: Vec<(String, String)>,
+}
+
+// The outputs the transactions of the block spend, to look up which of them are unspent below it
+pub fn inputs(block: &Block) -> Vec<OutPoint> {
+    block.transactions().unwrap_or_default().into_iter().flat_map(|transaction| transaction.inputs).collect()
+}
+
+// The value of the inputs, if the transaction may spend them. unspent looks up an unspent output.
+pub fn check_spends(transaction: &Transaction, unspent: impl Fn(&OutPoint) -> Option<Utxo>) -> Result<u64, BlockchainError> {
+    let mut value: u64 = 0;
+    let mut seen = HashSet::new();
+    for input in transaction.inputs.iter() {
+        if !seen.insert(input) {
+            return Err(BlockchainError::DoubleSpend(format!("output {} is spent twice", input)));
+        }
+        let utxo = unspent(input).ok_or_else(|| BlockchainError::Error(format!("output {} is not unspent", input)))?;
+        if utxo.address != transaction.sender {
+            return Err(BlockchainError::Error(format!("output {} does not belong to {}", input, transaction.sender)));
+        }
+        value = value.saturating_add(utxo.amount);
+    }
+    if !transaction.inputs.is_empty() && value < transaction.amount.saturating_add(transaction.fee) {
+        return Err(BlockchainError::Error(format!("inputs of {} do not cover amount and fee", value)));
+    }
+    Ok(value)
+}
+
+// The outputs the transaction creates, given the value of its inputs
+pub fn outputs(transaction: &Transaction, input_value: u64, block_hash: &str) -> Vec<Utxo> {
+    let txid = transaction.id();
+    let output = |vout: u32, address: &str, amount: u64| Utxo {
+        outpoint: OutPoint { txid: txid.clone(), vout },
+        address: address.to_owned(),
+        amount,
+        block_hash: block_hash.to_owned(),
+    };
+    let mut outputs = vec![output(RECIPIENT_OUTPUT, &transaction.recipient, transaction.amount)];
+    let change = input_value.saturating_sub(transaction.amount.saturating_add(transaction.fee));
+    if !transaction.inputs.is_empty() && change > 0 {
+        outputs.push(output(CHANGE_OUTPUT, &transaction.sender, change));
+    }
+    outputs
+}
+
+// Applies the transactions of the block in order, unspent holds the outputs its inputs refer to that
+// are unspent below it. Like its receipt (see receipts.rs) a block carrying a transaction with
+// invalid signatures changes nothing but its coinbase output.
+pub fn apply_block(block: &Block, unspent: &HashMap<OutPoint, Utxo>) -> BlockUtxos {
+    let mut changes = BlockUtxos::default();
+    let coinbase = block.coinbase.iter().flat_map(|coinbase| outputs(coinbase, 0, &block.hash));
+    if transactions::check_block_data(&block.data).is_err() {
+        changes.created = coinbase.collect();
+        return changes;
+    }
+    let mut spent = HashSet::new();
+    let mut created = BTreeMap::new();
+    for transaction in block.transactions().unwrap_or_default() {
+        let lookup = |outpoint: &OutPoint| match spent.contains(outpoint) {
+            true => None,
+            false => created.get(outpoint).or_else(|| unspent.get(outpoint)).cloned(),
+        };
+        let value = match check_spends(&transaction, lookup) {
+            Ok(value) => value,
+            Err(err) => {
+                changes.invalid.push((transaction.id(), err.to_string()));
+                continue;
+            }
+        };
+        for input in transaction.inputs.iter() {
+            spent.insert(input.clone());
+            // Outputs created by the block are spent before they were stored
+            if created.remove(input).is_none() {
+                changes.spent.push(input.clone());
+            }
+        }
+        for output in outputs(&transaction, value, &block.hash) {
+            created.insert(output.outpoint.clone(), output);
+        }
+    }
+    changes.created = created.into_values().chain(coinbase).collect();
+    changes
+}
+
+// The set in memory, for the in-memory storage. Spent outputs are kept with the hash of the block
+// spending them, like in the utxos table.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    unspent: HashMap<OutPoint, Utxo>,
+    spent: HashMap<OutPoint, String>,
+}
+
+impl UtxoSet {
+    // The set after the main chain blocks, oldest first
+    pub fn from_blocks<'a>(blocks: impl IntoIterator<Item = &'a Block>) -> Self {
+        let mut set = Self::default();
+        for block in blocks {
+            set.apply(block);
+        }
+        set
+    }
+
+    pub fn apply(&mut self, block: &Block) {
+        let changes = apply_block(block, &self.unspent);
+        for outpoint in changes.spent {
+            self.unspent.remove(&outpoint);
+            self.spent.insert(outpoint, block.hash.clone());
+        }
+        // Like the utxos table, which ignores outputs it holds already, an output created again
+        // keeps the block it was created in first and stays spent once it is
+        for utxo in changes.created {
+            if !self.spent.contains_key(&utxo.outpoint) {
+                self.unspent.entry(utxo.outpoint.clone()).or_insert(utxo);
+            }
+        }
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&Utxo> {
+        self.unspent.get(outpoint)
+    }
+
+    // The hash of the block that spent the output
+    pub fn spent_in(&self, outpoint: &OutPoint) -> Option<&String> {
+        self.spent.get(outpoint)
+    }
+
+    // Ordered by transaction ID and output
+    pub fn for_address(&self, address: &str) -> Vec<Utxo> {
+        let mut utxos = self.unspent.values().filter(|utxo| utxo.address == address).cloned().collect::<Vec<_>>();
+        utxos.sort_by(|a, b| a.outpoint.cmp(&b.outpoint));
+        utxos
+    }
+}
+
+// The summed amount of the outputs
+pub fn balance(utxos: &[Utxo]) -> u64 {
+    utxos.iter().fold(0, |balance, utxo| balance.saturating_add(utxo.amount))
+}
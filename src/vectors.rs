@@ -0,0 +1,224 @@
+// Golden vectors: fixed blocks together with the header bytes they hash (blockchain::header_bytes),
+// the hash they get and the JSON they are sent and stored as, one per header version and hash
+// encoding plus one for string escaping. Hashes and headers are consensus: a change to hasher or to
+// the header serialization that alters any of them splits us from the network. So the vectors are
+// never regenerated, tests/vectors_tests.rs fails loudly if the code no longer reproduces them.
+use crate::blockchain::{self, Block};
+use crate::transactions::Transaction;
+use crate::types::{Height, Nonce};
+
+pub struct HeaderVector {
+    pub name: &'static str,
+    pub id: u64,
+    pub version: u8,
+    pub prev_hash: &'static str,
+    pub miner: &'static str,
+    pub data: &'static str,
+    pub timestamp: i64,
+    pub nonce: u64,
+    pub extra_nonce: u64,
+    pub difficulty: u64,
+    pub fee: u64,
+    pub state_root: &'static str,
+    // Recipient and amount of the coinbase, whose nonce is the height
+    pub coinbase: Option<(&'static str, u64)>,
+    // The hashed header
+    pub header: &'static str,
+    pub hash: &'static str,
+    // The block serialized with serde_json
+    pub json: &'static str,
+}
+
+impl HeaderVector {
+    pub fn block(&self) -> Block {
+        Block {
+            hash: self.hash.to_owned(),
+            id: Height(self.id),
+            prev_hash: self.prev_hash.to_owned(),
+            timestamp: self.timestamp,
+            nonce: Nonce(self.nonce),
+            data: self.data.to_owned(),
+            version: self.version,
+            miner: self.miner.to_owned(),
+            extra_nonce: Nonce(self.extra_nonce),
+            difficulty: self.difficulty,
+            fee: self.fee,
+            state_root: self.state_root.to_owned(),
+            coinbase: self.coinbase.map(|(recipient, amount)| Transaction::coinbase(recipient.to_owned(), amount, Height(self.id))),
+        }
+    }
+
+    // Describes what the code produces differently from the vector, if anything
+    pub fn check(&self) -> Result<(), String> {
+        let block = self.block();
+        let header = String::from_utf8(blockchain::header_bytes(&block)).map_err(|err| format!("{}: header is no UTF-8: {}", self.name, err))?;
+        if header != self.header {
+            return Err(format!("{}: header {} instead of {}", self.name, header, self.header));
+        }
+        let hash = blockchain::hasher(&block);
+        if hash != self.hash {
+            return Err(format!("{}: hash {} instead of {}", self.name, hash, self.hash));
+        }
+        let json = serde_json::to_string(&block).map_err(|err| format!("{}: {}", self.name, err))?;
+        if json != self.json {
+            return Err(format!("{}: JSON {} instead of {}", self.name, json, self.json));
+        }
+        match serde_json::from_str::<Block>(self.json) {
+            Ok(parsed) if parsed == block => Ok(()),
+            Ok(parsed) => Err(format!("{}: JSON parsed as {:?}", self.name, parsed)),
+            Err(err) => Err(format!("{}: JSON does not parse: {}", self.name, err)),
+        }
+    }
+}
+
+pub const HEADER_VECTORS: [HeaderVector; 8] = [
+    HeaderVector {
+        name: "v0-legacy-encoding",
+        id: 1,
+        version: 0,
+        prev_hash: "0A31F6A1DB36EEDF9AA5C56AB90DCC76A3ABD90C77B1198336FD1AE512193F",
+        miner: "",
+        data: "block 1",
+        timestamp: 1_600_000_000,
+        nonce: 42,
+        extra_nonce: 0,
+        difficulty: 0,
+        fee: 0,
+        state_root: "",
+        coinbase: None,
+        header: r#"{"data":"block 1","nonce":42,"prev_hash":"0A31F6A1DB36EEDF9AA5C56AB90DCC76A3ABD90C77B1198336FD1AE512193F","timestamp":1600000000}"#,
+        hash: "22D09FE0F6D0D3B7F31CF06DAFE95D9B5754D0C810E93AA5A58389B294606F",
+        json: r#"{"hash":"22D09FE0F6D0D3B7F31CF06DAFE95D9B5754D0C810E93AA5A58389B294606F","id":1,"prev_hash":"0A31F6A1DB36EEDF9AA5C56AB90DCC76A3ABD90C77B1198336FD1AE512193F","timestamp":1600000000,"nonce":42,"data":"block 1","version":0,"miner":"","extra_nonce":0,"difficulty":0,"fee":0,"state_root":""}"#,
+    },
+    HeaderVector {
+        name: "v1-miner-and-extra-nonce",
+        id: 1_500,
+        version: 1,
+        prev_hash: "1A2B3C",
+        miner: "miner-a",
+        data: "block 1500",
+        timestamp: 1_600_015_000,
+        nonce: 7_000_001,
+        extra_nonce: 3,
+        difficulty: 0,
+        fee: 0,
+        state_root: "",
+        coinbase: None,
+        header: r#"{"data":"block 1500","extra_nonce":3,"miner":"miner-a","nonce":7000001,"prev_hash":"1A2B3C","timestamp":1600015000,"version":1}"#,
+        hash: "A4634E20F3BD16DCBB6B61956A2469DD3EBC01673F8B41E5D896715952ED4",
+        json: r#"{"hash":"A4634E20F3BD16DCBB6B61956A2469DD3EBC01673F8B41E5D896715952ED4","id":1500,"prev_hash":"1A2B3C","timestamp":1600015000,"nonce":7000001,"data":"block 1500","version":1,"miner":"miner-a","extra_nonce":3,"difficulty":0,"fee":0,"state_root":""}"#,
+    },
+    HeaderVector {
+        name: "v2-padded-hex-encoding",
+        id: 3_500,
+        version: 2,
+        prev_hash: "0000000000000000000000000000000000000000000000000000000000000000",
+        miner: "miner-b",
+        data: "",
+        timestamp: 1_600_035_000_123,
+        nonce: 18_446_744_073_709_551_615,
+        extra_nonce: 18_446_744_073_709_551_615,
+        difficulty: 0,
+        fee: 0,
+        state_root: "",
+        coinbase: None,
+        header: r#"{"data":"","extra_nonce":18446744073709551615,"miner":"miner-b","nonce":18446744073709551615,"prev_hash":"0000000000000000000000000000000000000000000000000000000000000000","timestamp":1600035000123,"version":2}"#,
+        hash: "38362B6E74465F327F9072A8740367431492BF6C6CEC3E017C091911E8D83608",
+        json: r#"{"hash":"38362B6E74465F327F9072A8740367431492BF6C6CEC3E017C091911E8D83608","id":3500,"prev_hash":"0000000000000000000000000000000000000000000000000000000000000000","timestamp":1600035000123,"nonce":18446744073709551615,"data":"","version":2,"miner":"miner-b","extra_nonce":18446744073709551615,"difficulty":0,"fee":0,"state_root":""}"#,
+    },
+    HeaderVector {
+        name: "v3-difficulty",
+        id: 4_500,
+        version: 3,
+        prev_hash: "ABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABAB",
+        miner: "miner-c",
+        data: "{\"recipient\":\"bob\",\"amount\":5}",
+        timestamp: 1_600_045_000_000,
+        nonce: 123_456,
+        extra_nonce: 1,
+        difficulty: 65_536,
+        fee: 0,
+        state_root: "",
+        coinbase: None,
+        header: r#"{"data":"{\"recipient\":\"bob\",\"amount\":5}","difficulty":65536,"extra_nonce":1,"miner":"miner-c","nonce":123456,"prev_hash":"ABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABAB","timestamp":1600045000000,"version":3}"#,
+        hash: "170EEC870161D651B3DF5B5DEA0E900DDDC9F1EC492F553B149291F31F15A792",
+        json: r#"{"hash":"170EEC870161D651B3DF5B5DEA0E900DDDC9F1EC492F553B149291F31F15A792","id":4500,"prev_hash":"ABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABAB","timestamp":1600045000000,"nonce":123456,"data":"{\"recipient\":\"bob\",\"amount\":5}","version":3,"miner":"miner-c","extra_nonce":1,"difficulty":65536,"fee":0,"state_root":""}"#,
+    },
+    HeaderVector {
+        name: "v4-fee",
+        id: 6_500,
+        version: 4,
+        prev_hash: "CDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCD",
+        miner: "miner-d",
+        data: "paid job",
+        timestamp: 1_600_065_000_000,
+        nonce: 99,
+        extra_nonce: 0,
+        difficulty: 9_223_372_036_854_775_807,
+        fee: 250,
+        state_root: "",
+        coinbase: None,
+        header: r#"{"data":"paid job","difficulty":9223372036854775807,"extra_nonce":0,"fee":250,"miner":"miner-d","nonce":99,"prev_hash":"CDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCD","timestamp":1600065000000,"version":4}"#,
+        hash: "6C157186E678093CA75B9355AD031EABE99BC7EA4E61444CC6D6A1DE62543030",
+        json: r#"{"hash":"6C157186E678093CA75B9355AD031EABE99BC7EA4E61444CC6D6A1DE62543030","id":6500,"prev_hash":"CDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCDCD","timestamp":1600065000000,"nonce":99,"data":"paid job","version":4,"miner":"miner-d","extra_nonce":0,"difficulty":9223372036854775807,"fee":250,"state_root":""}"#,
+    },
+    HeaderVector {
+        name: "v5-state-root",
+        id: 9_500,
+        version: 5,
+        prev_hash: "EFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEF",
+        miner: "miner-e",
+        data: "state",
+        timestamp: 1_600_095_000_000,
+        nonce: 5,
+        extra_nonce: 2,
+        difficulty: 1,
+        fee: 10,
+        state_root: "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        coinbase: None,
+        header: r#"{"data":"state","difficulty":1,"extra_nonce":2,"fee":10,"miner":"miner-e","nonce":5,"prev_hash":"EFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEF","state_root":"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef","timestamp":1600095000000,"version":5}"#,
+        hash: "7F9E084F2178A97920B92D17CDC5F26B1CC476101D7580055F57D37706BB30C0",
+        json: r#"{"hash":"7F9E084F2178A97920B92D17CDC5F26B1CC476101D7580055F57D37706BB30C0","id":9500,"prev_hash":"EFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEF","timestamp":1600095000000,"nonce":5,"data":"state","version":5,"miner":"miner-e","extra_nonce":2,"difficulty":1,"fee":10,"state_root":"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"}"#,
+    },
+    HeaderVector {
+        name: "v5-string-escaping",
+        id: 9_501,
+        version: 5,
+        prev_hash: "EFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEF",
+        miner: "minér €",
+        data: "say \"hi\" \\ back\nslash\ttab \u{1} naïve ☃",
+        timestamp: 1_600_095_010_000,
+        nonce: 0,
+        extra_nonce: 0,
+        difficulty: 1,
+        fee: 0,
+        state_root: "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        coinbase: None,
+        header: r#"{"data":"say \"hi\" \\ back\nslash\ttab \u0001 naïve ☃","difficulty":1,"extra_nonce":0,"fee":0,"miner":"minér €","nonce":0,"prev_hash":"EFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEF","state_root":"ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff","timestamp":1600095010000,"version":5}"#,
+        hash: "726615583826A0F6E84D10FF1ABD56954065A204D40FAA1D2FDBC8CE4E13B988",
+        json: r#"{"hash":"726615583826A0F6E84D10FF1ABD56954065A204D40FAA1D2FDBC8CE4E13B988","id":9501,"prev_hash":"EFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEFEF","timestamp":1600095010000,"nonce":0,"data":"say \"hi\" \\ back\nslash\ttab \u0001 naïve ☃","version":5,"miner":"minér €","extra_nonce":0,"difficulty":1,"fee":0,"state_root":"ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"}"#,
+    },
+    HeaderVector {
+        name: "v6-coinbase",
+        id: 11_500,
+        version: 6,
+        prev_hash: "A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1",
+        miner: "miner-f",
+        data: "coinbase",
+        timestamp: 1_600_115_000_000,
+        nonce: 12,
+        extra_nonce: 1,
+        difficulty: 1,
+        fee: 5,
+        state_root: "89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef",
+        coinbase: Some(("d9bf2148748a85c89da5aad8ee0b0fc2d105fd39d41a4c796536354f0ae2900c", 55)),
+        header: r#"{"coinbase":"0154d898cae93b15fc0f7ecd3a2e956401192391738757688a534e2541e7925e","data":"coinbase","difficulty":1,"extra_nonce":1,"fee":5,"miner":"miner-f","nonce":12,"prev_hash":"A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1","state_root":"89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef","timestamp":1600115000000,"version":6}"#,
+        hash: "1403E1E5EB90E4974A6DC8659CBCA663AE0C136F1C63A580F70D84CF6BD0C54D",
+        json: r#"{"hash":"1403E1E5EB90E4974A6DC8659CBCA663AE0C136F1C63A580F70D84CF6BD0C54D","id":11500,"prev_hash":"A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1A1","timestamp":1600115000000,"nonce":12,"data":"coinbase","version":6,"miner":"miner-f","extra_nonce":1,"difficulty":1,"fee":5,"state_root":"89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef89abcdef","coinbase":{"sender":"","recipient":"d9bf2148748a85c89da5aad8ee0b0fc2d105fd39d41a4c796536354f0ae2900c","amount":55,"fee":0,"nonce":11500,"signature":""}}"#,
+    },
+];
+
+// The problems of all vectors, empty if the code reproduces them
+pub fn check_all() -> Vec<String> {
+    HEADER_VECTORS.iter().filter_map(|vector| vector.check().err()).collect()
+}
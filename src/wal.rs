@@ -0,0 +1,101 @@
+// Write-ahead queue (`--wal PATH`): blocks accepted into the main chain are appended to a journal
+// on disk and acknowledged right away, instead of waiting for Postgres. The node writes the queued
+// blocks to Postgres in the background, oldest first, and empties the journal once all of them are
+// stored. Until then the queued blocks are served from the queue (see storage.rs). After a crash
+// the journaled blocks are written on start-up, skipping those that made it into the database.
+// Encrypted at rest every line is sealed on its own, see atrest.rs.
+use crate::atrest;
+use crate::blockchain::{Block, BlockchainError};
+use crate::types::Height;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// How often the queued blocks are written to Postgres
+pub const WAL_FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct WriteQueue {
+    path: PathBuf,
+    journal: File,
+    // Oldest first
+    blocks: VecDeque<Block>,
+}
+
+impl WriteQueue {
+    // Opens the journal, with the blocks that were journaled but maybe not written before a crash
+    pub fn open(path: &Path) -> Result<Self, BlockchainError> {
+        let journaled = match fs::read_to_string(path) {
+            Ok(journal) => journal,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        // A torn last line belongs to a block that was never acknowledged. Only that one can fail to
+        // be opened, any other line was sealed with another key.
+        let lines = journaled.lines().collect::<Vec<_>>();
+        let mut blocks = VecDeque::new();
+        for (i, line) in lines.iter().enumerate() {
+            let json = match atrest::open_file(line) {
+                Ok(json) => json,
+                Err(_) if i + 1 == lines.len() && !journaled.ends_with('\n') => break,
+                Err(err) => return Err(err),
+            };
+            match serde_json::from_str::<Block>(&json) {
+                Ok(block) => blocks.push_back(block),
+                Err(_) => break,
+            }
+        }
+
+        // Rewritten without the torn line, so new blocks are not appended to it
+        let tmp_path = path.with_extension("tmp");
+        let mut queue = Self { path: path.to_owned(), journal: File::create(&tmp_path)?, blocks: VecDeque::new() };
+        for block in blocks {
+            queue.push(block)?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(queue)
+    }
+
+    // Journals the block, it is acknowledged once this returns
+    pub fn push(&mut self, block: Block) -> Result<(), BlockchainError> {
+        let line = atrest::seal(&serde_json::to_string(&block).expect("can jsonify block"));
+        writeln!(self.journal, "{}", line)?;
+        self.journal.sync_data()?;
+        self.blocks.push_back(block);
+        Ok(())
+    }
+
+    pub fn front(&self) -> Option<&Block> {
+        self.blocks.front()
+    }
+
+    // Drops the oldest block after it was written, the journal is emptied with the last one
+    pub fn pop(&mut self) -> Result<(), BlockchainError> {
+        self.blocks.pop_front();
+        if self.blocks.is_empty() {
+            self.journal = OpenOptions::new().write(true).truncate(true).open(&self.path)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.hash == hash)
+    }
+
+    pub fn get_by_id(&self, id: Height) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.id == id)
+    }
+
+    pub fn last(&self) -> Option<&Block> {
+        self.blocks.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
@@ -0,0 +1,71 @@
+// The node's wallet (`--wallet FILE`): the keypair blocks we mine and the transactions we create are
+// attributed to, and the address derived from it. It is kept in a file of its own, encrypted like
+// the node keys (see keys.rs) with the passphrase in KEY_PASSPHRASE_ENV, and created on the first
+// start, so a node gets a lasting identity without a whole key directory. Without --wallet the
+// signing key of --keys DIR is the node's wallet. Unlike the HD wallet (see hdwallet.rs) it has a
+// single address.
+use crate::blockchain::BlockchainError;
+use crate::keys;
+use libp2p::identity::ed25519;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone)]
+pub struct Wallet {
+    keypair: ed25519::Keypair,
+}
+
+impl Wallet {
+    pub fn generate() -> Self {
+        Self::from_keypair(ed25519::Keypair::generate())
+    }
+
+    pub fn from_keypair(keypair: ed25519::Keypair) -> Self {
+        Self { keypair }
+    }
+
+    pub fn keypair(&self) -> &ed25519::Keypair {
+        &self.keypair
+    }
+
+    pub fn address(&self) -> String {
+        address(&self.keypair.public())
+    }
+
+    pub fn load(path: &Path, passphrase: &str) -> Result<Self, BlockchainError> {
+        let mut secret = keys::decrypt_key(&fs::read_to_string(path)?, passphrase)?;
+        ed25519::Keypair::decode(&mut secret)
+            .map(Self::from_keypair)
+            .map_err(|err| BlockchainError::Error(format!("invalid wallet in {}: {}", path.display(), err)))
+    }
+
+    // Written to a temporary file first, so a crash never leaves us with half a wallet
+    pub fn save(&self, path: &Path, passphrase: &str) -> Result<(), BlockchainError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, keys::encrypt_key(&self.keypair.encode(), passphrase))?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    // The wallet in the file, a new one if there is no file yet
+    pub fn load_or_generate(path: &Path, passphrase: &str) -> Result<Self, BlockchainError> {
+        if path.exists() {
+            return Wallet::load(path, passphrase);
+        }
+        let wallet = Wallet::generate();
+        wallet.save(path, passphrase)?;
+        Ok(wallet)
+    }
+}
+
+impl std::fmt::Debug for Wallet {
+    // Never the secret key
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Wallet").field("address", &self.address()).finish()
+    }
+}
+
+// The address of a public key, the hex encoded key like the senders and recipients of transactions
+pub fn address(public: &ed25519::PublicKey) -> String {
+    hex::encode(public.encode())
+}
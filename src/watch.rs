@@ -0,0 +1,72 @@
+// Watch-only addresses (`wallet watch ADDRESS [LABEL]`): addresses we hold no keys for, e.g. the
+// public key of a cold wallet or a multisig address, whose balance and transactions we track. An
+// address is tracked from the block after its import on, so transactions before it are not counted
+// until `wallet rescan --from-height H` makes all watched addresses count the blocks from H on.
+// Balances and histories are looked up in the address index when they are shown, so new blocks and
+// reorgs are reflected without updating the watch list, which is kept in the key directory next to
+// the wallet (see keys.rs).
+use crate::blockchain::{BlockchainError, Chain};
+use crate::hdwallet::{self, Balance};
+use crate::storage::Storage;
+use crate::transactions::Transaction;
+use crate::types::Height;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedAddress {
+    pub address: String,
+    pub label: String,
+    // Blocks below are not counted
+    pub from: Height,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchList {
+    pub addresses: Vec<WatchedAddress>,
+}
+
+impl WatchList {
+    // Starts tracking the address from the height on, an address watched already only gets the label
+    pub fn watch(&mut self, address: String, label: String, from: Height) -> Result<(), BlockchainError> {
+        if address.is_empty() || address.contains(char::is_whitespace) {
+            return Err(BlockchainError::Error(format!("invalid address: {}", address)));
+        }
+        match self.addresses.iter_mut().find(|watched| watched.address == address) {
+            Some(watched) => watched.label = label,
+            None => self.addresses.push(WatchedAddress { address, label, from }),
+        }
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, address: &str) -> bool {
+        let watched = self.addresses.len();
+        self.addresses.retain(|kept| kept.address != address);
+        self.addresses.len() < watched
+    }
+
+    // Makes every address count the blocks from the height on
+    pub fn rescan_from(&mut self, height: Height) {
+        for watched in self.addresses.iter_mut() {
+            watched.from = watched.from.min(height);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct History {
+    pub balance: Balance,
+    // Oldest first, with the height of their block
+    pub transactions: Vec<(Height, Transaction)>,
+}
+
+// The transactions of the address on the main chain from the height on
+pub async fn history(storage: &mut Storage, address: &str, from: Height) -> Result<History, BlockchainError> {
+    let mut blocks = Chain::get_address_blocks(storage, address).await?;
+    blocks.retain(|block| block.id >= from);
+    let transactions = blocks
+        .iter()
+        .flat_map(|block| block.transactions().unwrap_or_default().into_iter().map(|transaction| (block.id, transaction)))
+        .filter(|(_, transaction)| transaction.sender == address || transaction.recipient == address)
+        .collect();
+    Ok(History { balance: hdwallet::balance(address, &blocks), transactions })
+}
@@ -0,0 +1,154 @@
+// Watchdog for slow work: database queries (see repository.rs), validation passes (a block, a
+// received chain, `chain validate`) and the handling of gossip and other p2p events (see node.rs)
+// that take longer than their threshold are logged as warnings with what they were about (the
+// query with its parameters, the block hash, the peer) and counted. `node slow` and the
+// `getslowtasks` RPC method show the counts and the slowest occurrence of each kind since the start.
+// The thresholds are set with --slow-query-ms, --slow-validation-ms and --slow-gossip-ms and can be
+// reloaded (see reload.rs).
+use log::warn;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const DEFAULT_SLOW_QUERY: Duration = Duration::from_millis(100);
+pub const DEFAULT_SLOW_VALIDATION: Duration = Duration::from_secs(1);
+pub const DEFAULT_SLOW_GOSSIP: Duration = Duration::from_millis(250);
+// Subjects are cut after this many characters, e.g. queries with whole blocks as parameters
+pub const MAX_SUBJECT_LENGTH: usize = 200;
+
+static WATCHDOG: Lazy<Mutex<Watchdog>> = Lazy::new(|| Mutex::new(Watchdog::new(Thresholds::default())));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    Query,
+    Validation,
+    Gossip,
+}
+
+impl fmt::Display for TaskKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TaskKind::Query => "query",
+            TaskKind::Validation => "validation",
+            TaskKind::Gossip => "gossip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thresholds {
+    pub query: Duration,
+    pub validation: Duration,
+    pub gossip: Duration,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self { query: DEFAULT_SLOW_QUERY, validation: DEFAULT_SLOW_VALIDATION, gossip: DEFAULT_SLOW_GOSSIP }
+    }
+}
+
+impl Thresholds {
+    pub fn get(&self, kind: TaskKind) -> Duration {
+        match kind {
+            TaskKind::Query => self.query,
+            TaskKind::Validation => self.validation,
+            TaskKind::Gossip => self.gossip,
+        }
+    }
+}
+
+// The tasks of one kind that exceeded the threshold
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlowStats {
+    pub count: u64,
+    pub total_ms: u64,
+    pub slowest_ms: u64,
+    pub slowest_subject: String,
+}
+
+impl fmt::Display for SlowStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.count {
+            0 => write!(f, "none"),
+            _ => write!(f, "{} | total: {}ms | slowest: {}ms {}", self.count, self.total_ms, self.slowest_ms, self.slowest_subject),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchdogReport {
+    pub query: SlowStats,
+    pub validation: SlowStats,
+    pub gossip: SlowStats,
+}
+
+impl WatchdogReport {
+    fn stats_mut(&mut self, kind: TaskKind) -> &mut SlowStats {
+        match kind {
+            TaskKind::Query => &mut self.query,
+            TaskKind::Validation => &mut self.validation,
+            TaskKind::Gossip => &mut self.gossip,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    pub thresholds: Thresholds,
+    report: WatchdogReport,
+}
+
+impl Watchdog {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self { thresholds, report: WatchdogReport::default() }
+    }
+
+    // Counts the task if it took longer than the threshold of its kind and returns its subject then.
+    // The subject is only built for slow tasks.
+    pub fn observe(&mut self, kind: TaskKind, elapsed: Duration, subject: impl FnOnce() -> String) -> Option<String> {
+        if elapsed <= self.thresholds.get(kind) {
+            return None;
+        }
+        let subject = truncate(subject());
+        let elapsed_ms = elapsed.as_millis() as u64;
+        let stats = self.report.stats_mut(kind);
+        stats.count += 1;
+        stats.total_ms += elapsed_ms;
+        if elapsed_ms >= stats.slowest_ms {
+            stats.slowest_ms = elapsed_ms;
+            stats.slowest_subject = subject.clone();
+        }
+        Some(subject)
+    }
+
+    pub fn report(&self) -> WatchdogReport {
+        self.report.clone()
+    }
+}
+
+fn truncate(subject: String) -> String {
+    match subject.char_indices().nth(MAX_SUBJECT_LENGTH) {
+        Some((end, _)) => format!("{}...", &subject[..end]),
+        None => subject,
+    }
+}
+
+pub fn set_thresholds(thresholds: Thresholds) {
+    WATCHDOG.lock().unwrap().thresholds = thresholds;
+}
+
+// Observes a task with the global watchdog, slow ones are logged
+pub fn observe(kind: TaskKind, elapsed: Duration, subject: impl FnOnce() -> String) {
+    let slow = WATCHDOG.lock().unwrap().observe(kind, elapsed, subject);
+    if let Some(subject) = slow {
+        warn!("Slow {} ({}ms): {}", kind, elapsed.as_millis(), subject);
+    }
+}
+
+pub fn report() -> WatchdogReport {
+    WATCHDOG.lock().unwrap().report()
+}
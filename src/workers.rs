@@ -0,0 +1,171 @@
+// Workers next to the main loop: everything the node does used to funnel through the main loop, so
+// a long `chain validate` or the check of a long chain received from a peer stalled gossip handling.
+// The slow work is now done by dedicated tasks the main loop hands jobs to via channels:
+// - the validation worker runs `chain validate` on a copy of the main chain
+// - the sync worker checks complete chains received from peers (every block's proof of work and
+//   difficulty, see Chain::check_chain) and hands them back as ReceivedCheckedChain, so the main
+//   loop only swaps them in
+// - the serving worker answers chain and snapshot requests of our peers from a copy of the main
+//   chain it keeps up to date with the head events of the main loop (see head.rs)
+// Only the default chain has workers, additional chains (see chains.rs) are handled by the main loop.
+use crate::blockchain::{Block, BlockchainError, Chain};
+use crate::fastsync::ChainSnapshot;
+use crate::head::HeadEvent;
+use crate::storage::{MemoryStorage, Storage};
+use crate::types::{EventType, Height};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+// A copy of our main chain taken by the main loop, with the settings it is validated with
+pub struct ValidationJob {
+    pub chain: Chain,
+    pub blocks: Vec<Block>,
+}
+
+pub struct SyncJob {
+    // Our chain when the chain was received, for the rules it is checked with
+    pub chain: Chain,
+    pub sender: String,
+    pub session_id: String,
+    pub blocks: Vec<Block>,
+}
+
+pub enum ServingJob {
+    // Change of our main chain's head
+    Head(HeadEvent),
+    // ReceivedChainRequest or ReceivedSnapshotRequest
+    Request(EventType),
+}
+
+// Validates the copies of our main chain it is sent and prints the outcome
+pub async fn init_validation_worker(mut rx_rcv: mpsc::UnboundedReceiver<ValidationJob>) {
+    while let Some(job) = rx_rcv.recv().await {
+        let started_at = Instant::now();
+        match validate(job).await {
+            Ok(blocks) => println!("chain valid. ({} blocks in {:.1}s)", blocks, started_at.elapsed().as_secs_f64()),
+            Err(err) => println!("{:?}", err),
+        }
+    }
+}
+
+// Returns the number of blocks validated
+pub async fn validate(job: ValidationJob) -> Result<usize, BlockchainError> {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    match &job.chain.base {
+        Some(base) => storage.restore_snapshot(base, &job.blocks).await?,
+        None => {
+            for block in job.blocks.iter() {
+                storage.insert_block(block).await?;
+            }
+        }
+    }
+    job.chain.validate_chain(&mut storage).await?;
+    Ok(job.blocks.len())
+}
+
+// Checks the chains it is sent and hands them to main_sender as ReceivedCheckedChain
+pub async fn init_sync_worker(mut rx_rcv: mpsc::UnboundedReceiver<SyncJob>, main_sender: mpsc::UnboundedSender<EventType>) {
+    while let Some(job) = rx_rcv.recv().await {
+        let event = check(job).await;
+        if let Err(err) = main_sender.send(event) {
+            error!("Sync worker to main error: {:?}", err);
+            return;
+        }
+    }
+}
+
+pub async fn check(mut job: SyncJob) -> EventType {
+    job.blocks.sort_by_key(|block| block.id);
+    let error = match job.chain.check_chain(&job.blocks).await {
+        Ok(()) => None,
+        Err(err) => {
+            warn!(session = %job.session_id, "Chain from {} is invalid: {:?}", job.sender, err);
+            Some(err.to_string())
+        }
+    };
+    EventType::ReceivedCheckedChain{sender: job.sender, session_id: job.session_id, chain: job.blocks, error}
+}
+
+// Answers the requests it is sent from its copy of our main chain and hands the responses to p2p_sender
+pub async fn init_serving_worker(
+    mut server: ChainServer,
+    mut rx_rcv: mpsc::UnboundedReceiver<ServingJob>,
+    p2p_sender: mpsc::UnboundedSender<EventType>,
+) {
+    while let Some(job) = rx_rcv.recv().await {
+        match job {
+            ServingJob::Head(event) => server.apply(&event),
+            ServingJob::Request(request) => {
+                if let Some(response) = server.serve(request) {
+                    let _ = p2p_sender.send(response);
+                }
+            }
+        }
+    }
+}
+
+// Copy of our main chain requests are served from
+pub struct ChainServer {
+    chain: Chain,
+    // Oldest first, from the genesis block up unless our chain was restored from a snapshot
+    blocks: Vec<Block>,
+}
+
+impl ChainServer {
+    pub fn new(chain: Chain, blocks: Vec<Block>) -> Self {
+        Self { chain, blocks }
+    }
+
+    pub fn latest_block(&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    // Head events arrive in order, so a reorg is followed by the blocks of the new branch
+    pub fn apply(&mut self, event: &HeadEvent) {
+        if let HeadEvent::Extended(block) = event {
+            let below = self.blocks.partition_point(|kept| kept.id < block.id);
+            self.blocks.truncate(below);
+            // A chain restored from a snapshot starts at its base, without its parent
+            if self.blocks.last().is_some_and(|parent| parent.hash != block.prev_hash) {
+                self.blocks.clear();
+            }
+            self.blocks.push(block.clone());
+        }
+    }
+
+    pub fn serve(&self, request: EventType) -> Option<EventType> {
+        match request {
+            EventType::ReceivedChainRequest{receiver, session_id, from} => chain_response(self.blocks.clone(), receiver, session_id, from),
+            EventType::ReceivedSnapshotRequest{receiver, session_id} => snapshot_response(&self.chain, self.blocks.clone(), receiver, session_id),
+            _ => None,
+        }
+    }
+}
+
+// The blocks from the height on, unless we lack some of them because our chain was restored from a snapshot
+pub fn chain_response(mut blocks: Vec<Block>, receiver: String, session_id: String, from: Height) -> Option<EventType> {
+    info!(session = %session_id, "Received chain request from {} starting at {}", receiver, from);
+    let first = blocks.first().map_or(Height::GENESIS, |block| block.id);
+    if first > from {
+        warn!(session = %session_id, "Can not send blocks below {}, our chain was restored from a snapshot", first);
+        return None;
+    }
+    blocks.retain(|block| block.id >= from);
+    info!(session = %session_id, "Sending chain of {} blocks to {}", blocks.len(), receiver);
+    Some(EventType::SendChain{receiver, session_id, chain: blocks})
+}
+
+pub fn snapshot_response(chain: &Chain, blocks: Vec<Block>, receiver: String, session_id: String) -> Option<EventType> {
+    info!(session = %session_id, "Received snapshot request from {}", receiver);
+    match ChainSnapshot::from_blocks(chain, blocks) {
+        Ok(snapshot) => {
+            info!(session = %session_id, "Sending snapshot of {} blocks to {}", snapshot.blocks.len(), receiver);
+            Some(EventType::SendSnapshot{receiver, session_id, snapshot})
+        },
+        Err(err) => {
+            error!(session = %session_id, "{:?}", err);
+            None
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::address::*;
+use rust_blockchain::blockchain::BlockchainError;
+use rust_blockchain::multisig::MULTISIG_ADDRESS_PREFIX;
+
+#[test]
+fn test_base58check() {
+    // Bitcoin's P2PKH address of the all-zero hash
+    assert_eq!(encode(0, &[0; 20]), "1111111111111111111114oLvT2");
+    assert_eq!(decode("1111111111111111111114oLvT2").unwrap(), (0, vec![0; 20]));
+
+    let address = encode(ADDRESS_VERSION, &[7; 32]);
+    assert_eq!(decode(&address).unwrap(), (ADDRESS_VERSION, vec![7; 32]));
+    // A typo breaks the checksum
+    let last = address.chars().last().unwrap();
+    let typo = format!("{}{}", &address[..address.len() - 1], if last == 'x' { 'y' } else { 'x' });
+    assert!(matches!(decode(&typo), Err(BlockchainError::InvalidAddress(_))));
+    // 0 is not in the alphabet
+    assert!(matches!(decode("0OIl"), Err(BlockchainError::InvalidAddress(_))));
+    assert!(decode("1").is_err());
+}
+
+#[test]
+fn test_parse() {
+    let keypair = ed25519::Keypair::generate();
+    let key = hex::encode(keypair.public().encode());
+    let address = from_public_key(&keypair.public());
+    assert_eq!(parse(&address).unwrap(), key);
+    assert_eq!(parse(&format!(" {} ", key.to_uppercase())).unwrap(), key);
+    assert_eq!(from_internal(&key).unwrap(), address);
+
+    let multisig = format!("{}{}", MULTISIG_ADDRESS_PREFIX, "ab".repeat(32));
+    let encoded = from_internal(&multisig).unwrap();
+    assert_eq!(decode(&encoded).unwrap().0, MULTISIG_ADDRESS_VERSION);
+    assert_eq!(parse(&encoded).unwrap(), multisig);
+    assert_eq!(parse(&multisig).unwrap(), multisig);
+
+    let short = encode(ADDRESS_VERSION, &[1; 20]);
+    let unknown_version = encode(0x00, &[1; 32]);
+    for malformed in ["bob", "multisig-abc", short.as_str(), unknown_version.as_str(), &key[1..]] {
+        assert!(matches!(parse(malformed), Err(BlockchainError::InvalidAddress(_))), "{}", malformed);
+    }
+    assert!(from_internal("bob").is_err());
+}
@@ -0,0 +1,74 @@
+use rust_blockchain::anchor::*;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use std::env;
+use std::fs;
+
+fn leaves(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("{:064x}", i)).collect()
+}
+
+#[test]
+fn test_hash_file() {
+    let path = env::temp_dir().join("rust_blockchain_anchor_test.txt");
+    fs::write(&path, "abc").unwrap();
+    assert_eq!(hash_file(&path).unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    fs::remove_file(&path).unwrap();
+    assert!(hash_file(&path).is_err());
+}
+
+#[test]
+fn test_merkle_tree() {
+    let single = leaves(1);
+    assert_eq!(merkle_root(&single).unwrap(), single[0]);
+    assert!(merkle_path(&single, 0).unwrap().is_empty());
+    assert!(merkle_root(&[]).is_err());
+    assert!(merkle_root(&["not hex".to_owned()]).is_err());
+
+    // Every leaf of odd and even sized trees has a path to the root
+    for count in [2, 3, 5, 8] {
+        let digests = leaves(count);
+        let root = merkle_root(&digests).unwrap();
+        for index in 0..count {
+            let path = merkle_path(&digests, index).unwrap();
+            assert_eq!(path.len(), (count as f64).log2().ceil() as usize);
+            assert_eq!(path[0].left, index % 2 == 1);
+            let anchor = Anchor { root: root.clone(), digests: digests.clone() };
+            assert_eq!(Anchor::from_block_data(&anchor.to_block_data()), Some(anchor));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_anchor_proof() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+
+    let anchored = leaves(5);
+    let anchor = Anchor::new(anchored.clone()).unwrap();
+    let block = chain.mine_block(anchor.to_block_data(), &mut storage).await.unwrap();
+    assert!(AnchorProof::create(&mut storage, &"ff".repeat(32)).await.is_err());
+
+    let proof = AnchorProof::create(&mut storage, &anchored[3]).await.unwrap();
+    assert_eq!(proof.block, block);
+    assert_eq!(proof.root, anchor.root);
+    assert_eq!(proof.confirmations, 0);
+    proof.verify().unwrap();
+    chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    assert_eq!(AnchorProof::create(&mut storage, &anchored[3]).await.unwrap().confirmations, 1);
+
+    // Proofs for other digests, with another path or a modified block do not verify
+    assert!(AnchorProof { digest: anchored[4].clone(), ..proof.clone() }.verify().is_err());
+    let mut path = proof.path.clone();
+    path[0].left = !path[0].left;
+    assert!(AnchorProof { path, ..proof.clone() }.verify().is_err());
+    let mut tampered = proof.clone();
+    tampered.block.timestamp += 1;
+    assert!(tampered.verify().is_err());
+
+    // Data that only looks like an anchor anchors nothing
+    let forged = Anchor { root: "00".repeat(32), digests: anchored };
+    assert!(Anchor::from_block_data(&forged.to_block_data()).is_none());
+    assert!(digests("anchor not json").is_empty());
+}
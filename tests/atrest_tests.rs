@@ -0,0 +1,149 @@
+use rust_blockchain::atrest::{self, SEALED_PREFIX};
+use rust_blockchain::blockchain::Block;
+use rust_blockchain::keys::KeyStore;
+use rust_blockchain::node::Node;
+use rust_blockchain::payload::payload_hash;
+use rust_blockchain::repository::Repository;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::wal::WriteQueue;
+use std::{env, fs};
+
+// The key is set for the whole test binary, every test sets the same one
+const KEY: [u8; 32] = [0x5a; 32];
+
+#[test]
+fn test_seal() {
+    let other = [0x5b; 32];
+    let sealed = atrest::seal_with(&KEY, "block data");
+    assert!(sealed.starts_with(SEALED_PREFIX));
+    assert!(!sealed.contains("block data"));
+    assert_eq!(atrest::open_with(&KEY, &sealed).unwrap(), "block data");
+    // Every value gets a nonce of its own
+    assert_ne!(atrest::seal_with(&KEY, "block data"), sealed);
+    assert!(atrest::open_with(&other, &sealed).is_err());
+    let mut tampered = sealed.clone();
+    tampered.replace_range(tampered.len() - 2.., "00");
+    assert!(atrest::open_with(&KEY, &tampered).is_err());
+
+    // Empty payloads stay empty, any other value has to be sealed
+    assert_eq!(atrest::seal_with(&KEY, ""), "");
+    assert_eq!(atrest::open_with(&KEY, "").unwrap(), "");
+    assert!(atrest::open_with(&KEY, "plain data").is_err());
+
+    // Lookup keys are stable, but only with the same key
+    assert_eq!(atrest::lookup_key_with(&KEY, "hash"), atrest::lookup_key_with(&KEY, "hash"));
+    assert_ne!(atrest::lookup_key_with(&KEY, "hash"), atrest::lookup_key_with(&other, "hash"));
+    assert_ne!(atrest::lookup_key_with(&KEY, "hash"), atrest::lookup_key_with(&KEY, "other hash"));
+
+    assert_eq!(atrest::parse_key(&hex::encode(KEY)).unwrap(), KEY);
+    assert!(atrest::parse_key("5a5a").is_err());
+    assert!(atrest::parse_key("no key").is_err());
+}
+
+#[test]
+fn test_storage_key() {
+    let dir = env::temp_dir().join("rust_blockchain_atrest_keys");
+    let _ = fs::remove_dir_all(&dir);
+    let key_store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    let key = key_store.storage_key().unwrap();
+    // Created once and kept
+    assert_eq!(KeyStore::open(&dir, "passphrase".to_owned()).unwrap().storage_key().unwrap(), key);
+    assert!(KeyStore::open(&dir, "wrong".to_owned()).unwrap().storage_key().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_sealed_snapshot() {
+    let _ = atrest::set_key(KEY);
+    assert!(atrest::is_enabled());
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let block = node.chain.mine_block("secret payload".to_owned(), &mut node.storage).await.unwrap();
+
+    let path = env::temp_dir().join("rust_blockchain_atrest_snapshot.json");
+    node.storage.snapshot(&path).unwrap();
+    let contents = fs::read_to_string(&path).unwrap();
+    assert!(contents.starts_with(SEALED_PREFIX));
+    assert!(!contents.contains("secret payload"));
+
+    let mut storage = Storage::Memory(MemoryStorage::load(&path).unwrap());
+    assert_eq!(storage.get_block(&block.hash).await.unwrap(), block);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_sealed_journal() {
+    let _ = atrest::set_key(KEY);
+    let path = env::temp_dir().join("rust_blockchain_atrest_test.wal");
+    let _ = fs::remove_file(&path);
+    let genesis = Block::create_genesis();
+    let block1 = Block::new(&genesis, "secret payload".to_owned(), String::new());
+
+    let mut queue = WriteQueue::open(&path).unwrap();
+    queue.push(block1.clone()).unwrap();
+    drop(queue);
+    let journal = fs::read_to_string(&path).unwrap();
+    assert!(journal.starts_with(SEALED_PREFIX));
+    assert!(!journal.contains("secret payload"));
+
+    // A torn sealed line is dropped like a torn plain one
+    fs::write(&path, format!("{}{}", journal, &journal[..40])).unwrap();
+    let queue = WriteQueue::open(&path).unwrap();
+    assert_eq!(queue.front(), Some(&block1));
+    assert_eq!(queue.len(), 1);
+    drop(queue);
+
+    // A complete line sealed with another key is an error, not a torn line
+    fs::write(&path, format!("{}\n", atrest::seal_with(&[0x5b; 32], "{}"))).unwrap();
+    assert!(WriteQueue::open(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+// The values of a database stored before encryption was enabled are sealed on the first start with a key
+#[tokio::test]
+async fn test_seal_legacy_database() {
+    let _ = atrest::set_key(KEY);
+    let (db_client, connection) =
+        tokio_postgres::connect("host=localhost dbname=blockchain_test user=user password=pw", tokio_postgres::NoTls).await.unwrap();
+    tokio::spawn(connection);
+    db_client.batch_execute("DROP SCHEMA IF EXISTS atrest_legacy CASCADE").await.unwrap();
+    let repository = Repository::new(&db_client);
+    repository.use_schema("atrest_legacy").await.unwrap();
+    assert!(repository.migrate().await.is_empty());
+    // A legacy payload may look like a sealed one
+    let insert = "INSERT INTO blocks (hash, id, prev_hash, timestamp, nonce, data, payload_hash) VALUES ($1, $2, $3, 0, 0, $4, $5)";
+    let hash = payload_hash("sealed:legacy payload");
+    db_client.execute(insert, &[&"legacy", &0i64, &"none", &"sealed:legacy payload", &hash]).await.unwrap();
+    db_client.execute("INSERT INTO receipts (receipt, block_hash) VALUES ('legacy receipt', 'legacy')", &[]).await.unwrap();
+    db_client
+        .execute("INSERT INTO utxos (txid, vout, address, amount, block_hash) VALUES ('txid', 0, 'address', 42, 'legacy')", &[])
+        .await
+        .unwrap();
+
+    let mut storage = Storage::postgres(db_client, None);
+    storage.init().await.unwrap();
+    assert_eq!(storage.get_block("legacy").await.unwrap().data, "sealed:legacy payload");
+    assert_eq!(storage.get_payload(&hash).await.unwrap(), "sealed:legacy payload");
+    assert_eq!(storage.get_utxos("address").await.unwrap()[0].amount, 42);
+    let db_client = match &storage {
+        Storage::Postgres(db_client, ..) => db_client,
+        Storage::Memory(_) => unreachable!(),
+    };
+    let select = "SELECT data FROM blocks WHERE hash = 'legacy'";
+    let stored = db_client.query_one(select, &[]).await.unwrap().get::<_, String>("data");
+    assert!(stored.starts_with(SEALED_PREFIX));
+    assert_ne!(stored, "sealed:legacy payload");
+    let repository = Repository::new(db_client);
+    assert_eq!(repository.select_receipt("legacy").await.unwrap().unwrap(), "legacy receipt");
+    // Neither the receipt, the amount nor the payload hash are left in the clear
+    let row = db_client
+        .query_one("SELECT receipt, amount, payload_hash FROM receipts, utxos, blocks WHERE blocks.hash = 'legacy'", &[])
+        .await
+        .unwrap();
+    assert!(row.get::<_, String>("receipt").starts_with(SEALED_PREFIX));
+    assert_eq!(row.get::<_, i64>("amount"), 0);
+    assert_ne!(row.get::<_, String>("payload_hash"), hash);
+
+    // Once sealed, a value written in the clear is refused
+    db_client.execute(insert, &[&"plain", &1i64, &"legacy", &"plain payload", &payload_hash("plain payload")]).await.unwrap();
+    assert!(storage.get_block("plain").await.is_err());
+}
@@ -1,8 +1,22 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::anchor::{Anchor, AnchorProof};
 use rust_blockchain::blockchain::*;
+use rust_blockchain::deadletter::BadMessage;
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::payload::payload_hash;
+use rust_blockchain::replay;
+use rust_blockchain::repository::{BlockQuery, BlockTable, FromRow, Index, Repository};
+use rust_blockchain::storage::{MemoryStorage, Storage, PAYLOAD_INLINE_LIMIT};
+use rust_blockchain::transactions::{self, Transaction};
+use rust_blockchain::types::{Height, Nonce, PeerScore};
+use rust_blockchain::utxo::{OutPoint, RECIPIENT_OUTPUT};
+use rust_blockchain::wal::WriteQueue;
+use rust_blockchain::wallet;
+use std::env;
+use std::fs;
 use tokio::task::JoinHandle;
-use tokio_postgres::*;
 
-async fn setup() -> (Client, JoinHandle<()>) {
+async fn setup() -> (Storage, JoinHandle<()>) {
     let (db_client, connection) = tokio_postgres::connect(
         "host=localhost dbname=blockchain_test user=user password=pw",
         tokio_postgres::NoTls,
@@ -26,7 +40,10 @@ async fn setup() -> (Client, JoinHandle<()>) {
             prev_hash       VARCHAR UNIQUE NOT NULL,
             timestamp       INT8 NOT NULL,
             nonce           INT8 NOT NULL,
-            data            VARCHAR NOT NULL
+            data            VARCHAR NOT NULL,
+            version         INT2 NOT NULL DEFAULT 0,
+            miner           VARCHAR NOT NULL DEFAULT '',
+            extra_nonce     INT8 NOT NULL DEFAULT 0
             )
     ",
                 &[],
@@ -49,14 +66,27 @@ async fn setup() -> (Client, JoinHandle<()>) {
             println!("Error clearing blocks table: {:?}", err)
         }
 
-    (db_client, db_task)
+        // Gets re-created by Chain::init
+        if let Err(err) = db_client
+            .execute(
+                "
+        DROP TABLE IF EXISTS stale_blocks, address_index, anchor_index, peer_scores, payloads, bad_messages;
+        ",
+                &[],
+            )
+            .await
+        {
+            println!("Error dropping tables: {:?}", err)
+        }
+
+    (Storage::postgres(db_client, None), db_task)
 }
 
 #[tokio::test]
 async fn test_init_chain() {
-    let (mut db_client, _) = setup().await;
+    let (mut storage, _) = setup().await;
 
-    let mut chain = Chain::init(&mut db_client).await.unwrap();
+    let mut chain = Chain::init(&mut storage).await.unwrap();
 
     // Should have been initialized with genesis block
     assert_eq!(
@@ -65,11 +95,11 @@ async fn test_init_chain() {
     );
 
     let new_block = chain
-        .mine_block("new block".to_owned(), &mut db_client)
+        .mine_block("new block".to_owned(), &mut storage)
         .await
         .unwrap();
 
-    let chain2 = Chain::init(&mut db_client).await.unwrap();
+    let chain2 = Chain::init(&mut storage).await.unwrap();
 
     // Should have been initialized with latest block
     assert_eq!(chain2.latest_block.hash, new_block.hash);
@@ -77,87 +107,707 @@ async fn test_init_chain() {
 
 #[tokio::test]
 async fn test_mine_blocks() {
-    let (mut db_client, _) = setup().await;
+    let (mut storage, _) = setup().await;
 
-    let mut chain = Chain::init(&mut db_client).await.unwrap();
+    let mut chain = Chain::init(&mut storage).await.unwrap();
 
-    let block1 = chain.mine_block("new block 1".to_owned(), &mut db_client).await.unwrap();
-    let block2 = chain.mine_block("new block 2".to_owned(), &mut db_client).await.unwrap();
-    let block3 = chain.mine_block("new block 3".to_owned(), &mut db_client).await.unwrap();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    let block3 = chain.mine_block("new block 3".to_owned(), &mut storage).await.unwrap();
 
     assert_eq!(&chain.latest_block.hash, &block3.hash);
 
-    let block1 = Chain::get_block(&mut db_client,&block1.hash).await.unwrap();
-    let block2 = Chain::get_block(&mut db_client,&block2.hash).await.unwrap();
-    let block3 = Chain::get_block(&mut db_client,&block3.hash).await.unwrap();
+    let block1 = Chain::get_block(&mut storage,&block1.hash).await.unwrap();
+    let block2 = Chain::get_block(&mut storage,&block2.hash).await.unwrap();
+    let block3 = Chain::get_block(&mut storage,&block3.hash).await.unwrap();
 
-    assert_eq!(block1.id, 1);
+    assert_eq!(block1.id, Height(1));
     assert_eq!(block1.data, "new block 1");
-    assert_eq!(block2.id, 2);
+    assert_eq!(block2.id, Height(2));
     assert_eq!(block2.data, "new block 2");
-    assert_eq!(block3.id, 3);
+    assert_eq!(block3.id, Height(3));
     assert_eq!(block3.data, "new block 3");
 
-    assert!(matches!(Chain::check_if_block_valid(&mut db_client, &block1).await, Ok(())));
-    assert!(matches!(Chain::check_if_block_valid(&mut db_client, &block2).await, Ok(())));
-    assert!(matches!(Chain::check_if_block_valid(&mut db_client, &block3).await, Ok(())));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &block1).await, Ok(())));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &block2).await, Ok(())));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &block3).await, Ok(())));
 }
 
 #[tokio::test]
 async fn test_validate_invalid_block() {
-    let (mut db_client, _) = setup().await;
+    let (mut storage, _) = setup().await;
 
-    let mut chain = Chain::init(&mut db_client).await.unwrap();
+    let mut chain = Chain::init(&mut storage).await.unwrap();
 
-    let block1 = chain.mine_block("new block 1".to_owned(), &mut db_client).await.unwrap();
-    let block2 = chain.mine_block("new block 2".to_owned(), &mut db_client).await.unwrap();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
 
     let invalid_block = Block {
-        id: 1,
+        id: Height(1),
         data: "new block 1 invalid".to_owned(),
         timestamp: 12345,
         hash: block2.hash,
-        nonce: 123,
+        nonce: Nonce(123),
         prev_hash: block1.hash.clone(),
+        version: BLOCK_VERSION,
+        miner: String::new(),
+        extra_nonce: Nonce(0),
+        difficulty: 0,
+        fee: 0,
+        state_root: String::new(),
+        coinbase: None,
     };
 
-    assert!(matches!(Chain::check_if_block_valid(&mut db_client, &invalid_block).await, Err(BlockchainError::BlockInvalid(_))));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &invalid_block).await, Err(BlockchainError::BlockInvalid(_))));
 }
 
 #[tokio::test]
 async fn test_validate_chain() {
-    let (mut db_client, _) = setup().await;
+    let (mut storage, _) = setup().await;
 
-    let mut chain = Chain::init(&mut db_client).await.unwrap();
+    let mut chain = Chain::init(&mut storage).await.unwrap();
 
-    assert!(matches!(chain.validate_chain(&mut db_client).await, Ok(())));
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
 
-    let _ = chain.mine_block("new block 1".to_owned(), &mut db_client).await.unwrap();
-    let _ = chain.mine_block("new block 2".to_owned(), &mut db_client).await.unwrap();
-    let _ = chain.mine_block("new block 3".to_owned(), &mut db_client).await.unwrap();
+    let _ = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let _ = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    let _ = chain.mine_block("new block 3".to_owned(), &mut storage).await.unwrap();
 
-    assert!(matches!(chain.validate_chain(&mut db_client).await, Ok(())));
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
 }
 
 #[tokio::test]
 async fn test_validate_invalid_chain() {
-    let (mut db_client, _) = setup().await;
+    let (mut storage, _) = setup().await;
 
-    let mut chain = Chain::init(&mut db_client).await.unwrap();    
-    assert!(matches!(chain.validate_chain(&mut db_client).await, Ok(())));
+    let mut chain = Chain::init(&mut storage).await.unwrap();    
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
 
-    let _ = chain.mine_block("new block 1".to_owned(), &mut db_client).await.unwrap();
-    let block2 = chain.mine_block("new block 2".to_owned(), &mut db_client).await.unwrap();
-    let _ = chain.mine_block("new block 3".to_owned(), &mut db_client).await.unwrap();
+    let _ = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    let _ = chain.mine_block("new block 3".to_owned(), &mut storage).await.unwrap();
 
-    assert!(matches!(chain.validate_chain(&mut db_client).await, Ok(())));
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
 
     // Invalidate block
-    let _ = db_client.execute(&format!("
-        UPDATE blocks
-        SET data = 'invalid data'
-        WHERE hash = '{}'
-    ", block2.hash), &[]).await;
+    if let Storage::Postgres(db_client, ..) = &storage {
+        let _ = db_client.execute(&format!("
+            UPDATE blocks
+            SET data = 'invalid data'
+            WHERE hash = '{}'
+        ", block2.hash), &[]).await;
+    }
+
+    assert!(matches!(chain.validate_chain(&mut storage).await, Err(BlockchainError::ChainInvalid(_))));
+}
+
+#[tokio::test]
+async fn test_stale_blocks() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+
+    // Competing block for the same height
+    let competing_block = Block::new(&genesis, "competing block 1".to_owned(), "peer".to_owned());
+    assert!(matches!(chain.add_block(&mut storage, competing_block.clone()).await, Err(BlockchainError::BlockStale(_))));
+    assert_eq!(&chain.latest_block.hash, &block1.hash);
+
+    let stale_blocks = Chain::get_stale_blocks(&mut storage, 10).await.unwrap();
+    assert_eq!(stale_blocks.len(), 1);
+    assert_eq!(stale_blocks[0].block, competing_block);
+    assert_eq!(stale_blocks[0].block.miner, "peer");
+
+    // One stale and one main chain block (genesis is not counted)
+    assert_eq!(chain.get_stale_rate(&mut storage).await.unwrap(), 0.5);
+
+    // Replacing the chain turns our former block into a stale block
+    chain.update(&mut storage, &mut [genesis, competing_block.clone()]).await.unwrap();
+
+    let stale_blocks = Chain::get_stale_blocks(&mut storage, 10).await.unwrap();
+    assert_eq!(stale_blocks.len(), 1);
+    assert_eq!(stale_blocks[0].block, block1);
+    assert_eq!(&chain.latest_block.hash, &competing_block.hash);
+}
+
+#[tokio::test]
+async fn test_finality() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    assert_eq!(chain.finalized, Checkpoint::genesis());
+
+    for i in 2..=FINALITY_DEPTH + 1 {
+        chain.mine_block(format!("new block {}", i), &mut storage).await.unwrap();
+    }
+    assert_eq!(chain.finalized, Checkpoint { id: Height(1), hash: block1.hash.clone() });
+
+    // A fork from genesis would replace the finalized block
+    let fork_block = Block::new(&genesis, "fork block 1".to_owned(), String::new());
+    assert!(matches!(
+        chain.update(&mut storage, &mut [genesis, fork_block]).await,
+        Err(BlockchainError::ReorgBelowFinalized(Height(1)))
+    ));
+
+    // Any peer can only confirm blocks we finalize ourselves, a checkpoint signer can finalize our tip
+    let block2 = Chain::get_block_by_id(&mut storage, Height(2)).await.unwrap();
+    assert!(!chain.accept_checkpoint(&mut storage, Checkpoint { id: Height(2), hash: block2.hash.clone() }, "peer").await.unwrap());
+    assert_eq!(chain.finalized.id, Height(1));
+    chain.checkpoint_signers = vec!["signer".to_owned()];
+    assert!(chain.accept_checkpoint(&mut storage, Checkpoint { id: Height(2), hash: block2.hash.clone() }, "signer").await.unwrap());
+    assert_eq!(chain.finalized.id, Height(2));
+    // Checkpoints that are not newer than ours are ignored
+    assert!(!chain.accept_checkpoint(&mut storage, Checkpoint { id: Height(1), hash: block1.hash }, "signer").await.unwrap());
+    assert!(chain.accept_checkpoint(&mut storage, Checkpoint { id: Height(3), hash: "invalid".to_owned() }, "signer").await.is_err());
+}
+
+#[tokio::test]
+async fn test_block_metadata() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.miner = "test miner".to_owned();
+
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block1 = Chain::get_block(&mut storage, &block1.hash).await.unwrap();
+    assert_eq!(block1.version, BLOCK_VERSION);
+    assert_eq!(block1.miner, "test miner");
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &block1).await, Ok(())));
+
+    // All header fields are part of the hash
+    let mut tampered_block = block1.clone();
+    tampered_block.miner = "other miner".to_owned();
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &tampered_block).await, Err(BlockchainError::BlockInvalid(_))));
+
+    let mut tampered_block = block1.clone();
+    tampered_block.extra_nonce = Nonce(1);
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &tampered_block).await, Err(BlockchainError::BlockInvalid(_))));
+
+    // Unknown versions are rejected even if the hash matches
+    let mut future_block = Block { version: BLOCK_VERSION + 1, ..block1.clone() };
+    let (hash, nonce) = find_hash(&future_block, "00", 1).unwrap();
+    future_block.hash = hash;
+    future_block.nonce = nonce;
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &future_block).await, Err(BlockchainError::BlockInvalid(_))));
+
+    // Version 0 blocks are hashed without the new header fields
+    let mut legacy_block = Block { version: 0, miner: String::new(), ..block1.clone() };
+    let (hash, nonce) = find_hash(&legacy_block, "00", 1).unwrap();
+    legacy_block.hash = hash;
+    legacy_block.nonce = nonce;
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &legacy_block).await, Ok(())));
+}
+
+#[tokio::test]
+async fn test_timestamps() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    assert!(block2.timestamp >= block1.timestamp);
+    // Blocks mined in the same second are still ordered
+    assert_eq!(Chain::get_latest_block(&mut storage).await.unwrap(), block2);
+
+    // Blocks must not be older than their parent
+    let mut old_block = Block { timestamp: block1.timestamp - 1, prev_hash: block1.hash.clone(), ..block2.clone() };
+    let (hash, nonce) = find_hash(&old_block, "00", 1).unwrap();
+    old_block.hash = hash;
+    old_block.nonce = nonce;
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &old_block).await, Err(BlockchainError::BlockInvalid(_))));
+
+    // Timestamps of version 1 blocks are in seconds
+    let mut seconds_block = Block { version: 1, timestamp: block1.timestamp / 1000, ..old_block.clone() };
+    let (hash, nonce) = find_hash(&seconds_block, "00", 1).unwrap();
+    seconds_block.hash = hash;
+    seconds_block.nonce = nonce;
+    assert_eq!(seconds_block.timestamp_millis(), block1.timestamp / 1000 * 1000);
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &seconds_block).await, Ok(())));
+}
+
+#[tokio::test]
+async fn test_address_index() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+
+    chain.miner = "miner a".to_owned();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    chain.miner = "miner b".to_owned();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+
+    let blocks = Chain::get_address_blocks(&mut storage, "miner a").await.unwrap();
+    assert_eq!(blocks, vec![block1.clone()]);
+    let blocks = Chain::get_address_blocks(&mut storage, "miner b").await.unwrap();
+    assert_eq!(blocks, vec![block2]);
+
+    // Disconnected blocks are removed from the index
+    let fork_block = Block::new(&genesis, "fork block 1".to_owned(), "miner c".to_owned());
+    chain.update(&mut storage, &mut [genesis, fork_block.clone()]).await.unwrap();
+
+    assert!(Chain::get_address_blocks(&mut storage, "miner a").await.unwrap().is_empty());
+    assert!(Chain::get_address_blocks(&mut storage, "miner b").await.unwrap().is_empty());
+    let blocks = Chain::get_address_blocks(&mut storage, "miner c").await.unwrap();
+    assert_eq!(blocks, vec![fork_block]);
+}
+
+#[tokio::test]
+async fn test_anchor_index() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+
+    let digests = vec!["aa".repeat(32), "bb".repeat(32), "cc".repeat(32)];
+    let anchor = Anchor::new(digests.clone()).unwrap();
+    let block1 = chain.mine_block(anchor.to_block_data(), &mut storage).await.unwrap();
+    chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+
+    assert_eq!(Chain::get_anchor_blocks(&mut storage, &digests[1]).await.unwrap(), vec![block1.clone()]);
+    let proof = AnchorProof::create(&mut storage, &digests[2]).await.unwrap();
+    assert_eq!(proof.block, block1);
+    assert_eq!(proof.confirmations, 1);
+    proof.verify().unwrap();
+
+    // Disconnected blocks are removed from the index
+    let fork_block = Block::new(&genesis, "fork block 1".to_owned(), String::new());
+    chain.update(&mut storage, &mut [genesis, fork_block]).await.unwrap();
+    assert!(Chain::get_anchor_blocks(&mut storage, &digests[1]).await.unwrap().is_empty());
+    assert!(AnchorProof::create(&mut storage, &digests[1]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_reindex() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.miner = "miner a".to_owned();
+    let digest = "aa".repeat(32);
+    let block1 = chain.mine_block(Anchor::new(vec![digest.clone()]).unwrap().to_block_data(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+
+    // Lost index entries are restored
+    if let Storage::Postgres(db_client, ..) = &storage {
+        db_client.execute("DELETE FROM address_index", &[]).await.unwrap();
+        db_client.execute("DELETE FROM anchor_index", &[]).await.unwrap();
+    }
+    assert!(Chain::get_address_blocks(&mut storage, "miner a").await.unwrap().is_empty());
+
+    let mut reported = vec![];
+    Chain::reindex(&mut storage, &mut |done, total| reported.push((done, total))).await.unwrap();
+    assert_eq!(reported, vec![(1, 3), (2, 3), (3, 3)]);
+    assert_eq!(Chain::get_address_blocks(&mut storage, "miner a").await.unwrap(), vec![block1.clone(), block2]);
+    assert_eq!(Chain::get_anchor_blocks(&mut storage, &digest).await.unwrap(), vec![block1]);
+    assert_eq!(Chain::search_blocks(&mut storage, "block", 10).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_repository() {
+    let (mut storage, _) = setup().await;
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.miner = "miner a".to_owned();
+    let block1 = chain.mine_block("x".repeat(PAYLOAD_INLINE_LIMIT), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    let stale = Block::new(&block1, "stale block 2".to_owned(), "miner b".to_owned());
+
+    let db_client = match &storage {
+        Storage::Postgres(db_client, ..) => db_client,
+        Storage::Memory(_) => unreachable!(),
+    };
+    let repository = Repository::new(db_client);
+    repository.insert_block(BlockTable::StaleBlocks { received_at: 42 }, &stale).await.unwrap();
+    // Stored twice, kept once
+    repository.insert_block(BlockTable::StaleBlocks { received_at: 43 }, &stale).await.unwrap();
+    assert!(repository.insert_block(BlockTable::Blocks, &block2).await.is_err());
+
+    let genesis = Block::create_genesis();
+    assert_eq!(repository.select_blocks(BlockQuery::All).await.unwrap(), vec![genesis.clone(), block1.clone(), block2.clone()]);
+    assert_eq!(repository.select_block(BlockQuery::Hash(&block1.hash)).await.unwrap(), Some(block1.clone()));
+    assert_eq!(repository.select_block(BlockQuery::Id(Height(2))).await.unwrap(), Some(block2.clone()));
+    assert_eq!(repository.select_block(BlockQuery::Latest).await.unwrap(), Some(block2.clone()));
+    assert_eq!(repository.select_block(BlockQuery::Hash(&stale.hash)).await.unwrap(), None);
+    assert_eq!(repository.select_blocks(BlockQuery::Address("miner a")).await.unwrap(), vec![block1.clone(), block2.clone()]);
+    assert_eq!(repository.select_stale_blocks(10).await.unwrap(), vec![StaleBlock { block: stale.clone(), received_at: 42 }]);
+    assert_eq!(repository.count_blocks().await.unwrap(), 3);
+    assert_eq!(repository.count_blocks_from(Height(2)).await.unwrap(), (1, 1));
+    assert_eq!(repository.select_payload(&payload_hash(&block1.data)).await.unwrap(), Some(block1.data.clone()));
+
+    repository.insert_index_entry(Index::Anchor, "digest", &block2.hash).await.unwrap();
+    assert_eq!(repository.select_blocks(BlockQuery::Anchor("digest")).await.unwrap(), vec![block2.clone()]);
+    repository.clear_index(Index::Anchor).await.unwrap();
+    assert!(repository.select_blocks(BlockQuery::Anchor("digest")).await.unwrap().is_empty());
+    repository.insert_index_entry(Index::Receipt, "receipt", &block2.hash).await.unwrap();
+    assert_eq!(repository.select_receipt(&block2.hash).await.unwrap(), Some("receipt".to_owned()));
+    assert_eq!(repository.select_receipt(&block1.hash).await.unwrap(), None);
+
+    repository.delete_blocks(Some(Height::GENESIS)).await.unwrap();
+    assert_eq!(repository.select_blocks(BlockQuery::All).await.unwrap(), vec![genesis]);
+    assert!(repository.select_blocks(BlockQuery::Address("miner a")).await.unwrap().is_empty());
+    assert_eq!(repository.select_receipt(&block2.hash).await.unwrap(), None);
+    // The payload of the removed block is pruned, the stale blocks are kept
+    assert_eq!(repository.select_payload(&payload_hash(&block1.data)).await.unwrap(), None);
+    assert_eq!(repository.select_stale_blocks(10).await.unwrap().len(), 1);
+    // Pruning the payload keeps the header
+    assert!(repository.prune_stale_blocks(Height(1), true).await.unwrap().is_empty());
+    assert_eq!(repository.prune_stale_blocks(Height(2), true).await.unwrap(), vec![StaleBlock { block: stale.clone(), received_at: 42 }]);
+    assert_eq!(repository.select_stale_blocks(10).await.unwrap()[0].block.data, "");
+    assert!(repository.prune_stale_blocks(Height(2), true).await.unwrap().is_empty());
+    assert_eq!(repository.prune_stale_blocks(Height(2), false).await.unwrap().len(), 1);
+    assert!(repository.select_stale_blocks(10).await.unwrap().is_empty());
+    repository.delete_blocks(None).await.unwrap();
+    assert_eq!(repository.count_blocks().await.unwrap(), 0);
+    assert_eq!(repository.select_block(BlockQuery::Latest).await.unwrap(), None);
+}
+
+// Blocks are served from the cache until they leave the main chain
+#[tokio::test]
+async fn test_block_cache() {
+    let (mut storage, _) = setup().await;
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+
+    // Removed behind the back of the cache
+    if let Storage::Postgres(db_client, ..) = &storage {
+        db_client.execute("DELETE FROM blocks WHERE id >= 1", &[]).await.unwrap();
+    }
+    assert_eq!(Chain::get_block(&mut storage, &block2.hash).await.unwrap(), block2);
+    assert_eq!(Chain::get_block_by_id(&mut storage, Height(1)).await.unwrap(), block1);
+    let stats = storage.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses), (2, 0));
+
+    storage.remove_blocks_above(Height(1)).await.unwrap();
+    assert!(matches!(Chain::get_block(&mut storage, &block2.hash).await, Err(BlockchainError::BlockNotFound(_))));
+    assert_eq!(Chain::get_block(&mut storage, &block1.hash).await.unwrap(), block1);
+
+    storage.clear_blocks().await.unwrap();
+    assert!(matches!(Chain::get_block_by_id(&mut storage, Height(1)).await, Err(BlockchainError::BlockNotFound(_))));
+}
+
+// Number of main chain blocks in the database, bypassing the cache and the write queue
+async fn stored_blocks(storage: &Storage) -> i64 {
+    match storage {
+        Storage::Postgres(db_client, ..) => db_client.query_one("SELECT COUNT(*) FROM blocks", &[]).await.unwrap().get(0),
+        Storage::Memory(_) => unreachable!(),
+    }
+}
+
+// Blocks are acknowledged once journaled and written by flush_writes
+#[tokio::test]
+async fn test_write_queue() {
+    let path = env::temp_dir().join("rust_blockchain_wal_postgres_test.wal");
+    let _ = fs::remove_file(&path);
+    let (storage, _) = setup().await;
+    let db_client = match storage {
+        Storage::Postgres(db_client, ..) => db_client,
+        Storage::Memory(_) => unreachable!(),
+    };
+    let mut storage = Storage::postgres(db_client, Some(WriteQueue::open(&path).unwrap()));
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("new block 2".to_owned(), &mut storage).await.unwrap();
+    assert_eq!(storage.queued_writes(), 2);
+    assert_eq!(stored_blocks(&storage).await, 1);
+    // Queued blocks are served from the queue
+    assert_eq!(Chain::get_block(&mut storage, &block1.hash).await.unwrap(), block1);
+    assert_eq!(Chain::get_block_by_id(&mut storage, Height(2)).await.unwrap(), block2);
+    assert_eq!(Chain::get_latest_block(&mut storage).await.unwrap(), block2);
+    assert_eq!(storage.count_blocks().await.unwrap(), 3);
+
+    // Queries the queue can not answer write it first
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis.clone(), block1.clone(), block2.clone()]);
+    assert_eq!(storage.queued_writes(), 0);
+    assert_eq!(stored_blocks(&storage).await, 3);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+    // A crash after block 2 was written but before it was dropped from the journal
+    let block3 = Block::new(&block2, "new block 3".to_owned(), String::new());
+    let journal = [&block2, &block3].map(|block| serde_json::to_string(block).unwrap()).join("\n");
+    fs::write(&path, format!("{}\n{{\"torn", journal)).unwrap();
+    let db_client = match storage {
+        Storage::Postgres(db_client, ..) => db_client,
+        Storage::Memory(_) => unreachable!(),
+    };
+    let mut storage = Storage::postgres(db_client, Some(WriteQueue::open(&path).unwrap()));
+    storage.init().await.unwrap();
+    assert_eq!(storage.queued_writes(), 0);
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis, block1, block2, block3]);
+    fs::remove_file(&path).unwrap();
+}
+
+// The chain is swapped in one transaction
+#[tokio::test]
+async fn test_replace_chain_is_atomic() {
+    let (mut storage, _) = setup().await;
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let fork1 = Block::new(&genesis, "fork block 1".to_owned(), String::new());
+
+    // The second fork1 violates the unique constraints after the first was inserted
+    assert!(storage.replace_chain(&[genesis.clone(), fork1.clone(), fork1.clone()], std::slice::from_ref(&block1), 0).await.is_err());
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis.clone(), block1.clone()]);
+    assert!(Chain::get_stale_blocks(&mut storage, 10).await.unwrap().is_empty());
+    assert_eq!(Chain::get_address_blocks(&mut storage, "").await.unwrap(), vec![]);
+
+    storage.replace_chain(&[genesis.clone(), fork1.clone()], std::slice::from_ref(&block1), 0).await.unwrap();
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis, fork1]);
+    assert_eq!(Chain::get_stale_blocks(&mut storage, 10).await.unwrap()[0].block, block1);
+}
+
+// Blocks stored before payloads were content-addressed are moved by the migration with the same
+// limit blocks are stored with
+#[tokio::test]
+async fn test_migrate_payloads() {
+    let (mut storage, _) = setup().await;
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("x".repeat(PAYLOAD_INLINE_LIMIT - 1), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("y".repeat(PAYLOAD_INLINE_LIMIT), &mut storage).await.unwrap();
+    let db_client = match &storage {
+        Storage::Postgres(db_client, ..) => db_client,
+        Storage::Memory(_) => unreachable!(),
+    };
+    db_client
+        .batch_execute(&format!(
+            "UPDATE blocks SET data = '{}' WHERE hash = '{}'; UPDATE blocks SET payload_hash = NULL; DELETE FROM payloads",
+            block2.data, block2.hash
+        ))
+        .await
+        .unwrap();
+
+    let repository = Repository::new(db_client);
+    assert!(repository.migrate().await.is_empty());
+    let inline: Vec<(String, String)> = db_client
+        .query("SELECT hash, data FROM blocks WHERE id > 0 ORDER BY id", &[])
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+    assert_eq!(inline, vec![(block1.hash.clone(), block1.data.clone()), (block2.hash.clone(), String::new())]);
+    assert_eq!(repository.select_blocks(BlockQuery::All).await.unwrap()[1..], [block1, block2]);
+}
+
+// Every column a stored type reads has to exist in its table and every column of the table has to
+// be read, except the ones only used for storage
+#[tokio::test]
+async fn test_row_mapping_matches_schema() {
+    let (mut storage, _) = setup().await;
+    let _ = Chain::init(&mut storage).await.unwrap();
+    let db_client = match &storage {
+        Storage::Postgres(db_client, ..) => db_client,
+        Storage::Memory(_) => unreachable!(),
+    };
+
+    let tables: [(&str, &[&str], &[&str]); 4] = [
+        ("blocks", Block::COLUMNS, &["payload_hash"]),
+        ("stale_blocks", StaleBlock::COLUMNS, &["payload_hash"]),
+        ("peer_scores", PeerScore::COLUMNS, &[]),
+        ("bad_messages", BadMessage::COLUMNS, &["id"]),
+    ];
+    for (table, read, storage_only) in tables {
+        let rows = db_client
+            .query(
+                "SELECT column_name::VARCHAR FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+                &[&table],
+            )
+            .await
+            .unwrap();
+        let mut columns: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+        columns.retain(|column| !storage_only.contains(&column.as_str()));
+        columns.sort();
+        let mut read: Vec<String> = read.iter().map(|column| column.to_string()).collect();
+        read.sort();
+        assert_eq!(columns, read, "columns of {}", table);
+    }
+
+    // A row lacking a column is an error, not a panic
+    let row = db_client.query_one("SELECT 'hash' AS hash, 1::INT8 AS id", &[]).await.unwrap();
+    assert!(Block::from_row(&row).is_err());
+    // So is a column of another type
+    let row = db_client
+        .query_one("SELECT 'peer' AS peer_id, 'high' AS score, FALSE AS banned, ARRAY['a'] AS addresses, 0::INT8 AS updated_at", &[])
+        .await
+        .unwrap();
+    assert!(PeerScore::from_row(&row).is_err());
+}
+
+#[tokio::test]
+async fn test_replay() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.miner = "miner a".to_owned();
+    let digest = "aa".repeat(32);
+    let _ = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block(Anchor::new(vec![digest.clone()]).unwrap().to_block_data(), &mut storage).await.unwrap();
+    let _ = chain.mine_block("x".repeat(PAYLOAD_INLINE_LIMIT + 1), &mut storage).await.unwrap();
+    let report = replay::replay(&chain, &mut storage, &mut |_, _| {}).await.unwrap();
+    assert_eq!(report, replay::ReplayReport { blocks: 4, divergence: None });
+
+    // A lost index entry is reported at the block that added it
+    if let Storage::Postgres(db_client, ..) = &storage {
+        db_client.execute("DELETE FROM anchor_index", &[]).await.unwrap();
+    }
+    let divergence = replay::replay(&chain, &mut storage, &mut |_, _| {}).await.unwrap().divergence.unwrap();
+    assert_eq!((divergence.height, divergence.hash), (Height(2), block2.hash));
+    assert!(divergence.reason.contains(&digest));
+}
+
+#[tokio::test]
+async fn test_remove_blocks_above() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.miner = "miner a".to_owned();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let digest = "aa".repeat(32);
+    let _ = chain.mine_block(Anchor::new(vec![digest.clone()]).unwrap().to_block_data(), &mut storage).await.unwrap();
+
+    storage.remove_blocks_above(Height(1)).await.unwrap();
+    assert_eq!(Chain::get_latest_block(&mut storage).await.unwrap(), block1);
+    assert_eq!(Chain::get_address_blocks(&mut storage, "miner a").await.unwrap(), vec![block1]);
+    assert!(Chain::get_anchor_blocks(&mut storage, &digest).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_search_blocks() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+
+    let block1 = chain.mine_block("invoice 42 paid by alice".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("invoice 43 paid by bob".to_owned(), &mut storage).await.unwrap();
+    let _ = chain.mine_block("unrelated record".to_owned(), &mut storage).await.unwrap();
+
+    let results = Chain::search_blocks(&mut storage, "invoice", 10).await.unwrap();
+    assert_eq!(results.len(), 2);
+
+    let results = Chain::search_blocks(&mut storage, "invoice -bob", 10).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].block, block1);
+    assert_eq!(results[0].headline, "*invoice* 42 paid by alice");
+
+    let results = Chain::search_blocks(&mut storage, "\"paid by bob\"", 10).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].block, block2);
+
+    assert!(Chain::search_blocks(&mut storage, "missing", 10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_peer_scores() {
+    let (mut storage, _) = setup().await;
+
+    let _ = Chain::init(&mut storage).await.unwrap();
+    assert!(storage.get_peer_scores().await.unwrap().is_empty());
+
+    let good_peer = PeerScore {
+        peer_id: "good peer".to_owned(),
+        score: 12.5,
+        banned: false,
+        addresses: vec!["/ip4/10.0.0.1/tcp/4001".to_owned()],
+        updated_at: 1,
+    };
+    let bad_peer = PeerScore {
+        peer_id: "bad peer".to_owned(),
+        score: -100.0,
+        banned: true,
+        addresses: vec![],
+        updated_at: 1,
+    };
+    storage.save_peer_scores(&[bad_peer.clone(), good_peer.clone()]).await.unwrap();
+    assert_eq!(storage.get_peer_scores().await.unwrap(), vec![good_peer.clone(), bad_peer.clone()]);
+
+    // Scores are updated in place
+    let good_peer = PeerScore { score: -150.0, banned: true, updated_at: 2, ..good_peer };
+    storage.save_peer_scores(std::slice::from_ref(&good_peer)).await.unwrap();
+    assert_eq!(storage.get_peer_scores().await.unwrap(), vec![bad_peer, good_peer]);
+}
 
-    assert!(matches!(chain.validate_chain(&mut db_client).await, Err(BlockchainError::ChainInvalid(_))));
+#[tokio::test]
+async fn test_bad_messages() {
+    let (mut storage, _) = setup().await;
+
+    let _ = Chain::init(&mut storage).await.unwrap();
+    assert!(storage.get_bad_messages(10).await.unwrap().is_empty());
+    let first = BadMessage::new("peer".to_owned(), b"not json", "invalid JSON".to_owned(), 1);
+    let second = BadMessage::new("other peer".to_owned(), b"{}", "unknown message type".to_owned(), 2);
+    storage.insert_bad_message(&first).await.unwrap();
+    storage.insert_bad_message(&second).await.unwrap();
+    assert_eq!(storage.get_bad_messages(10).await.unwrap(), vec![second.clone(), first]);
+    assert_eq!(storage.get_bad_messages(1).await.unwrap(), vec![second]);
+}
+
+#[tokio::test]
+async fn test_payload_dedup() {
+    let (mut storage, _) = setup().await;
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let large = format!("anchored document {}", "x".repeat(PAYLOAD_INLINE_LIMIT));
+    let block1 = chain.mine_block(large.clone(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block(large.clone(), &mut storage).await.unwrap();
+    let small = chain.mine_block("small payload".to_owned(), &mut storage).await.unwrap();
+
+    // The large payload is stored once and read back with the blocks
+    if let Storage::Postgres(db_client, ..) = &storage {
+        let row = db_client.query_one("SELECT COUNT (*) FROM payloads", &[]).await.unwrap();
+        assert_eq!(row.get::<_, i64>(0), 1);
+        let row = db_client.query_one("SELECT data FROM blocks WHERE hash = $1", &[&block1.hash]).await.unwrap();
+        assert_eq!(row.get::<_, String>(0), "");
+    }
+    assert_eq!(Chain::get_block(&mut storage, &block1.hash).await.unwrap(), block1);
+    assert_eq!(Chain::get_block_by_id(&mut storage, block2.id).await.unwrap(), block2);
+    assert_eq!(Chain::get_latest_block(&mut storage).await.unwrap(), small);
+    assert_eq!(Chain::search_blocks(&mut storage, "anchored", 10).await.unwrap().len(), 2);
+    chain.validate_chain(&mut storage).await.unwrap();
+
+    assert_eq!(storage.get_payload(&payload_hash(&large)).await.unwrap(), large);
+    assert_eq!(storage.get_payload(&payload_hash("small payload")).await.unwrap(), "small payload");
+    assert!(storage.get_payload(&payload_hash("missing")).await.is_err());
+
+    // Payloads of removed blocks are removed with them
+    storage.clear_blocks().await.unwrap();
+    assert!(storage.get_payload(&payload_hash(&large)).await.is_err());
+}
+
+// Mines an output, spends it and stores a block carrying the transaction that created it again
+async fn replay_spent_output(storage: &mut Storage) {
+    let mut chain = Chain::init(storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let (minter, alice) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let address = wallet::address(&alice.public());
+    let signed = |sender: &ed25519::Keypair, recipient: &str, inputs: Vec<OutPoint>| {
+        let mut transaction = Transaction { inputs, ..Transaction::new(&sender.public(), recipient.to_owned(), 100, 0) };
+        transaction.sign(sender).unwrap();
+        transaction
+    };
+    let mint = signed(&minter, &address, vec![]);
+    let output = OutPoint { txid: mint.id(), vout: RECIPIENT_OUTPUT };
+    chain.mine_transactions(vec![mint.clone()], storage).await.unwrap();
+    chain.mine_transactions(vec![signed(&alice, "bob", vec![output.clone()])], storage).await.unwrap();
+
+    // Stored without validation, the output has to stay spent
+    let replayed = Block::new(&chain.latest_block, transactions::to_block_data(&[mint]), "miner".to_owned());
+    storage.insert_block(&replayed).await.unwrap();
+    storage.index_block(&replayed).await.unwrap();
+    assert!(Chain::get_utxos(storage, &address).await.unwrap().is_empty());
+    assert!(storage.get_unspent(&[output.clone()]).await.unwrap().is_empty());
+    let again = signed(&alice, "carol", vec![output]);
+    assert!(matches!(Chain::check_spends(storage, &again).await, Err(BlockchainError::DoubleSpend(_))));
+}
+
+#[tokio::test]
+async fn test_replayed_transaction() {
+    let (mut storage, _db_task) = setup().await;
+    replay_spent_output(&mut storage).await;
+    replay_spent_output(&mut Storage::Memory(MemoryStorage::default())).await;
 }
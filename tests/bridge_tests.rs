@@ -0,0 +1,88 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::bridge::*;
+use rust_blockchain::chains::ChainSpec;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[test]
+fn test_bridge_target() {
+    assert_eq!(BridgeTarget::parse("chain:notary").unwrap(), BridgeTarget::Chain("notary".to_owned()));
+    assert_eq!(
+        BridgeTarget::parse("http://127.0.0.1:8080/anchors").unwrap(),
+        BridgeTarget::Notary { host: "127.0.0.1".to_owned(), port: 8080, path: "/anchors".to_owned() }
+    );
+    assert_eq!(
+        BridgeTarget::parse("http://notary.example").unwrap(),
+        BridgeTarget::Notary { host: "notary.example".to_owned(), port: 80, path: "/".to_owned() }
+    );
+    assert!(BridgeTarget::parse("https://notary.example").is_err());
+    assert!(BridgeTarget::parse("http://:8080/").is_err());
+    assert!(BridgeTarget::parse("http://notary.example:port/").is_err());
+}
+
+#[test]
+fn test_bridge_anchor_block_data() {
+    let genesis = Block::create_genesis();
+    let block = Block::new(&genesis, "block 1".to_owned(), String::new());
+    let anchor = BridgeAnchor::new(&genesis, &block);
+    assert_eq!(anchor.height, Height(1));
+    let data = anchor.to_block_data();
+    assert!(data.starts_with(BRIDGE_PREFIX));
+    assert_eq!(BridgeAnchor::from_block_data(&data), Some(anchor.clone()));
+    assert_eq!(BridgeAnchor::from_block_data("block 1"), None);
+
+    assert!(anchor.verify(&block).is_ok());
+    let other = Block::new(&genesis, "other block 1".to_owned(), String::new());
+    assert!(anchor.verify(&other).is_err());
+}
+
+#[tokio::test]
+async fn test_find_and_verify_anchors() {
+    let mut source_storage = Storage::Memory(MemoryStorage::default());
+    let mut source = Chain::init(&mut source_storage).await.unwrap();
+    let block1 = source.mine_block("block 1".to_owned(), &mut source_storage).await.unwrap();
+
+    let target_genesis = ChainSpec::parse("notary").unwrap().genesis();
+    let mut target_storage = Storage::Memory(MemoryStorage::default());
+    let mut target = Chain::init_with_genesis(&mut target_storage, target_genesis).await.unwrap();
+    target.mine_block("unrelated".to_owned(), &mut target_storage).await.unwrap();
+    let anchor = BridgeAnchor::new(&source.genesis, &block1);
+    target.mine_block(anchor.to_block_data(), &mut target_storage).await.unwrap();
+    // Anchors of other chains are left out
+    let foreign = BridgeAnchor { genesis: "other".to_owned(), ..anchor.clone() };
+    target.mine_block(foreign.to_block_data(), &mut target_storage).await.unwrap();
+    // Rewritten history does not match the anchor any more
+    let rewritten = BridgeAnchor { hash: "rewritten".to_owned(), ..anchor.clone() };
+    target.mine_block(rewritten.to_block_data(), &mut target_storage).await.unwrap();
+    let beyond = BridgeAnchor { height: Height(5), ..anchor.clone() };
+    target.mine_block(beyond.to_block_data(), &mut target_storage).await.unwrap();
+
+    let anchors = find_anchors(&mut target_storage, &source.genesis).await.unwrap();
+    assert_eq!(anchors.iter().map(|(_, anchor)| anchor).collect::<Vec<_>>(), vec![&anchor, &rewritten, &beyond]);
+    assert_eq!(anchors[0].0.id, Height(2));
+    assert!(verify_anchor(&mut source_storage, &anchor).await.is_ok());
+    assert!(verify_anchor(&mut source_storage, &rewritten).await.is_err());
+    assert!(verify_anchor(&mut source_storage, &beyond).await.is_err());
+}
+
+#[tokio::test]
+async fn test_notarize() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let notary = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0; 4096];
+        let len = stream.read(&mut request).await.unwrap();
+        stream.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+        String::from_utf8_lossy(&request[..len]).into_owned()
+    });
+
+    let genesis = Block::create_genesis();
+    let anchor = BridgeAnchor::new(&genesis, &Block::new(&genesis, "block 1".to_owned(), String::new()));
+    assert_eq!(notarize("127.0.0.1", port, "/anchors", &anchor).await.unwrap(), 201);
+    let request = notary.await.unwrap();
+    assert!(request.starts_with("POST /anchors HTTP/1.1\r\n"));
+    assert!(request.ends_with(&serde_json::to_string(&anchor).unwrap()));
+}
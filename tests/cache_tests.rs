@@ -0,0 +1,41 @@
+use rust_blockchain::blockchain::Block;
+use rust_blockchain::cache::*;
+use rust_blockchain::types::Height;
+
+fn block(id: u64, hash: &str) -> Block {
+    Block { id: Height(id), hash: hash.to_owned(), ..Block::create_genesis() }
+}
+
+#[test]
+fn test_block_cache() {
+    let mut cache = BlockCache::new(2);
+    cache.insert(block(1, "a"));
+    cache.insert(block(2, "b"));
+    assert_eq!(cache.get("a"), Some(block(1, "a")));
+    assert_eq!(cache.get_by_id(Height(2)), Some(block(2, "b")));
+    assert_eq!(cache.get("c"), None);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1, blocks: 2 });
+
+    // The least recently used block is evicted together with its height
+    cache.insert(block(3, "c"));
+    assert_eq!(cache.get("a"), None);
+    assert_eq!(cache.get_by_id(Height(1)), None);
+    assert_eq!(cache.get_by_id(Height(3)), Some(block(3, "c")));
+
+    // A block replacing another one at its height
+    cache.insert(block(3, "d"));
+    assert_eq!(cache.get("c"), None);
+    assert_eq!(cache.get_by_id(Height(3)), Some(block(3, "d")));
+    // Caching a block again keeps it
+    cache.insert(block(3, "d"));
+    assert_eq!(cache.get_by_id(Height(3)), Some(block(3, "d")));
+
+    cache.invalidate_above(Height(2));
+    assert_eq!(cache.get("d"), None);
+    assert_eq!(cache.get_by_id(Height(3)), None);
+    assert_eq!(cache.get_by_id(Height(2)), Some(block(2, "b")));
+
+    cache.clear();
+    assert_eq!(cache.get("b"), None);
+    assert_eq!(cache.stats().blocks, 0);
+}
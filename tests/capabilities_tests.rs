@@ -0,0 +1,34 @@
+use rust_blockchain::blockchain::BLOCK_VERSION;
+use rust_blockchain::capabilities::*;
+use rust_blockchain::consensus::ACTIVATIONS;
+use rust_blockchain::handshake::HANDSHAKE_VERSION;
+use rust_blockchain::rpc;
+
+#[test]
+fn test_capabilities() {
+    let report = capabilities();
+    // Nothing about the run, two reports of one binary are the same
+    assert_eq!(report, capabilities());
+    assert_eq!(report.build.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(report.build.features.contains(&"gpu".to_owned()), cfg!(feature = "gpu"));
+    assert_eq!(report.hash_backends.contains(&"gpu".to_owned()), cfg!(feature = "gpu"));
+    assert_eq!(report.storage_backends, vec!["postgres", "memory"]);
+    assert!(report.difficulty_algorithms.contains(&"asert".to_owned()));
+    assert_eq!(report.consensus_upgrades.len(), ACTIVATIONS.len());
+    assert_eq!(report.consensus_upgrades[0].name, "VersionedHeader");
+    assert_eq!(report.protocols.handshake, HANDSHAKE_VERSION);
+    assert_eq!(report.protocols.block_versions.last(), Some(&BLOCK_VERSION));
+    assert!(report.protocols.peer_features.contains(&"state-proofs".to_owned()));
+    assert!(report.protocols.rpc_methods.contains(&"getcapabilities".to_owned()));
+
+    // Machine-readable for other tools
+    let json = serde_json::to_string(&report).unwrap();
+    assert_eq!(serde_json::from_str::<Capabilities>(&json).unwrap(), report);
+}
+
+#[test]
+fn test_getcapabilities() {
+    let document = rpc::openrpc();
+    let method = rpc::METHODS.iter().find(|method| method.name == "getcapabilities").unwrap();
+    assert!(document["components"]["schemas"].get(method.result).is_some());
+}
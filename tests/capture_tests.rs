@@ -0,0 +1,137 @@
+use libp2p::PeerId;
+use rust_blockchain::blockchain::{Block, Chain};
+use rust_blockchain::capture::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::p2p;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::EventType;
+use std::env;
+use std::fs;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+fn message(direction: Direction, channel: Channel, peer: &PeerId, data: &[u8]) -> CapturedMessage {
+    CapturedMessage {
+        at: 1_000,
+        direction,
+        channel,
+        peer: Some(peer.to_string()),
+        source: None,
+        chain: None,
+        data: hex::encode(data),
+    }
+}
+
+#[test]
+fn test_capture_write_and_read() {
+    let path = env::temp_dir().join("rust_blockchain_capture_test.gz");
+    let peer = PeerId::random();
+    let messages = vec![
+        message(Direction::Received, Channel::Gossip, &peer, b"{\"block\": null}"),
+        message(Direction::Sent, Channel::Direct, &peer, &[0xff, 0x00]),
+    ];
+    let mut writer = CaptureWriter::create(&path, DEFAULT_CAPTURE_MAX_BYTES).unwrap();
+    for message in messages.iter() {
+        writer.write(message).unwrap();
+    }
+    // Readable before the capture is closed, like after a crash
+    assert_eq!(read_capture(&path).unwrap(), messages);
+    drop(writer);
+    assert_eq!(read_capture(&path).unwrap(), messages);
+    assert_eq!(messages[1].bytes().unwrap(), vec![0xff, 0x00]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_capture_rotation() {
+    let path = env::temp_dir().join("rust_blockchain_capture_rotation_test.gz");
+    let peer = PeerId::random();
+    let first = message(Direction::Received, Channel::Gossip, &peer, b"first");
+    let second = message(Direction::Received, Channel::Gossip, &peer, b"second");
+    let third = message(Direction::Received, Channel::Gossip, &peer, b"third");
+    // Every message fills the capture
+    let mut writer = CaptureWriter::create(&path, 1).unwrap();
+    writer.write(&first).unwrap();
+    writer.write(&second).unwrap();
+    writer.write(&third).unwrap();
+    drop(writer);
+    assert_eq!(read_capture(&rotated_path(&path)).unwrap(), vec![third]);
+    assert!(read_capture(&path).unwrap().is_empty());
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(rotated_path(&path)).unwrap();
+}
+
+#[test]
+fn test_replay_capture() {
+    let path = env::temp_dir().join("rust_blockchain_capture_replay_test.gz");
+    let peer = PeerId::random();
+    let block = Block::create_genesis();
+    let new_block = serde_json::to_vec(&serde_json::json!({ "block": block })).unwrap();
+    let mut writer = CaptureWriter::create(&path, DEFAULT_CAPTURE_MAX_BYTES).unwrap();
+    writer.write(&message(Direction::Received, Channel::Gossip, &peer, &new_block)).unwrap();
+    // Sent messages and peer exchanges are not handled again
+    writer.write(&message(Direction::Sent, Channel::Gossip, &peer, &new_block)).unwrap();
+    writer.write(&message(Direction::Received, Channel::Direct, &peer, b"{\"peers\": []}")).unwrap();
+    writer.write(&message(Direction::Received, Channel::Direct, &peer, b"garbage")).unwrap();
+    drop(writer);
+
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel();
+    let replay = p2p::replay_capture(&path, &[], &main_sender).unwrap();
+    assert_eq!((replay.replayed, replay.skipped), (2, 1));
+    assert_eq!(replay.bad.len(), 1);
+    assert_eq!(replay.bad[0].peer, peer.to_string());
+    assert_eq!(replay.bad[0].prefix, hex::encode(b"garbage"));
+    assert_eq!(main_rcv.try_recv().unwrap(), EventType::ReceivedNewBlock { sender: peer.to_string(), block });
+    assert!(main_rcv.try_recv().is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_replay_expired_messages() {
+    let path = env::temp_dir().join("rust_blockchain_capture_expired_test.gz");
+    let peer = PeerId::random();
+    let block = Block::create_genesis();
+    let latest_block = |sent_at: Option<i64>| {
+        let receiver = p2p::LOCAL_PEER_ID.to_string();
+        let json = match sent_at {
+            Some(sent_at) => serde_json::json!({ "receiver": receiver, "block": block, "sent_at": sent_at }),
+            None => serde_json::json!({ "receiver": receiver, "block": block }),
+        };
+        serde_json::to_vec(&json).unwrap()
+    };
+    // The messages were received at 1000, see message()
+    let max_age = p2p::MAX_MESSAGE_AGE.as_millis() as i64;
+    let mut writer = CaptureWriter::create(&path, DEFAULT_CAPTURE_MAX_BYTES).unwrap();
+    writer.write(&message(Direction::Received, Channel::Gossip, &peer, &latest_block(Some(1_000 - max_age - 1)))).unwrap();
+    writer.write(&message(Direction::Received, Channel::Gossip, &peer, &latest_block(Some(1_000 - max_age)))).unwrap();
+    writer.write(&message(Direction::Received, Channel::Gossip, &peer, &latest_block(None))).unwrap();
+    drop(writer);
+
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel();
+    let replay = p2p::replay_capture(&path, &[], &main_sender).unwrap();
+    assert_eq!(replay.replayed, 3);
+    assert!(replay.bad.is_empty());
+    // Only the expired one is ignored, messages of older nodes without a time are accepted
+    for _ in 0..2 {
+        assert_eq!(main_rcv.try_recv().unwrap(), EventType::ReceivedLatestBlock { sender: peer.to_string(), block: block.clone() });
+    }
+    assert!(main_rcv.try_recv().is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+// Replayed events are handled by a copy of the node, our chain stays as it is
+#[tokio::test]
+async fn test_replay_isolated() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let genesis = node.chain.latest_block.clone();
+    let block = Block::new(&genesis, "replayed block".to_owned(), String::new());
+
+    let mut isolated = node.isolated().await.unwrap();
+    let event = EventType::ReceivedNewBlock { sender: PeerId::random().to_string(), block: block.clone() };
+    isolated.handle_event(event, Instant::now()).await;
+    assert_eq!(isolated.chain.latest_block, block);
+    assert_eq!(Chain::get_chain(&mut isolated.storage).await.unwrap(), vec![genesis.clone(), block]);
+
+    assert_eq!(node.chain.latest_block, genesis);
+    assert_eq!(Chain::get_chain(&mut node.storage).await.unwrap(), vec![genesis]);
+}
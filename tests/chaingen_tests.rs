@@ -0,0 +1,59 @@
+use rust_blockchain::blockchain::{Chain, REGTEST_DIFFICULTY};
+use rust_blockchain::chaingen::*;
+use rust_blockchain::difficulty::{Fixed, MIN_DIFFICULTY};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use std::sync::Arc;
+
+async fn generated_chain(blocks: u64, seed: u64) -> (Chain, Storage) {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    let mut reported = vec![];
+    generate(&mut chain, &mut storage, &GenerateConfig { blocks, seed }, &mut |done, total| reported.push((done, total)))
+        .await
+        .unwrap();
+    assert_eq!(reported, (1..=blocks).map(|done| (done, blocks)).collect::<Vec<_>>());
+    (chain, storage)
+}
+
+#[test]
+fn test_generate_options() {
+    assert_eq!(GenerateConfig::from_args(" --blocks 10000 --seed 42").unwrap(), GenerateConfig { blocks: 10_000, seed: 42 });
+    assert_eq!(GenerateConfig::from_args("--blocks 5").unwrap(), GenerateConfig { blocks: 5, seed: DEFAULT_SEED });
+    assert!(GenerateConfig::from_args("--seed 42").is_err());
+    assert!(GenerateConfig::from_args("--blocks 0").is_err());
+    assert!(GenerateConfig::from_args("--blocks 5 --seed x").is_err());
+    assert!(GenerateConfig::from_args("--blocks 5 --fast").is_err());
+}
+
+#[test]
+fn test_payloads() {
+    let first = payloads(42).take(3).collect::<Vec<_>>();
+    assert_eq!(first, payloads(42).take(3).collect::<Vec<_>>());
+    assert_ne!(first, payloads(43).take(3).collect::<Vec<_>>());
+    assert!(first[1].starts_with(&format!("{}1 ", GENERATED_PREFIX)));
+}
+
+#[tokio::test]
+async fn test_generate_deterministic_chain() {
+    let (mut chain, mut storage) = generated_chain(20, 42).await;
+    assert_eq!(chain.latest_block.id.0, 20);
+    chain.validate_chain(&mut storage).await.unwrap();
+    let blocks = Chain::get_chain(&mut storage).await.unwrap();
+    assert!(blocks.windows(2).all(|pair| pair[1].timestamp_millis() - pair[0].timestamp_millis() == BLOCK_INTERVAL_MS || pair[0].id.0 == 0));
+    assert!(blocks[1..].iter().all(|block| block.miner == GENERATOR_MINER));
+
+    // The same blocks again, other ones with another seed
+    let (_, mut same_storage) = generated_chain(20, 42).await;
+    assert_eq!(Chain::get_chain(&mut same_storage).await.unwrap(), blocks);
+    let (other, _) = generated_chain(20, 43).await;
+    assert_ne!(other.latest_block.hash, chain.latest_block.hash);
+
+    // Generating continues on top of the chain
+    let (continued, _) = generated_chain(5, 42).await;
+    assert_eq!(continued.latest_block, blocks[5]);
+    generate(&mut chain, &mut storage, &GenerateConfig { blocks: 5, seed: 1 }, &mut |_, _| {}).await.unwrap();
+    assert_eq!(chain.latest_block.id.0, 25);
+    chain.validate_chain(&mut storage).await.unwrap();
+}
@@ -0,0 +1,57 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::chains::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use std::path::Path;
+
+#[test]
+fn test_chain_spec() {
+    let spec = ChainSpec::parse("test_2").unwrap();
+    assert_eq!(spec, ChainSpec { name: "test_2".to_owned(), difficulty_algorithm: None });
+    assert_eq!(ChainSpec::parse("test:lwma").unwrap().difficulty_algorithm.as_deref(), Some("lwma"));
+    assert!(ChainSpec::parse("test:unknown").is_err());
+    assert!(ChainSpec::parse("Test").is_err());
+    assert!(ChainSpec::parse("te-st").is_err());
+    assert!(ChainSpec::parse("").is_err());
+    assert!(ChainSpec::parse(&"a".repeat(MAX_CHAIN_NAME_LENGTH + 1)).is_err());
+
+    assert_eq!(spec.topic(), "blockchain/test_2");
+    assert_eq!(spec.schema(), "chain_test_2");
+    assert_eq!(spec.chain_file(Path::new("data/chain.json")), Path::new("data/chain.json.test_2"));
+
+    // Every chain has a genesis block of its own
+    let genesis = spec.genesis();
+    assert_eq!(genesis.id, Height::GENESIS);
+    assert_eq!(genesis.hash, hasher(&genesis));
+    assert_ne!(genesis.hash, Block::create_genesis().hash);
+    assert_ne!(genesis.hash, ChainSpec::parse("other").unwrap().genesis().hash);
+    assert_eq!(genesis, spec.genesis());
+}
+
+#[test]
+fn test_split_scope() {
+    assert_eq!(split_scope("@test block mine some data"), Some(("test", "block mine some data")));
+    assert_eq!(split_scope("@test"), Some(("test", "")));
+    assert_eq!(split_scope("block mine @test"), None);
+}
+
+#[tokio::test]
+async fn test_chain_node() {
+    let genesis = ChainSpec::parse("test").unwrap().genesis();
+    let mut node = Node::init_with_genesis(Storage::Memory(MemoryStorage::default()), "miner".to_owned(), genesis.clone()).await.unwrap();
+    assert_eq!(node.chain.latest_block, genesis);
+    assert_eq!(node.chain.finalized.hash, genesis.hash);
+    let block = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    assert_eq!(block.prev_hash, genesis.hash);
+    assert!(matches!(node.chain.validate_chain(&mut node.storage).await, Ok(())));
+
+    // Blocks of the default chain do not fit
+    let mut default_node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let default_block = default_node.chain.mine_block("block 1".to_owned(), &mut default_node.storage).await.unwrap();
+    assert!(node.chain.add_block(&mut node.storage, default_block).await.is_err());
+    assert!(matches!(
+        Chain::check_if_block_valid(&mut default_node.storage, &default_node.chain.params, &genesis).await,
+        Err(BlockchainError::BlockNotFound(_))
+    ));
+}
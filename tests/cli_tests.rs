@@ -0,0 +1,54 @@
+use rust_blockchain::cli::*;
+use rust_blockchain::config::Config;
+
+#[test]
+fn test_parse_options() {
+    let options = parse_options("rust-blockchain [DB_NAME] [--storage postgres|memory] [--peer MULTIADDR]... [--chain NAME[:DAA]]... [--regtest] [--keys DIR]");
+    let names = options.iter().map(|option| option.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["--storage", "--peer", "--chain", "--regtest", "--keys"]);
+    assert_eq!(options[0].choices(), vec!["postgres", "memory"]);
+    assert!(!options[0].repeatable && options[1].repeatable && options[2].repeatable);
+    assert_eq!(options[2].value.as_deref(), Some("NAME[:DAA]"));
+    assert_eq!(options[3].value, None);
+    assert!(options[4].takes_path());
+    assert!(options[1].choices().is_empty());
+}
+
+#[test]
+fn test_usage_matches_the_parser() {
+    // Every option of the usage is known to the parser, whatever it says about the missing value
+    for option in options() {
+        let unknown = format!("unknown option: {}", option.name);
+        match Config::from_args(std::iter::once(option.name.clone())) {
+            Err(err) => assert!(!err.to_string().contains(&unknown), "{}", unknown),
+            Ok(_) => assert!(option.value.is_none(), "{} requires a value", option.name),
+        }
+    }
+}
+
+#[test]
+fn test_completions() {
+    let bash = completions("bash").unwrap();
+    assert!(bash.contains("--storage) COMPREPLY=($(compgen -W \"postgres memory\" -- \"$cur\"))"));
+    assert!(bash.contains("complete -F _rust_blockchain_cli rust-blockchain-cli"));
+    assert!(bash.contains("getblockrange"));
+    let zsh = completions("zsh").unwrap();
+    assert!(zsh.starts_with("#compdef rust-blockchain rust-blockchain-cli"));
+    assert!(zsh.contains("'*--peer:MULTIADDR: '"));
+    assert!(zsh.contains("'--keys:DIR:_files'"));
+    let fish = completions("fish").unwrap();
+    assert!(fish.contains("complete -c rust-blockchain -l log-level -x -a 'off error warn info debug trace'"));
+    assert!(fish.contains("complete -c rust-blockchain -l no-p2p\n"));
+    assert!(completions("powershell").is_err());
+}
+
+#[test]
+fn test_manpage() {
+    let page = manpage();
+    assert!(page.starts_with(".TH RUST-BLOCKCHAIN 1"));
+    assert!(page.contains(".B \\-\\-storage \\fIpostgres|memory\\fR"));
+    assert!(page.contains(".B node completions bash|zsh|fish"));
+    assert!(page.contains("\n.B exit\n"));
+    // No line starts with a dot that is not a request of ours
+    assert!(page.lines().all(|line| !line.starts_with('.') || [".TH", ".SH", ".TP", ".B"].iter().any(|request| line.starts_with(request))));
+}
@@ -0,0 +1,121 @@
+use chrono::Utc;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{activation_height, rules_at, Feature};
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::node::Node;
+use rust_blockchain::state::EMPTY_STATE_ROOT;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::{self, Transaction};
+use rust_blockchain::types::{Height, Nonce};
+use rust_blockchain::utxo;
+use rust_blockchain::wallet::Wallet;
+
+#[test]
+fn test_coinbase_transaction() {
+    let address = Wallet::generate().address();
+    let coinbase = Transaction::coinbase(address.clone(), 50, Height(7));
+    assert!(coinbase.is_coinbase());
+    assert!(coinbase.check_coinbase(Height(7)).is_ok());
+    // The nonce is the height, coinbases of other blocks have other IDs
+    assert!(coinbase.check_coinbase(Height(8)).is_err());
+    assert_ne!(Transaction::coinbase(address.clone(), 50, Height(8)).id(), coinbase.id());
+
+    assert!(Transaction { fee: 1, ..coinbase.clone() }.check_coinbase(Height(7)).is_err());
+    assert!(Transaction { signature: "00".to_owned(), ..coinbase.clone() }.check_coinbase(Height(7)).is_err());
+    assert!(Transaction { recipient: "bob".to_owned(), ..coinbase.clone() }.check_coinbase(Height(7)).is_err());
+    assert!(Transaction { sender: address.clone(), ..coinbase.clone() }.check_coinbase(Height(7)).is_err());
+    assert!(!Transaction::new(&Wallet::generate().keypair().public(), address, 1, 0).is_coinbase());
+}
+
+#[tokio::test]
+async fn test_mined_coinbase() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    // Without a wallet blocks below the Coinbase upgrade are mined without one
+    let block = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    assert_eq!(block.coinbase, None);
+
+    let wallet = Wallet::generate();
+    node.set_wallet(Some(wallet.clone()));
+    let block = node.chain.mine_block_with_fee("block 2".to_owned(), 3, &mut node.storage).await.unwrap();
+    let coinbase = block.coinbase.clone().unwrap();
+    assert_eq!(coinbase.recipient, wallet.address());
+    assert_eq!(coinbase.amount, node.chain.params.reward_at(Height(2)) + 3);
+    assert!(Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &block).await.is_ok());
+    node.chain.validate_chain(&mut node.storage).await.unwrap();
+
+    // It is received without being sent and can be spent
+    let balance = node.chain.get_balance(&mut node.storage, &wallet.address()).await.unwrap();
+    assert_eq!(balance.available(), coinbase.amount);
+    let utxos = Chain::get_utxos(&mut node.storage, &wallet.address()).await.unwrap();
+    assert_eq!(utxo::balance(&utxos), coinbase.amount);
+    assert_eq!(utxos[0].outpoint.txid, coinbase.id());
+    assert!(Chain::get_address_blocks(&mut node.storage, &wallet.address()).await.unwrap().contains(&block));
+}
+
+#[tokio::test]
+async fn test_coinbase_amount() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let wallet = Wallet::generate();
+    let mut template = node.chain.block_template(&mut node.storage, "block 1".to_owned()).await.unwrap();
+    template.coinbase = Some(Transaction::coinbase(wallet.address(), 1_000, Height(1)));
+    let forged = Block::mine_template(template, &node.chain.difficulty, HashBackend::Cpu);
+
+    let reason = node.chain.violation(&mut node.storage, &forged).await.unwrap().unwrap();
+    assert!(reason.contains("coinbase pays 1000"), "{}", reason);
+    // Also without a chain, e.g. for a block a peer relays
+    let reason = Chain::block_violation(&mut node.storage, &node.chain.params, &forged).await.unwrap().unwrap();
+    assert!(reason.contains("coinbase pays 1000"), "{}", reason);
+    assert!(matches!(Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &forged).await, Err(BlockchainError::BlockInvalid(_))));
+    assert!(matches!(node.chain.add_block(&mut node.storage, forged).await, Err(BlockchainError::BlockInvalid(_))));
+}
+
+#[tokio::test]
+async fn test_coinbase_activation() {
+    let height = activation_height(Feature::Coinbase);
+    assert!(!rules_at(height - 1).coinbase);
+    assert!(rules_at(height).coinbase);
+
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let parent = Block {
+        hash: "parent".to_owned(),
+        id: height - 1,
+        prev_hash: "grandparent".to_owned(),
+        timestamp: Utc::now().timestamp_millis() - 60_000,
+        nonce: Nonce(0),
+        data: String::new(),
+        version: BLOCK_VERSION,
+        miner: String::new(),
+        extra_nonce: Nonce(0),
+        difficulty: 1,
+        fee: 0,
+        state_root: EMPTY_STATE_ROOT.to_owned(),
+        coinbase: None,
+    };
+    storage.insert_block(&parent).await.unwrap();
+    let address = Wallet::generate().address();
+    let coinbase = Transaction::coinbase(address.clone(), 50, height);
+    let mine = |data: String, version: u8, coinbase: Option<Transaction>| {
+        let mut template = Block::template(&parent, data, "miner".to_owned(), 1);
+        template.version = version;
+        template.state_root = EMPTY_STATE_ROOT.to_owned();
+        template.coinbase = coinbase;
+        Block::mine_template(template, "", HashBackend::Cpu)
+    };
+
+    let valid = mine("data".to_owned(), BLOCK_VERSION, Some(coinbase.clone()));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &valid).await, Ok(())));
+    // The same coinbase overpays on a network with a smaller reward
+    let params = NetworkParams { initial_reward: 25, ..NetworkParams::default() };
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &params, &valid).await, Err(BlockchainError::BlockInvalid(_))));
+    let missing = mine("data".to_owned(), BLOCK_VERSION, None);
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &missing).await, Err(BlockchainError::BlockInvalid(_))));
+    let older = mine("data".to_owned(), BLOCK_VERSION - 1, Some(coinbase.clone()));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &older).await, Err(BlockchainError::BlockInvalid(_))));
+    let second = transactions::to_block_data(&[Transaction::coinbase(address, 50, height)]);
+    let twice = mine(second, BLOCK_VERSION, Some(coinbase));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &twice).await, Err(BlockchainError::BlockInvalid(_))));
+
+    // Mining requires an address to pay
+    let chain = Chain::build(parent.clone());
+    assert!(chain.coinbase(&Block::template(&parent, "data".to_owned(), String::new(), 1)).is_err());
+}
@@ -0,0 +1,110 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{Height, Nonce};
+
+fn block_at(id: Height) -> Block {
+    Block {
+        hash: String::new(),
+        id,
+        prev_hash: "prev_hash".to_owned(),
+        timestamp: 1234545678,
+        nonce: Nonce(0),
+        data: "data".to_owned(),
+        version: BLOCK_VERSION,
+        miner: "miner".to_owned(),
+        extra_nonce: Nonce(0),
+        difficulty: 0,
+        fee: 0,
+        state_root: String::new(),
+        coinbase: None,
+    }
+}
+
+#[test]
+fn test_rules_by_height() {
+    let versioned_header = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::VersionedHeader)
+        .unwrap();
+
+    assert!(!is_active(Feature::VersionedHeader, versioned_header.height - 1));
+    assert!(is_active(Feature::VersionedHeader, versioned_header.height));
+    assert_eq!(rules_at(versioned_header.height - 1).min_block_version, 0);
+    assert_eq!(rules_at(versioned_header.height).min_block_version, 1);
+    assert_eq!(rules_at(Height::GENESIS).hash_encoding, HashEncoding::Legacy);
+
+    let millisecond_timestamps = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::MillisecondTimestamps)
+        .unwrap();
+    assert_eq!(rules_at(millisecond_timestamps.height - 1).min_block_version, 1);
+    assert_eq!(rules_at(millisecond_timestamps.height).min_block_version, 2);
+}
+
+#[test]
+fn test_header_hasher() {
+    let mut block = block_at(Height(1));
+    block.data = "a".repeat(100);
+    for version in [0, 1, 2, BLOCK_VERSION] {
+        block.version = version;
+        let header_hasher = HeaderHasher::new(&block);
+        for nonce in [0, 1, 9, 10, 99_999, Nonce::MAX.0, u64::MAX] {
+            assert_eq!(header_hasher.digest(Nonce(nonce)).to_vec(), digest_with_nonce(&block, Nonce(nonce)));
+        }
+    }
+}
+
+#[test]
+fn test_hash_encoding_by_height() {
+    let padded_hex_hash = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::PaddedHexHash)
+        .unwrap();
+
+    // Legacy hashes drop the leading zero of every byte below 0x10
+    let mut block = block_at(padded_hex_hash.height - 1);
+    for nonce in 0..100 {
+        block.nonce = Nonce(nonce);
+        assert!(hasher(&block).len() <= 64);
+    }
+
+    let mut block = block_at(padded_hex_hash.height);
+    for nonce in 0..100 {
+        block.nonce = Nonce(nonce);
+        assert_eq!(hasher(&block).len(), 64);
+    }
+}
+
+// Synced chains are held to the rules active at the height of each block
+#[tokio::test]
+async fn test_update_enforces_activations() {
+    let versioned_header = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::VersionedHeader)
+        .unwrap();
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    // Every hash meets the empty difficulty prefix, so no mining is needed
+    chain.difficulty = String::new();
+
+    let mut blocks = vec![chain.latest_block.clone()];
+    while blocks.last().unwrap().id < versioned_header.height - 1 {
+        let mut block = Block::template(blocks.last().unwrap(), "data".to_owned(), String::new(), 0);
+        block.hash = hasher(&block);
+        blocks.push(block);
+    }
+    let mut unversioned = Block::template(blocks.last().unwrap(), "data".to_owned(), String::new(), 0);
+    unversioned.version = 0;
+    unversioned.hash = hasher(&unversioned);
+
+    let mut invalid = [blocks.clone(), vec![unversioned.clone()]].concat();
+    assert!(matches!(chain.update(&mut storage, &mut invalid).await, Err(BlockchainError::ChainInvalid(_))));
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![blocks[0].clone()]);
+
+    let mut versioned = Block { version: 1, ..unversioned };
+    versioned.hash = hasher(&versioned);
+    let mut valid = [blocks, vec![versioned.clone()]].concat();
+    chain.update(&mut storage, &mut valid).await.unwrap();
+    assert_eq!(chain.latest_block, versioned);
+}
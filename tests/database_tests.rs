@@ -0,0 +1,48 @@
+use rust_blockchain::config::Config;
+use rust_blockchain::database::*;
+use std::time::Duration;
+
+#[test]
+fn test_backoff() {
+    assert_eq!(backoff(0), MIN_BACKOFF);
+    assert_eq!(backoff(1), MIN_BACKOFF * 2);
+    assert_eq!(backoff(2), MIN_BACKOFF * 4);
+    assert_eq!(backoff(10), MAX_BACKOFF);
+    assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+}
+
+#[test]
+fn test_create_database_statement() {
+    assert_eq!(create_database_statement("node1"), "CREATE DATABASE \"node1\"");
+    assert_eq!(create_database_statement("a\"b"), "CREATE DATABASE \"a\"\"b\"");
+}
+
+#[test]
+fn test_db_options() {
+    let config = Config::from_args(["node1"].iter().map(|arg| arg.to_string())).unwrap();
+    assert_eq!(config.db_host, DEFAULT_DB_HOST);
+    assert_eq!(config.db_wait, DEFAULT_DB_WAIT);
+
+    let config = Config::from_args(["node1", "--db-host", "postgres", "--db-wait", "0"].iter().map(|arg| arg.to_string())).unwrap();
+    assert_eq!(config.db_host, "postgres");
+    assert_eq!(config.db_wait, Duration::ZERO);
+
+    assert!(Config::from_args(["node1", "--db-wait", "soon"].iter().map(|arg| arg.to_string())).is_err());
+    assert!(Config::from_args(["node1", "--db-host"].iter().map(|arg| arg.to_string())).is_err());
+}
+
+// A missing database is created on connect
+#[tokio::test]
+async fn test_create_missing_database() {
+    let db_name = "blockchain_test_created";
+    let (db_client, _db_task) = connect(DEFAULT_DB_HOST, MAINTENANCE_DB, Duration::ZERO).await.unwrap();
+    db_client.batch_execute(&format!("DROP DATABASE IF EXISTS {}", db_name)).await.unwrap();
+
+    let (created, _db_task) = connect(DEFAULT_DB_HOST, db_name, Duration::ZERO).await.unwrap();
+    let row = created.query_one("SELECT current_database()", &[]).await.unwrap();
+    assert_eq!(row.get::<_, String>(0), db_name);
+    drop(created);
+
+    // Exists now
+    connect(DEFAULT_DB_HOST, db_name, Duration::ZERO).await.unwrap();
+}
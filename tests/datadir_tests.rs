@@ -0,0 +1,60 @@
+use rust_blockchain::config::{Config, StorageKind};
+use rust_blockchain::datadir::*;
+use rust_blockchain::network::NetworkParams;
+use std::fs;
+use std::path::PathBuf;
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("blockchain_datadir_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn parse(args: &[&str]) -> Result<Config, rust_blockchain::blockchain::BlockchainError> {
+    Config::from_args(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn test_data_dir_layout() {
+    let dir = data_dir("layout");
+    let data_dir = DataDir::new(&dir);
+    assert!(data_dir.init().unwrap());
+    assert!(dir.join(KEYS_DIR).is_dir() && dir.join(LOGS_DIR).is_dir());
+    // The config template only holds comments
+    assert_eq!(data_dir.config_args().unwrap(), Vec::<String>::new());
+    assert!(!data_dir.init().unwrap());
+
+    let config = parse(&["--storage", "memory", "--data-dir", dir.to_str().unwrap()]).unwrap();
+    assert_eq!(config.data_dir, Some(data_dir.clone()));
+    assert_eq!(config.keys, Some(dir.join(KEYS_DIR)));
+    assert_eq!(config.snapshot, Some(dir.join(SNAPSHOT_FILE)));
+    assert_eq!(config.mempool, Some(dir.join(MEMPOOL_FILE)));
+    assert_eq!(data_dir.log_file(), dir.join(LOGS_DIR).join(LOG_FILE));
+
+    // Explicit options win
+    let config = parse(&["--storage", "memory", "--data-dir", dir.to_str().unwrap(), "--snapshot", "other.json"]).unwrap();
+    assert_eq!(config.snapshot, Some(PathBuf::from("other.json")));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_data_dir_config_file() {
+    let dir = data_dir("config");
+    let data_dir = DataDir::new(&dir);
+    data_dir.init().unwrap();
+    fs::write(data_dir.config(), "# test node\n--storage memory\n--listen /ip4/127.0.0.1/tcp/4001 # local only\n").unwrap();
+    let network = NetworkParams { initial_reward: 10, ..NetworkParams::default() };
+    fs::write(data_dir.network(), serde_json::to_string(&network).unwrap()).unwrap();
+
+    let config = parse(&["--data-dir", dir.to_str().unwrap(), "--listen", "/ip4/127.0.0.1/tcp/4002"]).unwrap();
+    assert_eq!(config.storage, StorageKind::Memory);
+    assert_eq!(config.listen, vec!["/ip4/127.0.0.1/tcp/4001", "/ip4/127.0.0.1/tcp/4002"]);
+    assert_eq!(config.network, network);
+    // The command line overrides the config file
+    assert_eq!(parse(&["--data-dir", dir.to_str().unwrap(), "--storage", "postgres", "db"]).unwrap().storage, StorageKind::Postgres);
+
+    fs::write(data_dir.config(), "--data-dir /elsewhere\n").unwrap();
+    assert!(parse(&["--data-dir", dir.to_str().unwrap()]).is_err());
+    assert!(parse(&["--storage", "memory", "--data-dir"]).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
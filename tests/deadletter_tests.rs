@@ -0,0 +1,32 @@
+use rust_blockchain::deadletter::*;
+
+#[test]
+fn test_bad_message() {
+    let data = vec![b'x'; BAD_MESSAGE_PREFIX + 10];
+    let message = BadMessage::new("peer".to_owned(), &data, decode_error(&data), 1);
+    assert_eq!(message.size, data.len() as u64);
+    assert_eq!(message.prefix, hex::encode(&data[..BAD_MESSAGE_PREFIX]));
+    assert!(message.error.starts_with("invalid JSON"));
+
+    let message = BadMessage::new("peer".to_owned(), b"{}", decode_error(b"{}"), 1);
+    assert_eq!(message.prefix, hex::encode(b"{}"));
+    assert_eq!(message.error, "unknown message type");
+}
+
+#[test]
+fn test_dead_letters() {
+    let message = |peer: &str, received_at| BadMessage::new(peer.to_owned(), b"bad", "invalid JSON".to_owned(), received_at);
+    let mut dead_letters = DeadLetters::new(2);
+    assert_eq!(dead_letters.recent().count(), 0);
+    dead_letters.push(message("a", 1));
+    dead_letters.push(message("b", 2));
+    dead_letters.push(message("b", 3));
+
+    // The oldest message is dropped, but still counted
+    let recent = dead_letters.recent().map(|message| message.received_at).collect::<Vec<_>>();
+    assert_eq!(recent, vec![3, 2]);
+    assert_eq!(dead_letters.count("a"), 1);
+    assert_eq!(dead_letters.count("b"), 2);
+    assert_eq!(dead_letters.count("c"), 0);
+    assert_eq!(dead_letters.counts(), vec![("b", 2), ("a", 1)]);
+}
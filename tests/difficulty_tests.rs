@@ -0,0 +1,196 @@
+use chrono::Utc;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::*;
+use rust_blockchain::difficulty::*;
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{Height, Nonce};
+use std::sync::Arc;
+
+const TARGET: i64 = 1_000;
+
+// Blocks with the given block times (the first block is at time 0)
+fn blocks(block_times: &[i64], difficulty: u64) -> Vec<BlockInfo> {
+    let mut timestamp = 0;
+    let mut blocks = vec![BlockInfo { height: Height(0), timestamp, difficulty }];
+    for (index, block_time) in block_times.iter().enumerate() {
+        timestamp += block_time;
+        blocks.push(BlockInfo { height: Height(index as u64 + 1), timestamp, difficulty });
+    }
+    blocks
+}
+
+#[test]
+fn test_fixed() {
+    let fixed = Fixed { difficulty: 42 };
+    assert_eq!(fixed.next_difficulty(&[]), 42);
+    assert_eq!(fixed.next_difficulty(&blocks(&[1, 1, 1], 1_000)), 42);
+}
+
+#[test]
+fn test_epoch() {
+    let epoch = Epoch { interval: 4, target_block_time: TARGET };
+    // Only retargets at multiples of the interval
+    let fast = blocks(&[TARGET / 2; 7], 100);
+    assert_eq!(epoch.next_difficulty(&fast[..7]), 100);
+    assert_eq!(epoch.next_difficulty(&fast[3..]), 200);
+    // By at most a factor of 4
+    let slow = blocks(&[TARGET * 10; 7], 100);
+    assert_eq!(epoch.next_difficulty(&slow[3..]), 25);
+    let on_target = blocks(&[TARGET; 7], 100);
+    assert_eq!(epoch.next_difficulty(&on_target[3..]), 100);
+}
+
+#[test]
+fn test_lwma() {
+    let lwma = Lwma { window: 10, target_block_time: TARGET };
+    assert_eq!(lwma.next_difficulty(&blocks(&[TARGET; 10], 1_000)), 1_000);
+    assert_eq!(lwma.next_difficulty(&blocks(&[TARGET / 2; 10], 1_000)), 2_000);
+    assert_eq!(lwma.next_difficulty(&blocks(&[TARGET * 2; 10], 1_000)), 500);
+
+    // Recent blocks count the most
+    let mut block_times = vec![TARGET; 9];
+    block_times.push(TARGET / 10);
+    let recent_fast = lwma.next_difficulty(&blocks(&block_times, 1_000));
+    block_times.rotate_right(1);
+    let early_fast = lwma.next_difficulty(&blocks(&block_times, 1_000));
+    assert!(recent_fast > early_fast && early_fast > 1_000);
+
+    // Out of order timestamps do not result in huge jumps
+    assert!(lwma.next_difficulty(&blocks(&[-TARGET * 100; 10], 1_000)) <= 10_000);
+    assert_eq!(lwma.next_difficulty(&[]), INITIAL_DIFFICULTY);
+}
+
+#[test]
+fn test_asert() {
+    let asert = Asert { anchor: Height(0), half_life: 10 * TARGET, target_block_time: TARGET };
+    assert_eq!(asert.next_difficulty(&blocks(&[TARGET], 1_000)), 1_000);
+    assert_eq!(asert.next_difficulty(&blocks(&[TARGET + 10 * TARGET], 1_000)), 500);
+    assert_eq!(asert.next_difficulty(&blocks(&[TARGET - 10 * TARGET], 1_000)), 2_000);
+
+    // Half a half-life slower is about 1/sqrt(2)
+    let difficulty = asert.next_difficulty(&blocks(&[TARGET + 5 * TARGET], 1_000_000));
+    assert!((707_000..=707_200).contains(&difficulty));
+
+    // Stays within the range that can be stored
+    assert_eq!(asert.next_difficulty(&blocks(&[i64::MIN / 2], MAX_DIFFICULTY)), MAX_DIFFICULTY);
+    assert_eq!(asert.next_difficulty(&blocks(&[i64::MAX / 2], 1)), MIN_DIFFICULTY);
+
+    // Only the schedule since the anchor counts, not the difficulties in between
+    let mut on_schedule = blocks(&[TARGET / 2; 10], 1_000);
+    on_schedule[10].timestamp = 10 * TARGET;
+    on_schedule[10].difficulty = 5;
+    assert_eq!(asert.next_difficulty(&[on_schedule[0], on_schedule[10]]), 1_000);
+    on_schedule[10].timestamp = 0;
+    assert_eq!(asert.next_difficulty(&[on_schedule[0], on_schedule[10]]), 2_000);
+    // Without the anchor the difficulty stays the same
+    assert_eq!(asert.next_difficulty(&on_schedule[5..6]), 1_000);
+}
+
+#[test]
+fn test_meets_difficulty() {
+    assert!(meets_difficulty(&[0; 32], MAX_DIFFICULTY));
+    assert!(meets_difficulty(&[0xff; 32], 1));
+    assert!(!meets_difficulty(&[0xff; 32], 2));
+    let mut digest = [0; 32];
+    digest[0] = 0x7f;
+    assert!(meets_difficulty(&digest, 2));
+    assert!(!meets_difficulty(&digest, 3));
+
+    for name in ALGORITHMS {
+        assert!(algorithm_by_name(name).is_some());
+    }
+    assert!(algorithm_by_name("magic").is_none());
+}
+
+#[tokio::test]
+async fn test_difficulty_adjustment_activation() {
+    let activation = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::DifficultyAdjustment)
+        .unwrap();
+    assert!(!rules_at(activation.height - 1).difficulty_adjustment);
+    assert!(rules_at(activation.height).difficulty_adjustment);
+    assert_eq!(rules_at(activation.height).min_block_version, 3);
+
+    // Blocks right below the activation height, only their links and timestamps matter here
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let now = Utc::now().timestamp_millis();
+    let mut parent = Block::create_genesis();
+    for id in activation.height.0 - 10..activation.height.0 {
+        parent = Block {
+            hash: format!("block {}", id),
+            id: Height(id),
+            prev_hash: format!("block {}", id - 1),
+            timestamp: now - 60_000,
+            nonce: Nonce(0),
+            data: String::new(),
+            version: 2,
+            miner: String::new(),
+            extra_nonce: Nonce(0),
+            difficulty: 0,
+            fee: 0,
+            state_root: String::new(),
+            coinbase: None,
+        };
+        storage.insert_block(&parent).await.unwrap();
+    }
+
+    let mut chain = Chain::build(parent);
+    chain.difficulty_algorithm = Arc::new(Asert {
+        anchor: activation.height - 10,
+        half_life: 10 * TARGET,
+        target_block_time: TARGET,
+    });
+    let block = chain.mine_block("first adjusted block".to_owned(), &mut storage).await.unwrap();
+    assert_eq!(block.version, BLOCK_VERSION);
+    // The anchor counts with the initial difficulty, the blocks since were all found instantly
+    assert!(block.difficulty > INITIAL_DIFFICULTY);
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &block).await, Ok(())));
+    assert!(matches!(chain.check_difficulty(&mut storage, &block).await, Ok(())));
+
+    // Nodes with another algorithm expect another difficulty
+    let mut other_chain = chain.clone();
+    other_chain.difficulty_algorithm = Arc::new(Fixed { difficulty: 2 });
+    assert!(matches!(other_chain.check_difficulty(&mut storage, &block).await, Err(BlockchainError::BlockInvalid(_))));
+
+    // The hash has to meet the difficulty in the header
+    let mut hard_block = block.clone();
+    hard_block.difficulty = MAX_DIFFICULTY;
+    hard_block.hash = hasher(&hard_block);
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &hard_block).await, Err(BlockchainError::BlockInvalid(_))));
+}
+
+// Synced chains have to carry the difficulties our algorithm computes
+#[tokio::test]
+async fn test_update_checks_difficulty() {
+    let activation = activation_height(Feature::DifficultyAdjustment);
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    // Every hash meets the empty difficulty prefix and the minimal difficulty, so no mining is needed
+    chain.difficulty = String::new();
+    chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+
+    let mut blocks = vec![chain.latest_block.clone()];
+    while blocks.last().unwrap().id < activation - 1 {
+        let mut block = Block::template(blocks.last().unwrap(), String::new(), String::new(), 0);
+        block.hash = hasher(&block);
+        blocks.push(block);
+    }
+    let mut easy = Block::template(blocks.last().unwrap(), String::new(), String::new(), MIN_DIFFICULTY);
+    easy.hash = hasher(&easy);
+    // Any difficulty the hash meets, but not the one of the network
+    let mut hard = Block { difficulty: MIN_DIFFICULTY + 1, ..easy.clone() };
+    while !meets_difficulty(&digest_with_nonce(&hard, hard.nonce), hard.difficulty) {
+        hard.nonce = Nonce(hard.nonce.0 + 1);
+    }
+    hard.hash = hasher(&hard);
+
+    let mut invalid = [blocks.clone(), vec![hard]].concat();
+    assert!(matches!(chain.update(&mut storage, &mut invalid).await, Err(BlockchainError::ChainInvalid(_))));
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![blocks[0].clone()]);
+
+    let mut valid = [blocks, vec![easy.clone()]].concat();
+    chain.update(&mut storage, &mut valid).await.unwrap();
+    assert_eq!(chain.latest_block, easy);
+}
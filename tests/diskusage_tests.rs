@@ -0,0 +1,58 @@
+use rust_blockchain::blockchain::{Block, Chain};
+use rust_blockchain::diskusage::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+
+#[test]
+fn test_usage_options() {
+    assert_eq!(UsageConfig::from_args("").unwrap(), UsageConfig { from: Height::GENESIS, to: None, top: DEFAULT_TOP });
+    assert_eq!(
+        UsageConfig::from_args(" --from-height 10 --to-height 20 --top 3").unwrap(),
+        UsageConfig { from: Height(10), to: Some(Height(20)), top: 3 }
+    );
+    assert!(UsageConfig::from_args("--top").is_err());
+    assert!(UsageConfig::from_args("--from-height x").is_err());
+    assert!(UsageConfig::from_args("--from-height 20 --to-height 10").is_err());
+    assert!(UsageConfig::from_args("--all").is_err());
+}
+
+#[test]
+fn test_format_bytes() {
+    assert_eq!(format_bytes(0), "0 B");
+    assert_eq!(format_bytes(1023), "1023 B");
+    assert_eq!(format_bytes(1536), "1.5 KiB");
+    assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+}
+
+#[test]
+fn test_payload_usage() {
+    let block = |id: u64, size: usize| Block { id: Height(id), hash: format!("hash{}", id), data: "x".repeat(size), ..Block::create_genesis() };
+    let blocks = vec![block(1, 10), block(2, 30), block(3, 30), block(4, 0)];
+    let usage = PayloadUsage::from_blocks(&blocks, 2);
+    assert_eq!((usage.blocks, usage.total_bytes, usage.max_bytes), (4, 70, 30));
+    assert_eq!(usage.avg_bytes(), 17.5);
+    // Blocks of the same size lowest first
+    assert_eq!(usage.largest.iter().map(|block| block.id).collect::<Vec<_>>(), vec![Height(2), Height(3)]);
+    assert_eq!(PayloadUsage::from_blocks(&[], 2).avg_bytes(), 0.0);
+}
+
+#[tokio::test]
+async fn test_disk_usage_report() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    for data in ["small", "a much larger payload", "medium data"] {
+        chain.mine_block(data.to_owned(), &mut storage).await.unwrap();
+    }
+
+    let usage = report(&chain, &mut storage, &UsageConfig { from: Height(1), to: Some(Height(100)), top: 1 }).await.unwrap();
+    assert_eq!((usage.from, usage.to), (Height(1), Height(3)));
+    assert_eq!(usage.payloads.blocks, 3);
+    assert_eq!(usage.payloads.total_bytes, 37);
+    assert_eq!(usage.payloads.largest[0].id, Height(2));
+    assert_eq!(usage.tables.len(), 1);
+    assert!(usage.total_bytes() > usage.payloads.total_bytes);
+    assert!(usage.to_string().contains("heights 1 to 3: 3 blocks | payloads: 37 B | avg: 12 B | max: 21 B"));
+
+    let usage = report(&chain, &mut storage, &UsageConfig { from: Height(5), to: None, top: 1 }).await.unwrap();
+    assert_eq!(usage.payloads, PayloadUsage::default());
+}
@@ -0,0 +1,52 @@
+use libp2p::{Multiaddr, PeerId};
+use rust_blockchain::diversity::*;
+use std::time::{Duration, Instant};
+
+fn addr(addr: &str) -> Multiaddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_address_group() {
+    assert_eq!(address_group(&addr("/ip4/10.1.2.3/tcp/4001")), Some("10.1.0.0/16".to_owned()));
+    assert_eq!(address_group(&addr("/ip4/10.1.200.7/tcp/4002")), address_group(&addr("/ip4/10.1.2.3/tcp/4001")));
+    assert_ne!(address_group(&addr("/ip4/10.2.2.3/tcp/4001")), address_group(&addr("/ip4/10.1.2.3/tcp/4001")));
+    assert_eq!(address_group(&addr("/ip6/2001:db8:1::1/tcp/4001")), Some("2001:db8::/32".to_owned()));
+    assert_eq!(address_group(&addr("/dns4/seed.example.com/tcp/4001")), Some("seed.example.com".to_owned()));
+    assert_eq!(address_group(&addr("/memory/1")), None);
+}
+
+#[test]
+fn test_outbound_diversity() {
+    let now = Instant::now();
+    let mut diversity = Diversity::default();
+    let (mdns_a, mdns_b, px, inbound) = (PeerId::random(), PeerId::random(), PeerId::random(), PeerId::random());
+    diversity.discovered(mdns_a, Discovery::Mdns);
+    diversity.discovered(mdns_b, Discovery::Mdns);
+    diversity.discovered(px, Discovery::PeerExchange);
+    // The first mechanism counts
+    diversity.discovered(px, Discovery::Mdns);
+    assert_eq!(diversity.discovery(&px), Some(Discovery::PeerExchange));
+
+    diversity.connected(mdns_a, &addr("/ip4/192.168.1.2/tcp/4001"), now);
+    assert!(!diversity.is_diverse());
+    // Another mDNS peer in the same range adds nothing, a peer exchange peer in another range does
+    assert!(!diversity.adds_diversity(&mdns_b, &addr("/ip4/192.168.1.3/tcp/4001")));
+    assert!(diversity.adds_diversity(&mdns_b, &addr("/ip4/203.0.113.7/tcp/4001")));
+    assert!(diversity.adds_diversity(&px, &addr("/ip4/192.168.1.4/tcp/4001")));
+
+    diversity.connected(mdns_b, &addr("/ip4/192.168.1.3/tcp/4001"), now + Duration::from_secs(1));
+    // Too few outbound peers to rotate one
+    assert_eq!(diversity.rotate(), None);
+    diversity.connected(px, &addr("/ip4/203.0.113.7/tcp/4001"), now + Duration::from_secs(2));
+    assert_eq!((diversity.mechanisms(), diversity.groups()), (2, 2));
+    assert!(diversity.is_diverse());
+    assert!(!diversity.adds_diversity(&inbound, &addr("/ip4/198.51.100.1/tcp/4001")));
+
+    // The longest connected peer of the most common range is rotated out
+    assert_eq!(diversity.rotate(), Some(mdns_a));
+    assert!(diversity.was_rotated(&mdns_a));
+    diversity.disconnected(&mdns_a);
+    assert!(!diversity.is_outbound(&mdns_a));
+    assert_eq!(diversity.rotate(), None);
+}
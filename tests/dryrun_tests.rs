@@ -0,0 +1,82 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::dryrun::{self, BlockVerdict, ChainVerdict};
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use std::{env, fs};
+
+async fn node() -> Node {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    node
+}
+
+#[tokio::test]
+async fn test_check_block() {
+    let mut node = node().await;
+    let mut candidate = node.isolated().await.unwrap();
+    let block = candidate.chain.mine_block("block 2".to_owned(), &mut candidate.storage).await.unwrap();
+
+    let verdict = dryrun::check_block(&node.chain, &mut node.storage, &block).await.unwrap();
+    assert_eq!(verdict, BlockVerdict::Extends);
+    // Nothing was stored
+    assert_eq!(node.chain.latest_block.id, Height(1));
+    assert!(node.storage.get_block(&block.hash).await.is_err());
+
+    let tampered = Block { data: "tampered".to_owned(), ..block.clone() };
+    match dryrun::check_block(&node.chain, &mut node.storage, &tampered).await.unwrap() {
+        BlockVerdict::Invalid(reason) => assert!(reason.contains("hashes to"), "{}", reason),
+        verdict => panic!("unexpected verdict {:?}", verdict),
+    }
+    let orphan = Block { prev_hash: "unknown".to_owned(), ..block.clone() };
+    assert_eq!(dryrun::check_block(&node.chain, &mut node.storage, &orphan).await.unwrap(), BlockVerdict::UnknownParent);
+
+    // A competing block at the same height would be stale
+    node.chain.mine_block("block 2b".to_owned(), &mut node.storage).await.unwrap();
+    assert_eq!(dryrun::check_block(&node.chain, &mut node.storage, &block).await.unwrap(), BlockVerdict::Stale);
+    let latest = node.chain.latest_block.clone();
+    assert_eq!(dryrun::check_block(&node.chain, &mut node.storage, &latest).await.unwrap(), BlockVerdict::Known);
+}
+
+#[test]
+fn test_parse_block() {
+    let block = Block::create_genesis();
+    let json = serde_json::to_string(&block).unwrap();
+    assert_eq!(dryrun::parse_block(&json).unwrap(), block);
+    assert_eq!(dryrun::parse_block(&format!(" {}\n", hex::encode(&json))).unwrap(), block);
+    assert!(dryrun::parse_block("not hex").is_err());
+    assert!(dryrun::parse_block("{}").is_err());
+}
+
+#[tokio::test]
+async fn test_check_chain() {
+    let mut node = node().await;
+    let mut candidate = node.isolated().await.unwrap();
+    candidate.chain.mine_block("block 2".to_owned(), &mut candidate.storage).await.unwrap();
+    candidate.chain.mine_block("block 3".to_owned(), &mut candidate.storage).await.unwrap();
+    let blocks = Chain::get_chain(&mut candidate.storage).await.unwrap();
+
+    // A complete chain and its tail above our latest block
+    assert_eq!(dryrun::check_chain(&mut node, &blocks).await.unwrap(), ChainVerdict::Extends);
+    assert_eq!(dryrun::check_chain(&mut node, &blocks[2..]).await.unwrap(), ChainVerdict::Extends);
+    assert_eq!(node.chain.latest_block.id, Height(1));
+
+    let mut tampered = blocks.clone();
+    tampered[3].data = "tampered".to_owned();
+    match dryrun::check_chain(&mut node, &tampered).await.unwrap() {
+        ChainVerdict::Invalid(rejection) => assert_eq!(rejection.height, Height(3)),
+        verdict => panic!("unexpected verdict {:?}", verdict),
+    }
+
+    // Once we mined our own block 2 the candidate is a fork replacing it
+    node.chain.mine_block("block 2b".to_owned(), &mut node.storage).await.unwrap();
+    assert_eq!(dryrun::check_chain(&mut node, &blocks).await.unwrap(), ChainVerdict::Replaces { fork: Height(2), replaced: 1 });
+    assert_eq!(dryrun::check_chain(&mut node, &blocks[..3]).await.unwrap(), ChainVerdict::NotLonger);
+
+    let path = env::temp_dir().join("rust_blockchain_dryrun_chain.json");
+    fs::write(&path, serde_json::to_string(&blocks.iter().rev().collect::<Vec<_>>()).unwrap()).unwrap();
+    assert_eq!(dryrun::read_chain(&path).unwrap(), blocks);
+    fs::write(&path, "[]").unwrap();
+    assert!(dryrun::read_chain(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,62 @@
+use rust_blockchain::config::Config;
+use rust_blockchain::endpoints::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::EventType;
+use std::time::Instant;
+
+fn announcement(peer_id: &str, url: &str, announced_at: i64) -> ServiceAnnouncement {
+    ServiceAnnouncement { peer_id: peer_id.to_owned(), services: vec![format!("rpc={}", url).parse().unwrap()], announced_at }
+}
+
+#[test]
+fn test_services() {
+    let service = "rpc=https://node.example.org/rpc".parse::<Service>().unwrap();
+    assert_eq!(service, Service { kind: "rpc".to_owned(), url: "https://node.example.org/rpc".to_owned() });
+    assert_eq!(service.to_string(), "rpc=https://node.example.org/rpc");
+    assert!("events=tcp://10.0.0.1:3336".parse::<Service>().is_ok());
+    assert!("https://node.example.org".parse::<Service>().is_err());
+    assert!("RPC=https://node.example.org".parse::<Service>().is_err());
+    assert!("rpc=ftp://node.example.org".parse::<Service>().is_err());
+    assert!("rpc=https://".parse::<Service>().is_err());
+    assert!("rpc=https://node example".parse::<Service>().is_err());
+
+    let args = ["--announce-service", "rpc=https://node.example.org/rpc", "--announce-service", "events=tcp://node.example.org:3336"];
+    let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+    assert_eq!(config.services.len(), 2);
+    assert!(Config::from_args(["--announce-service", "rpc"].iter().map(|arg| arg.to_string())).is_err());
+}
+
+#[test]
+fn test_directory() {
+    let now = 1_000_000_000;
+    let mut directory = ServiceDirectory::new();
+    assert!(directory.record(announcement("b", "https://b.example.org", now), now).unwrap());
+    assert!(directory.record(announcement("a", "https://a.example.org", now - 1_000), now).unwrap());
+    assert_eq!(directory.urls("rpc"), vec!["https://a.example.org", "https://b.example.org"]);
+    // Older or repeated announcements do not replace newer ones
+    assert!(!directory.record(announcement("b", "https://old.example.org", now - 1), now).unwrap());
+    assert!(!directory.record(announcement("b", "https://b.example.org", now), now).unwrap());
+    assert!(directory.record(announcement("a", "https://new.example.org", now), now).unwrap());
+    assert_eq!(directory.urls("rpc"), vec!["https://new.example.org", "https://b.example.org"]);
+    assert!(directory.urls("events").is_empty());
+
+    // Expired, from the future or without services
+    assert!(!directory.record(announcement("c", "https://c.example.org", now - SERVICE_TTL_MILLIS - 1), now).unwrap());
+    assert!(directory.record(announcement("c", "https://c.example.org", now + 10 * 60_000), now).is_err());
+    let empty = ServiceAnnouncement { services: vec![], ..announcement("c", "https://c.example.org", now) };
+    assert!(directory.record(empty, now).is_err());
+
+    assert_eq!(directory.expire(now + SERVICE_TTL_MILLIS), 0);
+    assert_eq!(directory.expire(now + SERVICE_TTL_MILLIS + 1), 2);
+    assert!(directory.is_empty());
+}
+
+#[tokio::test]
+async fn test_node_records_announcements() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "node".to_owned()).await.unwrap();
+    let now = chrono::Utc::now().timestamp_millis();
+    let event = EventType::ReceivedServiceAnnouncement { sender: "peer".to_owned(), announcement: announcement("peer", "https://peer.example.org", now) };
+    assert!(node.handle_event(event, Instant::now()).await.is_empty());
+    assert_eq!(node.services.urls("rpc"), vec!["https://peer.example.org"]);
+}
@@ -0,0 +1,92 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::events::*;
+use rust_blockchain::head::HeadEvent;
+use rust_blockchain::keys::KeyRotation;
+use rust_blockchain::mining::{Enqueued, MiningQueue, Priority};
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time;
+
+#[test]
+fn test_block_filter() {
+    let genesis = Block::create_genesis();
+    let block = Block::new(&genesis, "invoice 42 paid".to_owned(), "alice".to_owned());
+    assert!(BlockFilter::default().matches(&block));
+    assert!(BlockFilter { data_contains: Some("42 paid".to_owned()), ..Default::default() }.matches(&block));
+    assert!(!BlockFilter { data_contains: Some("43".to_owned()), ..Default::default() }.matches(&block));
+    assert!(BlockFilter { address: Some("alice".to_owned()), ..Default::default() }.matches(&block));
+    assert!(!BlockFilter { address: Some("bob".to_owned()), ..Default::default() }.matches(&block));
+    assert!(BlockFilter { min_height: Some(Height(1)), max_height: Some(Height(1)), ..Default::default() }.matches(&block));
+    assert!(!BlockFilter { min_height: Some(Height(2)), ..Default::default() }.matches(&block));
+    assert!(!BlockFilter { max_height: Some(Height(0)), ..Default::default() }.matches(&block));
+    assert_eq!(serde_json::from_str::<BlockFilter>("{}").unwrap(), BlockFilter::default());
+}
+
+#[tokio::test]
+async fn test_blocks_since() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let genesis = node.chain.latest_block.clone();
+    let block1 = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    let block2 = node.chain.mine_block("block 2".to_owned(), &mut node.storage).await.unwrap();
+    assert_eq!(node.blocks_since(&genesis).await.unwrap(), vec![block1.clone(), block2.clone()]);
+    assert!(node.blocks_since(&block2).await.unwrap().is_empty());
+
+    // After a reorg everything after the finalized block is new
+    let fork = Block::new(&genesis, "fork 1".to_owned(), String::new());
+    node.chain.update(&mut node.storage, &mut [genesis, fork.clone()]).await.unwrap();
+    assert_eq!(node.blocks_since(&block2).await.unwrap(), vec![fork]);
+}
+
+async fn next_message<R: AsyncBufReadExt + Unpin>(lines: &mut tokio::io::Lines<R>) -> EventMessage {
+    let line = time::timeout(Duration::from_secs(10), lines.next_line()).await.unwrap().unwrap().unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+#[tokio::test]
+async fn test_event_stream() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (head_sender, _) = broadcast::channel(EVENT_BUFFER);
+    let (mempool_sender, _) = broadcast::channel(EVENT_BUFFER);
+    tokio::spawn(init_events(listener, head_sender.clone(), mempool_sender.clone()));
+
+    let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let filter = BlockFilter { data_contains: Some("invoice".to_owned()), ..Default::default() };
+    writer.write_all(format!("{}\n", serde_json::to_string(&filter).unwrap()).as_bytes()).await.unwrap();
+    assert_eq!(next_message(&mut lines).await, EventMessage::Subscribed(filter));
+
+    // Only matching blocks are sent
+    let genesis = Block::create_genesis();
+    let other = Block::new(&genesis, "other".to_owned(), String::new());
+    let invoice = Block::new(&other, "invoice 1".to_owned(), String::new());
+    head_sender.send(HeadEvent::Extended(other.clone())).unwrap();
+    head_sender.send(HeadEvent::Extended(invoice.clone())).unwrap();
+    assert_eq!(next_message(&mut lines).await, EventMessage::Block(invoice.clone()));
+
+    // Reorgs are sent regardless of the filter
+    let reorged = HeadEvent::Reorged { old_tip: invoice.clone(), new_tip: other.clone(), depth: 1 };
+    head_sender.send(reorged).unwrap();
+    assert_eq!(next_message(&mut lines).await, EventMessage::Reorged { old_tip: invoice, new_tip: other, depth: 1 });
+
+    // So are replaced mining jobs
+    let (old, new, newer) = (ed25519::Keypair::generate(), ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let mut queue = MiningQueue::new();
+    queue.set_event_sender(mempool_sender);
+    queue.push_with_fee(KeyRotation::new(&old, &new).to_block_data(), Priority::Normal, 10);
+    assert_eq!(queue.push_with_fee(KeyRotation::new(&old, &newer).to_block_data(), Priority::Normal, 20), Enqueued::Replaced { id: 2, replaced: 1 });
+    match next_message(&mut lines).await {
+        EventMessage::Replaced { replaced, by } => assert_eq!((replaced.id, by.id), (1, 2)),
+        message => panic!("unexpected message: {:?}", message),
+    }
+
+    writer.write_all(b"not a filter\n").await.unwrap();
+    assert!(matches!(next_message(&mut lines).await, EventMessage::Invalid(_)));
+}
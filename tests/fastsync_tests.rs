@@ -0,0 +1,325 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{activation_height, Feature};
+use rust_blockchain::difficulty::{BlockInfo, DifficultyAlgorithm, Fixed, MIN_DIFFICULTY};
+use rust_blockchain::fastsync::{self, ChainSnapshot, SnapshotTrust, SnapshotVotes, FAST_SYNC_MIN_HEIGHT, SNAPSHOT_BLOCKS};
+use rust_blockchain::handshake::{Features, Handshake};
+use rust_blockchain::integrity;
+use rust_blockchain::node::Node;
+use rust_blockchain::replay;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{EventType, Height};
+use std::sync::Arc;
+use std::time::Instant;
+
+// A chain whose blocks need no mining: every hash meets the empty prefix and the minimal difficulty
+async fn easy_chain(storage: &mut Storage) -> Chain {
+    let mut chain = Chain::init(storage).await.unwrap();
+    chain.difficulty = String::new();
+    chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    chain
+}
+
+async fn grow(chain: &mut Chain, storage: &mut Storage, height: Height) {
+    while chain.latest_block.id < height {
+        let mut block = chain.block_template(storage, String::new()).await.unwrap();
+        block.hash = hasher(&block);
+        chain.add_block(storage, block).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_restore_snapshot() {
+    let mut server_storage = Storage::Memory(MemoryStorage::default());
+    let mut server = easy_chain(&mut server_storage).await;
+    grow(&mut server, &mut server_storage, Height(300)).await;
+    let snapshot = ChainSnapshot::create(&server, &mut server_storage).await.unwrap();
+    assert_eq!(snapshot.blocks.len() as u64, SNAPSHOT_BLOCKS);
+    assert_eq!(snapshot.links.len(), 201);
+    assert_eq!(snapshot.blocks.last(), Some(&server.latest_block));
+
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = easy_chain(&mut storage).await;
+    fastsync::restore(&mut chain, &mut storage, &snapshot).await.unwrap();
+    assert_eq!(chain.latest_block, server.latest_block);
+    assert_eq!(chain.base.as_ref().map(|base| base.id), Some(Height(201)));
+    assert_eq!(chain.finalized.id, Height(300 - FINALITY_DEPTH));
+    assert_eq!(storage.count_blocks().await.unwrap(), SNAPSHOT_BLOCKS);
+    assert_eq!(integrity::check(&mut storage, &chain.genesis).await.unwrap(), None);
+    chain.validate_chain(&mut storage).await.unwrap();
+    // A restored chain can not be snapshotted or replayed, it lacks the blocks below its base
+    assert!(ChainSnapshot::create(&chain, &mut storage).await.is_err());
+    assert!(replay::replay(&chain, &mut storage, &mut |_, _| {}).await.is_err());
+
+    // Only the tail is synced from now on
+    grow(&mut server, &mut server_storage, Height(305)).await;
+    let tail = Chain::get_chain(&mut server_storage).await.unwrap().split_off(301);
+    chain.extend(&mut storage, &tail).await.unwrap();
+    assert_eq!(chain.latest_block, server.latest_block);
+    assert!(chain.extend(&mut storage, &tail).await.is_err());
+
+    // The base survives a restart
+    let mut restarted = easy_chain(&mut storage).await;
+    assert_eq!(restarted.base, chain.base);
+    assert_eq!(restarted.latest_block, chain.latest_block);
+    restarted.validate_chain(&mut storage).await.unwrap();
+
+    // A complete chain replaces the snapshot
+    let mut complete = Chain::get_chain(&mut server_storage).await.unwrap();
+    grow(&mut server, &mut server_storage, Height(306)).await;
+    complete.push(server.latest_block.clone());
+    restarted.update(&mut storage, &mut complete).await.unwrap();
+    assert_eq!(restarted.base, None);
+    assert_eq!(storage.get_base().await.unwrap(), None);
+    assert_eq!(storage.count_blocks().await.unwrap(), 307);
+}
+
+#[tokio::test]
+async fn test_verify_rejects_invalid_snapshots() {
+    let mut server_storage = Storage::Memory(MemoryStorage::default());
+    let mut server = easy_chain(&mut server_storage).await;
+    grow(&mut server, &mut server_storage, Height(150)).await;
+    let snapshot = ChainSnapshot::create(&server, &mut server_storage).await.unwrap();
+
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = easy_chain(&mut storage).await;
+    let mut unlinked = snapshot.clone();
+    unlinked.links[10].hash = "0".repeat(64);
+    let mut tampered = snapshot.clone();
+    tampered.blocks[50].data = "tampered".to_owned();
+    let mut short = snapshot.clone();
+    short.blocks.truncate(10);
+    let mut foreign = snapshot.clone();
+    foreign.links[0].hash = "foreign".to_owned();
+    let mut unmined = snapshot.clone();
+    unmined.blocks[0].nonce.0 += 1;
+    let mut unexpected_state = snapshot.clone();
+    unexpected_state.state.difficulty_anchor = Some(BlockInfo { height: Height(1), timestamp: 0, difficulty: 1 });
+    for invalid in [unlinked, tampered, short, foreign, unmined, unexpected_state] {
+        assert!(matches!(invalid.verify(&chain).await, Err(BlockchainError::ChainInvalid(_))));
+        assert!(fastsync::restore(&mut chain, &mut storage, &invalid).await.is_err());
+    }
+
+    // Blocks we consider final are never replaced
+    let mut fork_storage = Storage::Memory(MemoryStorage::default());
+    let mut fork = easy_chain(&mut fork_storage).await;
+    fork.miner = "fork".to_owned();
+    grow(&mut fork, &mut fork_storage, Height(10)).await;
+    assert!(matches!(
+        fastsync::restore(&mut fork, &mut fork_storage, &snapshot).await,
+        Err(BlockchainError::ReorgBelowFinalized(_))
+    ));
+
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![chain.genesis.clone()]);
+    fastsync::restore(&mut chain, &mut storage, &snapshot).await.unwrap();
+    // Not ahead of our chain anymore
+    assert!(fastsync::restore(&mut chain, &mut storage, &snapshot).await.is_err());
+}
+
+// Anchored at a block below the base of the snapshots, like ASERT
+#[derive(Debug)]
+struct Anchored;
+
+impl DifficultyAlgorithm for Anchored {
+    fn window(&self) -> usize {
+        1
+    }
+
+    fn anchor(&self) -> Option<Height> {
+        Some(Height(10))
+    }
+
+    fn next_difficulty(&self, blocks: &[BlockInfo]) -> u64 {
+        assert_eq!(blocks[0].height, Height(10));
+        MIN_DIFFICULTY
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_keeps_difficulty_anchor() {
+    let activation = activation_height(Feature::DifficultyAdjustment);
+    let mut server_storage = Storage::Memory(MemoryStorage::default());
+    let mut server = easy_chain(&mut server_storage).await;
+    server.difficulty_algorithm = Arc::new(Anchored);
+    grow(&mut server, &mut server_storage, activation + SNAPSHOT_BLOCKS).await;
+    let snapshot = ChainSnapshot::create(&server, &mut server_storage).await.unwrap();
+    assert_eq!(snapshot.state.difficulty_anchor.map(|anchor| anchor.height), Some(Height(10)));
+
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = easy_chain(&mut storage).await;
+    chain.difficulty_algorithm = Arc::new(Anchored);
+    fastsync::restore(&mut chain, &mut storage, &snapshot).await.unwrap();
+    chain.validate_chain(&mut storage).await.unwrap();
+    grow(&mut chain, &mut storage, server.latest_block.id + 1).await;
+}
+
+#[tokio::test]
+async fn test_fast_sync_node() {
+    let now = Instant::now();
+    let mut server = Node::init(Storage::Memory(MemoryStorage::default()), "server".to_owned()).await.unwrap();
+    server.chain.difficulty = String::new();
+    server.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    grow(&mut server.chain, &mut server.storage, FAST_SYNC_MIN_HEIGHT).await;
+    let mut client = Node::init(Storage::Memory(MemoryStorage::default()), "client".to_owned()).await.unwrap();
+    client.chain.difficulty = String::new();
+    client.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    client.fast_sync = true;
+
+    // Asks for a snapshot instead of the chain
+    let latest = EventType::ReceivedLatestBlock { sender: "server".to_owned(), block: server.chain.latest_block.clone() };
+    let session_id = match client.handle_event(latest, now).await.as_slice() {
+        [EventType::SendSnapshotRequest { receiver, session_id }] if receiver == "server" => session_id.clone(),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let request = EventType::ReceivedSnapshotRequest { receiver: "client".to_owned(), session_id: session_id.clone() };
+    let snapshot = match server.handle_event(request, now).await.pop() {
+        Some(EventType::SendSnapshot { snapshot, .. }) => snapshot,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    let received = EventType::ReceivedSnapshot { sender: "server".to_owned(), session_id, snapshot };
+    client.handle_event(received, now).await;
+    assert_eq!(client.chain.latest_block, server.chain.latest_block);
+
+    // Then only the tail
+    grow(&mut server.chain, &mut server.storage, FAST_SYNC_MIN_HEIGHT + 3).await;
+    let latest = EventType::ReceivedLatestBlock { sender: "server".to_owned(), block: server.chain.latest_block.clone() };
+    let (session_id, from) = match client.handle_event(latest, now).await.as_slice() {
+        [EventType::SendChainRequest { session_id, from, .. }] => (session_id.clone(), *from),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    assert_eq!(from, FAST_SYNC_MIN_HEIGHT + 1);
+    let request = EventType::ReceivedChainRequest { receiver: "client".to_owned(), session_id: session_id.clone(), from };
+    let chain = match server.handle_event(request, now).await.pop() {
+        Some(EventType::SendChain { chain, .. }) => chain,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    assert_eq!(chain.len(), 3);
+    client.handle_event(EventType::ReceivedChain { sender: "server".to_owned(), session_id, chain }, now).await;
+    assert_eq!(client.chain.latest_block, server.chain.latest_block);
+
+    // A restored chain can not serve the complete chain
+    let request = EventType::ReceivedChainRequest { receiver: "other".to_owned(), session_id: "s".to_owned(), from: Height::GENESIS };
+    assert!(client.handle_event(request, now).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_snapshot_votes() {
+    let mut server_storage = Storage::Memory(MemoryStorage::default());
+    let mut server = easy_chain(&mut server_storage).await;
+    grow(&mut server, &mut server_storage, Height(300)).await;
+    let lower = ChainSnapshot::create(&server, &mut server_storage).await.unwrap();
+    grow(&mut server, &mut server_storage, Height(310)).await;
+    let higher = ChainSnapshot::create(&server, &mut server_storage).await.unwrap();
+    let mut fork_storage = Storage::Memory(MemoryStorage::default());
+    let mut fork = easy_chain(&mut fork_storage).await;
+    fork.miner = "fork".to_owned();
+    grow(&mut fork, &mut fork_storage, Height(300)).await;
+    let forked = ChainSnapshot::create(&fork, &mut fork_storage).await.unwrap();
+
+    // Snapshots at different heights agree on the base of the lower one
+    assert!(higher.vouches_for(&lower));
+    assert!(lower.vouches_for(&higher));
+    assert!(!forked.vouches_for(&lower));
+
+    let mut votes = SnapshotVotes::default();
+    assert_eq!(votes.add("a".to_owned(), lower.clone(), 2), None);
+    // Every signer counts once
+    assert_eq!(votes.add("a".to_owned(), lower.clone(), 2), None);
+    assert_eq!(votes.add("b".to_owned(), forked, 2), None);
+    assert!(votes.has_voted("b"));
+    assert_eq!(votes.add("c".to_owned(), higher.clone(), 2), Some(higher.clone()));
+    assert!(votes.is_empty());
+    assert_eq!(votes.add("a".to_owned(), lower.clone(), 1), Some(lower));
+
+    let trust = SnapshotTrust { signers: vec!["a".to_owned()], quorum: 1 };
+    assert!(trust.trusts("a"));
+    assert!(!trust.trusts("b"));
+    assert!(SnapshotTrust::default().trusts("b"));
+}
+
+#[tokio::test]
+async fn test_fast_sync_trust() {
+    let now = Instant::now();
+    let mut server = Node::init(Storage::Memory(MemoryStorage::default()), "server".to_owned()).await.unwrap();
+    server.chain.difficulty = String::new();
+    server.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    grow(&mut server.chain, &mut server.storage, FAST_SYNC_MIN_HEIGHT).await;
+    let client = || async {
+        let mut client = Node::init(Storage::Memory(MemoryStorage::default()), "client".to_owned()).await.unwrap();
+        client.chain.difficulty = String::new();
+        client.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+        client.fast_sync = true;
+        client
+    };
+    let latest_block = server.chain.latest_block.clone();
+    let latest = |sender: &str| EventType::ReceivedLatestBlock { sender: sender.to_owned(), block: latest_block.clone() };
+
+    // Peers that are no trusted signers are synced from completely
+    let mut trusting = client().await;
+    trusting.snapshot_trust = SnapshotTrust { signers: vec!["trusted".to_owned()], quorum: 1 };
+    match trusting.handle_event(latest("server"), now).await.as_slice() {
+        [EventType::SendChainRequest { receiver, from, .. }] if receiver == "server" => assert_eq!(*from, Height::GENESIS),
+        events => panic!("unexpected events: {:?}", events),
+    }
+
+    // With a quorum of two the snapshot is only restored once a second signer sent one
+    let mut client = client().await;
+    client.snapshot_trust = SnapshotTrust { signers: vec![], quorum: 2 };
+    for (signer, restored) in [("a", false), ("b", true)] {
+        let session_id = match client.handle_event(latest(signer), now).await.as_slice() {
+            [EventType::SendSnapshotRequest { receiver, session_id }] if receiver == signer => session_id.clone(),
+            events => panic!("unexpected events: {:?}", events),
+        };
+        let request = EventType::ReceivedSnapshotRequest { receiver: "client".to_owned(), session_id: session_id.clone() };
+        let snapshot = match server.handle_event(request, now).await.pop() {
+            Some(EventType::SendSnapshot { snapshot, .. }) => snapshot,
+            event => panic!("unexpected event: {:?}", event),
+        };
+        client.handle_event(EventType::ReceivedSnapshot { sender: signer.to_owned(), session_id, snapshot }, now).await;
+        assert_eq!(client.chain.latest_block == latest_block, restored);
+        if !restored {
+            // The same signer is not asked again
+            assert!(client.handle_event(latest(signer), now).await.is_empty());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_sync_routing_by_features() {
+    let now = Instant::now();
+    let mut server = Node::init(Storage::Memory(MemoryStorage::default()), "server".to_owned()).await.unwrap();
+    server.chain.difficulty = String::new();
+    server.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    grow(&mut server.chain, &mut server.storage, FAST_SYNC_MIN_HEIGHT).await;
+    assert_eq!(Features::of_chain(&server.chain), Features::BODIES.with(Features::SNAPSHOTS).with(Features::STATE_PROOFS));
+    let mut client = Node::init(Storage::Memory(MemoryStorage::default()), "client".to_owned()).await.unwrap();
+    client.fast_sync = true;
+    let latest = |sender: &str| EventType::ReceivedLatestBlock { sender: sender.to_owned(), block: server.chain.latest_block.clone() };
+    let best_block = Checkpoint { id: server.chain.latest_block.id, hash: server.chain.latest_block.hash.clone() };
+    let features = |peer: &str, features| EventType::PeerHandshake {
+        peer: peer.to_owned(),
+        handshake: Some(Handshake::new(server.chain.genesis.hash.clone(), best_block.clone(), features)),
+    };
+
+    // A peer restored from a snapshot serves neither snapshots nor the complete chain
+    client.handle_event(features("pruned", Features::default()), now).await;
+    assert!(client.handle_event(latest("pruned"), now).await.is_empty());
+    // A peer serving bodies but no snapshots is asked for the complete chain instead
+    client.handle_event(features("bodies", Features::BODIES), now).await;
+    match client.handle_event(latest("bodies"), now).await.as_slice() {
+        [EventType::SendChainRequest { receiver, from, .. }] if receiver == "bodies" => assert_eq!(*from, Height::GENESIS),
+        events => panic!("unexpected events: {:?}", events),
+    }
+    client.handle_event(features("archive", Features::BODIES.with(Features::SNAPSHOTS)), now).await;
+    assert!(matches!(client.handle_event(latest("archive"), now).await.as_slice(), [EventType::SendSnapshotRequest { .. }]));
+    // Peers without a handshake serve everything, disconnected peers are forgotten
+    assert!(matches!(client.handle_event(latest("unknown"), now).await.as_slice(), [EventType::SendSnapshotRequest { .. }]));
+    client.handle_event(EventType::PeerHandshake { peer: "pruned".to_owned(), handshake: None }, now).await;
+    assert!(matches!(client.handle_event(latest("pruned"), now).await.as_slice(), [EventType::SendSnapshotRequest { .. }]));
+
+    // Restoring a snapshot drops the features that need the blocks below the base
+    let snapshot = ChainSnapshot::create(&server.chain, &mut server.storage).await.unwrap();
+    client.chain.difficulty = String::new();
+    client.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    fastsync::restore(&mut client.chain, &mut client.storage, &snapshot).await.unwrap();
+    assert_eq!(Features::of_chain(&client.chain), Features::default());
+}
@@ -0,0 +1,73 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{activation_height, rules_at, Feature};
+use rust_blockchain::fees::*;
+use rust_blockchain::mining::*;
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+
+#[test]
+fn test_fee_rate() {
+    assert_eq!(fee_rate(5, 1_000), 5);
+    assert_eq!(fee_rate(5, 500), 10);
+    assert_eq!(fee_rate(1, 3_000), 0);
+    assert_eq!(fee_rate(2, 0), 2 * FEE_RATE_BYTES);
+
+    assert_eq!(split_fee("--fee 10 some  data").unwrap(), (10, "some  data"));
+    assert_eq!(split_fee("some data --fee 10").unwrap(), (0, "some data --fee 10"));
+    assert!(split_fee("--fee ten data").is_err());
+}
+
+#[test]
+fn test_mempool_orders_by_fee_rate() {
+    let mut queue = MiningQueue::with_policy(MempoolPolicy { min_fee_rate: 10, ..MempoolPolicy::default() });
+    assert_eq!(queue.push_at("free".to_owned(), Priority::Normal, 0, 0), Enqueued::Rejected);
+    assert_eq!(queue.push_at("cheap".to_owned(), Priority::Normal, 1, 0), Enqueued::Added(1));
+    assert_eq!(queue.push_at("long and paying".to_owned(), Priority::Normal, 2, 0), Enqueued::Added(2));
+    assert_eq!(queue.push_at("paying".to_owned(), Priority::Normal, 1, 0), Enqueued::Added(3));
+    assert_eq!(queue.push_at("urgent".to_owned(), Priority::High, 1, 0), Enqueued::Added(4));
+    let order = |queue: &MiningQueue| queue.jobs().iter().map(|job| job.id).collect::<Vec<u64>>();
+    assert_eq!(order(&queue), vec![4, 1, 3, 2]);
+
+    // A duplicate offering more moves the job up
+    assert_eq!(queue.push_at("long and paying".to_owned(), Priority::Low, 20, 0), Enqueued::Merged(2));
+    assert_eq!(order(&queue), vec![4, 2, 1, 3]);
+    assert_eq!(queue.jobs()[1].priority, Priority::Normal);
+}
+
+#[tokio::test]
+async fn test_miner_collects_fees() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    node.mining_queue.push_with_fee("paying".to_owned(), Priority::Normal, 7);
+    node.mining_queue.push("free".to_owned(), Priority::Normal);
+    let paying = node.mine_next().await.unwrap().unwrap();
+    let free = node.mine_next().await.unwrap().unwrap();
+    assert_eq!((paying.fee, free.fee), (7, 0));
+    assert_eq!(paying.version, BLOCK_VERSION);
+    node.chain.validate_chain(&mut node.storage).await.unwrap();
+
+    let blocks = Chain::get_address_blocks(&mut node.storage, "miner").await.unwrap();
+    let params = NetworkParams::default();
+    let collected = earnings(&params, "miner", &blocks);
+    assert_eq!(collected, Earnings { blocks: 2, rewards: 2 * params.initial_reward, fees: 7 });
+    assert_eq!(collected.total(), 2 * params.initial_reward + 7);
+    assert_eq!(earnings(&params, "other", &blocks), Earnings::default());
+
+    // The fee is part of the hash, and only of version 4 headers
+    let mut forged = Block { fee: 100, ..paying.clone() };
+    assert!(Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &forged).await.is_err());
+    forged.version = 3;
+    forged.hash = hasher(&forged);
+    assert!(Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &forged).await.is_err());
+    forged.fee = 0;
+    forged.hash = hasher(&forged);
+    assert!(Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &forged).await.is_ok());
+}
+
+#[test]
+fn test_fee_market_activation() {
+    let height = activation_height(Feature::FeeMarket);
+    assert_eq!(rules_at(height - 1).min_block_version, 3);
+    assert_eq!(rules_at(height).min_block_version, 4);
+}
@@ -0,0 +1,42 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::gc::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+
+#[tokio::test]
+async fn test_prune_stale_blocks() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let mut blocks = vec![node.chain.latest_block.clone()];
+    for i in 0..FINALITY_DEPTH + 2 {
+        blocks.push(node.chain.mine_block(format!("block {}", i), &mut node.storage).await.unwrap());
+    }
+    assert_eq!(node.chain.finalized.id, Height(2));
+    // Below and above the finalized block
+    let old = Block::new(&blocks[0], "old stale block".to_owned(), "peer".to_owned());
+    let recent = Block::new(&blocks[4], "recent stale block".to_owned(), "peer".to_owned());
+    for stale in [&old, &recent] {
+        Chain::add_stale_block(&mut node.storage, stale).await.unwrap();
+    }
+
+    let mut archival = StaleCollector::new(StalePruning::Off);
+    assert_eq!(archival.collect(&mut node.storage, &node.chain).await.unwrap(), GcStats::default());
+    assert_eq!(Chain::get_stale_blocks(&mut node.storage, 10).await.unwrap().len(), 2);
+
+    let mut collector = StaleCollector::new(StalePruning::Bodies);
+    let run = collector.collect(&mut node.storage, &node.chain).await.unwrap();
+    assert_eq!(run, GcStats { runs: 1, blocks_pruned: 0, bodies_pruned: 1, bytes_reclaimed: old.data.len() as u64 });
+    let stale_blocks = Chain::get_stale_blocks(&mut node.storage, 10).await.unwrap();
+    assert_eq!(stale_blocks.iter().map(|stale| stale.block.data.as_str()).collect::<Vec<_>>(), vec!["recent stale block", ""]);
+    // Payloads are pruned once
+    assert_eq!(collector.collect(&mut node.storage, &node.chain).await.unwrap().bodies_pruned, 0);
+
+    collector.pruning = StalePruning::Blocks;
+    let run = collector.collect(&mut node.storage, &node.chain).await.unwrap();
+    assert_eq!(run.blocks_pruned, 1);
+    assert_eq!(Chain::get_stale_blocks(&mut node.storage, 10).await.unwrap()[0].block, recent);
+    assert_eq!(collector.stats.runs, 3);
+    assert_eq!((collector.stats.blocks_pruned, collector.stats.bodies_pruned), (1, 1));
+    assert!(collector.stats.bytes_reclaimed > old.data.len() as u64);
+}
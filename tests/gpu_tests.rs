@@ -0,0 +1,60 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{Feature, ACTIVATIONS};
+use rust_blockchain::gpu;
+use rust_blockchain::types::{Height, Nonce};
+use sha2::{Digest, Sha256};
+
+fn block_at(id: Height) -> Block {
+    let mut block = Block::template(&Block::create_genesis(), "gpu \"block\" with \"nonce\":0".to_owned(), "miner".to_owned(), 0);
+    block.id = id;
+    block
+}
+
+#[test]
+fn test_header_around_nonce() {
+    for id in [Height(1), Height(1_000_000)] {
+        let block = block_at(id);
+        let (prefix, suffix) = header_around_nonce(&block);
+        for nonce in [0, 9, 10, 123_456_789, Nonce::MAX.0] {
+            let digest = Sha256::digest(format!("{}{}{}", prefix, nonce, suffix).as_bytes());
+            assert_eq!(digest.as_slice(), digest_with_nonce(&block, Nonce(nonce)).as_slice());
+        }
+    }
+}
+
+#[test]
+fn test_threshold() {
+    let padded_hex_hash = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::PaddedHexHash)
+        .unwrap();
+
+    // Legacy hashes start with one 0 per zero byte
+    let legacy = block_at(padded_hex_hash.height - 1);
+    assert_eq!(gpu::threshold(&legacy, ""), Some(u64::MAX));
+    assert_eq!(gpu::threshold(&legacy, "00"), Some(u64::MAX >> 16));
+    assert_eq!(gpu::threshold(&legacy, "00000000"), None);
+    assert_eq!(gpu::threshold(&legacy, "0A"), None);
+
+    let padded = block_at(padded_hex_hash.height);
+    assert_eq!(gpu::threshold(&padded, "00"), Some(u64::MAX >> 8));
+    assert_eq!(gpu::threshold(&padded, "000000000000000"), Some(u64::MAX >> 60));
+
+    // The difficulty in the header replaces the prefix
+    let mut adjusted = padded.clone();
+    adjusted.difficulty = 4;
+    assert_eq!(gpu::threshold(&adjusted, "00"), Some(u64::MAX / 4));
+}
+
+#[cfg(not(feature = "gpu"))]
+#[test]
+fn test_gpu_fallback() {
+    assert!(gpu::init().is_err());
+    assert!(gpu::find_hash(&block_at(Height(1)), BLOCK_DIFFICULTY).is_err());
+
+    // Mining still works, on the CPU
+    let genesis = Block::create_genesis();
+    let block = Block::mine(&genesis, "data".to_owned(), "miner".to_owned(), REGTEST_DIFFICULTY, 0, HashBackend::Gpu);
+    assert_eq!(block.hash, hasher(&block));
+    assert!(block.hash.starts_with(REGTEST_DIFFICULTY));
+}
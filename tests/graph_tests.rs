@@ -0,0 +1,68 @@
+use rust_blockchain::blockchain::{Block, Chain, StaleBlock};
+use rust_blockchain::graph::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use std::path::PathBuf;
+
+fn block(id: u64, hash: &str, prev_hash: &str, miner: &str) -> Block {
+    Block { id: Height(id), hash: hash.to_owned(), prev_hash: prev_hash.to_owned(), miner: miner.to_owned(), ..Block::create_genesis() }
+}
+
+#[test]
+fn test_graph_options() {
+    assert_eq!(GraphConfig::from_args(" --out chain.dot").unwrap(), GraphConfig { out: PathBuf::from("chain.dot"), from: Height::GENESIS });
+    assert_eq!(GraphConfig::from_args("--from-height 100 --out /tmp/chain.dot").unwrap(), GraphConfig { out: PathBuf::from("/tmp/chain.dot"), from: Height(100) });
+    assert!(GraphConfig::from_args("").is_err());
+    assert!(GraphConfig::from_args("--out").is_err());
+    assert!(GraphConfig::from_args("--out chain.dot --from-height x").is_err());
+    assert!(GraphConfig::from_args("--out chain.dot --svg").is_err());
+}
+
+#[test]
+fn test_dot_graph() {
+    let main = vec![block(0, "genesis", "", "satoshi"), block(1, "a1", "genesis", "alice"), block(2, "a2", "a1", "alice")];
+    let stale = vec![
+        StaleBlock { block: block(2, "b2", "a1", "bob \"the builder\""), received_at: 1 },
+        StaleBlock { block: block(5, "c5", "0123456789abcdef", "carol"), received_at: 2 },
+    ];
+    assert_eq!(
+        to_dot(&main, &stale, "a2"),
+        r#"digraph chain {
+    rankdir=LR;
+    node [shape=box, fontname="monospace"];
+    "genesis" [label="0\ngenesis\nsatoshi"];
+    "a1" [label="1\na1\nalice"];
+    "genesis" -> "a1";
+    "a2" [label="2\na2\nalice", style="bold"];
+    "a1" -> "a2";
+    "b2" [label="2\nb2\nbob \"the builder\"", style="dashed"];
+    "a1" -> "b2";
+    "c5" [label="5\nc5\ncarol", style="dashed"];
+    "0123456789abcdef" -> "c5";
+    "0123456789abcdef" [label="0123456789ab", style="dotted"];
+}
+"#
+    );
+}
+
+#[tokio::test]
+async fn test_export_graph() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("block 2".to_owned(), &mut storage).await.unwrap();
+    let fork = Block { hash: "fork".to_owned(), ..block2.clone() };
+    storage.insert_stale_block(&fork, 1).await.unwrap();
+
+    let dot = export(&chain, &mut storage, Height::GENESIS).await.unwrap();
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\";", chain.genesis.hash, block1.hash)));
+    assert!(dot.contains(&format!("\"{}\" -> \"fork\";", block1.hash)));
+    assert!(dot.contains(&format!("\"{}\" [label=\"2\\n{}\\n{}\", style=\"bold\"];", block2.hash, &block2.hash[..LABEL_HASH_LENGTH], block2.miner)));
+    assert!(!dot.contains("dotted"));
+
+    // The parent of the first exported block is a placeholder
+    let dot = export(&chain, &mut storage, Height(2)).await.unwrap();
+    assert!(!dot.contains(&format!("\"{}\" [", chain.genesis.hash)));
+    assert!(dot.contains(&format!("\"{}\" [label=\"{}\", style=\"dotted\"];", block1.hash, &block1.hash[..LABEL_HASH_LENGTH])));
+    assert_eq!(dot.matches("dotted").count(), 1);
+}
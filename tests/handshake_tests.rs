@@ -0,0 +1,39 @@
+use rust_blockchain::blockchain::{Block, Checkpoint};
+use rust_blockchain::config::Config;
+use rust_blockchain::handshake::*;
+use rust_blockchain::types::Height;
+
+fn args(args: &[&str]) -> impl Iterator<Item = String> {
+    args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().into_iter()
+}
+
+fn handshake(genesis_hash: &str, height: u64) -> Handshake {
+    Handshake::new(genesis_hash.to_owned(), Checkpoint { id: Height(height), hash: format!("block {}", height) }, Features::SNAPSHOTS)
+}
+
+#[test]
+fn test_handshake_mismatch() {
+    let genesis = Block::create_genesis();
+    let ours = handshake(&genesis.hash, 10);
+    // Peers behind or ahead of us are of the same chain
+    assert_eq!(handshake(&genesis.hash, 5).mismatch(&ours), None);
+    assert_eq!(handshake(&genesis.hash, 20).mismatch(&ours), None);
+    assert!(handshake("other genesis", 10).mismatch(&ours).is_some());
+    let newer = Handshake { version: HANDSHAKE_VERSION + 1, ..handshake(&genesis.hash, 10) };
+    assert!(newer.mismatch(&ours).unwrap().contains("version"));
+}
+
+#[test]
+fn test_features() {
+    let config = Config::from_args(args(&["node_1"])).unwrap();
+    assert_eq!(Features::of(&config), Features::default());
+    let features = Features::of(&Config::from_args(args(&["node_1", "--archival", "--propagation", "announce"])).unwrap());
+    assert!(features.contains(Features::ARCHIVAL) && features.contains(Features::ANNOUNCEMENTS));
+    assert_eq!(features.with(Features::BODIES).names(), vec!["archival", "announcements", "bodies"]);
+    // Unknown bits of newer peers are ignored
+    assert_eq!(Features(1 << 40).with(Features::ARCHIVAL).names(), vec!["archival"]);
+
+    let json = serde_json::to_string(&handshake("genesis", 1)).unwrap();
+    assert!(json.contains("\"features\":2"));
+    assert_eq!(serde_json::from_str::<Handshake>(&json).unwrap(), handshake("genesis", 1));
+}
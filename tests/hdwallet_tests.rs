@@ -0,0 +1,100 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::hdwallet::*;
+use rust_blockchain::keys::KeyStore;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::Transaction;
+use std::env;
+use std::fs;
+
+#[test]
+fn test_mnemonic() {
+    let wallet = HdWallet::generate();
+    let words = wallet.mnemonic.split(' ').collect::<Vec<_>>();
+    assert_eq!(words.len(), 17);
+    assert_eq!(entropy_to_mnemonic(&mnemonic_to_entropy(&wallet.mnemonic).unwrap()), wallet.mnemonic);
+    // Extra whitespace does not matter
+    assert_eq!(HdWallet::restore(&format!("  {}\n", words.join("   "))).unwrap().mnemonic, wallet.mnemonic);
+
+    let mut swapped = words.clone();
+    swapped.swap(0, 1);
+    if swapped != words {
+        assert!(mnemonic_to_entropy(&swapped.join(" ")).is_err());
+    }
+    assert!(mnemonic_to_entropy(&words[1..].join(" ")).is_err());
+    assert!(HdWallet::restore(&format!("{} xyz", words[1..].join(" "))).is_err());
+    assert!(HdWallet::restore("ñ").is_err());
+
+    assert_eq!(split_from("--from 3 recipient 10").unwrap(), (Some(3), "recipient 10"));
+    assert_eq!(split_from("recipient 10").unwrap(), (None, "recipient 10"));
+    assert!(split_from("--from x recipient 10").is_err());
+}
+
+#[test]
+fn test_key_derivation() {
+    let mut wallet = HdWallet::generate();
+    let restored = HdWallet::restore(&wallet.mnemonic).unwrap();
+    assert_eq!(restored.address(5), wallet.address(5));
+    assert_ne!(wallet.address(0), wallet.address(1));
+    assert_ne!(wallet.derive(&[1, 0]).public(), wallet.keypair(0).public());
+    assert_ne!(HdWallet::generate().address(0), wallet.address(0));
+
+    assert_eq!(wallet.next_address(), (0, wallet.address(0)));
+    assert_eq!(wallet.next_address(), (1, wallet.address(1)));
+    assert_eq!(wallet.find_keypair(&wallet.address(1)).map(|keypair| keypair.public()), Some(wallet.keypair(1).public()));
+    assert!(wallet.find_keypair(&wallet.address(2)).is_none());
+    assert_eq!(derivation_path(1), "m/0'/1'");
+
+    // Derived keys sign transactions of their address
+    let mut transaction = Transaction::new(&wallet.keypair(1).public(), "recipient".to_owned(), 1, 0);
+    transaction.sign(&wallet.keypair(1)).unwrap();
+    transaction.verify().unwrap();
+}
+
+#[tokio::test]
+async fn test_restore_rescans_chain() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let wallet = HdWallet::generate();
+    let payer = wallet.keypair(100);
+    for (index, amount) in [(0, 50), (3, 20), (0, 5)] {
+        let mut transaction = Transaction::new(&payer.public(), wallet.address(index), amount, 0);
+        transaction.sign(&payer).unwrap();
+        node.chain.mine_block(transaction.to_block_data(), &mut node.storage).await.unwrap();
+    }
+    let mut spending = Transaction::new(&wallet.keypair(0).public(), "shop".to_owned(), 30, 2);
+    spending.sign(&wallet.keypair(0)).unwrap();
+    node.chain.mine_block(spending.to_block_data(), &mut node.storage).await.unwrap();
+
+    let mut restored = HdWallet::restore(&wallet.mnemonic).unwrap();
+    let used = restored.rescan(&mut node.storage).await.unwrap();
+    assert_eq!(
+        used,
+        vec![
+            (0, wallet.address(0), Balance { received: 55, sent: 32, transactions: 3 }),
+            (3, wallet.address(3), Balance { received: 20, sent: 0, transactions: 1 }),
+        ]
+    );
+    assert_eq!(used[0].2.available(), 23);
+    // The payer is beyond the gap limit
+    assert_eq!(restored.addresses, 4);
+}
+
+#[test]
+fn test_wallet_in_key_store() {
+    let dir = env::temp_dir().join("rust_blockchain_hdwallet_test");
+    let _ = fs::remove_dir_all(&dir);
+    let store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    assert_eq!(store.wallet().unwrap(), None);
+    assert_eq!(store.transaction_keys().unwrap().len(), 1);
+
+    let mut wallet = HdWallet::generate();
+    wallet.next_address();
+    store.save_wallet(&wallet).unwrap();
+    let store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    assert_eq!(store.wallet().unwrap(), Some(wallet.clone()));
+    let keys = store.transaction_keys().unwrap();
+    assert_eq!(keys.iter().map(|keypair| keypair.public()).collect::<Vec<_>>(), vec![store.signing_key().unwrap().public(), wallet.keypair(0).public()]);
+    assert!(KeyStore::open(&dir, "wrong".to_owned()).unwrap().wallet().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
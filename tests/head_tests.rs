@@ -0,0 +1,36 @@
+use futures::StreamExt;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::head::HeadEvent;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+
+#[tokio::test]
+async fn test_head_events() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let mut head = node.subscribe_head();
+    let genesis = node.chain.latest_block.clone();
+    assert!(node.watch_head().await.unwrap().is_empty());
+
+    let block1 = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    let block2 = node.chain.mine_block("block 2".to_owned(), &mut node.storage).await.unwrap();
+    let extended = vec![HeadEvent::Extended(block1.clone()), HeadEvent::Extended(block2.clone())];
+    assert_eq!(node.watch_head().await.unwrap(), extended);
+    assert!(node.watch_head().await.unwrap().is_empty());
+
+    // A longer fork on top of block 1 replaces block 2
+    let fork2 = Block::mine(&block1, "fork 2".to_owned(), String::new(), REGTEST_DIFFICULTY, 0, HashBackend::Cpu);
+    let fork3 = Block::mine(&fork2, "fork 3".to_owned(), String::new(), REGTEST_DIFFICULTY, 0, HashBackend::Cpu);
+    let mut fork = vec![genesis, block1, fork2.clone(), fork3.clone()];
+    node.chain.update(&mut node.storage, &mut fork).await.unwrap();
+    let reorged = vec![
+        HeadEvent::Reorged { old_tip: block2, new_tip: fork3.clone(), depth: 1 },
+        HeadEvent::Extended(fork2),
+        HeadEvent::Extended(fork3),
+    ];
+    assert_eq!(node.watch_head().await.unwrap(), reorged);
+
+    // Subscribers get the same events, in order
+    let received = head.by_ref().take(extended.len() + reorged.len()).collect::<Vec<HeadEvent>>().await;
+    assert_eq!(received, [extended, reorged].concat());
+}
@@ -0,0 +1,69 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::history::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::Transaction;
+use rust_blockchain::types::Height;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn signed(sender: &ed25519::Keypair, recipient: &str, amount: u64, fee: u64) -> Transaction {
+    let mut transaction = Transaction::new(&sender.public(), recipient.to_owned(), amount, fee);
+    transaction.sign(sender).unwrap();
+    transaction
+}
+
+fn address(keypair: &ed25519::Keypair) -> String {
+    hex::encode(keypair.public().encode())
+}
+
+#[test]
+fn test_history_config() {
+    assert_eq!(HistoryConfig::from_args("").unwrap(), HistoryConfig::default());
+    assert_eq!(HistoryConfig::from_args(" --export csv").unwrap(), HistoryConfig { export: true, path: None });
+    assert_eq!(
+        HistoryConfig::from_args("--export csv history.csv").unwrap(),
+        HistoryConfig { export: true, path: Some(PathBuf::from("history.csv")) }
+    );
+    assert!(HistoryConfig::from_args("--export json").is_err());
+    assert!(HistoryConfig::from_args("--export").is_err());
+    assert!(HistoryConfig::from_args("--export csv a b").is_err());
+}
+
+#[tokio::test]
+async fn test_wallet_history() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let (ours, other, stranger) = (ed25519::Keypair::generate(), ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let (incoming, unrelated) = (signed(&stranger, &address(&ours), 100, 1), signed(&stranger, "bob", 5, 0));
+    chain.mine_transactions(vec![incoming.clone(), unrelated], &mut storage).await.unwrap();
+    let internal = signed(&ours, &address(&other), 40, 2);
+    chain.mine_transactions(vec![internal.clone()], &mut storage).await.unwrap();
+    let outgoing = signed(&other, "bob, inc.", 30, 3);
+    chain.mine_transactions(vec![outgoing.clone()], &mut storage).await.unwrap();
+
+    let entries = wallet_history(&chain, &mut storage, &[address(&ours), address(&other)]).await.unwrap();
+    let summary = entries.iter().map(|entry| (entry.txid.clone(), entry.direction, entry.change, entry.balance, entry.confirmations)).collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (incoming.id(), Direction::In, 100, 100, 2),
+            (internal.id(), Direction::Internal, -2, 98, 1),
+            (outgoing.id(), Direction::Out, -33, 65, 0),
+        ]
+    );
+    assert_eq!(entries[0].counterparty, address(&stranger));
+
+    let csv = to_csv(&entries);
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], CSV_HEADER);
+    assert!(lines[3].contains(&format!("{},out,\"bob, inc.\",30,3,-33,65,0", outgoing.id())));
+}
+
+#[test]
+fn test_unrelated_blocks_are_skipped() {
+    let block = Block::new(&Block::genesis(), "hello".to_owned(), "miner".to_owned());
+    assert!(entries(&HashSet::from(["alice".to_owned()]), &[block], Height(1)).is_empty());
+}
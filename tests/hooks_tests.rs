@@ -0,0 +1,65 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::hooks::TemplateHooks;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_template_hooks() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.template_hooks.add("payload", |template: &mut Block| {
+        template.data = format!("{} +injected", template.data);
+        Ok(())
+    });
+    chain.template_hooks.add("tag", |template: &mut Block| {
+        template.miner = "pool-7".to_owned();
+        Ok(())
+    });
+    assert_eq!(chain.template_hooks.names().collect::<Vec<_>>(), vec!["payload", "tag"]);
+
+    // The hooks run before hashing, so the hash commits to their changes
+    let block = chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap();
+    assert_eq!(block.data, "block 1 +injected");
+    assert_eq!(block.miner, "pool-7");
+    assert_eq!(block.hash, hasher(&block));
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
+
+    let template = chain.pool_template(&mut storage, "block 2".to_owned()).await.unwrap();
+    assert_eq!(template.data, "block 2 +injected");
+    assert_eq!(chain.block_template(&mut storage, "block 2".to_owned()).await.unwrap().data, "block 2");
+}
+
+#[tokio::test]
+async fn test_template_hook_veto() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let paused = Arc::new(AtomicBool::new(true));
+    let hook_paused = paused.clone();
+    chain.template_hooks.add("pause", move |_: &mut Block| match hook_paused.load(Ordering::SeqCst) {
+        true => Err("mining paused".to_owned()),
+        false => Ok(()),
+    });
+
+    let err = chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap_err();
+    assert!(matches!(&err, BlockchainError::MiningVetoed(reason) if reason == "pause: mining paused"));
+    assert_eq!(chain.latest_block, Block::create_genesis());
+    assert!(chain.pool_template(&mut storage, "block 1".to_owned()).await.is_err());
+
+    paused.store(false, Ordering::SeqCst);
+    assert_eq!(chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap().id, chain.latest_block.id);
+
+    chain.template_hooks.remove("pause");
+    assert!(chain.template_hooks.is_empty());
+}
+
+#[test]
+fn test_template_hooks_keep_consensus_fields() {
+    let mut hooks = TemplateHooks::default();
+    hooks.add("reparent", |template: &mut Block| {
+        template.prev_hash = "other".to_owned();
+        Ok(())
+    });
+    let mut template = Block::template(&Block::create_genesis(), "block 1".to_owned(), String::new(), 0);
+    assert!(matches!(hooks.apply(&mut template), Err(BlockchainError::MiningVetoed(_))));
+}
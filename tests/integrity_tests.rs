@@ -0,0 +1,56 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::integrity::{self, Corruption};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+
+#[tokio::test]
+async fn test_check_and_truncate() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    assert_eq!(integrity::check(&mut storage, &Block::create_genesis()).await.unwrap(), None);
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap();
+    assert_eq!(integrity::check(&mut storage, &Block::create_genesis()).await.unwrap(), None);
+
+    // A block whose data was changed after it was mined, and a valid block on top of it
+    let mut block2 = Block::new(&block1, "block 2".to_owned(), String::new());
+    block2.data = "tampered".to_owned();
+    let block3 = Block::new(&block2, "block 3".to_owned(), String::new());
+    storage.insert_block(&block2).await.unwrap();
+    storage.insert_block(&block3).await.unwrap();
+    let corruption = integrity::check(&mut storage, &Block::create_genesis()).await.unwrap().unwrap();
+    assert_eq!(corruption.last_valid, Some(Height(1)));
+    assert!(corruption.reason.contains(&block2.hash));
+
+    integrity::truncate(&mut storage, &corruption).await.unwrap();
+    assert_eq!(integrity::check(&mut storage, &Block::create_genesis()).await.unwrap(), None);
+    chain.reload(&mut storage).await.unwrap();
+    assert_eq!(chain.latest_block, block1);
+}
+
+#[tokio::test]
+async fn test_missing_block() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap();
+    let mut block3 = Block::new(&block1, "block 3".to_owned(), String::new());
+    block3.id = Height(3);
+    storage.insert_block(&block3).await.unwrap();
+    let corruption = integrity::check(&mut storage, &Block::create_genesis()).await.unwrap().unwrap();
+    assert_eq!(corruption.last_valid, Some(Height(1)));
+}
+
+#[tokio::test]
+async fn test_foreign_genesis() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let genesis = Block { data: "other genesis".to_owned(), ..Block::create_genesis() };
+    storage.insert_block(&genesis).await.unwrap();
+    let corruption = integrity::check(&mut storage, &Block::create_genesis()).await.unwrap().unwrap();
+    assert_eq!(corruption, Corruption { last_valid: None, reason: format!("genesis block {} is not ours", genesis.hash) });
+
+    // Starts over from our genesis block
+    integrity::truncate(&mut storage, &corruption).await.unwrap();
+    let mut chain = Chain::build(genesis);
+    chain.reload(&mut storage).await.unwrap();
+    assert_eq!(chain.latest_block, Block::create_genesis());
+    assert_eq!(integrity::check(&mut storage, &Block::create_genesis()).await.unwrap(), None);
+}
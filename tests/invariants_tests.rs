@@ -0,0 +1,180 @@
+use proptest::prelude::*;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::difficulty::{Fixed, MIN_DIFFICULTY};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Memory,
+    Postgres,
+}
+
+// What is done to the stored chain, the index is the height of the affected block
+#[derive(Debug, Clone, Copy)]
+enum Corruption {
+    // The block is missing
+    Gap(usize),
+    // The block links to an unknown parent
+    BrokenLink(usize),
+    // The block's data was changed, its hash no longer matches
+    TamperedData(usize),
+    // The blocks above the height are gone, our latest block is not stored anymore
+    Truncated(usize),
+}
+
+// An empty database for every case
+async fn storage(backend: Backend) -> Storage {
+    match backend {
+        Backend::Memory => Storage::Memory(MemoryStorage::default()),
+        Backend::Postgres => {
+            let (db_client, connection) =
+                tokio_postgres::connect("host=localhost dbname=blockchain_test user=user password=pw", tokio_postgres::NoTls)
+                    .await
+                    .unwrap();
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    println!("DB connection error: {}", e);
+                }
+            });
+            db_client
+                .execute(
+                    "DROP TABLE IF EXISTS blocks, stale_blocks, address_index, payloads, anchor_index, receipts, transaction_index, peer_scores, bad_messages, chain_base, sync_history",
+                    &[],
+                )
+                .await
+                .unwrap();
+            let mut storage = Storage::postgres(db_client, None);
+            storage.init().await.unwrap();
+            storage
+        }
+    }
+}
+
+// A chain whose blocks need no mining, with one block per data
+async fn mine_chain(storage: &mut Storage, data: &[String]) -> Chain {
+    let mut chain = Chain::init(storage).await.unwrap();
+    chain.difficulty = String::new();
+    chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    for data in data {
+        let mut block = chain.block_template(storage, data.clone()).await.unwrap();
+        block.hash = hasher(&block);
+        chain.add_block(storage, block).await.unwrap();
+    }
+    chain
+}
+
+fn corrupt(mut blocks: Vec<Block>, corruption: Corruption) -> Vec<Block> {
+    match corruption {
+        Corruption::Gap(height) => {
+            blocks.remove(height);
+        }
+        Corruption::BrokenLink(height) => blocks[height].prev_hash = "unknown parent".to_owned(),
+        Corruption::TamperedData(height) => blocks[height].data.push_str(" tampered"),
+        Corruption::Truncated(height) => blocks.truncate(height + 1),
+    }
+    blocks
+}
+
+// Block data and a corruption of one of the blocks above the genesis block. Gaps are below the
+// latest block, a missing latest block is a truncation.
+fn chains() -> impl Strategy<Value = (Vec<String>, Corruption)> {
+    (2usize..8).prop_flat_map(|length| {
+        let corruption = prop_oneof![
+            (1..length).prop_map(Corruption::Gap),
+            (1..=length).prop_map(Corruption::BrokenLink),
+            (1..=length).prop_map(Corruption::TamperedData),
+            (0..length).prop_map(Corruption::Truncated),
+        ];
+        (prop::collection::vec("[a-z ]{0,16}", length), corruption)
+    })
+}
+
+fn run(future: impl std::future::Future<Output = ()>) {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
+
+async fn check_stored_chain(backend: Backend, data: Vec<String>, corruption: Corruption) {
+    let mut storage = storage(backend).await;
+    let chain = mine_chain(&mut storage, &data).await;
+    chain.check_invariants(&mut storage).await.unwrap();
+    chain.validate_chain(&mut storage).await.unwrap();
+
+    let blocks = corrupt(Chain::get_chain(&mut storage).await.unwrap(), corruption);
+    let mut storage = self::storage(backend).await;
+    for block in blocks.iter() {
+        storage.insert_block(block).await.unwrap();
+    }
+    let invariants = chain.check_invariants(&mut storage).await;
+    match corruption {
+        // Structurally the chain is fine, only the full validation notices
+        Corruption::TamperedData(_) => invariants.unwrap(),
+        _ => assert!(matches!(invariants, Err(BlockchainError::ChainInvalid(_))), "{:?}", invariants),
+    }
+    assert!(chain.validate_chain(&mut storage).await.is_err());
+}
+
+// A longer but corrupted chain of a peer never replaces ours, which stays intact
+async fn reject_incoming_chain(backend: Backend, data: Vec<String>, corruption: Corruption) {
+    let mut other_storage = Storage::Memory(MemoryStorage::default());
+    let mut other_data = data.iter().map(|data| format!("{} other", data)).collect::<Vec<_>>();
+    other_data.push("longer".to_owned());
+    mine_chain(&mut other_storage, &other_data).await;
+    let mut incoming = corrupt(Chain::get_chain(&mut other_storage).await.unwrap(), corruption);
+
+    let mut storage = storage(backend).await;
+    let mut chain = mine_chain(&mut storage, &data).await;
+    let latest_block = chain.latest_block.clone();
+    match corruption {
+        // Still a valid chain, only shorter
+        Corruption::Truncated(_) => {}
+        _ => assert!(chain.update(&mut storage, &mut incoming).await.is_err()),
+    }
+    assert_eq!(chain.latest_block, latest_block);
+    chain.check_invariants(&mut storage).await.unwrap();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn test_invariants_memory((data, corruption) in chains()) {
+        run(check_stored_chain(Backend::Memory, data, corruption));
+    }
+
+    #[test]
+    fn test_reject_invalid_chains_memory((data, corruption) in chains()) {
+        run(reject_incoming_chain(Backend::Memory, data, corruption));
+    }
+}
+
+// The Postgres cases share one database, so they run in one test
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(8))]
+
+    #[test]
+    fn test_invariants_postgres((data, corruption) in chains()) {
+        run(async {
+            check_stored_chain(Backend::Postgres, data.clone(), corruption).await;
+            reject_incoming_chain(Backend::Postgres, data, corruption).await;
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_invariant_violations() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = mine_chain(&mut storage, &["block 1".to_owned(), "block 2".to_owned()]).await;
+    chain.check_invariants(&mut storage).await.unwrap();
+
+    // Our latest block has to be the stored tip
+    let latest_block = chain.latest_block.clone();
+    chain.latest_block = Chain::get_block_by_id(&mut storage, Height(1)).await.unwrap();
+    assert!(matches!(chain.check_invariants(&mut storage).await, Err(BlockchainError::ChainInvalid(_))));
+    chain.latest_block = latest_block;
+
+    // The first block has to be our genesis block
+    chain.genesis.hash = "other genesis".to_owned();
+    assert!(matches!(chain.check_invariants(&mut storage).await, Err(BlockchainError::ChainInvalid(_))));
+}
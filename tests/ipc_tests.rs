@@ -0,0 +1,94 @@
+use rust_blockchain::ipc::*;
+use rust_blockchain::rpc::{RpcReply, RpcRequest, RpcResponse, INVALID_PARAMS};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+fn args(args: &[&str]) -> impl Iterator<Item = String> {
+    args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+}
+
+#[test]
+fn test_client_config() {
+    let config = ClientConfig::from_args(args(&["--connect", "/tmp/node.sock", "getblock", "{\"height\":", "1}"])).unwrap();
+    assert_eq!(config, ClientConfig { socket: PathBuf::from("/tmp/node.sock"), command: Some("getblock {\"height\": 1}".to_owned()) });
+    assert_eq!(ClientConfig::from_args(args(&["--connect", "node.sock"])).unwrap().command, None);
+    assert!(ClientConfig::from_args(args(&["--connect"])).is_err());
+    assert!(ClientConfig::from_args(args(&["node.sock"])).is_err());
+}
+
+#[test]
+fn test_request_line() {
+    let request = serde_json::from_str::<RpcRequest>(&request_line("getblock {\"height\": 1}", 7).unwrap()).unwrap();
+    assert_eq!(request.method, "getblock");
+    assert_eq!(request.params, json!({"height": 1}));
+    assert_eq!(request.id, json!(7));
+    let request = serde_json::from_str::<RpcRequest>(&request_line(" getlatestblock ", 1).unwrap()).unwrap();
+    assert_eq!(request.params, Value::Null);
+    // Raw requests are passed on
+    let raw = r#"[{"jsonrpc": "2.0", "method": "getlatestblock", "id": 1}]"#;
+    assert_eq!(request_line(raw, 2).unwrap(), raw);
+    assert!(request_line("getblock {height}", 1).is_err());
+    assert!(request_line("", 1).is_err());
+
+    let error = RpcResponse::error(json!(1), INVALID_PARAMS, "invalid".to_owned());
+    let result = RpcResponse::result(json!(2), json!(true));
+    assert!(!is_error(&RpcReply::Single(result.clone())));
+    assert!(is_error(&RpcReply::Batch(vec![result, error])));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_ipc_server() {
+    use rust_blockchain::head::HeadEvent;
+    use rust_blockchain::mining::MiningQueue;
+    use rust_blockchain::node::Node;
+    use rust_blockchain::nodeinfo::NodeInfo;
+    use rust_blockchain::orphans::OrphanPool;
+    use rust_blockchain::rpc::{answer, MAX_REQUEST_LENGTH, PARSE_ERROR};
+    use rust_blockchain::storage::{MemoryStorage, Storage};
+    use rust_blockchain::types::EventType;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    use std::{env, fs};
+    use tokio::sync::{broadcast, mpsc};
+    use tokio::time;
+
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let path = env::temp_dir().join("rust_blockchain_ipc_test.sock");
+    // A stale socket is replaced, other files are not
+    let _ = fs::remove_file(&path);
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    drop(bind(&path).unwrap());
+    assert!(!path.exists());
+    fs::write(&path, "not a socket").unwrap();
+    assert!(bind(&path).is_err());
+    fs::remove_file(&path).unwrap();
+    let listener = bind(&path).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let (ipc_sender, ipc_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (head_sender, _) = broadcast::channel::<HeadEvent>(16);
+    tokio::spawn(init_ipc(listener, ipc_rcv, main_sender, head_sender));
+
+    let mut client = IpcClient::connect(&path).await.unwrap();
+    let line = request_line("getlatestblock", 1).unwrap();
+    let reply = tokio::spawn(async move { client.call(&line).await.unwrap() });
+    let (sender, call) = match time::timeout(Duration::from_secs(10), main_rcv.recv()).await.unwrap() {
+        Some(EventType::ReceivedRpcCall{sender, call}) => (sender, call),
+        other => panic!("unexpected event {:?}", other),
+    };
+    assert!(sender.starts_with(IPC_PREFIX));
+    let expected = answer(&mut node.storage, &NodeInfo::default(), &MiningQueue::new(), &OrphanPool::default(), call).await;
+    ipc_sender.send(EventType::SendRpcReply{receiver: sender, reply: expected.clone()}).unwrap();
+    let reply = time::timeout(Duration::from_secs(10), reply).await.unwrap().unwrap();
+    assert_eq!(serde_json::from_str::<RpcReply>(&reply).unwrap(), expected);
+
+    // Overlong requests are refused like over TCP
+    let mut client = IpcClient::connect(&path).await.unwrap();
+    let reply = time::timeout(Duration::from_secs(10), client.call(&"x".repeat(MAX_REQUEST_LENGTH + 1))).await.unwrap().unwrap();
+    match serde_json::from_str::<RpcReply>(&reply).unwrap() {
+        RpcReply::Single(response) => assert_eq!(response.error.unwrap().code, PARSE_ERROR),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::Utc;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{Feature, ACTIVATIONS};
+use rust_blockchain::keys::*;
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{Height, Nonce};
+use std::env;
+use std::fs;
+
+#[test]
+fn test_derive_key() {
+    // RFC 7914 PBKDF2-HMAC-SHA256 test vectors
+    assert_eq!(
+        hex::encode(derive_key("password", b"salt", 1)),
+        "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+    );
+    assert_eq!(
+        hex::encode(derive_key("password", b"salt", 4096)),
+        "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+    );
+}
+
+#[test]
+fn test_encrypted_keys() {
+    let encrypted = encrypt_key(b"secret key", "passphrase");
+    assert!(!encrypted.contains(&hex::encode(b"secret key")));
+    assert_eq!(decrypt_key(&encrypted, "passphrase").unwrap(), b"secret key");
+    assert!(decrypt_key(&encrypted, "wrong").is_err());
+    assert!(decrypt_key("{}", "passphrase").is_err());
+
+    // A tampered key file can not weaken or stall the key derivation
+    let rounds = serde_json::from_str::<serde_json::Value>(&encrypted).unwrap()["rounds"].as_u64().unwrap();
+    for tampered in [1, u32::MAX] {
+        let json = encrypted.replace(&format!("\"rounds\": {}", rounds), &format!("\"rounds\": {}", tampered));
+        assert_ne!(json, encrypted);
+        assert!(decrypt_key(&json, "passphrase").unwrap_err().to_string().contains("kdf rounds"));
+    }
+}
+
+#[test]
+fn test_key_store() {
+    let dir = env::temp_dir().join("rust_blockchain_keys_test");
+    let _ = fs::remove_dir_all(&dir);
+
+    let store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    let identity = store.identity().unwrap().public();
+    let signing_key = store.signing_key().unwrap().public();
+    let encryption_key = store.encryption_key().unwrap().to_bytes();
+    // The keys are kept across restarts and are independent of each other
+    let store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    assert_eq!(store.identity().unwrap().public(), identity);
+    assert_eq!(store.signing_key().unwrap().public(), signing_key);
+    assert_eq!(store.encryption_key().unwrap().to_bytes(), encryption_key);
+    assert_ne!(identity.to_peer_id(), libp2p::identity::PublicKey::Ed25519(signing_key.clone()).to_peer_id());
+    assert!(KeyStore::open(&dir, "wrong".to_owned()).unwrap().signing_key().is_err());
+
+    // Rotating replaces the signing key only
+    let rotation = store.rotate_signing_key().unwrap();
+    assert_eq!(rotation.old_key, hex::encode(signing_key.encode()));
+    assert_eq!(rotation.new_key, hex::encode(store.signing_key().unwrap().public().encode()));
+    assert_eq!(store.identity().unwrap().public(), identity);
+    assert!(dir.join("retired").join(format!("{}.key", rotation.old_key)).exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_key_rotation() {
+    let (old, new) = (libp2p::identity::ed25519::Keypair::generate(), libp2p::identity::ed25519::Keypair::generate());
+    let rotation = KeyRotation::new(&old, &new);
+    assert!(rotation.verify().is_ok());
+    let data = rotation.to_block_data();
+    assert_eq!(KeyRotation::from_block_data(&data).unwrap().unwrap(), rotation);
+    assert!(check_block_data(&data).is_ok());
+    assert!(check_block_data("just data").is_ok());
+    assert!(check_block_data(&format!("{}not json", KEY_ROTATION_PREFIX)).is_err());
+
+    // Both keys have to sign
+    let other = libp2p::identity::ed25519::Keypair::generate();
+    let forged = KeyRotation { old_signature: KeyRotation::new(&other, &new).old_signature, ..rotation.clone() };
+    assert!(forged.verify().is_err());
+    let forged = KeyRotation { new_key: hex::encode(other.public().encode()), ..rotation };
+    assert!(check_block_data(&forged.to_block_data()).is_err());
+}
+
+#[tokio::test]
+async fn test_key_rotation_activation() {
+    let activation = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::KeyRotations)
+        .unwrap();
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut parents = vec![];
+    for id in [activation.height.0 - 2, activation.height.0 - 1] {
+        let parent = Block {
+            hash: format!("block {}", id),
+            id: Height(id),
+            prev_hash: format!("block {}", id - 1),
+            timestamp: Utc::now().timestamp_millis() - 60_000,
+            nonce: Nonce(0),
+            data: String::new(),
+            version: BLOCK_VERSION,
+            miner: String::new(),
+            extra_nonce: Nonce(0),
+            difficulty: 1,
+            fee: 0,
+            state_root: String::new(),
+            coinbase: None,
+        };
+        storage.insert_block(&parent).await.unwrap();
+        parents.push(parent);
+    }
+
+    let (old, new) = (libp2p::identity::ed25519::Keypair::generate(), libp2p::identity::ed25519::Keypair::generate());
+    let mut forged = KeyRotation::new(&old, &new);
+    forged.new_key = forged.old_key.clone();
+    let mine = |parent: &Block, data: String| Block::mine(parent, data, "miner".to_owned(), "", 1, HashBackend::Cpu);
+
+    // Invalid announcements are just data before the upgrade
+    let before = mine(&parents[0], forged.to_block_data());
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &before).await, Ok(())));
+
+    let valid = mine(&parents[1], KeyRotation::new(&old, &new).to_block_data());
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &valid).await, Ok(())));
+    let invalid = mine(&parents[1], forged.to_block_data());
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &invalid).await, Err(BlockchainError::BlockInvalid(_))));
+}
+
+#[tokio::test]
+async fn test_exclusive_key_rotations() {
+    let activation = ACTIVATIONS
+        .iter()
+        .find(|activation| activation.feature == Feature::ExclusiveKeyRotations)
+        .unwrap();
+    let (old, new, newer) = (
+        libp2p::identity::ed25519::Keypair::generate(),
+        libp2p::identity::ed25519::Keypair::generate(),
+        libp2p::identity::ed25519::Keypair::generate(),
+    );
+    let rotation = KeyRotation::new(&old, &new);
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut parents = vec![];
+    for (id, data) in [(activation.height.0 - 2, rotation.to_block_data()), (activation.height.0 - 1, String::new())] {
+        let parent = Block {
+            hash: format!("block {}", id),
+            id: Height(id),
+            prev_hash: format!("block {}", id - 1),
+            timestamp: Utc::now().timestamp_millis() - 60_000,
+            nonce: Nonce(0),
+            data,
+            version: BLOCK_VERSION,
+            miner: String::new(),
+            extra_nonce: Nonce(0),
+            difficulty: 1,
+            fee: 0,
+            state_root: String::new(),
+            coinbase: None,
+        };
+        storage.insert_block(&parent).await.unwrap();
+        parents.push(parent);
+    }
+    assert_eq!(rotated_key(&rotation.to_block_data()), Some(rotation.old_key.clone()));
+    assert_eq!(rotated_key("data"), None);
+    let rotating = Chain::get_key_rotation(&mut storage, &rotation.old_key, activation.height).await.unwrap();
+    assert_eq!(rotating.map(|block| block.id), Some(parents[0].id));
+    assert!(Chain::get_key_rotation(&mut storage, &rotation.old_key, parents[0].id).await.unwrap().is_none());
+    assert!(Chain::get_key_rotation(&mut storage, &rotation.new_key, activation.height).await.unwrap().is_none());
+
+    let mine = |parent: &Block, data: String| Block::mine(parent, data, "miner".to_owned(), "", 1, HashBackend::Cpu);
+    // A second rotation of the key is just another block before the upgrade
+    let before = mine(&parents[0], KeyRotation::new(&old, &newer).to_block_data());
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &before).await, Ok(())));
+
+    let conflicting = mine(&parents[1], KeyRotation::new(&old, &newer).to_block_data());
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &conflicting).await, Err(BlockchainError::BlockInvalid(_))));
+    // The new key can be rotated in turn
+    let next = mine(&parents[1], KeyRotation::new(&new, &newer).to_block_data());
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &next).await, Ok(())));
+}
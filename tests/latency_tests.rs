@@ -0,0 +1,60 @@
+use rust_blockchain::blockchain::Block;
+use rust_blockchain::latency::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::EventType;
+use std::time::Instant;
+
+#[test]
+fn test_arrival_latency() {
+    let mut tracker = LatencyTracker::default();
+    assert_eq!(tracker.report(), LatencyReport::default());
+    assert_eq!(tracker.record_arrival(1_000, 1_030), 30);
+    assert_eq!(tracker.record_arrival(1_000, 1_010), 10);
+    // The miner's clock is ahead of ours
+    assert_eq!(tracker.record_arrival(1_000, 990), 0);
+
+    let report = tracker.report();
+    assert_eq!(report.arrivals, 3);
+    let arrival = report.arrival.unwrap();
+    assert_eq!((arrival.min, arrival.p50, arrival.max), (0, 10, 30));
+    assert_eq!(report.first_ack, None);
+
+    // The percentiles only cover the latest samples, the count covers all
+    for _ in 0..LATENCY_SAMPLES {
+        tracker.record_arrival(1_000, 1_005);
+    }
+    let report = tracker.report();
+    assert_eq!(report.arrivals, LATENCY_SAMPLES as u64 + 3);
+    assert_eq!(report.arrival.unwrap().max, 5);
+}
+
+#[test]
+fn test_first_acknowledgement() {
+    let mut tracker = LatencyTracker::default();
+    tracker.record_mined("a", 1_000);
+    tracker.record_mined("b", 2_000);
+    assert_eq!(tracker.record_receipt("a", 1_040), Some(40));
+    // Only the first receipt of a block counts
+    assert_eq!(tracker.record_receipt("a", 1_100), None);
+    assert_eq!(tracker.record_receipt("other", 1_100), None);
+
+    let report = tracker.report();
+    assert_eq!(report.acknowledged, 1);
+    assert_eq!(report.first_ack.as_ref().map(|stats| stats.p95), Some(40));
+    assert_eq!(report.to_string(), "block arrival ms (0 blocks): none\nfirst acknowledgement of mined blocks ms (1 blocks): min 40 / avg 40.0 / p50 40 / p95 40 / max 40");
+}
+
+// Every received block is acknowledged to its miner
+#[tokio::test]
+async fn test_receipts_for_new_blocks() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "node".to_owned()).await.unwrap();
+    let block = Block::new(&node.chain.latest_block, "block 1".to_owned(), "miner".to_owned());
+    let events = node.handle_event(EventType::ReceivedNewBlock { sender: "miner".to_owned(), block: block.clone() }, Instant::now()).await;
+    match events.as_slice() {
+        [EventType::SendBlockReceipt { receiver, hash, .. }] => assert_eq!((receiver.as_str(), hash), ("miner", &block.hash)),
+        events => panic!("unexpected events: {:?}", events),
+    }
+    assert_eq!(node.chain.latest_block, block);
+    assert!(report().arrivals >= 1);
+}
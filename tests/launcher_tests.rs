@@ -0,0 +1,63 @@
+use rust_blockchain::config::{Config, StorageKind};
+use rust_blockchain::launcher::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn launch_config(args: &[&str]) -> Result<LaunchConfig, rust_blockchain::blockchain::BlockchainError> {
+    LaunchConfig::from_args(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn test_launch_options() {
+    let config = launch_config(&[]).unwrap();
+    assert_eq!(config.nodes, DEFAULT_NODES);
+    assert_eq!(config.storage, StorageKind::Postgres);
+    assert_eq!(config.base_port, DEFAULT_BASE_PORT);
+
+    let config = launch_config(&["5", "--storage", "memory", "--base-port", "5000", "--dir", "demo", "--", "--regtest", "--no-p2p"]).unwrap();
+    assert_eq!(config.nodes, 5);
+    assert_eq!(config.storage, StorageKind::Memory);
+    assert_eq!(config.base_port, 5000);
+    assert_eq!(config.dir, PathBuf::from("demo"));
+    // Everything after -- is for the nodes
+    assert_eq!(config.node_args, vec!["--regtest", "--no-p2p"]);
+
+    assert!(launch_config(&["0"]).is_err());
+    assert!(launch_config(&["--db-prefix", "no-dashes"]).is_err());
+    assert!(launch_config(&["--storage", "disk"]).is_err());
+    assert!(launch_config(&["--unknown"]).is_err());
+    // The RPC port of the last node would overflow
+    assert!(launch_config(&["100", "--base-port", "65400"]).is_err());
+}
+
+#[test]
+fn test_launch_plan() {
+    let config = launch_config(&["4", "--db-prefix", "demo", "--", "--regtest"]).unwrap();
+    let plan = config.plan();
+    assert_eq!(plan.len(), 4);
+    assert_eq!(plan.iter().map(|spec| spec.p2p_port).chain(plan.iter().map(|spec| spec.rpc_port)).collect::<HashSet<_>>().len(), 8);
+    assert_eq!(plan.iter().map(|spec| spec.data_dir.clone()).collect::<HashSet<_>>().len(), 4);
+
+    for (i, spec) in plan.iter().enumerate() {
+        // Every command line is one the node accepts
+        let node = Config::from_args(spec.args.iter().cloned()).unwrap();
+        assert_eq!(node.db_name, Some(format!("demo_node{}", i)));
+        assert_eq!(node.data_dir.map(|data_dir| data_dir.root), Some(spec.data_dir.clone()));
+        assert_eq!(node.listen, vec![format!("/ip4/127.0.0.1/tcp/{}", spec.p2p_port)]);
+        assert_eq!(node.rpc.map(|addr| addr.port()), Some(spec.rpc_port));
+        assert!(node.regtest);
+        // Dials the nodes started before
+        assert_eq!(node.peers, plan[..i].iter().map(|peer| format!("/ip4/127.0.0.1/tcp/{}", peer.p2p_port)).collect::<Vec<_>>());
+    }
+
+    let memory = launch_config(&["2", "--storage", "memory"]).unwrap().plan();
+    let node = Config::from_args(memory[1].args.iter().cloned()).unwrap();
+    assert_eq!(node.storage, StorageKind::Memory);
+    assert_eq!(node.db_name, None);
+}
+
+#[test]
+fn test_prefix_line() {
+    assert_eq!(prefix_line("node1", 6, "started"), "node1  | started");
+    assert_eq!(prefix_line("node10", 6, ""), "node10 | ");
+}
@@ -0,0 +1,98 @@
+use async_trait::async_trait;
+use rust_blockchain::blockchain::{Block, BlockchainError, Checkpoint};
+use rust_blockchain::handshake::{Features, Handshake};
+use rust_blockchain::lifecycle::LifecycleHook;
+use rust_blockchain::mining::Priority;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{EventType, Height};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Default)]
+struct Recorder {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl LifecycleHook for Recorder {
+    async fn on_block_accepted(&self, block: &Block) {
+        self.events.lock().unwrap().push(format!("accepted {}", block.id));
+    }
+
+    async fn on_reorg(&self, old_tip: &Block, new_tip: &Block, depth: u64) {
+        self.events.lock().unwrap().push(format!("reorg {} -> {} depth {}", old_tip.id, new_tip.id, depth));
+    }
+
+    async fn before_mine(&self, data: &str) -> Result<(), String> {
+        match data {
+            "forbidden" => Err("forbidden data".to_owned()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn on_peer_connected(&self, peer: &str, _handshake: &Handshake) {
+        self.events.lock().unwrap().push(format!("connected {}", peer));
+    }
+}
+
+// Hooks only implementing some of the methods
+struct Quiet;
+
+impl LifecycleHook for Quiet {}
+
+#[tokio::test]
+async fn test_lifecycle_hooks() {
+    let now = Instant::now();
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "node".to_owned()).await.unwrap();
+    let recorder = Recorder::default();
+    let events = recorder.events.clone();
+    node.lifecycle.add("quiet", Quiet);
+    node.lifecycle.add("recorder", recorder);
+    assert_eq!(node.lifecycle.names().collect::<Vec<_>>(), vec!["quiet", "recorder"]);
+
+    node.mining_queue.push("block 1".to_owned(), Priority::Normal);
+    assert!(node.mine_next().await.unwrap().is_some());
+    node.watch_head().await.unwrap();
+    assert_eq!(*events.lock().unwrap(), vec!["accepted 1"]);
+
+    // A veto drops the job
+    node.mining_queue.push("forbidden".to_owned(), Priority::Normal);
+    match node.mine_next().await {
+        Err(BlockchainError::MiningVetoed(reason)) => assert_eq!(reason, "recorder: forbidden data"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(node.mining_queue.is_empty());
+    assert_eq!(node.chain.latest_block.id, Height(1));
+
+    let handshake = Handshake::new(node.chain.genesis.hash.clone(), Checkpoint::genesis(), Features::default());
+    node.handle_event(EventType::PeerHandshake { peer: "other".to_owned(), handshake: Some(handshake) }, now).await;
+    node.handle_event(EventType::PeerHandshake { peer: "other".to_owned(), handshake: None }, now).await;
+    assert_eq!(events.lock().unwrap().last().unwrap(), "connected other");
+
+    // A longer chain of another miner replaces our block
+    let mut other = Node::init(Storage::Memory(MemoryStorage::default()), "other".to_owned()).await.unwrap();
+    for data in ["other 1", "other 2"] {
+        other.chain.mine_block(data.to_owned(), &mut other.storage).await.unwrap();
+    }
+    let latest = EventType::ReceivedLatestBlock { sender: "other".to_owned(), block: other.chain.latest_block.clone() };
+    let (session_id, from) = match node.handle_event(latest, now).await.as_slice() {
+        [EventType::SendChainRequest { session_id, from, .. }] => (session_id.clone(), *from),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let request = EventType::ReceivedChainRequest { receiver: "node".to_owned(), session_id: session_id.clone(), from };
+    let chain = match other.handle_event(request, now).await.pop() {
+        Some(EventType::SendChain { chain, .. }) => chain,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    node.handle_event(EventType::ReceivedChain { sender: "other".to_owned(), session_id, chain }, now).await;
+    node.watch_head().await.unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec!["accepted 1", "connected other", "reorg 1 -> 2 depth 1", "accepted 1", "accepted 2"]
+    );
+
+    node.lifecycle.remove("recorder");
+    node.mining_queue.push("forbidden".to_owned(), Priority::Normal);
+    assert!(node.mine_next().await.unwrap().is_some());
+}
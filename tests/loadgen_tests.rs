@@ -0,0 +1,72 @@
+use rust_blockchain::blockchain::{Block, REGTEST_DIFFICULTY};
+use rust_blockchain::loadgen::*;
+use rust_blockchain::simulation::{ChaosConfig, Simulation};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_loadgen_config() {
+    let config = LoadgenConfig::from_args(" --blocks-per-sec 4 --payload-size 1024").unwrap();
+    assert_eq!(config.blocks_per_sec, 4.0);
+    assert_eq!(config.payload_size, 1024);
+    assert_eq!(config.interval(), Duration::from_millis(250));
+
+    assert!(LoadgenConfig::from_args("").is_ok());
+    assert!(LoadgenConfig::from_args("--blocks-per-sec 0").is_err());
+    assert!(LoadgenConfig::from_args("--payload-size").is_err());
+    assert!(LoadgenConfig::from_args("--transactions 5").is_err());
+}
+
+#[test]
+fn test_loadgen_receipts() {
+    let now = Instant::now();
+    let config = LoadgenConfig::from_args("--payload-size 100").unwrap();
+    let mut loadgen = LoadGenerator::new(config, now);
+
+    let mut block = Block::create_genesis();
+    block.data = loadgen.payload();
+    block.hash = "block 1".to_owned();
+    assert_eq!(block.data.len(), 100);
+    assert!(is_loadgen_block(&block));
+    assert!(!is_loadgen_block(&Block::create_genesis()));
+
+    loadgen.record_block(&block, 1_000);
+    assert_eq!(loadgen.record_receipt("peer a", "block 1", 1_040), Some(40));
+    assert_eq!(loadgen.record_receipt("peer b", "block 1", 1_020), Some(20));
+    // Duplicate receipts and receipts for blocks we did not generate are ignored
+    assert_eq!(loadgen.record_receipt("peer a", "block 1", 1_050), None);
+    assert_eq!(loadgen.record_receipt("peer a", "unknown", 1_050), None);
+
+    let report = loadgen.report(now + Duration::from_secs(2));
+    assert_eq!(report.blocks, 1);
+    assert_eq!(report.blocks_per_sec, 0.5);
+    assert_eq!(report.bytes_per_sec, 50.0);
+    assert_eq!(report.receipts, 2);
+    assert_eq!(report.peers, 2);
+    let latency = report.latency.unwrap();
+    assert_eq!((latency.min, latency.p50, latency.max), (20, 20, 40));
+    assert_eq!(latency.avg, 30.0);
+}
+
+#[tokio::test]
+async fn test_loadgen_simulation() {
+    let nodes = 3;
+    let mut simulation = Simulation::new(nodes, ChaosConfig::default(), 0).await.unwrap();
+    let config = LoadgenConfig::from_args("--payload-size 512").unwrap();
+    // Blocks below the difficulty are rejected, so the whole network runs at regtest difficulty
+    for node in simulation.nodes.iter_mut() {
+        node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    }
+    simulation.nodes[0].loadgen = Some(LoadGenerator::new(config, Instant::now()));
+
+    for _ in 0..3 {
+        simulation.generate_load(0).await.unwrap();
+        assert!(simulation.run_until_idle(10).await);
+    }
+
+    assert!(simulation.converged());
+    let report = simulation.nodes[0].loadgen.as_ref().unwrap().report(Instant::now());
+    assert_eq!(report.blocks, 3);
+    assert_eq!(report.receipts, 3 * (nodes - 1));
+    assert_eq!(report.peers, nodes - 1);
+    assert!(report.latency.is_some());
+}
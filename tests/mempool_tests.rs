@@ -0,0 +1,145 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::mempool::*;
+use rust_blockchain::mining::Priority;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::{self, Transaction, TxStatus};
+use rust_blockchain::utxo::{OutPoint, RECIPIENT_OUTPUT};
+use rust_blockchain::wallet::address;
+use std::collections::HashMap;
+use std::thread;
+
+fn signed(sender: &ed25519::Keypair, recipient: &str, fee: u64) -> Transaction {
+    let mut transaction = Transaction::new(&sender.public(), recipient.to_owned(), 10, fee);
+    transaction.sign(sender).unwrap();
+    transaction
+}
+
+#[test]
+fn test_transaction_pool() {
+    let sender = ed25519::Keypair::generate();
+    let pool = TransactionPool::new();
+    let (cheap, expensive) = (signed(&sender, "alice", 1), signed(&sender, "bob", 5));
+    assert!(pool.insert(cheap.clone()).unwrap());
+    assert!(pool.insert(expensive.clone()).unwrap());
+    // Deduplicated by ID
+    assert!(!pool.insert(cheap.clone()).unwrap());
+    assert!(pool.insert(Transaction::new(&sender.public(), "carol".to_owned(), 10, 1)).is_err());
+    assert_eq!(pool.len(), 2);
+    assert_eq!(pool.get(&cheap.id()), Some(cheap.clone()));
+    assert_eq!(pool.pending(), vec![expensive.clone(), cheap.clone()]);
+
+    assert_eq!(pool.drain(1), vec![expensive]);
+    assert_eq!(pool.pending(), vec![cheap.clone()]);
+    assert_eq!(pool.remove(&[cheap.id()]), 1);
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn test_pool_is_shared() {
+    let sender = ed25519::Keypair::generate();
+    let pool = TransactionPool::new();
+    let handles = (0..4)
+        .map(|fee| {
+            let (pool, transaction) = (pool.clone(), signed(&sender, "alice", fee));
+            thread::spawn(move || pool.insert(transaction).unwrap())
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        assert!(handle.join().unwrap());
+    }
+    assert_eq!(pool.len(), 4);
+}
+
+#[tokio::test]
+async fn test_mine_pending_transactions() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    assert!(node.mine_next().await.unwrap().is_none());
+
+    let sender = ed25519::Keypair::generate();
+    let (first, second) = (signed(&sender, "alice", 1), signed(&sender, "bob", 2));
+    node.transactions.insert(first.clone()).unwrap();
+    node.transactions.insert(second.clone()).unwrap();
+    let found = transactions::find(&node.chain, &mut node.storage, &node.mining_queue, &node.transactions, &HashMap::new(), &first.id()).await.unwrap();
+    assert_eq!(found, Some((first.clone(), TxStatus::Pooled)));
+
+    // Queued jobs are mined first
+    node.mining_queue.push("job".to_owned(), Priority::Low);
+    assert_eq!(node.mine_next().await.unwrap().unwrap().data, "job");
+    let block = node.mine_next().await.unwrap().unwrap();
+    assert_eq!(block.transactions().unwrap(), vec![second.clone(), first.clone()]);
+    assert_eq!(block.fee, 3);
+    assert!(node.transactions.is_empty());
+
+    // Transactions another block carried are dropped
+    node.transactions.insert(first.clone()).unwrap();
+    assert!(node.mine_next().await.unwrap().is_none());
+    assert!(node.transactions.is_empty());
+
+    // Blocks of the main chain remove the transactions they carry from the pool
+    let third = signed(&sender, "carol", 1);
+    node.transactions.insert(third.clone()).unwrap();
+    node.chain.mine_block(third.to_block_data(), &mut node.storage).await.unwrap();
+    node.watch_head().await.unwrap();
+    assert!(node.transactions.is_empty());
+}
+
+fn spend(sender: &ed25519::Keypair, recipient: &str, amount: u64, inputs: Vec<OutPoint>) -> Transaction {
+    let mut transaction = Transaction::new(&sender.public(), recipient.to_owned(), amount, 0);
+    transaction.inputs = inputs;
+    transaction.sign(sender).unwrap();
+    transaction
+}
+
+async fn broadcast(node: &mut Node, transaction: Transaction) -> Result<bool, BlockchainError> {
+    admit(&mut node.chain, &mut node.storage, &node.transactions, &node.mining_queue, transaction).await
+}
+
+#[tokio::test]
+async fn test_double_spend() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let (minter, alice, bob) = (ed25519::Keypair::generate(), ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let (mint, funding) = (spend(&minter, &address(&alice.public()), 100, vec![]), spend(&minter, &address(&bob.public()), 100, vec![]));
+    let change = spend(&minter, &address(&alice.public()), 50, vec![]);
+    node.chain.mine_transactions(vec![mint.clone(), funding, change.clone()], &mut node.storage).await.unwrap();
+    let output = OutPoint { txid: mint.id(), vout: RECIPIENT_OUTPUT };
+
+    // An output spent by a pending transaction
+    let first = spend(&alice, "carol", 70, vec![output.clone()]);
+    let second = spend(&alice, "dave", 10, vec![output.clone()]);
+    assert!(broadcast(&mut node, first.clone()).await.unwrap());
+    assert!(matches!(node.transactions.insert(second.clone()), Err(BlockchainError::DoubleSpend(_))));
+    assert!(matches!(broadcast(&mut node, second.clone()).await, Err(BlockchainError::DoubleSpend(_))));
+    // and by a main chain block
+    node.mine_next().await.unwrap().unwrap();
+    match broadcast(&mut node, second).await {
+        Err(BlockchainError::DoubleSpend(reason)) => assert!(reason.contains("was spent in block"), "{}", reason),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    // A confirmed transaction can not be replayed
+    match broadcast(&mut node, first).await {
+        Err(BlockchainError::DoubleSpend(reason)) => assert!(reason.contains("was confirmed"), "{}", reason),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // Transactions of queued mining jobs count like pending ones
+    let change_output = OutPoint { txid: change.id(), vout: RECIPIENT_OUTPUT };
+    let queued = spend(&alice, "carol", 20, vec![change_output.clone()]);
+    node.mining_queue.push(transactions::to_block_data(&[queued.clone(), spend(&bob, "carol", 30, vec![])]), Priority::Normal);
+    assert!(!broadcast(&mut node, queued).await.unwrap());
+    match broadcast(&mut node, spend(&alice, "dave", 20, vec![change_output])).await {
+        Err(BlockchainError::DoubleSpend(reason)) => assert!(reason.contains("queued transaction"), "{}", reason),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // Transfers without inputs may not send more than the confirmed balance left by the pending and
+    // queued ones
+    assert!(broadcast(&mut node, spend(&bob, "carol", 40, vec![])).await.unwrap());
+    assert!(matches!(broadcast(&mut node, spend(&bob, "dave", 40, vec![])).await, Err(BlockchainError::DoubleSpend(_))));
+    assert!(broadcast(&mut node, spend(&bob, "dave", 30, vec![])).await.unwrap());
+    assert_eq!(node.transactions.pending_spend(&address(&bob.public())), 70);
+    assert!(matches!(broadcast(&mut node, spend(&ed25519::Keypair::generate(), "carol", 1, vec![])).await, Err(BlockchainError::DoubleSpend(_))));
+}
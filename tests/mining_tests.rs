@@ -0,0 +1,184 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::keys::KeyRotation;
+use rust_blockchain::mining::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use std::env;
+use std::fs;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+fn data(queue: &MiningQueue) -> Vec<&str> {
+    queue.jobs().iter().map(|job| job.data.as_str()).collect()
+}
+
+#[test]
+fn test_mining_queue_order() {
+    let mut queue = MiningQueue::new();
+    assert_eq!(queue.push("a".to_owned(), Priority::Normal), Enqueued::Added(1));
+    assert_eq!(queue.push("b".to_owned(), Priority::Low), Enqueued::Added(2));
+    assert_eq!(queue.push("c".to_owned(), Priority::High), Enqueued::Added(3));
+    assert_eq!(queue.push("d".to_owned(), Priority::Normal), Enqueued::Added(4));
+    assert_eq!(data(&queue), vec!["c", "a", "d", "b"]);
+
+    // Duplicates are merged and keep the higher priority
+    assert_eq!(queue.push("d".to_owned(), Priority::Low), Enqueued::Merged(4));
+    assert_eq!(queue.push("b".to_owned(), Priority::High), Enqueued::Merged(2));
+    assert_eq!(data(&queue), vec!["c", "b", "a", "d"]);
+
+    assert!(queue.set_priority(1, Priority::Low));
+    assert_eq!(data(&queue), vec!["c", "b", "d", "a"]);
+    assert!(queue.move_to_front(4));
+    assert_eq!(queue.jobs()[0].priority, Priority::High);
+    assert_eq!(data(&queue), vec!["d", "c", "b", "a"]);
+
+    assert_eq!(queue.remove(3).map(|job| job.data), Some("c".to_owned()));
+    assert!(queue.remove(3).is_none());
+    assert!(!queue.set_priority(3, Priority::High));
+    assert!(!queue.move_to_front(3));
+
+    assert_eq!(queue.pop().map(|job| job.id), Some(4));
+    assert_eq!(queue.pop().map(|job| job.id), Some(2));
+    assert_eq!(queue.pop().map(|job| job.id), Some(1));
+    assert!(queue.pop().is_none());
+    assert!(queue.is_empty());
+    // Mined data can be queued again
+    assert_eq!(queue.push("a".to_owned(), Priority::Normal), Enqueued::Added(5));
+
+    assert_eq!("high".parse::<Priority>().unwrap(), Priority::High);
+    assert!("urgent".parse::<Priority>().is_err());
+}
+
+#[tokio::test]
+async fn test_mine_next() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    assert!(node.mine_next().await.unwrap().is_none());
+
+    node.mining_queue.push("later".to_owned(), Priority::Low);
+    node.mining_queue.push("first".to_owned(), Priority::High);
+    let first = node.mine_next().await.unwrap().unwrap();
+    assert_eq!(first.data, "first");
+    let later = node.mine_next().await.unwrap().unwrap();
+    assert_eq!(later.data, "later");
+    assert_eq!(later.prev_hash, first.hash);
+    assert_eq!(node.chain.latest_block, later);
+    assert!(node.mining_queue.is_empty());
+}
+
+#[test]
+fn test_mempool_policy() {
+    let policy = MempoolPolicy { max_age: Some(Duration::from_secs(60)), max_bytes: Some(10), min_priority: Priority::Normal, ..MempoolPolicy::default() };
+    let mut queue = MiningQueue::with_policy(policy);
+    assert_eq!(queue.push_at("low".to_owned(), Priority::Low, 0, 0), Enqueued::Rejected);
+    assert_eq!(queue.push_at("aaaa".to_owned(), Priority::Normal, 0, 0), Enqueued::Added(1));
+    assert_eq!(queue.push_at("bbbb".to_owned(), Priority::Normal, 0, 30_000), Enqueued::Added(2));
+    // Beyond the size cap the jobs mined last are dropped, which may be the new one
+    assert_eq!(queue.push_at("cccc".to_owned(), Priority::Normal, 0, 30_000), Enqueued::Rejected);
+    assert_eq!(queue.push_at("dddd".to_owned(), Priority::High, 0, 30_000), Enqueued::Added(4));
+    assert_eq!(data(&queue), vec!["dddd", "aaaa"]);
+    assert_eq!(queue.size(), 8);
+
+    let expired = queue.expire(60_001);
+    assert_eq!(expired.iter().map(|job| job.id).collect::<Vec<u64>>(), vec![1]);
+    assert_eq!(data(&queue), vec!["dddd"]);
+    assert!(queue.expire(60_001).is_empty());
+}
+
+#[test]
+fn test_mempool_caps() {
+    // The jobs mined last make room, a new job that would be mined last is rejected
+    let mut queue = MiningQueue::with_policy(MempoolPolicy { max_jobs: Some(2), ..MempoolPolicy::default() });
+    assert_eq!(queue.push_at("a".to_owned(), Priority::Normal, 0, 0), Enqueued::Added(1));
+    assert_eq!(queue.push_at("b".to_owned(), Priority::Low, 0, 1), Enqueued::Added(2));
+    assert_eq!(queue.push_at("c".to_owned(), Priority::High, 0, 2), Enqueued::Added(3));
+    assert_eq!(queue.push_at("d".to_owned(), Priority::Low, 0, 3), Enqueued::Rejected);
+    assert_eq!(data(&queue), vec!["c", "a"]);
+    assert_eq!(
+        queue.occupancy(),
+        MempoolOccupancy { jobs: 2, max_jobs: Some(2), bytes: 2, max_bytes: None, evicted: 2 }
+    );
+    assert_eq!(queue.occupancy().to_string(), "jobs: 2 of 2 | size: 2 of unlimited bytes | evicted: 2");
+
+    // The jobs queued first make room
+    let policy = MempoolPolicy { max_jobs: Some(2), max_bytes: Some(6), eviction: Eviction::Oldest, ..MempoolPolicy::default() };
+    let mut queue = MiningQueue::with_policy(policy);
+    assert_eq!(queue.push_at("aa".to_owned(), Priority::High, 0, 0), Enqueued::Added(1));
+    assert_eq!(queue.push_at("bb".to_owned(), Priority::Low, 0, 1), Enqueued::Added(2));
+    assert_eq!(queue.push_at("cc".to_owned(), Priority::Low, 0, 2), Enqueued::Added(3));
+    assert_eq!(data(&queue), vec!["bb", "cc"]);
+    assert_eq!(queue.push_at("dddd".to_owned(), Priority::Low, 0, 3), Enqueued::Added(4));
+    assert_eq!(data(&queue), vec!["cc", "dddd"]);
+    // Larger than the size cap, nothing is evicted for it
+    assert_eq!(queue.push_at("eeeeeee".to_owned(), Priority::High, 0, 4), Enqueued::Rejected);
+    assert_eq!(data(&queue), vec!["cc", "dddd"]);
+    assert_eq!(queue.occupancy().evicted, 2);
+
+    // A tighter policy is applied to the queued jobs
+    assert_eq!(queue.set_policy(MempoolPolicy { max_jobs: Some(1), ..policy }, 4), 1);
+    assert_eq!(data(&queue), vec!["dddd"]);
+    assert_eq!("oldest".parse::<Eviction>().unwrap(), Eviction::Oldest);
+    assert_eq!(Eviction::LowestFee.to_string(), "lowest-fee");
+    assert!("newest".parse::<Eviction>().is_err());
+}
+
+#[test]
+fn test_mempool_survives_restart() {
+    let path = env::temp_dir().join("rust_blockchain_mempool_test.json");
+    let _ = fs::remove_file(&path);
+    let mut queue = MiningQueue::open(&path, MempoolPolicy::default(), 0).unwrap();
+    assert!(queue.is_empty());
+    queue.push_at("old".to_owned(), Priority::Low, 0, 0);
+    queue.push_at("mined".to_owned(), Priority::High, 0, 1_000);
+    queue.push_at("new".to_owned(), Priority::Normal, 0, 100_000);
+    queue.push_at("large".to_owned(), Priority::Low, 0, 100_000);
+    assert_eq!(queue.pop().map(|job| job.data), Some("mined".to_owned()));
+    drop(queue);
+
+    let restored = MiningQueue::open(&path, MempoolPolicy::default(), 100_000).unwrap();
+    assert_eq!(data(&restored), vec!["new", "old", "large"]);
+    assert_eq!(restored.jobs()[0].added_at, 100_000);
+
+    // The policy is enforced on the restored jobs, and new jobs get new IDs
+    let policy = MempoolPolicy { max_age: Some(Duration::from_secs(60)), max_bytes: Some(3), ..MempoolPolicy::default() };
+    let restored = MiningQueue::open(&path, policy, 100_000).unwrap();
+    assert_eq!(data(&restored), vec!["new"]);
+    let policy = MempoolPolicy { min_priority: Priority::High, ..MempoolPolicy::default() };
+    let mut restored = MiningQueue::open(&path, policy, 100_000).unwrap();
+    assert!(restored.is_empty());
+    assert_eq!(restored.push_at("n".to_owned(), Priority::High, 0, 100_000), Enqueued::Added(5));
+    assert_eq!(data(&MiningQueue::open(&path, MempoolPolicy::default(), 100_000).unwrap()), vec!["n"]);
+
+    fs::write(&path, "not json").unwrap();
+    assert!(MiningQueue::open(&path, MempoolPolicy::default(), 0).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_replace_by_fee() {
+    let (old, new, newer) = (ed25519::Keypair::generate(), ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let (rotation, conflicting) = (KeyRotation::new(&old, &new).to_block_data(), KeyRotation::new(&old, &newer).to_block_data());
+    let mut queue = MiningQueue::new();
+    let (sender, mut events) = broadcast::channel(10);
+    queue.set_event_sender(sender);
+    assert_eq!(queue.push_at(rotation.clone(), Priority::Normal, 100, 0), Enqueued::Added(1));
+    assert_eq!(queue.push_at("other".to_owned(), Priority::Normal, 0, 0), Enqueued::Added(2));
+
+    // The replacement has to offer at least REPLACEMENT_FEE_INCREASE percent more
+    assert_eq!(queue.push_at(conflicting.clone(), Priority::High, 109, 0), Enqueued::Conflicting(1));
+    assert_eq!(queue.push_at(conflicting.clone(), Priority::Normal, 110, 0), Enqueued::Replaced { id: 3, replaced: 1 });
+    assert_eq!(data(&queue), vec![conflicting.as_str(), "other"]);
+    match events.try_recv() {
+        Ok(MempoolEvent::Replaced { replaced, by }) => assert_eq!((replaced.data, by.id), (rotation.clone(), 3)),
+        event => panic!("unexpected event: {:?}", event),
+    }
+    // Rotations of other keys do not conflict
+    assert_eq!(queue.push_at(KeyRotation::new(&new, &newer).to_block_data(), Priority::Normal, 0, 0), Enqueued::Added(4));
+
+    // Unpaid jobs can not be replaced for free
+    let mut queue = MiningQueue::new();
+    queue.push_at(rotation, Priority::Normal, 0, 0);
+    assert_eq!(queue.push_at(conflicting.clone(), Priority::Normal, 0, 0), Enqueued::Conflicting(1));
+    assert_eq!(queue.push_at(conflicting, Priority::Normal, 1, 0), Enqueued::Replaced { id: 2, replaced: 1 });
+}
@@ -0,0 +1,70 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::multisig::*;
+use rust_blockchain::transactions::{self, Transaction};
+
+fn key(keypair: &ed25519::Keypair) -> String {
+    hex::encode(keypair.public().encode())
+}
+
+#[test]
+fn test_multisig_policy() {
+    let keypairs = (0..3).map(|_| ed25519::Keypair::generate()).collect::<Vec<_>>();
+    let keys = keypairs.iter().map(key).collect::<Vec<_>>();
+    let policy = Multisig::new(2, keys.clone()).unwrap();
+    assert!(policy.address().starts_with(MULTISIG_ADDRESS_PREFIX));
+
+    // The order of the keys does not matter, duplicates are ignored
+    let reversed = Multisig::parse(&format!("2 {},{},{},{}", keys[2], keys[1], keys[0], keys[0])).unwrap();
+    assert_eq!(reversed, policy);
+    assert_eq!(reversed.address(), policy.address());
+    assert_ne!(Multisig::new(3, keys.clone()).unwrap().address(), policy.address());
+
+    assert!(Multisig::new(0, keys.clone()).is_err());
+    assert!(Multisig::new(4, keys.clone()).is_err());
+    assert!(Multisig::new(1, vec!["no key".to_owned()]).is_err());
+    assert!(Multisig::parse("two keys").is_err());
+    let unsorted = Multisig { threshold: 1, keys: vec![keys[0].clone(), keys[0].clone()] };
+    assert!(unsorted.check().is_err());
+
+    let args = format!("--multisig 2 {} --fee 3 recipient 10", keys.join(","));
+    assert_eq!(split_multisig(&args).unwrap(), (Some(policy), "--fee 3 recipient 10"));
+    assert_eq!(split_multisig("recipient 10").unwrap(), (None, "recipient 10"));
+    assert!(split_multisig("--multisig 2").is_err());
+}
+
+#[test]
+fn test_multisig_transaction() {
+    let keypairs = (0..3).map(|_| ed25519::Keypair::generate()).collect::<Vec<_>>();
+    let policy = Multisig::new(2, keypairs.iter().map(key).collect()).unwrap();
+    let mut transaction = Transaction::new_multisig(policy.clone(), "recipient".to_owned(), 100, 1);
+    assert_eq!(transaction.sender, policy.address());
+    let id = transaction.id();
+    assert!(transaction.sign(&keypairs[0]).is_err());
+    assert!(transaction.sign_partial(&ed25519::Keypair::generate()).is_err());
+
+    // Two owners sign copies in parallel, signing twice counts once
+    let mut copy = transaction.clone();
+    assert_eq!(transaction.sign_partial(&keypairs[0]).unwrap(), 1);
+    assert_eq!(transaction.sign_partial(&keypairs[0]).unwrap(), 1);
+    assert!(!transaction.is_signed());
+    assert!(transaction.verify().is_err());
+    assert_eq!(copy.sign_partial(&keypairs[2]).unwrap(), 1);
+    transaction.combine(&copy).unwrap();
+    assert!(transaction.is_signed());
+    transaction.verify().unwrap();
+    assert_eq!(transaction.id(), id);
+    transactions::check_block_data(&transaction.to_block_data()).unwrap();
+    assert!(transaction.combine(&Transaction::new_multisig(policy.clone(), "recipient".to_owned(), 100, 1)).is_err());
+
+    // The policy can not be swapped for one the signers do not belong to
+    let other = Multisig::new(1, vec![key(&ed25519::Keypair::generate())]).unwrap();
+    let swapped = Transaction { multisig: Some(other.clone()), ..transaction.clone() };
+    assert!(swapped.verify().is_err());
+    let swapped = Transaction { sender: other.address(), multisig: Some(other), ..transaction.clone() };
+    assert!(swapped.verify().is_err());
+    let lowered = Transaction { multisig: Some(Multisig { threshold: 1, ..policy }), ..transaction.clone() };
+    assert!(lowered.verify().is_err());
+    let mut forged = transaction;
+    forged.signatures[1].signature = forged.signatures[0].signature.clone();
+    assert!(forged.verify().is_err());
+}
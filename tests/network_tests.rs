@@ -0,0 +1,91 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::difficulty;
+use rust_blockchain::network::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+use std::env;
+use std::fs;
+
+#[test]
+fn test_network_genesis() {
+    let default = NetworkParams::default();
+    assert_eq!(default.max_reorg_depth, FINALITY_DEPTH);
+    // The default network keeps its genesis block
+    assert_eq!(default.genesis(), Block::create_genesis());
+    assert_eq!(default.protocol_version(), "/blockchain/1");
+
+    let fast = NetworkParams { target_block_time_ms: 1_000, ..NetworkParams::default() };
+    let genesis = fast.genesis();
+    assert_eq!(genesis.id, Height::GENESIS);
+    assert_eq!(genesis.hash, hasher(&genesis));
+    assert!(genesis.data.contains(&fast.digest()));
+    assert_ne!(genesis.hash, Block::create_genesis().hash);
+    assert_eq!(fast.protocol_version(), format!("/blockchain/1/{}", genesis.hash));
+    // Every parameter is part of the genesis block
+    let scarce = NetworkParams { max_supply: 1_000, ..NetworkParams::default() };
+    let shallow = NetworkParams { max_reorg_depth: 3, ..NetworkParams::default() };
+    assert_ne!(scarce.genesis().hash, genesis.hash);
+    assert_ne!(shallow.genesis().hash, scarce.genesis().hash);
+    assert_eq!(genesis, fast.genesis());
+}
+
+#[test]
+fn test_load_network() {
+    let path = env::temp_dir().join("rust_blockchain_network_test.json");
+    fs::write(&path, r#"{"target_block_time_ms": 2000, "max_reorg_depth": 10}"#).unwrap();
+    let params = NetworkParams::load(&path).unwrap();
+    assert_eq!(params, NetworkParams { target_block_time_ms: 2_000, max_reorg_depth: 10, ..NetworkParams::default() });
+
+    for invalid in [
+        r#"{"target_block_time": 2000}"#,
+        r#"{"target_block_time_ms": 0}"#,
+        r#"{"halving_interval": 0}"#,
+        r#"{"max_reorg_depth": 0}"#,
+        r#"{"initial_reward": 100, "max_supply": 50}"#,
+        "not json",
+    ] {
+        fs::write(&path, invalid).unwrap();
+        assert!(NetworkParams::load(&path).is_err(), "{}", invalid);
+    }
+    fs::remove_file(&path).unwrap();
+    assert!(NetworkParams::load(&path).is_err());
+}
+
+#[test]
+fn test_reward_schedule() {
+    let params = NetworkParams { initial_reward: 8, halving_interval: 10, max_supply: 1_000, ..NetworkParams::default() };
+    assert_eq!(params.reward_at(Height::GENESIS), 0);
+    assert_eq!(params.reward_at(Height(1)), 8);
+    assert_eq!(params.reward_at(Height(10)), 8);
+    assert_eq!(params.reward_at(Height(11)), 4);
+    assert_eq!(params.reward_at(Height(31)), 1);
+    assert_eq!(params.reward_at(Height(41)), 0);
+    assert_eq!(params.supply_at(Height(40)), 150);
+    assert_eq!(params.supply_at(Height(1_000)), 150);
+
+    // The last reward is cut at the max supply
+    let capped = NetworkParams { max_supply: 100, ..params };
+    assert_eq!(capped.reward_at(Height(12)), 4);
+    assert_eq!(capped.supply_at(Height(14)), 96);
+    assert_eq!(capped.reward_at(Height(15)), 4);
+    assert_eq!(capped.reward_at(Height(16)), 0);
+    assert_eq!(capped.supply_at(Height(u64::MAX)), 100);
+
+    let default = NetworkParams::default();
+    assert!(default.supply_at(Height(u64::MAX)) <= default.max_supply);
+}
+
+#[tokio::test]
+async fn test_chain_uses_network_params() {
+    let params = NetworkParams { max_reorg_depth: 2, ..NetworkParams::default() };
+    let mut node = Node::init_with_genesis(Storage::Memory(MemoryStorage::default()), "miner".to_owned(), params.genesis()).await.unwrap();
+    node.chain.params = params.clone();
+    node.chain.difficulty = String::new();
+    node.chain.difficulty_algorithm = difficulty::algorithm_with_block_time("fixed", params.target_block_time_ms).unwrap();
+    for i in 1..=3 {
+        node.chain.mine_block(format!("block {}", i), &mut node.storage).await.unwrap();
+    }
+    assert_eq!(node.chain.genesis, params.genesis());
+    assert_eq!(node.chain.finalized.id, Height(1));
+}
@@ -0,0 +1,78 @@
+use rust_blockchain::blockchain::{Block, Checkpoint};
+use rust_blockchain::config::Config;
+use rust_blockchain::handshake::{Features, Handshake};
+use rust_blockchain::mining::MiningQueue;
+use rust_blockchain::node::Node;
+use rust_blockchain::nodeinfo::*;
+use rust_blockchain::orphans::OrphanPool;
+use rust_blockchain::p2p::DEFAULT_LISTEN_ADDRS;
+use rust_blockchain::rpc::{answer, parse_call, RpcReply, BLOCK_NOT_FOUND};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+
+fn node_info(args: &[&str]) -> NodeInfo {
+    let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+    NodeInfo::new("peer".to_owned(), &config)
+}
+
+#[test]
+fn test_node_info() {
+    let info = node_info(&["--storage", "memory"]);
+    assert_eq!(info.peer_id, "peer");
+    assert_eq!(info.listen_addresses, DEFAULT_LISTEN_ADDRS.to_vec());
+    assert_eq!(info.genesis_hash, Block::create_genesis().hash);
+    assert_eq!(info.network, "default");
+    assert_eq!(info.storage, "memory");
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.build(), format!("{} ({})", VERSION, COMMIT));
+
+    let info = node_info(&["node_1", "--regtest", "--listen", "/ip4/127.0.0.1/tcp/4001"]);
+    assert_eq!(info.listen_addresses, vec!["/ip4/127.0.0.1/tcp/4001"]);
+    assert_eq!(info.network, "default (regtest)");
+    assert_eq!(info.storage, "postgres");
+    assert!(node_info(&["--storage", "memory", "--outbound-only"]).listen_addresses.is_empty());
+    assert!(node_info(&["--storage", "memory", "--no-p2p"]).listen_addresses.is_empty());
+
+    let banner = node_info(&["--storage", "memory"]).to_string();
+    for line in ["peer id: peer", "network: default", "storage: memory", "build: "] {
+        assert!(banner.contains(line), "{}", banner);
+    }
+}
+
+// Peers learn our node info from the handshake, RPC clients from getnodeinfo
+#[tokio::test]
+async fn test_node_info_exchange() {
+    let info = node_info(&["--storage", "memory"]);
+    let genesis = Block::create_genesis();
+    let mut handshake = Handshake::new(genesis.hash.clone(), Checkpoint::genesis(), Features::default());
+    handshake.node = info.clone();
+    let json = serde_json::to_string(&handshake).unwrap();
+    assert_eq!(serde_json::from_str::<Handshake>(&json).unwrap().node, info);
+
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let call = parse_call(r#"{"jsonrpc": "2.0", "method": "getnodeinfo", "id": 1}"#).unwrap();
+    match answer(&mut storage, &info, &MiningQueue::new(), &OrphanPool::default(), call).await {
+        RpcReply::Single(response) => assert_eq!(response.result, Some(serde_json::to_value(&info).unwrap())),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+}
+
+// gethealth is our /healthz: ok with our tip and node info, an error without a latest block
+#[tokio::test]
+async fn test_health() {
+    let info = node_info(&["--storage", "memory"]);
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "peer".to_owned()).await.unwrap();
+    let latest = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    let call = parse_call(r#"{"jsonrpc": "2.0", "method": "gethealth", "id": 1}"#).unwrap();
+    let health = match answer(&mut node.storage, &info, &MiningQueue::new(), &OrphanPool::default(), call.clone()).await {
+        RpcReply::Single(response) => serde_json::from_value::<Health>(response.result.unwrap()).unwrap(),
+        reply => panic!("unexpected reply {:?}", reply),
+    };
+    assert_eq!(health, Health::new(&info, &latest));
+    assert_eq!((health.status.as_str(), health.height, health.node.peer_id.as_str()), ("ok", latest.id, "peer"));
+
+    let mut empty = Storage::Memory(MemoryStorage::default());
+    match answer(&mut empty, &info, &MiningQueue::new(), &OrphanPool::default(), call).await {
+        RpcReply::Single(response) => assert_eq!(response.error.map(|error| error.code), Some(BLOCK_NOT_FOUND)),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+}
@@ -0,0 +1,76 @@
+use rust_blockchain::blockchain::Block;
+use rust_blockchain::config::Config;
+use rust_blockchain::node::Node;
+use rust_blockchain::orphans::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{EventType, Height};
+use std::time::Instant;
+
+fn args(args: &[&str]) -> impl Iterator<Item = String> {
+    args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().into_iter()
+}
+
+fn orphan(prev_hash: &str, data: &str) -> Block {
+    let mut block = Block::new(&Block::create_genesis(), data.to_owned(), String::new());
+    block.prev_hash = prev_hash.to_owned();
+    block.hash = format!("{}-{}", prev_hash, data);
+    block
+}
+
+#[test]
+fn test_orphan_pool() {
+    let mut pool = OrphanPool::new(2, 10);
+    pool.insert(orphan("a", "1234")).unwrap();
+    pool.insert(orphan("a", "1234")).unwrap();
+    assert_eq!(pool.occupancy(), OrphanOccupancy { blocks: 1, max_blocks: 2, bytes: 4, max_bytes: 10, evicted: 0 });
+
+    // The oldest orphans make room, by count and by size
+    pool.insert(orphan("b", "12")).unwrap();
+    pool.insert(orphan("c", "12")).unwrap();
+    assert_eq!(pool.occupancy().evicted, 1);
+    assert!(pool.take_children("a").is_empty());
+    pool.insert(orphan("d", "123456789")).unwrap();
+    assert_eq!((pool.len(), pool.occupancy().bytes, pool.occupancy().evicted), (1, 9, 3));
+    // An orphan larger than the cap is rejected without evicting anything
+    assert!(pool.insert(orphan("e", "12345678901")).is_err());
+    assert_eq!(pool.len(), 1);
+
+    let children = pool.take_children("d");
+    assert_eq!(children.len(), 1);
+    assert!(pool.is_empty());
+    assert_eq!(pool.occupancy().bytes, 0);
+
+    pool.insert(orphan("f", "1")).unwrap();
+    pool.prune(Height(1));
+    assert!(pool.is_empty());
+    assert_eq!(pool.occupancy().to_string(), "orphans: 0 of 2 | size: 0 of 10 bytes | evicted: 3");
+}
+
+#[test]
+fn test_orphan_caps() {
+    let config = Config::from_args(args(&["node_1"])).unwrap();
+    assert_eq!((config.max_orphans, config.max_orphan_bytes), (DEFAULT_MAX_ORPHANS, DEFAULT_MAX_ORPHAN_BYTES));
+    let config = Config::from_args(args(&["node_1", "--max-orphans", "5", "--max-orphan-bytes", "1000"])).unwrap();
+    assert_eq!((config.max_orphans, config.max_orphan_bytes), (5, 1_000));
+    assert!(Config::from_args(args(&["node_1", "--max-orphans", "0"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--max-orphan-bytes", "many"])).is_err());
+}
+
+// A block that overtook its parent is added once the parent arrives
+#[tokio::test]
+async fn test_orphan_is_connected() {
+    let now = Instant::now();
+    let mut miner = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let mut peer = Node::init(Storage::Memory(MemoryStorage::default()), "peer".to_owned()).await.unwrap();
+    let parent = miner.chain.mine_block("parent".to_owned(), &mut miner.storage).await.unwrap();
+    let child = miner.chain.mine_block("child".to_owned(), &mut miner.storage).await.unwrap();
+
+    let received = EventType::ReceivedNewBlock { sender: "miner".to_owned(), block: child.clone() };
+    assert!(peer.handle_event(received, now).await.iter().any(|event| matches!(event, EventType::SendChainRequest { .. })));
+    assert_eq!(peer.orphans.len(), 1);
+    assert_eq!(peer.chain.latest_block.id, Height::GENESIS);
+
+    peer.handle_event(EventType::ReceivedNewBlock { sender: "miner".to_owned(), block: parent }, now).await;
+    assert_eq!(peer.chain.latest_block, child);
+    assert!(peer.orphans.is_empty());
+}
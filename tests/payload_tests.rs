@@ -0,0 +1,30 @@
+use rust_blockchain::payload::*;
+use x25519_dalek::PublicKey;
+
+#[test]
+fn test_encrypted_payload() {
+    let (alice, bob, eve) = (generate_secret(), generate_secret(), generate_secret());
+    let data = encrypt_payload("private data", &[PublicKey::from(&alice), PublicKey::from(&bob)]).unwrap();
+    assert!(is_encrypted(&data));
+    assert!(!data.contains("private data"));
+
+    assert_eq!(decrypt_payload(&data, &alice).unwrap().unwrap(), "private data");
+    assert_eq!(decrypt_payload(&data, &bob).unwrap().unwrap(), "private data");
+    assert!(decrypt_payload(&data, &eve).unwrap().is_err());
+    assert!(decrypt_payload("public data", &alice).is_none());
+    assert!(encrypt_payload("private data", &[]).is_err());
+
+    // Payloads are authenticated
+    let mut payload: EncryptedPayload = serde_json::from_str(data.strip_prefix(ENCRYPTED_PREFIX).unwrap()).unwrap();
+    payload.ciphertext.replace_range(0..2, if payload.ciphertext.starts_with("00") { "01" } else { "00" });
+    let tampered = format!("{}{}", ENCRYPTED_PREFIX, serde_json::to_string(&payload).unwrap());
+    assert!(decrypt_payload(&tampered, &alice).unwrap().is_err());
+}
+
+#[test]
+fn test_public_keys() {
+    let key = PublicKey::from(&generate_secret());
+    assert_eq!(decode_public_key(&encode_public_key(&key)).unwrap(), key);
+    assert!(decode_public_key("abcd").is_err());
+    assert!(decode_public_key("not hex").is_err());
+}
@@ -0,0 +1,167 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::difficulty::meets_difficulty;
+use rust_blockchain::node::Node;
+use rust_blockchain::pool::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{EventType, Nonce};
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+// With legacy hash encoding about one in 256 hashes starts with the regtest prefix
+const SHARE_DIFFICULTY: u64 = 16;
+
+fn template() -> Block {
+    let mut template = Block::template(&Block::create_genesis(), "pool block".to_owned(), "coordinator".to_owned(), 0);
+    // Fixed, so the same nonces are found in every run
+    template.timestamp = 1_000;
+    template
+}
+
+// The shares of the job up to and including the first one that completes the block
+fn shares_until_block(job: &PoolJob) -> Vec<PoolShare> {
+    let mut shares = vec![];
+    search_shares(job, &AtomicBool::new(false), |share| {
+        shares.push(share);
+        let digest = digest_with_nonce(&job.template, share.nonce);
+        !meets_target(&job.template, &digest, &job.prefix)
+    });
+    shares
+}
+
+#[test]
+fn test_split_nonces() {
+    assert!(split_nonces(0).is_empty());
+    assert_eq!(split_nonces(1), vec![(Nonce(0), Nonce::MAX)]);
+
+    let ranges = split_nonces(3);
+    assert_eq!(ranges.len(), 3);
+    assert_eq!(ranges[0].0, Nonce(0));
+    assert_eq!(ranges[2].1, Nonce::MAX);
+    for pair in ranges.windows(2) {
+        assert!(pair[0].0 < pair[0].1);
+        assert_eq!(pair[0].1, pair[1].0);
+    }
+}
+
+#[test]
+fn test_coordinator_jobs() {
+    let mut pool = Coordinator::new("pool block".to_owned(), SHARE_DIFFICULTY);
+    // Without a template there is nothing to work on yet
+    assert!(pool.add_worker("worker a").is_empty());
+
+    let jobs = pool.set_template(template(), REGTEST_DIFFICULTY);
+    assert_eq!(jobs.len(), 1);
+    assert_eq!((jobs[0].1.nonce_start, jobs[0].1.nonce_end), (Nonce(0), Nonce::MAX));
+
+    // The ranges are split again with a new extra nonce when a worker joins
+    let jobs = pool.add_worker("worker b");
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0].1.job_id, jobs[1].1.job_id);
+    assert_eq!(jobs[0].1.nonce_end, jobs[1].1.nonce_start);
+    assert_eq!(jobs[0].1.template.extra_nonce, Nonce(1));
+    assert_eq!(jobs[0].1.share_difficulty, SHARE_DIFFICULTY);
+
+    let jobs = pool.remove_worker("worker a");
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].0, "worker b");
+    assert!(pool.remove_worker("worker a").is_empty());
+
+    // Shares never have to be harder than the block
+    let mut hard_template = template();
+    hard_template.difficulty = 4;
+    assert_eq!(pool.set_template(hard_template, REGTEST_DIFFICULTY)[0].1.share_difficulty, 4);
+}
+
+#[test]
+fn test_coordinator_shares() {
+    let mut pool = Coordinator::new("pool block".to_owned(), SHARE_DIFFICULTY);
+    pool.add_worker("worker a");
+    pool.add_worker("worker b");
+    let jobs = pool.set_template(template(), REGTEST_DIFFICULTY);
+    let (job_a, job_b) = (&jobs[0].1, &jobs[1].1);
+
+    let shares = shares_until_block(job_a);
+    let (last, accepted) = shares.split_last().unwrap();
+    for share in accepted {
+        assert_eq!(pool.submit_share("worker a", *share), ShareOutcome::Accepted);
+    }
+    assert!(matches!(pool.submit_share("worker a", accepted[0]), ShareOutcome::Rejected(_)));
+    // Shares have to be in the worker's own range and meet the share difficulty
+    assert!(matches!(pool.submit_share("worker b", accepted[0]), ShareOutcome::Rejected(_)));
+    let bad_nonce = (job_b.nonce_start.0..)
+        .map(Nonce)
+        .find(|nonce| !meets_difficulty(&digest_with_nonce(&job_b.template, *nonce), SHARE_DIFFICULTY))
+        .unwrap();
+    assert!(matches!(pool.submit_share("worker b", PoolShare { job_id: job_b.job_id, nonce: bad_nonce }), ShareOutcome::Rejected(_)));
+    assert!(matches!(pool.submit_share("worker c", *last), ShareOutcome::Rejected(_)));
+    assert!(matches!(pool.submit_share("worker a", PoolShare { job_id: 0, nonce: last.nonce }), ShareOutcome::Rejected(_)));
+
+    let block = match pool.submit_share("worker a", *last) {
+        ShareOutcome::BlockFound(block) => block,
+        outcome => panic!("expected a block, got {:?}", outcome),
+    };
+    assert_eq!(block.nonce, last.nonce);
+    assert_eq!(block.hash, hasher(&block));
+    assert!(block.hash.starts_with(REGTEST_DIFFICULTY));
+    // The template is used up
+    assert!(pool.template().is_none());
+    assert!(matches!(pool.submit_share("worker a", accepted[0]), ShareOutcome::Rejected(_)));
+
+    let stats = &pool.workers()["worker a"];
+    assert_eq!(stats.accepted, accepted.len() as u64 + 1);
+    assert_eq!(stats.blocks, 1);
+    assert_eq!(stats.rejected, 3);
+    assert_eq!(pool.workers()["worker b"].rejected, 2);
+}
+
+#[tokio::test]
+async fn test_pool_mines_block() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "coordinator".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    node.pool = Some(Coordinator::new("pool block".to_owned(), SHARE_DIFFICULTY));
+
+    let subscribe = EventType::ReceivedPoolMessage { sender: "worker".to_owned(), message: PoolMessage::Subscribe };
+    let job = match node.handle_event(subscribe, Instant::now()).await.as_slice() {
+        [EventType::SendPoolMessage { receiver, message: PoolMessage::Job(job) }] if receiver == "worker" => job.clone(),
+        outgoing => panic!("expected a job, got {:?}", outgoing),
+    };
+    assert_eq!(job.template.prev_hash, node.chain.latest_block.hash);
+
+    // The worker searches on its own thread until its shares complete the block
+    let (share_sender, mut share_rcv) = mpsc::unbounded_channel();
+    let mut worker = PoolWorker::new("coordinator".to_owned());
+    worker.start_job(job.clone(), share_sender);
+    let outgoing = loop {
+        let share = share_rcv.recv().await.unwrap();
+        let event = EventType::ReceivedPoolMessage { sender: "worker".to_owned(), message: PoolMessage::Share(share) };
+        let mut outgoing = node.handle_event(event, Instant::now()).await;
+        // Every share is answered
+        match outgoing.remove(0) {
+            EventType::SendPoolMessage { receiver, message: PoolMessage::Accepted(accepted) } if receiver == "worker" => {
+                assert_eq!(accepted, share)
+            }
+            event => panic!("expected the share to be accepted, got {:?}", event),
+        }
+        if !outgoing.is_empty() {
+            break outgoing;
+        }
+    };
+    worker.stop();
+
+    let block = match &outgoing[0] {
+        EventType::SendNewBlock(block) => block.clone(),
+        event => panic!("expected the pool block, got {:?}", event),
+    };
+    assert_eq!(node.chain.latest_block, block);
+    assert!(matches!(Chain::check_if_block_valid(&mut node.storage, &node.chain.params, &block).await, Ok(())));
+    // The worker gets a job for the next block right away
+    match &outgoing[1] {
+        EventType::SendPoolMessage { message: PoolMessage::Job(next), .. } => {
+            assert_eq!(next.template.prev_hash, block.hash);
+            assert!(next.job_id > job.job_id);
+        }
+        event => panic!("expected a new job, got {:?}", event),
+    }
+    assert_eq!(node.pool.as_ref().unwrap().workers()["worker"].blocks, 1);
+}
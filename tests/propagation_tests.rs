@@ -0,0 +1,75 @@
+use rust_blockchain::node::Node;
+use rust_blockchain::propagation::*;
+use rust_blockchain::simulation::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{EventType, Height};
+use std::time::Instant;
+
+#[test]
+fn test_propagation_from_name() {
+    assert_eq!(Propagation::default(), Propagation::Full);
+    for name in PROPAGATION_POLICIES {
+        assert!(Propagation::from_name(name).is_some());
+    }
+    assert_eq!(Propagation::from_name("announce"), Some(Propagation::Announce));
+    assert_eq!(Propagation::from_name("flood"), None);
+}
+
+#[tokio::test]
+async fn test_announced_block_is_fetched() {
+    let now = Instant::now();
+    let mut miner = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let mut peer = Node::init(Storage::Memory(MemoryStorage::default()), "peer".to_owned()).await.unwrap();
+    let block = miner.chain.mine_block("announced".to_owned(), &mut miner.storage).await.unwrap();
+    let announcement = BlockAnnouncement::of(&block);
+    assert_eq!((announcement.id, announcement.prev_hash.as_str()), (Height(1), peer.chain.genesis.hash.as_str()));
+
+    // Unknown blocks are requested from the announcing peer
+    let announced = EventType::ReceivedBlockAnnouncement { sender: "miner".to_owned(), announcement: announcement.clone() };
+    let hash = match peer.handle_event(announced.clone(), now).await.as_slice() {
+        [EventType::SendBlockRequest { receiver, hash }] if receiver == "miner" => hash.clone(),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let request = EventType::ReceivedBlockRequest { receiver: "peer".to_owned(), hash };
+    let body = match miner.handle_event(request, now).await.as_slice() {
+        [EventType::SendBlock { receiver, block }] if receiver == "peer" => block.clone(),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    assert_eq!(body, block);
+    peer.handle_event(EventType::ReceivedNewBlock { sender: "miner".to_owned(), block: body }, now).await;
+    assert_eq!(peer.chain.latest_block, block);
+
+    // Known blocks are not
+    assert!(peer.handle_event(announced, now).await.is_empty());
+    // Blocks we do not have are not sent
+    let request = EventType::ReceivedBlockRequest { receiver: "peer".to_owned(), hash: "unknown".to_owned() };
+    assert!(miner.handle_event(request, now).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_orphan_announcement_requests_chain() {
+    let now = Instant::now();
+    let mut miner = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let mut peer = Node::init(Storage::Memory(MemoryStorage::default()), "peer".to_owned()).await.unwrap();
+    miner.chain.mine_block("missed".to_owned(), &mut miner.storage).await.unwrap();
+    let block = miner.chain.mine_block("announced".to_owned(), &mut miner.storage).await.unwrap();
+
+    let announced = EventType::ReceivedBlockAnnouncement { sender: "miner".to_owned(), announcement: BlockAnnouncement::of(&block) };
+    match peer.handle_event(announced, now).await.as_slice() {
+        [EventType::SendChainRequest { receiver, .. }] if receiver == "miner" => {}
+        events => panic!("unexpected events: {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn test_simulation_with_announcements() {
+    let mut simulation = Simulation::new(3, ChaosConfig::default(), 0).await.unwrap();
+    simulation.propagation = Propagation::Announce;
+
+    for round in 0..3 {
+        simulation.mine(round % 3, &format!("block {}", round)).await.unwrap();
+        assert!(simulation.run_until_idle(10).await);
+        assert!(simulation.converged());
+    }
+    assert_eq!(simulation.nodes[2].chain.latest_block.id, Height(3));
+}
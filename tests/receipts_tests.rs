@@ -0,0 +1,76 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::anchor::Anchor;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::keys::KeyRotation;
+use rust_blockchain::multisig::Multisig;
+use rust_blockchain::node::Node;
+use rust_blockchain::receipts::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::Transaction;
+
+fn block(data: String) -> Block {
+    Block::new(&Block::create_genesis(), data, "miner".to_owned())
+}
+
+#[test]
+fn test_execute_payloads() {
+    let sender = ed25519::Keypair::generate();
+    let mut transaction = Transaction::new(&sender.public(), "recipient".to_owned(), 10, 1);
+    transaction.sign(&sender).unwrap();
+    let paying = block(transaction.to_block_data());
+    let receipt = execute(&paying).unwrap();
+    assert_eq!(receipt.outcome, Outcome::Success);
+    assert_eq!((receipt.block_hash.as_str(), receipt.height), (paying.hash.as_str(), paying.id));
+    assert_eq!(receipt.gas_used, paying.data.len() as u64 * GAS_PER_BYTE + GAS_PER_SIGNATURE);
+    assert_eq!(
+        receipt.events,
+        vec![ReceiptEvent::Transfer { txid: transaction.id(), sender: transaction.sender.clone(), recipient: "recipient".to_owned(), amount: 10, fee: 1 }]
+    );
+    assert_eq!(Receipt::from_json(&receipt.to_json()).unwrap(), receipt);
+
+    // Invalid payloads fail, and are charged for
+    let forged = block(Transaction { amount: 1_000, ..transaction }.to_block_data());
+    let receipt = execute(&forged).unwrap();
+    assert!(matches!(receipt.outcome, Outcome::Failure(_)));
+    assert!(receipt.events.is_empty());
+    assert_eq!(receipt.gas_used, forged.data.len() as u64 + GAS_PER_SIGNATURE);
+    assert!(matches!(execute(&block("tx {".to_owned())).unwrap().outcome, Outcome::Failure(_)));
+    assert!(matches!(execute(&block("anchor {}".to_owned())).unwrap().outcome, Outcome::Failure(_)));
+
+    let owners = (0..2).map(|_| ed25519::Keypair::generate()).collect::<Vec<_>>();
+    let policy = Multisig::new(2, owners.iter().map(|owner| hex::encode(owner.public().encode())).collect()).unwrap();
+    let mut shared = Transaction::new_multisig(policy, "recipient".to_owned(), 5, 0);
+    for owner in owners.iter() {
+        shared.sign_partial(owner).unwrap();
+    }
+    let receipt = execute(&block(shared.to_block_data())).unwrap();
+    assert_eq!((receipt.outcome, receipt.gas_used), (Outcome::Success, shared.to_block_data().len() as u64 + 2 * GAS_PER_SIGNATURE));
+
+    let rotation = KeyRotation::new(&sender, &ed25519::Keypair::generate());
+    let receipt = execute(&block(rotation.to_block_data())).unwrap();
+    assert_eq!(receipt.events, vec![ReceiptEvent::KeyRotated { old_key: rotation.old_key, new_key: rotation.new_key }]);
+
+    let anchor = Anchor::new(vec!["a".repeat(64), "b".repeat(64)]).unwrap();
+    let receipt = execute(&block(anchor.to_block_data())).unwrap();
+    assert_eq!(receipt.events, vec![ReceiptEvent::Anchored { root: anchor.root, digests: 2 }]);
+
+    // Other data changes no state
+    assert!(execute(&block("data".to_owned())).is_none());
+}
+
+#[tokio::test]
+async fn test_receipts_of_main_chain() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let sender = ed25519::Keypair::generate();
+    let mut transaction = Transaction::new(&sender.public(), "recipient".to_owned(), 10, 0);
+    transaction.sign(&sender).unwrap();
+    let paying = node.chain.mine_block(transaction.to_block_data(), &mut node.storage).await.unwrap();
+    let plain = node.chain.mine_block("data".to_owned(), &mut node.storage).await.unwrap();
+
+    let receipt = Chain::get_receipt(&mut node.storage, &paying.hash).await.unwrap();
+    assert_eq!(receipt, execute(&paying));
+    assert_eq!(receipt.unwrap().outcome, Outcome::Success);
+    assert_eq!(Chain::get_receipt(&mut node.storage, &plain.hash).await.unwrap(), None);
+    assert_eq!(Chain::get_receipt(&mut node.storage, "unknown").await.unwrap(), None);
+}
@@ -0,0 +1,77 @@
+use rust_blockchain::blockchain::{mining_threads, set_mining_threads};
+use rust_blockchain::config::Config;
+use rust_blockchain::mining::{MempoolPolicy, MiningQueue, Priority};
+use rust_blockchain::p2p::DEFAULT_TARGET_PEERS;
+use rust_blockchain::reload::*;
+use std::fs;
+use std::thread;
+use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+
+fn parse(args: &[&str]) -> Config {
+    Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap()
+}
+
+#[test]
+fn test_reloadable_options() {
+    let config = parse(&["--storage", "memory"]);
+    assert_eq!(config.log_level, LevelFilter::INFO);
+    assert_eq!(config.mining_threads, 0);
+    assert_eq!(config.target_peers, DEFAULT_TARGET_PEERS);
+    let config = parse(&["--storage", "memory", "--log-level", "debug", "--mining-threads", "2", "--target-peers", "12"]);
+    assert_eq!((config.log_level, config.mining_threads, config.target_peers), (LevelFilter::DEBUG, 2, 12));
+    for args in [["--log-level", "loud"], ["--mining-threads", "0"], ["--target-peers", "none"]] {
+        assert!(Config::from_args(["--storage", "memory"].iter().chain(args.iter()).map(|arg| arg.to_string())).is_err());
+    }
+
+    set_mining_threads(3);
+    assert_eq!(mining_threads(), 3);
+    set_mining_threads(0);
+    assert_eq!(mining_threads(), num_cpus::get());
+}
+
+#[test]
+fn test_config_changes() {
+    let old = parse(&["--storage", "memory"]);
+    assert_eq!(ConfigChanges::between(&old, &old), ConfigChanges::default());
+
+    let new = parse(&["--storage", "memory", "--log-level", "warn", "--target-peers", "8", "--min-fee-rate", "5", "--stale-tip-factor", "4"]);
+    let changes = ConfigChanges::between(&old, &new);
+    assert_eq!(changes.reloaded, vec!["log level", "target peers", "mempool policy", "stale tip factor"]);
+    assert!(!changes.restart_required);
+
+    // Consensus and storage settings need a restart
+    let new = parse(&["--storage", "memory", "--regtest", "--mining-threads", "1"]);
+    let changes = ConfigChanges::between(&old, &new);
+    assert_eq!(changes.reloaded, vec!["mining threads"]);
+    assert!(changes.restart_required);
+}
+
+#[test]
+fn test_mempool_policy_reload() {
+    let mut queue = MiningQueue::new();
+    queue.push_at("low".to_owned(), Priority::Low, 0, 0);
+    queue.push_at("normal".to_owned(), Priority::Normal, 0, 0);
+    queue.push_at("recent".to_owned(), Priority::High, 0, 9_000);
+    let policy = MempoolPolicy { min_priority: Priority::Normal, max_age: Some(Duration::from_secs(5)), ..MempoolPolicy::default() };
+    assert_eq!(queue.set_policy(policy, 10_000), 2);
+    assert_eq!(queue.jobs().iter().map(|job| job.data.as_str()).collect::<Vec<_>>(), vec!["recent"]);
+    assert_eq!(queue.policy(), policy);
+}
+
+#[test]
+fn test_file_watcher() {
+    let path = std::env::temp_dir().join(format!("blockchain_reload_{}.conf", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let mut watcher = FileWatcher::new(&path);
+    assert!(!watcher.changed());
+    fs::write(&path, "--log-level debug\n").unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+    // Modification times may be as coarse as a second
+    thread::sleep(Duration::from_millis(1100));
+    fs::write(&path, "--log-level warn\n").unwrap();
+    assert!(watcher.changed());
+    fs::remove_file(&path).unwrap();
+    assert!(watcher.changed());
+}
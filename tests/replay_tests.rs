@@ -0,0 +1,40 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::replay::{self, ReplayReport};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::Height;
+
+#[tokio::test]
+async fn test_replay() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("block 1".to_owned(), &mut storage).await.unwrap();
+    let _ = chain.mine_block("block 2".to_owned(), &mut storage).await.unwrap();
+
+    let mut reported = vec![];
+    let report = replay::replay(&chain, &mut storage, &mut |done, total| reported.push((done, total))).await.unwrap();
+    assert_eq!(report, ReplayReport { blocks: 3, divergence: None });
+    assert_eq!(reported, vec![(1, 3), (2, 3), (3, 3)]);
+
+    // A block changed after it was mined is the first divergent one, even with valid blocks on top
+    storage.remove_blocks_above(Height::GENESIS).await.unwrap();
+    let tampered = Block { data: "tampered".to_owned(), ..block1 };
+    let block2 = Block::new(&tampered, "block 2".to_owned(), String::new());
+    storage.insert_block(&tampered).await.unwrap();
+    storage.insert_block(&block2).await.unwrap();
+    let report = replay::replay(&chain, &mut storage, &mut |_, _| {}).await.unwrap();
+    assert_eq!(report.blocks, 2);
+    let divergence = report.divergence.unwrap();
+    assert_eq!((divergence.height, divergence.hash), (Height(1), tampered.hash));
+    assert!(divergence.reason.contains("does not replay"));
+}
+
+#[tokio::test]
+async fn test_replay_foreign_genesis() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let chain = Chain::init(&mut storage).await.unwrap();
+    let mut other = Storage::Memory(MemoryStorage::default());
+    let genesis = Block { data: "other genesis".to_owned(), ..Block::create_genesis() };
+    let _ = Chain::init_with_genesis(&mut other, genesis).await.unwrap();
+    let divergence = replay::replay(&chain, &mut other, &mut |_, _| {}).await.unwrap().divergence.unwrap();
+    assert_eq!(divergence.height, Height::GENESIS);
+}
@@ -0,0 +1,219 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::head::HeadEvent;
+use rust_blockchain::mining::MiningQueue;
+use rust_blockchain::orphans::OrphanPool;
+use rust_blockchain::node::Node;
+use rust_blockchain::nodeinfo::NodeInfo;
+use rust_blockchain::rpc::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::EventType;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time;
+
+async fn call(storage: &mut Storage, line: &str) -> RpcReply {
+    match parse_call(line) {
+        Ok(call) => answer(storage, &NodeInfo::default(), &MiningQueue::new(), &OrphanPool::default(), call).await,
+        Err(reply) => reply,
+    }
+}
+
+fn single(reply: RpcReply) -> RpcResponse {
+    match reply {
+        RpcReply::Single(response) => response,
+        RpcReply::Batch(_) => panic!("expected a single response"),
+    }
+}
+
+fn error_code(reply: RpcReply) -> i64 {
+    single(reply).error.expect("is an error").code
+}
+
+#[tokio::test]
+async fn test_rpc_methods() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let genesis = node.chain.latest_block.clone();
+    let block1 = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    let block2 = node.chain.mine_block("block 2".to_owned(), &mut node.storage).await.unwrap();
+    let storage = &mut node.storage;
+
+    let response = single(call(storage, &format!(r#"{{"jsonrpc": "2.0", "method": "getblock", "params": {{"hash": "{}"}}, "id": 1}}"#, block1.hash)).await);
+    assert_eq!(response.id, json!(1));
+    assert_eq!(response.result, Some(serde_json::to_value(&block1).unwrap()));
+    let response = single(call(storage, r#"{"jsonrpc": "2.0", "method": "getblock", "params": {"height": 2}, "id": "a"}"#).await);
+    assert_eq!(response.result, Some(serde_json::to_value(&block2).unwrap()));
+    let response = single(call(storage, r#"{"jsonrpc": "2.0", "method": "getlatestblock", "id": 2}"#).await);
+    assert_eq!(response.result, Some(serde_json::to_value(&block2).unwrap()));
+
+    // Unknown keys are null, in the order of the keys
+    let line = format!(r#"{{"jsonrpc": "2.0", "method": "getblocks", "params": {{"hashes": ["{}", "unknown", "{}"]}}, "id": 3}}"#, block2.hash, genesis.hash);
+    let response = single(call(storage, &line).await);
+    assert_eq!(response.result, Some(json!([block2, Value::Null, genesis])));
+    let response = single(call(storage, r#"{"jsonrpc": "2.0", "method": "getblocks", "params": {"heights": [1, 7]}, "id": 4}"#).await);
+    assert_eq!(response.result, Some(json!([block1, Value::Null])));
+    let response = single(call(storage, r#"{"jsonrpc": "2.0", "method": "getblockrange", "params": {"from": 1, "to": 5}, "id": 5}"#).await);
+    assert_eq!(response.result, Some(json!([block1, block2])));
+
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "2.0", "method": "getblock", "params": {"hash": "unknown"}, "id": 6}"#).await), BLOCK_NOT_FOUND);
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "2.0", "method": "getblock", "id": 7}"#).await), INVALID_PARAMS);
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "2.0", "method": "getblocks", "params": {"hashes": ["a"], "heights": [1]}, "id": 8}"#).await), INVALID_PARAMS);
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "2.0", "method": "getblockrange", "params": {"from": 0, "to": 500}, "id": 9}"#).await), INVALID_PARAMS);
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "2.0", "method": "getblock", "params": {"size": 1}, "id": 10}"#).await), INVALID_PARAMS);
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "2.0", "method": "mine", "id": 11}"#).await), METHOD_NOT_FOUND);
+    assert_eq!(error_code(call(storage, r#"{"jsonrpc": "1.0", "method": "getlatestblock", "id": 12}"#).await), INVALID_REQUEST);
+    assert_eq!(error_code(call(storage, r#"{"method": "getlatestblock""#).await), PARSE_ERROR);
+    assert_eq!(error_code(call(storage, r#"{"id": 13}"#).await), INVALID_REQUEST);
+}
+
+#[tokio::test]
+async fn test_rpc_batch() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let block1 = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    let storage = &mut node.storage;
+
+    let line = r#"[{"jsonrpc": "2.0", "method": "getblock", "params": {"height": 1}, "id": 1}, {"jsonrpc": "2.0", "method": "mine", "id": 2}]"#;
+    match call(storage, line).await {
+        RpcReply::Batch(responses) => {
+            assert_eq!(responses.iter().map(|response| response.id.clone()).collect::<Vec<_>>(), vec![json!(1), json!(2)]);
+            assert_eq!(responses[0].result, Some(serde_json::to_value(&block1).unwrap()));
+            assert_eq!(responses[1].error.as_ref().map(|error| error.code), Some(METHOD_NOT_FOUND));
+        }
+        RpcReply::Single(_) => panic!("expected a batch"),
+    }
+    assert_eq!(error_code(call(storage, "[]").await), INVALID_REQUEST);
+    let request = json!({"jsonrpc": "2.0", "method": "getlatestblock", "id": 1});
+    let too_many = Value::Array(vec![request; MAX_BATCH + 1]).to_string();
+    assert_eq!(error_code(call(storage, &too_many).await), INVALID_REQUEST);
+}
+
+#[tokio::test]
+async fn test_rpc_server() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (rpc_sender, rpc_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (head_sender, _) = broadcast::channel::<HeadEvent>(16);
+    tokio::spawn(init_rpc(listener, rpc_rcv, main_sender, head_sender));
+
+    let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // Lines that are no JSON are answered by the server itself
+    writer.write_all(b"not json\n").await.unwrap();
+    let line = time::timeout(Duration::from_secs(10), lines.next_line()).await.unwrap().unwrap().unwrap();
+    assert_eq!(error_code(serde_json::from_str(&line).unwrap()), PARSE_ERROR);
+
+    writer.write_all(b"[{\"jsonrpc\": \"2.0\", \"method\": \"getlatestblock\", \"id\": 1}]\n").await.unwrap();
+    let (sender, call) = match time::timeout(Duration::from_secs(10), main_rcv.recv()).await.unwrap() {
+        Some(EventType::ReceivedRpcCall{sender, call}) => (sender, call),
+        other => panic!("unexpected event {:?}", other),
+    };
+    assert!(sender.starts_with(RPC_PREFIX));
+    let reply = answer(&mut node.storage, &NodeInfo::default(), &node.mining_queue, &node.orphans, call).await;
+    rpc_sender.send(EventType::SendRpcReply{receiver: sender, reply: reply.clone()}).unwrap();
+    let line = time::timeout(Duration::from_secs(10), lines.next_line()).await.unwrap().unwrap().unwrap();
+    assert_eq!(serde_json::from_str::<RpcReply>(&line).unwrap(), reply);
+
+    // A line that never ends is cut off and closes the connection
+    writer.write_all(&vec![b'x'; MAX_REQUEST_LENGTH + 1]).await.unwrap();
+    let line = time::timeout(Duration::from_secs(10), lines.next_line()).await.unwrap().unwrap().unwrap();
+    assert_eq!(error_code(serde_json::from_str(&line).unwrap()), PARSE_ERROR);
+    assert_eq!(time::timeout(Duration::from_secs(10), lines.next_line()).await.unwrap().unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_read_line() {
+    let mut reader = &b"first\r\nsecond\n\nlast"[..];
+    let mut buffer = vec![];
+    for expected in ["first", "second", "", "last"] {
+        assert_eq!(read_line(&mut reader, &mut buffer, 6).await.unwrap(), Some(expected.to_owned()));
+    }
+    assert_eq!(read_line(&mut reader, &mut buffer, 6).await.unwrap(), None);
+
+    let mut reader = &b"exactly\n"[..];
+    let err = read_line(&mut reader, &mut vec![], 6).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(read_line(&mut &b"exactly\n"[..], &mut vec![], 7).await.unwrap(), Some("exactly".to_owned()));
+}
+
+#[tokio::test]
+async fn test_rpc_conditional_queries() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let block1 = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    let storage = &mut node.storage;
+
+    let line = format!(r#"{{"jsonrpc": "2.0", "method": "getlatestblock", "params": {{"if_none_match": "{}"}}, "id": 1}}"#, block1.hash);
+    assert_eq!(error_code(call(storage, &line).await), NOT_MODIFIED);
+    let line = format!(r#"{{"jsonrpc": "2.0", "method": "getblockrange", "params": {{"from": 0, "to": 1, "if_none_match": "{}"}}, "id": 2}}"#, block1.hash);
+    assert_eq!(error_code(call(storage, &line).await), NOT_MODIFIED);
+    let response = single(call(storage, r#"{"jsonrpc": "2.0", "method": "getlatestblock", "params": {"if_none_match": "old tip"}, "id": 3}"#).await);
+    assert_eq!(response.result, Some(serde_json::to_value(&block1).unwrap()));
+    // Only the tip endpoints are conditional
+    let line = format!(r#"{{"jsonrpc": "2.0", "method": "getblock", "params": {{"height": 1, "if_none_match": "{}"}}, "id": 4}}"#, block1.hash);
+    assert_eq!(single(call(storage, &line).await).result, Some(serde_json::to_value(&block1).unwrap()));
+    let line = format!(r#"{{"jsonrpc": "2.0", "method": "getlatestblock", "params": {{"if_none_match": "{}", "wait": 61}}, "id": 5}}"#, block1.hash);
+    assert_eq!(error_code(call(storage, &line).await), INVALID_PARAMS);
+}
+
+// Answers the next call like the main task
+async fn answer_next(main_rcv: &mut mpsc::UnboundedReceiver<EventType>, rpc_sender: &mpsc::UnboundedSender<EventType>, node: &mut Node) {
+    match time::timeout(Duration::from_secs(10), main_rcv.recv()).await.unwrap() {
+        Some(EventType::ReceivedRpcCall{sender, call}) => {
+            let reply = answer(&mut node.storage, &NodeInfo::default(), &node.mining_queue, &node.orphans, call).await;
+            rpc_sender.send(EventType::SendRpcReply{receiver: sender, reply}).unwrap();
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_rpc_long_poll() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (rpc_sender, rpc_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel::<EventType>();
+    let (head_sender, _) = broadcast::channel::<HeadEvent>(16);
+    tokio::spawn(init_rpc(listener, rpc_rcv, main_sender, head_sender.clone()));
+
+    let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let tip = node.chain.latest_block.hash.clone();
+    let line = format!("{{\"jsonrpc\": \"2.0\", \"method\": \"getlatestblock\", \"params\": {{\"if_none_match\": \"{}\", \"wait\": 30}}, \"id\": 1}}\n", tip);
+    writer.write_all(line.as_bytes()).await.unwrap();
+
+    // Our tip is still the one the client knows
+    answer_next(&mut main_rcv, &rpc_sender, &mut node).await;
+    // The not modified reply is held back
+    assert!(time::timeout(Duration::from_millis(200), lines.next_line()).await.is_err());
+
+    let block1 = node.chain.mine_block("block 1".to_owned(), &mut node.storage).await.unwrap();
+    head_sender.send(HeadEvent::Extended(block1.clone())).unwrap();
+    answer_next(&mut main_rcv, &rpc_sender, &mut node).await;
+    let line = time::timeout(Duration::from_secs(10), lines.next_line()).await.unwrap().unwrap().unwrap();
+    let response = single(serde_json::from_str(&line).unwrap());
+    assert_eq!(response.result, Some(serde_json::to_value(&block1).unwrap()));
+}
+
+#[tokio::test]
+async fn test_openrpc_document() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let document = openrpc();
+    let names: Vec<&str> = document["methods"].as_array().unwrap().iter().map(|method| method["name"].as_str().unwrap()).collect();
+    assert_eq!(names, METHODS.iter().map(|method| method.name).collect::<Vec<_>>());
+    // Every documented method is answered and every referenced schema exists
+    for method in METHODS {
+        let line = json!({"jsonrpc": "2.0", "method": method.name, "id": 1}).to_string();
+        assert_ne!(single(call(&mut storage, &line).await).error.map(|error| error.code), Some(METHOD_NOT_FOUND));
+        assert!(document["components"]["schemas"].get(method.result).is_some());
+    }
+    let response = single(call(&mut storage, r#"{"jsonrpc": "2.0", "method": "rpc.discover", "id": 1}"#).await);
+    assert_eq!(response.result, Some(document));
+}
@@ -0,0 +1,39 @@
+use rust_blockchain::service::*;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_systemd_unit() {
+    let unit = ServiceManager::Systemd
+        .definition(Path::new("/opt/node/rust-blockchain"), &args(&["node_1", "--data-dir", "/var/lib/my node", "--bridge", "http://notary/100%"]), Path::new("/var/lib"));
+    assert!(unit.contains("Type=notify\n"));
+    assert!(unit.contains("ExecStart=/opt/node/rust-blockchain node_1 --data-dir \"/var/lib/my node\" --bridge http://notary/100%%\n"), "{}", unit);
+    assert!(unit.contains("WorkingDirectory=/var/lib\n"));
+}
+
+#[test]
+fn test_launchd_plist() {
+    let plist = ServiceManager::Launchd
+        .definition(Path::new("/usr/local/bin/rust-blockchain"), &args(&["--storage", "memory", "--chain", "a&b"]), Path::new("/Users/node"));
+    assert!(plist.contains(&format!("<string>{}</string>", LAUNCHD_LABEL)));
+    assert!(plist.contains("        <string>/usr/local/bin/rust-blockchain</string>\n        <string>--storage</string>\n        <string>memory</string>\n"));
+    assert!(plist.contains("<string>a&amp;b</string>"));
+    assert_eq!(ServiceManager::from_name("launchd"), Some(ServiceManager::Launchd));
+    assert_eq!(ServiceManager::from_name("upstart"), None);
+}
+
+#[test]
+fn test_notify() {
+    let path = std::env::temp_dir().join(format!("blockchain_notify_{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    send_notification(path.to_str().unwrap(), "READY=1\nSTATUS=height 3").unwrap();
+    let mut buf = [0u8; 64];
+    let len = socket.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"READY=1\nSTATUS=height 3");
+    std::fs::remove_file(&path).unwrap();
+}
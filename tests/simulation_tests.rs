@@ -0,0 +1,132 @@
+use rust_blockchain::difficulty::TARGET_BLOCK_TIME_MS;
+use rust_blockchain::propagation::Propagation;
+use rust_blockchain::simulation::*;
+use rust_blockchain::staletip::DEFAULT_STALE_TIP_FACTOR;
+use rust_blockchain::sync::SYNC_TIMEOUT;
+use rust_blockchain::types::Height;
+
+const NODES: usize = 4;
+
+async fn assert_converged(simulation: &mut Simulation) {
+    // Heal the network and let every node compare its chain with its peers
+    simulation.network.config = ChaosConfig::default();
+    assert!(simulation.run_until_idle(100).await);
+    // Requests lost to chaos block new syncs with the same peer until they time out
+    simulation.run(SYNC_TIMEOUT.as_secs()).await;
+    for index in 0..NODES {
+        simulation.announce(index);
+    }
+    assert!(simulation.run_until_idle(100).await);
+
+    assert!(simulation.converged());
+    for node in simulation.nodes.iter_mut() {
+        assert!(matches!(node.chain.validate_chain(&mut node.storage).await, Ok(())));
+    }
+}
+
+#[tokio::test]
+async fn test_simulation_without_chaos() {
+    let mut simulation = Simulation::new(NODES, ChaosConfig::default(), 0).await.unwrap();
+
+    for round in 0..5 {
+        simulation.mine(round % NODES, &format!("block {}", round)).await.unwrap();
+        assert!(simulation.run_until_idle(10).await);
+        assert!(simulation.converged());
+    }
+    assert_eq!(simulation.nodes[NODES - 1].chain.latest_block.id, Height(5));
+    assert_eq!(simulation.network.stats.dropped, 0);
+}
+
+#[tokio::test]
+async fn test_chaos_network() {
+    let chaos = ChaosConfig {
+        drop_probability: 0.5,
+        duplicate_probability: 0.5,
+        delay_probability: 0.5,
+        max_delay: 3,
+        reorder_probability: 1.0,
+    };
+    let mut simulation = Simulation::new(NODES, chaos, 1).await.unwrap();
+    for round in 0..20 {
+        simulation.announce(round % NODES);
+        simulation.step().await;
+    }
+
+    let stats = simulation.network.stats;
+    assert!(stats.sent >= 20 * (NODES - 1));
+    assert!(stats.dropped > 0);
+    assert!(stats.duplicated > 0);
+    assert!(stats.delayed > 0);
+    assert!(stats.reordered > 0);
+}
+
+// Nodes keep mining while messages are dropped, delayed, duplicated and reordered. Missed blocks
+// have to be recovered via orphan handling and sync, so all nodes end up with the same chain.
+#[tokio::test]
+async fn test_sync_under_chaos() {
+    let chaos = ChaosConfig {
+        drop_probability: 0.2,
+        duplicate_probability: 0.2,
+        delay_probability: 0.3,
+        max_delay: 5,
+        reorder_probability: 0.5,
+    };
+    for seed in 0..3 {
+        let mut simulation = Simulation::new(NODES, chaos, seed).await.unwrap();
+        for round in 0..8 {
+            simulation.mine(round % NODES, &format!("block {}", round)).await.unwrap();
+            simulation.run(3).await;
+        }
+        // The next block on the longest chain reaches everyone, nodes that are behind or on another
+        // fork treat it as orphan and sync
+        simulation.network.config = ChaosConfig::default();
+        let longest = (0..NODES)
+            .max_by_key(|index| simulation.nodes[*index].chain.latest_block.id)
+            .unwrap();
+        let block = simulation.mine(longest, "final block").await.unwrap();
+
+        assert_converged(&mut simulation).await;
+        assert_eq!(simulation.nodes[0].chain.latest_block, block);
+    }
+}
+
+// A node that missed a block and hears of no other one notices its stale tip and asks its peers
+#[tokio::test]
+async fn test_stale_tip_recovery() {
+    let mut simulation = Simulation::new(2, ChaosConfig::default(), 0).await.unwrap();
+    simulation.announce(0);
+    simulation.announce(1);
+    assert!(simulation.run_until_idle(10).await);
+
+    simulation.network.config.drop_probability = 1.0;
+    simulation.mine(0, "lost block").await.unwrap();
+    simulation.network.config = ChaosConfig::default();
+    assert!(simulation.run_until_idle(10).await);
+    assert!(!simulation.converged());
+
+    let stale_after = TARGET_BLOCK_TIME_MS as u64 / 1000 * DEFAULT_STALE_TIP_FACTOR as u64;
+    simulation.run(stale_after + 1).await;
+    assert!(simulation.run_until_idle(10).await);
+    assert!(simulation.converged());
+    assert_eq!(simulation.nodes[1].chain.latest_block.id, Height(1));
+    assert!(simulation.nodes[1].stale_tip.stale_tips >= 1);
+}
+
+// The virtual latency benches/propagation.rs reports, from mining a block until every node has it
+#[tokio::test]
+async fn test_propagation_ticks() {
+    for (propagation, ticks) in [(Propagation::Full, 1), (Propagation::Announce, 3)] {
+        let mut simulation = Simulation::new(NODES, ChaosConfig::default(), 0).await.unwrap();
+        simulation.propagation = propagation;
+        for round in 0..3 {
+            let block = simulation.mine(round % NODES, &format!("block {}", round)).await.unwrap();
+            assert_eq!(simulation.run_until_accepted(&block, 10).await, Some(ticks), "{:?}", propagation);
+            assert!(simulation.run_until_idle(10).await);
+        }
+    }
+
+    // A partitioned node never gets the block
+    let mut simulation = Simulation::new(NODES, ChaosConfig { drop_probability: 1.0, ..ChaosConfig::default() }, 0).await.unwrap();
+    let block = simulation.mine(0, "lost").await.unwrap();
+    assert_eq!(simulation.run_until_accepted(&block, 5).await, None);
+}
@@ -0,0 +1,53 @@
+use chrono::Utc;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::mining::Priority;
+use rust_blockchain::node::Node;
+use rust_blockchain::slots::SlotSchedule;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use std::time::Duration;
+
+#[test]
+fn test_slot_schedule() {
+    let schedule = SlotSchedule::new(Duration::from_secs(10), vec!["a".to_owned(), "b".to_owned()]);
+    assert_eq!(schedule.slot_at(0), 0);
+    assert_eq!(schedule.slot_at(9_999), 0);
+    assert_eq!(schedule.slot_at(10_000), 1);
+    assert_eq!(schedule.slot_start(3), 30_000);
+    assert_eq!(schedule.proposer(0), Some("a"));
+    assert_eq!(schedule.proposer(1), Some("b"));
+    assert_eq!(schedule.proposer(2), Some("a"));
+    assert!(schedule.is_proposer(1, "b"));
+    assert!(!schedule.is_proposer(1, "a"));
+
+    // Without proposers every slot is ours
+    let schedule = SlotSchedule::new(Duration::from_secs(10), vec![]);
+    assert_eq!(schedule.proposer(1), None);
+    assert!(schedule.is_proposer(1, "a"));
+}
+
+#[tokio::test]
+async fn test_produce_slot_block() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let now = Utc::now().timestamp_millis();
+    assert!(node.produce_slot_block(now).await.unwrap().is_none());
+
+    // Long slots, so the block is produced in the slot we ask for
+    node.slots = Some(SlotSchedule::new(Duration::from_secs(3600), vec!["other".to_owned()]));
+    assert!(node.produce_slot_block(now).await.unwrap().is_none());
+
+    node.slots = Some(SlotSchedule::new(Duration::from_secs(3600), vec!["miner".to_owned()]));
+    node.mining_queue.push("queued".to_owned(), Priority::Normal);
+    let block = node.produce_slot_block(now).await.unwrap().unwrap();
+    assert_eq!(block.data, "queued");
+    assert_eq!(node.chain.latest_block, block);
+    assert!(node.mining_queue.is_empty());
+    // One block per slot
+    assert!(node.produce_slot_block(now).await.unwrap().is_none());
+
+    // Empty blocks when nothing is queued
+    let next_slot = now + 3_600_000;
+    let empty = node.produce_slot_block(next_slot).await.unwrap().unwrap();
+    assert_eq!(empty.data, "");
+    assert_eq!(empty.prev_hash, block.hash);
+}
@@ -0,0 +1,39 @@
+use rust_blockchain::config::Config;
+use rust_blockchain::staletip::*;
+use std::time::{Duration, Instant};
+
+const BLOCK_TIME_MS: i64 = 10_000;
+
+#[test]
+fn test_stale_tip() {
+    let mut monitor = StaleTipMonitor::default();
+    let threshold = monitor.threshold(BLOCK_TIME_MS);
+    assert_eq!(threshold, Duration::from_secs(30));
+    let start = Instant::now();
+
+    assert!(!monitor.check("a", BLOCK_TIME_MS, 1, start));
+    assert!(!monitor.check("a", BLOCK_TIME_MS, 1, start + threshold - Duration::from_secs(1)));
+    // Nobody to ask without peers
+    assert!(!monitor.check("a", BLOCK_TIME_MS, 0, start + threshold));
+    assert!(monitor.check("a", BLOCK_TIME_MS, 1, start + threshold));
+    assert_eq!(monitor.age(start + threshold), threshold);
+    // Asked again only after another period
+    assert!(!monitor.check("a", BLOCK_TIME_MS, 1, start + threshold * 2 - Duration::from_secs(1)));
+    assert!(monitor.check("a", BLOCK_TIME_MS, 1, start + threshold * 2));
+    assert_eq!(monitor.stale_tips, 2);
+
+    // A new tip starts over
+    let later = start + threshold * 3;
+    assert!(!monitor.check("b", BLOCK_TIME_MS, 1, later));
+    assert_eq!(monitor.age(later), Duration::ZERO);
+    assert!(!monitor.check("b", BLOCK_TIME_MS, 1, later + threshold - Duration::from_secs(1)));
+    assert!(monitor.check("b", BLOCK_TIME_MS, 1, later + threshold));
+}
+
+#[test]
+fn test_stale_tip_factor_option() {
+    let args = |args: &[&str]| Config::from_args(args.iter().map(|arg| arg.to_string()));
+    assert_eq!(args(&["--storage", "memory"]).unwrap().stale_tip_factor, DEFAULT_STALE_TIP_FACTOR);
+    assert_eq!(args(&["--storage", "memory", "--stale-tip-factor", "5"]).unwrap().stale_tip_factor, 5);
+    assert!(args(&["--storage", "memory", "--stale-tip-factor", "0"]).is_err());
+}
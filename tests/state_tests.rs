@@ -0,0 +1,154 @@
+use chrono::Utc;
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{activation_height, Feature};
+use rust_blockchain::keys::KeyRotation;
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::node::Node;
+use rust_blockchain::state::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::Transaction;
+use rust_blockchain::types::{Height, Nonce};
+
+fn block(data: String) -> Block {
+    Block::new(&Block::create_genesis(), data, "miner".to_owned())
+}
+
+fn transfer(sender: &ed25519::Keypair, recipient: &str, amount: u64, fee: u64) -> Transaction {
+    let mut transaction = Transaction::new(&sender.public(), recipient.to_owned(), amount, fee);
+    transaction.sign(sender).unwrap();
+    transaction
+}
+
+#[test]
+fn test_state() {
+    let sender = ed25519::Keypair::generate();
+    let transaction = transfer(&sender, "recipient", 10, 1);
+    let mut state = State::default();
+    assert_eq!(state.root(), EMPTY_STATE_ROOT);
+    state.apply(&block("data".to_owned()));
+    assert_eq!(state.root(), EMPTY_STATE_ROOT);
+
+    state.apply(&block(transaction.to_block_data()));
+    state.apply(&block(transfer(&sender, "recipient", 5, 0).to_block_data()));
+    assert_eq!(state.get("received/recipient"), Some("15"));
+    assert_eq!(state.get(&format!("sent/{}", transaction.sender)), Some("16"));
+    // Failed payloads change nothing
+    let root = state.root();
+    state.apply(&block(Transaction { amount: 1_000, ..transaction.clone() }.to_block_data()));
+    assert_eq!(state.root(), root);
+
+    let rotation = KeyRotation::new(&sender, &ed25519::Keypair::generate());
+    state.apply(&block(rotation.to_block_data()));
+    assert_eq!(state.get(&format!("rotated/{}", rotation.old_key)), Some(rotation.new_key.as_str()));
+    assert_ne!(state.root(), root);
+
+    let (value, _) = state.prove("received/recipient").unwrap();
+    assert_eq!(value, "15");
+    assert!(state.prove("received/unknown").is_err());
+}
+
+#[tokio::test]
+async fn test_state_proofs() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let sender = ed25519::Keypair::generate();
+    let paying = node.chain.mine_block(transfer(&sender, "recipient", 10, 0).to_block_data(), &mut node.storage).await.unwrap();
+    node.chain.mine_block(transfer(&sender, "recipient", 5, 0).to_block_data(), &mut node.storage).await.unwrap();
+    assert_eq!(paying.state_root, State::at(&mut node.storage, paying.id).await.unwrap().root());
+
+    // The value after the block at the height
+    let proof = StateProof::create(&mut node.storage, "received/recipient", paying.id).await.unwrap();
+    assert_eq!((proof.value.as_str(), proof.header.hash.as_str()), ("10", paying.hash.as_str()));
+    proof.verify().unwrap();
+    let latest = StateProof::create(&mut node.storage, "received/recipient", node.chain.latest_block.id).await.unwrap();
+    assert_eq!(latest.value, "15");
+    latest.verify().unwrap();
+
+    assert!(StateProof { value: "1000".to_owned(), ..proof.clone() }.verify().is_err());
+    let mut forged_header = proof.clone();
+    forged_header.header.state_root = EMPTY_STATE_ROOT.to_owned();
+    forged_header.root = EMPTY_STATE_ROOT.to_owned();
+    assert!(forged_header.verify().is_err());
+    assert!(StateProof::create(&mut node.storage, "received/unknown", paying.id).await.is_err());
+    // The genesis block commits to no state root
+    assert!(StateProof::create(&mut node.storage, "received/recipient", Height::GENESIS).await.is_err());
+    node.chain.validate_chain(&mut node.storage).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_state_root_validation() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let sender = ed25519::Keypair::generate();
+    let mut template = node.chain.block_template(&mut node.storage, transfer(&sender, "recipient", 10, 0).to_block_data()).await.unwrap();
+    template.state_root = EMPTY_STATE_ROOT.to_owned();
+    let wrong = Block::mine_template(template.clone(), REGTEST_DIFFICULTY, HashBackend::Cpu);
+    assert!(matches!(node.chain.add_block(&mut node.storage, wrong).await, Err(BlockchainError::BlockInvalid(_))));
+
+    template.state_root = node.chain.state_root(&mut node.storage, &template).await.unwrap();
+    let right = Block::mine_template(template, REGTEST_DIFFICULTY, HashBackend::Cpu);
+    node.chain.add_block(&mut node.storage, right).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_state_roots_activation() {
+    let height = activation_height(Feature::StateRoots);
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let parent = Block {
+        hash: "parent".to_owned(),
+        id: height - 1,
+        prev_hash: "grandparent".to_owned(),
+        timestamp: Utc::now().timestamp_millis() - 60_000,
+        nonce: Nonce(0),
+        data: String::new(),
+        version: BLOCK_VERSION,
+        miner: String::new(),
+        extra_nonce: Nonce(0),
+        difficulty: 1,
+        fee: 0,
+        state_root: EMPTY_STATE_ROOT.to_owned(),
+        coinbase: None,
+    };
+    storage.insert_block(&parent).await.unwrap();
+    let mine = |state_root: &str| {
+        let mut template = Block::template(&parent, "data".to_owned(), "miner".to_owned(), 1);
+        template.state_root = state_root.to_owned();
+        Block::mine_template(template, "", HashBackend::Cpu)
+    };
+
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &mine("")).await, Err(BlockchainError::BlockInvalid(_))));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &mine(EMPTY_STATE_ROOT)).await, Ok(())));
+}
+
+#[tokio::test]
+async fn test_account_balances() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let sender = ed25519::Keypair::generate();
+    let paying = transfer(&sender, "recipient", 10, 1);
+    chain.mine_block(paying.to_block_data(), &mut storage).await.unwrap();
+    let balance = chain.get_balance(&mut storage, "recipient").await.unwrap();
+    assert_eq!(balance, AccountBalance { received: 10, sent: 0 });
+    assert_eq!(chain.get_balance(&mut storage, &paying.sender).await.unwrap().sent, 11);
+
+    // Added blocks are applied to the derived state
+    chain.mine_block(transfer(&sender, "recipient", 5, 0).to_block_data(), &mut storage).await.unwrap();
+    assert_eq!(chain.latest_state.as_ref().map(|latest| latest.block_hash.clone()), Some(chain.latest_block.hash.clone()));
+    assert_eq!(chain.get_balance(&mut storage, "recipient").await.unwrap().available(), 15);
+    let derived = State::at(&mut storage, chain.latest_block.id).await.unwrap();
+    assert_eq!(chain.state(&mut storage).await.unwrap(), &derived);
+
+    // A longer chain paying someone else replaces ours and its state
+    let mut other_storage = Storage::Memory(MemoryStorage::default());
+    let mut other = Chain::init(&mut other_storage).await.unwrap();
+    other.difficulty = REGTEST_DIFFICULTY.to_owned();
+    for amount in [1, 2, 3] {
+        other.mine_block(transfer(&sender, "other", amount, 0).to_block_data(), &mut other_storage).await.unwrap();
+    }
+    let mut replacement = Chain::get_chain(&mut other_storage).await.unwrap();
+    chain.update(&mut storage, &mut replacement).await.unwrap();
+    assert_eq!(chain.get_balance(&mut storage, "recipient").await.unwrap(), AccountBalance::default());
+    assert_eq!(chain.get_balance(&mut storage, "other").await.unwrap().available(), 6);
+}
@@ -0,0 +1,99 @@
+use rust_blockchain::blockchain::Checkpoint;
+use rust_blockchain::handshake::{Features, Handshake};
+use rust_blockchain::mining::Priority;
+use rust_blockchain::node::Node;
+use rust_blockchain::stats::*;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::EventType;
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(0), "0s");
+    assert_eq!(format_duration(61), "1m 1s");
+    assert_eq!(format_duration(3_600), "1h 0m 0s");
+    assert_eq!(format_duration(3 * 86_400 + 4 * 3_600 + 5 * 60 + 6), "3d 4h 5m 6s");
+}
+
+#[test]
+fn test_stats_recorder() {
+    let start = Instant::now();
+    let mut recorder = StatsRecorder::new(start);
+    recorder.block_mined();
+    recorder.blocks_received(3);
+    recorder.peer_seen("peer");
+    recorder.peer_seen("peer");
+
+    let counts = recorder.take(start + Duration::from_millis(2_500));
+    assert_eq!(counts.uptime_secs, 2);
+    assert_eq!(counts.values(), vec![(UPTIME_SECS, 2), (BLOCKS_MINED, 1), (BLOCKS_RECEIVED, 3)]);
+    assert_eq!(counts.peers.len(), 1);
+
+    // The fraction of a second carries over
+    let counts = recorder.take(start + Duration::from_millis(3_000));
+    assert_eq!(counts.uptime_secs, 1);
+    assert_eq!(counts.values(), vec![(UPTIME_SECS, 1)]);
+    assert!(recorder.take(start + Duration::from_millis(3_500)).is_empty());
+
+    // Counts that were not stored are taken again
+    recorder.reorged();
+    let counts = recorder.take(start + Duration::from_secs(5));
+    recorder.put_back(counts.clone());
+    assert_eq!(recorder.take(start + Duration::from_secs(5)), counts);
+}
+
+#[tokio::test]
+async fn test_node_stats_survive_restart() {
+    let path = env::temp_dir().join("rust_blockchain_stats_test.json");
+    let _ = fs::remove_file(&path);
+    let now = Instant::now();
+
+    let mut node = Node::init(Storage::Memory(MemoryStorage::load(&path).unwrap()), "node".to_owned()).await.unwrap();
+    assert_eq!(node.storage.get_node_stats().await.unwrap(), NodeStats::default());
+    node.storage.record_start(1_000).await.unwrap();
+    node.mining_queue.push("block 1".to_owned(), Priority::Normal);
+    node.mine_next().await.unwrap();
+    let handshake = Handshake::new(node.chain.genesis.hash.clone(), Checkpoint::genesis(), Features::default());
+    node.handle_event(EventType::PeerHandshake { peer: "other".to_owned(), handshake: Some(handshake) }, now).await;
+
+    // A longer chain of another node replaces our block
+    let mut other = Node::init(Storage::Memory(MemoryStorage::default()), "other".to_owned()).await.unwrap();
+    for data in ["other 1", "other 2"] {
+        other.chain.mine_block(data.to_owned(), &mut other.storage).await.unwrap();
+    }
+    let latest = EventType::ReceivedLatestBlock { sender: "other".to_owned(), block: other.chain.latest_block.clone() };
+    let (session_id, from) = match node.handle_event(latest, now).await.as_slice() {
+        [EventType::SendChainRequest { session_id, from, .. }] => (session_id.clone(), *from),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let request = EventType::ReceivedChainRequest { receiver: "node".to_owned(), session_id: session_id.clone(), from };
+    let chain = match other.handle_event(request, now).await.pop() {
+        Some(EventType::SendChain { chain, .. }) => chain,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    node.handle_event(EventType::ReceivedChain { sender: "other".to_owned(), session_id, chain }, now).await;
+    node.watch_head().await.unwrap();
+    node.flush_stats(Instant::now()).await.unwrap();
+    node.storage.snapshot(&path).unwrap();
+
+    // A restart adds to the stored stats
+    let mut storage = Storage::Memory(MemoryStorage::load(&path).unwrap());
+    storage.record_start(2_000).await.unwrap();
+    let stats = storage.get_node_stats().await.unwrap();
+    assert_eq!(
+        stats,
+        NodeStats {
+            first_started_at: Some(1_000),
+            last_started_at: Some(2_000),
+            starts: 2,
+            uptime_secs: stats.uptime_secs,
+            blocks_mined: 1,
+            blocks_received: 1,
+            reorgs: 1,
+            peers_seen: 1,
+        }
+    );
+    let _ = fs::remove_file(&path);
+}
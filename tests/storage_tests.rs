@@ -0,0 +1,274 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::bridge::BridgeTarget;
+use rust_blockchain::config::{split_min_confirmations, Config, StorageKind};
+use rust_blockchain::deadletter::BadMessage;
+use rust_blockchain::fastsync::SnapshotTrust;
+use rust_blockchain::gc::StalePruning;
+use rust_blockchain::integrity::RepairStrategy;
+use rust_blockchain::mining::{Eviction, MempoolPolicy, Priority};
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::payload::payload_hash;
+use rust_blockchain::propagation::Propagation;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{Height, PeerScore};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn args(args: &[&str]) -> impl Iterator<Item = String> {
+    args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().into_iter()
+}
+
+#[tokio::test]
+async fn test_memory_chain() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    assert_eq!(chain.latest_block, Block::create_genesis());
+    let genesis = chain.latest_block.clone();
+
+    chain.miner = "miner a".to_owned();
+    let block1 = chain.mine_block("invoice 42 paid by alice".to_owned(), &mut storage).await.unwrap();
+    let block2 = chain.mine_block("invoice 43 paid by bob".to_owned(), &mut storage).await.unwrap();
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
+    assert_eq!(chain.confirmations(&block1), 1);
+    assert_eq!(chain.confirmations(&block2), 0);
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis.clone(), block1.clone(), block2.clone()]);
+    assert_eq!(Chain::get_block_by_id(&mut storage, Height(1)).await.unwrap(), block1);
+    assert_eq!(Chain::get_address_blocks(&mut storage, "miner a").await.unwrap().len(), 2);
+
+    let results = Chain::search_blocks(&mut storage, "invoice -bob", 10).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].block, block1);
+    assert_eq!(results[0].headline, "*invoice* 42 paid by alice");
+
+    // Competing block for the same height
+    let competing_block = Block::new(&genesis, "competing block 1".to_owned(), "miner b".to_owned());
+    assert!(matches!(chain.add_block(&mut storage, competing_block.clone()).await, Err(BlockchainError::BlockStale(_))));
+    assert_eq!(Chain::get_stale_blocks(&mut storage, 10).await.unwrap()[0].block, competing_block);
+
+    chain.update(&mut storage, &mut [genesis, competing_block.clone()]).await.unwrap();
+    assert_eq!(chain.latest_block, competing_block);
+    assert!(Chain::get_address_blocks(&mut storage, "miner a").await.unwrap().is_empty());
+    assert_eq!(Chain::get_stale_blocks(&mut storage, 10).await.unwrap().len(), 2);
+    // Payloads of stale blocks can still be looked up by their hash
+    assert_eq!(storage.get_payload(&payload_hash(&block1.data)).await.unwrap(), block1.data);
+    assert!(storage.get_payload(&payload_hash("missing")).await.is_err());
+}
+
+// Synced chains are checked block by block before anything is stored
+#[tokio::test]
+async fn test_update_rejects_invalid_chains() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let genesis = chain.latest_block.clone();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+
+    let fork1 = Block::new(&genesis, "fork block 1".to_owned(), String::new());
+    let fork2 = Block::new(&fork1, "fork block 2".to_owned(), String::new());
+    let tampered = Block { data: "tampered".to_owned(), ..fork2.clone() };
+    let unlinked = Block::new(&genesis, "fork block 2".to_owned(), String::new());
+    let unlinked = Block { id: Height(2), ..unlinked };
+    // Mined without proof of work
+    let unmined = Block::mine(&fork1, "fork block 2".to_owned(), String::new(), "", 0, HashBackend::Cpu);
+    assert!(!unmined.hash.starts_with(&chain.difficulty));
+    let other_genesis = Block { data: "other genesis".to_owned(), ..genesis.clone() };
+    for invalid in [
+        vec![genesis.clone(), fork1.clone(), tampered],
+        vec![genesis.clone(), fork1.clone(), unlinked],
+        vec![genesis.clone(), fork1.clone(), unmined],
+        vec![genesis.clone(), fork1.clone(), fork1.clone()],
+        vec![other_genesis, fork1.clone(), fork2.clone()],
+    ] {
+        assert!(matches!(chain.update(&mut storage, &mut invalid.clone()).await, Err(BlockchainError::ChainInvalid(_))));
+        assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis.clone(), block1.clone()]);
+        assert_eq!(chain.latest_block, block1);
+    }
+
+    chain.update(&mut storage, &mut [genesis.clone(), fork1.clone(), fork2.clone()]).await.unwrap();
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis.clone(), fork1.clone(), fork2.clone()]);
+
+    // A swap failing halfway leaves the stored chain as it was
+    assert!(storage.replace_chain(&[genesis.clone(), block1.clone(), block1.clone()], &[], 0).await.is_err());
+    assert_eq!(Chain::get_chain(&mut storage).await.unwrap(), vec![genesis, fork1, fork2]);
+}
+
+#[tokio::test]
+async fn test_memory_snapshot() {
+    let path = env::temp_dir().join("rust_blockchain_snapshot_test.json");
+    let _ = fs::remove_file(&path);
+
+    // A missing snapshot starts a new chain
+    let mut storage = Storage::Memory(MemoryStorage::load(&path).unwrap());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    let block1 = chain.mine_block("new block 1".to_owned(), &mut storage).await.unwrap();
+    let peer_score = PeerScore {
+        peer_id: "peer".to_owned(),
+        score: -100.0,
+        banned: true,
+        addresses: vec!["/ip4/10.0.0.1/tcp/4001".to_owned()],
+        updated_at: 1,
+    };
+    storage.save_peer_scores(std::slice::from_ref(&peer_score)).await.unwrap();
+    let bad_message = BadMessage::new("peer".to_owned(), b"not json", "invalid JSON".to_owned(), 1);
+    storage.insert_bad_message(&bad_message).await.unwrap();
+    storage.snapshot(&path).unwrap();
+
+    let mut storage = Storage::Memory(MemoryStorage::load(&path).unwrap());
+    let chain = Chain::init(&mut storage).await.unwrap();
+    assert_eq!(chain.latest_block, block1);
+    assert_eq!(storage.get_peer_scores().await.unwrap(), vec![peer_score]);
+    assert_eq!(storage.get_bad_messages(10).await.unwrap(), vec![bad_message]);
+    assert!(matches!(chain.validate_chain(&mut storage).await, Ok(())));
+
+    fs::write(&path, "not json").unwrap();
+    assert!(MemoryStorage::load(&path).is_err());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_config_from_args() {
+    let config = Config::from_args(args(&["node_1"])).unwrap();
+    assert_eq!(config.storage, StorageKind::Postgres);
+    assert_eq!(config.db_name, Some("node_1".to_owned()));
+    assert!(config.p2p);
+
+    let config = Config::from_args(args(&["--storage", "memory", "--no-p2p", "--snapshot", "chain.json", "--snapshot-interval", "5"])).unwrap();
+    assert_eq!(config.storage, StorageKind::Memory);
+    assert!(!config.p2p);
+    assert_eq!(config.snapshot.unwrap().to_str(), Some("chain.json"));
+    assert_eq!(config.snapshot_interval.as_secs(), 5);
+
+    let config = Config::from_args(args(&["node_1", "--outbound-only", "--peer", "/ip4/10.0.0.1/tcp/4001", "--peer", "/ip4/10.0.0.2/tcp/4001"])).unwrap();
+    assert!(config.outbound_only);
+    assert_eq!(config.peers, vec!["/ip4/10.0.0.1/tcp/4001".to_owned(), "/ip4/10.0.0.2/tcp/4001".to_owned()]);
+    assert!(config.listen.is_empty());
+    let config = Config::from_args(args(&["node_1", "--listen", "/ip4/0.0.0.0/tcp/4001", "--listen", "/ip6/::/tcp/4001"])).unwrap();
+    assert_eq!(config.listen, vec!["/ip4/0.0.0.0/tcp/4001".to_owned(), "/ip6/::/tcp/4001".to_owned()]);
+    assert!(Config::from_args(args(&["node_1", "--outbound-only", "--listen", "/ip4/0.0.0.0/tcp/4001"])).is_err());
+    assert!(!config.regtest);
+    assert!(Config::from_args(args(&["node_1", "--regtest"])).unwrap().regtest);
+    let config = Config::from_args(args(&["node_1", "--daa", "asert"])).unwrap();
+    assert_eq!(config.difficulty_algorithm, Some("asert".to_owned()));
+    assert!(Config::from_args(args(&["node_1", "--daa", "magic"])).is_err());
+    let config = Config::from_args(args(&["node_1", "--stratum", "127.0.0.1:3333"])).unwrap();
+    assert_eq!(config.stratum, Some("127.0.0.1:3333".parse().unwrap()));
+    assert!(Config::from_args(args(&["node_1", "--stratum", "localhost"])).is_err());
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().hash_backend, HashBackend::Cpu);
+    let config = Config::from_args(args(&["node_1", "--hasher", "gpu"])).unwrap();
+    assert_eq!(config.hash_backend, HashBackend::Gpu);
+    assert!(Config::from_args(args(&["node_1", "--hasher", "fpga"])).is_err());
+
+    let config = Config::from_args(args(&["node_1", "--slot-time", "10", "--proposer", "peer a", "--proposer", "peer b"])).unwrap();
+    assert_eq!(config.slot_time.map(|slot_time| slot_time.as_secs()), Some(10));
+    assert_eq!(config.proposers, vec!["peer a".to_owned(), "peer b".to_owned()]);
+    assert!(Config::from_args(args(&["node_1", "--slot-time", "0"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--proposer", "peer a"])).is_err());
+    let config = Config::from_args(args(&["node_1", "--checkpoint-signer", "peer a", "--checkpoint-signer", "peer b"])).unwrap();
+    assert_eq!(config.checkpoint_signers, vec!["peer a".to_owned(), "peer b".to_owned()]);
+    assert!(Config::from_args(args(&["node_1", "--checkpoint-signer"])).is_err());
+
+    let config = Config::from_args(args(&["node_1", "--events", "127.0.0.1:3334"])).unwrap();
+    assert_eq!(config.events, Some("127.0.0.1:3334".parse().unwrap()));
+    assert!(Config::from_args(args(&["node_1", "--events", "localhost"])).is_err());
+    let config = Config::from_args(args(&["node_1", "--rpc", "127.0.0.1:3335"])).unwrap();
+    assert_eq!(config.rpc, Some("127.0.0.1:3335".parse().unwrap()));
+    assert!(Config::from_args(args(&["node_1", "--rpc"])).is_err());
+
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().min_confirmations, 0);
+    assert_eq!(Config::from_args(args(&["node_1", "--min-confirmations", "6"])).unwrap().min_confirmations, 6);
+    assert!(Config::from_args(args(&["node_1", "--min-confirmations", "-1"])).is_err());
+    assert_eq!(split_min_confirmations("invoice  --min-confirmations 3 paid", 1).unwrap(), ("invoice paid".to_owned(), 3));
+    assert_eq!(split_min_confirmations("invoice paid", 1).unwrap(), ("invoice paid".to_owned(), 1));
+    assert!(split_min_confirmations("invoice --min-confirmations", 1).is_err());
+
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().repair, None);
+    assert_eq!(Config::from_args(args(&["node_1", "--repair", "resync"])).unwrap().repair, Some(RepairStrategy::Resync));
+    assert!(Config::from_args(args(&["node_1", "--repair", "rebuild"])).is_err());
+
+    let config = Config::from_args(args(&["node_1", "--chain", "test", "--chain", "other:asert"])).unwrap();
+    assert_eq!(config.chains.iter().map(|chain| chain.name.as_str()).collect::<Vec<_>>(), vec!["test", "other"]);
+    assert_eq!(config.chains[1].difficulty_algorithm.as_deref(), Some("asert"));
+    assert!(Config::from_args(args(&["node_1", "--chain", "test", "--chain", "test"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--chain", "Test"])).is_err());
+
+    let config = Config::from_args(args(&["node_1", "--chain", "notary", "--bridge", "chain:notary", "--bridge-interval", "60"])).unwrap();
+    assert_eq!(config.bridge, Some(BridgeTarget::Chain("notary".to_owned())));
+    assert_eq!(config.bridge_interval, Duration::from_secs(60));
+    assert!(Config::from_args(args(&["node_1", "--bridge", "chain:notary"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--bridge", "http://127.0.0.1:8080/anchors"])).unwrap().bridge.is_some());
+    assert!(Config::from_args(args(&["node_1", "--bridge-interval", "0"])).is_err());
+
+    let config = Config::from_args(args(&["node_1", "--capture", "p2p.gz", "--capture-max-bytes", "1024"])).unwrap();
+    assert_eq!(config.capture, Some(PathBuf::from("p2p.gz")));
+    assert_eq!(config.capture_max_bytes, 1024);
+    assert!(Config::from_args(args(&["node_1", "--capture"])).is_err());
+
+    let config = Config::from_args(args(&["node_1", "--wal", "node_1.wal"])).unwrap();
+    assert_eq!(config.wal, Some(PathBuf::from("node_1.wal")));
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().wal, None);
+    assert!(Config::from_args(args(&["node_1", "--wal"])).is_err());
+    assert!(Config::from_args(args(&["--storage", "memory", "--wal", "node_1.wal"])).is_err());
+
+    assert!(Config::from_args(args(&["node_1", "--fast-sync"])).unwrap().fast_sync);
+    assert!(!Config::from_args(args(&["node_1"])).unwrap().fast_sync);
+
+    let config = Config::from_args(args(&["node_1", "--fast-sync", "--snapshot-signer", "a", "--snapshot-signer", "b", "--snapshot-quorum", "2"])).unwrap();
+    assert_eq!(config.snapshot_trust, SnapshotTrust { signers: vec!["a".to_owned(), "b".to_owned()], quorum: 2 });
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().snapshot_trust, SnapshotTrust::default());
+    assert!(Config::from_args(args(&["node_1", "--snapshot-quorum", "2"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--fast-sync", "--snapshot-quorum", "0"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--fast-sync", "--snapshot-signer", "a", "--snapshot-quorum", "2"])).is_err());
+
+    assert!(Config::from_args(args(&["node_1", "--encrypt-at-rest"])).unwrap().encrypt_at_rest);
+    assert!(!Config::from_args(args(&["node_1"])).unwrap().encrypt_at_rest);
+
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().propagation, Propagation::Full);
+    assert_eq!(Config::from_args(args(&["node_1", "--propagation", "announce"])).unwrap().propagation, Propagation::Announce);
+    assert!(Config::from_args(args(&["node_1", "--propagation", "flood"])).is_err());
+
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().network, NetworkParams::default());
+    let path = env::temp_dir().join("rust_blockchain_config_network_test.json");
+    fs::write(&path, r#"{"max_supply": 1000}"#).unwrap();
+    let config = Config::from_args(args(&["node_1", "--network", path.to_str().unwrap()])).unwrap();
+    assert_eq!(config.network.max_supply, 1_000);
+    fs::remove_file(&path).unwrap();
+    assert!(Config::from_args(args(&["node_1", "--network", path.to_str().unwrap()])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--network"])).is_err());
+
+    let config = Config::from_args(args(&["node_1"])).unwrap();
+    assert_eq!((config.mempool, config.mempool_policy), (None, MempoolPolicy::default()));
+    let config = Config::from_args(args(&[
+        "node_1", "--mempool", "mempool.json", "--mempool-max-age", "60", "--mempool-max-jobs", "50", "--mempool-max-bytes", "1000",
+        "--mempool-eviction", "oldest", "--mempool-min-priority", "normal", "--min-fee-rate", "5",
+    ]))
+    .unwrap();
+    assert_eq!(config.mempool, Some(PathBuf::from("mempool.json")));
+    assert_eq!(
+        config.mempool_policy,
+        MempoolPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            max_jobs: Some(50),
+            max_bytes: Some(1_000),
+            eviction: Eviction::Oldest,
+            min_priority: Priority::Normal,
+            min_fee_rate: 5,
+        }
+    );
+    assert!(Config::from_args(args(&["node_1", "--mempool-max-jobs", "0"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--mempool-eviction", "random"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--mempool-max-age", "0"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--mempool-min-priority", "urgent"])).is_err());
+    assert!(Config::from_args(args(&["node_1", "--min-fee-rate", "-1"])).is_err());
+
+    assert_eq!(Config::from_args(args(&["node_1"])).unwrap().stale_pruning, StalePruning::Blocks);
+    assert_eq!(Config::from_args(args(&["node_1", "--keep-stale-headers"])).unwrap().stale_pruning, StalePruning::Bodies);
+    assert_eq!(Config::from_args(args(&["node_1", "--archival"])).unwrap().stale_pruning, StalePruning::Off);
+    assert!(Config::from_args(args(&["node_1", "--archival", "--keep-stale-headers"])).is_err());
+
+    // Postgres needs a database name
+    assert!(Config::from_args(args(&[])).is_err());
+    assert!(Config::from_args(args(&["--storage", "files"])).is_err());
+    assert!(Config::from_args(args(&["--storage", "memory", "--snapshot-interval", "0"])).is_err());
+}
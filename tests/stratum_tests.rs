@@ -0,0 +1,70 @@
+use rust_blockchain::blockchain::REGTEST_DIFFICULTY;
+use rust_blockchain::node::Node;
+use rust_blockchain::pool::{Coordinator, PoolMessage};
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::stratum::*;
+use rust_blockchain::types::{EventType, Height};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time;
+
+#[tokio::test]
+async fn test_hasher_mines_pool_block() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "coordinator".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    node.pool = Some(Coordinator::new("stratum block".to_owned(), 16));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stratum_sender, stratum_rcv) = mpsc::unbounded_channel();
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel();
+    tokio::spawn(init_stratum(listener, stratum_rcv, main_sender));
+    let hasher = tokio::spawn(run_hasher(addr));
+
+    // Plays the main task: pool messages go back to the stratum server until the block is found
+    let block = time::timeout(Duration::from_secs(60), async {
+        loop {
+            let event = main_rcv.recv().await.unwrap();
+            if let EventType::ReceivedPoolMessage { sender, .. } = &event {
+                assert!(is_stratum_worker(sender));
+            }
+            for outgoing in node.handle_event(event, Instant::now()).await {
+                match outgoing {
+                    EventType::SendNewBlock(block) => return block,
+                    event @ EventType::SendPoolMessage { .. } => stratum_sender.send(event).unwrap(),
+                    event => panic!("unexpected event {:?}", event),
+                }
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(block.id, Height(1));
+    assert_eq!(node.chain.latest_block, block);
+    let stats = node.pool.as_ref().unwrap().workers().values().next().cloned().unwrap();
+    assert_eq!(stats.blocks, 1);
+
+    // Closing the stratum server disconnects the hasher
+    drop(stratum_sender);
+    time::timeout(Duration::from_secs(10), hasher).await.unwrap().unwrap().unwrap();
+    assert!(!is_stratum_worker("12D3KooWPeer"));
+}
+
+#[tokio::test]
+async fn test_overlong_message() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (_stratum_sender, stratum_rcv) = mpsc::unbounded_channel();
+    let (main_sender, mut main_rcv) = mpsc::unbounded_channel();
+    tokio::spawn(init_stratum(listener, stratum_rcv, main_sender));
+
+    // A line that never ends closes the connection, which unsubscribes the hasher
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&vec![b'x'; MAX_MESSAGE_LENGTH + 1]).await.unwrap();
+    match time::timeout(Duration::from_secs(10), main_rcv.recv()).await.unwrap() {
+        Some(EventType::ReceivedPoolMessage { sender, message: PoolMessage::Unsubscribe }) => assert!(is_stratum_worker(&sender)),
+        other => panic!("unexpected event {:?}", other),
+    }
+}
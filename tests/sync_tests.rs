@@ -0,0 +1,163 @@
+use rust_blockchain::blockchain::{hasher, Chain, Checkpoint};
+use rust_blockchain::difficulty::{Fixed, MIN_DIFFICULTY};
+use rust_blockchain::handshake::{Features, Handshake};
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::sync::*;
+use rust_blockchain::types::{EventType, Height};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// A node whose blocks need no mining
+async fn easy_node(name: &str) -> Node {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), name.to_owned()).await.unwrap();
+    node.chain.difficulty = String::new();
+    node.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    node
+}
+
+async fn grow(chain: &mut Chain, storage: &mut Storage, height: Height, data: &str) {
+    while chain.latest_block.id < height {
+        let mut block = chain.block_template(storage, data.to_owned()).await.unwrap();
+        block.hash = hasher(&block);
+        chain.add_block(storage, block).await.unwrap();
+    }
+}
+
+#[test]
+fn test_sync_sessions() {
+    let mut sync_manager = SyncManager::new();
+    let now = Instant::now();
+
+    let session_a = sync_manager.start("peer a", SyncTrigger::LatestBlock(Height(1)), now).unwrap();
+    let session_b = sync_manager.start("peer b", SyncTrigger::LatestBlock(Height(1)), now).unwrap();
+    assert_ne!(session_a, session_b);
+    // Only one sync per peer at a time
+    assert!(sync_manager.start("peer a", SyncTrigger::LatestBlock(Height(1)), now).is_none());
+    assert!(sync_manager.is_pending(&session_a));
+
+    let session = sync_manager
+        .finish(&session_a, 10, SyncOutcome::Success, now + Duration::from_secs(2))
+        .unwrap();
+    assert_eq!(session.blocks_transferred, 10);
+    assert_eq!(session.duration, Some(Duration::from_secs(2)));
+    assert!(!sync_manager.is_pending(&session_a));
+    // Responses are only accepted once
+    assert!(sync_manager.finish(&session_a, 10, SyncOutcome::Success, now).is_none());
+    assert!(sync_manager.finish("unknown", 10, SyncOutcome::Success, now).is_none());
+
+    assert!(sync_manager.expire(now + SYNC_TIMEOUT - Duration::from_secs(1)).is_empty());
+    let expired = sync_manager.expire(now + SYNC_TIMEOUT);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id, session_b);
+    assert_eq!(expired[0].outcome, SyncOutcome::TimedOut);
+
+    // A new sync with the peer can be started after the previous one finished
+    let session_c = sync_manager.start("peer a", SyncTrigger::LatestBlock(Height(1)), now).unwrap();
+    let sessions = sync_manager.sessions().map(|session| session.id.clone()).collect::<Vec<String>>();
+    assert_eq!(sessions, vec![session_c, session_b, session_a]);
+}
+
+#[test]
+fn test_sync_session_history() {
+    let mut sync_manager = SyncManager::new();
+    for i in 0..SYNC_SESSION_HISTORY + 5 {
+        sync_manager.start(&format!("peer {}", i), SyncTrigger::LatestBlock(Height(1)), Instant::now()).unwrap();
+    }
+    assert_eq!(sync_manager.sessions().count(), SYNC_SESSION_HISTORY);
+}
+
+#[test]
+fn test_sync_source_ranking() {
+    let mut sync_manager = SyncManager::new();
+    let now = Instant::now();
+    let sync = |sync_manager: &mut SyncManager, peer: &str, outcome: SyncOutcome, latency: Duration| {
+        let session_id = sync_manager.start(peer, SyncTrigger::LatestBlock(Height(1)), now).unwrap();
+        sync_manager.finish(&session_id, 1, outcome, now + latency);
+    };
+    for peer in ["fast", "slow", "liar"] {
+        sync_manager.record_block(peer, Height(100));
+    }
+    sync_manager.record_block("short", Height(50));
+    sync(&mut sync_manager, "fast", SyncOutcome::Success, Duration::from_millis(100));
+    sync(&mut sync_manager, "slow", SyncOutcome::Success, Duration::from_secs(10));
+    for _ in 0..2 {
+        sync(&mut sync_manager, "liar", SyncOutcome::Failed("invalid chain".to_owned()), Duration::from_millis(100));
+    }
+
+    let ranking = sync_manager.ranking().into_iter().map(|(peer, _, _)| peer).collect::<Vec<String>>();
+    assert_eq!(ranking, vec!["fast", "liar", "short", "slow"]);
+    let (_, _, stats) = &sync_manager.ranking()[1];
+    assert_eq!((stats.valid, stats.invalid, stats.best_block), (0, 2, Height(100)));
+
+    // The peer that told us about the block is replaced by a higher ranking one having it
+    assert_eq!(sync_manager.select_source("slow", Features::default(), Height(100)), "fast");
+    assert_eq!(sync_manager.select_source("fast", Features::default(), Height(100)), "fast");
+    // Peers not having the block are no candidates
+    assert_eq!(sync_manager.select_source("slow", Features::default(), Height(101)), "slow");
+    // Neither are peers we sync with already
+    sync_manager.start("fast", SyncTrigger::LatestBlock(Height(1)), now).unwrap();
+    assert_eq!(sync_manager.select_source("slow", Features::default(), Height(100)), "liar");
+
+    // Nor peers not serving what we ask for, or disconnected ones
+    let handshake = Handshake::new("genesis".to_owned(), Checkpoint { id: Height(200), hash: "block".to_owned() }, Features::SNAPSHOTS);
+    sync_manager.set_handshake("liar", Some(&handshake));
+    assert_eq!(sync_manager.select_source("slow", Features::BODIES, Height(100)), "slow");
+    assert_eq!(sync_manager.select_source("slow", Features::SNAPSHOTS, Height(200)), "liar");
+    sync_manager.set_handshake("liar", None);
+    assert_eq!(sync_manager.select_source("slow", Features::SNAPSHOTS, Height(100)), "slow");
+    assert!(sync_manager.ranking().iter().all(|(peer, _, _)| peer != "liar"));
+}
+
+#[tokio::test]
+async fn test_sync_history() {
+    let now = Instant::now();
+    let mut server = easy_node("server").await;
+    grow(&mut server.chain, &mut server.storage, Height(4), "server").await;
+    let mut client = easy_node("client").await;
+    grow(&mut client.chain, &mut client.storage, Height(2), "client").await;
+    let old_tip = Checkpoint { id: Height(2), hash: client.chain.latest_block.hash.clone() };
+
+    // The server's longer fork replaces ours
+    let latest = EventType::ReceivedLatestBlock { sender: "server".to_owned(), block: server.chain.latest_block.clone() };
+    let (session_id, from) = match client.handle_event(latest, now).await.as_slice() {
+        [EventType::SendChainRequest { session_id, from, .. }] => (session_id.clone(), *from),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let request = EventType::ReceivedChainRequest { receiver: "client".to_owned(), session_id: session_id.clone(), from };
+    let chain = match server.handle_event(request, now).await.pop() {
+        Some(EventType::SendChain { chain, .. }) => chain,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    let received = EventType::ReceivedChain { sender: "server".to_owned(), session_id: session_id.clone(), chain };
+    client.handle_event(received, now + Duration::from_millis(250)).await;
+    assert_eq!(client.chain.latest_block, server.chain.latest_block);
+
+    // A request that is never answered times out
+    grow(&mut server.chain, &mut server.storage, Height(5), "server").await;
+    let latest = EventType::ReceivedLatestBlock { sender: "server".to_owned(), block: server.chain.latest_block.clone() };
+    assert_eq!(client.handle_event(latest, now).await.len(), 1);
+    client.expire_sync_sessions(now + SYNC_TIMEOUT).await;
+
+    let history = client.storage.get_sync_history(10).await.unwrap();
+    assert_eq!(history.len(), 2);
+    let new_tip = Checkpoint { id: Height(4), hash: history[1].new_tip.hash.clone() };
+    assert_eq!(
+        history[1],
+        SyncRecord {
+            session_id,
+            peer: "server".to_owned(),
+            trigger: "latest block at 4".to_owned(),
+            blocks_transferred: 5,
+            duration_ms: 250,
+            outcome: "success".to_owned(),
+            old_tip,
+            new_tip: new_tip.clone(),
+            replaced: true,
+            finished_at: history[1].finished_at,
+        }
+    );
+    assert_eq!((history[0].trigger.as_str(), history[0].outcome.as_str()), ("latest block at 5", "timed out"));
+    assert_eq!((&history[0].old_tip, &history[0].new_tip, history[0].replaced), (&new_tip, &new_tip, false));
+    assert_eq!(client.storage.get_sync_history(1).await.unwrap(), vec![history[0].clone()]);
+}
@@ -0,0 +1,142 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{activation_height, rules_at, Feature};
+use rust_blockchain::mempool::TransactionPool;
+use rust_blockchain::mining::*;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::*;
+use rust_blockchain::types::Height;
+use std::collections::HashMap;
+
+#[test]
+fn test_sign_transaction() {
+    let (sender, other) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let mut transaction = Transaction::new(&sender.public(), "recipient".to_owned(), 50, 2);
+    let id = transaction.id();
+    assert!(!transaction.is_signed());
+    assert!(transaction.verify().is_err());
+    assert!(transaction.sign(&other).is_err());
+    transaction.sign(&sender).unwrap();
+    assert!(transaction.is_signed());
+    transaction.verify().unwrap();
+    assert_eq!(transaction.id(), id);
+    // The same transfer again is another transaction
+    assert_ne!(Transaction::new(&sender.public(), "recipient".to_owned(), 50, 2).id(), id);
+
+    assert_eq!(Transaction::from_json(&transaction.to_json()).unwrap(), transaction);
+    assert_eq!(Transaction::from_block_data(&transaction.to_block_data()).unwrap().unwrap(), transaction);
+    assert!(Transaction::from_block_data("data").is_none());
+    assert_eq!(transaction_ids(&transaction.to_block_data()), vec![id]);
+    check_block_data(&transaction.to_block_data()).unwrap();
+    check_block_data("data").unwrap();
+
+    let tampered = Transaction { amount: 5_000, ..transaction };
+    assert!(tampered.verify().is_err());
+    assert!(check_block_data(&tampered.to_block_data()).is_err());
+    assert!(check_block_data("tx {}").is_err());
+}
+
+#[tokio::test]
+async fn test_transaction_status() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let sender = ed25519::Keypair::generate();
+    let mut broadcast = HashMap::new();
+    let mut transactions = vec![];
+    for amount in [10, 20] {
+        let mut transaction = Transaction::new(&sender.public(), "recipient".to_owned(), amount, 3);
+        transaction.sign(&sender).unwrap();
+        node.mining_queue.push_with_fee(transaction.to_block_data(), Priority::Normal, transaction.fee);
+        broadcast.insert(transaction.id(), transaction.clone());
+        transactions.push(transaction);
+    }
+    let (confirmed, dropped) = (&transactions[0], &transactions[1]);
+    let status = |found: Option<(Transaction, TxStatus)>| found.map(|(_, status)| status);
+
+    let found = find(&node.chain, &mut node.storage, &node.mining_queue, &node.transactions, &broadcast, &confirmed.id()).await.unwrap();
+    assert_eq!(found, Some((confirmed.clone(), TxStatus::Pending(1))));
+
+    let block = node.mine_next().await.unwrap().unwrap();
+    assert_eq!(block.fee, confirmed.fee);
+    node.mining_queue.remove(2);
+    node.chain.mine_block("on top".to_owned(), &mut node.storage).await.unwrap();
+    let found = find(&node.chain, &mut node.storage, &node.mining_queue, &node.transactions, &broadcast, &confirmed.id()).await.unwrap();
+    assert_eq!(status(found), Some(TxStatus::Confirmed { height: Height(1), confirmations: 1 }));
+    let found = find(&node.chain, &mut node.storage, &node.mining_queue, &node.transactions, &broadcast, &dropped.id()).await.unwrap();
+    assert_eq!(status(found), Some(TxStatus::Dropped));
+    assert!(find(&node.chain, &mut node.storage, &node.mining_queue, &node.transactions, &broadcast, "unknown").await.unwrap().is_none());
+
+    // Transfers touch the addresses of their sender and recipient
+    let sender_key = hex::encode(sender.public().encode());
+    assert_eq!(Chain::get_address_blocks(&mut node.storage, "recipient").await.unwrap(), vec![block.clone()]);
+    assert_eq!(Chain::get_address_blocks(&mut node.storage, &sender_key).await.unwrap(), vec![block]);
+}
+
+#[test]
+fn test_transaction_batches() {
+    let sender = ed25519::Keypair::generate();
+    let mut transactions = vec![];
+    for amount in [10, 20] {
+        let mut transaction = Transaction::new(&sender.public(), "recipient".to_owned(), amount, 2);
+        transaction.sign(&sender).unwrap();
+        transactions.push(transaction);
+    }
+    let data = to_block_data(&transactions);
+    assert!(data.starts_with(TRANSACTIONS_PREFIX));
+    assert_eq!(from_block_data(&data).unwrap().unwrap(), transactions);
+    assert_eq!(transaction_ids(&data), transactions.iter().map(Transaction::id).collect::<Vec<_>>());
+    assert_eq!(total_fee(&transactions), 4);
+    check_block_data(&data).unwrap();
+    // A single transaction keeps its own encoding
+    assert_eq!(to_block_data(&transactions[..1]), transactions[0].to_block_data());
+    assert!(from_block_data("data").is_none());
+
+    assert!(check_block_data(&to_block_data(&[transactions[0].clone(), transactions[0].clone()])).is_err());
+    assert!(check_block_data(&format!("{}[]", TRANSACTIONS_PREFIX)).is_err());
+    let tampered = Transaction { amount: 5_000, ..transactions[1].clone() };
+    assert!(check_block_data(&to_block_data(&[transactions[0].clone(), tampered])).is_err());
+}
+
+#[tokio::test]
+async fn test_mine_transactions() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    assert!(chain.mine_transactions(vec![], &mut storage).await.is_err());
+
+    let sender = ed25519::Keypair::generate();
+    let mut transactions = vec![];
+    for (recipient, fee) in [("alice", 1), ("bob", 5)] {
+        let mut transaction = Transaction::new(&sender.public(), recipient.to_owned(), 10, fee);
+        transaction.sign(&sender).unwrap();
+        transactions.push(transaction);
+    }
+    let block = chain.mine_transactions(transactions.clone(), &mut storage).await.unwrap();
+    assert_eq!(block.fee, 6);
+    assert_eq!(block.transactions().unwrap(), transactions);
+
+    // Every transaction of the block is indexed
+    for transaction in transactions.iter() {
+        assert_eq!(Chain::get_transaction_blocks(&mut storage, &transaction.id()).await.unwrap(), vec![block.clone()]);
+        let found = find(&chain, &mut storage, &MiningQueue::new(), &TransactionPool::new(), &HashMap::new(), &transaction.id()).await.unwrap();
+        assert_eq!(found, Some((transaction.clone(), TxStatus::Confirmed { height: Height(1), confirmations: 0 })));
+    }
+    assert_eq!(Chain::get_address_blocks(&mut storage, "bob").await.unwrap(), vec![block]);
+
+    // Transaction data mined as a block collects the fees too
+    let mut transaction = Transaction::new(&sender.public(), "carol".to_owned(), 10, 3);
+    transaction.sign(&sender).unwrap();
+    let block = chain.mine_block(transaction.to_block_data(), &mut storage).await.unwrap();
+    assert_eq!((block.fee, block.transactions().unwrap()), (3, vec![transaction]));
+    // Data that only looks like transactions carries none, which is an error
+    assert!(chain.mine_block(format!("{}not json", TRANSACTIONS_PREFIX), &mut storage).await.unwrap().transactions().is_err());
+    assert_eq!(chain.mine_block("data".to_owned(), &mut storage).await.unwrap().transactions().unwrap(), vec![]);
+}
+
+#[test]
+fn test_signed_transactions_activation() {
+    let height = activation_height(Feature::SignedTransactions);
+    assert!(!rules_at(height - 1).signed_transactions);
+    assert!(rules_at(height).signed_transactions);
+}
@@ -0,0 +1,50 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::types::{Height, Nonce};
+
+#[test]
+fn test_height_and_nonce_serde() {
+    // Both are plain numbers on the wire, so hashes and messages do not change
+    assert_eq!(serde_json::to_string(&Height(42)).unwrap(), "42");
+    assert_eq!(serde_json::from_str::<Height>("42").unwrap(), Height(42));
+    assert_eq!(serde_json::to_string(&Nonce(u64::MAX)).unwrap(), u64::MAX.to_string());
+    assert_eq!(serde_json::from_str::<Nonce>(&u64::MAX.to_string()).unwrap(), Nonce(u64::MAX));
+
+    // Negative values are not representable
+    assert!(serde_json::from_str::<Height>("-1").is_err());
+    assert!(serde_json::from_str::<Nonce>("-1").is_err());
+
+    let mut block = Block::create_genesis();
+    block.id = Height(7);
+    block.nonce = Nonce(123);
+    block.extra_nonce = Nonce(1);
+    let json = serde_json::to_string(&block).unwrap();
+    assert!(json.contains("\"id\":7") && json.contains("\"nonce\":123") && json.contains("\"extra_nonce\":1"));
+    assert_eq!(serde_json::from_str::<Block>(&json).unwrap(), block);
+
+    let json = json.replace("\"id\":7", "\"id\":-7");
+    assert!(serde_json::from_str::<Block>(&json).is_err());
+}
+
+#[test]
+fn test_storage_conversions() {
+    // Postgres stores heights and nonces as INT8
+    assert_eq!(Height::try_from(5_i64).unwrap(), Height(5));
+    assert!(Height::try_from(-1_i64).is_err());
+    assert_eq!(i64::try_from(Height(5)).unwrap(), 5);
+    assert!(i64::try_from(Height(u64::MAX)).is_err());
+
+    assert_eq!(Nonce::try_from(i64::MAX).unwrap(), Nonce::MAX);
+    assert!(Nonce::try_from(i64::MIN).is_err());
+    assert_eq!(i64::try_from(Nonce::MAX).unwrap(), i64::MAX);
+    assert!(i64::try_from(Nonce(Nonce::MAX.0 + 1)).is_err());
+}
+
+#[test]
+fn test_height_arithmetic() {
+    assert_eq!(Height(5) + 1, Height(6));
+    assert_eq!(Height(5) - 1, Height(4));
+    assert_eq!(Height::GENESIS.checked_sub(1), None);
+    assert_eq!(Height(3).saturating_sub(FINALITY_DEPTH), Height::GENESIS);
+    assert!(Height(2) > Height::GENESIS);
+    assert_eq!(Height(12).to_string(), "12");
+}
@@ -0,0 +1,153 @@
+use chrono::Utc;
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::consensus::{activation_height, rules_at, Feature};
+use rust_blockchain::network::NetworkParams;
+use rust_blockchain::state::EMPTY_STATE_ROOT;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::{self, Transaction};
+use rust_blockchain::types::Nonce;
+use rust_blockchain::utxo::*;
+use std::collections::HashMap;
+
+fn signed(sender: &ed25519::Keypair, recipient: &str, amount: u64, fee: u64, inputs: Vec<OutPoint>) -> Transaction {
+    let mut transaction = Transaction { inputs, ..Transaction::new(&sender.public(), recipient.to_owned(), amount, fee) };
+    transaction.sign(sender).unwrap();
+    transaction
+}
+
+fn outpoint(transaction: &Transaction, vout: u32) -> OutPoint {
+    OutPoint { txid: transaction.id(), vout }
+}
+
+#[test]
+fn test_outpoints() {
+    let outpoint = "abc:1".parse::<OutPoint>().unwrap();
+    assert_eq!(outpoint, OutPoint { txid: "abc".to_owned(), vout: 1 });
+    assert_eq!(outpoint.to_string(), "abc:1");
+    assert!("abc".parse::<OutPoint>().is_err());
+    assert!(":1".parse::<OutPoint>().is_err());
+    assert!("abc:-1".parse::<OutPoint>().is_err());
+
+    let (inputs, rest) = split_inputs("--inputs abc:0,def:1 bob 10").unwrap();
+    assert_eq!(inputs, vec!["abc:0".parse().unwrap(), "def:1".parse().unwrap()]);
+    assert_eq!(rest, "bob 10");
+    assert_eq!(split_inputs("bob 10").unwrap(), (vec![], "bob 10"));
+    assert!(split_inputs("--inputs abc bob 10").is_err());
+}
+
+#[tokio::test]
+async fn test_apply_block() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let chain = Chain::init(&mut storage).await.unwrap();
+    let (minter, alice) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    // Without inputs value enters the set
+    let mint = signed(&minter, &Transaction::new(&alice.public(), String::new(), 0, 0).sender, 100, 0, vec![]);
+    let block = Block::new(&chain.latest_block, transactions::to_block_data(&[mint.clone()]), "miner".to_owned());
+    let changes = apply_block(&block, &HashMap::new());
+    assert!(changes.spent.is_empty() && changes.invalid.is_empty());
+    assert_eq!(changes.created.len(), 1);
+    assert_eq!(changes.created[0].amount, 100);
+
+    let unspent = changes.created.iter().map(|utxo| (utxo.outpoint.clone(), utxo.clone())).collect::<HashMap<_, _>>();
+    // Pays 60 to bob and gets 30 back, the change of the second transfer is spent within the block
+    let spend = signed(&alice, "bob", 60, 10, vec![outpoint(&mint, RECIPIENT_OUTPUT)]);
+    let respend = signed(&alice, "carol", 30, 0, vec![outpoint(&spend, CHANGE_OUTPUT)]);
+    let double_spend = signed(&alice, "dave", 10, 0, vec![outpoint(&mint, RECIPIENT_OUTPUT)]);
+    let data = transactions::to_block_data(&[spend.clone(), respend.clone(), double_spend.clone()]);
+    let changes = apply_block(&Block::new(&block, data, "miner".to_owned()), &unspent);
+    assert_eq!(changes.spent, vec![outpoint(&mint, RECIPIENT_OUTPUT)]);
+    let mut created = changes.created.iter().map(|utxo| (utxo.address.as_str(), utxo.amount)).collect::<Vec<_>>();
+    created.sort();
+    assert_eq!(created, vec![("bob", 60), ("carol", 30)]);
+    assert_eq!(changes.invalid.len(), 1);
+    assert_eq!(changes.invalid[0].0, double_spend.id());
+}
+
+#[test]
+fn test_check_spends() {
+    let (alice, bob) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let spend = |keypair: &ed25519::Keypair, amount: u64| signed(keypair, "carol", amount, 5, vec!["abc:0".parse().unwrap()]);
+    let utxo = Utxo {
+        outpoint: "abc:0".parse().unwrap(),
+        address: spend(&alice, 0).sender,
+        amount: 50,
+        block_hash: "hash".to_owned(),
+    };
+    let unspent = |outpoint: &OutPoint| Some(utxo.clone()).filter(|utxo| utxo.outpoint == *outpoint);
+    assert_eq!(check_spends(&spend(&alice, 45), unspent).unwrap(), 50);
+    // Amount and fee exceed the input
+    assert!(check_spends(&spend(&alice, 46), unspent).is_err());
+    // Not the owner
+    assert!(check_spends(&spend(&bob, 10), unspent).is_err());
+    assert!(check_spends(&spend(&alice, 10), |_| None).is_err());
+    let twice = signed(&alice, "carol", 10, 0, vec!["abc:0".parse().unwrap(), "abc:0".parse().unwrap()]);
+    assert!(check_spends(&twice, unspent).is_err());
+    // Transactions without inputs are not checked
+    assert_eq!(check_spends(&signed(&alice, "carol", 1_000, 0, vec![]), |_| None).unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_utxos_follow_the_chain() {
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let mut chain = Chain::init(&mut storage).await.unwrap();
+    chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let (minter, alice) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let address = Transaction::new(&alice.public(), String::new(), 0, 0).sender;
+    let mint = signed(&minter, &address, 100, 0, vec![]);
+    chain.mine_transactions(vec![mint.clone()], &mut storage).await.unwrap();
+    let utxos = Chain::get_utxos(&mut storage, &address).await.unwrap();
+    assert_eq!(utxos.iter().map(|utxo| utxo.outpoint.clone()).collect::<Vec<_>>(), vec![outpoint(&mint, RECIPIENT_OUTPUT)]);
+    assert_eq!(balance(&utxos), 100);
+
+    let spend = signed(&alice, "bob", 70, 5, vec![outpoint(&mint, RECIPIENT_OUTPUT)]);
+    assert_eq!(Chain::check_spends(&mut storage, &spend).await.unwrap(), 100);
+    chain.mine_transactions(vec![spend.clone()], &mut storage).await.unwrap();
+    assert_eq!(balance(&Chain::get_utxos(&mut storage, &address).await.unwrap()), 25);
+    assert_eq!(balance(&Chain::get_utxos(&mut storage, "bob").await.unwrap()), 70);
+    // The output is spent now
+    let again = signed(&alice, "carol", 10, 0, vec![outpoint(&mint, RECIPIENT_OUTPUT)]);
+    assert!(matches!(Chain::check_spends(&mut storage, &again).await, Err(BlockchainError::DoubleSpend(_))));
+}
+
+#[test]
+fn test_utxo_spends_activation() {
+    let height = activation_height(Feature::UtxoSpends);
+    assert!(!rules_at(height - 1).utxo_spends);
+    assert!(rules_at(height).utxo_spends);
+}
+
+#[tokio::test]
+async fn test_replayed_transaction_rejected() {
+    let height = activation_height(Feature::UtxoSpends);
+    let mut storage = Storage::Memory(MemoryStorage::default());
+    let (minter, alice) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let mint = signed(&minter, &Transaction::new(&alice.public(), String::new(), 0, 0).sender, 100, 0, vec![]);
+    let parent = Block {
+        hash: "parent".to_owned(),
+        id: height - 1,
+        prev_hash: "grandparent".to_owned(),
+        timestamp: Utc::now().timestamp_millis() - 60_000,
+        nonce: Nonce(0),
+        data: transactions::to_block_data(&[mint.clone()]),
+        version: BLOCK_VERSION,
+        miner: String::new(),
+        extra_nonce: Nonce(0),
+        difficulty: 1,
+        fee: 0,
+        state_root: EMPTY_STATE_ROOT.to_owned(),
+        coinbase: None,
+    };
+    storage.insert_block(&parent).await.unwrap();
+    let mine = |data: String| {
+        let mut template = Block::template(&parent, data, "miner".to_owned(), 1);
+        template.state_root = EMPTY_STATE_ROOT.to_owned();
+        Block::mine_template(template, "", HashBackend::Cpu)
+    };
+
+    let fresh = mine(transactions::to_block_data(&[signed(&minter, "bob", 10, 0, vec![])]));
+    assert!(matches!(Chain::check_if_block_valid(&mut storage, &NetworkParams::default(), &fresh).await, Ok(())));
+    let replayed = mine(transactions::to_block_data(&[mint.clone()]));
+    let reason = Chain::block_violation(&mut storage, &NetworkParams::default(), &replayed).await.unwrap().unwrap();
+    assert!(reason.contains(&format!("transaction {} was confirmed", mint.id())), "{}", reason);
+}
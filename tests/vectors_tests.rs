@@ -0,0 +1,46 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::transactions::Transaction;
+use rust_blockchain::types::Nonce;
+use rust_blockchain::vectors::{check_all, HEADER_VECTORS};
+use sha2::{Digest, Sha256};
+
+#[test]
+fn test_golden_vectors() {
+    assert_eq!(check_all(), Vec::<String>::new());
+}
+
+// The other ways to hash a header have to agree with the vectors too
+#[test]
+fn test_vector_digests() {
+    for vector in HEADER_VECTORS.iter() {
+        let block = vector.block();
+        let digest = digest_with_nonce(&block, block.nonce);
+        assert_eq!(digest, Sha256::digest(vector.header.as_bytes()).to_vec(), "{}", vector.name);
+        assert_eq!(HeaderHasher::new(&block).digest(block.nonce).to_vec(), digest, "{}", vector.name);
+        let (prefix, suffix) = header_around_nonce(&block);
+        assert_eq!(format!("{}{}{}", prefix, vector.nonce, suffix), vector.header, "{}", vector.name);
+    }
+}
+
+// Every field of a header version is committed to, the fields of later versions are not
+#[test]
+fn test_vector_header_fields() {
+    for vector in HEADER_VECTORS.iter() {
+        let block = vector.block();
+        let coinbase = Transaction::coinbase(String::new(), block.coinbase.as_ref().map_or(0, |coinbase| coinbase.amount + 1), block.id);
+        let changes: [(&str, u8, Block); 8] = [
+            ("data", 0, Block { data: format!("{}!", block.data), ..block.clone() }),
+            ("nonce", 0, Block { nonce: Nonce(block.nonce.0.wrapping_add(1)), ..block.clone() }),
+            ("miner", 1, Block { miner: format!("{}!", block.miner), ..block.clone() }),
+            ("extra nonce", 1, Block { extra_nonce: Nonce(block.extra_nonce.0.wrapping_add(1)), ..block.clone() }),
+            ("difficulty", 3, Block { difficulty: block.difficulty.wrapping_add(1), ..block.clone() }),
+            ("fee", 4, Block { fee: block.fee + 1, ..block.clone() }),
+            ("state root", 5, Block { state_root: format!("{}0", block.state_root), ..block.clone() }),
+            ("coinbase", 6, Block { coinbase: Some(coinbase), ..block.clone() }),
+        ];
+        for (field, since, changed) in changes.iter() {
+            let committed = vector.version >= *since;
+            assert_eq!(hasher(changed) != vector.hash, committed, "{} of {}", field, vector.name);
+        }
+    }
+}
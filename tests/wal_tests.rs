@@ -0,0 +1,43 @@
+use rust_blockchain::blockchain::Block;
+use rust_blockchain::types::Height;
+use rust_blockchain::wal::WriteQueue;
+use std::env;
+use std::fs;
+
+#[test]
+fn test_write_queue() {
+    let path = env::temp_dir().join("rust_blockchain_wal_test.wal");
+    let _ = fs::remove_file(&path);
+    let genesis = Block::create_genesis();
+    let block1 = Block::new(&genesis, "block 1".to_owned(), String::new());
+    let block2 = Block::new(&block1, "block 2".to_owned(), String::new());
+
+    let mut queue = WriteQueue::open(&path).unwrap();
+    assert!(queue.is_empty());
+    queue.push(block1.clone()).unwrap();
+    queue.push(block2.clone()).unwrap();
+    assert_eq!(queue.front(), Some(&block1));
+    assert_eq!(queue.last(), Some(&block2));
+    assert_eq!(queue.get(&block2.hash), Some(&block2));
+    assert_eq!(queue.get_by_id(Height(1)), Some(&block1));
+    assert_eq!(queue.get("unknown"), None);
+    drop(queue);
+
+    // Journaled blocks survive a restart, a torn last line is dropped
+    let journal = fs::read_to_string(&path).unwrap();
+    fs::write(&path, format!("{}{{\"torn", journal)).unwrap();
+    let mut queue = WriteQueue::open(&path).unwrap();
+    assert_eq!(queue.len(), 2);
+    assert_eq!(fs::read_to_string(&path).unwrap(), journal);
+
+    queue.pop().unwrap();
+    assert_eq!(queue.front(), Some(&block2));
+    // The journal is emptied with the last block
+    queue.pop().unwrap();
+    assert!(queue.is_empty());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    queue.push(block1.clone()).unwrap();
+    drop(queue);
+    assert_eq!(WriteQueue::open(&path).unwrap().front(), Some(&block1));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,44 @@
+use rust_blockchain::config::Config;
+use rust_blockchain::keys::KeyStore;
+use rust_blockchain::wallet::*;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn test_wallet_file() {
+    let path = env::temp_dir().join("rust_blockchain_wallet_test.key");
+    let _ = fs::remove_file(&path);
+
+    // Created on the first start, the same wallet on the next one
+    let wallet = Wallet::load_or_generate(&path, "passphrase").unwrap();
+    assert_eq!(wallet.address().len(), 64);
+    assert!(!fs::read_to_string(&path).unwrap().contains(&hex::encode(wallet.keypair().encode())));
+    assert_eq!(Wallet::load_or_generate(&path, "passphrase").unwrap().address(), wallet.address());
+    assert!(Wallet::load(&path, "wrong").is_err());
+    assert!(!format!("{:?}", wallet).contains(&hex::encode(wallet.keypair().encode())));
+
+    let other = Wallet::generate();
+    other.save(&path, "passphrase").unwrap();
+    assert_eq!(Wallet::load(&path, "passphrase").unwrap().address(), other.address());
+    assert_ne!(other.address(), wallet.address());
+    fs::remove_file(&path).unwrap();
+    assert!(Wallet::load(&path, "passphrase").is_err());
+}
+
+#[test]
+fn test_signing_key_wallet() {
+    let dir = env::temp_dir().join("rust_blockchain_wallet_test_keys");
+    let _ = fs::remove_dir_all(&dir);
+    let key_store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    let wallet = Wallet::from_keypair(key_store.signing_key().unwrap());
+    assert_eq!(wallet.address(), address(&key_store.signing_key().unwrap().public()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_wallet_config() {
+    let config = Config::from_args(["--wallet", "node.key"].iter().map(|arg| arg.to_string())).unwrap();
+    assert_eq!(config.wallet, Some(PathBuf::from("node.key")));
+    assert!(Config::from_args(std::iter::once("--wallet".to_owned())).is_err());
+}
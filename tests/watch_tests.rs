@@ -0,0 +1,70 @@
+use libp2p::identity::ed25519;
+use rust_blockchain::blockchain::*;
+use rust_blockchain::hdwallet::Balance;
+use rust_blockchain::keys::KeyStore;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::transactions::Transaction;
+use rust_blockchain::types::Height;
+use rust_blockchain::watch::*;
+use std::env;
+use std::fs;
+
+#[test]
+fn test_watch_list() {
+    let mut watch_list = WatchList::default();
+    watch_list.watch("cold".to_owned(), String::new(), Height(10)).unwrap();
+    watch_list.watch("shop".to_owned(), "till".to_owned(), Height(20)).unwrap();
+    // Watching again only changes the label
+    watch_list.watch("cold".to_owned(), "savings".to_owned(), Height(30)).unwrap();
+    assert_eq!(watch_list.addresses[0], WatchedAddress { address: "cold".to_owned(), label: "savings".to_owned(), from: Height(10) });
+    assert!(watch_list.watch("two words".to_owned(), String::new(), Height(10)).is_err());
+    assert!(watch_list.watch(String::new(), String::new(), Height(10)).is_err());
+
+    watch_list.rescan_from(Height(15));
+    assert_eq!(watch_list.addresses.iter().map(|watched| watched.from).collect::<Vec<_>>(), vec![Height(10), Height(15)]);
+    assert!(watch_list.unwatch("shop"));
+    assert!(!watch_list.unwatch("shop"));
+    assert_eq!(watch_list.addresses.len(), 1);
+}
+
+#[tokio::test]
+async fn test_watched_history() {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), "miner".to_owned()).await.unwrap();
+    node.chain.difficulty = REGTEST_DIFFICULTY.to_owned();
+    let (payer, cold) = (ed25519::Keypair::generate(), ed25519::Keypair::generate());
+    let cold_address = hex::encode(cold.public().encode());
+    let transfers = [(&payer, cold_address.clone(), 40), (&payer, cold_address.clone(), 10), (&cold, "shop".to_owned(), 15)];
+    let mut transactions = vec![];
+    for (sender, recipient, amount) in transfers {
+        let mut transaction = Transaction::new(&sender.public(), recipient, amount, 1);
+        transaction.sign(sender).unwrap();
+        node.chain.mine_block(transaction.to_block_data(), &mut node.storage).await.unwrap();
+        transactions.push(transaction);
+    }
+    node.chain.mine_block("other".to_owned(), &mut node.storage).await.unwrap();
+
+    // Imported at height 3, the earlier transfers are not counted
+    let imported = history(&mut node.storage, &cold_address, Height(3)).await.unwrap();
+    assert_eq!(imported.transactions, vec![(Height(3), transactions[2].clone())]);
+    assert_eq!(imported.balance, Balance { received: 0, sent: 16, transactions: 1 });
+
+    // After a rescan from genesis they are
+    let rescanned = history(&mut node.storage, &cold_address, Height::GENESIS).await.unwrap();
+    assert_eq!(rescanned.transactions.iter().map(|(height, _)| height.0).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(rescanned.balance, Balance { received: 50, sent: 16, transactions: 3 });
+    assert_eq!(rescanned.balance.available(), 34);
+}
+
+#[test]
+fn test_watch_list_in_key_store() {
+    let dir = env::temp_dir().join("rust_blockchain_watch_test");
+    let _ = fs::remove_dir_all(&dir);
+    let store = KeyStore::open(&dir, "passphrase".to_owned()).unwrap();
+    assert_eq!(store.watch_list().unwrap(), WatchList::default());
+    let mut watch_list = WatchList::default();
+    watch_list.watch("cold".to_owned(), "savings".to_owned(), Height(3)).unwrap();
+    store.save_watch_list(&watch_list).unwrap();
+    assert_eq!(KeyStore::open(&dir, "passphrase".to_owned()).unwrap().watch_list().unwrap(), watch_list);
+    fs::remove_dir_all(&dir).unwrap();
+}
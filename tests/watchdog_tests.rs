@@ -0,0 +1,56 @@
+use rust_blockchain::config::Config;
+use rust_blockchain::reload::ConfigChanges;
+use rust_blockchain::watchdog::*;
+use std::time::Duration;
+
+fn parse(args: &[&str]) -> Config {
+    Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap()
+}
+
+#[test]
+fn test_watchdog_options() {
+    let config = parse(&["--storage", "memory"]);
+    assert_eq!(config.watchdog, Thresholds::default());
+    let new = parse(&["--storage", "memory", "--slow-query-ms", "20", "--slow-validation-ms", "500", "--slow-gossip-ms", "0"]);
+    assert_eq!(
+        new.watchdog,
+        Thresholds { query: Duration::from_millis(20), validation: Duration::from_millis(500), gossip: Duration::ZERO }
+    );
+    assert!(Config::from_args(["--storage", "memory", "--slow-query-ms", "fast"].iter().map(|arg| arg.to_string())).is_err());
+
+    // The thresholds are reloaded without a restart
+    let changes = ConfigChanges::between(&config, &new);
+    assert_eq!(changes.reloaded, vec!["watchdog thresholds"]);
+    assert!(!changes.restart_required);
+}
+
+#[test]
+fn test_watchdog_counts_slow_tasks() {
+    let mut watchdog = Watchdog::new(Thresholds { query: Duration::from_millis(10), ..Thresholds::default() });
+    // Subjects of fast tasks are never built
+    assert_eq!(watchdog.observe(TaskKind::Query, Duration::from_millis(10), || panic!("not slow")), None);
+
+    let subject = watchdog.observe(TaskKind::Query, Duration::from_millis(30), || "SELECT 1".to_owned());
+    assert_eq!(subject.as_deref(), Some("SELECT 1"));
+    watchdog.observe(TaskKind::Query, Duration::from_millis(50), || "SELECT 2".to_owned());
+    watchdog.observe(TaskKind::Query, Duration::from_millis(20), || "SELECT 3".to_owned());
+    watchdog.observe(TaskKind::Gossip, DEFAULT_SLOW_GOSSIP * 2, || "new block from peer".to_owned());
+
+    let report = watchdog.report();
+    assert_eq!(
+        report.query,
+        SlowStats { count: 3, total_ms: 100, slowest_ms: 50, slowest_subject: "SELECT 2".to_owned() }
+    );
+    assert_eq!(report.validation, SlowStats::default());
+    assert_eq!(report.gossip.count, 1);
+    assert_eq!(report.gossip.slowest_subject, "new block from peer");
+    assert_eq!(report.validation.to_string(), "none");
+    assert_eq!(report.query.to_string(), "3 | total: 100ms | slowest: 50ms SELECT 2");
+}
+
+#[test]
+fn test_watchdog_truncates_subjects() {
+    let mut watchdog = Watchdog::new(Thresholds::default());
+    let subject = watchdog.observe(TaskKind::Validation, DEFAULT_SLOW_VALIDATION * 2, || "ä".repeat(MAX_SUBJECT_LENGTH * 2)).unwrap();
+    assert_eq!(subject, format!("{}...", "ä".repeat(MAX_SUBJECT_LENGTH)));
+}
@@ -0,0 +1,112 @@
+use rust_blockchain::blockchain::*;
+use rust_blockchain::difficulty::{Fixed, MIN_DIFFICULTY};
+use rust_blockchain::head::HeadEvent;
+use rust_blockchain::node::Node;
+use rust_blockchain::storage::{MemoryStorage, Storage};
+use rust_blockchain::types::{EventType, Height};
+use rust_blockchain::workers::*;
+use std::sync::Arc;
+use std::time::Instant;
+
+// A node whose blocks need no mining: every hash meets the empty prefix and the minimal difficulty
+async fn easy_node(name: &str) -> Node {
+    let mut node = Node::init(Storage::Memory(MemoryStorage::default()), name.to_owned()).await.unwrap();
+    node.chain.difficulty = String::new();
+    node.chain.difficulty_algorithm = Arc::new(Fixed { difficulty: MIN_DIFFICULTY });
+    node
+}
+
+async fn grow(node: &mut Node, height: Height) {
+    while node.chain.latest_block.id < height {
+        let mut block = node.chain.block_template(&mut node.storage, String::new()).await.unwrap();
+        block.hash = hasher(&block);
+        node.chain.add_block(&mut node.storage, block).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_chain_server_follows_head() {
+    let mut node = easy_node("server").await;
+    grow(&mut node, Height(3)).await;
+    let mut server = ChainServer::new(node.chain.clone(), Chain::get_chain(&mut node.storage).await.unwrap());
+    let blocks = Chain::get_chain(&mut node.storage).await.unwrap();
+
+    // Extended
+    grow(&mut node, Height(4)).await;
+    server.apply(&HeadEvent::Extended(node.chain.latest_block.clone()));
+    assert_eq!(server.latest_block(), Some(&node.chain.latest_block));
+
+    // Reorged at height 2, followed by the blocks of the new branch
+    let mut fork = blocks[..2].to_vec();
+    for data in ["fork 2", "fork 3"] {
+        let parent = fork.last().unwrap().clone();
+        let mut block = Block { id: parent.id + 1, prev_hash: parent.hash.clone(), data: data.to_owned(), ..parent };
+        block.hash = hasher(&block);
+        fork.push(block);
+    }
+    server.apply(&HeadEvent::Reorged { old_tip: node.chain.latest_block.clone(), new_tip: fork[3].clone(), depth: 3 });
+    server.apply(&HeadEvent::Extended(fork[2].clone()));
+    server.apply(&HeadEvent::Extended(fork[3].clone()));
+    let request = EventType::ReceivedChainRequest { receiver: "client".to_owned(), session_id: "s".to_owned(), from: Height(1) };
+    match server.serve(request) {
+        Some(EventType::SendChain { receiver, chain, .. }) if receiver == "client" => assert_eq!(chain, fork[1..]),
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    // A block not linking to the copy replaces it, as after a restore from a snapshot
+    let mut detached = Block { id: Height(10), prev_hash: "unknown".to_owned(), ..fork[3].clone() };
+    detached.hash = hasher(&detached);
+    server.apply(&HeadEvent::Extended(detached.clone()));
+    assert_eq!(server.latest_block(), Some(&detached));
+    let request = EventType::ReceivedChainRequest { receiver: "client".to_owned(), session_id: "s".to_owned(), from: Height::GENESIS };
+    assert!(server.serve(request).is_none());
+}
+
+#[tokio::test]
+async fn test_sync_worker_checks_chains() {
+    let now = Instant::now();
+    let mut server = easy_node("server").await;
+    grow(&mut server, Height(5)).await;
+    let mut client = easy_node("client").await;
+
+    let latest = EventType::ReceivedLatestBlock { sender: "server".to_owned(), block: server.chain.latest_block.clone() };
+    let session_id = match client.handle_event(latest, now).await.as_slice() {
+        [EventType::SendChainRequest { session_id, .. }] => session_id.clone(),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let blocks = Chain::get_chain(&mut server.storage).await.unwrap();
+
+    // A tampered chain is rejected by the worker, and then by the node
+    let mut tampered = blocks.clone();
+    tampered[3].data = "tampered".to_owned();
+    let job = SyncJob { chain: client.chain.clone(), sender: "server".to_owned(), session_id: session_id.clone(), blocks: tampered };
+    let checked = check(job).await;
+    assert!(matches!(&checked, EventType::ReceivedCheckedChain { error: Some(_), .. }));
+    client.handle_event(checked, now).await;
+    assert_eq!(client.chain.latest_block.id, Height::GENESIS);
+
+    // A valid one is swapped in
+    let latest = EventType::ReceivedLatestBlock { sender: "server".to_owned(), block: server.chain.latest_block.clone() };
+    let session_id = match client.handle_event(latest, now).await.as_slice() {
+        [EventType::SendChainRequest { session_id, .. }] => session_id.clone(),
+        events => panic!("unexpected events: {:?}", events),
+    };
+    let job = SyncJob { chain: client.chain.clone(), sender: "server".to_owned(), session_id, blocks };
+    let checked = check(job).await;
+    assert!(matches!(&checked, EventType::ReceivedCheckedChain { error: None, .. }));
+    client.handle_event(checked, now).await;
+    assert_eq!(client.chain.latest_block, server.chain.latest_block);
+    client.chain.validate_chain(&mut client.storage).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_validation_worker() {
+    let mut node = easy_node("node").await;
+    grow(&mut node, Height(5)).await;
+    let blocks = Chain::get_chain(&mut node.storage).await.unwrap();
+    assert_eq!(validate(ValidationJob { chain: node.chain.clone(), blocks: blocks.clone() }).await.unwrap(), 6);
+
+    let mut tampered = blocks;
+    tampered[2].data = "tampered".to_owned();
+    assert!(validate(ValidationJob { chain: node.chain.clone(), blocks: tampered }).await.is_err());
+}